@@ -0,0 +1,91 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Alerts currently raised by the node, kept up to date by the server and
+//! listed by the API.
+
+use crate::types::Alert;
+use crate::util::RwLock;
+
+/// Alerts active on the node, at most one per kind, oldest first.
+#[derive(Default)]
+pub struct ActiveAlerts {
+	alerts: RwLock<Vec<Alert>>,
+}
+
+impl ActiveAlerts {
+	pub fn new() -> ActiveAlerts {
+		ActiveAlerts::default()
+	}
+
+	/// Raise the alert, returns false if one of the same kind was already
+	/// active, in which case only its message is updated.
+	pub fn raise(&self, alert: Alert) -> bool {
+		let mut alerts = self.alerts.write();
+		match alerts.iter_mut().find(|a| a.kind == alert.kind) {
+			Some(active) => {
+				active.message = alert.message;
+				false
+			}
+			None => {
+				alerts.push(alert);
+				true
+			}
+		}
+	}
+
+	/// Clear the alert of the kind, returning it if it was active.
+	pub fn clear(&self, kind: &str) -> Option<Alert> {
+		let mut alerts = self.alerts.write();
+		let pos = alerts.iter().position(|a| a.kind == kind)?;
+		Some(alerts.remove(pos))
+	}
+
+	/// The active alerts, oldest first.
+	pub fn list(&self) -> Vec<Alert> {
+		self.alerts.read().clone()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn alert(kind: &str, message: &str) -> Alert {
+		Alert {
+			kind: kind.to_owned(),
+			message: message.to_owned(),
+			raised_at: "2020-06-01T00:00:00+00:00".to_owned(),
+		}
+	}
+
+	#[test]
+	fn test_active_alerts() {
+		let alerts = ActiveAlerts::new();
+		assert!(alerts.list().is_empty());
+
+		assert!(alerts.raise(alert("no_peers", "no peers")));
+		assert!(alerts.raise(alert("disk_space", "100MB left")));
+		assert!(!alerts.raise(alert("disk_space", "50MB left")));
+
+		let list = alerts.list();
+		assert_eq!(list.len(), 2);
+		assert_eq!(list[0].kind, "no_peers");
+		assert_eq!(list[1].message, "50MB left");
+
+		assert_eq!(alerts.clear("no_peers").unwrap().kind, "no_peers");
+		assert!(alerts.clear("no_peers").is_none());
+		assert_eq!(alerts.list().len(), 1);
+	}
+}
@@ -133,7 +133,7 @@ impl Handler for BasicAuthURIMiddleware {
 	}
 }
 
-fn unauthorized_response(basic_realm: &HeaderValue) -> ResponseFuture {
+pub(crate) fn unauthorized_response(basic_realm: &HeaderValue) -> ResponseFuture {
 	let response = Response::builder()
 		.status(StatusCode::UNAUTHORIZED)
 		.header(WWW_AUTHENTICATE, basic_realm)
@@ -0,0 +1,185 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Short lived cache of expensive, idempotent API responses (blocks and
+//! headers), so explorers re-requesting the same hot blocks don't take the
+//! chain locks over and over.
+
+use crate::chain;
+use crate::core::core::hash::Hash;
+use crate::rest::Error;
+use crate::router::ResponseFuture;
+use crate::util::RwLock;
+use crate::web::*;
+use hyper::{Body, Request, StatusCode};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+
+/// Maximum number of responses kept in the cache.
+const MAX_CACHED_RESPONSES: usize = 1_000;
+
+struct CachedResponse {
+	body: String,
+	inserted: Instant,
+}
+
+struct Responses {
+	// Chain head the responses were built against.
+	head: Option<Hash>,
+	by_key: HashMap<String, CachedResponse>,
+}
+
+/// Caches serialized responses by request uri for a configurable time.
+/// Most responses depend on the chain head (spent outputs, next block,
+/// blocks looked up by height), so they are only served for the head they
+/// were built against and all dropped once the head changes.
+pub struct ResponseCache {
+	ttl: Duration,
+	responses: RwLock<Responses>,
+}
+
+impl ResponseCache {
+	/// New cache keeping responses for the provided duration.
+	pub fn new(ttl: Duration) -> ResponseCache {
+		ResponseCache {
+			ttl,
+			responses: RwLock::new(Responses {
+				head: None,
+				by_key: HashMap::new(),
+			}),
+		}
+	}
+
+	/// Cached response body for the provided key and chain head, if still
+	/// fresh.
+	pub fn get(&self, head: &Hash, key: &str) -> Option<String> {
+		let responses = self.responses.read();
+		if responses.head.as_ref() != Some(head) {
+			return None;
+		}
+		responses
+			.by_key
+			.get(key)
+			.filter(|r| r.inserted.elapsed() < self.ttl)
+			.map(|r| r.body.clone())
+	}
+
+	/// Cache a response body built against the provided chain head, dropping
+	/// the responses built against another head, and evicting stale or the
+	/// oldest responses when full.
+	pub fn insert(&self, head: Hash, key: String, body: String) {
+		let mut responses = self.responses.write();
+		if responses.head != Some(head) {
+			responses.head = Some(head);
+			responses.by_key.clear();
+		}
+		let by_key = &mut responses.by_key;
+		if by_key.len() >= MAX_CACHED_RESPONSES && !by_key.contains_key(&key) {
+			let ttl = self.ttl;
+			by_key.retain(|_, r| r.inserted.elapsed() < ttl);
+			if by_key.len() >= MAX_CACHED_RESPONSES {
+				let oldest = by_key
+					.iter()
+					.min_by_key(|(_, r)| r.inserted)
+					.map(|(k, _)| k.clone());
+				if let Some(oldest) = oldest {
+					by_key.remove(&oldest);
+				}
+			}
+		}
+		by_key.insert(
+			key,
+			CachedResponse {
+				body,
+				inserted: Instant::now(),
+			},
+		);
+	}
+}
+
+/// Serve the response to a GET request from the cache if possible, or build
+/// it and cache it if successful, for as long as the chain head stays the
+/// same.
+pub fn cached_response<T, F>(
+	cache: &Option<Arc<ResponseCache>>,
+	chain: &Weak<chain::Chain>,
+	req: &Request<Body>,
+	f: F,
+) -> ResponseFuture
+where
+	T: Serialize,
+	F: FnOnce() -> Result<T, Error>,
+{
+	// The head is read before building the response, a response built while
+	// the head moves on is cached against the previous one, never served for
+	// the new one.
+	let head = chain.upgrade().and_then(|chain| chain.head().ok());
+	let (cache, head) = match (cache, head) {
+		(Some(cache), Some(head)) => (cache, head.last_block_h),
+		_ => return result_to_response(f()),
+	};
+	// Responses serialized with different ser versions are cached apart.
+	let key = format!("{} {}", req.uri(), ser_version(req).0);
+	if let Some(body) = cache.get(&head, &key) {
+		return response(StatusCode::OK, body);
+	}
+	let res = match f() {
+		Ok(res) => res,
+		Err(e) => return result_to_response::<T>(Err(e)),
+	};
+	match serde_json::to_string_pretty(&res) {
+		Ok(json) => {
+			cache.insert(head, key, json.clone());
+			response(StatusCode::OK, json)
+		}
+		Err(e) => response(
+			StatusCode::INTERNAL_SERVER_ERROR,
+			format!("can't create json response: {}", e),
+		),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_response_cache() {
+		let head = Hash::from_vec(&[1]);
+		let cache = ResponseCache::new(Duration::from_secs(60));
+
+		cache.insert(head, "/v1/blocks/aa".to_owned(), "aa".to_owned());
+		cache.insert(head, "/v1/blocks/99".to_owned(), "99".to_owned());
+		assert_eq!(cache.get(&head, "/v1/blocks/aa"), Some("aa".to_owned()));
+		assert_eq!(cache.get(&head, "/v1/blocks/99"), Some("99".to_owned()));
+		assert_eq!(cache.get(&head, "/v1/blocks/2"), None);
+
+		// Not served for another head, whether looked up by height or hash.
+		let next = Hash::from_vec(&[2]);
+		assert_eq!(cache.get(&next, "/v1/blocks/aa"), None);
+		assert_eq!(cache.get(&next, "/v1/blocks/99"), None);
+
+		// And dropped once a response is cached for the new head.
+		cache.insert(next, "/v1/blocks/bb".to_owned(), "bb".to_owned());
+		assert_eq!(cache.get(&next, "/v1/blocks/bb"), Some("bb".to_owned()));
+		assert_eq!(cache.get(&head, "/v1/blocks/aa"), None);
+
+		// Expired responses aren't served.
+		let cache = ResponseCache::new(Duration::from_secs(0));
+		cache.insert(head, "/v1/blocks/aa".to_owned(), "aa".to_owned());
+		assert_eq!(cache.get(&head, "/v1/blocks/aa"), None);
+	}
+}
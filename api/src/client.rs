@@ -14,11 +14,13 @@
 
 //! High level JSON/HTTP client API
 
+use crate::core::ser::{self, ProtocolVersion, Readable};
 use crate::rest::{Error, ErrorKind};
 use crate::util::to_base64;
+use crate::web::SER_VERSION_HEADER;
 use failure::{Fail, ResultExt};
-use hyper::body;
-use hyper::header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE, USER_AGENT};
+use hyper::body::{self, Bytes};
+use hyper::header::{HeaderMap, HeaderValue, ACCEPT, AUTHORIZATION, CONTENT_TYPE, USER_AGENT};
 use hyper::{Body, Client, Request};
 use hyper_rustls;
 use hyper_timeout::TimeoutConnector;
@@ -50,6 +52,45 @@ where
 	handle_request_async(build_request(url, "GET", api_secret, None)?).await
 }
 
+/// Helper function to issue a HTTP GET request against a given URL that
+/// returns binary data, deserialized with the ser protocol version the server
+/// answered with. Servers not telling are assumed to answer with the first
+/// version.
+/// Spawns a new Tokio runtime, as `get` does.
+pub fn get_ser<T>(url: &str, api_secret: Option<String>) -> Result<T, Error>
+where
+	T: Readable,
+{
+	let mut rt = Builder::new()
+		.basic_scheduler()
+		.enable_all()
+		.build()
+		.map_err(|e| ErrorKind::RequestError(format!("{}", e)))?;
+	rt.block_on(get_ser_async(url, api_secret))
+}
+
+/// Async version of `get_ser`.
+pub async fn get_ser_async<T>(url: &str, api_secret: Option<String>) -> Result<T, Error>
+where
+	T: Readable,
+{
+	let mut req = build_request(url, "GET", api_secret, None)?;
+	req.headers_mut()
+		.insert(ACCEPT, HeaderValue::from_static("application/octet-stream"));
+	let (headers, raw) = send_request_raw_async(req).await?;
+	let version = match headers.get(SER_VERSION_HEADER) {
+		Some(v) => v
+			.to_str()
+			.ok()
+			.and_then(|v| v.trim().parse::<u32>().ok())
+			.filter(|v| *v <= ProtocolVersion::local().0)
+			.ok_or_else(|| ErrorKind::ResponseError(format!("Unsupported ser version: {:?}", v)))?,
+		None => 1,
+	};
+	ser::deserialize(&mut &raw[..], ProtocolVersion(version))
+		.map_err(|e| ErrorKind::ResponseError(format!("Cannot deserialize response: {}", e)).into())
+}
+
 /// Helper function to easily issue a HTTP GET request
 /// on a given URL that returns nothing. Handles request
 /// building and response code checking.
@@ -132,6 +173,7 @@ fn build_request(
 		.method(method)
 		.uri(url)
 		.header(USER_AGENT, "kepler-client")
+		.header(SER_VERSION_HEADER, ProtocolVersion::local().0)
 		.header(ACCEPT, "application/json")
 		.header(CONTENT_TYPE, "application/json")
 		.body(match body {
@@ -179,6 +221,12 @@ where
 }
 
 async fn send_request_async(req: Request<Body>) -> Result<String, Error> {
+	let (_, raw) = send_request_raw_async(req).await?;
+	Ok(String::from_utf8_lossy(&raw).to_string())
+}
+
+// Headers and body of the response, failing unless successful.
+async fn send_request_raw_async(req: Request<Body>) -> Result<(HeaderMap, Bytes), Error> {
 	let https = hyper_rustls::HttpsConnector::new();
 	let mut connector = TimeoutConnector::new(https);
 	connector.set_connect_timeout(Some(Duration::from_secs(20)));
@@ -200,11 +248,12 @@ async fn send_request_async(req: Request<Body>) -> Result<String, Error> {
 		.into());
 	}
 
+	let headers = resp.headers().clone();
 	let raw = body::to_bytes(resp)
 		.await
 		.map_err(|e| ErrorKind::RequestError(format!("Cannot read response body: {}", e)))?;
 
-	Ok(String::from_utf8_lossy(&raw).to_string())
+	Ok((headers, raw))
 }
 
 pub fn send_request(req: Request<Body>) -> Result<String, Error> {
@@ -19,14 +19,14 @@ use crate::core::core::hash::Hash;
 use crate::core::core::transaction::Transaction;
 use crate::handlers::blocks_api::{BlockHandler, HeaderHandler};
 use crate::handlers::chain_api::{ChainHandler, KernelHandler, OutputHandler};
-use crate::handlers::pool_api::PoolHandler;
+use crate::handlers::pool_api::{OutputStatusHandler, PoolHandler};
 use crate::handlers::transactions_api::TxHashSetHandler;
 use crate::handlers::version_api::VersionHandler;
 use crate::pool::{self, PoolEntry};
 use crate::rest::*;
 use crate::types::{
-	BlockHeaderPrintable, BlockPrintable, LocatedTxKernel, OutputListing, OutputPrintable, Tip,
-	Version,
+	BlockHeaderPrintable, BlockPrintable, LocatedTxKernel, MergedOutput, OutputListing,
+	OutputPrintable, Tip, Version,
 };
 use crate::util::RwLock;
 use std::sync::Weak;
@@ -92,6 +92,7 @@ impl Foreign {
 	) -> Result<BlockHeaderPrintable, Error> {
 		let header_handler = HeaderHandler {
 			chain: self.chain.clone(),
+			cache: None,
 		};
 		let hash = header_handler.parse_inputs(height, hash, commit)?;
 		header_handler.get_header_v2(&hash)
@@ -119,6 +120,7 @@ impl Foreign {
 	) -> Result<BlockPrintable, Error> {
 		let block_handler = BlockHandler {
 			chain: self.chain.clone(),
+			cache: None,
 		};
 		let hash = block_handler.parse_inputs(height, hash, commit)?;
 		block_handler.get_block(&hash, true, true)
@@ -246,6 +248,27 @@ impl Foreign {
 		output_handler.get_unspent_outputs(start_index, end_index, max, include_proof)
 	}
 
+	/// Status of an output merging the chain with the transaction pool: unspent
+	/// on chain, created by a pool transaction, spent by a pool transaction or
+	/// unknown. Stem transactions are left out.
+	///
+	/// # Arguments
+	/// * `commit` - the output commitment, hex encoded.
+	///
+	/// # Returns
+	/// * Result Containing:
+	/// * A [`MergedOutput`](types/struct.MergedOutput.html)
+	/// * or [`Error`](struct.Error.html) if an error is encountered.
+	///
+
+	pub fn get_output_status(&self, commit: String) -> Result<MergedOutput, Error> {
+		let output_status_handler = OutputStatusHandler {
+			chain: self.chain.clone(),
+			tx_pool: self.tx_pool.clone(),
+		};
+		output_status_handler.get_output_status(&commit)
+	}
+
 	/// Retrieves the PMMR indices based on the provided block height(s).
 	///
 	/// # Arguments
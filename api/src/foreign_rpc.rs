@@ -20,8 +20,8 @@ use crate::foreign::Foreign;
 use crate::pool::PoolEntry;
 use crate::rest::ErrorKind;
 use crate::types::{
-	BlockHeaderPrintable, BlockPrintable, LocatedTxKernel, OutputListing, OutputPrintable, Tip,
-	Version,
+	BlockHeaderPrintable, BlockPrintable, LocatedTxKernel, MergedOutput, OutputListing,
+	OutputPrintable, Tip, Version,
 };
 use crate::util;
 
@@ -539,6 +539,43 @@ pub trait ForeignRpc: Sync + Send {
 		end_block_height: Option<u64>,
 	) -> Result<OutputListing, ErrorKind>;
 
+	/**
+	Networked version of [Foreign::get_output_status](struct.Node.html#method.get_output_status).
+
+	# Json rpc example
+
+	```
+	# kepler_api::doctest_helper_json_rpc_foreign_assert_response!(
+	# r#"
+	{
+		"jsonrpc": "2.0",
+		"method": "get_output_status",
+		"params": ["09bab2bdba2e6aed690b5eda11accc13c06723ca5965bb460c5f2383655989af3f"],
+		"id": 1
+	}
+	# "#
+	# ,
+	# r#"
+	{
+		"id": 1,
+		"jsonrpc": "2.0",
+		"result": {
+			"Ok": {
+				"commit": "09bab2bdba2e6aed690b5eda11accc13c06723ca5965bb460c5f2383655989af3f",
+				"status": "PoolSpent",
+				"height": 1,
+				"mmr_index": 1,
+				"created_by": null,
+				"spent_by": "1fca6a0ab4a8e8a8ab8d4b2eecd92dc5d8e3ff6c0afdfb3fc8e3cbd0ffdfbb47"
+			}
+		}
+	}
+	# "#
+	# );
+	```
+	 */
+	fn get_output_status(&self, commit: String) -> Result<MergedOutput, ErrorKind>;
+
 	/**
 	Networked version of [Foreign::get_pool_size](struct.Node.html#method.get_pool_size).
 
@@ -824,6 +861,10 @@ impl ForeignRpc for Foreign {
 			.map_err(|e| e.kind().clone())
 	}
 
+	fn get_output_status(&self, commit: String) -> Result<MergedOutput, ErrorKind> {
+		Foreign::get_output_status(self, commit).map_err(|e| e.kind().clone())
+	}
+
 	fn get_pool_size(&self) -> Result<usize, ErrorKind> {
 		Foreign::get_pool_size(self).map_err(|e| e.kind().clone())
 	}
@@ -0,0 +1,336 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! gRPC server of the node services, mirroring the foreign API for the
+//! integrators preferring generated bindings over JSON. The services are
+//! defined in proto/node.proto. Requests need the api secret, if set, sent
+//! as the "authorization" metadata as for the REST api, and are held to the
+//! same rate limits.
+
+use crate::chain::{Chain, SyncState};
+use crate::core::core::hash::Hash;
+use crate::core::core::transaction::Transaction;
+use crate::core::ser::{self, ProtocolVersion};
+use crate::foreign::Foreign;
+use crate::handlers::peers_api::PeersConnectedHandler;
+use crate::p2p;
+use crate::pool;
+use crate::rest::{Error, ErrorKind, IpRateLimitMiddleware, RequestLimits, Stopping};
+use crate::types::{BlockHeaderPrintable, BlockPrintable, OutputPrintable, OutputType};
+use crate::util::{self, to_base64, RwLock};
+use ring::constant_time::verify_slices_are_equal;
+use std::net::SocketAddr;
+use std::sync::{Arc, Weak};
+use tokio::runtime::Handle;
+use tokio::sync::mpsc;
+use tonic::{Request, Response, Status};
+
+mod proto {
+	tonic::include_proto!("kepler.node");
+}
+
+use self::proto::node_server::{Node, NodeServer};
+
+/// Blocks whose outputs are read from the chain at once while streaming them.
+const OUTPUT_STREAM_BLOCKS: u64 = 100;
+
+/// Outputs read ahead of the client while streaming them.
+const OUTPUT_STREAM_BUFFER: usize = 1_000;
+
+/// Start the gRPC server of the node services on the runtime, until the
+/// API server stops.
+pub fn start_grpc(
+	addr: SocketAddr,
+	chain: Weak<Chain>,
+	tx_pool: Weak<RwLock<pool::TransactionPool>>,
+	peers: Weak<p2p::Peers>,
+	sync_state: Weak<SyncState>,
+	api_secret: Option<String>,
+	limits: &RequestLimits,
+	public_node: bool,
+	stopping: Stopping,
+	runtime: &Handle,
+) {
+	let service = NodeService {
+		foreign: Arc::new(Foreign::new(chain, tx_pool, sync_state)),
+		peers,
+		basic_auth: api_secret
+			.map(|secret| "Basic ".to_string() + &to_base64(&("kepler:".to_string() + &secret))),
+		ip_rate_limit: limits
+			.for_public_node(public_node)
+			.max_per_ip_per_sec
+			.map(IpRateLimitMiddleware::new),
+	};
+	warn!("Starting gRPC Node APIs server at {}.", addr);
+	runtime.spawn(async move {
+		let res = tonic::transport::Server::builder()
+			.add_service(NodeServer::new(service))
+			.serve_with_shutdown(addr, stopping.wait())
+			.await;
+		match res {
+			Ok(()) => info!("gRPC API server has been stopped"),
+			Err(e) => error!("gRPC API server failed: {}", e),
+		}
+	});
+}
+
+struct NodeService {
+	foreign: Arc<Foreign>,
+	peers: Weak<p2p::Peers>,
+	basic_auth: Option<String>,
+	ip_rate_limit: Option<IpRateLimitMiddleware>,
+}
+
+impl NodeService {
+	// Checks the request carries the api secret and is within the rate
+	// limits, as the REST api does.
+	fn check<T>(&self, request: &Request<T>) -> Result<(), Status> {
+		if let Some(ref basic_auth) = self.basic_auth {
+			let authorized = request
+				.metadata()
+				.get("authorization")
+				.map_or(false, |auth| {
+					verify_slices_are_equal(auth.as_bytes(), basic_auth.as_bytes()).is_ok()
+				});
+			if !authorized {
+				return Err(Status::unauthenticated("api secret expected"));
+			}
+		}
+		if let (Some(limit), Some(addr)) = (&self.ip_rate_limit, request.remote_addr()) {
+			if !limit.allow(addr.ip()) {
+				debug!("rate limiting gRPC requests from {}", addr.ip());
+				return Err(too_many_requests());
+			}
+		}
+		Ok(())
+	}
+}
+
+// Runs the call on the blocking pool, as the chain and pool locks it takes
+// mustn't hold up the runtime.
+async fn blocking<F, T>(f: F) -> Result<T, Status>
+where
+	F: FnOnce() -> Result<T, Error> + Send + 'static,
+	T: Send + 'static,
+{
+	match tokio::task::spawn_blocking(f).await {
+		Ok(res) => res.map_err(status),
+		Err(e) => Err(Status::internal(format!("handler failed: {}", e))),
+	}
+}
+
+#[tonic::async_trait]
+impl Node for NodeService {
+	type GetOutputsStream = mpsc::Receiver<Result<proto::Output, Status>>;
+
+	async fn get_tip(
+		&self,
+		request: Request<proto::GetTipRequest>,
+	) -> Result<Response<proto::Tip>, Status> {
+		self.check(&request)?;
+		let foreign = self.foreign.clone();
+		let tip = blocking(move || foreign.get_tip()).await?;
+		Ok(Response::new(proto::Tip {
+			height: tip.height,
+			last_block_pushed: bytes(tip.last_block_pushed),
+			prev_block_to_last: bytes(tip.prev_block_to_last),
+			total_difficulty: tip.total_difficulty,
+		}))
+	}
+
+	async fn get_block(
+		&self,
+		request: Request<proto::GetBlockRequest>,
+	) -> Result<Response<proto::Block>, Status> {
+		use self::proto::get_block_request::Block;
+		self.check(&request)?;
+		let (height, hash) = match request.into_inner().block {
+			Some(Block::Height(height)) => (Some(height), None),
+			Some(Block::Hash(hash)) if hash.len() == 32 => (None, Some(Hash::from_vec(&hash))),
+			_ => return Err(Status::invalid_argument("block height or hash expected")),
+		};
+		let foreign = self.foreign.clone();
+		let block = blocking(move || foreign.get_block(height, hash, None)).await?;
+		Ok(Response::new(block_msg(block)))
+	}
+
+	async fn get_outputs(
+		&self,
+		request: Request<proto::GetOutputsRequest>,
+	) -> Result<Response<Self::GetOutputsStream>, Status> {
+		self.check(&request)?;
+		let req = request.into_inner();
+		let end_height = match req.end_height {
+			0 => {
+				let foreign = self.foreign.clone();
+				blocking(move || foreign.get_tip()).await?.height
+			}
+			height => height,
+		};
+		let foreign = self.foreign.clone();
+		let (mut tx, rx) = mpsc::channel(OUTPUT_STREAM_BUFFER);
+		tokio::task::spawn_blocking(move || {
+			let mut send = |outputs: Result<Vec<OutputPrintable>, Error>| match outputs {
+				Ok(outputs) => outputs.into_iter().all(|output| {
+					futures::executor::block_on(tx.send(Ok(output_msg(output)))).is_ok()
+				}),
+				Err(e) => {
+					let _ = futures::executor::block_on(tx.send(Err(status(e))));
+					false
+				}
+			};
+			if !req.commits.is_empty() {
+				let commits = req.commits.into_iter().map(util::to_hex).collect();
+				let outputs =
+					foreign.get_outputs(Some(commits), None, None, Some(req.include_proof), None);
+				send(outputs);
+				return;
+			}
+			// Read by batches of blocks, until the client goes away.
+			let mut start = req.start_height;
+			while start <= end_height {
+				let end = end_height.min(start + OUTPUT_STREAM_BLOCKS - 1);
+				let outputs = foreign.get_outputs(
+					None,
+					Some(start),
+					Some(end),
+					Some(req.include_proof),
+					None,
+				);
+				if !send(outputs) {
+					break;
+				}
+				start = end + 1;
+			}
+		});
+		Ok(Response::new(rx))
+	}
+
+	async fn push_transaction(
+		&self,
+		request: Request<proto::PushTransactionRequest>,
+	) -> Result<Response<proto::PushTransactionResponse>, Status> {
+		self.check(&request)?;
+		let req = request.into_inner();
+		// As for the rest api, wallets serialize with protocol version 1.
+		let tx: Transaction = ser::deserialize(&mut &req.tx[..], ProtocolVersion(1))
+			.map_err(|e| Status::invalid_argument(format!("invalid transaction: {}", e)))?;
+		let foreign = self.foreign.clone();
+		blocking(move || foreign.push_transaction(tx, Some(req.fluff))).await?;
+		Ok(Response::new(proto::PushTransactionResponse {}))
+	}
+
+	async fn get_peers(
+		&self,
+		request: Request<proto::GetPeersRequest>,
+	) -> Result<Response<proto::GetPeersResponse>, Status> {
+		self.check(&request)?;
+		let handler = PeersConnectedHandler {
+			peers: self.peers.clone(),
+		};
+		let peers = blocking(move || handler.get_connected_peers())
+			.await?
+			.into_iter()
+			.map(|peer| proto::Peer {
+				capabilities: peer.capabilities.bits(),
+				user_agent: peer.user_agent,
+				version: peer.version.value(),
+				addr: peer.addr.to_string(),
+				direction: match peer.direction {
+					p2p::Direction::Inbound => proto::Direction::Inbound,
+					p2p::Direction::Outbound => proto::Direction::Outbound,
+				} as i32,
+				total_difficulty: peer.total_difficulty.to_num(),
+				height: peer.height,
+			})
+			.collect();
+		Ok(Response::new(proto::GetPeersResponse { peers }))
+	}
+}
+
+fn too_many_requests() -> Status {
+	Status::resource_exhausted("too many requests, retry later")
+}
+
+fn status(e: Error) -> Status {
+	match e.kind() {
+		ErrorKind::NotFound => Status::not_found(e.to_string()),
+		ErrorKind::Argument(_) | ErrorKind::RequestError(_) => {
+			Status::invalid_argument(e.to_string())
+		}
+		_ => Status::internal(e.to_string()),
+	}
+}
+
+// The printable types hold hex strings, sent as raw bytes.
+fn bytes(hex: String) -> Vec<u8> {
+	util::from_hex(hex).unwrap_or_default()
+}
+
+fn header_msg(header: BlockHeaderPrintable) -> proto::BlockHeader {
+	proto::BlockHeader {
+		hash: bytes(header.hash),
+		version: header.version as u32,
+		height: header.height,
+		previous: bytes(header.previous),
+		prev_root: bytes(header.prev_root),
+		timestamp: header.timestamp,
+		output_root: bytes(header.output_root),
+		range_proof_root: bytes(header.range_proof_root),
+		kernel_root: bytes(header.kernel_root),
+		nonce: header.nonce,
+		edge_bits: header.edge_bits as u32,
+		cuckoo_solution: header.cuckoo_solution,
+		total_difficulty: header.total_difficulty,
+		secondary_scaling: header.secondary_scaling,
+		total_kernel_offset: bytes(header.total_kernel_offset),
+	}
+}
+
+fn output_msg(output: OutputPrintable) -> proto::Output {
+	let output_type = match output.output_type {
+		OutputType::Coinbase => proto::OutputType::Coinbase,
+		OutputType::Transaction => proto::OutputType::Transaction,
+	};
+	proto::Output {
+		output_type: output_type as i32,
+		commit: output.commit.0.to_vec(),
+		spent: output.spent,
+		proof: output.proof.map(bytes).unwrap_or_default(),
+		proof_hash: bytes(output.proof_hash),
+		block_height: output.block_height.unwrap_or(0),
+		mmr_index: output.mmr_index,
+	}
+}
+
+fn block_msg(block: BlockPrintable) -> proto::Block {
+	proto::Block {
+		header: Some(header_msg(block.header)),
+		inputs: block.inputs.into_iter().map(bytes).collect(),
+		outputs: block.outputs.into_iter().map(output_msg).collect(),
+		kernels: block
+			.kernels
+			.into_iter()
+			.map(|kernel| proto::Kernel {
+				features: kernel.features,
+				fee: kernel.fee,
+				lock_height: kernel.lock_height,
+				excess: bytes(kernel.excess),
+				excess_sig: bytes(kernel.excess_sig),
+			})
+			.collect(),
+		weight: block.weight,
+		size: block.size,
+	}
+}
@@ -14,55 +14,109 @@
 
 pub mod blocks_api;
 pub mod chain_api;
+pub mod events_api;
+pub mod faucet_api;
+pub mod graphql_api;
 pub mod peers_api;
 pub mod pool_api;
+pub mod scan_api;
 pub mod server_api;
+pub mod sse_api;
+pub mod stats_api;
 pub mod transactions_api;
 pub mod utils;
+pub mod v2;
+pub mod validation_api;
 pub mod version_api;
+pub mod ws_api;
 
 use self::blocks_api::BlockHandler;
 use self::blocks_api::HeaderHandler;
+use self::blocks_api::HeaderProofHandler;
 use self::chain_api::ChainCompactHandler;
 use self::chain_api::ChainHandler;
 use self::chain_api::ChainValidationHandler;
+use self::chain_api::FeeMarketHandler;
 use self::chain_api::KernelHandler;
+use self::chain_api::KernelSearchHandler;
+use self::chain_api::KernelsHandler;
+use self::chain_api::OrphansHandler;
+use self::chain_api::OutputBatchHandler;
 use self::chain_api::OutputHandler;
+use self::chain_api::OutputMerkleProofHandler;
+use self::chain_api::OutputStreamHandler;
+use self::chain_api::RangeProofHandler;
+use self::chain_api::ReorgsHandler;
+use self::events_api::{EventsStreamHandler, EventsSubscriptionHandler, NodeEvents};
+use self::faucet_api::{Faucet, FaucetHandler};
+use self::graphql_api::GraphQLHandler;
 use self::peers_api::PeerHandler;
 use self::peers_api::PeersAllHandler;
 use self::peers_api::PeersConnectedHandler;
+use self::peers_api::PeersHandler;
+use self::pool_api::PoolCheckHandler;
 use self::pool_api::PoolInfoHandler;
 use self::pool_api::PoolPushHandler;
+use self::pool_api::PoolTxsHandler;
+use self::pool_api::TxStatusHandler;
+use self::scan_api::OutputScans;
+use self::server_api::AlertsHandler;
+use self::server_api::ApiStatsHandler;
+use self::server_api::HealthHandler;
 use self::server_api::IndexHandler;
 use self::server_api::KernelDownloadHandler;
 use self::server_api::StatusHandler;
+use self::server_api::WritableProbe;
+use self::sse_api::{PoolEvents, PoolStreamHandler};
+use self::stats_api::StatsHandler;
 use self::transactions_api::TxHashSetHandler;
+use self::v2::foreign::ForeignAPIHandlerV2;
+use self::v2::owner::OwnerAPIHandlerV2;
+use self::v2::spec::SpecHandler;
+use self::validation_api::{ValidationJobHandler, ValidationJobs};
+use self::version_api::UpgradeStatusHandler;
 use self::version_api::VersionHandler;
-use crate::auth::{
-	BasicAuthMiddleware, BasicAuthURIMiddleware, KEPLER_BASIC_REALM, KEPLER_FOREIGN_BASIC_REALM,
-};
+use self::ws_api::{BlockEvents, BlockSubscriptionHandler};
+use crate::alerts::ActiveAlerts;
+use crate::auth::{BasicAuthURIMiddleware, KEPLER_BASIC_REALM, KEPLER_FOREIGN_BASIC_REALM};
+use crate::cache::ResponseCache;
 use crate::chain;
 use crate::chain::{Chain, SyncState};
-use crate::foreign::Foreign;
-use crate::foreign_rpc::ForeignRpc;
-use crate::owner::Owner;
-use crate::owner_rpc::OwnerRpc;
 use crate::p2p;
 use crate::pool;
-use crate::rest::{ApiServer, Error, TLSConfig};
-use crate::router::ResponseFuture;
-use crate::router::{Router, RouterError};
+use crate::rest::{
+	ApiServer, ApiServerHandle, CorsPolicy, Error, RequestLimits,
+	TLSConfig,
+};
+use crate::route_stats::RouteStats;
+use crate::router::{RouteAuth, RouteAuthConfig, Router, RouterError};
 use crate::util::to_base64;
 use crate::util::RwLock;
-use crate::web::*;
-use easy_jsonrpc_mw::{Handler, MaybeReply};
-use hyper::{Body, Request, Response, StatusCode};
-use serde::Serialize;
 use std::net::SocketAddr;
-use std::sync::{Arc, Weak};
+use std::sync::atomic::AtomicUsize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::Handle;
 
 /// Listener version, providing same API but listening for requests on a
-/// port and wrapping the calls
+/// port and wrapping the calls.
+/// Returns the running ApiServer (to stop it) along with its handle.
+/// A public node serves the foreign API only, rate limited per client.
+/// Requests are further limited per client and in flight as per `limits`.
+/// Browsers may query the node from the pages of `cors_origins`.
+/// Block and header responses are cached for `cache_ttl`, if provided.
+/// Requests are served on the provided runtime, if any.
+/// Blocks accepted by the chain are pushed to WebSocket subscribers from
+/// `block_events`, transactions entering and leaving the pool to Server-Sent
+/// Events subscribers from `pool_events`. All of them, along with the peer
+/// and sync events, are pushed to both from `node_events`.
+/// The alerts raised by the node are listed from `alerts`.
+/// Chain queries are served in GraphQL if `graphql` is set.
+/// The owner api scans the UTXO set for the outputs of a wallet if
+/// `scan_outputs` is set.
+/// Test coins are handed out from `faucet`, if provided.
+/// Requests changing the node state need the api secret, read-only ones
+/// as per `auth_config`.
 pub fn node_apis(
 	addr: &str,
 	chain: Arc<chain::Chain>,
@@ -71,8 +125,22 @@ pub fn node_apis(
 	sync_state: Arc<chain::SyncState>,
 	api_secret: Option<String>,
 	foreign_api_secret: Option<String>,
+	auth_config: RouteAuthConfig,
+	limits: RequestLimits,
+	cors_origins: Vec<String>,
 	tls_config: Option<TLSConfig>,
-) -> Result<(), Error> {
+	public_node: bool,
+	cache_ttl: Option<Duration>,
+	runtime: Option<Handle>,
+	block_events: Arc<BlockEvents>,
+	pool_events: Arc<PoolEvents>,
+	node_events: Arc<NodeEvents>,
+	alerts: Arc<ActiveAlerts>,
+	graphql: bool,
+	scan_outputs: bool,
+	faucet: Option<Arc<Faucet>>,
+) -> Result<(ApiServer, ApiServerHandle), Error> {
+	let cache = cache_ttl.map(|ttl| Arc::new(ResponseCache::new(ttl)));
 	// Manually build router when getting rid of v1
 	//let mut router = Router::new();
 	let mut router = build_router(
@@ -80,27 +148,81 @@ pub fn node_apis(
 		tx_pool.clone(),
 		peers.clone(),
 		sync_state.clone(),
+		public_node,
+		cache,
+		block_events,
+		pool_events,
+		node_events,
+		alerts,
 	)
 	.expect("unable to build API router");
 
-	// Add basic auth to v1 API and owner v2 API
-	if let Some(api_secret) = api_secret {
-		let api_basic_auth =
-			"Basic ".to_string() + &to_base64(&("kepler:".to_string() + &api_secret));
-		let basic_auth_middleware = Arc::new(BasicAuthMiddleware::new(
-			api_basic_auth,
-			&KEPLER_BASIC_REALM,
-			Some("/v2/foreign".into()),
-		));
-		router.add_middleware(basic_auth_middleware);
+	if !cors_origins.is_empty() {
+		router.set_cors(CorsPolicy::new(cors_origins));
 	}
+	limits.for_public_node(public_node).apply(&mut router);
 
-	let api_handler_v2 = OwnerAPIHandlerV2::new(
-		Arc::downgrade(&chain),
-		Arc::downgrade(&peers),
-		Arc::downgrade(&sync_state),
-	);
-	router.add_route("/v2/owner", Arc::new(api_handler_v2))?;
+	if !public_node {
+		let output_scans = if scan_outputs {
+			Some(Arc::new(OutputScans::new()))
+		} else {
+			None
+		};
+		let api_handler_v2 = OwnerAPIHandlerV2::new(
+			Arc::downgrade(&chain),
+			Arc::downgrade(&peers),
+			Arc::downgrade(&sync_state),
+			output_scans,
+		);
+		router.add_route("/v2/owner", Arc::new(api_handler_v2))?;
+
+		let validation_job_handler = Arc::new(ValidationJobHandler {
+			chain: Arc::downgrade(&chain),
+			jobs: Arc::new(ValidationJobs::new()),
+		});
+		router.add_route("/v2/chain/validate", validation_job_handler.clone())?;
+		router.add_route("/v2/chain/validate/*", validation_job_handler)?;
+	}
+
+	let output_stream_handler = OutputStreamHandler {
+		chain: Arc::downgrade(&chain),
+	};
+	router
+		.add_route("/v2/outputs", Arc::new(output_stream_handler))?
+		.read_only();
+	let output_merkle_proof_handler = OutputMerkleProofHandler {
+		chain: Arc::downgrade(&chain),
+	};
+	router
+		.add_route(
+			"/v2/outputs/*/merkleproof",
+			Arc::new(output_merkle_proof_handler),
+		)?
+		.read_only();
+	let header_proof_handler = Arc::new(HeaderProofHandler {
+		chain: Arc::downgrade(&chain),
+	});
+	router
+		.add_route("/v2/headers/root", header_proof_handler.clone())?
+		.read_only();
+	router
+		.add_route("/v2/headers/*/inclusionproof", header_proof_handler)?
+		.read_only();
+
+	if graphql {
+		let graphql_handler = GraphQLHandler {
+			chain: Arc::downgrade(&chain),
+			schema: Arc::new(graphql_api::schema()),
+		};
+		router
+			.add_route("/v2/graphql", Arc::new(graphql_handler))?
+			.read_only();
+	}
+
+	let faucet_enabled = faucet.is_some();
+	if let Some(faucet) = faucet {
+		router.add_route("/v1/faucet", Arc::new(FaucetHandler { faucet }))?;
+	}
 
 	// Add basic auth to v2 foreign API only
 	if let Some(api_secret) = foreign_api_secret {
@@ -120,8 +242,29 @@ pub fn node_apis(
 		Arc::downgrade(&sync_state),
 	);
 	router.add_route("/v2/foreign", Arc::new(api_handler_v2))?;
+	router
+		.add_route("/v2/spec.json", Arc::new(SpecHandler::new(!public_node)))?
+		.read_only();
+
+	// Add basic auth to v1 API and owner v2 API
+	if let Some(api_secret) = api_secret {
+		let api_basic_auth =
+			"Basic ".to_string() + &to_base64(&("kepler:".to_string() + &api_secret));
+		let mut auth = RouteAuth::new(api_basic_auth, &KEPLER_BASIC_REALM, auth_config)
+			.exempt("/v2/foreign")
+			.exempt("/v1/healthz")
+			.exempt("/v1/readyz");
+		// The faucet is for anyone, its quotas keep it from being drained.
+		if faucet_enabled {
+			auth = auth.exempt("/v1/faucet");
+		}
+		router.set_auth(auth);
+	}
 
-	let mut apis = ApiServer::new();
+	let mut apis = match runtime {
+		Some(runtime) => ApiServer::new().with_runtime(runtime),
+		None => ApiServer::new(),
+	};
 	warn!("Starting HTTP Node APIs server at {}.", addr);
 	let socket_addr: SocketAddr = addr.parse().expect("unable to parse socket address");
 	let api_thread = apis.start(socket_addr, router, tls_config);
@@ -129,7 +272,7 @@ pub fn node_apis(
 	warn!("HTTP Node listener started.");
 
 	match api_thread {
-		Ok(_) => Ok(()),
+		Ok(api_thread) => Ok((apis, api_thread)),
 		Err(e) => {
 			error!("HTTP API server failed to start. Err: {}", e);
 			Err(e)
@@ -137,191 +280,38 @@ pub fn node_apis(
 	}
 }
 
-/// V2 API Handler/Wrapper for owner functions
-pub struct OwnerAPIHandlerV2 {
-	pub chain: Weak<Chain>,
-	pub peers: Weak<p2p::Peers>,
-	pub sync_state: Weak<SyncState>,
-}
-
-impl OwnerAPIHandlerV2 {
-	/// Create a new owner API handler for GET methods
-	pub fn new(chain: Weak<Chain>, peers: Weak<p2p::Peers>, sync_state: Weak<SyncState>) -> Self {
-		OwnerAPIHandlerV2 {
-			chain,
-			peers,
-			sync_state,
-		}
-	}
-}
-
-impl crate::router::Handler for OwnerAPIHandlerV2 {
-	fn post(&self, req: Request<Body>) -> ResponseFuture {
-		let api = Owner::new(
-			self.chain.clone(),
-			self.peers.clone(),
-			self.sync_state.clone(),
-		);
-
-		Box::pin(async move {
-			match parse_body(req).await {
-				Ok(val) => {
-					let owner_api = &api as &dyn OwnerRpc;
-					let res = match owner_api.handle_request(val) {
-						MaybeReply::Reply(r) => r,
-						MaybeReply::DontReply => {
-							// Since it's http, we need to return something. We return [] because jsonrpc
-							// clients will parse it as an empty batch response.
-							serde_json::json!([])
-						}
-					};
-					Ok(json_response_pretty(&res))
-				}
-				Err(e) => {
-					error!("Request Error: {:?}", e);
-					Ok(create_error_response(e))
-				}
-			}
-		})
-	}
-
-	fn options(&self, _req: Request<Body>) -> ResponseFuture {
-		Box::pin(async { Ok(create_ok_response("{}")) })
-	}
-}
-
-/// V2 API Handler/Wrapper for foreign functions
-pub struct ForeignAPIHandlerV2 {
-	pub chain: Weak<Chain>,
-	pub tx_pool: Weak<RwLock<pool::TransactionPool>>,
-	pub sync_state: Weak<SyncState>,
-}
-
-impl ForeignAPIHandlerV2 {
-	/// Create a new foreign API handler for GET methods
-	pub fn new(
-		chain: Weak<Chain>,
-		tx_pool: Weak<RwLock<pool::TransactionPool>>,
-		sync_state: Weak<SyncState>,
-	) -> Self {
-		ForeignAPIHandlerV2 {
-			chain,
-			tx_pool,
-			sync_state,
-		}
-	}
-}
-
-impl crate::router::Handler for ForeignAPIHandlerV2 {
-	fn post(&self, req: Request<Body>) -> ResponseFuture {
-		let api = Foreign::new(
-			self.chain.clone(),
-			self.tx_pool.clone(),
-			self.sync_state.clone(),
-		);
-
-		Box::pin(async move {
-			match parse_body(req).await {
-				Ok(val) => {
-					let foreign_api = &api as &dyn ForeignRpc;
-					let res = match foreign_api.handle_request(val) {
-						MaybeReply::Reply(r) => r,
-						MaybeReply::DontReply => {
-							// Since it's http, we need to return something. We return [] because jsonrpc
-							// clients will parse it as an empty batch response.
-							serde_json::json!([])
-						}
-					};
-					Ok(json_response_pretty(&res))
-				}
-				Err(e) => {
-					error!("Request Error: {:?}", e);
-					Ok(create_error_response(e))
-				}
-			}
-		})
-	}
-
-	fn options(&self, _req: Request<Body>) -> ResponseFuture {
-		Box::pin(async { Ok(create_ok_response("{}")) })
-	}
-}
-
-// pretty-printed version of above
-fn json_response_pretty<T>(s: &T) -> Response<Body>
-where
-	T: Serialize,
-{
-	match serde_json::to_string_pretty(s) {
-		Ok(json) => response(StatusCode::OK, json),
-		Err(_) => response(StatusCode::INTERNAL_SERVER_ERROR, ""),
-	}
-}
-
-fn create_error_response(e: Error) -> Response<Body> {
-	Response::builder()
-		.status(StatusCode::INTERNAL_SERVER_ERROR)
-		.header("access-control-allow-origin", "*")
-		.header(
-			"access-control-allow-headers",
-			"Content-Type, Authorization",
-		)
-		.body(format!("{}", e).into())
-		.unwrap()
-}
-
-fn create_ok_response(json: &str) -> Response<Body> {
-	Response::builder()
-		.status(StatusCode::OK)
-		.header("access-control-allow-origin", "*")
-		.header(
-			"access-control-allow-headers",
-			"Content-Type, Authorization",
-		)
-		.header(hyper::header::CONTENT_TYPE, "application/json")
-		.body(json.to_string().into())
-		.unwrap()
-}
-
-/// Build a new hyper Response with the status code and body provided.
-///
-/// Whenever the status code is `StatusCode::OK` the text parameter should be
-/// valid JSON as the content type header will be set to `application/json'
-fn response<T: Into<Body>>(status: StatusCode, text: T) -> Response<Body> {
-	let mut builder = Response::builder();
-
-	builder = builder
-		.status(status)
-		.header("access-control-allow-origin", "*")
-		.header(
-			"access-control-allow-headers",
-			"Content-Type, Authorization",
-		);
-
-	if status == StatusCode::OK {
-		builder = builder.header(hyper::header::CONTENT_TYPE, "application/json");
-	}
-
-	builder.body(text.into()).unwrap()
-}
-
 // Legacy V1 router
+// Routes meant for the node operator (peers, status, chain maintenance)
+// are left out on public nodes.
 pub fn build_router(
 	chain: Arc<chain::Chain>,
 	tx_pool: Arc<RwLock<pool::TransactionPool>>,
 	peers: Arc<p2p::Peers>,
 	sync_state: Arc<chain::SyncState>,
+	public_node: bool,
+	cache: Option<Arc<ResponseCache>>,
+	block_events: Arc<BlockEvents>,
+	pool_events: Arc<PoolEvents>,
+	node_events: Arc<NodeEvents>,
+	alerts: Arc<ActiveAlerts>,
 ) -> Result<Router, RouterError> {
-	let route_list = vec![
+	let mut route_list = vec![
 		"get blocks".to_string(),
+		"get blocks?start_height=101&end_height=200&limit=100".to_string(),
+		"get blocks/xxx/spent".to_string(),
+		"get blocks/xxx/filter".to_string(),
 		"get headers".to_string(),
+		"get headers?start=101&count=512&format=bin".to_string(),
 		"get chain".to_string(),
-		"post chain/compact".to_string(),
-		"get chain/validate".to_string(),
 		"get chain/kernels/xxx?min_height=yyy&max_height=zzz".to_string(),
 		"get chain/outputs/byids?id=xxx,yyy,zzz".to_string(),
 		"get chain/outputs/byheight?start_height=101&end_height=200".to_string(),
-		"get status".to_string(),
+		"get chain/feemarket?n=60".to_string(),
+		"get chain/reorgs?since=1590000000".to_string(),
+		"post outputs/batch".to_string(),
+		"get outputs/xxx/rangeproof".to_string(),
+		"get kernels?start_height=101&end_height=200&start_index=1&max=1000".to_string(),
+		"post kernels/search".to_string(),
 		"get txhashset/roots".to_string(),
 		"get txhashset/lastoutputs?n=10".to_string(),
 		"get txhashset/lastrangeproofs".to_string(),
@@ -329,28 +319,68 @@ pub fn build_router(
 		"get txhashset/outputs?start_index=1&max=100".to_string(),
 		"get txhashset/merkleproof?n=1".to_string(),
 		"get pool".to_string(),
+		"get pool/txs".to_string(),
 		"post pool/push_tx".to_string(),
-		"post peers/a.b.c.d:p/ban".to_string(),
-		"post peers/a.b.c.d:p/unban".to_string(),
-		"get peers/all".to_string(),
-		"get peers/connected".to_string(),
-		"get peers/a.b.c.d".to_string(),
+		"post pool/check".to_string(),
+		"get pool/stream".to_string(),
+		"get txn/xxx".to_string(),
+		"get ws/blocks".to_string(),
+		"get events".to_string(),
+		"get ws/events".to_string(),
 		"get version".to_string(),
+		"get network/upgrade_status?version=3.1.0&n=1440".to_string(),
+		"get stats?n=60".to_string(),
+		"get stats/propagation?n=10".to_string(),
+		"get stats/history?hours=24".to_string(),
+		"get api/stats".to_string(),
+		"get healthz".to_string(),
+		"get readyz".to_string(),
 	];
+	if !public_node {
+		route_list.extend(vec![
+			"post chain/compact".to_string(),
+			"get chain/compact".to_string(),
+			"get chain/orphans".to_string(),
+			"get chain/validate".to_string(),
+			"get status".to_string(),
+			"get alerts".to_string(),
+			"post peers/a.b.c.d:p/ban".to_string(),
+			"post peers/a.b.c.d:p/unban".to_string(),
+			"get peers?state=connected&capabilities=15&sort=last_seen&offset=0&limit=100"
+				.to_string(),
+			"get peers/all".to_string(),
+			"get peers/connected".to_string(),
+			"get peers/a.b.c.d".to_string(),
+		]);
+	}
 	let index_handler = IndexHandler { list: route_list };
 
 	let output_handler = OutputHandler {
 		chain: Arc::downgrade(&chain),
 	};
+	let output_batch_handler = OutputBatchHandler {
+		chain: Arc::downgrade(&chain),
+	};
+	let rangeproof_handler = RangeProofHandler {
+		chain: Arc::downgrade(&chain),
+	};
 	let kernel_handler = KernelHandler {
 		chain: Arc::downgrade(&chain),
 	};
-	let block_handler = BlockHandler {
+	let kernels_handler = KernelsHandler {
 		chain: Arc::downgrade(&chain),
 	};
-	let header_handler = HeaderHandler {
+	let kernel_search_handler = KernelSearchHandler {
 		chain: Arc::downgrade(&chain),
 	};
+	let block_handler = Arc::new(BlockHandler {
+		chain: Arc::downgrade(&chain),
+		cache: cache.clone(),
+	});
+	let header_handler = Arc::new(HeaderHandler {
+		chain: Arc::downgrade(&chain),
+		cache,
+	});
 	let chain_tip_handler = ChainHandler {
 		chain: Arc::downgrade(&chain),
 	};
@@ -360,11 +390,36 @@ pub fn build_router(
 	let chain_validation_handler = ChainValidationHandler {
 		chain: Arc::downgrade(&chain),
 	};
+	let fee_market_handler = FeeMarketHandler {
+		chain: Arc::downgrade(&chain),
+	};
+	let reorgs_handler = ReorgsHandler {
+		chain: Arc::downgrade(&chain),
+	};
+	let orphans_handler = OrphansHandler {
+		chain: Arc::downgrade(&chain),
+	};
 	let status_handler = StatusHandler {
 		chain: Arc::downgrade(&chain),
 		peers: Arc::downgrade(&peers),
 		sync_state: Arc::downgrade(&sync_state),
 	};
+	let alerts_handler = AlertsHandler { alerts };
+	let writable_probe = Arc::new(WritableProbe::new());
+	let liveness_handler = HealthHandler {
+		chain: Arc::downgrade(&chain),
+		peers: Arc::downgrade(&peers),
+		sync_state: Arc::downgrade(&sync_state),
+		writable: writable_probe.clone(),
+		readiness: false,
+	};
+	let readiness_handler = HealthHandler {
+		chain: Arc::downgrade(&chain),
+		peers: Arc::downgrade(&peers),
+		sync_state: Arc::downgrade(&sync_state),
+		writable: writable_probe,
+		readiness: true,
+	};
 	let kernel_download_handler = KernelDownloadHandler {
 		peers: Arc::downgrade(&peers),
 	};
@@ -374,9 +429,40 @@ pub fn build_router(
 	let pool_info_handler = PoolInfoHandler {
 		tx_pool: Arc::downgrade(&tx_pool),
 	};
+	let pool_txs_handler = PoolTxsHandler {
+		tx_pool: Arc::downgrade(&tx_pool),
+		include_stem: !public_node,
+	};
 	let pool_push_handler = PoolPushHandler {
 		tx_pool: Arc::downgrade(&tx_pool),
 	};
+	let pool_check_handler = PoolCheckHandler {
+		tx_pool: Arc::downgrade(&tx_pool),
+	};
+	let tx_status_handler = TxStatusHandler {
+		chain: Arc::downgrade(&chain),
+		tx_pool: Arc::downgrade(&tx_pool),
+		include_stem: !public_node,
+	};
+	let pool_stream_handler = PoolStreamHandler {
+		events: pool_events,
+		subscribers: Arc::new(AtomicUsize::new(0)),
+	};
+	let block_subscription_handler = BlockSubscriptionHandler {
+		events: block_events,
+		subscribers: Arc::new(AtomicUsize::new(0)),
+	};
+	let events_stream_handler = EventsStreamHandler {
+		events: node_events.clone(),
+		subscribers: Arc::new(AtomicUsize::new(0)),
+	};
+	let events_subscription_handler = EventsSubscriptionHandler {
+		events: node_events,
+		subscribers: Arc::new(AtomicUsize::new(0)),
+	};
+	let peers_handler = PeersHandler {
+		peers: Arc::downgrade(&peers),
+	};
 	let peers_all_handler = PeersAllHandler {
 		peers: Arc::downgrade(&peers),
 	};
@@ -389,25 +475,70 @@ pub fn build_router(
 	let version_handler = VersionHandler {
 		chain: Arc::downgrade(&chain),
 	};
+	let upgrade_status_handler = UpgradeStatusHandler {
+		chain: Arc::downgrade(&chain),
+		peers: Arc::downgrade(&peers),
+	};
+	let stats_handler = Arc::new(StatsHandler::new(Arc::downgrade(&chain)));
+	let route_stats = Arc::new(RouteStats::new());
+	let api_stats_handler = ApiStatsHandler {
+		stats: route_stats.clone(),
+	};
 
 	let mut router = Router::new();
+	router.set_stats(route_stats);
 
 	router.add_route("/v1/", Arc::new(index_handler))?;
-	router.add_route("/v1/blocks/*", Arc::new(block_handler))?;
-	router.add_route("/v1/headers/*", Arc::new(header_handler))?;
+	router.add_route("/v1/blocks", block_handler.clone())?;
+	router.add_route("/v1/blocks/**", block_handler)?;
+	router.add_route("/v1/headers", header_handler.clone())?;
+	router.add_route("/v1/headers/*", header_handler)?;
 	router.add_route("/v1/chain", Arc::new(chain_tip_handler))?;
 	router.add_route("/v1/chain/outputs/*", Arc::new(output_handler))?;
+	router.add_route("/v1/chain/feemarket", Arc::new(fee_market_handler))?;
+	router.add_route("/v1/chain/reorgs", Arc::new(reorgs_handler))?;
+	router
+		.add_route("/v1/outputs/batch", Arc::new(output_batch_handler))?
+		.read_only();
+	router.add_route("/v1/outputs/*/rangeproof", Arc::new(rangeproof_handler))?;
 	router.add_route("/v1/chain/kernels/*", Arc::new(kernel_handler))?;
-	router.add_route("/v1/chain/compact", Arc::new(chain_compact_handler))?;
-	router.add_route("/v1/chain/validate", Arc::new(chain_validation_handler))?;
+	router.add_route("/v1/kernels", Arc::new(kernels_handler))?;
+	router
+		.add_route("/v1/kernels/search", Arc::new(kernel_search_handler))?
+		.read_only();
 	router.add_route("/v1/txhashset/*", Arc::new(txhashset_handler))?;
-	router.add_route("/v1/status", Arc::new(status_handler))?;
-	router.add_route("/v1/kerneldownload", Arc::new(kernel_download_handler))?;
 	router.add_route("/v1/pool", Arc::new(pool_info_handler))?;
+	router.add_route("/v1/pool/txs", Arc::new(pool_txs_handler))?;
 	router.add_route("/v1/pool/push_tx", Arc::new(pool_push_handler))?;
-	router.add_route("/v1/peers/all", Arc::new(peers_all_handler))?;
-	router.add_route("/v1/peers/connected", Arc::new(peers_connected_handler))?;
-	router.add_route("/v1/peers/**", Arc::new(peer_handler))?;
+	router
+		.add_route("/v1/pool/check", Arc::new(pool_check_handler))?
+		.read_only();
+	router.add_route("/v1/pool/stream", Arc::new(pool_stream_handler))?;
+	router.add_route("/v1/txn/*", Arc::new(tx_status_handler))?;
+	router.add_route("/v1/ws/blocks", Arc::new(block_subscription_handler))?;
+	router.add_route("/v1/events", Arc::new(events_stream_handler))?;
+	router.add_route("/v1/ws/events", Arc::new(events_subscription_handler))?;
 	router.add_route("/v1/version", Arc::new(version_handler))?;
+	router.add_route(
+		"/v1/network/upgrade_status",
+		Arc::new(upgrade_status_handler),
+	)?;
+	router.add_route("/v1/stats", stats_handler.clone())?;
+	router.add_route("/v1/stats/*", stats_handler)?;
+	router.add_route("/v1/api/stats", Arc::new(api_stats_handler))?;
+	router.add_route("/v1/healthz", Arc::new(liveness_handler))?;
+	router.add_route("/v1/readyz", Arc::new(readiness_handler))?;
+	if !public_node {
+		router.add_route("/v1/chain/compact", Arc::new(chain_compact_handler))?;
+		router.add_route("/v1/chain/orphans", Arc::new(orphans_handler))?;
+		router.add_route("/v1/chain/validate", Arc::new(chain_validation_handler))?;
+		router.add_route("/v1/status", Arc::new(status_handler))?;
+		router.add_route("/v1/alerts", Arc::new(alerts_handler))?;
+		router.add_route("/v1/kerneldownload", Arc::new(kernel_download_handler))?;
+		router.add_route("/v1/peers", Arc::new(peers_handler))?;
+		router.add_route("/v1/peers/all", Arc::new(peers_all_handler))?;
+		router.add_route("/v1/peers/connected", Arc::new(peers_connected_handler))?;
+		router.add_route("/v1/peers/**", Arc::new(peer_handler))?;
+	}
 	Ok(router)
 }
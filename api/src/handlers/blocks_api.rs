@@ -12,9 +12,14 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 use super::utils::{get_output, get_output_v2, w};
+use crate::cache::{cached_response, ResponseCache};
 use crate::chain;
-use crate::core::core::hash::Hash;
-use crate::core::core::hash::Hashed;
+use crate::core::core::hash::{Hash, Hashed, ZERO_HASH};
+use crate::core::core::merkle_proof::MerkleProof;
+use crate::core::core::BlockHeader;
+use crate::core::ser::{self, ProtocolVersion};
+use crate::p2p;
+use crate::p2p::msg::Headers;
 use crate::rest::*;
 use crate::router::{Handler, ResponseFuture};
 use crate::types::*;
@@ -23,15 +28,25 @@ use crate::web::*;
 use failure::ResultExt;
 use hyper::{Body, Request, StatusCode};
 use regex::Regex;
-use std::sync::Weak;
+use std::sync::{Arc, Weak};
 
 /// Gets block headers given either a hash or height or an output commit.
 /// GET /v1/headers/<hash>
 /// GET /v1/headers/<height>
 /// GET /v1/headers/<output commit>
 ///
+/// Headers looked up by hash or height are cached when the node has an api
+/// cache configured.
+///
+/// A batch of `count` headers of the header chain from height `start`, at
+/// most MAX_HEADERS_PER_BATCH, fewer past the header head. With `format=bin`
+/// they are serialized as in the Headers messages of the p2p protocol, for
+/// light clients to bootstrap from without joining the network.
+/// GET /v1/headers?start=101&count=512
+/// GET /v1/headers?start=101&count=512&format=bin
 pub struct HeaderHandler {
 	pub chain: Weak<chain::Chain>,
+	pub cache: Option<Arc<ResponseCache>>,
 }
 
 impl HeaderHandler {
@@ -41,8 +56,11 @@ impl HeaderHandler {
 			return Ok(h);
 		}
 		if let Ok(height) = input.parse() {
-			match w(&self.chain)?.get_header_by_height(height) {
-				Ok(header) => return Ok(BlockHeaderPrintable::from_header(&header)),
+			let chain = w(&self.chain)?;
+			match chain.get_header_by_height(height) {
+				Ok(header) => {
+					return Ok(BlockHeaderPrintable::from_header_in_chain(&header, &chain))
+				}
 				Err(_) => return Err(ErrorKind::NotFound.into()),
 			}
 		}
@@ -50,16 +68,16 @@ impl HeaderHandler {
 		let vec = util::from_hex(input)
 			.map_err(|e| ErrorKind::Argument(format!("invalid input: {}", e)))?;
 		let h = Hash::from_vec(&vec);
-		let header = w(&self.chain)?
-			.get_block_header(&h)
-			.context(ErrorKind::NotFound)?;
-		Ok(BlockHeaderPrintable::from_header(&header))
+		let chain = w(&self.chain)?;
+		let header = chain.get_block_header(&h).context(ErrorKind::NotFound)?;
+		Ok(BlockHeaderPrintable::from_header_in_chain(&header, &chain))
 	}
 
 	fn get_header_for_output(&self, commit_id: String) -> Result<BlockHeaderPrintable, Error> {
 		let oid = get_output(&self.chain, &commit_id)?.1;
-		match w(&self.chain)?.get_header_for_output(&oid) {
-			Ok(header) => Ok(BlockHeaderPrintable::from_header(&header)),
+		let chain = w(&self.chain)?;
+		match chain.get_header_for_output(&oid) {
+			Ok(header) => Ok(BlockHeaderPrintable::from_header_in_chain(&header, &chain)),
 			Err(_) => Err(ErrorKind::NotFound.into()),
 		}
 	}
@@ -67,7 +85,7 @@ impl HeaderHandler {
 	pub fn get_header_v2(&self, h: &Hash) -> Result<BlockHeaderPrintable, Error> {
 		let chain = w(&self.chain)?;
 		let header = chain.get_block_header(h).context(ErrorKind::NotFound)?;
-		Ok(BlockHeaderPrintable::from_header(&header))
+		Ok(BlockHeaderPrintable::from_header_in_chain(&header, &chain))
 	}
 
 	// Try to get hash from height, hash or output commit
@@ -95,15 +113,186 @@ impl HeaderHandler {
 		}
 		Err(ErrorKind::Argument("not a valid hash, height or output commit".to_owned()).into())
 	}
+
+	fn get_header_batch(&self, start: u64, count: u64) -> Result<Vec<BlockHeader>, Error> {
+		let end = start.saturating_add(count.max(1).min(MAX_HEADERS_PER_BATCH) - 1);
+		w(&self.chain)?
+			.headers_by_height(start, end)
+			.map_err(|e| ErrorKind::Internal(format!("can't get headers: {}", e)))?
+			.map(|header| -> Result<BlockHeader, Error> {
+				Ok(header.context(ErrorKind::NotFound)?)
+			})
+			.collect()
+	}
+
+	fn get_header_listing(&self, headers: Vec<BlockHeader>) -> Result<HeaderListing, Error> {
+		let chain = w(&self.chain)?;
+		let header_head = chain
+			.header_head()
+			.map_err(|e| ErrorKind::Internal(format!("can't get header head: {}", e)))?;
+		let next_height = headers
+			.last()
+			.map(|header| header.height + 1)
+			.filter(|height| *height <= header_head.height);
+		Ok(HeaderListing {
+			next_height,
+			headers: headers
+				.iter()
+				.map(|header| BlockHeaderPrintable::from_header_in_chain(header, &chain))
+				.collect(),
+		})
+	}
+
+	fn get_headers(&self, req: &Request<Body>) -> ResponseFuture {
+		let params = QueryParams::from(req.uri().query());
+		let start = parse_param_no_err!(params, "start", 0);
+		let count = parse_param_no_err!(params, "count", MAX_HEADERS_PER_BATCH);
+		let headers = match self.get_header_batch(start, count) {
+			Ok(headers) => headers,
+			Err(e) => return result_to_response::<()>(Err(e)),
+		};
+		match params.get("format").map(|f| f.as_str()) {
+			Some("bin") => ser_response(&Headers { headers }, ser_version(req)),
+			_ => result_to_response(self.get_header_listing(headers)),
+		}
+	}
+
+	// Headers looked up by height or hash are tagged with their hash and the
+	// hash of the next block they link to, a different header at that height
+	// or a new or different next block gets a different tag.
+	fn header_etag(&self, el: &str, height: Option<u64>) -> Option<String> {
+		let chain = w(&self.chain).ok()?;
+		let header = match height {
+			Some(height) => chain.get_header_by_height(height).ok()?,
+			None if el.len() == 64 => chain.get_block_header(&Hash::from_hex(el).ok()?).ok()?,
+			None => return None,
+		};
+		let hash = header.hash();
+		let next = chain
+			.get_header_by_height(header.height + 1)
+			.ok()
+			.filter(|next| next.prev_hash == hash)
+			.map(|next| next.hash())
+			.unwrap_or(ZERO_HASH);
+		Some(etag(&(hash, next).hash()))
+	}
 }
 
 impl Handler for HeaderHandler {
 	fn get(&self, req: Request<Body>) -> ResponseFuture {
 		let el = right_path_element!(req);
-		result_to_response(self.get_header(el.to_string()))
+		if el == "headers" {
+			return self.get_headers(&req);
+		}
+		let el = el.to_string();
+		let height: Option<u64> = el.parse().ok();
+		// Lookups by output commit aren't cached, the output can be spent
+		// any time.
+		if height.is_none() && el.len() != 64 {
+			return result_to_response(self.get_header(el));
+		}
+		let tag = self.header_etag(&el, height);
+		conditional_response(&req, tag, || {
+			cached_response(&self.cache, &self.chain, &req, || self.get_header(el))
+		})
+	}
+}
+
+/// Root of the header MMR at the chain head, committing to all the headers
+/// of the chain up to it.
+/// GET /v2/headers/root
+///
+/// Proof of a header of the chain being included under that root, for light
+/// clients only tracking the root to verify the header with
+/// core::core::merkle_proof::verify_header_inclusion.
+/// GET /v2/headers/<hash>/inclusionproof
+/// GET /v2/headers/<height>/inclusionproof
+pub struct HeaderProofHandler {
+	pub chain: Weak<chain::Chain>,
+}
+
+impl HeaderProofHandler {
+	fn get_proof(&self, header: &BlockHeader) -> Result<(HeaderRoot, MerkleProof), Error> {
+		let chain = w(&self.chain)?;
+		let (head, root, merkle_proof) = chain
+			.get_header_inclusion_proof(header)
+			.map_err(|e| ErrorKind::Internal(format!("can't build header proof: {}", e)))?;
+		let root = HeaderRoot {
+			height: head.height,
+			hash: head.hash().to_hex(),
+			mmr_size: merkle_proof.mmr_size,
+			header_root: root.to_hex(),
+		};
+		Ok((root, merkle_proof))
+	}
+
+	fn get_root(&self) -> Result<HeaderRoot, Error> {
+		let head = w(&self.chain)?
+			.head_header()
+			.map_err(|e| ErrorKind::Internal(format!("can't get head: {}", e)))?;
+		Ok(self.get_proof(&head)?.0)
+	}
+
+	fn get_inclusion_proof(&self, input: &str) -> Result<HeaderInclusionProof, Error> {
+		let chain = w(&self.chain)?;
+		let header = if let Ok(height) = input.parse() {
+			chain
+				.get_header_by_height(height)
+				.context(ErrorKind::NotFound)?
+		} else {
+			check_block_param(input)?;
+			let hash = Hash::from_hex(input)
+				.map_err(|e| ErrorKind::Argument(format!("invalid input: {}", e)))?;
+			let header = chain.get_block_header(&hash).context(ErrorKind::NotFound)?;
+			// Only the headers of the chain are in the header MMR.
+			chain
+				.is_on_current_chain(&header)
+				.context(ErrorKind::NotFound)?;
+			header
+		};
+		let head = chain
+			.head()
+			.map_err(|e| ErrorKind::Internal(format!("can't get head: {}", e)))?;
+		if header.height > head.height {
+			// Past the head, the header chain being ahead of the blocks.
+			return Err(ErrorKind::NotFound.into());
+		}
+		let (root, merkle_proof) = self.get_proof(&header)?;
+		let data = ser::ser_vec(&header, ProtocolVersion::local())
+			.map_err(|e| ErrorKind::Internal(format!("can't serialize header: {}", e)))?;
+		Ok(HeaderInclusionProof {
+			height: header.height,
+			hash: header.hash().to_hex(),
+			header: util::to_hex(data),
+			root,
+			merkle_proof: merkle_proof.to_hex(),
+		})
 	}
 }
 
+impl Handler for HeaderProofHandler {
+	fn get(&self, req: Request<Body>) -> ResponseFuture {
+		let mut path = req.uri().path().trim_end_matches('/').rsplit('/');
+		match (path.next(), path.next()) {
+			(Some("root"), _) => result_to_response(self.get_root()),
+			(Some("inclusionproof"), Some(input)) => {
+				result_to_response(self.get_inclusion_proof(input))
+			}
+			_ => response(StatusCode::BAD_REQUEST, "invalid url"),
+		}
+	}
+}
+
+/// Most blocks returned per request for a range of blocks.
+const MAX_BLOCKS_PER_REQUEST: u64 = 100;
+
+/// Most headers returned per request for a range of headers.
+const MAX_HEADERS_PER_REQUEST: u64 = 1000;
+
+/// Most headers returned per batch of headers, as many as a Headers message
+/// carries.
+const MAX_HEADERS_PER_BATCH: u64 = p2p::MAX_BLOCK_HEADERS as u64;
+
 /// Gets block details given either a hash or an unspent commit
 /// GET /v1/blocks/<hash>
 /// GET /v1/blocks/<height>
@@ -114,8 +303,31 @@ impl Handler for HeaderHandler {
 ///
 /// Optionally turn off the Merkle proof extraction by passing "?no_merkle_proof" query
 /// param GET /v1/blocks/<hash>?no_merkle_proof
+///
+/// Optionally return the full block in its hex encoded wire serialization
+/// GET /v1/blocks/<hash>?raw
+///
+/// On archive nodes, the outputs spent by the block inputs, with the height
+/// and features they were created with
+/// GET /v1/blocks/<hash>/spent
+/// GET /v1/blocks/<height>/spent
+///
+/// The compact filter of the output and input commitments of the block
+/// GET /v1/blocks/<hash>/filter
+/// GET /v1/blocks/<height>/filter
+///
+/// A contiguous range of blocks of the main chain, at most
+/// MAX_BLOCKS_PER_REQUEST per page (MAX_HEADERS_PER_REQUEST when only the
+/// headers are requested). `end_height` defaults to the chain head, the
+/// `include_proof` and `no_merkle_proof` flags apply to the range too.
+/// GET /v1/blocks?start_height=101&end_height=200&limit=100
+/// GET /v1/blocks?start_height=101&end_height=200&headers_only
+///
+/// Blocks looked up by hash or height are cached when the node has an api
+/// cache configured.
 pub struct BlockHandler {
 	pub chain: Weak<chain::Chain>,
+	pub cache: Option<Arc<ResponseCache>>,
 }
 
 impl BlockHandler {
@@ -131,6 +343,13 @@ impl BlockHandler {
 			.map_err(|_| ErrorKind::Internal("chain error".to_owned()).into())
 	}
 
+	fn get_raw_block(&self, h: &Hash, version: ProtocolVersion) -> Result<RawBlock, Error> {
+		let chain = w(&self.chain)?;
+		let block = chain.get_block(h).context(ErrorKind::NotFound)?;
+		RawBlock::from_block(&block, version)
+			.map_err(|e| ErrorKind::Internal(format!("failed to serialize block: {}", e)).into())
+	}
+
 	fn get_compact_block(&self, h: &Hash) -> Result<CompactBlockPrintable, Error> {
 		let chain = w(&self.chain)?;
 		let block = chain.get_block(h).context(ErrorKind::NotFound)?;
@@ -138,6 +357,110 @@ impl BlockHandler {
 			.map_err(|_| ErrorKind::Internal("chain error".to_owned()).into())
 	}
 
+	fn get_spent_outputs(&self, h: &Hash) -> Result<Vec<SpentOutputPrintable>, Error> {
+		let chain = w(&self.chain)?;
+		if !chain.archive_mode() {
+			return Err(ErrorKind::Argument(
+				"spent outputs are only available on archive nodes".to_owned(),
+			)
+			.into());
+		}
+		let spent = chain.get_spent_outputs(h).context(ErrorKind::NotFound)?;
+		Ok(spent
+			.iter()
+			.map(|(out, pos)| SpentOutputPrintable::new(out, pos))
+			.collect())
+	}
+
+	fn get_block_filter(&self, h: &Hash) -> Result<BlockFilterPrintable, Error> {
+		let chain = w(&self.chain)?;
+		let header = chain.get_block_header(h).context(ErrorKind::NotFound)?;
+		let filter = chain.get_block_filter(h).context(ErrorKind::NotFound)?;
+		Ok(BlockFilterPrintable::new(&header, &filter))
+	}
+
+	// Main chain heights to return a page of, along with the height the next
+	// page starts at.
+	fn range_page(
+		&self,
+		start_height: u64,
+		end_height: Option<u64>,
+		limit: u64,
+	) -> Result<(u64, u64, Option<u64>), Error> {
+		let head = w(&self.chain)?
+			.head()
+			.map_err(|e| ErrorKind::Internal(format!("can't get head: {}", e)))?;
+		let end_height = end_height.unwrap_or(head.height).min(head.height);
+		if start_height > end_height {
+			return Err(ErrorKind::Argument(format!(
+				"no blocks between heights {} and {}",
+				start_height, end_height
+			))
+			.into());
+		}
+		let page_end = end_height.min(start_height.saturating_add(limit.max(1) - 1));
+		let next_height = if page_end < end_height {
+			Some(page_end + 1)
+		} else {
+			None
+		};
+		Ok((start_height, page_end, next_height))
+	}
+
+	pub fn get_blocks(
+		&self,
+		start_height: u64,
+		end_height: Option<u64>,
+		limit: u64,
+		include_proof: bool,
+		include_merkle_proof: bool,
+	) -> Result<BlockListing, Error> {
+		let (start_height, end_height, next_height) =
+			self.range_page(start_height, end_height, limit.min(MAX_BLOCKS_PER_REQUEST))?;
+		let chain = w(&self.chain)?;
+		let blocks = chain
+			.blocks_by_height(start_height, end_height)
+			.map_err(|e| ErrorKind::Internal(format!("can't get blocks: {}", e)))?
+			.map(|block| -> Result<BlockPrintable, Error> {
+				let block = block.context(ErrorKind::NotFound)?;
+				BlockPrintable::from_block(
+					&block,
+					chain.clone(),
+					include_proof,
+					include_merkle_proof,
+				)
+				.map_err(|_| ErrorKind::Internal("chain error".to_owned()).into())
+			})
+			.collect::<Result<Vec<_>, _>>()?;
+		Ok(BlockListing {
+			next_height,
+			blocks,
+		})
+	}
+
+	pub fn get_headers(
+		&self,
+		start_height: u64,
+		end_height: Option<u64>,
+		limit: u64,
+	) -> Result<HeaderListing, Error> {
+		let (start_height, end_height, next_height) =
+			self.range_page(start_height, end_height, limit.min(MAX_HEADERS_PER_REQUEST))?;
+		let chain = w(&self.chain)?;
+		let headers = chain
+			.headers_by_height(start_height, end_height)
+			.map_err(|e| ErrorKind::Internal(format!("can't get headers: {}", e)))?
+			.map(|header| -> Result<BlockHeaderPrintable, Error> {
+				let header = header.context(ErrorKind::NotFound)?;
+				Ok(BlockHeaderPrintable::from_header_in_chain(&header, &chain))
+			})
+			.collect::<Result<Vec<_>, _>>()?;
+		Ok(HeaderListing {
+			next_height,
+			headers,
+		})
+	}
+
 	// Try to decode the string as a height or a hash.
 	fn parse_input(&self, input: String) -> Result<Hash, Error> {
 		if let Ok(height) = input.parse() {
@@ -189,9 +512,45 @@ fn check_block_param(input: &str) -> Result<(), Error> {
 	Ok(())
 }
 
+// Start height, optional end height and page size of a range request.
+fn parse_range(params: &QueryParams) -> Result<(u64, Option<u64>, u64), Error> {
+	let start_height = parse_param!(params, "start_height", 0);
+	let end_height = match params.get("end_height") {
+		Some(h) => Some(
+			h.parse()
+				.map_err(|_| ErrorKind::RequestError("invalid end height".into()))?,
+		),
+		None => None,
+	};
+	let limit = parse_param!(params, "limit", MAX_BLOCKS_PER_REQUEST);
+	Ok((start_height, end_height, limit))
+}
+
 impl Handler for BlockHandler {
 	fn get(&self, req: Request<Body>) -> ResponseFuture {
-		let el = right_path_element!(req);
+		let mut path_elems = req.uri().path().trim_end_matches('/').rsplit('/');
+		let (el, sub) = match path_elems.next() {
+			None => return response(StatusCode::BAD_REQUEST, "invalid url"),
+			Some("blocks") => {
+				let params = QueryParams::from(req.uri().query());
+				let range = parse_range(&params);
+				if params.get("headers_only").is_some() {
+					return result_to_response(
+						range.and_then(|(start, end, limit)| self.get_headers(start, end, limit)),
+					);
+				}
+				let include_proof = params.get("include_proof").is_some();
+				let include_merkle_proof = params.get("no_merkle_proof").is_none();
+				return result_to_response(range.and_then(|(start, end, limit)| {
+					self.get_blocks(start, end, limit, include_proof, include_merkle_proof)
+				}));
+			}
+			Some(sub @ "spent") | Some(sub @ "filter") => match path_elems.next() {
+				None => return response(StatusCode::BAD_REQUEST, "invalid url"),
+				Some(el) => (el, Some(sub)),
+			},
+			Some(el) => (el, None),
+		};
 		let h = match self.parse_input(el.to_string()) {
 			Err(e) => {
 				return response(
@@ -202,14 +561,41 @@ impl Handler for BlockHandler {
 			Ok(h) => h,
 		};
 
+		// Raw blocks and filters never change, while the spent status and
+		// Merkle proofs of the outputs of a block, full or compact, change as
+		// the chain grows.
+		let block_etag = Some(etag(&h));
+		let state_etag = w(&self.chain)
+			.ok()
+			.and_then(|chain| chain.head().ok())
+			.map(|head| etag(&(h, head.last_block_h).hash()));
+
+		match sub {
+			Some("spent") => {
+				return conditional_response(&req, state_etag, || {
+					cached_response(&self.cache, &self.chain, &req, || {
+						self.get_spent_outputs(&h)
+					})
+				});
+			}
+			Some("filter") => {
+				return conditional_response(&req, block_etag, || {
+					cached_response(&self.cache, &self.chain, &req, || self.get_block_filter(&h))
+				});
+			}
+			_ => {}
+		}
+
 		let mut include_proof = false;
 		let mut include_merkle_proof = true;
 		if let Some(params) = req.uri().query() {
 			let query = url::form_urlencoded::parse(params.as_bytes());
 			let mut compact = false;
+			let mut raw = false;
 			for (param, _) in query {
 				match param.as_ref() {
 					"compact" => compact = true,
+					"raw" => raw = true,
 					"no_merkle_proof" => include_merkle_proof = false,
 					"include_proof" => include_proof = true,
 					_ => {
@@ -221,10 +607,25 @@ impl Handler for BlockHandler {
 				}
 			}
 
+			if raw {
+				return conditional_response(&req, block_etag, || {
+					cached_response(&self.cache, &self.chain, &req, || {
+						self.get_raw_block(&h, ser_version(&req))
+					})
+				});
+			}
 			if compact {
-				return result_to_response(self.get_compact_block(&h));
+				return conditional_response(&req, state_etag, || {
+					cached_response(&self.cache, &self.chain, &req, || {
+						self.get_compact_block(&h)
+					})
+				});
 			}
 		}
-		result_to_response(self.get_block(&h, include_proof, include_merkle_proof))
+		conditional_response(&req, state_etag, || {
+			cached_response(&self.cache, &self.chain, &req, || {
+				self.get_block(&h, include_proof, include_merkle_proof)
+			})
+		})
 	}
 }
@@ -14,7 +14,8 @@
 
 use super::utils::{get_output, get_output_v2, w};
 use crate::chain;
-use crate::core::core::hash::Hashed;
+use crate::core::consensus::{DAY_HEIGHT, HOUR_HEIGHT};
+use crate::core::core::hash::{Hash, Hashed};
 use crate::rest::*;
 use crate::router::{Handler, ResponseFuture};
 use crate::types::*;
@@ -22,8 +23,12 @@ use crate::util;
 use crate::util::secp::pedersen::Commitment;
 use crate::web::*;
 use failure::ResultExt;
-use hyper::{Body, Request, StatusCode};
+use futures::{stream, StreamExt};
+use hyper::header::CONTENT_TYPE;
+use hyper::{Body, Request, Response, StatusCode};
+use std::io;
 use std::sync::Weak;
+use std::thread;
 
 /// Chain handler. Get the head details.
 /// GET /v1/chain
@@ -41,8 +46,13 @@ impl ChainHandler {
 }
 
 impl Handler for ChainHandler {
-	fn get(&self, _req: Request<Body>) -> ResponseFuture {
-		result_to_response(self.get_tip())
+	fn get(&self, req: Request<Body>) -> ResponseFuture {
+		// Tagged with the head hash, so pollers only get the tip when it moved.
+		let tag = w(&self.chain)
+			.ok()
+			.and_then(|chain| chain.head().ok())
+			.map(|head| etag(&head.last_block_h));
+		conditional_response(&req, tag, || result_to_response(self.get_tip()))
 	}
 }
 
@@ -75,6 +85,9 @@ impl Handler for ChainValidationHandler {
 /// Chain compaction handler. Trigger a compaction of the chain state to regain
 /// storage space.
 /// POST /v1/chain/compact
+/// Or get the status of the compactions, the node also compacting its chain
+/// on its own every now and then:
+/// GET /v1/chain/compact
 pub struct ChainCompactHandler {
 	pub chain: Weak<chain::Chain>,
 }
@@ -85,9 +98,32 @@ impl ChainCompactHandler {
 			.compact()
 			.map_err(|_| ErrorKind::Internal("chain error".to_owned()).into())
 	}
+
+	/// Starts a compaction in the background, its outcome reported by the
+	/// compaction status.
+	pub fn start_compaction(&self) -> Result<(), Error> {
+		let chain = w(&self.chain)?;
+		thread::Builder::new()
+			.name("compactor".to_string())
+			.spawn(move || {
+				if let Err(e) = chain.compact() {
+					warn!("Could not compact chain: {}", e);
+				}
+			})
+			.map_err(|e| ErrorKind::Internal(format!("can't start compaction: {}", e)))?;
+		Ok(())
+	}
+
+	pub fn compaction_status(&self) -> Result<chain::CompactionStatus, Error> {
+		Ok(w(&self.chain)?.compaction_status())
+	}
 }
 
 impl Handler for ChainCompactHandler {
+	fn get(&self, _req: Request<Body>) -> ResponseFuture {
+		result_to_response(self.compaction_status())
+	}
+
 	fn post(&self, _req: Request<Body>) -> ResponseFuture {
 		match w_fut!(&self.chain).compact() {
 			Ok(_) => response(StatusCode::OK, "{}"),
@@ -99,6 +135,411 @@ impl Handler for ChainCompactHandler {
 	}
 }
 
+/// Orphans handler. The blocks waiting in the orphan pool for their parent,
+/// lowest first. The owner API can also drop them, when one is suspected of
+/// wedging the sync.
+/// GET /v1/chain/orphans
+pub struct OrphansHandler {
+	pub chain: Weak<chain::Chain>,
+}
+
+impl OrphansHandler {
+	pub fn list_orphans(&self) -> Result<Vec<OrphanBlock>, Error> {
+		let orphans = w(&self.chain)?.orphans();
+		Ok(orphans.iter().map(OrphanBlock::from_orphan).collect())
+	}
+
+	pub fn remove_orphan(&self, hash: &str) -> Result<(), Error> {
+		let hash = Hash::from_hex(hash)
+			.map_err(|_| ErrorKind::Argument(format!("invalid block hash {}", hash)))?;
+		if !w(&self.chain)?.remove_orphan(&hash) {
+			return Err(ErrorKind::NotFound.into());
+		}
+		Ok(())
+	}
+
+	pub fn clear_orphans(&self) -> Result<usize, Error> {
+		Ok(w(&self.chain)?.clear_orphans())
+	}
+}
+
+impl Handler for OrphansHandler {
+	fn get(&self, _req: Request<Body>) -> ResponseFuture {
+		result_to_response(self.list_orphans())
+	}
+}
+
+/// Reorgs handler. The latest reorgs of the chain, oldest first, optionally
+/// only those since a time (seconds since epoch). Lets services caching chain
+/// data drop what a fork invalidated.
+/// GET /v1/chain/reorgs?since=1590000000
+pub struct ReorgsHandler {
+	pub chain: Weak<chain::Chain>,
+}
+
+impl ReorgsHandler {
+	pub fn get_reorgs(&self, since: i64) -> Result<Vec<Reorg>, Error> {
+		let reorgs = w(&self.chain)?
+			.reorgs(since)
+			.map_err(|e| ErrorKind::Internal(format!("can't get reorgs: {}", e)))?;
+		Ok(reorgs.into_iter().map(Reorg::from_reorg).collect())
+	}
+}
+
+impl Handler for ReorgsHandler {
+	fn get(&self, req: Request<Body>) -> ResponseFuture {
+		let params = QueryParams::from(req.uri().query());
+		let since = parse_param_no_err!(params, "since", 0);
+		result_to_response(self.get_reorgs(since))
+	}
+}
+
+/// Fee market handler. Block weight utilization, fees and fee per weight
+/// percentiles over the last n blocks (default an hour, at most a day).
+/// GET /v1/chain/feemarket?n=60
+pub struct FeeMarketHandler {
+	pub chain: Weak<chain::Chain>,
+}
+
+impl FeeMarketHandler {
+	pub fn get_fee_market(&self, n: u64) -> Result<FeeMarketStats, Error> {
+		let blocks = w(&self.chain)?
+			.latest_block_fees(n.min(DAY_HEIGHT))
+			.map_err(|e| ErrorKind::Internal(format!("can't get block fees: {}", e)))?;
+		Ok(FeeMarketStats::from_blocks(&blocks))
+	}
+}
+
+impl Handler for FeeMarketHandler {
+	fn get(&self, req: Request<Body>) -> ResponseFuture {
+		let params = QueryParams::from(req.uri().query());
+		let n = parse_param_no_err!(params, "n", HOUR_HEIGHT);
+		result_to_response(self.get_fee_market(n))
+	}
+}
+
+/// Maximum number of outputs looked up in a single batch request.
+const OUTPUT_LOOKUP_MAX: usize = 1_000;
+
+// Looks up to OUTPUT_LOOKUP_MAX outputs by commitment in a single request,
+// returning whether each is unspent or spent along with its height and MMR
+// index:
+// POST /v1/outputs/batch
+// {"commits": ["xxx", "yyy"]}
+pub struct OutputBatchHandler {
+	pub chain: Weak<chain::Chain>,
+}
+
+// Position of an output no longer unspent, if the chain still knows it was
+// spent.
+fn spent_output(
+	chain: &Weak<chain::Chain>,
+	commit: &str,
+) -> Result<Option<chain::CommitPos>, Error> {
+	let commit = util::from_hex(commit.to_owned())
+		.map_err(|e| ErrorKind::Argument(format!("invalid commitment {}: {}", commit, e)))?;
+	Ok(w(chain)?
+		.get_spent_output_pos(&Commitment::from_vec(commit))
+		.ok())
+}
+
+async fn lookup_outputs(
+	chain: Weak<chain::Chain>,
+	req: Request<Body>,
+) -> Result<Vec<OutputLookup>, Error> {
+	let req: OutputLookupRequest = parse_body(req).await?;
+	if req.commits.len() > OUTPUT_LOOKUP_MAX {
+		return Err(ErrorKind::RequestError(format!(
+			"too many commitments, at most {} per request",
+			OUTPUT_LOOKUP_MAX
+		))
+		.into());
+	}
+	let mut outputs = Vec::with_capacity(req.commits.len());
+	for commit in req.commits {
+		if commit.len() != 66 {
+			return Err(
+				ErrorKind::RequestError(format!("invalid commit length for {}", commit)).into(),
+			);
+		}
+		let output = match get_output(&chain, &commit) {
+			Ok((output, _)) => OutputLookup {
+				commit,
+				status: OutputStatus::Unspent,
+				height: Some(output.height),
+				mmr_index: Some(output.mmr_index),
+			},
+			Err(e) => match e.kind() {
+				ErrorKind::NotFound => match spent_output(&chain, &commit)? {
+					Some(pos) => OutputLookup {
+						commit,
+						status: OutputStatus::Spent,
+						height: Some(pos.height),
+						mmr_index: Some(pos.pos),
+					},
+					None => OutputLookup {
+						commit,
+						status: OutputStatus::Unknown,
+						height: None,
+						mmr_index: None,
+					},
+				},
+				_ => return Err(e),
+			},
+		};
+		outputs.push(output);
+	}
+	Ok(outputs)
+}
+
+impl Handler for OutputBatchHandler {
+	fn post(&self, req: Request<Body>) -> ResponseFuture {
+		let chain = self.chain.clone();
+		Box::pin(async move { result_to_response(lookup_outputs(chain, req).await).await })
+	}
+}
+
+/// Maximum number of outputs looked up in a single streamed request.
+const OUTPUT_STREAM_MAX: usize = 10_000;
+
+/// Outputs looked up and written to the response at a time.
+const OUTPUT_STREAM_CHUNK: usize = 100;
+
+// Looks up to OUTPUT_STREAM_MAX outputs by commitment, returning whether
+// each is unspent along with the block holding it. The JSON array of the
+// results is streamed as the outputs are looked up:
+// POST /v2/outputs
+// ["xxx", "yyy"]
+pub struct OutputStreamHandler {
+	pub chain: Weak<chain::Chain>,
+}
+
+async fn parse_commits(req: Request<Body>) -> Result<Vec<String>, Error> {
+	let commits: Vec<String> = parse_body(req).await?;
+	if commits.len() > OUTPUT_STREAM_MAX {
+		return Err(ErrorKind::RequestError(format!(
+			"too many commitments, at most {} per request",
+			OUTPUT_STREAM_MAX
+		))
+		.into());
+	}
+	// Reject the whole request upfront rather than halfway through the
+	// response.
+	for commit in &commits {
+		if commit.len() != 66 || util::from_hex(commit.clone()).is_err() {
+			return Err(ErrorKind::RequestError(format!("invalid commitment {}", commit)).into());
+		}
+	}
+	Ok(commits)
+}
+
+fn lookup_output_v2(chain: &Weak<chain::Chain>, commit: &str) -> Result<OutputLookupV2, Error> {
+	match get_output(chain, commit) {
+		Ok((output, id)) => {
+			let header = w(chain)?
+				.get_header_by_height(output.height)
+				.map_err(|e| ErrorKind::Internal(format!("can't get header: {}", e)))?;
+			let output_type = if id.features.is_coinbase() {
+				OutputType::Coinbase
+			} else {
+				OutputType::Transaction
+			};
+			Ok(OutputLookupV2 {
+				commit: commit.to_owned(),
+				status: OutputStatus::Unspent,
+				output_type: Some(output_type),
+				height: Some(output.height),
+				block_hash: Some(header.hash().to_hex()),
+				mmr_index: Some(output.mmr_index),
+			})
+		}
+		Err(e) => match e.kind() {
+			ErrorKind::NotFound => match spent_output(chain, commit)? {
+				Some(pos) => {
+					let header = w(chain)?
+						.get_header_by_height(pos.height)
+						.map_err(|e| ErrorKind::Internal(format!("can't get header: {}", e)))?;
+					Ok(OutputLookupV2 {
+						commit: commit.to_owned(),
+						status: OutputStatus::Spent,
+						output_type: None,
+						height: Some(pos.height),
+						block_hash: Some(header.hash().to_hex()),
+						mmr_index: Some(pos.pos),
+					})
+				}
+				None => Ok(OutputLookupV2 {
+					commit: commit.to_owned(),
+					status: OutputStatus::Unknown,
+					output_type: None,
+					height: None,
+					block_hash: None,
+					mmr_index: None,
+				}),
+			},
+			_ => Err(e),
+		},
+	}
+}
+
+// Streams the JSON array of the lookups, a chunk of outputs at a time.
+fn stream_outputs(chain: Weak<chain::Chain>, commits: Vec<String>) -> Response<Body> {
+	let chunks: Vec<Vec<String>> = commits
+		.chunks(OUTPUT_STREAM_CHUNK)
+		.map(|chunk| chunk.to_vec())
+		.collect();
+	let last = chunks.len().saturating_sub(1);
+	let body = stream::iter(chunks.into_iter().enumerate()).map(move |(i, chunk)| {
+		let mut json = String::from(if i == 0 { "[" } else { "," });
+		for (j, commit) in chunk.iter().enumerate() {
+			let lookup = lookup_output_v2(&chain, commit)
+				.map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+			if j > 0 {
+				json.push(',');
+			}
+			json.push_str(&serde_json::to_string(&lookup)?);
+		}
+		if i == last {
+			json.push(']');
+		}
+		Ok::<_, io::Error>(json)
+	});
+	Response::builder()
+		.status(StatusCode::OK)
+		.header(CONTENT_TYPE, "application/json")
+		.body(Body::wrap_stream(body))
+		.unwrap()
+}
+
+impl Handler for OutputStreamHandler {
+	fn post(&self, req: Request<Body>) -> ResponseFuture {
+		let chain = self.chain.clone();
+		Box::pin(async move {
+			match parse_commits(req).await {
+				Ok(commits) if commits.is_empty() => json_response(&commits).await,
+				Ok(commits) => Ok(stream_outputs(chain, commits)),
+				Err(e) => result_to_response::<()>(Err(e)).await,
+			}
+		})
+	}
+}
+
+// Full rangeproof of an unspent output and its MMR index, without fetching
+// the whole block it's in:
+// GET /v1/outputs/<commit>/rangeproof
+pub struct RangeProofHandler {
+	pub chain: Weak<chain::Chain>,
+}
+
+impl RangeProofHandler {
+	fn get_rangeproof(&self, req: Request<Body>) -> Result<OutputRangeProof, Error> {
+		let commit = req
+			.uri()
+			.path()
+			.trim_end_matches('/')
+			.rsplit('/')
+			.nth(1)
+			.ok_or_else(|| ErrorKind::RequestError("missing commit".into()))?;
+		if commit.len() != 66 {
+			return Err(
+				ErrorKind::RequestError(format!("invalid commit length for {}", commit)).into(),
+			);
+		}
+		let chain = w(&self.chain)?;
+		let tip = chain
+			.head()
+			.map_err(|e| ErrorKind::Internal(format!("can't get head: {}", e)))?;
+		let (output, _) = get_output(&self.chain, commit)?;
+		let unspent = chain
+			.get_unspent_output_at(output.mmr_index)
+			.map_err(|_| ErrorKind::NotFound)?;
+		Ok(OutputRangeProof {
+			commit: commit.to_owned(),
+			proof: util::to_hex(unspent.proof.proof[..unspent.proof.plen].to_vec()),
+			proof_hash: util::to_hex(unspent.proof.hash().to_vec()),
+			height: output.height,
+			mmr_index: output.mmr_index,
+			tip_height: tip.height,
+			tip_hash: tip.last_block_h.to_hex(),
+		})
+	}
+}
+
+impl Handler for RangeProofHandler {
+	fn get(&self, req: Request<Body>) -> ResponseFuture {
+		result_to_response(self.get_rangeproof(req))
+	}
+}
+
+// Merkle proof of an unspent output against the output root of the chain
+// head. Lets light clients check an output is unspent from the headers
+// alone. Only served at the head, a proof at another header would need the
+// txhashset rewound. A header hash can be provided to make sure the proof
+// is against it, the request fails if the head moved on:
+// GET /v2/outputs/<commit>/merkleproof?header=xxx
+pub struct OutputMerkleProofHandler {
+	pub chain: Weak<chain::Chain>,
+}
+
+impl OutputMerkleProofHandler {
+	fn get_merkle_proof(&self, req: Request<Body>) -> Result<OutputMerkleProof, Error> {
+		let commit = req
+			.uri()
+			.path()
+			.trim_end_matches('/')
+			.rsplit('/')
+			.nth(1)
+			.ok_or_else(|| ErrorKind::RequestError("missing commit".into()))?;
+		if commit.len() != 66 {
+			return Err(
+				ErrorKind::RequestError(format!("invalid commit length for {}", commit)).into(),
+			);
+		}
+		let chain = w(&self.chain)?;
+		let params = QueryParams::from(req.uri().query());
+		let expected = params
+			.get("header")
+			.map(|hash| {
+				Hash::from_hex(hash)
+					.map_err(|_| ErrorKind::RequestError(format!("invalid header hash {}", hash)))
+			})
+			.transpose()?;
+		let (output, id) = get_output(&self.chain, commit)?;
+		let (header, merkle_proof) = chain
+			.get_merkle_proof_at_head(&id)
+			.map_err(|e| ErrorKind::Internal(format!("can't build merkle proof: {}", e)))?;
+		if let Some(expected) = expected {
+			if expected != header.hash() {
+				return Err(ErrorKind::Argument(format!(
+					"merkle proofs are only served at the head {}",
+					header.hash()
+				))
+				.into());
+			}
+		}
+		let output_type = if id.features.is_coinbase() {
+			OutputType::Coinbase
+		} else {
+			OutputType::Transaction
+		};
+		Ok(OutputMerkleProof {
+			commit: commit.to_owned(),
+			output_type,
+			mmr_index: output.mmr_index,
+			header_height: header.height,
+			header_hash: header.hash().to_hex(),
+			output_root: header.output_root.to_hex(),
+			merkle_proof: merkle_proof.to_hex(),
+		})
+	}
+}
+
+impl Handler for OutputMerkleProofHandler {
+	fn get(&self, req: Request<Body>) -> ResponseFuture {
+		result_to_response(self.get_merkle_proof(req))
+	}
+}
+
 // Supports retrieval of multiple outputs in a single request -
 // GET /v1/chain/outputs/byids?id=xxx,yyy,zzz
 // GET /v1/chain/outputs/byids?id=xxx&id=yyy&id=zzz
@@ -381,7 +822,9 @@ impl Handler for OutputHandler {
 
 /// Kernel handler, search for a kernel by excess commitment
 /// GET /v1/chain/kernels/XXX?min_height=YYY&max_height=ZZZ
-/// The `min_height` and `max_height` parameters are optional
+/// The `min_height` and `max_height` parameters are optional. Without them the
+/// kernel is looked up in the kernel index, otherwise the kernel MMR is
+/// searched between those heights.
 pub struct KernelHandler {
 	pub chain: Weak<chain::Chain>,
 }
@@ -395,12 +838,7 @@ impl KernelHandler {
 			.rsplit('/')
 			.next()
 			.ok_or_else(|| ErrorKind::RequestError("missing excess".into()))?;
-		let excess = util::from_hex(excess.to_owned())
-			.map_err(|_| ErrorKind::RequestError("invalid excess hex".into()))?;
-		if excess.len() != 33 {
-			return Err(ErrorKind::RequestError("invalid excess length".into()).into());
-		}
-		let excess = Commitment::from_vec(excess);
+		let excess = parse_excess(excess.to_owned())?;
 
 		let chain = w(&self.chain)?;
 
@@ -430,15 +868,7 @@ impl KernelHandler {
 			}
 		}
 
-		let kernel = chain
-			.get_kernel_height(&excess, min_height, max_height)
-			.map_err(|e| ErrorKind::Internal(format!("{}", e)))?
-			.map(|(tx_kernel, height, mmr_index)| LocatedTxKernel {
-				tx_kernel,
-				height,
-				mmr_index,
-			});
-		Ok(kernel)
+		locate_kernel(&chain, &excess, min_height, max_height)
 	}
 
 	pub fn get_kernel_v2(
@@ -447,24 +877,71 @@ impl KernelHandler {
 		min_height: Option<u64>,
 		max_height: Option<u64>,
 	) -> Result<LocatedTxKernel, Error> {
-		let excess = util::from_hex(excess)
-			.map_err(|_| ErrorKind::RequestError("invalid excess hex".into()))?;
-		if excess.len() != 33 {
-			return Err(ErrorKind::RequestError("invalid excess length".into()).into());
-		}
-		let excess = Commitment::from_vec(excess);
-
+		let excess = parse_excess(excess)?;
 		let chain = w(&self.chain)?;
+		let kernel = locate_kernel(&chain, &excess, min_height, max_height)?;
+		kernel.ok_or_else(|| ErrorKind::NotFound.into())
+	}
+}
+
+fn parse_excess(excess: String) -> Result<Commitment, Error> {
+	let excess =
+		util::from_hex(excess).map_err(|_| ErrorKind::RequestError("invalid excess hex".into()))?;
+	if excess.len() != 33 {
+		return Err(ErrorKind::RequestError("invalid excess length".into()).into());
+	}
+	Ok(Commitment::from_vec(excess))
+}
+
+// Latest kernel with the given excess along with the block it was included
+// in, from the kernel index unless restricted to a range of heights.
+fn locate_kernel(
+	chain: &chain::Chain,
+	excess: &Commitment,
+	min_height: Option<u64>,
+	max_height: Option<u64>,
+) -> Result<Option<LocatedTxKernel>, Error> {
+	if min_height.is_none() && max_height.is_none() {
 		let kernel = chain
-			.get_kernel_height(&excess, min_height, max_height)
+			.get_kernel_by_excess(excess)
 			.map_err(|e| ErrorKind::Internal(format!("{}", e)))?
-			.map(|(tx_kernel, height, mmr_index)| LocatedTxKernel {
+			.map(|(tx_kernel, header, mmr_index)| LocatedTxKernel {
+				first_seen: kernel_first_seen(chain, excess),
 				tx_kernel,
-				height,
+				height: header.height,
 				mmr_index,
+				block_hash: Some(header.hash().to_hex()),
 			});
-		kernel.ok_or_else(|| ErrorKind::NotFound.into())
+		return Ok(kernel);
 	}
+
+	let kernel = match chain
+		.get_kernel_height(excess, min_height, max_height)
+		.map_err(|e| ErrorKind::Internal(format!("{}", e)))?
+	{
+		Some(kernel) => kernel,
+		None => return Ok(None),
+	};
+	let (tx_kernel, height, mmr_index) = kernel;
+	let header = chain
+		.get_header_by_height(height)
+		.map_err(|e| ErrorKind::Internal(format!("{}", e)))?;
+	Ok(Some(LocatedTxKernel {
+		tx_kernel,
+		height,
+		mmr_index,
+		block_hash: Some(header.hash().to_hex()),
+		first_seen: kernel_first_seen(chain, excess),
+	}))
+}
+
+// When the kernel was first seen, if recorded. Kernels of the blocks we synced
+// through, or seen before the node kept track, have none.
+fn kernel_first_seen(chain: &chain::Chain, excess: &Commitment) -> Option<String> {
+	chain
+		.get_kernel_first_seen(excess)
+		.ok()
+		.map(|seen| seen.to_rfc3339())
 }
 
 impl Handler for KernelHandler {
@@ -472,3 +949,150 @@ impl Handler for KernelHandler {
 		result_to_response(self.get_kernel(req))
 	}
 }
+
+/// Maximum number of kernels searched in a single batch request.
+const KERNEL_SEARCH_MAX: usize = 1_000;
+
+// Searches up to KERNEL_SEARCH_MAX kernels by excess in a single pass over
+// the kernel MMR, returning the height and MMR index of each one found,
+// optionally only among the blocks between min_height and max_height:
+// POST /v1/kernels/search
+// {"excesses": ["xxx", "yyy"], "min_height": 101, "max_height": 200}
+pub struct KernelSearchHandler {
+	pub chain: Weak<chain::Chain>,
+}
+
+async fn search_kernels(
+	chain: Weak<chain::Chain>,
+	req: Request<Body>,
+) -> Result<Vec<KernelLookup>, Error> {
+	let req: KernelSearchRequest = parse_body(req).await?;
+	if req.excesses.len() > KERNEL_SEARCH_MAX {
+		return Err(ErrorKind::RequestError(format!(
+			"too many excesses, at most {} per request",
+			KERNEL_SEARCH_MAX
+		))
+		.into());
+	}
+	let mut excesses = Vec::with_capacity(req.excesses.len());
+	for excess in &req.excesses {
+		let bytes = util::from_hex(excess.clone())
+			.map_err(|_| ErrorKind::RequestError(format!("invalid excess hex for {}", excess)))?;
+		if bytes.len() != 33 {
+			return Err(
+				ErrorKind::RequestError(format!("invalid excess length for {}", excess)).into(),
+			);
+		}
+		excesses.push(Commitment::from_vec(bytes));
+	}
+
+	let chain = w(&chain)?;
+	let head_height = chain
+		.head()
+		.map_err(|e| ErrorKind::Internal(format!("{}", e)))?
+		.height;
+	// Default to the whole chain, from genesis to the current head.
+	let min_height = req.min_height.filter(|h| *h > 0);
+	let max_height = req.max_height.filter(|h| *h < head_height);
+	if let (Some(min), Some(max)) = (min_height, max_height) {
+		if min > max {
+			return Err(ErrorKind::RequestError("invalid height range".into()).into());
+		}
+	}
+
+	let found = chain
+		.get_blocks_by_kernel(&excesses, min_height, max_height)
+		.map_err(|e| ErrorKind::Internal(format!("{}", e)))?;
+	Ok(req
+		.excesses
+		.into_iter()
+		.zip(found)
+		.map(|(excess, kernel)| KernelLookup {
+			excess,
+			height: kernel.as_ref().map(|k| k.1),
+			mmr_index: kernel.as_ref().map(|k| k.2),
+		})
+		.collect())
+}
+
+impl Handler for KernelSearchHandler {
+	fn post(&self, req: Request<Body>) -> ResponseFuture {
+		let chain = self.chain.clone();
+		Box::pin(async move { result_to_response(search_kernels(chain, req).await).await })
+	}
+}
+
+/// Most kernels returned per request by the kernels handler.
+const MAX_KERNELS_PER_REQUEST: u64 = 1000;
+
+/// Kernels handler, lists the kernels of a range of blocks, for supply auditing.
+/// GET /v1/kernels?start_height=101&end_height=200
+/// The `end_height` parameter defaults to the current head.
+/// Results are paginated by kernel MMR index, pass `start_index` (the previous
+/// `last_retrieved_index` + 1) and optionally `max` to page through the range.
+/// GET /v1/kernels?start_height=101&end_height=200&start_index=1&max=1000
+pub struct KernelsHandler {
+	pub chain: Weak<chain::Chain>,
+}
+
+impl KernelsHandler {
+	pub fn get_kernels(
+		&self,
+		start_height: u64,
+		end_height: Option<u64>,
+		start_index: Option<u64>,
+		max: u64,
+	) -> Result<KernelListing, Error> {
+		let chain = w(&self.chain)?;
+		let (highest_index, kernels) = chain
+			.kernels_by_block_height(start_height, end_height, start_index, max)
+			.context(ErrorKind::NotFound)?;
+		let last_retrieved_index = kernels.last().map_or(0, |(_, _, mmr_index)| *mmr_index);
+		Ok(KernelListing {
+			highest_index,
+			last_retrieved_index,
+			kernels: kernels
+				.into_iter()
+				.map(|(tx_kernel, height, mmr_index)| LocatedTxKernel {
+					tx_kernel,
+					height,
+					mmr_index,
+					block_hash: None,
+					first_seen: None,
+				})
+				.collect(),
+		})
+	}
+
+	fn kernels(&self, req: &Request<Body>) -> Result<KernelListing, Error> {
+		let params = QueryParams::from(req.uri().query());
+		let start_height = parse_param!(params, "start_height", 1);
+		let end_height = match params.get("end_height") {
+			Some(h) => Some(
+				h.parse()
+					.map_err(|_| ErrorKind::RequestError("invalid end height".into()))?,
+			),
+			None => None,
+		};
+		let start_index = match params.get("start_index") {
+			Some(i) => Some(
+				i.parse()
+					.map_err(|_| ErrorKind::RequestError("invalid start index".into()))?,
+			),
+			None => None,
+		};
+		let max = parse_param!(params, "max", MAX_KERNELS_PER_REQUEST);
+		self.get_kernels(
+			start_height,
+			end_height,
+			start_index,
+			max.min(MAX_KERNELS_PER_REQUEST),
+		)
+	}
+}
+
+impl Handler for KernelsHandler {
+	fn get(&self, req: Request<Body>) -> ResponseFuture {
+		result_to_response(self.kernels(&req))
+	}
+}
@@ -0,0 +1,180 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::server_api::sync_status_to_api;
+use super::sse_api::sse_response;
+use super::ws_api::websocket_response;
+use crate::chain::SyncStatus;
+use crate::p2p::{PeerAddr, PeerInfo};
+use crate::router::{Handler, ResponseFuture};
+use crate::types::*;
+use hyper::{Body, Request};
+use std::sync::atomic::AtomicUsize;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+/// Events a subscriber can fall behind by before missing some.
+const NODE_EVENTS_CAPACITY: usize = 1024;
+
+/// Maximum number of concurrent subscribers, per transport.
+const MAX_EVENT_SUBSCRIBERS: usize = 100;
+
+/// Block, pool, peer and sync events of the node, fanned out to the event
+/// subscribers whatever their transport, so they all see the same events.
+pub struct NodeEvents {
+	sender: broadcast::Sender<NodeEvent>,
+}
+
+impl NodeEvents {
+	pub fn new() -> NodeEvents {
+		let (sender, _) = broadcast::channel(NODE_EVENTS_CAPACITY);
+		NodeEvents { sender }
+	}
+
+	/// Notify the subscribers of an event.
+	pub fn publish(&self, event: NodeEvent) {
+		// Only fails when nobody is subscribed.
+		let _ = self.sender.send(event);
+	}
+
+	/// Whether anybody is subscribed, to skip building unread events.
+	pub fn has_subscribers(&self) -> bool {
+		self.sender.receiver_count() > 0
+	}
+
+	/// Notify the subscribers of a newly connected peer.
+	pub fn peer_connected(&self, info: &PeerInfo) {
+		if self.has_subscribers() {
+			self.publish(NodeEvent::Peer(PeerNotification::connected(info)));
+		}
+	}
+
+	/// Notify the subscribers of a peer we're no longer connected to.
+	pub fn peer_disconnected(&self, addr: PeerAddr) {
+		if self.has_subscribers() {
+			self.publish(NodeEvent::Peer(PeerNotification::disconnected(addr)));
+		}
+	}
+
+	/// Notify the subscribers of a change of the sync status.
+	pub fn sync_status_changed(&self, status: SyncStatus) {
+		if self.has_subscribers() {
+			let (sync_status, sync_info) = sync_status_to_api(status);
+			self.publish(NodeEvent::Sync(SyncNotification {
+				sync_status,
+				sync_info,
+			}));
+		}
+	}
+
+	fn subscribe(&self) -> broadcast::Receiver<NodeEvent> {
+		self.sender.subscribe()
+	}
+}
+
+impl Default for NodeEvents {
+	fn default() -> NodeEvents {
+		NodeEvents::new()
+	}
+}
+
+/// Subscribe to the block, pool, peer and sync events of the node, pushed
+/// as Server-Sent Events named after their type, for clients behind proxies
+/// not passing WebSockets through. Their data is the JSON event, as pushed
+/// over the WebSocket.
+/// GET /v1/events
+pub struct EventsStreamHandler {
+	pub events: Arc<NodeEvents>,
+	pub subscribers: Arc<AtomicUsize>,
+}
+
+impl Handler for EventsStreamHandler {
+	fn get(&self, req: Request<Body>) -> ResponseFuture {
+		sse_response(
+			&req,
+			&self.subscribers,
+			MAX_EVENT_SUBSCRIBERS,
+			|| self.events.subscribe(),
+			sse_event,
+			"event subscription",
+		)
+	}
+}
+
+/// Subscribe to the block, pool, peer and sync events of the node, pushed
+/// as JSON text messages over a WebSocket, tagged with their type.
+/// GET /v1/ws/events
+pub struct EventsSubscriptionHandler {
+	pub events: Arc<NodeEvents>,
+	pub subscribers: Arc<AtomicUsize>,
+}
+
+impl Handler for EventsSubscriptionHandler {
+	fn get(&self, req: Request<Body>) -> ResponseFuture {
+		websocket_response(
+			req,
+			&self.subscribers,
+			MAX_EVENT_SUBSCRIBERS,
+			|| self.events.subscribe(),
+			"event subscription",
+		)
+	}
+}
+
+// An event as a Server-Sent Event, named after its type.
+fn sse_event(event: &NodeEvent) -> Result<String, serde_json::Error> {
+	let data = serde_json::to_string(event)?;
+	Ok(format!("event: {}\ndata: {}\n\n", event.name(), data))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::chain::BlockStatus;
+	use crate::core::core::BlockHeader;
+	use crate::handlers::ws_api::BlockEvents;
+
+	#[test]
+	fn test_node_events() {
+		let events = Arc::new(NodeEvents::new());
+		let block_events = BlockEvents::new().with_node_events(events.clone());
+
+		// Nobody listening yet.
+		events.sync_status_changed(SyncStatus::NoSync);
+
+		let mut rx = events.subscribe();
+		block_events.block_accepted(&BlockHeader::default(), &BlockStatus::Next);
+		events.peer_disconnected(PeerAddr::from_ip("10.0.0.1".parse().unwrap()));
+		events.sync_status_changed(SyncStatus::NoSync);
+
+		let block = rx.try_recv().unwrap();
+		assert_eq!(block.name(), "block");
+		let text = sse_event(&block).unwrap();
+		assert!(text.starts_with("event: block\ndata: {\"type\":\"block\",\"data\":{"));
+		assert!(text.ends_with("}\n\n"));
+
+		match rx.try_recv().unwrap() {
+			NodeEvent::Peer(peer) => {
+				assert_eq!(peer.event, "disconnected");
+				assert!(peer.user_agent.is_none());
+			}
+			event => panic!("unexpected event {:?}", event),
+		}
+		match rx.try_recv().unwrap() {
+			NodeEvent::Sync(sync) => assert_eq!(sync.sync_status, "no_sync"),
+			event => panic!("unexpected event {:?}", event),
+		}
+		assert!(rx.try_recv().is_err());
+	}
+}
@@ -0,0 +1,413 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::client;
+use crate::core::consensus::KEPLER_BASE;
+use crate::core::global;
+use crate::rest::*;
+use crate::router::{Handler, ResponseFuture};
+use crate::types::*;
+use crate::util::Mutex;
+use crate::web::*;
+use hyper::{Body, Request};
+use serde_json::json;
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task;
+use url::{Host, Url};
+
+/// Faucet configuration. The faucet hands out coins of a funded wallet,
+/// through its owner api, on the test networks only.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FaucetConfig {
+	/// Serve POST /v1/faucet
+	#[serde(default)]
+	pub enable_faucet: bool,
+	/// Owner api url of the funded wallet
+	#[serde(default = "default_wallet_owner_url")]
+	pub wallet_owner_url: String,
+	/// Location of the secret of the wallet owner api, if it has one
+	#[serde(default)]
+	pub wallet_owner_secret_path: Option<String>,
+	/// Amount sent per request, in nanokeplers
+	#[serde(default = "default_faucet_amount")]
+	pub faucet_amount: u64,
+	/// Requests per client IP, or IPv6 /64, within the quota period, failed
+	/// ones included
+	#[serde(default = "default_max_requests_per_ip")]
+	pub max_requests_per_ip: usize,
+	/// Quota period, in seconds
+	#[serde(default = "default_quota_period_secs")]
+	pub quota_period_secs: u64,
+	/// url to POST the captcha responses to for verification, as
+	/// {"secret", "response", "remoteip"}, answering {"success": bool}.
+	/// No captcha if not set.
+	#[serde(default)]
+	pub captcha_verify_url: Option<String>,
+	/// Secret sent along with the captcha responses
+	#[serde(default)]
+	pub captcha_secret: Option<String>,
+}
+
+fn default_wallet_owner_url() -> String {
+	"http://127.0.0.1:17420".to_owned()
+}
+
+fn default_faucet_amount() -> u64 {
+	10 * KEPLER_BASE
+}
+
+fn default_max_requests_per_ip() -> usize {
+	1
+}
+
+fn default_quota_period_secs() -> u64 {
+	24 * 3600
+}
+
+impl Default for FaucetConfig {
+	fn default() -> FaucetConfig {
+		FaucetConfig {
+			enable_faucet: false,
+			wallet_owner_url: default_wallet_owner_url(),
+			wallet_owner_secret_path: None,
+			faucet_amount: default_faucet_amount(),
+			max_requests_per_ip: default_max_requests_per_ip(),
+			quota_period_secs: default_quota_period_secs(),
+			captcha_verify_url: None,
+			captcha_secret: None,
+		}
+	}
+}
+
+/// The faucet, with the requests served to each client IP.
+pub struct Faucet {
+	config: FaucetConfig,
+	wallet_secret: Option<String>,
+	quotas: Mutex<Quotas>,
+}
+
+impl Faucet {
+	/// Faucet sending coins with the wallet behind the owner api secret, if
+	/// any. Refuses to run on mainnet.
+	pub fn new(config: FaucetConfig, wallet_secret: Option<String>) -> Result<Faucet, Error> {
+		if global::is_production_mode() && !global::is_floonet() {
+			return Err(ErrorKind::Internal("no faucet on mainnet".to_owned()).into());
+		}
+		let quotas = Quotas::new(
+			config.max_requests_per_ip,
+			Duration::from_secs(config.quota_period_secs),
+		);
+		Ok(Faucet {
+			config,
+			wallet_secret,
+			quotas: Mutex::new(quotas),
+		})
+	}
+
+	async fn payout(&self, ip: Option<IpAddr>, req: FaucetRequest) -> Result<FaucetPayout, Error> {
+		if req.dest.is_empty() {
+			return Err(ErrorKind::Argument("missing dest".to_owned()).into());
+		}
+		if let Some(ref url) = self.config.captcha_verify_url {
+			self.verify_captcha(url, ip, req.captcha.as_ref()).await?;
+		}
+
+		// Requests from an unknown address share a quota. Failed requests
+		// count too, so the wallet can't be hammered through the faucet.
+		let ip = ip.map(quota_key).unwrap_or(IpAddr::from([0, 0, 0, 0]));
+		if !self.quotas.lock().take(ip, Instant::now()) {
+			return Err(
+				ErrorKind::RequestError("faucet quota exceeded, retry later".to_owned()).into(),
+			);
+		}
+		validate_dest(&req.dest).await?;
+		match self.send(&req.dest).await {
+			Ok(slate_id) => {
+				info!(
+					"faucet: sent {} to {} for {}",
+					self.config.faucet_amount, req.dest, ip
+				);
+				Ok(FaucetPayout {
+					amount: self.config.faucet_amount,
+					slate_id,
+				})
+			}
+			Err(e) => {
+				error!("faucet: failed to send to {}: {}", req.dest, e);
+				Err(ErrorKind::Internal("faucet wallet failed to send".to_owned()).into())
+			}
+		}
+	}
+
+	async fn verify_captcha(
+		&self,
+		url: &str,
+		ip: Option<IpAddr>,
+		captcha: Option<&String>,
+	) -> Result<(), Error> {
+		let captcha = captcha.ok_or_else(|| ErrorKind::Argument("missing captcha".to_owned()))?;
+		let req_body = json!({
+			"secret": self.config.captcha_secret,
+			"response": captcha,
+			"remoteip": ip.map(|ip| ip.to_string()),
+		});
+		let res: serde_json::Value =
+			client::post_async(url, &req_body, None)
+				.await
+				.map_err(|e| {
+					error!("faucet: captcha hook failed: {}", e);
+					ErrorKind::Internal("captcha verification failed".to_owned())
+				})?;
+		if res["success"].as_bool() != Some(true) {
+			return Err(ErrorKind::RequestError("invalid captcha".to_owned()).into());
+		}
+		Ok(())
+	}
+
+	// Has the wallet send the coins to the dest listener, finalize and post
+	// the transaction. Returns the id of its slate.
+	async fn send(&self, dest: &str) -> Result<Option<String>, Error> {
+		let url = format!("{}/v2/owner", self.config.wallet_owner_url);
+		let req_body = json!({
+			"jsonrpc": "2.0",
+			"method": "init_send_tx",
+			"id": 1,
+			"params": {
+				"args": {
+					"src_acct_name": null,
+					"amount": self.config.faucet_amount,
+					"minimum_confirmations": 10,
+					"max_outputs": 500,
+					"num_change_outputs": 1,
+					"selection_strategy_is_use_all": false,
+					"message": "kepler faucet",
+					"target_slate_version": null,
+					"payment_proof_recipient_address": null,
+					"ttl_blocks": null,
+					"send_args": {
+						"method": "http",
+						"dest": dest,
+						"finalize": true,
+						"post_tx": true,
+						"fluff": false
+					}
+				}
+			}
+		});
+		let res: serde_json::Value =
+			client::post_async(&url, &req_body, self.wallet_secret.clone()).await?;
+		let result = &res["result"];
+		if result["Ok"].is_null() {
+			return Err(ErrorKind::ResponseError(format!("{}", res["result"]["Err"])).into());
+		}
+		Ok(result["Ok"]["id"].as_str().map(|id| id.to_owned()))
+	}
+}
+
+// Requests served to each client IP over the quota period.
+struct Quotas {
+	max: usize,
+	period: Duration,
+	served: HashMap<IpAddr, VecDeque<Instant>>,
+}
+
+impl Quotas {
+	fn new(max: usize, period: Duration) -> Quotas {
+		Quotas {
+			max,
+			period,
+			served: HashMap::new(),
+		}
+	}
+
+	// Counts a request from the IP, unless over its quota.
+	fn take(&mut self, ip: IpAddr, now: Instant) -> bool {
+		let period = self.period;
+		self.served.retain(|_, times| {
+			while times
+				.front()
+				.map_or(false, |t| now.duration_since(*t) >= period)
+			{
+				times.pop_front();
+			}
+			!times.is_empty()
+		});
+		let times = self.served.entry(ip).or_insert_with(VecDeque::new);
+		if times.len() >= self.max {
+			return false;
+		}
+		times.push_back(now);
+		true
+	}
+}
+
+// The key of the quota of a client: its IPv4 address, or the /64 of its
+// IPv6 address, as a single host usually gets a whole /64.
+fn quota_key(ip: IpAddr) -> IpAddr {
+	match ip {
+		IpAddr::V4(ip) => IpAddr::V4(ip),
+		IpAddr::V6(ip) => match ip.to_ipv4() {
+			Some(v4) if ip.segments()[..6] == [0, 0, 0, 0, 0, 0xffff] => IpAddr::V4(v4),
+			_ => {
+				let s = ip.segments();
+				IpAddr::V6(Ipv6Addr::new(s[0], s[1], s[2], s[3], 0, 0, 0, 0))
+			}
+		},
+	}
+}
+
+// Checks the dest is an http(s) url resolving to public addresses only, so
+// the faucet wallet can't be made to reach into our own network.
+async fn validate_dest(dest: &str) -> Result<(), Error> {
+	let url = Url::parse(dest)
+		.map_err(|e| ErrorKind::Argument(format!("invalid dest {}: {}", dest, e)))?;
+	if url.scheme() != "http" && url.scheme() != "https" {
+		return Err(ErrorKind::Argument("dest must be an http(s) url".to_owned()).into());
+	}
+	let port = url.port_or_known_default().unwrap_or(80);
+	let addrs: Vec<IpAddr> = match url.host() {
+		Some(Host::Ipv4(ip)) => vec![IpAddr::V4(ip)],
+		Some(Host::Ipv6(ip)) => vec![IpAddr::V6(ip)],
+		Some(Host::Domain(domain)) => {
+			let domain = domain.to_owned();
+			task::spawn_blocking(move || (domain.as_str(), port).to_socket_addrs())
+				.await
+				.map_err(|e| ErrorKind::Internal(format!("resolving dest: {}", e)))?
+				.map_err(|e| ErrorKind::Argument(format!("can't resolve dest: {}", e)))?
+				.map(|addr| addr.ip())
+				.collect()
+		}
+		None => vec![],
+	};
+	if addrs.is_empty() || !addrs.iter().all(is_public) {
+		return Err(ErrorKind::Argument("dest must be a public address".to_owned()).into());
+	}
+	Ok(())
+}
+
+fn is_public(ip: &IpAddr) -> bool {
+	match ip {
+		IpAddr::V4(ip) => is_public_v4(ip),
+		IpAddr::V6(ip) => {
+			if let Some(v4) = ip.to_ipv4() {
+				return is_public_v4(&v4);
+			}
+			let first = ip.segments()[0];
+			!(ip.is_loopback()
+				|| ip.is_unspecified()
+				|| ip.is_multicast()
+				// unique local, fc00::/7
+				|| first & 0xfe00 == 0xfc00
+				// link-local, fe80::/10
+				|| first & 0xffc0 == 0xfe80)
+		}
+	}
+}
+
+fn is_public_v4(ip: &Ipv4Addr) -> bool {
+	let octets = ip.octets();
+	!(ip.is_loopback()
+		|| ip.is_private()
+		|| ip.is_link_local()
+		|| ip.is_unspecified()
+		|| ip.is_broadcast()
+		|| ip.is_multicast()
+		|| ip.is_documentation()
+		|| octets[0] == 0
+		// shared address space, 100.64.0.0/10
+		|| (octets[0] == 100 && octets[1] & 0xc0 == 64))
+}
+
+/// Sends test coins to the wallet listener of the requester, within a quota
+/// per client IP and once the captcha is checked, if the faucet has one.
+/// POST /v1/faucet
+/// {"dest": "http://wallet.example.com:17415", "captcha": "..."}
+pub struct FaucetHandler {
+	pub faucet: Arc<Faucet>,
+}
+
+impl Handler for FaucetHandler {
+	fn post(&self, req: Request<Body>) -> ResponseFuture {
+		let faucet = self.faucet.clone();
+		let ip = req.extensions().get::<RemoteAddr>().map(|addr| addr.0.ip());
+		Box::pin(async move {
+			let res = match parse_body(req).await {
+				Ok(faucet_req) => faucet.payout(ip, faucet_req).await,
+				Err(e) => Err(e),
+			};
+			result_to_response(res).await
+		})
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_faucet_quotas() {
+		let mut quotas = Quotas::new(2, Duration::from_secs(60));
+		let ip = IpAddr::from([10, 0, 0, 1]);
+		let other = IpAddr::from([10, 0, 0, 2]);
+		let now = Instant::now();
+
+		assert!(quotas.take(ip, now));
+		assert!(quotas.take(ip, now + Duration::from_secs(1)));
+		assert!(!quotas.take(ip, now + Duration::from_secs(2)));
+		assert!(quotas.take(other, now + Duration::from_secs(2)));
+
+		// Only the ones older than the period don't count.
+		assert!(!quotas.take(ip, now + Duration::from_secs(59)));
+		assert!(quotas.take(ip, now + Duration::from_secs(60)));
+	}
+	#[test]
+	fn test_faucet_quota_keys() {
+		let a: IpAddr = "2001:db8:1:2:3:4:5:6".parse().unwrap();
+		let b: IpAddr = "2001:db8:1:2:ffff::1".parse().unwrap();
+		let c: IpAddr = "2001:db8:1:3::1".parse().unwrap();
+		assert_eq!(quota_key(a), quota_key(b));
+		assert_ne!(quota_key(a), quota_key(c));
+		let mapped: IpAddr = "::ffff:10.0.0.1".parse().unwrap();
+		assert_eq!(quota_key(mapped), IpAddr::from([10, 0, 0, 1]));
+	}
+
+	#[test]
+	fn test_faucet_dest() {
+		let mut rt = tokio::runtime::Runtime::new().unwrap();
+		for dest in &[
+			"ftp://93.184.216.34/",
+			"http://127.0.0.1:17415",
+			"http://localhost:17415",
+			"http://10.1.2.3:17415",
+			"http://192.168.0.2",
+			"http://169.254.169.254/latest/meta-data",
+			"http://[::1]:17415",
+			"http://[fe80::1]:17415",
+			"http://[fd00::1]:17415",
+			"http://[::ffff:127.0.0.1]:17415",
+			"not a url",
+		] {
+			assert!(rt.block_on(validate_dest(dest)).is_err(), "{}", dest);
+		}
+		assert!(rt
+			.block_on(validate_dest("http://93.184.216.34:17415"))
+			.is_ok());
+		assert!(rt
+			.block_on(validate_dest("https://[2606:2800:220:1::]:17415"))
+			.is_ok());
+	}
+}
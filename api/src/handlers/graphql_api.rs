@@ -0,0 +1,647 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::utils::w;
+use crate::chain;
+use crate::core::core::hash::{Hash, Hashed};
+use crate::core::core::{Block, BlockHeader, OutputFeatures, OutputIdentifier, TxKernel};
+use crate::router::{Handler, ResponseFuture};
+use crate::types::*;
+use crate::util;
+use crate::util::secp::pedersen::Commitment;
+use crate::web::*;
+use hyper::{Body, Request, StatusCode};
+use juniper::http::GraphQLRequest;
+use juniper::{EmptyMutation, FieldError, FieldResult, InputValue, RootNode};
+use std::convert::TryFrom;
+use std::iter::Peekable;
+use std::str::Chars;
+use std::sync::{Arc, Weak};
+use tokio::task;
+
+/// Most blocks returned by a single `blocks` query.
+const MAX_BLOCKS_PER_QUERY: u64 = 100;
+
+/// Deepest nesting of the selections of a query.
+pub const MAX_QUERY_DEPTH: usize = 6;
+
+/// Most fields selected by a query, all nesting levels included.
+pub const MAX_QUERY_FIELDS: usize = 100;
+
+/// The schema of the chain queries, there are no mutations.
+pub type Schema = RootNode<'static, Query, EmptyMutation<ChainContext>>;
+
+/// A new schema of the chain queries.
+pub fn schema() -> Schema {
+	Schema::new(Query, EmptyMutation::new())
+}
+
+/// Query the blocks, headers, outputs and kernels of the chain in GraphQL,
+/// selecting the fields to return and following the links between them
+/// (from a block to its kernels to where they were included...) in a single
+/// request.
+/// POST /v2/graphql
+///
+/// GraphQL integers are 32 bits, so heights are integers while amounts,
+/// difficulties, nonces and MMR positions are decimal strings.
+///
+/// Queries nested deeper than `MAX_QUERY_DEPTH` or selecting more than
+/// `MAX_QUERY_FIELDS` fields are rejected before being executed, and named
+/// fragments aren't supported (inline ones are). Queries are executed on
+/// the blocking pool, off the runtime serving the other requests.
+pub struct GraphQLHandler {
+	pub chain: Weak<chain::Chain>,
+	pub schema: Arc<Schema>,
+}
+
+impl Handler for GraphQLHandler {
+	fn post(&self, req: Request<Body>) -> ResponseFuture {
+		let chain = match w(&self.chain) {
+			Ok(chain) => chain,
+			Err(e) => return response(StatusCode::INTERNAL_SERVER_ERROR, format!("{}", e)),
+		};
+		let schema = self.schema.clone();
+		Box::pin(async move {
+			let body: QueryBody = match parse_body(req).await {
+				Ok(body) => body,
+				Err(e) => return Ok(just_response(StatusCode::BAD_REQUEST, format!("{}", e))),
+			};
+			if let Err(e) = check_limits(&body.query) {
+				return Ok(just_response(StatusCode::BAD_REQUEST, e));
+			}
+			let request = GraphQLRequest::new(body.query, body.operation_name, body.variables);
+			let res = task::spawn_blocking(move || {
+				let context = ChainContext { chain };
+				let res = request.execute(&schema, &context);
+				// Field errors still come with data, only failed queries are
+				// bad requests.
+				let status = if res.is_ok() {
+					StatusCode::OK
+				} else {
+					StatusCode::BAD_REQUEST
+				};
+				serde_json::to_string(&res).map(|json| (status, json))
+			})
+			.await;
+			let resp = match res {
+				Ok(Ok((status, json))) => just_response(status, json),
+				Ok(Err(e)) => just_response(
+					StatusCode::INTERNAL_SERVER_ERROR,
+					format!("can't create json response: {}", e),
+				),
+				Err(e) => just_response(
+					StatusCode::INTERNAL_SERVER_ERROR,
+					format!("query failed: {}", e),
+				),
+			};
+			Ok(resp)
+		})
+	}
+}
+
+// The body of a GraphQL request, the query checked against the limits
+// before it's executed.
+#[derive(Deserialize)]
+struct QueryBody {
+	query: String,
+	#[serde(rename = "operationName")]
+	operation_name: Option<String>,
+	variables: Option<InputValue>,
+}
+
+/// The chain the queries are resolved against.
+pub struct ChainContext {
+	chain: Arc<chain::Chain>,
+}
+
+impl juniper::Context for ChainContext {}
+
+/// The root of the chain queries.
+pub struct Query;
+
+#[juniper::object(Context = ChainContext)]
+impl Query {
+	/// The head of the chain.
+	fn tip(context: &ChainContext) -> FieldResult<GqlTip> {
+		let tip = context.chain.head()?;
+		Ok(GqlTip(Tip::from_tip(tip)))
+	}
+
+	/// A block of the main chain by height, or any known block by hash.
+	fn block(
+		context: &ChainContext,
+		height: Option<i32>,
+		hash: Option<String>,
+	) -> FieldResult<Option<GqlBlock>> {
+		let header = match find_header(context, height, hash)? {
+			Some(header) => header,
+			None => return Ok(None),
+		};
+		block_of(context, &header.hash())
+	}
+
+	/// The blocks of the main chain between two heights, both included, at
+	/// most 100 of them. Up to the chain head if no end height is provided.
+	fn blocks(
+		context: &ChainContext,
+		start_height: i32,
+		end_height: Option<i32>,
+	) -> FieldResult<Vec<GqlBlock>> {
+		let start_height = to_height(start_height)?;
+		let end_height = match end_height {
+			Some(h) => to_height(h)?,
+			None => context.chain.head()?.height,
+		};
+		let end_height = end_height.min(start_height.saturating_add(MAX_BLOCKS_PER_QUERY - 1));
+		let blocks = context
+			.chain
+			.blocks_by_height(start_height, end_height)?
+			.map(|block| block.map(GqlBlock))
+			.collect::<Result<Vec<_>, _>>()?;
+		Ok(blocks)
+	}
+
+	/// A block header of the main chain by height, or any known header by
+	/// hash.
+	fn header(
+		context: &ChainContext,
+		height: Option<i32>,
+		hash: Option<String>,
+	) -> FieldResult<Option<GqlHeader>> {
+		Ok(find_header(context, height, hash)?.map(GqlHeader))
+	}
+
+	/// An unspent output by commitment.
+	fn output(context: &ChainContext, commit: String) -> FieldResult<Option<GqlOutput>> {
+		let commit = parse_commitment(&commit)?;
+		let output = [OutputFeatures::Plain, OutputFeatures::Coinbase]
+			.iter()
+			.map(|features| OutputIdentifier::new(*features, &commit))
+			.find(|output| context.chain.is_unspent(output).is_ok());
+		Ok(output.map(GqlOutput))
+	}
+
+	/// The latest kernel with the excess.
+	fn kernel(context: &ChainContext, excess: String) -> FieldResult<Option<GqlKernel>> {
+		let excess = parse_commitment(&excess)?;
+		let kernel = context.chain.get_kernel_by_excess(&excess)?;
+		Ok(kernel.map(|(kernel, _, _)| GqlKernel(kernel)))
+	}
+}
+
+/// The head of the chain.
+pub struct GqlTip(Tip);
+
+#[juniper::object(Context = ChainContext, name = "Tip")]
+impl GqlTip {
+	/// Height of the head.
+	fn height(&self) -> i32 {
+		from_height(self.0.height)
+	}
+
+	/// Hash of the head block.
+	fn last_block_pushed(&self) -> &str {
+		&self.0.last_block_pushed
+	}
+
+	/// Hash of the block previous to the head.
+	fn prev_block_to_last(&self) -> &str {
+		&self.0.prev_block_to_last
+	}
+
+	/// Total difficulty accumulated up to the head.
+	fn total_difficulty(&self) -> String {
+		self.0.total_difficulty.to_string()
+	}
+
+	/// The head block.
+	fn block(&self, context: &ChainContext) -> FieldResult<Option<GqlBlock>> {
+		block_of(context, &Hash::from_hex(&self.0.last_block_pushed)?)
+	}
+}
+
+/// A block header.
+pub struct GqlHeader(BlockHeader);
+
+#[juniper::object(Context = ChainContext, name = "Header")]
+impl GqlHeader {
+	/// Hash of the block.
+	fn hash(&self) -> String {
+		self.0.hash().to_hex()
+	}
+
+	/// Height of the block since the genesis block (height 0).
+	fn height(&self) -> i32 {
+		from_height(self.0.height)
+	}
+
+	/// Version of the block.
+	fn version(&self) -> i32 {
+		i32::from(u16::from(self.0.version))
+	}
+
+	/// Hash of the previous block.
+	fn previous(&self) -> String {
+		self.0.prev_hash.to_hex()
+	}
+
+	/// Timestamp of the block, RFC 3339 formatted.
+	fn timestamp(&self) -> String {
+		self.0.timestamp.to_rfc3339()
+	}
+
+	/// Root of the output MMR after the block.
+	fn output_root(&self) -> String {
+		self.0.output_root.to_hex()
+	}
+
+	/// Root of the range proof MMR after the block.
+	fn range_proof_root(&self) -> String {
+		self.0.range_proof_root.to_hex()
+	}
+
+	/// Root of the kernel MMR after the block.
+	fn kernel_root(&self) -> String {
+		self.0.kernel_root.to_hex()
+	}
+
+	/// Total kernel offset since the genesis block.
+	fn total_kernel_offset(&self) -> String {
+		self.0.total_kernel_offset.to_hex()
+	}
+
+	/// Size of the output MMR after the block.
+	fn output_mmr_size(&self) -> String {
+		self.0.output_mmr_size.to_string()
+	}
+
+	/// Size of the kernel MMR after the block.
+	fn kernel_mmr_size(&self) -> String {
+		self.0.kernel_mmr_size.to_string()
+	}
+
+	/// Total difficulty accumulated since the genesis block.
+	fn total_difficulty(&self) -> String {
+		self.0.pow.total_difficulty.to_num().to_string()
+	}
+
+	/// Nonce of the proof of work.
+	fn nonce(&self) -> String {
+		self.0.pow.nonce.to_string()
+	}
+
+	/// The block of the header, if it wasn't compacted away.
+	fn block(&self, context: &ChainContext) -> FieldResult<Option<GqlBlock>> {
+		block_of(context, &self.0.hash())
+	}
+}
+
+/// A full block.
+pub struct GqlBlock(Block);
+
+#[juniper::object(Context = ChainContext, name = "Block")]
+impl GqlBlock {
+	/// The header of the block.
+	fn header(&self) -> GqlHeader {
+		GqlHeader(self.0.header.clone())
+	}
+
+	/// Commitments of the outputs spent by the block.
+	fn inputs(&self) -> Vec<String> {
+		self.0
+			.inputs()
+			.iter()
+			.map(|input| util::to_hex(input.commitment().0.to_vec()))
+			.collect()
+	}
+
+	/// The outputs created by the block.
+	fn outputs(&self) -> Vec<GqlOutput> {
+		self.0
+			.outputs()
+			.iter()
+			.map(|output| GqlOutput(OutputIdentifier::from_output(output)))
+			.collect()
+	}
+
+	/// The kernels of the block.
+	fn kernels(&self) -> Vec<GqlKernel> {
+		self.0.kernels().iter().cloned().map(GqlKernel).collect()
+	}
+}
+
+/// An output, with its current status on chain.
+pub struct GqlOutput(OutputIdentifier);
+
+#[juniper::object(Context = ChainContext, name = "Output")]
+impl GqlOutput {
+	/// Commitment of the output.
+	fn commit(&self) -> String {
+		util::to_hex(self.0.commit.0.to_vec())
+	}
+
+	/// Either "Coinbase" or "Transaction".
+	fn output_type(&self) -> &str {
+		if self.0.features.is_coinbase() {
+			"Coinbase"
+		} else {
+			"Transaction"
+		}
+	}
+
+	/// Whether the output was spent, or never made it to the main chain.
+	fn spent(&self, context: &ChainContext) -> bool {
+		context.chain.is_unspent(&self.0).is_err()
+	}
+
+	/// Height of the block creating the output, if unspent.
+	fn height(&self, context: &ChainContext) -> Option<i32> {
+		let pos = context.chain.is_unspent(&self.0).ok()?;
+		Some(from_height(pos.height))
+	}
+
+	/// Position of the output in the output MMR, if unspent.
+	fn mmr_index(&self, context: &ChainContext) -> Option<String> {
+		let pos = context.chain.is_unspent(&self.0).ok()?;
+		Some(pos.pos.to_string())
+	}
+
+	/// The block creating the output, if unspent.
+	fn block(&self, context: &ChainContext) -> FieldResult<Option<GqlBlock>> {
+		match context.chain.is_unspent(&self.0) {
+			Ok(pos) => {
+				let header = context.chain.get_header_by_height(pos.height)?;
+				block_of(context, &header.hash())
+			}
+			Err(_) => Ok(None),
+		}
+	}
+}
+
+/// A transaction kernel.
+pub struct GqlKernel(TxKernel);
+
+#[juniper::object(Context = ChainContext, name = "Kernel")]
+impl GqlKernel {
+	/// Features of the kernel, "Plain", "Coinbase" or "HeightLocked".
+	fn features(&self) -> String {
+		self.0.features.as_string()
+	}
+
+	/// Fee of the kernel.
+	fn fee(&self) -> String {
+		TxKernelPrintable::from_txkernel(&self.0).fee.to_string()
+	}
+
+	/// Height the kernel is locked until.
+	fn lock_height(&self) -> String {
+		TxKernelPrintable::from_txkernel(&self.0)
+			.lock_height
+			.to_string()
+	}
+
+	/// Excess of the kernel.
+	fn excess(&self) -> String {
+		util::to_hex(self.0.excess.0.to_vec())
+	}
+
+	/// Signature of the kernel excess.
+	fn excess_sig(&self) -> String {
+		util::to_hex(self.0.excess_sig.to_raw_data().to_vec())
+	}
+
+	/// Where the latest kernel with the same excess was included on the
+	/// main chain.
+	fn lookup(&self, context: &ChainContext) -> FieldResult<Option<GqlKernelLocation>> {
+		let location = context.chain.get_kernel_by_excess(&self.0.excess)?;
+		Ok(location.map(|(_, header, mmr_index)| GqlKernelLocation { header, mmr_index }))
+	}
+}
+
+/// Where a kernel was included on the main chain.
+pub struct GqlKernelLocation {
+	header: BlockHeader,
+	mmr_index: u64,
+}
+
+#[juniper::object(Context = ChainContext, name = "KernelLocation")]
+impl GqlKernelLocation {
+	/// Height of the block including the kernel.
+	fn height(&self) -> i32 {
+		from_height(self.header.height)
+	}
+
+	/// Position of the kernel in the kernel MMR.
+	fn mmr_index(&self) -> String {
+		self.mmr_index.to_string()
+	}
+
+	/// The header of the block including the kernel.
+	fn header(&self) -> GqlHeader {
+		GqlHeader(self.header.clone())
+	}
+
+	/// The block including the kernel, if it wasn't compacted away.
+	fn block(&self, context: &ChainContext) -> FieldResult<Option<GqlBlock>> {
+		block_of(context, &self.header.hash())
+	}
+}
+
+// Header of the main chain at the height, or of any known block with the
+// hash.
+fn find_header(
+	context: &ChainContext,
+	height: Option<i32>,
+	hash: Option<String>,
+) -> FieldResult<Option<BlockHeader>> {
+	match (height, hash) {
+		(Some(height), None) => Ok(context.chain.get_header_by_height(to_height(height)?).ok()),
+		(None, Some(hash)) => Ok(context.chain.get_block_header(&Hash::from_hex(&hash)?).ok()),
+		_ => Err(FieldError::from("either a height or a hash is expected")),
+	}
+}
+
+// The block with the hash, None if unknown or compacted away.
+fn block_of(context: &ChainContext, hash: &Hash) -> FieldResult<Option<GqlBlock>> {
+	Ok(context.chain.get_block(hash).ok().map(GqlBlock))
+}
+
+// Checks the depth and the number of fields of a query, without parsing it
+// fully. The names in the selection sets are counted as fields, except the
+// aliases, directives, fragment spreads and type conditions. Named fragments
+// are rejected, their spreads would hide the depth of their selections.
+fn check_limits(query: &str) -> Result<(), String> {
+	let mut chars = query.chars().peekable();
+	let mut depth: usize = 0;
+	let mut args: usize = 0;
+	let mut fields = 0;
+	// Names following a spread, "on" or a directive aren't fields.
+	let mut skip = false;
+	while let Some(c) = chars.next() {
+		match c {
+			'#' => {
+				for c in chars.by_ref() {
+					if c == '\n' || c == '\r' {
+						break;
+					}
+				}
+			}
+			'"' => skip_string(&mut chars),
+			'{' => {
+				depth += 1;
+				skip = false;
+				if depth > MAX_QUERY_DEPTH {
+					return Err(format!("query deeper than {} levels", MAX_QUERY_DEPTH));
+				}
+			}
+			'}' => {
+				depth = depth.saturating_sub(1);
+				skip = false;
+			}
+			'(' => args += 1,
+			')' => args = args.saturating_sub(1),
+			'.' | '@' if args == 0 => skip = true,
+			c if c == '_' || c.is_ascii_alphabetic() => {
+				let mut name = c.to_string();
+				while let Some(&c) = chars.peek() {
+					if c != '_' && !c.is_ascii_alphanumeric() {
+						break;
+					}
+					name.push(c);
+					chars.next();
+				}
+				if depth == 0 {
+					if name == "fragment" && args == 0 {
+						return Err("named fragments aren't supported, use inline ones".to_owned());
+					}
+					continue;
+				}
+				if args > 0 {
+					continue;
+				}
+				if skip {
+					skip = name == "on";
+					continue;
+				}
+				let alias = chars
+					.clone()
+					.find(|c| !c.is_whitespace() && *c != ',')
+					.map_or(false, |c| c == ':');
+				if !alias {
+					fields += 1;
+					if fields > MAX_QUERY_FIELDS {
+						return Err(format!(
+							"query selecting more than {} fields",
+							MAX_QUERY_FIELDS
+						));
+					}
+				}
+			}
+			_ => {}
+		}
+	}
+	Ok(())
+}
+
+// Skips a string value, its opening quote already read.
+fn skip_string(chars: &mut Peekable<Chars<'_>>) {
+	let block = {
+		let mut ahead = chars.clone();
+		ahead.next() == Some('"') && ahead.next() == Some('"')
+	};
+	if block {
+		chars.next();
+		chars.next();
+		let mut quotes = 0;
+		while let Some(c) = chars.next() {
+			match c {
+				'"' => {
+					quotes += 1;
+					if quotes == 3 {
+						return;
+					}
+				}
+				'\\' => {
+					quotes = 0;
+					chars.next();
+				}
+				_ => quotes = 0,
+			}
+		}
+	} else {
+		while let Some(c) = chars.next() {
+			match c {
+				'"' | '\n' => return,
+				'\\' => {
+					chars.next();
+				}
+				_ => {}
+			}
+		}
+	}
+}
+
+fn parse_commitment(hex: &str) -> FieldResult<Commitment> {
+	let bytes = util::from_hex(hex.to_owned())?;
+	if bytes.len() != 33 {
+		return Err(FieldError::from("invalid commitment length"));
+	}
+	Ok(Commitment::from_vec(bytes))
+}
+
+fn to_height(height: i32) -> FieldResult<u64> {
+	u64::try_from(height).map_err(|_| FieldError::from("negative height"))
+}
+
+// Heights won't overflow a GraphQL integer for a few thousand years.
+fn from_height(height: u64) -> i32 {
+	i32::try_from(height).unwrap_or(i32::max_value())
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_graphql_limits() {
+		let query = r#"
+			query Blocks($start: Int!) {
+				# the kernels { of each block }
+				blocks(startHeight: $start, endHeight: 10) {
+					h: header { height hash }
+					... on Block { kernels { fee lookup @include(if: true) { height } } }
+					outputs { commit spent }
+				}
+			}"#;
+		assert_eq!(check_limits(query), Ok(()));
+
+		let deep = "{ tip { block { kernels { lookup { block { kernels { fee } } } } } } }";
+		assert!(check_limits(deep).is_err());
+		// Braces in strings and comments don't count.
+		let quoted = r#"{ header(hash: "{{{{{{{{") { height } } # {{{{{{{{"#;
+		assert_eq!(check_limits(quoted), Ok(()));
+
+		let wide = format!("{{ tip {{ {} }} }}", "height ".repeat(MAX_QUERY_FIELDS));
+		assert!(check_limits(&wide).is_err());
+		// Aliased fields count once.
+		let aliased = format!(
+			"{{ {} }}",
+			"a: tip { height } ".repeat(MAX_QUERY_FIELDS / 2)
+		);
+		assert_eq!(check_limits(&aliased), Ok(()));
+
+		let fragment = "{ tip { ...T } } fragment T on Tip { height }";
+		assert!(check_limits(fragment).is_err());
+	}
+}
@@ -13,15 +13,80 @@
 // limitations under the License.
 
 use super::utils::w;
-use crate::p2p::types::{PeerAddr, PeerInfoDisplay, ReasonForBan};
-use crate::p2p::{self, PeerData};
+use crate::p2p::types::{Capabilities, IpSubnet, PeerAddr, PeerInfoDisplay, ReasonForBan};
+use crate::p2p::{self, PeerData, PeerOrder, PeerQuery, State};
 use crate::rest::*;
 use crate::router::{Handler, ResponseFuture};
+use crate::types::PeerListing;
 use crate::web::*;
 use hyper::{Body, Request, StatusCode};
 use std::net::SocketAddr;
 use std::sync::Weak;
 
+const MAX_PEERS_PER_REQUEST: usize = 1000;
+
+/// Known peers, filtered, sorted and paged
+/// GET /v1/peers?state=connected&capabilities=15&sort=last_seen&offset=0&limit=100
+///
+/// state is one of connected, known (healthy), banned or defunct, capabilities
+/// the bits all the listed peers must advertise and sort either last_seen or
+/// last_banned, most recent first.
+pub struct PeersHandler {
+	pub peers: Weak<p2p::Peers>,
+}
+
+impl PeersHandler {
+	pub fn get_peers(&self, query: &PeerQuery) -> Result<PeerListing, Error> {
+		let (total, peers) = w(&self.peers)?
+			.query_peers(query)
+			.map_err(|e| ErrorKind::Internal(format!("query peers error: {:?}", e)))?;
+		Ok(PeerListing {
+			total,
+			offset: query.offset,
+			peers,
+		})
+	}
+
+	fn peers(&self, req: &Request<Body>) -> Result<PeerListing, Error> {
+		let params = QueryParams::from(req.uri().query());
+		let (state, connected) = match params.get("state").map(|s| s.as_str()) {
+			None => (None, false),
+			Some("connected") => (None, true),
+			Some("known") => (Some(State::Healthy), false),
+			Some("banned") => (Some(State::Banned), false),
+			Some("defunct") => (Some(State::Defunct), false),
+			Some(s) => {
+				return Err(ErrorKind::RequestError(format!("invalid peer state {}", s)).into())
+			}
+		};
+		let order = match params.get("sort").map(|s| s.as_str()) {
+			None => None,
+			Some("last_seen") => Some(PeerOrder::LastSeen),
+			Some("last_banned") => Some(PeerOrder::LastBanned),
+			Some(s) => {
+				return Err(ErrorKind::RequestError(format!("invalid peer sort {}", s)).into())
+			}
+		};
+		let capabilities: u32 = parse_param!(params, "capabilities", 0);
+		let offset = parse_param!(params, "offset", 0);
+		let limit: usize = parse_param!(params, "limit", MAX_PEERS_PER_REQUEST);
+		self.get_peers(&PeerQuery {
+			state,
+			connected,
+			capabilities: Capabilities::from_bits_truncate(capabilities),
+			order,
+			offset,
+			limit: limit.min(MAX_PEERS_PER_REQUEST),
+		})
+	}
+}
+
+impl Handler for PeersHandler {
+	fn get(&self, req: Request<Body>) -> ResponseFuture {
+		result_to_response(self.peers(&req))
+	}
+}
+
 pub struct PeersAllHandler {
 	pub peers: Weak<p2p::Peers>,
 }
@@ -94,6 +159,23 @@ impl PeerHandler {
 			.unban_peer(peer_addr)
 			.map_err(|e| ErrorKind::Internal(format!("unban peer error: {:?}", e)).into())
 	}
+
+	pub fn get_ban_exemptions(&self) -> Result<Vec<IpSubnet>, Error> {
+		Ok(w(&self.peers)?.ban_exemptions())
+	}
+
+	pub fn add_ban_exemption(&self, subnet: IpSubnet) -> Result<(), Error> {
+		w(&self.peers)?.add_ban_exemption(subnet);
+		Ok(())
+	}
+
+	pub fn remove_ban_exemption(&self, subnet: IpSubnet) -> Result<(), Error> {
+		if w(&self.peers)?.remove_ban_exemption(&subnet) {
+			Ok(())
+		} else {
+			Err(ErrorKind::NotFound.into())
+		}
+	}
 }
 
 impl Handler for PeerHandler {
@@ -12,15 +12,17 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::utils::w;
-use crate::core::core::hash::Hashed;
-use crate::core::core::Transaction;
+use super::utils::{get_output, w};
+use crate::chain;
+use crate::core::core::hash::{Hash, Hashed};
+use crate::core::core::{InputProof, Transaction};
 use crate::core::ser::{self, ProtocolVersion};
 use crate::pool::{self, PoolEntry};
 use crate::rest::*;
 use crate::router::{Handler, ResponseFuture};
 use crate::types::*;
 use crate::util;
+use crate::util::secp::pedersen::Commitment;
 use crate::util::RwLock;
 use crate::web::*;
 use failure::ResultExt;
@@ -44,6 +46,45 @@ impl Handler for PoolInfoHandler {
 	}
 }
 
+/// Get the transactions of the pool, with their kernels, fees and links to
+/// the other pool transactions they depend on.
+/// GET /v1/pool/txs
+///
+/// Stem txs aren't public yet, they are only listed when not running a
+/// public node.
+pub struct PoolTxsHandler {
+	pub tx_pool: Weak<RwLock<pool::TransactionPool>>,
+	pub include_stem: bool,
+}
+
+impl PoolTxsHandler {
+	pub fn get_pool_txs(&self) -> Result<Vec<PoolTxPrintable>, Error> {
+		let pool_arc = w(&self.tx_pool)?;
+		let pool = pool_arc.read();
+		let mut entries: Vec<_> = pool
+			.txpool
+			.entries
+			.iter()
+			.map(|entry| (entry, TxStatus::Fluff))
+			.collect();
+		if self.include_stem {
+			entries.extend(
+				pool.stempool
+					.entries
+					.iter()
+					.map(|entry| (entry, TxStatus::Stem)),
+			);
+		}
+		Ok(PoolTxPrintable::from_entries(&entries))
+	}
+}
+
+impl Handler for PoolTxsHandler {
+	fn get(&self, _req: Request<Body>) -> ResponseFuture {
+		result_to_response(self.get_pool_txs())
+	}
+}
+
 pub struct PoolHandler {
 	pub tx_pool: Weak<RwLock<pool::TransactionPool>>,
 }
@@ -88,14 +129,175 @@ impl PoolHandler {
 		Ok(())
 	}
 }
-/// Dummy wrapper for the hex-encoded serialized transaction.
+/// Status of an output merging the chain with the transaction pool. Only the
+/// txpool is looked at, stem transactions aren't public yet.
+pub struct OutputStatusHandler {
+	pub chain: Weak<chain::Chain>,
+	pub tx_pool: Weak<RwLock<pool::TransactionPool>>,
+}
+
+impl OutputStatusHandler {
+	pub fn get_output_status(&self, commit: &str) -> Result<MergedOutput, Error> {
+		let c = util::from_hex(commit.to_owned()).context(ErrorKind::Argument(format!(
+			"Not a valid commitment: {}",
+			commit
+		)))?;
+		let c = Commitment::from_vec(c);
+
+		let on_chain = match get_output(&self.chain, commit) {
+			Ok((output, _)) => Some(output),
+			Err(e) => match e.kind() {
+				ErrorKind::NotFound => None,
+				_ => return Err(e),
+			},
+		};
+
+		let mut created_by = None;
+		let mut spent_by = None;
+		{
+			let pool_arc = w(&self.tx_pool)?;
+			let pool = pool_arc.read();
+			for entry in &pool.txpool.entries {
+				let tx = &entry.tx;
+				if created_by.is_none() && tx.outputs().iter().any(|out| out.commitment() == c) {
+					created_by = Some(tx.hash().to_hex());
+				}
+				if spent_by.is_none() && tx.inputs().iter().any(|input| input.commitment() == c) {
+					spent_by = Some(tx.hash().to_hex());
+				}
+			}
+		}
+
+		let status = match (&on_chain, &created_by, &spent_by) {
+			(_, _, Some(_)) => MergedOutputStatus::PoolSpent,
+			(Some(_), _, None) => MergedOutputStatus::Unspent,
+			(None, Some(_), None) => MergedOutputStatus::PoolCreated,
+			(None, None, None) => MergedOutputStatus::Unknown,
+		};
+		Ok(MergedOutput {
+			commit: commit.to_owned(),
+			status,
+			height: on_chain.as_ref().map(|output| output.height),
+			mmr_index: on_chain.as_ref().map(|output| output.mmr_index),
+			created_by,
+			spent_by,
+		})
+	}
+}
+
+/// Status of a transaction, looked up by the excess of one of its kernels or
+/// by its hash, whether in the pool or confirmed on chain.
+/// GET /v1/txn/<kernel excess>
+/// GET /v1/txn/<tx hash>
+///
+/// Txs aggregated in the pool or in a block don't keep their hash, so only
+/// kernel excesses are looked up on chain. Stem txs aren't public yet, they
+/// are only reported when not running a public node.
+pub struct TxStatusHandler {
+	pub chain: Weak<chain::Chain>,
+	pub tx_pool: Weak<RwLock<pool::TransactionPool>>,
+	pub include_stem: bool,
+}
+
+impl TxStatusHandler {
+	pub fn get_tx_status(&self, id: &str) -> Result<TxStatusLookup, Error> {
+		let invalid =
+			|| ErrorKind::Argument(format!("Not a valid kernel excess or tx hash: {}", id));
+		let (excess, hash) = match id.len() {
+			66 => {
+				let excess = util::from_hex(id.to_owned()).context(invalid())?;
+				(Some(Commitment::from_vec(excess)), None)
+			}
+			64 => (None, Some(Hash::from_hex(id).map_err(|_| invalid())?)),
+			_ => return Err(invalid().into()),
+		};
+		let mut lookup = TxStatusLookup {
+			id: id.to_owned(),
+			status: TxStatus::Unknown,
+			tx_hash: None,
+			height: None,
+			block_hash: None,
+			confirmations: None,
+		};
+
+		if let Some(ref excess) = excess {
+			let chain = w(&self.chain)?;
+			let kernel = chain
+				.get_kernel_by_excess(excess)
+				.map_err(|e| ErrorKind::Internal(format!("{}", e)))?;
+			if let Some((_, header, _)) = kernel {
+				let head = chain
+					.head()
+					.map_err(|e| ErrorKind::Internal(format!("can't get head: {}", e)))?;
+				lookup.status = TxStatus::Confirmed;
+				lookup.height = Some(header.height);
+				lookup.block_hash = Some(header.hash().to_hex());
+				lookup.confirmations = Some(head.height.saturating_sub(header.height) + 1);
+				return Ok(lookup);
+			}
+		}
+
+		let matches = |entry: &PoolEntry| match (&excess, &hash) {
+			(Some(excess), _) => entry.tx.kernels().iter().any(|k| k.excess == *excess),
+			(_, Some(hash)) => entry.tx.hash() == *hash,
+			_ => false,
+		};
+		let pool_arc = w(&self.tx_pool)?;
+		let pool = pool_arc.read();
+		let mut pools = vec![(&pool.txpool, TxStatus::Fluff)];
+		if self.include_stem {
+			pools.push((&pool.stempool, TxStatus::Stem));
+		}
+		for (pool, status) in pools {
+			if let Some(entry) = pool.entries.iter().find(|entry| matches(entry)) {
+				lookup.status = status;
+				lookup.tx_hash = Some(entry.tx.hash().to_hex());
+				break;
+			}
+		}
+		Ok(lookup)
+	}
+}
+
+impl Handler for TxStatusHandler {
+	fn get(&self, req: Request<Body>) -> ResponseFuture {
+		let id = right_path_element!(req);
+		result_to_response(self.get_tx_status(id))
+	}
+}
+
+/// Dummy wrapper for the hex-encoded serialized transaction, and the proofs
+/// of its inputs for the peers asking for them.
 #[derive(Serialize, Deserialize)]
 struct TxWrapper {
 	tx_hex: String,
+	#[serde(default)]
+	input_proofs: Vec<InputProof>,
+}
+
+// Deserialize the hex-encoded transaction of the request body, with the
+// proofs of its inputs if any.
+async fn parse_tx(req: Request<Body>) -> Result<(Transaction, Vec<InputProof>), Error> {
+	let wrapper: TxWrapper = parse_body(req).await?;
+	let tx_bin = util::from_hex(wrapper.tx_hex)
+		.map_err(|e| ErrorKind::RequestError(format!("Bad request: {}", e)))?;
+
+	// All wallet api interaction explicitly uses protocol version 1 for now.
+	let version = ProtocolVersion(1);
+	let tx: Transaction = ser::deserialize(&mut &tx_bin[..], version)
+		.map_err(|e| ErrorKind::RequestError(format!("Bad request: {}", e)))?;
+	Ok((tx, wrapper.input_proofs))
 }
 
 /// Push new transaction to our local transaction pool.
 /// POST /v1/pool/push_tx
+///
+/// Optionally override the Dandelion relay of this transaction, fluff
+/// immediately, always stem or follow the node config (default)
+/// POST /v1/pool/push_tx?fluff=true|false|auto
+///
+/// Optionally fluff the transaction if still stemmed after the given secs
+/// POST /v1/pool/push_tx?max_stem_secs=10
 pub struct PoolPushHandler {
 	pub tx_pool: Weak<RwLock<pool::TransactionPool>>,
 }
@@ -106,16 +308,20 @@ async fn update_pool(
 ) -> Result<(), Error> {
 	let pool = w(&pool)?;
 	let params = QueryParams::from(req.uri().query());
-	let fluff = params.get("fluff").is_some();
+	let stem_opts = parse_stem_opts(&params)?;
 
-	let wrapper: TxWrapper = parse_body(req).await?;
-	let tx_bin = util::from_hex(wrapper.tx_hex)
-		.map_err(|e| ErrorKind::RequestError(format!("Bad request: {}", e)))?;
+	let (tx, input_proofs) = parse_tx(req).await?;
 
-	// All wallet api interaction explicitly uses protocol version 1 for now.
-	let version = ProtocolVersion(1);
-	let tx: Transaction = ser::deserialize(&mut &tx_bin[..], version)
-		.map_err(|e| ErrorKind::RequestError(format!("Bad request: {}", e)))?;
+	// Kept to relay along with the tx, verified without the pool lock.
+	let proofs = pool.read().input_proofs.clone();
+	let invalid = proofs.add(pool::ProofSource::Api, input_proofs);
+	if invalid > 0 {
+		return Err(ErrorKind::RequestError(format!(
+			"Bad request: {} invalid input proofs",
+			invalid
+		))
+		.into());
+	}
 
 	let source = pool::TxSource::PushApi;
 	info!(
@@ -133,11 +339,36 @@ async fn update_pool(
 		.chain_head()
 		.context(ErrorKind::Internal("Failed to get chain head".to_owned()))?;
 	tx_pool
-		.add_to_pool(source, tx, !fluff, &header)
+		.add_to_pool_with_opts(source, tx, stem_opts, &header)
 		.context(ErrorKind::Internal("Failed to update pool".to_owned()))?;
 	Ok(())
 }
 
+// A bare "fluff" param is accepted as "fluff=true" for backwards compatibility.
+fn parse_stem_opts(params: &QueryParams) -> Result<pool::StemOptions, Error> {
+	let fluff = match params.get("fluff").map(|x| x.as_str()) {
+		None | Some("auto") => pool::FluffMode::Auto,
+		Some("") | Some("true") => pool::FluffMode::Fluff,
+		Some("false") => pool::FluffMode::Stem,
+		Some(x) => {
+			return Err(ErrorKind::RequestError(format!("invalid fluff param: {}", x)).into());
+		}
+	};
+	let max_stem_secs = match params.get("max_stem_secs") {
+		Some(x) => {
+			let secs = x
+				.parse::<u16>()
+				.map_err(|_| ErrorKind::RequestError(format!("invalid max_stem_secs: {}", x)))?;
+			Some(secs)
+		}
+		None => None,
+	};
+	Ok(pool::StemOptions {
+		fluff,
+		max_stem_secs,
+	})
+}
+
 impl Handler for PoolPushHandler {
 	fn post(&self, req: Request<Body>) -> ResponseFuture {
 		let pool = self.tx_pool.clone();
@@ -152,3 +383,233 @@ impl Handler for PoolPushHandler {
 		})
 	}
 }
+
+/// Check whether our local transaction pool would accept a transaction,
+/// without adding it to the pool or relaying it.
+/// POST /v1/pool/check
+///
+/// The fluff param is handled as when pushing the transaction.
+/// POST /v1/pool/check?fluff=true|false|auto
+pub struct PoolCheckHandler {
+	pub tx_pool: Weak<RwLock<pool::TransactionPool>>,
+}
+
+async fn check_pool(
+	pool: Weak<RwLock<pool::TransactionPool>>,
+	req: Request<Body>,
+) -> Result<PoolCheck, Error> {
+	let pool = w(&pool)?;
+	let params = QueryParams::from(req.uri().query());
+	let stem = parse_stem_opts(&params)?.fluff != pool::FluffMode::Fluff;
+
+	let (tx, _) = parse_tx(req).await?;
+
+	let tx_pool = pool.read();
+	let header = tx_pool
+		.blockchain
+		.chain_head()
+		.context(ErrorKind::Internal("Failed to get chain head".to_owned()))?;
+	let res = tx_pool.check_tx(&tx, stem, &header);
+	Ok(PoolCheck {
+		tx_hash: tx.hash().to_hex(),
+		accept: res.is_ok(),
+		error: res.err().map(|e| e.to_string()),
+	})
+}
+
+impl Handler for PoolCheckHandler {
+	fn post(&self, req: Request<Body>) -> ResponseFuture {
+		let pool = self.tx_pool.clone();
+		Box::pin(async move { result_to_response(check_pool(pool, req).await).await })
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::core::clock::{Clock, SystemClock};
+	use crate::core::core::verifier_cache::LruVerifierCache;
+	use crate::core::core::{BlockHeader, BlockSums, Input, Output, OutputFeatures, TxKernel};
+	use crate::util::secp::key::SecretKey;
+	use crate::util::secp::pedersen::RangeProof;
+	use crate::util::static_secp_instance;
+	use kepler_keychain::BlindingFactor;
+	use std::sync::Arc;
+
+	// Every output is unspent, there's no chain head to add txs at.
+	struct UnspentChain;
+
+	impl pool::BlockChain for UnspentChain {
+		fn verify_coinbase_maturity(&self, _tx: &Transaction) -> Result<(), pool::PoolError> {
+			Ok(())
+		}
+
+		fn verify_tx_lock_height(&self, _tx: &Transaction) -> Result<(), pool::PoolError> {
+			Ok(())
+		}
+
+		fn validate_tx(&self, _tx: &Transaction) -> Result<(), pool::PoolError> {
+			Ok(())
+		}
+
+		fn chain_head(&self) -> Result<BlockHeader, pool::PoolError> {
+			Err(pool::PoolError::Other("no chain head".to_owned()))
+		}
+
+		fn get_block_header(&self, _hash: &Hash) -> Result<BlockHeader, pool::PoolError> {
+			Err(pool::PoolError::Other("no block header".to_owned()))
+		}
+
+		fn get_block_sums(&self, _hash: &Hash) -> Result<BlockSums, pool::PoolError> {
+			Err(pool::PoolError::Other("no block sums".to_owned()))
+		}
+
+		fn is_unspent(&self, _commit: &Commitment) -> bool {
+			true
+		}
+	}
+
+	// A tx spending an input of the blinding factor, along with its proof.
+	fn proven_tx(blind: &[u8; 32]) -> (Transaction, InputProof) {
+		let value = 1_000;
+		let (commit, blind) = {
+			let secp = static_secp_instance();
+			let secp = secp.lock();
+			let key = SecretKey::from_slice(&secp, blind).unwrap();
+			let commit = secp.commit(value, key.clone()).unwrap();
+			(commit, BlindingFactor::from_secret_key(key))
+		};
+		let kernel = TxKernel::empty();
+		let proof = InputProof::create(commit, value, &blind, kernel.excess).unwrap();
+		let input = Input {
+			features: OutputFeatures::Plain,
+			commit,
+		};
+		let tx = Transaction::empty().with_input(input).with_kernel(kernel);
+		(tx, proof)
+	}
+
+	fn test_pool() -> Arc<RwLock<pool::TransactionPool>> {
+		Arc::new(RwLock::new(pool::TransactionPool::new(
+			pool::PoolConfig::default(),
+			Arc::new(UnspentChain),
+			Arc::new(RwLock::new(LruVerifierCache::new())),
+			Arc::new(pool::types::NoopAdapter {}),
+			Arc::new(SystemClock),
+		)))
+	}
+
+	// A pool entry spending the given commitments and creating the others.
+	fn pool_entry(inputs: &[Commitment], outputs: &[Commitment]) -> PoolEntry {
+		let mut tx = Transaction::empty().with_kernel(TxKernel::empty());
+		for commit in inputs {
+			tx = tx.with_input(Input::new(OutputFeatures::Plain, *commit));
+		}
+		for commit in outputs {
+			tx = tx.with_output(Output {
+				features: OutputFeatures::Plain,
+				commit: *commit,
+				proof: RangeProof::zero(),
+			});
+		}
+		PoolEntry {
+			src: pool::TxSource::Broadcast,
+			tx_at: SystemClock.now(),
+			tx,
+			stem_opts: None,
+			input_proofs: vec![],
+		}
+	}
+
+	fn push_request(tx: &Transaction, input_proofs: Vec<InputProof>) -> Request<Body> {
+		let tx_bin = ser::ser_vec(tx, ProtocolVersion(1)).unwrap();
+		let wrapper = TxWrapper {
+			tx_hex: util::to_hex(tx_bin),
+			input_proofs,
+		};
+		Request::post("/v1/pool/push_tx")
+			.body(Body::from(serde_json::to_string(&wrapper).unwrap()))
+			.unwrap()
+	}
+
+	#[test]
+	fn test_pool_txs_links() {
+		let commits: Vec<Commitment> = {
+			let secp = static_secp_instance();
+			let secp = secp.lock();
+			(1..=4).map(|v| secp.commit_value(v).unwrap()).collect()
+		};
+		// a creates an output spent by b, itself spending the unrelated
+		// output of d, whose output is then spent by the stem tx c.
+		let a = pool_entry(&[], &[commits[0]]);
+		let b = pool_entry(&[commits[0], commits[3]], &[commits[1]]);
+		let c = pool_entry(&[commits[1]], &[commits[2]]);
+		let d = pool_entry(&[], &[commits[3]]);
+		let hash = |entry: &PoolEntry| entry.tx.hash().to_hex();
+
+		let tx_pool = test_pool();
+		{
+			let mut pool = tx_pool.write();
+			pool.txpool.entries = vec![a.clone(), b.clone(), d.clone()];
+			pool.stempool.entries = vec![c.clone()];
+		}
+		let mut handler = PoolTxsHandler {
+			tx_pool: Arc::downgrade(&tx_pool),
+			include_stem: false,
+		};
+
+		// Stem txs aren't listed, nor linked to.
+		let txs = handler.get_pool_txs().unwrap();
+		assert_eq!(txs.len(), 3);
+		assert_eq!(txs[0].tx_hash, hash(&a));
+		assert!(txs[0].depends_on.is_empty());
+		assert_eq!(txs[0].spent_by, vec![hash(&b)]);
+		assert_eq!(txs[1].tx_hash, hash(&b));
+		assert_eq!(txs[1].inputs, 2);
+		let mut depends_on = txs[1].depends_on.clone();
+		depends_on.sort();
+		let mut expected = vec![hash(&a), hash(&d)];
+		expected.sort();
+		assert_eq!(depends_on, expected);
+		assert!(txs[1].spent_by.is_empty());
+		assert_eq!(txs[2].tx_hash, hash(&d));
+		assert_eq!(txs[2].spent_by, vec![hash(&b)]);
+
+		// Listed, they are linked to the txs they spend outputs of.
+		handler.include_stem = true;
+		let txs = handler.get_pool_txs().unwrap();
+		assert_eq!(txs.len(), 4);
+		assert_eq!(txs[1].spent_by, vec![hash(&c)]);
+		assert_eq!(txs[3].tx_hash, hash(&c));
+		assert_eq!(txs[3].status, TxStatus::Stem);
+		assert_eq!(txs[3].depends_on, vec![hash(&b)]);
+		assert!(txs[3].spent_by.is_empty());
+	}
+
+	#[test]
+	fn test_push_input_proofs() {
+		let tx_pool = test_pool();
+		let mut rt = tokio::runtime::Runtime::new().unwrap();
+		let (tx, proof) = proven_tx(&[7; 32]);
+
+		// Invalid proofs turn the tx down.
+		let mut invalid = proof.clone();
+		invalid.s_value = invalid.s_blind.clone();
+		let req = push_request(&tx, vec![invalid]);
+		let res = rt.block_on(update_pool(Arc::downgrade(&tx_pool), req));
+		match res.unwrap_err().kind() {
+			ErrorKind::RequestError(_) => (),
+			e => panic!("unexpected error: {:?}", e),
+		}
+		assert!(tx_pool.read().input_proofs.for_tx(&tx).is_empty());
+
+		// Valid ones are kept for the tx, which then goes on to the pool.
+		let req = push_request(&tx, vec![proof]);
+		let res = rt.block_on(update_pool(Arc::downgrade(&tx_pool), req));
+		match res.unwrap_err().kind() {
+			ErrorKind::Internal(_) => (),
+			e => panic!("unexpected error: {:?}", e),
+		}
+		assert_eq!(tx_pool.read().input_proofs.for_tx(&tx).len(), 1);
+	}
+}
@@ -0,0 +1,193 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::utils::w;
+use crate::chain;
+use crate::core::core::OutputIdentifier;
+use crate::core::libtx::proof;
+use crate::rest::*;
+use crate::types::*;
+use crate::util::secp::{ContextFlag, Secp256k1};
+use crate::util::{self, Mutex};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+
+/// Most outputs rewound by a single scan, the rest is scanned by the next
+/// ones.
+pub const MAX_SCANNED_OUTPUTS: u64 = 50_000;
+
+/// Outputs read from the chain at once while scanning.
+const SCAN_BATCH_SIZE: u64 = 1_000;
+
+/// Shortest time between two scans.
+const SCAN_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Rate limit of the output scans, running one at a time and no more often
+/// than every couple of seconds as rewinding the rangeproofs is costly.
+pub struct OutputScans {
+	last_scan: Mutex<Option<Instant>>,
+}
+
+impl OutputScans {
+	pub fn new() -> OutputScans {
+		OutputScans {
+			last_scan: Mutex::new(None),
+		}
+	}
+}
+
+impl Default for OutputScans {
+	fn default() -> OutputScans {
+		OutputScans::new()
+	}
+}
+
+/// Scans the UTXO set for the outputs of a wallet, the ones whose rangeproof
+/// rewinds with the rewind hash of its view key. The wallet then checks the
+/// derivation path of the candidates, in their proof message.
+pub struct OutputScanHandler {
+	pub chain: Weak<chain::Chain>,
+	pub scans: Arc<OutputScans>,
+}
+
+impl OutputScanHandler {
+	pub fn scan_outputs(
+		&self,
+		rewind_hash: &str,
+		start_height: u64,
+		start_index: Option<u64>,
+		max: Option<u64>,
+	) -> Result<OutputScan, Error> {
+		let rewind_hash = util::from_hex(rewind_hash.to_owned())
+			.ok()
+			.filter(|h| h.len() == 32)
+			.ok_or_else(|| ErrorKind::Argument("invalid rewind hash".to_owned()))?;
+		let max = max
+			.unwrap_or(MAX_SCANNED_OUTPUTS)
+			.min(MAX_SCANNED_OUTPUTS)
+			.max(1);
+
+		// Hold the lock for the whole scan, so others are turned down.
+		let mut last_scan = self
+			.scans
+			.last_scan
+			.try_lock()
+			.ok_or_else(|| ErrorKind::RequestError("an output scan is running".to_owned()))?;
+		if let Some(last) = *last_scan {
+			if last.elapsed() < SCAN_INTERVAL {
+				return Err(ErrorKind::RequestError("too many output scans".to_owned()).into());
+			}
+		}
+		let res = self.scan(&rewind_hash, start_height, start_index, max);
+		*last_scan = Some(Instant::now());
+		res
+	}
+
+	fn scan(
+		&self,
+		rewind_hash: &[u8],
+		start_height: u64,
+		start_index: Option<u64>,
+		max: u64,
+	) -> Result<OutputScan, Error> {
+		let chain = w(&self.chain)?;
+		let (first_index, _) = chain
+			.block_height_range_to_pmmr_indices(start_height, None)
+			.map_err(|_| ErrorKind::NotFound)?;
+		let start_index = start_index.unwrap_or(first_index).max(first_index);
+
+		let secp = Secp256k1::with_caps(ContextFlag::Commit);
+		let mut scan = OutputScan {
+			highest_index: 0,
+			last_retrieved_index: start_index.saturating_sub(1),
+			outputs: vec![],
+		};
+		let mut scanned = 0;
+		while scanned < max {
+			let count = SCAN_BATCH_SIZE.min(max - scanned);
+			let (last_index, highest_index, outputs) = chain
+				.unspent_outputs_by_pmmr_index(scan.last_retrieved_index + 1, count, None)
+				.map_err(|e| ErrorKind::Internal(format!("chain error: {}", e)))?;
+			scan.highest_index = highest_index;
+			if outputs.is_empty() {
+				break;
+			}
+			scanned += outputs.len() as u64;
+			scan.last_retrieved_index = last_index;
+
+			for output in outputs {
+				let rewound =
+					proof::rewind_with_hash(&secp, rewind_hash, output.commit, output.proof)
+						.map_err(|e| ErrorKind::Internal(format!("can't rewind: {}", e)))?;
+				let (amount, message) = match rewound {
+					Some(rewound) => rewound,
+					None => continue,
+				};
+				let pos = chain
+					.is_unspent(&OutputIdentifier::from(output))
+					.map_err(|e| ErrorKind::Internal(format!("chain error: {}", e)))?;
+				scan.outputs.push(ScannedOutput {
+					commit: util::to_hex(output.commit.0.to_vec()),
+					output_type: if output.is_coinbase() {
+						OutputType::Coinbase
+					} else {
+						OutputType::Transaction
+					},
+					amount,
+					proof_message: util::to_hex(message.as_bytes().to_vec()),
+					mmr_index: pos.pos,
+					height: pos.height,
+				});
+			}
+		}
+		Ok(scan)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_output_scan_limits() {
+		let handler = OutputScanHandler {
+			chain: Weak::new(),
+			scans: Arc::new(OutputScans::new()),
+		};
+		let rewind_hash = "00".repeat(32);
+
+		// Bad rewind hashes are turned down before scanning.
+		assert!(handler.scan_outputs("00", 0, None, None).is_err());
+		assert!(handler.scans.last_scan.lock().is_none());
+
+		// Scans don't run again right after the last one, nor in parallel.
+		assert!(handler.scan_outputs(&rewind_hash, 0, None, None).is_err());
+		assert!(handler.scans.last_scan.lock().is_some());
+		match handler.scan_outputs(&rewind_hash, 0, None, None) {
+			Err(e) => assert_eq!(
+				e.kind(),
+				&ErrorKind::RequestError("too many output scans".to_owned())
+			),
+			Ok(_) => panic!("scan not rate limited"),
+		}
+		let _running = handler.scans.last_scan.lock();
+		match handler.scan_outputs(&rewind_hash, 0, None, None) {
+			Err(e) => assert_eq!(
+				e.kind(),
+				&ErrorKind::RequestError("an output scan is running".to_owned())
+			),
+			Ok(_) => panic!("scans ran in parallel"),
+		}
+	}
+}
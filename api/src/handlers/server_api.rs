@@ -13,15 +13,20 @@
 // limitations under the License.
 
 use super::utils::w;
+use crate::alerts::ActiveAlerts;
 use crate::chain::{Chain, SyncState, SyncStatus};
 use crate::p2p;
 use crate::rest::*;
+use crate::route_stats::RouteStats;
 use crate::router::{Handler, ResponseFuture};
 use crate::types::*;
+use crate::util::Mutex;
 use crate::web::*;
 use hyper::{Body, Request, StatusCode};
 use serde_json::json;
-use std::sync::Weak;
+use std::fs;
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
 
 // RESTful index of available api endpoints
 // GET /v1/
@@ -60,6 +65,31 @@ impl Handler for KernelDownloadHandler {
 	}
 }
 
+/// Request count, error rate and latency percentiles of each API route,
+/// most requested first.
+/// GET /v1/api/stats
+pub struct ApiStatsHandler {
+	pub stats: Arc<RouteStats>,
+}
+
+impl Handler for ApiStatsHandler {
+	fn get(&self, _req: Request<Body>) -> ResponseFuture {
+		json_response(&self.stats.snapshot())
+	}
+}
+
+/// Alerts currently raised by the node, oldest first.
+/// GET /v1/alerts
+pub struct AlertsHandler {
+	pub alerts: Arc<ActiveAlerts>,
+}
+
+impl Handler for AlertsHandler {
+	fn get(&self, _req: Request<Body>) -> ResponseFuture {
+		json_response(&self.alerts.list())
+	}
+}
+
 /// Status handler. Post a summary of the server status
 /// GET /v1/status
 pub struct StatusHandler {
@@ -70,16 +100,22 @@ pub struct StatusHandler {
 
 impl StatusHandler {
 	pub fn get_status(&self) -> Result<Status, Error> {
-		let head = w(&self.chain)?
+		let chain = w(&self.chain)?;
+		let head = chain
 			.head()
 			.map_err(|e| ErrorKind::Internal(format!("can't get head: {}", e)))?;
+		let orphans = OrphanCounts::from_stats(&chain.orphan_stats());
 		let sync_status = w(&self.sync_state)?.status();
 		let (api_sync_status, api_sync_info) = sync_status_to_api(sync_status);
+		let peers = w(&self.peers)?;
 		Ok(Status::from_tip_and_peers(
 			head,
-			w(&self.peers)?.peer_count(),
+			peers.peer_count(),
 			api_sync_status,
 			api_sync_info,
+			peers.network_time_offset(),
+			peers.insufficient_outbound_peers(),
+			orphans,
 		))
 	}
 }
@@ -90,8 +126,150 @@ impl Handler for StatusHandler {
 	}
 }
 
+/// Blocks the chain head can be behind the most-work header chain while
+/// ready to serve.
+const MAX_READY_BLOCKS_BEHIND: u64 = 5;
+
+/// Time the last probe of whether the chain data can be written is reused
+/// for, so the probes don't write to disk on every request.
+const WRITABLE_PROBE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Health probes, answering 503 with the same report when failing.
+/// The liveness probe only fails when the chain data can't be read, syncing
+/// being no reason to restart the node:
+/// GET /v1/healthz
+/// The readiness probe also fails when the chain data can't be written,
+/// while syncing, when more than a few blocks behind the most-work header
+/// chain or without peers:
+/// GET /v1/readyz
+pub struct HealthHandler {
+	pub chain: Weak<Chain>,
+	pub peers: Weak<p2p::Peers>,
+	pub sync_state: Weak<SyncState>,
+	pub writable: Arc<WritableProbe>,
+	pub readiness: bool,
+}
+
+/// Whether a file can be written next to the chain data, probed at most
+/// once per `WRITABLE_PROBE_INTERVAL` and shared by the health probes.
+pub struct WritableProbe {
+	last: Mutex<Option<(Instant, bool)>>,
+}
+
+impl WritableProbe {
+	pub fn new() -> WritableProbe {
+		WritableProbe {
+			last: Mutex::new(None),
+		}
+	}
+
+	fn writable(&self, chain: &Chain) -> bool {
+		if chain.is_read_only() {
+			return false;
+		}
+		let mut last = self.last.lock();
+		if let Some((at, writable)) = *last {
+			if at.elapsed() < WRITABLE_PROBE_INTERVAL {
+				return writable;
+			}
+		}
+		let path = chain.get_tmpfile_pathname("healthz".to_owned());
+		let writable = fs::write(&path, b"ok").is_ok();
+		let _ = fs::remove_file(&path);
+		*last = Some((Instant::now(), writable));
+		writable
+	}
+}
+
+impl Default for WritableProbe {
+	fn default() -> WritableProbe {
+		WritableProbe::new()
+	}
+}
+
+impl HealthHandler {
+	pub fn get_health(&self) -> Result<Health, Error> {
+		let chain = w(&self.chain)?;
+		let sync_state = w(&self.sync_state)?;
+		let peers = w(&self.peers)?;
+		let (height, header_height) = match (chain.head(), chain.header_head()) {
+			(Ok(head), Ok(header_head)) => (Some(head.height), header_head.height),
+			_ => (None, 0),
+		};
+		let (sync_status, _) = sync_status_to_api(sync_state.status());
+		let mut health = Health {
+			healthy: true,
+			failures: vec![],
+			sync_status,
+			syncing: sync_state.is_syncing(),
+			height: height.unwrap_or(0),
+			header_height,
+			blocks_behind: header_height.saturating_sub(height.unwrap_or(0)),
+			db_writable: self.writable.writable(&chain),
+			read_only: chain.is_read_only(),
+			connections: peers.peer_count(),
+			insufficient_peers: peers.insufficient_outbound_peers(),
+		};
+		if height.is_none() {
+			health.failures.push("can't read the chain head".to_owned());
+		}
+		health.failures.extend(failures(&health, self.readiness));
+		health.healthy = health.failures.is_empty();
+		Ok(health)
+	}
+}
+
+impl Handler for HealthHandler {
+	fn get(&self, _req: Request<Body>) -> ResponseFuture {
+		match self.get_health() {
+			Ok(health) => {
+				let status = if health.healthy {
+					StatusCode::OK
+				} else {
+					StatusCode::SERVICE_UNAVAILABLE
+				};
+				match serde_json::to_string_pretty(&health) {
+					Ok(json) => response(status, json),
+					Err(e) => response(
+						StatusCode::INTERNAL_SERVER_ERROR,
+						format!("can't create json response: {}", e),
+					),
+				}
+			}
+			Err(e) => response(StatusCode::SERVICE_UNAVAILABLE, format!("{}", e)),
+		}
+	}
+}
+
+// Why the node fails the probe, given its health.
+fn failures(health: &Health, readiness: bool) -> Vec<String> {
+	let mut failures = vec![];
+	if health.sync_status == "shutdown" {
+		failures.push("shutting down".to_owned());
+	}
+	if !readiness {
+		return failures;
+	}
+	if !health.db_writable && !health.read_only {
+		failures.push("chain data not writable".to_owned());
+	}
+	if health.syncing {
+		failures.push(format!("syncing ({})", health.sync_status));
+	}
+	if health.blocks_behind > MAX_READY_BLOCKS_BEHIND {
+		failures.push(format!(
+			"{} blocks behind the most-work header chain",
+			health.blocks_behind
+		));
+	}
+	if health.connections == 0 {
+		failures.push("no peers".to_owned());
+	}
+	failures
+}
+
 /// Convert a SyncStatus in a readable API representation
-fn sync_status_to_api(sync_status: SyncStatus) -> (String, Option<serde_json::Value>) {
+pub(crate) fn sync_status_to_api(sync_status: SyncStatus) -> (String, Option<serde_json::Value>) {
 	match sync_status {
 		SyncStatus::NoSync => ("no_sync".to_string(), None),
 		SyncStatus::AwaitingPeers(_) => ("awaiting_peers".to_string(), None),
@@ -113,6 +291,34 @@ fn sync_status_to_api(sync_status: SyncStatus) -> (String, Option<serde_json::Va
 			"txhashset_download".to_string(),
 			Some(json!({ "downloaded_size": downloaded_size, "total_size": total_size })),
 		),
+		SyncStatus::TxHashsetUnzip {
+			unzipped_size,
+			total_size,
+		} => (
+			"txhashset_unzip".to_string(),
+			Some(json!({ "unzipped_size": unzipped_size, "total_size": total_size })),
+		),
+		SyncStatus::TxHashsetKernelHistoryValidation {
+			headers,
+			headers_total,
+		} => (
+			"txhashset_kernel_history_validation".to_string(),
+			Some(json!({ "headers": headers, "headers_total": headers_total })),
+		),
+		SyncStatus::TxHashsetMmrsValidation {
+			mmrs,
+			mmrs_total,
+			nodes,
+			nodes_total,
+		} => (
+			"txhashset_mmrs_validation".to_string(),
+			Some(json!({
+				"mmrs": mmrs,
+				"mmrs_total": mmrs_total,
+				"nodes": nodes,
+				"nodes_total": nodes_total,
+			})),
+		),
 		SyncStatus::TxHashsetRangeProofsValidation {
 			rproofs,
 			rproofs_total,
@@ -139,3 +345,48 @@ fn sync_status_to_api(sync_status: SyncStatus) -> (String, Option<serde_json::Va
 		_ => ("syncing".to_string(), None),
 	}
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn health() -> Health {
+		Health {
+			healthy: true,
+			failures: vec![],
+			sync_status: "body_sync".to_owned(),
+			syncing: true,
+			height: 100,
+			header_height: 200,
+			blocks_behind: 100,
+			db_writable: true,
+			read_only: false,
+			connections: 0,
+			insufficient_peers: true,
+		}
+	}
+
+	#[test]
+	fn test_health_failures() {
+		// Syncing nodes are alive, not ready.
+		let mut health = health();
+		assert!(failures(&health, false).is_empty());
+		assert_eq!(failures(&health, true).len(), 3);
+
+		health.syncing = false;
+		health.sync_status = "no_sync".to_owned();
+		health.blocks_behind = MAX_READY_BLOCKS_BEHIND;
+		health.connections = 8;
+		assert!(failures(&health, true).is_empty());
+
+		// Only a node meant to write its chain data isn't ready without.
+		health.db_writable = false;
+		assert!(failures(&health, false).is_empty());
+		assert_eq!(
+			failures(&health, true),
+			vec!["chain data not writable".to_owned()]
+		);
+		health.read_only = true;
+		assert!(failures(&health, true).is_empty());
+	}
+}
@@ -0,0 +1,250 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::events_api::NodeEvents;
+use crate::pool::{PoolEntry, TxRemoval};
+use crate::rest::Stopping;
+use crate::router::{Handler, ResponseFuture};
+use crate::types::*;
+use crate::web::*;
+use futures::stream;
+use hyper::header::{CACHE_CONTROL, CONTENT_TYPE};
+use hyper::{Body, Request, Response, StatusCode};
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+/// Notifications a subscriber can fall behind by before missing some.
+const POOL_EVENTS_CAPACITY: usize = 1024;
+
+/// Maximum number of concurrent pool subscribers.
+const MAX_POOL_SUBSCRIBERS: usize = 100;
+
+/// Comment sent to idle subscribers so proxies don't close the stream.
+const KEEPALIVE_SECS: u64 = 15;
+
+/// Transactions entering and leaving the pool, fanned out to the pool
+/// subscribers and published on the node events, if set.
+pub struct PoolEvents {
+	sender: broadcast::Sender<PoolNotification>,
+	node_events: Option<Arc<NodeEvents>>,
+}
+
+impl PoolEvents {
+	pub fn new() -> PoolEvents {
+		let (sender, _) = broadcast::channel(POOL_EVENTS_CAPACITY);
+		PoolEvents {
+			sender,
+			node_events: None,
+		}
+	}
+
+	/// Also publish the pool events on the node events.
+	pub fn with_node_events(mut self, node_events: Arc<NodeEvents>) -> PoolEvents {
+		self.node_events = Some(node_events);
+		self
+	}
+
+	/// Notify the subscribers of a transaction accepted by the pool.
+	pub fn tx_accepted(&self, entry: &PoolEntry) {
+		self.send("added", entry);
+	}
+
+	/// Notify the subscribers of a transaction that left the pool.
+	pub fn tx_removed(&self, entry: &PoolEntry, removal: TxRemoval) {
+		let event = match removal {
+			TxRemoval::Confirmed => "confirmed",
+			TxRemoval::Evicted => "evicted",
+			TxRemoval::Subsumed => "subsumed",
+		};
+		self.send(event, entry);
+	}
+
+	fn send(&self, event: &str, entry: &PoolEntry) {
+		let node_events = self
+			.node_events
+			.as_ref()
+			.filter(|node_events| node_events.has_subscribers());
+		// Nobody subscribed, no need to build the notification.
+		if self.sender.receiver_count() == 0 && node_events.is_none() {
+			return;
+		}
+		let notification = PoolNotification::new(event, entry);
+		if let Some(node_events) = node_events {
+			node_events.publish(NodeEvent::Pool(notification.clone()));
+		}
+		let _ = self.sender.send(notification);
+	}
+
+	fn subscribe(&self) -> broadcast::Receiver<PoolNotification> {
+		self.sender.subscribe()
+	}
+}
+
+impl Default for PoolEvents {
+	fn default() -> PoolEvents {
+		PoolEvents::new()
+	}
+}
+
+/// Subscribe to the transactions entering the pool and leaving it, either
+/// confirmed by a block or evicted, pushed as Server-Sent Events. Each event
+/// is named after what happened to the transaction, its data is JSON.
+/// GET /v1/pool/stream
+pub struct PoolStreamHandler {
+	pub events: Arc<PoolEvents>,
+	pub subscribers: Arc<AtomicUsize>,
+}
+
+impl Handler for PoolStreamHandler {
+	fn get(&self, req: Request<Body>) -> ResponseFuture {
+		sse_response(
+			&req,
+			&self.subscribers,
+			MAX_POOL_SUBSCRIBERS,
+			|| self.events.subscribe(),
+			sse_event,
+			"pool subscription",
+		)
+	}
+}
+
+/// Streams the messages of the channel from `subscribe` as Server-Sent
+/// Events, formatted by `to_event`, unless `max` subscribers are streaming
+/// already. The stream ends when the server serving `req` stops. `name`
+/// tells the subscriptions apart in the logs.
+pub(crate) fn sse_response<T, F, E>(
+	req: &Request<Body>,
+	subscribers: &Arc<AtomicUsize>,
+	max: usize,
+	subscribe: F,
+	to_event: E,
+	name: &'static str,
+) -> ResponseFuture
+where
+	T: Clone + Send + 'static,
+	F: FnOnce() -> broadcast::Receiver<T>,
+	E: Fn(&T) -> Result<String, serde_json::Error> + Send + 'static,
+{
+	if subscribers.fetch_add(1, Ordering::SeqCst) >= max {
+		subscribers.fetch_sub(1, Ordering::SeqCst);
+		return response(StatusCode::SERVICE_UNAVAILABLE, "too many subscribers");
+	}
+	let subscription = Subscription(subscribers.clone());
+	let stopping = req.extensions().get::<Stopping>().cloned();
+	let events = stream::unfold(
+		(subscribe(), subscription, to_event, stopping),
+		move |(mut rx, subscription, to_event, stopping)| async move {
+			let keepalive = Duration::from_secs(KEEPALIVE_SECS);
+			loop {
+				let recv = tokio::time::timeout(keepalive, rx.recv());
+				let res = tokio::select! {
+					res = recv => res,
+					// Let the server stop rather than wait on the subscriber.
+					_ = stopped(stopping.clone()) => return None,
+				};
+				let msg = match res {
+					Err(_) => {
+						let comment = ": keepalive\n\n".to_owned();
+						let state = (rx, subscription, to_event, stopping);
+						return Some((Ok::<_, io::Error>(comment), state));
+					}
+					Ok(Ok(msg)) => msg,
+					// Too slow a subscriber, it misses the oldest ones.
+					Ok(Err(broadcast::RecvError::Lagged(n))) => {
+						debug!("{}: lagging, skipped {}", name, n);
+						continue;
+					}
+					Ok(Err(broadcast::RecvError::Closed)) => return None,
+				};
+				match to_event(&msg) {
+					Ok(text) => return Some((Ok(text), (rx, subscription, to_event, stopping))),
+					Err(e) => error!("{}: failed to serialize: {}", name, e),
+				}
+			}
+		},
+	);
+
+	let resp = Response::builder()
+		.status(StatusCode::OK)
+		.header(CONTENT_TYPE, "text/event-stream")
+		.header(CACHE_CONTROL, "no-cache")
+		.body(Body::wrap_stream(events))
+		.unwrap();
+	Box::pin(async move { Ok(resp) })
+}
+
+// Resolves once the server is stopping, never if it's not telling.
+async fn stopped(stopping: Option<Stopping>) {
+	match stopping {
+		Some(stopping) => stopping.wait().await,
+		None => futures::future::pending().await,
+	}
+}
+
+// Counts a subscriber until its stream is dropped, when it goes away.
+struct Subscription(Arc<AtomicUsize>);
+
+impl Drop for Subscription {
+	fn drop(&mut self) {
+		self.0.fetch_sub(1, Ordering::SeqCst);
+	}
+}
+
+// A notification as a Server-Sent Event, named after the pool event.
+fn sse_event(notification: &PoolNotification) -> Result<String, serde_json::Error> {
+	let data = serde_json::to_string(notification)?;
+	Ok(format!("event: {}\ndata: {}\n\n", notification.event, data))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::core::clock::{Clock, SystemClock};
+	use crate::core::core::Transaction;
+
+	#[test]
+	fn test_pool_events() {
+		let events = PoolEvents::new();
+		let entry = PoolEntry {
+			src: crate::pool::TxSource::Broadcast,
+			tx_at: SystemClock.now(),
+			tx: Transaction::empty(),
+			stem_opts: None,
+			input_proofs: vec![],
+		};
+
+		// Nobody listening yet.
+		events.tx_accepted(&entry);
+
+		let mut rx = events.subscribe();
+		events.tx_accepted(&entry);
+		events.tx_removed(&entry, TxRemoval::Confirmed);
+		events.tx_removed(&entry, TxRemoval::Evicted);
+		events.tx_removed(&entry, TxRemoval::Subsumed);
+
+		let added = rx.try_recv().unwrap();
+		assert_eq!(added.event, "added");
+		assert_eq!(rx.try_recv().unwrap().event, "confirmed");
+		assert_eq!(rx.try_recv().unwrap().event, "evicted");
+		assert_eq!(rx.try_recv().unwrap().event, "subsumed");
+		assert!(rx.try_recv().is_err());
+
+		let text = sse_event(&added).unwrap();
+		assert!(text.starts_with("event: added\ndata: {"));
+		assert!(text.ends_with("}\n\n"));
+	}
+}
@@ -0,0 +1,179 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::utils::w;
+use crate::chain;
+use crate::core::consensus::DAY_HEIGHT;
+use crate::core::core::hash::{Hash, Hashed};
+use crate::rest::*;
+use crate::router::{Handler, ResponseFuture};
+use crate::types::*;
+use crate::util;
+use crate::util::Mutex;
+use crate::web::*;
+use hyper::{Body, Request, StatusCode};
+use std::sync::Weak;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Node statistics handler.
+//
+// Rolling statistics over the last n blocks (default 60, at most a day of
+// blocks): block interval, kernel counts, fees, difficulty and secondary PoW
+// ratio. Computed again only once a new block got accepted:
+// GET /v1/stats?n=60
+//
+// Block receive latency for the last n blocks (default 10, at most a day of
+// blocks) along with the median latency over the last day and the day before:
+// GET /v1/stats/propagation?n=10
+//
+// Hourly snapshots of the node statistics (peer count, mempool size, sync
+// lag, bandwidth and block intervals) over the last n hours (default 24):
+// GET /v1/stats/history?hours=24
+
+/// Most blocks the statistics and receive latencies are reported over, a day
+/// of blocks.
+pub const MAX_STATS_BLOCKS: u64 = DAY_HEIGHT;
+
+// Blocks asked for, within 1 and MAX_STATS_BLOCKS.
+fn stats_blocks(n: u64) -> u64 {
+	n.max(1).min(MAX_STATS_BLOCKS)
+}
+
+pub struct StatsHandler {
+	pub chain: Weak<chain::Chain>,
+	// Latest block statistics, with the head and block count they are for.
+	block_stats: Mutex<Option<(Hash, u64, BlockStats)>>,
+}
+
+impl StatsHandler {
+	pub fn new(chain: Weak<chain::Chain>) -> StatsHandler {
+		StatsHandler {
+			chain,
+			block_stats: Mutex::new(None),
+		}
+	}
+
+	fn get_block_stats(&self, last_n: u64) -> Result<BlockStats, Error> {
+		let chain = w(&self.chain)?;
+		let head = chain
+			.head()
+			.map_err(|e| ErrorKind::Internal(format!("can't get head: {}", e)))?;
+		let last_n = stats_blocks(last_n);
+		if let Some((hash, n, stats)) = &*self.block_stats.lock() {
+			if *hash == head.last_block_h && *n == last_n {
+				return Ok(stats.clone());
+			}
+		}
+
+		// The last n blocks are at heights (head - n, head], along with the
+		// parent of the earliest one, for its interval and difficulty.
+		let headers = chain
+			.headers_by_height(head.height.saturating_sub(last_n), head.height)
+			.map_err(|e| ErrorKind::Internal(format!("can't get headers: {}", e)))?
+			.collect::<Result<Vec<_>, _>>()
+			.map_err(|e| ErrorKind::Internal(format!("can't get header: {}", e)))?;
+		let fees = chain
+			.latest_block_fees(last_n)
+			.map_err(|e| ErrorKind::Internal(format!("can't get block fees: {}", e)))?;
+		let stats = BlockStats::from_headers(&headers, &fees);
+		// Headers and fees read against a head that moved on in between are
+		// still served, but not cached.
+		if stats.hash == head.last_block_h.to_hex() {
+			*self.block_stats.lock() = Some((head.last_block_h, last_n, stats.clone()));
+		}
+		Ok(stats)
+	}
+
+	fn get_propagation(&self, last_n: u64) -> Result<PropagationStats, Error> {
+		let chain = w(&self.chain)?;
+		let head = chain
+			.head()
+			.map_err(|e| ErrorKind::Internal(format!("can't get head: {}", e)))?;
+		let last_n = stats_blocks(last_n);
+
+		// Latest first, down to (but excluding) the genesis block.
+		let headers = chain
+			.headers_by_height(head.height.saturating_sub(last_n) + 1, head.height)
+			.map_err(|e| ErrorKind::Internal(format!("can't get headers: {}", e)))?;
+		let mut blocks = vec![];
+		for header in headers.rev() {
+			let header =
+				header.map_err(|e| ErrorKind::Internal(format!("can't get header: {}", e)))?;
+			let arrival = chain.get_block_arrival(&header.hash()).ok();
+			blocks.push(BlockPropagation {
+				hash: util::to_hex(header.hash().to_vec()),
+				height: header.height,
+				timestamp: header.timestamp.to_rfc3339(),
+				arrival: arrival.map(|x| x.to_rfc3339()),
+				latency_ms: arrival
+					.map(|x| x.timestamp_millis() - header.timestamp.timestamp_millis()),
+			});
+		}
+
+		let median_latency_ms = chain
+			.median_block_latency(head.height, DAY_HEIGHT)
+			.map_err(|e| ErrorKind::Internal(format!("can't get median latency: {}", e)))?;
+		let prev_median_latency_ms = chain
+			.median_block_latency(head.height.saturating_sub(DAY_HEIGHT), DAY_HEIGHT)
+			.map_err(|e| ErrorKind::Internal(format!("can't get median latency: {}", e)))?;
+
+		Ok(PropagationStats {
+			blocks,
+			median_latency_ms,
+			prev_median_latency_ms,
+		})
+	}
+
+	fn get_history(&self, hours: u64) -> Result<Vec<chain::StatsSnapshot>, Error> {
+		let now = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map_err(|e| ErrorKind::Internal(format!("invalid system time: {}", e)))?;
+		let since = now.as_secs().saturating_sub(hours.saturating_mul(3600));
+		w(&self.chain)?
+			.stats_history(since as i64)
+			.map_err(|e| ErrorKind::Internal(format!("can't get stats history: {}", e)).into())
+	}
+}
+
+impl Handler for StatsHandler {
+	fn get(&self, req: Request<Body>) -> ResponseFuture {
+		let params = QueryParams::from(req.uri().query());
+		let hours = parse_param_no_err!(params, "hours", 24);
+
+		match right_path_element!(req) {
+			"stats" => {
+				result_to_response(self.get_block_stats(parse_param_no_err!(params, "n", 60)))
+			}
+			"propagation" => {
+				result_to_response(self.get_propagation(parse_param_no_err!(params, "n", 10)))
+			}
+			"history" => result_to_response(self.get_history(hours)),
+			_ => response(StatusCode::BAD_REQUEST, ""),
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_stats_blocks_capped() {
+		assert_eq!(stats_blocks(0), 1);
+		assert_eq!(stats_blocks(10), 10);
+		assert_eq!(stats_blocks(MAX_STATS_BLOCKS), MAX_STATS_BLOCKS);
+		assert_eq!(stats_blocks(MAX_STATS_BLOCKS + 1), MAX_STATS_BLOCKS);
+		assert_eq!(stats_blocks(u64::max_value()), MAX_STATS_BLOCKS);
+	}
+}
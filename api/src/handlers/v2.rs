@@ -0,0 +1,136 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! V2 API, JSON-RPC 2.0 methods split between the owner API, for the node
+//! operator, and the foreign API, for anyone. Both are described by the
+//! OpenAPI spec served at /v2/spec.json.
+
+#[macro_use]
+pub mod spec;
+pub mod foreign;
+pub mod owner;
+
+use crate::rest::Error;
+use easy_jsonrpc_mw::{Handler, MaybeReply};
+use hyper::{Body, Response, StatusCode};
+use serde::Serialize;
+use serde_json::Value;
+
+/// JSON-RPC 2.0 error code of a request body that isn't valid JSON.
+pub const PARSE_ERROR: i64 = -32700;
+
+/// JSON-RPC 2.0 response, carrying either the result of the method or the
+/// error that prevented calling it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RpcResponse {
+	pub jsonrpc: String,
+	pub id: Value,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub result: Option<Value>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub error: Option<RpcError>,
+}
+
+/// Error of a JSON-RPC 2.0 request, as opposed to the error a method
+/// returns in its result.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RpcError {
+	pub code: i64,
+	pub message: String,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub data: Option<Value>,
+}
+
+impl RpcResponse {
+	/// Response to a request whose body couldn't be parsed, to be told apart
+	/// from the errors of the methods.
+	pub fn parse_error(e: &Error) -> RpcResponse {
+		RpcResponse {
+			jsonrpc: "2.0".to_string(),
+			id: Value::Null,
+			result: None,
+			error: Some(RpcError {
+				code: PARSE_ERROR,
+				message: "Parse error".to_string(),
+				data: Some(Value::String(e.to_string())),
+			}),
+		}
+	}
+}
+
+// Calls the methods of the request (or batch of requests) on the api.
+fn handle_rpc<H: Handler + ?Sized>(api: &H, body: Result<Value, Error>) -> Response<Body> {
+	match body {
+		Ok(val) => {
+			let res = match api.handle_request(val) {
+				MaybeReply::Reply(r) => r,
+				MaybeReply::DontReply => {
+					// Since it's http, we need to return something. We return [] because jsonrpc
+					// clients will parse it as an empty batch response.
+					serde_json::json!([])
+				}
+			};
+			json_response_pretty(&res)
+		}
+		Err(e) => {
+			error!("Request Error: {:?}", e);
+			json_response_pretty(&RpcResponse::parse_error(&e))
+		}
+	}
+}
+
+fn json_response_pretty<T>(s: &T) -> Response<Body>
+where
+	T: Serialize,
+{
+	match serde_json::to_string_pretty(s) {
+		Ok(json) => response(StatusCode::OK, json),
+		Err(_) => response(StatusCode::INTERNAL_SERVER_ERROR, ""),
+	}
+}
+
+fn create_ok_response(json: &str) -> Response<Body> {
+	Response::builder()
+		.status(StatusCode::OK)
+		.header("access-control-allow-origin", "*")
+		.header(
+			"access-control-allow-headers",
+			"Content-Type, Authorization",
+		)
+		.header(hyper::header::CONTENT_TYPE, "application/json")
+		.body(json.to_string().into())
+		.unwrap()
+}
+
+/// Build a new hyper Response with the status code and body provided.
+///
+/// Whenever the status code is `StatusCode::OK` the text parameter should be
+/// valid JSON as the content type header will be set to `application/json'
+fn response<T: Into<Body>>(status: StatusCode, text: T) -> Response<Body> {
+	let mut builder = Response::builder();
+
+	builder = builder
+		.status(status)
+		.header("access-control-allow-origin", "*")
+		.header(
+			"access-control-allow-headers",
+			"Content-Type, Authorization",
+		);
+
+	if status == StatusCode::OK {
+		builder = builder.header(hyper::header::CONTENT_TYPE, "application/json");
+	}
+
+	builder.body(text.into()).unwrap()
+}
@@ -0,0 +1,129 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::spec::RpcMethod;
+use super::{create_ok_response, handle_rpc};
+use crate::chain::{Chain, SyncState};
+use crate::core::core::transaction::Transaction;
+use crate::foreign::Foreign;
+use crate::foreign_rpc::ForeignRpc;
+use crate::pool::{self, PoolEntry};
+use crate::router::{Handler, ResponseFuture};
+use crate::types::{
+	BlockHeaderPrintable, BlockPrintable, LocatedTxKernel, MergedOutput, OutputListing,
+	OutputPrintable, Tip, Version,
+};
+use crate::util::RwLock;
+use crate::web::parse_body;
+use hyper::{Body, Request};
+use std::sync::Weak;
+
+/// V2 API Handler/Wrapper for foreign functions
+pub struct ForeignAPIHandlerV2 {
+	pub chain: Weak<Chain>,
+	pub tx_pool: Weak<RwLock<pool::TransactionPool>>,
+	pub sync_state: Weak<SyncState>,
+}
+
+impl ForeignAPIHandlerV2 {
+	/// Create a new foreign API handler for GET methods
+	pub fn new(
+		chain: Weak<Chain>,
+		tx_pool: Weak<RwLock<pool::TransactionPool>>,
+		sync_state: Weak<SyncState>,
+	) -> Self {
+		ForeignAPIHandlerV2 {
+			chain,
+			tx_pool,
+			sync_state,
+		}
+	}
+
+	/// The methods of the foreign API, as described in the spec.
+	pub fn methods() -> Vec<RpcMethod> {
+		rpc_methods!(ForeignRpc {
+			/// Header of a block, by height, hash or the commitment of one of its outputs.
+			fn get_header(
+				height: Option<u64>,
+				hash: Option<String>,
+				commit: Option<String>,
+			) -> BlockHeaderPrintable;
+			/// Block, by height, hash or the commitment of one of its outputs.
+			fn get_block(
+				height: Option<u64>,
+				hash: Option<String>,
+				commit: Option<String>,
+			) -> BlockPrintable;
+			/// Version of the node and of the block headers it builds.
+			fn get_version() -> Version;
+			/// Tip of the chain.
+			fn get_tip() -> Tip;
+			/// Kernel with the given excess, searched between the heights if given.
+			fn get_kernel(
+				excess: String,
+				min_height: Option<u64>,
+				max_height: Option<u64>,
+			) -> LocatedTxKernel;
+			/// Unspent outputs, by commitment or between block heights.
+			fn get_outputs(
+				commits: Option<Vec<String>>,
+				start_height: Option<u64>,
+				end_height: Option<u64>,
+				include_proof: Option<bool>,
+				include_merkle_proof: Option<bool>,
+			) -> Vec<OutputPrintable>;
+			/// Unspent outputs between MMR indices, at most `max` of them.
+			fn get_unspent_outputs(
+				start_index: u64,
+				end_index: Option<u64>,
+				max: u64,
+				include_proof: Option<bool>,
+			) -> OutputListing;
+			/// Range of output MMR indices of the blocks between the heights.
+			fn get_pmmr_indices(
+				start_block_height: u64,
+				end_block_height: Option<u64>,
+			) -> OutputListing;
+			/// Status of an output, the txs of the pool accounted for.
+			fn get_output_status(commit: String) -> MergedOutput;
+			/// Number of txs in the pool.
+			fn get_pool_size() -> usize;
+			/// Number of txs in the stem pool.
+			fn get_stempool_size() -> usize;
+			/// Txs of the pool.
+			fn get_unconfirmed_transactions() -> Vec<PoolEntry>;
+			/// Adds a tx to the pool, fluffed right away if `fluff` is set.
+			fn push_transaction(tx: Transaction, fluff: Option<bool>) -> ();
+		})
+	}
+}
+
+impl Handler for ForeignAPIHandlerV2 {
+	fn post(&self, req: Request<Body>) -> ResponseFuture {
+		let api = Foreign::new(
+			self.chain.clone(),
+			self.tx_pool.clone(),
+			self.sync_state.clone(),
+		);
+
+		Box::pin(async move {
+			let body = parse_body(req).await;
+			Ok(handle_rpc(&api as &dyn ForeignRpc, body))
+		})
+	}
+
+	fn options(&self, _req: Request<Body>) -> ResponseFuture {
+		Box::pin(async { Ok(create_ok_response("{}")) })
+	}
+}
@@ -0,0 +1,115 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::spec::RpcMethod;
+use super::{create_ok_response, handle_rpc};
+use crate::chain::{Chain, CompactionStatus, SyncState};
+use crate::handlers::scan_api::OutputScans;
+use crate::owner::Owner;
+use crate::owner_rpc::OwnerRpc;
+use crate::p2p;
+use crate::p2p::types::{IpSubnet, PeerInfoDisplay};
+use crate::p2p::PeerData;
+use crate::router::{Handler, ResponseFuture};
+use crate::types::{OrphanBlock, OutputScan, Status};
+use crate::web::parse_body;
+use hyper::{Body, Request};
+use std::net::SocketAddr;
+use std::sync::{Arc, Weak};
+
+/// V2 API Handler/Wrapper for owner functions
+pub struct OwnerAPIHandlerV2 {
+	pub chain: Weak<Chain>,
+	pub peers: Weak<p2p::Peers>,
+	pub sync_state: Weak<SyncState>,
+	pub output_scans: Option<Arc<OutputScans>>,
+}
+
+impl OwnerAPIHandlerV2 {
+	/// Create a new owner API handler for GET methods
+	pub fn new(
+		chain: Weak<Chain>,
+		peers: Weak<p2p::Peers>,
+		sync_state: Weak<SyncState>,
+		output_scans: Option<Arc<OutputScans>>,
+	) -> Self {
+		OwnerAPIHandlerV2 {
+			chain,
+			peers,
+			sync_state,
+			output_scans,
+		}
+	}
+
+	/// The methods of the owner API, as described in the spec.
+	pub fn methods() -> Vec<RpcMethod> {
+		rpc_methods!(OwnerRpc {
+			/// Status of the node, its tip and its peers.
+			fn get_status() -> Status;
+			/// Validates the whole chain, which may take minutes.
+			fn validate_chain() -> ();
+			/// Starts compacting the chain in the background, pruning the spent outputs.
+			fn compact_chain() -> ();
+			/// Whether a compaction is running and how the last one went.
+			fn get_compaction_status() -> CompactionStatus;
+			/// Blocks waiting in the orphan pool for their parent.
+			fn list_orphan_blocks() -> Vec<OrphanBlock>;
+			/// Drops a block from the orphan pool.
+			fn remove_orphan(hash: String) -> ();
+			/// Drops all the blocks from the orphan pool, returning how many.
+			fn clear_orphans() -> usize;
+			/// Scans the UTXO set for the outputs a rewind hash can rewind.
+			fn scan_outputs(
+				rewind_hash: String,
+				start_height: u64,
+				start_index: Option<u64>,
+				max: Option<u64>,
+			) -> OutputScan;
+			/// Peers known to the node, or the one at `peer_addr`.
+			fn get_peers(peer_addr: Option<SocketAddr>) -> Vec<PeerData>;
+			/// Peers the node is connected to.
+			fn get_connected_peers() -> Vec<PeerInfoDisplay>;
+			/// Bans a peer.
+			fn ban_peer(peer_addr: SocketAddr) -> ();
+			/// Lifts the ban of a peer.
+			fn unban_peer(peer_addr: SocketAddr) -> ();
+			/// Subnets whose peers are never banned.
+			fn get_ban_exemptions() -> Vec<IpSubnet>;
+			/// Exempts the peers of a subnet from bans.
+			fn add_ban_exemption(subnet: IpSubnet) -> ();
+			/// Removes the ban exemption of a subnet.
+			fn remove_ban_exemption(subnet: IpSubnet) -> ();
+		})
+	}
+}
+
+impl Handler for OwnerAPIHandlerV2 {
+	fn post(&self, req: Request<Body>) -> ResponseFuture {
+		let api = Owner::new(
+			self.chain.clone(),
+			self.peers.clone(),
+			self.sync_state.clone(),
+			self.output_scans.clone(),
+		);
+
+		Box::pin(async move {
+			let body = parse_body(req).await;
+			Ok(handle_rpc(&api as &dyn OwnerRpc, body))
+		})
+	}
+
+	fn options(&self, _req: Request<Body>) -> ResponseFuture {
+		Box::pin(async { Ok(create_ok_response("{}")) })
+	}
+}
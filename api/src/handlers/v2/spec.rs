@@ -0,0 +1,682 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! OpenAPI spec of the v2 API, generated from the method lists of its
+//! handlers and the schemas of the types the methods take and return.
+
+use super::foreign::ForeignAPIHandlerV2;
+use super::owner::OwnerAPIHandlerV2;
+use super::RpcError;
+use crate::chain::CompactionStatus;
+use crate::core::core::transaction::{Transaction, TxKernel};
+use crate::core::core::InputProof;
+use crate::core::pow::Difficulty;
+use crate::core::ser::ProtocolVersion;
+use crate::p2p::types::{
+	Capabilities, Direction, IpSubnet, PeerAddr, PeerInfoDisplay, ReasonForBan,
+};
+use crate::p2p::{PeerData, State};
+use crate::pool::{FluffMode, PoolEntry, StemOptions, TxSource};
+use crate::rest::ErrorKind;
+use crate::router::{Handler, ResponseFuture};
+use crate::types::{
+	BlockHeaderPrintable, BlockPrintable, LocatedTxKernel, MergedOutput, MergedOutputStatus,
+	OrphanBlock, OrphanCounts, OutputListing, OutputPrintable, OutputScan, OutputType,
+	ScannedOutput, Status, Tip, TxKernelPrintable, Version,
+};
+use crate::web::json_response_pretty;
+use hyper::{Body, Request};
+use serde_json::{json, Map, Value};
+use std::net::SocketAddr;
+
+/// Schemas of the named types, referred to by the others.
+pub type Components = Map<String, Value>;
+
+/// Builds the schema of a type.
+pub type SchemaFn = fn(&mut Components) -> Value;
+
+/// Type described in the spec.
+pub trait ApiSchema {
+	/// JSON schema of the type, the named types it refers to added to
+	/// `components`.
+	fn schema(components: &mut Components) -> Value;
+}
+
+// Stands for the time fields, serialized in RFC 3339.
+struct Timestamp;
+
+/// JSON-RPC method of the v2 API, as described in the spec.
+pub struct RpcMethod {
+	pub name: &'static str,
+	pub summary: &'static str,
+	pub params: Vec<(&'static str, SchemaFn)>,
+	pub result: SchemaFn,
+}
+
+/// Lists the methods of an RPC trait with their params and result. Doesn't
+/// build unless the trait has these methods, taking and returning these
+/// types, so that the spec can't drift from the handlers.
+macro_rules! rpc_methods {
+	($api:ident {
+		$(
+			#[doc = $summary:literal]
+			fn $name:ident($($param:ident: $ty:ty),* $(,)?) -> $ret:ty;
+		)*
+	}) => {{
+		$(
+			let _: fn(&dyn $api, $($ty),*) -> Result<$ret, $crate::rest::ErrorKind> =
+				|api, $($param),*| api.$name($($param),*);
+		)*
+		vec![$(
+			$crate::handlers::v2::spec::RpcMethod {
+				name: stringify!($name),
+				summary: $summary.trim(),
+				params: vec![$((
+					stringify!($param),
+					<$ty as $crate::handlers::v2::spec::ApiSchema>::schema
+						as $crate::handlers::v2::spec::SchemaFn,
+				)),*],
+				result: <$ret as $crate::handlers::v2::spec::ApiSchema>::schema,
+			}
+		),*]
+	}};
+}
+
+/// Serves the spec of the v2 API.
+pub struct SpecHandler {
+	spec: Value,
+}
+
+impl SpecHandler {
+	/// The owner API is left out of the spec of the nodes not serving it.
+	pub fn new(owner: bool) -> SpecHandler {
+		SpecHandler {
+			spec: document(owner),
+		}
+	}
+}
+
+impl Handler for SpecHandler {
+	fn get(&self, _req: Request<Body>) -> ResponseFuture {
+		json_response_pretty(&self.spec)
+	}
+}
+
+/// OpenAPI document describing the methods of the foreign API and, if
+/// `owner` is set, of the owner API.
+pub fn document(owner: bool) -> Value {
+	let mut apis = vec![("foreign", ForeignAPIHandlerV2::methods())];
+	if owner {
+		apis.push(("owner", OwnerAPIHandlerV2::methods()));
+	}
+
+	let mut components = Components::new();
+	let mut paths = Map::new();
+	for (api, methods) in apis {
+		for method in methods {
+			// All the methods of an API are posted to its url, the fragment
+			// only tells their paths apart.
+			paths.insert(
+				format!("/v2/{}#{}", api, method.name),
+				operation(api, &method, &mut components),
+			);
+		}
+	}
+
+	json!({
+		"openapi": "3.1.0",
+		"info": {
+			"title": "Kepler Node API",
+			"version": env!("CARGO_PKG_VERSION"),
+			"description": "JSON-RPC 2.0 methods of the v2 node API. The params \
+				of a method are posted in order, as an array.",
+		},
+		"paths": paths,
+		"components": { "schemas": components },
+	})
+}
+
+fn operation(api: &str, method: &RpcMethod, components: &mut Components) -> Value {
+	let params: Vec<Value> = method
+		.params
+		.iter()
+		.map(|(name, schema)| {
+			let mut schema = schema(components);
+			schema["title"] = json!(name);
+			schema
+		})
+		.collect();
+	let request = json!({
+		"type": "object",
+		"required": ["jsonrpc", "method", "params", "id"],
+		"properties": {
+			"jsonrpc": { "const": "2.0" },
+			"method": { "const": method.name },
+			"params": {
+				"type": "array",
+				"prefixItems": params,
+				"minItems": params.len(),
+				"maxItems": params.len(),
+			},
+			"id": { "type": ["integer", "string", "null"] },
+		},
+	});
+	let response = json!({
+		"type": "object",
+		"required": ["jsonrpc", "id"],
+		"properties": {
+			"jsonrpc": { "const": "2.0" },
+			"id": { "type": ["integer", "string", "null"] },
+			"result": {
+				"oneOf": [
+					{
+						"type": "object",
+						"required": ["Ok"],
+						"properties": { "Ok": (method.result)(components) },
+					},
+					{
+						"type": "object",
+						"required": ["Err"],
+						"properties": { "Err": ErrorKind::schema(components) },
+					},
+				],
+			},
+			"error": RpcError::schema(components),
+		},
+	});
+
+	json!({
+		"post": {
+			"tags": [api],
+			"operationId": format!("{}_{}", api, method.name),
+			"summary": method.summary,
+			"requestBody": {
+				"required": true,
+				"content": { "application/json": { "schema": request } },
+			},
+			"responses": {
+				"200": {
+					"description": "Result of the method, or error of the request",
+					"content": { "application/json": { "schema": response } },
+				},
+			},
+		},
+	})
+}
+
+// Adds the schema of a named type to the components, once, and refers to it.
+fn named<F>(components: &mut Components, name: &str, schema: F) -> Value
+where
+	F: FnOnce(&mut Components) -> Value,
+{
+	if !components.contains_key(name) {
+		// Reserved first, for the types referring to themselves.
+		components.insert(name.to_string(), Value::Null);
+		let schema = schema(components);
+		components.insert(name.to_string(), schema);
+	}
+	json!({ "$ref": format!("#/components/schemas/{}", name) })
+}
+
+impl<T: ApiSchema> ApiSchema for Option<T> {
+	fn schema(components: &mut Components) -> Value {
+		json!({ "anyOf": [T::schema(components), { "type": "null" }] })
+	}
+}
+
+impl<T: ApiSchema> ApiSchema for Vec<T> {
+	fn schema(components: &mut Components) -> Value {
+		json!({ "type": "array", "items": T::schema(components) })
+	}
+}
+
+macro_rules! inline_schema {
+	($($ty:ty => $schema:tt,)*) => {
+		$(
+			impl ApiSchema for $ty {
+				fn schema(_: &mut Components) -> Value {
+					json!($schema)
+				}
+			}
+		)*
+	};
+}
+
+// Named types, described by the type of each field as serialized, which
+// may differ from the type of the field itself.
+macro_rules! object_schema {
+	($($ty:ident { $($field:ident: $field_ty:ty,)* })*) => {
+		$(
+			impl ApiSchema for $ty {
+				fn schema(components: &mut Components) -> Value {
+					named(components, stringify!($ty), |components| {
+						let mut properties = Map::new();
+						$(
+							properties.insert(
+								stringify!($field).to_string(),
+								<$field_ty as ApiSchema>::schema(components),
+							);
+						)*
+						json!({ "type": "object", "properties": properties })
+					})
+				}
+			}
+		)*
+	};
+}
+
+macro_rules! enum_schema {
+	($($ty:ident [$($variant:ident),*])*) => {
+		$(
+			impl ApiSchema for $ty {
+				fn schema(components: &mut Components) -> Value {
+					named(components, stringify!($ty), |_| {
+						json!({ "type": "string", "enum": [$(stringify!($variant)),*] })
+					})
+				}
+			}
+		)*
+	};
+}
+
+inline_schema! {
+	() => { "type": "null" },
+	bool => { "type": "boolean" },
+	u8 => { "type": "integer", "minimum": 0 },
+	u16 => { "type": "integer", "minimum": 0 },
+	u32 => { "type": "integer", "minimum": 0 },
+	u64 => { "type": "integer", "minimum": 0 },
+	usize => { "type": "integer", "minimum": 0 },
+	i64 => { "type": "integer" },
+	String => { "type": "string" },
+	Value => {},
+	Timestamp => { "type": "string", "format": "date-time" },
+	SocketAddr => { "type": "string", "description": "IP address and port" },
+	PeerAddr => { "type": "string", "description": "IP address and port" },
+	IpSubnet => { "type": "string", "description": "IP address or subnet, in CIDR notation" },
+	Difficulty => { "type": "integer", "minimum": 0 },
+	ProtocolVersion => { "type": "integer", "minimum": 0 },
+	Capabilities => {
+		"type": "object",
+		"properties": { "bits": { "type": "integer", "minimum": 0 } },
+	},
+}
+
+// Txs and kernels keep the JSON form of the core types, not described
+// field by field.
+impl ApiSchema for Transaction {
+	fn schema(components: &mut Components) -> Value {
+		named(
+			components,
+			"Transaction",
+			|_| json!({ "type": "object", "description": "Transaction, as serialized by the node" }),
+		)
+	}
+}
+
+impl ApiSchema for TxKernel {
+	fn schema(components: &mut Components) -> Value {
+		named(
+			components,
+			"TxKernel",
+			|_| json!({ "type": "object", "description": "Kernel, as serialized by the node" }),
+		)
+	}
+}
+
+// Unit variants serialize as their name, the others as an object keyed by
+// it.
+impl ApiSchema for ErrorKind {
+	fn schema(components: &mut Components) -> Value {
+		named(components, "ErrorKind", |_| {
+			json!({
+				"oneOf": [
+					{ "type": "string", "enum": ["NotFound"] },
+					{
+						"type": "object",
+						"minProperties": 1,
+						"maxProperties": 1,
+						"additionalProperties": { "type": "string" },
+					},
+				],
+			})
+		})
+	}
+}
+
+object_schema! {
+	RpcError {
+		code: i64,
+		message: String,
+		data: Option<Value>,
+	}
+	Tip {
+		height: u64,
+		last_block_pushed: String,
+		prev_block_to_last: String,
+		total_difficulty: u64,
+	}
+	CompactionStatus {
+		running: bool,
+		last_started: Option<i64>,
+		last_finished: Option<i64>,
+		bytes_reclaimed: Option<u64>,
+		last_error: Option<String>,
+	}
+	Version {
+		node_version: String,
+		block_header_version: u16,
+	}
+	Status {
+		protocol_version: u32,
+		user_agent: String,
+		connections: u32,
+		tip: Tip,
+		sync_status: String,
+		sync_info: Option<Value>,
+		network_time_offset: Option<i64>,
+		insufficient_peers: bool,
+		orphans: OrphanCounts,
+	}
+	OrphanCounts {
+		blocks: usize,
+		max_blocks: usize,
+		evicted: usize,
+		evicted_expired: usize,
+		evicted_too_far: usize,
+		evicted_full: usize,
+	}
+	OrphanBlock {
+		hash: String,
+		height: u64,
+		previous: String,
+		inputs: usize,
+		outputs: usize,
+		kernels: usize,
+		arrived: Timestamp,
+		peer: Option<String>,
+	}
+	BlockHeaderPrintable {
+		hash: String,
+		version: u16,
+		height: u64,
+		previous: String,
+		prev_root: String,
+		timestamp: String,
+		output_root: String,
+		range_proof_root: String,
+		kernel_root: String,
+		nonce: u64,
+		edge_bits: u8,
+		cuckoo_solution: Vec<u64>,
+		total_difficulty: u64,
+		secondary_scaling: u32,
+		total_kernel_offset: String,
+		pre_pow: String,
+		next: Option<String>,
+	}
+	BlockPrintable {
+		header: BlockHeaderPrintable,
+		inputs: Vec<String>,
+		outputs: Vec<OutputPrintable>,
+		kernels: Vec<TxKernelPrintable>,
+		weight: u64,
+		size: u64,
+	}
+	TxKernelPrintable {
+		features: String,
+		fee: u64,
+		lock_height: u64,
+		excess: String,
+		excess_sig: String,
+	}
+	OutputPrintable {
+		output_type: OutputType,
+		commit: String,
+		spent: bool,
+		proof: Option<String>,
+		proof_hash: String,
+		block_height: Option<u64>,
+		merkle_proof: Option<String>,
+		mmr_index: u64,
+	}
+	LocatedTxKernel {
+		tx_kernel: TxKernel,
+		height: u64,
+		mmr_index: u64,
+		block_hash: Option<String>,
+		first_seen: Option<String>,
+	}
+	OutputListing {
+		highest_index: u64,
+		last_retrieved_index: u64,
+		outputs: Vec<OutputPrintable>,
+	}
+	MergedOutput {
+		commit: String,
+		status: MergedOutputStatus,
+		height: Option<u64>,
+		mmr_index: Option<u64>,
+		created_by: Option<String>,
+		spent_by: Option<String>,
+	}
+	OutputScan {
+		highest_index: u64,
+		last_retrieved_index: u64,
+		outputs: Vec<ScannedOutput>,
+	}
+	ScannedOutput {
+		commit: String,
+		output_type: OutputType,
+		amount: u64,
+		proof_message: String,
+		mmr_index: u64,
+		height: u64,
+	}
+	PoolEntry {
+		src: TxSource,
+		tx_at: Timestamp,
+		tx: Transaction,
+		stem_opts: Option<StemOptions>,
+		input_proofs: Vec<InputProof>,
+	}
+	InputProof {
+		commit: String,
+		excess: String,
+		nonce: String,
+		s_blind: String,
+		s_value: String,
+	}
+	StemOptions {
+		fluff: FluffMode,
+		max_stem_secs: Option<u16>,
+	}
+	PeerData {
+		addr: PeerAddr,
+		capabilities: Capabilities,
+		user_agent: String,
+		flags: State,
+		last_banned: i64,
+		ban_reason: ReasonForBan,
+		last_connected: i64,
+		body_tail: Option<u64>,
+	}
+	PeerInfoDisplay {
+		capabilities: Capabilities,
+		user_agent: String,
+		version: ProtocolVersion,
+		addr: PeerAddr,
+		direction: Direction,
+		total_difficulty: Difficulty,
+		height: u64,
+		body_tail: Option<u64>,
+	}
+}
+
+enum_schema! {
+	OutputType [Coinbase, Transaction]
+	MergedOutputStatus [Unspent, PoolCreated, PoolSpent, Unknown]
+	TxSource [PushApi, Broadcast, Fluff, EmbargoExpired, Deaggregate]
+	FluffMode [Fluff, Stem, Auto]
+	State [Healthy, Banned, Defunct]
+	Direction [Inbound, Outbound]
+	ReasonForBan [
+		None,
+		BadBlock,
+		BadCompactBlock,
+		BadBlockHeader,
+		BadTxHashSet,
+		ManualBan,
+		FraudHeight,
+		BadHandshake,
+		DuplicateBlocks,
+		BadBlockState,
+		ForkSpam,
+		BadInputProofs
+	]
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use serde::Serialize;
+	use std::collections::BTreeSet;
+
+	fn assert_fields<T: ApiSchema + Serialize>(sample: &T) {
+		let mut components = Components::new();
+		let reference = T::schema(&mut components);
+		let name = reference["$ref"]
+			.as_str()
+			.unwrap()
+			.rsplit('/')
+			.next()
+			.unwrap();
+		let properties: BTreeSet<String> = components[name]["properties"]
+			.as_object()
+			.unwrap()
+			.keys()
+			.cloned()
+			.collect();
+		let fields: BTreeSet<String> = serde_json::to_value(sample)
+			.unwrap()
+			.as_object()
+			.unwrap()
+			.keys()
+			.cloned()
+			.collect();
+		assert_eq!(properties, fields, "schema of {} out of date", name);
+	}
+
+	fn assert_refs_resolve(schema: &Value, components: &Map<String, Value>) {
+		match schema {
+			Value::Object(map) => {
+				if let Some(reference) = map.get("$ref") {
+					let name = reference.as_str().unwrap().rsplit('/').next().unwrap();
+					assert!(components.contains_key(name), "{} undefined", name);
+				}
+				for value in map.values() {
+					assert_refs_resolve(value, components);
+				}
+			}
+			Value::Array(values) => {
+				for value in values {
+					assert_refs_resolve(value, components);
+				}
+			}
+			_ => {}
+		}
+	}
+
+	#[test]
+	fn schemas_match_serialized_fields() {
+		let tip = Tip {
+			height: 1,
+			last_block_pushed: "00".to_string(),
+			prev_block_to_last: "00".to_string(),
+			total_difficulty: 1,
+		};
+		assert_fields(&tip);
+		assert_fields(&Status {
+			protocol_version: 1,
+			user_agent: "kepler".to_string(),
+			connections: 8,
+			tip,
+			sync_status: "no_sync".to_string(),
+			sync_info: Some(json!({})),
+			network_time_offset: None,
+			insufficient_peers: false,
+			orphans: OrphanCounts::default(),
+		});
+		assert_fields(&Version {
+			node_version: "4.0.0".to_string(),
+			block_header_version: 4,
+		});
+		assert_fields(&CompactionStatus::default());
+		assert_fields(&OrphanBlock {
+			hash: "00".to_string(),
+			height: 1,
+			previous: "00".to_string(),
+			inputs: 0,
+			outputs: 1,
+			kernels: 1,
+			arrived: "2020-06-01T12:00:00+00:00".to_string(),
+			peer: Some("10.0.0.2:7414".to_string()),
+		});
+		assert_fields(&OutputListing {
+			highest_index: 2,
+			last_retrieved_index: 1,
+			outputs: vec![],
+		});
+		assert_fields(&MergedOutput {
+			commit: "08".to_string(),
+			status: MergedOutputStatus::Unspent,
+			height: Some(1),
+			mmr_index: Some(1),
+			created_by: None,
+			spent_by: None,
+		});
+		assert_fields(&ScannedOutput {
+			commit: "08".to_string(),
+			output_type: OutputType::Coinbase,
+			amount: 60,
+			proof_message: "00".to_string(),
+			mmr_index: 1,
+			height: 1,
+		});
+		assert_fields(&RpcError {
+			code: -32700,
+			message: "Parse error".to_string(),
+			data: Some(json!("")),
+		});
+	}
+
+	#[test]
+	fn spec_describes_all_methods() {
+		let spec = document(true);
+		let paths = spec["paths"].as_object().unwrap();
+		assert_eq!(
+			paths.len(),
+			ForeignAPIHandlerV2::methods().len() + OwnerAPIHandlerV2::methods().len()
+		);
+		assert!(paths.contains_key("/v2/foreign#get_tip"));
+		assert!(paths.contains_key("/v2/owner#get_status"));
+		let components = spec["components"]["schemas"].as_object().unwrap();
+		assert!(components.values().all(|schema| !schema.is_null()));
+		assert_refs_resolve(&spec, components);
+
+		let spec = document(false);
+		let paths = spec["paths"].as_object().unwrap();
+		assert!(paths.keys().all(|path| path.starts_with("/v2/foreign#")));
+	}
+}
@@ -0,0 +1,210 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::utils::w;
+use crate::chain::{self, TxHashsetWriteStatus};
+use crate::rest::*;
+use crate::router::{Handler, ResponseFuture};
+use crate::types::*;
+use crate::util::RwLock;
+use crate::web::*;
+use hyper::{Body, Request};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Weak};
+use std::thread;
+
+/// Number of the latest validation jobs kept around to be polled.
+const MAX_VALIDATION_JOBS: usize = 16;
+
+/// Chain validation jobs, the latest ones by id.
+pub struct ValidationJobs {
+	jobs: RwLock<BTreeMap<u64, Arc<RwLock<ValidationJob>>>>,
+}
+
+impl ValidationJobs {
+	pub fn new() -> ValidationJobs {
+		ValidationJobs {
+			jobs: RwLock::new(BTreeMap::new()),
+		}
+	}
+
+	/// Add a new running job, unless one is running already. Returns the
+	/// running job and whether it's the new one.
+	fn start(&self) -> (Arc<RwLock<ValidationJob>>, bool) {
+		let mut jobs = self.jobs.write();
+		if let Some(job) = jobs.values().find(|j| j.read().state == "running") {
+			return (job.clone(), false);
+		}
+		let id = jobs.keys().next_back().map_or(1, |id| id + 1);
+		let job = Arc::new(RwLock::new(ValidationJob {
+			id,
+			state: "running".to_owned(),
+			stage: "setup".to_owned(),
+			progress: 0,
+			error: None,
+		}));
+		jobs.insert(id, job.clone());
+		while jobs.len() > MAX_VALIDATION_JOBS {
+			let oldest = *jobs.keys().next().unwrap();
+			jobs.remove(&oldest);
+		}
+		(job, true)
+	}
+
+	fn get(&self, id: u64) -> Option<ValidationJob> {
+		self.jobs.read().get(&id).map(|job| job.read().clone())
+	}
+}
+
+impl Default for ValidationJobs {
+	fn default() -> ValidationJobs {
+		ValidationJobs::new()
+	}
+}
+
+// Reports the progress of the validation to its job. The MMRs are quick to
+// validate compared to the rangeproofs and kernel signatures, which take
+// about the same time, the progress is weighted accordingly.
+struct JobStatus(Arc<RwLock<ValidationJob>>);
+
+impl JobStatus {
+	fn update(&self, stage: &str, from: u64, to: u64, done: u64, total: u64) {
+		let progress = from + (to - from) * done / total.max(1);
+		let mut job = self.0.write();
+		job.stage = stage.to_owned();
+		job.progress = progress.min(to) as u8;
+	}
+}
+
+impl TxHashsetWriteStatus for JobStatus {
+	fn on_setup(&self) {}
+	fn on_unzip(&self, _us: u64, _ts: u64) {}
+	fn on_validation_kernel_history(&self, _hs: u64, _ht: u64) {}
+	fn on_validation_mmrs(&self, _ms: u64, _mt: u64, ns: u64, nt: u64) {
+		self.update("mmrs", 0, 10, ns, nt);
+	}
+	fn on_validation_kernels(&self, ks: u64, kts: u64) {
+		self.update("kernels", 55, 100, ks, kts);
+	}
+	fn on_validation_rproofs(&self, rs: u64, rt: u64) {
+		self.update("rangeproofs", 10, 55, rs, rt);
+	}
+	fn on_save(&self) {}
+	fn on_done(&self) {}
+}
+
+/// Chain validation job handler. Starts a full validation of the chain state,
+/// rangeproofs and kernel signatures included, in the background and returns
+/// its job, unless one is running already then returns that one:
+/// POST /v2/chain/validate
+/// The job is then polled by id for its progress and outcome:
+/// GET /v2/chain/validate/1
+/// Blocks are only processed once the validation is over.
+pub struct ValidationJobHandler {
+	pub chain: Weak<chain::Chain>,
+	pub jobs: Arc<ValidationJobs>,
+}
+
+impl ValidationJobHandler {
+	fn start_validation(&self) -> Result<ValidationJob, Error> {
+		let chain = w(&self.chain)?;
+		let (job, started) = self.jobs.start();
+		if started {
+			let status = JobStatus(job.clone());
+			let spawned = thread::Builder::new()
+				.name("chain_validation".to_string())
+				.spawn(move || {
+					let res = chain.validate_with_status(false, &status);
+					let mut job = status.0.write();
+					match res {
+						Ok(_) => {
+							job.state = "done".to_owned();
+							job.progress = 100;
+						}
+						Err(e) => {
+							warn!("chain validation job {} failed: {}", job.id, e);
+							job.state = "failed".to_owned();
+							job.error = Some(format!("{}", e));
+						}
+					}
+				});
+			if let Err(e) = spawned {
+				let mut job = job.write();
+				job.state = "failed".to_owned();
+				job.error = Some(format!("can't start validation: {}", e));
+			}
+		}
+		let job = job.read().clone();
+		Ok(job)
+	}
+
+	fn get_job(&self, req: &Request<Body>) -> Result<ValidationJob, Error> {
+		let id = req
+			.uri()
+			.path()
+			.trim_end_matches('/')
+			.rsplit('/')
+			.next()
+			.and_then(|id| id.parse().ok())
+			.ok_or_else(|| ErrorKind::RequestError("invalid job id".to_owned()))?;
+		self.jobs.get(id).ok_or_else(|| ErrorKind::NotFound.into())
+	}
+}
+
+impl Handler for ValidationJobHandler {
+	fn get(&self, req: Request<Body>) -> ResponseFuture {
+		result_to_response(self.get_job(&req))
+	}
+
+	fn post(&self, _req: Request<Body>) -> ResponseFuture {
+		result_to_response(self.start_validation())
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_validation_jobs() {
+		let jobs = ValidationJobs::new();
+		let (job, started) = jobs.start();
+		assert!(started);
+		assert_eq!(job.read().id, 1);
+
+		// Only one job runs at a time.
+		let (running, started) = jobs.start();
+		assert!(!started);
+		assert_eq!(running.read().id, 1);
+
+		let status = JobStatus(job.clone());
+		status.on_validation_mmrs(3, 3, 50, 100);
+		assert_eq!(jobs.get(1).unwrap().progress, 5);
+		status.on_validation_rproofs(100, 100);
+		assert_eq!(jobs.get(1).unwrap().progress, 55);
+		status.on_validation_kernels(50, 100);
+		assert_eq!(jobs.get(1).unwrap().stage, "kernels");
+		assert_eq!(jobs.get(1).unwrap().progress, 77);
+
+		// The oldest finished jobs are dropped.
+		for _ in 0..MAX_VALIDATION_JOBS {
+			jobs.jobs.read().values().for_each(|j| {
+				j.write().state = "done".to_owned();
+			});
+			let _ = jobs.start();
+		}
+		assert!(jobs.get(1).is_none());
+		assert!(jobs.get(MAX_VALIDATION_JOBS as u64 + 1).is_some());
+	}
+}
@@ -14,11 +14,14 @@
 
 use super::utils::w;
 use crate::chain;
+use crate::core::consensus::{self, DAY_HEIGHT, WEEK_HEIGHT};
+use crate::p2p;
 use crate::rest::*;
 use crate::router::{Handler, ResponseFuture};
-use crate::types::Version;
+use crate::types::*;
 use crate::web::*;
 use hyper::{Body, Request};
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Weak;
 
 const CRATE_VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -47,3 +50,109 @@ impl Handler for VersionHandler {
 		result_to_response(self.get_version())
 	}
 }
+
+/// Node version of a user agent, as in "MW/Kepler 3.1.0", ignoring any
+/// pre-release suffix.
+fn agent_version(user_agent: &str) -> Option<Vec<u64>> {
+	let version = user_agent.split_whitespace().last()?.split('-').next()?;
+	version.split('.').map(|n| n.parse().ok()).collect()
+}
+
+/// Upgrade status handler. Share of the connected peers running a node
+/// version ready for the next scheduled hard fork (by default at least the
+/// version of this node, which knows about it) and header versions of the
+/// last n blocks (default 1 day worth).
+/// GET /v1/network/upgrade_status?version=3.1.0&n=1440
+pub struct UpgradeStatusHandler {
+	pub chain: Weak<chain::Chain>,
+	pub peers: Weak<p2p::Peers>,
+}
+
+impl UpgradeStatusHandler {
+	pub fn get_upgrade_status(
+		&self,
+		ready_version: &str,
+		recent_blocks: u64,
+	) -> Result<UpgradeStatus, Error> {
+		let min_version = agent_version(ready_version).ok_or_else(|| {
+			ErrorKind::RequestError(format!("invalid version: {}", ready_version))
+		})?;
+		let chain = w(&self.chain)?;
+		let head = chain
+			.head_header()
+			.map_err(|e| ErrorKind::Internal(format!("can't get head: {}", e)))?;
+
+		let next_hard_fork =
+			consensus::next_hard_fork(head.height).map(|(height, version)| HardForkPrintable {
+				height,
+				header_version: version.into(),
+				blocks_left: height - head.height,
+				supported: consensus::valid_header_version(height, version),
+			});
+
+		let mut agents: HashMap<(String, u32), u64> = HashMap::new();
+		for peer in w(&self.peers)?.connected_peers() {
+			*agents
+				.entry((peer.info.user_agent.clone(), peer.info.version.0))
+				.or_insert(0) += 1;
+		}
+		let mut user_agents: Vec<_> = agents
+			.into_iter()
+			.map(|((user_agent, protocol_version), peers)| UserAgentCount {
+				ready: agent_version(&user_agent).map_or(false, |v| v >= min_version),
+				user_agent,
+				protocol_version,
+				peers,
+			})
+			.collect();
+		user_agents.sort_by(|a, b| b.peers.cmp(&a.peers).then(a.user_agent.cmp(&b.user_agent)));
+		let peers = user_agents.iter().map(|a| a.peers).sum::<u64>();
+		let ready_peers = user_agents
+			.iter()
+			.filter(|a| a.ready)
+			.map(|a| a.peers)
+			.sum::<u64>();
+
+		let recent_blocks = recent_blocks.min(head.height);
+		let mut versions: BTreeMap<u16, u64> = BTreeMap::new();
+		let headers = chain
+			.headers_by_height(head.height - recent_blocks + 1, head.height)
+			.map_err(|e| ErrorKind::Internal(format!("can't get headers: {}", e)))?;
+		for header in headers {
+			let header =
+				header.map_err(|e| ErrorKind::Internal(format!("can't get header: {}", e)))?;
+			*versions.entry(header.version.into()).or_insert(0) += 1;
+		}
+
+		Ok(UpgradeStatus {
+			height: head.height,
+			header_version: head.version.into(),
+			next_hard_fork,
+			ready_version: ready_version.to_owned(),
+			peers,
+			ready_peers,
+			ready_percentage: ready_peers as f64 * 100.0 / peers.max(1) as f64,
+			user_agents,
+			header_versions: versions
+				.into_iter()
+				.map(|(header_version, blocks)| HeaderVersionCount {
+					header_version,
+					blocks,
+				})
+				.collect(),
+			recent_blocks,
+		})
+	}
+}
+
+impl Handler for UpgradeStatusHandler {
+	fn get(&self, req: Request<Body>) -> ResponseFuture {
+		let params = QueryParams::from(req.uri().query());
+		let recent_blocks: u64 = parse_param_no_err!(params, "n", DAY_HEIGHT);
+		let ready_version = params
+			.get("version")
+			.map(|v| v.to_owned())
+			.unwrap_or_else(|| CRATE_VERSION.to_owned());
+		result_to_response(self.get_upgrade_status(&ready_version, recent_blocks.min(WEEK_HEIGHT)))
+	}
+}
@@ -0,0 +1,301 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::events_api::NodeEvents;
+use crate::chain::{self, BlockStatus};
+use crate::core::core::BlockHeader;
+use crate::router::{Handler, ResponseFuture};
+use crate::types::*;
+use crate::web::*;
+use futures::{SinkExt, StreamExt};
+use hyper::header::{
+	HeaderName, CONNECTION, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY, SEC_WEBSOCKET_VERSION, UPGRADE,
+};
+use hyper::{Body, Request, Response, StatusCode};
+use ring::digest;
+use serde::Serialize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::broadcast;
+use tokio_tungstenite::tungstenite::protocol::{Message, Role};
+use tokio_tungstenite::WebSocketStream;
+
+/// Notifications a subscriber can fall behind by before missing some.
+const BLOCK_EVENTS_CAPACITY: usize = 64;
+
+/// Maximum number of concurrent block subscribers.
+const MAX_BLOCK_SUBSCRIBERS: usize = 100;
+
+// Appended to the client key to derive the handshake accept key (RFC 6455).
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Blocks accepted by the chain, fanned out to the block subscribers and
+/// published on the node events, if set.
+pub struct BlockEvents {
+	sender: broadcast::Sender<BlockNotification>,
+	node_events: Option<Arc<NodeEvents>>,
+}
+
+impl BlockEvents {
+	pub fn new() -> BlockEvents {
+		let (sender, _) = broadcast::channel(BLOCK_EVENTS_CAPACITY);
+		BlockEvents {
+			sender,
+			node_events: None,
+		}
+	}
+
+	/// Also publish the blocks on the node events.
+	pub fn with_node_events(mut self, node_events: Arc<NodeEvents>) -> BlockEvents {
+		self.node_events = Some(node_events);
+		self
+	}
+
+	/// Notify the subscribers of a block accepted by the chain.
+	pub fn block_accepted(&self, header: &BlockHeader, status: &BlockStatus) {
+		let (status, reorg_depth) = match status {
+			BlockStatus::Next => ("next", None),
+			BlockStatus::Fork => ("fork", None),
+			BlockStatus::Reorg(depth) => ("reorg", Some(*depth)),
+		};
+		let head = match status {
+			"fork" => None,
+			_ => Some(Tip::from_tip(chain::Tip::from_header(header))),
+		};
+		let notification = BlockNotification {
+			status: status.to_owned(),
+			reorg_depth,
+			header: BlockHeaderPrintable::from_header(header),
+			head,
+		};
+		if let Some(ref node_events) = self.node_events {
+			node_events.publish(NodeEvent::Block(notification.clone()));
+		}
+		// Only fails when nobody is subscribed.
+		let _ = self.sender.send(notification);
+	}
+
+	fn subscribe(&self) -> broadcast::Receiver<BlockNotification> {
+		self.sender.subscribe()
+	}
+}
+
+impl Default for BlockEvents {
+	fn default() -> BlockEvents {
+		BlockEvents::new()
+	}
+}
+
+/// Subscribe to the blocks accepted by the chain, pushed as JSON text
+/// messages over a WebSocket. Fork blocks are included, chain head changes
+/// and reorgs are flagged.
+/// GET /v1/ws/blocks
+pub struct BlockSubscriptionHandler {
+	pub events: Arc<BlockEvents>,
+	pub subscribers: Arc<AtomicUsize>,
+}
+
+impl Handler for BlockSubscriptionHandler {
+	fn get(&self, req: Request<Body>) -> ResponseFuture {
+		websocket_response(
+			req,
+			&self.subscribers,
+			MAX_BLOCK_SUBSCRIBERS,
+			|| self.events.subscribe(),
+			"block subscription",
+		)
+	}
+}
+
+/// Upgrades the request to a WebSocket pushing the messages of the channel
+/// from `subscribe` as JSON text, unless `max` subscribers are connected
+/// already. `name` tells the subscriptions apart in the logs.
+pub(crate) fn websocket_response<T, F>(
+	req: Request<Body>,
+	subscribers: &Arc<AtomicUsize>,
+	max: usize,
+	subscribe: F,
+	name: &'static str,
+) -> ResponseFuture
+where
+	T: Serialize + Clone + Send + 'static,
+	F: FnOnce() -> broadcast::Receiver<T>,
+{
+	let key = match websocket_key(&req) {
+		Some(key) => key,
+		None => return response(StatusCode::BAD_REQUEST, "expected a websocket upgrade"),
+	};
+	if subscribers.fetch_add(1, Ordering::SeqCst) >= max {
+		subscribers.fetch_sub(1, Ordering::SeqCst);
+		return response(StatusCode::SERVICE_UNAVAILABLE, "too many subscribers");
+	}
+
+	let rx = subscribe();
+	let subscribers = subscribers.clone();
+	tokio::spawn(async move {
+		match req.into_body().on_upgrade().await {
+			Ok(upgraded) => {
+				let ws = WebSocketStream::from_raw_socket(upgraded, Role::Server, None).await;
+				push_messages(ws, rx, name).await;
+			}
+			Err(e) => debug!("{}: upgrade failed: {}", name, e),
+		}
+		subscribers.fetch_sub(1, Ordering::SeqCst);
+	});
+
+	let resp = Response::builder()
+		.status(StatusCode::SWITCHING_PROTOCOLS)
+		.header(CONNECTION, "upgrade")
+		.header(UPGRADE, "websocket")
+		.header(SEC_WEBSOCKET_ACCEPT, accept_key(&key))
+		.body(Body::empty())
+		.unwrap();
+	Box::pin(async move { Ok(resp) })
+}
+
+// Push the messages to the subscriber until either side goes away.
+async fn push_messages<S, T>(
+	ws: WebSocketStream<S>,
+	mut rx: broadcast::Receiver<T>,
+	name: &'static str,
+) where
+	S: AsyncRead + AsyncWrite + Unpin,
+	T: Serialize + Clone,
+{
+	let (mut sink, mut stream) = ws.split();
+	loop {
+		tokio::select! {
+			msg = rx.recv() => {
+				let msg = match msg {
+					Ok(msg) => msg,
+					// Too slow a subscriber, it misses the oldest ones.
+					Err(broadcast::RecvError::Lagged(n)) => {
+						debug!("{}: lagging, skipped {}", name, n);
+						continue;
+					}
+					Err(broadcast::RecvError::Closed) => break,
+				};
+				let text = match serde_json::to_string(&msg) {
+					Ok(text) => text,
+					Err(e) => {
+						error!("{}: failed to serialize: {}", name, e);
+						continue;
+					}
+				};
+				if sink.send(Message::Text(text)).await.is_err() {
+					break;
+				}
+			}
+			msg = stream.next() => match msg {
+				// Pings are answered by the stream itself, anything else the
+				// subscriber sends is ignored.
+				Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+				Some(Ok(_)) => {}
+			}
+		}
+	}
+}
+
+// Client key of a WebSocket upgrade request, None if it isn't one.
+fn websocket_key(req: &Request<Body>) -> Option<Vec<u8>> {
+	let headers = req.headers();
+	let has_token = |name: HeaderName, token: &str| {
+		headers.get_all(name).iter().any(|v| {
+			v.to_str()
+				.map(|v| v.split(',').any(|x| x.trim().eq_ignore_ascii_case(token)))
+				.unwrap_or(false)
+		})
+	};
+	if !has_token(CONNECTION, "upgrade")
+		|| !has_token(UPGRADE, "websocket")
+		|| headers
+			.get(SEC_WEBSOCKET_VERSION)
+			.map_or(true, |v| *v != "13")
+	{
+		return None;
+	}
+	headers
+		.get(SEC_WEBSOCKET_KEY)
+		.map(|key| key.as_bytes().to_vec())
+}
+
+// Key the handshake is answered with, proving the upgrade was understood.
+fn accept_key(key: &[u8]) -> String {
+	let mut ctx = digest::Context::new(&digest::SHA1_FOR_LEGACY_USE_ONLY);
+	ctx.update(key);
+	ctx.update(WEBSOCKET_GUID.as_bytes());
+	base64::encode(ctx.finish().as_ref())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::core::core::hash::Hashed;
+
+	#[test]
+	fn test_websocket_handshake() {
+		// Sample handshake of RFC 6455.
+		assert_eq!(
+			accept_key(b"dGhlIHNhbXBsZSBub25jZQ=="),
+			"s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+		);
+
+		let req = Request::builder()
+			.uri("/v1/ws/blocks")
+			.header(CONNECTION, "keep-alive, Upgrade")
+			.header(UPGRADE, "websocket")
+			.header(SEC_WEBSOCKET_VERSION, "13")
+			.header(SEC_WEBSOCKET_KEY, "dGhlIHNhbXBsZSBub25jZQ==")
+			.body(Body::empty())
+			.unwrap();
+		assert_eq!(
+			websocket_key(&req),
+			Some(b"dGhlIHNhbXBsZSBub25jZQ==".to_vec())
+		);
+
+		let req = Request::builder()
+			.uri("/v1/ws/blocks")
+			.body(Body::empty())
+			.unwrap();
+		assert_eq!(websocket_key(&req), None);
+	}
+
+	#[test]
+	fn test_block_events() {
+		let events = BlockEvents::new();
+		let header = BlockHeader::default();
+
+		// Nobody listening yet.
+		events.block_accepted(&header, &BlockStatus::Next);
+
+		let mut rx = events.subscribe();
+		events.block_accepted(&header, &BlockStatus::Fork);
+		events.block_accepted(&header, &BlockStatus::Reorg(2));
+
+		let fork = rx.try_recv().unwrap();
+		assert_eq!(fork.status, "fork");
+		assert_eq!(fork.header.hash, header.hash().to_hex());
+		assert!(fork.head.is_none());
+
+		let reorg = rx.try_recv().unwrap();
+		assert_eq!(reorg.status, "reorg");
+		assert_eq!(reorg.reorg_depth, Some(2));
+		assert_eq!(
+			reorg.head.unwrap().last_block_pushed,
+			header.hash().to_hex()
+		);
+		assert!(rx.try_recv().is_err());
+	}
+}
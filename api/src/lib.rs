@@ -33,26 +33,40 @@ extern crate log;
 
 #[macro_use]
 mod web;
+mod alerts;
 pub mod auth;
+mod cache;
 pub mod client;
 mod foreign;
 mod foreign_rpc;
+#[cfg(feature = "grpc")]
+mod grpc;
 mod handlers;
 mod owner;
 mod owner_rpc;
 mod rest;
+mod route_stats;
 mod router;
 mod types;
 
+pub use crate::alerts::ActiveAlerts;
 pub use crate::auth::{
 	BasicAuthMiddleware, BasicAuthURIMiddleware, KEPLER_BASIC_REALM, KEPLER_FOREIGN_BASIC_REALM,
 };
+pub use crate::cache::ResponseCache;
 pub use crate::foreign::Foreign;
 pub use crate::foreign_rpc::ForeignRpc;
+#[cfg(feature = "grpc")]
+pub use crate::grpc::start_grpc;
+pub use crate::handlers::events_api::NodeEvents;
+pub use crate::handlers::faucet_api::{Faucet, FaucetConfig};
 pub use crate::handlers::node_apis;
+pub use crate::handlers::sse_api::{PoolEvents, PoolStreamHandler};
+pub use crate::handlers::ws_api::BlockEvents;
 pub use crate::owner::Owner;
 pub use crate::owner_rpc::OwnerRpc;
 pub use crate::rest::*;
+pub use crate::route_stats::RouteStats;
 pub use crate::router::*;
 pub use crate::types::*;
 pub use crate::web::*;
@@ -14,16 +14,17 @@
 
 //! Owner API External Definition
 
-use crate::chain::{Chain, SyncState};
-use crate::handlers::chain_api::{ChainCompactHandler, ChainValidationHandler};
+use crate::chain::{Chain, CompactionStatus, SyncState};
+use crate::handlers::chain_api::{ChainCompactHandler, ChainValidationHandler, OrphansHandler};
 use crate::handlers::peers_api::{PeerHandler, PeersConnectedHandler};
+use crate::handlers::scan_api::{OutputScanHandler, OutputScans};
 use crate::handlers::server_api::StatusHandler;
-use crate::p2p::types::PeerInfoDisplay;
+use crate::p2p::types::{IpSubnet, PeerInfoDisplay};
 use crate::p2p::{self, PeerData};
 use crate::rest::*;
-use crate::types::Status;
+use crate::types::{OrphanBlock, OutputScan, Status};
 use std::net::SocketAddr;
-use std::sync::Weak;
+use std::sync::{Arc, Weak};
 
 /// Main interface into all node API functions.
 /// Node APIs are split into two seperate blocks of functionality
@@ -36,6 +37,7 @@ pub struct Owner {
 	pub chain: Weak<Chain>,
 	pub peers: Weak<p2p::Peers>,
 	pub sync_state: Weak<SyncState>,
+	pub output_scans: Option<Arc<OutputScans>>,
 }
 
 impl Owner {
@@ -47,16 +49,23 @@ impl Owner {
 	/// * `tx_pool` - A non-owning reference of the transaction pool.
 	/// * `peers` - A non-owning reference of the peers.
 	/// * `sync_state` - A non-owning reference of the `sync_state`.
+	/// * `output_scans` - The rate limit of the output scans, if they're enabled.
 	///
 	/// # Returns
 	/// * An instance of the Node holding references to the current chain, transaction pool, peers and sync_state.
 	///
 
-	pub fn new(chain: Weak<Chain>, peers: Weak<p2p::Peers>, sync_state: Weak<SyncState>) -> Self {
+	pub fn new(
+		chain: Weak<Chain>,
+		peers: Weak<p2p::Peers>,
+		sync_state: Weak<SyncState>,
+		output_scans: Option<Arc<OutputScans>>,
+	) -> Self {
 		Owner {
 			chain,
 			peers,
 			sync_state,
+			output_scans,
 		}
 	}
 
@@ -92,11 +101,14 @@ impl Owner {
 		chain_validation_handler.validate_chain()
 	}
 
-	/// Trigger a compaction of the chain state to regain storage space.
+	/// Trigger a compaction of the chain state to regain storage space. The
+	/// compaction runs in the background, unless one is running already,
+	/// and is skipped if the chain was compacted within the last 60 blocks.
+	/// Its outcome is reported by [`get_compaction_status`](struct.Owner.html#method.get_compaction_status).
 	///
 	/// # Returns
 	/// * Result Containing:
-	/// * `Ok(())` if the compaction was done successfully
+	/// * `Ok(())` if the compaction was started successfully
 	/// * or [`Error`](struct.Error.html) if an error is encountered.
 	///
 
@@ -104,7 +116,109 @@ impl Owner {
 		let chain_compact_handler = ChainCompactHandler {
 			chain: self.chain.clone(),
 		};
-		chain_compact_handler.compact_chain()
+		chain_compact_handler.start_compaction()
+	}
+
+	/// Retrieves the status of the chain compactions, whether one is running
+	/// and when the last one ran, triggered by the owner or by the node itself.
+	///
+	/// # Returns
+	/// * Result Containing:
+	/// * A [`CompactionStatus`](../kepler_chain/types/struct.CompactionStatus.html)
+	/// * or [`Error`](struct.Error.html) if an error is encountered.
+	///
+
+	pub fn get_compaction_status(&self) -> Result<CompactionStatus, Error> {
+		let chain_compact_handler = ChainCompactHandler {
+			chain: self.chain.clone(),
+		};
+		chain_compact_handler.compaction_status()
+	}
+
+	/// Lists the blocks waiting in the orphan pool for their parent, lowest
+	/// first.
+	///
+	/// # Returns
+	/// * Result Containing:
+	/// * A vector of [`OrphanBlock`](types/struct.OrphanBlock.html)
+	/// * or [`Error`](struct.Error.html) if an error is encountered.
+	///
+
+	pub fn list_orphan_blocks(&self) -> Result<Vec<OrphanBlock>, Error> {
+		let orphans_handler = OrphansHandler {
+			chain: self.chain.clone(),
+		};
+		orphans_handler.list_orphans()
+	}
+
+	/// Drops a block from the orphan pool, e.g. when a malformed orphan is
+	/// suspected of stalling the sync. The block is processed again if
+	/// received again.
+	///
+	/// # Arguments
+	/// * `hash` - the hash of the orphan block (as hex string).
+	///
+	/// # Returns
+	/// * Result Containing:
+	/// * `Ok(())` if the orphan was removed
+	/// * or [`Error`](struct.Error.html) if the block isn't an orphan.
+	///
+
+	pub fn remove_orphan(&self, hash: String) -> Result<(), Error> {
+		let orphans_handler = OrphansHandler {
+			chain: self.chain.clone(),
+		};
+		orphans_handler.remove_orphan(&hash)
+	}
+
+	/// Drops all the blocks from the orphan pool.
+	///
+	/// # Returns
+	/// * Result Containing:
+	/// * The number of orphans removed
+	/// * or [`Error`](struct.Error.html) if an error is encountered.
+	///
+
+	pub fn clear_orphans(&self) -> Result<usize, Error> {
+		let orphans_handler = OrphansHandler {
+			chain: self.chain.clone(),
+		};
+		orphans_handler.clear_orphans()
+	}
+
+	/// Scans the UTXO set for the outputs of a wallet, to restore it without
+	/// fetching every output. Only available if `owner_api_scan_outputs` is
+	/// set, and rate limited.
+	///
+	/// # Arguments
+	/// * `rewind_hash` - the rewind hash of the wallet view key (as hex string).
+	/// * `start_height` - height of the block to scan the outputs from.
+	/// * `start_index` - MMR index to continue a previous scan from, if any.
+	/// * `max` - the most outputs to scan, up to 50000.
+	///
+	/// # Returns
+	/// * Result Containing:
+	/// * An [`OutputScan`](types/struct.OutputScan.html) listing the outputs
+	/// whose rangeproof rewinds, to be checked by the wallet
+	/// * or [`Error`](struct.Error.html) if an error is encountered.
+	///
+
+	pub fn scan_outputs(
+		&self,
+		rewind_hash: String,
+		start_height: u64,
+		start_index: Option<u64>,
+		max: Option<u64>,
+	) -> Result<OutputScan, Error> {
+		let scans = self
+			.output_scans
+			.clone()
+			.ok_or_else(|| ErrorKind::RequestError("output scans are disabled".to_owned()))?;
+		let output_scan_handler = OutputScanHandler {
+			chain: self.chain.clone(),
+			scans,
+		};
+		output_scan_handler.scan_outputs(&rewind_hash, start_height, start_index, max)
 	}
 
 	/// Retrieves information about stored peers.
@@ -176,4 +290,56 @@ impl Owner {
 		};
 		peer_handler.unban_peer(addr)
 	}
+
+	/// Lists the peers and subnets exempt from automatic banning.
+	///
+	/// # Returns
+	/// * Result Containing:
+	/// * A vector of [`IpSubnet`](../kepler_p2p/types/struct.IpSubnet.html)
+	/// * or [`Error`](struct.Error.html) if an error is encountered.
+	///
+
+	pub fn get_ban_exemptions(&self) -> Result<Vec<IpSubnet>, Error> {
+		let peer_handler = PeerHandler {
+			peers: self.peers.clone(),
+		};
+		peer_handler.get_ban_exemptions()
+	}
+
+	/// Exempts a peer or a subnet from automatic banning, until the node
+	/// restarts. Manual bans still apply.
+	///
+	/// # Arguments
+	/// * `subnet` - the ip or subnet (e.g. 10.0.0.0/8) to exempt.
+	///
+	/// # Returns
+	/// * Result Containing:
+	/// * `Ok(())` if the exemption was added
+	/// * or [`Error`](struct.Error.html) if an error is encountered.
+	///
+
+	pub fn add_ban_exemption(&self, subnet: IpSubnet) -> Result<(), Error> {
+		let peer_handler = PeerHandler {
+			peers: self.peers.clone(),
+		};
+		peer_handler.add_ban_exemption(subnet)
+	}
+
+	/// Lifts the exemption of a peer or a subnet from automatic banning.
+	///
+	/// # Arguments
+	/// * `subnet` - the ip or subnet, as it was exempted.
+	///
+	/// # Returns
+	/// * Result Containing:
+	/// * `Ok(())` if the exemption was removed
+	/// * or [`Error`](struct.Error.html) if it wasn't exempt.
+	///
+
+	pub fn remove_ban_exemption(&self, subnet: IpSubnet) -> Result<(), Error> {
+		let peer_handler = PeerHandler {
+			peers: self.peers.clone(),
+		};
+		peer_handler.remove_ban_exemption(subnet)
+	}
 }
@@ -14,11 +14,12 @@
 
 //! JSON-RPC Stub generation for the Owner API
 
+use crate::chain::CompactionStatus;
 use crate::owner::Owner;
-use crate::p2p::types::PeerInfoDisplay;
+use crate::p2p::types::{IpSubnet, PeerInfoDisplay};
 use crate::p2p::PeerData;
 use crate::rest::ErrorKind;
-use crate::types::Status;
+use crate::types::{OrphanBlock, OutputScan, Status};
 use std::net::SocketAddr;
 
 /// Public definition used to generate Node jsonrpc api.
@@ -132,6 +133,192 @@ pub trait OwnerRpc: Sync + Send {
 	 */
 	fn compact_chain(&self) -> Result<(), ErrorKind>;
 
+	/**
+	Networked version of [Owner::get_compaction_status](struct.Node.html#method.get_compaction_status).
+
+	# Json rpc example
+
+	```
+	# kepler_api::doctest_helper_json_rpc_owner_assert_response!(
+	# r#"
+	{
+		"jsonrpc": "2.0",
+		"method": "get_compaction_status",
+		"params": [],
+		"id": 1
+	}
+	# "#
+	# ,
+	# r#"
+	{
+		"id": 1,
+		"jsonrpc": "2.0",
+		"result": {
+			"Ok": {
+				"running": false,
+				"last_started": null,
+				"last_finished": null,
+				"bytes_reclaimed": null,
+				"last_error": null
+			}
+		}
+	}
+	# "#
+	# );
+	```
+	 */
+	fn get_compaction_status(&self) -> Result<CompactionStatus, ErrorKind>;
+
+	/**
+	Networked version of [Owner::list_orphan_blocks](struct.Node.html#method.list_orphan_blocks).
+
+	# Json rpc example
+
+	```
+	# kepler_api::doctest_helper_json_rpc_owner_assert_response!(
+	# r#"
+	{
+		"jsonrpc": "2.0",
+		"method": "list_orphan_blocks",
+		"params": [],
+		"id": 1
+	}
+	# "#
+	# ,
+	# r#"
+	{
+		"id": 1,
+		"jsonrpc": "2.0",
+		"result": {
+			"Ok": [
+				{
+					"hash": "0b6e6d57c8c3b4e6c1f0a2e1b3e3f3cfc3a1e3d0b4b1f0c6c2c3e4f5a6b7c8d9",
+					"height": 374275,
+					"previous": "ed6ba96009b86173bade6a9227ed60422916593fa32dd6d78b25b7a4eeef4946",
+					"inputs": 2,
+					"outputs": 3,
+					"kernels": 2,
+					"arrived": "2020-06-01T12:00:00+00:00",
+					"peer": "10.0.0.2:7414"
+				}
+			]
+		}
+	}
+	# "#
+	# );
+	```
+	 */
+	fn list_orphan_blocks(&self) -> Result<Vec<OrphanBlock>, ErrorKind>;
+
+	/**
+	Networked version of [Owner::remove_orphan](struct.Node.html#method.remove_orphan).
+
+	# Json rpc example
+
+	```
+	# kepler_api::doctest_helper_json_rpc_owner_assert_response!(
+	# r#"
+	{
+		"jsonrpc": "2.0",
+		"method": "remove_orphan",
+		"params": ["0b6e6d57c8c3b4e6c1f0a2e1b3e3f3cfc3a1e3d0b4b1f0c6c2c3e4f5a6b7c8d9"],
+		"id": 1
+	}
+	# "#
+	# ,
+	# r#"
+	{
+		"id": 1,
+		"jsonrpc": "2.0",
+		"result": {
+			"Ok": null
+		}
+	}
+	# "#
+	# );
+	```
+	 */
+	fn remove_orphan(&self, hash: String) -> Result<(), ErrorKind>;
+
+	/**
+	Networked version of [Owner::clear_orphans](struct.Node.html#method.clear_orphans).
+
+	# Json rpc example
+
+	```
+	# kepler_api::doctest_helper_json_rpc_owner_assert_response!(
+	# r#"
+	{
+		"jsonrpc": "2.0",
+		"method": "clear_orphans",
+		"params": [],
+		"id": 1
+	}
+	# "#
+	# ,
+	# r#"
+	{
+		"id": 1,
+		"jsonrpc": "2.0",
+		"result": {
+			"Ok": 1
+		}
+	}
+	# "#
+	# );
+	```
+	 */
+	fn clear_orphans(&self) -> Result<usize, ErrorKind>;
+
+	/**
+	Networked version of [Owner::scan_outputs](struct.Node.html#method.scan_outputs).
+
+	# Json rpc example
+
+	```
+	# kepler_api::doctest_helper_json_rpc_owner_assert_response!(
+	# r#"
+	{
+		"jsonrpc": "2.0",
+		"method": "scan_outputs",
+		"params": ["f5b7b3d9a43c0c4da7de2a8e0a0e3b9ad6ef7d8a44c54b2bc0b9e1e6a67e0c41", 1, null, null],
+		"id": 1
+	}
+	# "#
+	# ,
+	# r#"
+	{
+		"id": 1,
+		"jsonrpc": "2.0",
+		"result": {
+			"Ok": {
+				"highest_index": 2281,
+				"last_retrieved_index": 2281,
+				"outputs": [
+					{
+						"commit": "08ae7b5a35d2a2b6e29b71e0a6e5cd65b36e84a1fa06d3a1ef4e7b7c2f4e0b9a51",
+						"output_type": "Transaction",
+						"amount": 60000000000,
+						"proof_message": "0000000300000000000000010000000000000000",
+						"mmr_index": 1786,
+						"height": 354
+					}
+				]
+			}
+		}
+	}
+	# "#
+	# );
+	```
+	 */
+	fn scan_outputs(
+		&self,
+		rewind_hash: String,
+		start_height: u64,
+		start_index: Option<u64>,
+		max: Option<u64>,
+	) -> Result<OutputScan, ErrorKind>;
+
 	/**
 	Networked version of [Owner::get_peers](struct.Node.html#method.get_peers).
 
@@ -352,6 +539,96 @@ pub trait OwnerRpc: Sync + Send {
 	```
 	 */
 	fn unban_peer(&self, peer_addr: SocketAddr) -> Result<(), ErrorKind>;
+
+	/**
+	Networked version of [Owner::get_ban_exemptions](struct.Node.html#method.get_ban_exemptions).
+
+	# Json rpc example
+
+	```
+	# kepler_api::doctest_helper_json_rpc_owner_assert_response!(
+	# r#"
+	{
+		"jsonrpc": "2.0",
+		"method": "get_ban_exemptions",
+		"params": [],
+		"id": 1
+	}
+	# "#
+	# ,
+	# r#"
+	{
+		"id": 1,
+		"jsonrpc": "2.0",
+		"result": {
+			"Ok": ["10.0.0.0/8", "70.50.33.130"]
+		}
+	}
+	# "#
+	# );
+	```
+	 */
+	fn get_ban_exemptions(&self) -> Result<Vec<IpSubnet>, ErrorKind>;
+
+	/**
+	Networked version of [Owner::add_ban_exemption](struct.Node.html#method.add_ban_exemption).
+
+	# Json rpc example
+
+	```
+	# kepler_api::doctest_helper_json_rpc_owner_assert_response!(
+	# r#"
+	{
+		"jsonrpc": "2.0",
+		"method": "add_ban_exemption",
+		"params": ["10.0.0.0/8"],
+		"id": 1
+	}
+	# "#
+	# ,
+	# r#"
+	{
+		"id": 1,
+		"jsonrpc": "2.0",
+		"result": {
+			"Ok": null
+		}
+	}
+	# "#
+	# );
+	```
+	 */
+	fn add_ban_exemption(&self, subnet: IpSubnet) -> Result<(), ErrorKind>;
+
+	/**
+	Networked version of [Owner::remove_ban_exemption](struct.Node.html#method.remove_ban_exemption).
+
+	# Json rpc example
+
+	```
+	# kepler_api::doctest_helper_json_rpc_owner_assert_response!(
+	# r#"
+	{
+		"jsonrpc": "2.0",
+		"method": "remove_ban_exemption",
+		"params": ["10.0.0.0/8"],
+		"id": 1
+	}
+	# "#
+	# ,
+	# r#"
+	{
+		"id": 1,
+		"jsonrpc": "2.0",
+		"result": {
+			"Ok": null
+		}
+	}
+	# "#
+	# );
+	```
+	 */
+	fn remove_ban_exemption(&self, subnet: IpSubnet) -> Result<(), ErrorKind>;
 }
 
 impl OwnerRpc for Owner {
@@ -367,6 +644,33 @@ impl OwnerRpc for Owner {
 		Owner::compact_chain(self).map_err(|e| e.kind().clone())
 	}
 
+	fn get_compaction_status(&self) -> Result<CompactionStatus, ErrorKind> {
+		Owner::get_compaction_status(self).map_err(|e| e.kind().clone())
+	}
+
+	fn list_orphan_blocks(&self) -> Result<Vec<OrphanBlock>, ErrorKind> {
+		Owner::list_orphan_blocks(self).map_err(|e| e.kind().clone())
+	}
+
+	fn remove_orphan(&self, hash: String) -> Result<(), ErrorKind> {
+		Owner::remove_orphan(self, hash).map_err(|e| e.kind().clone())
+	}
+
+	fn clear_orphans(&self) -> Result<usize, ErrorKind> {
+		Owner::clear_orphans(self).map_err(|e| e.kind().clone())
+	}
+
+	fn scan_outputs(
+		&self,
+		rewind_hash: String,
+		start_height: u64,
+		start_index: Option<u64>,
+		max: Option<u64>,
+	) -> Result<OutputScan, ErrorKind> {
+		Owner::scan_outputs(self, rewind_hash, start_height, start_index, max)
+			.map_err(|e| e.kind().clone())
+	}
+
 	fn get_peers(&self, addr: Option<SocketAddr>) -> Result<Vec<PeerData>, ErrorKind> {
 		Owner::get_peers(self, addr).map_err(|e| e.kind().clone())
 	}
@@ -382,6 +686,18 @@ impl OwnerRpc for Owner {
 	fn unban_peer(&self, addr: SocketAddr) -> Result<(), ErrorKind> {
 		Owner::unban_peer(self, addr).map_err(|e| e.kind().clone())
 	}
+
+	fn get_ban_exemptions(&self) -> Result<Vec<IpSubnet>, ErrorKind> {
+		Owner::get_ban_exemptions(self).map_err(|e| e.kind().clone())
+	}
+
+	fn add_ban_exemption(&self, subnet: IpSubnet) -> Result<(), ErrorKind> {
+		Owner::add_ban_exemption(self, subnet).map_err(|e| e.kind().clone())
+	}
+
+	fn remove_ban_exemption(&self, subnet: IpSubnet) -> Result<(), ErrorKind> {
+		Owner::remove_ban_exemption(self, subnet).map_err(|e| e.kind().clone())
+	}
 }
 
 #[doc(hidden)]
@@ -19,23 +19,33 @@
 //! register them on a ApiServer.
 
 use crate::router::{Handler, HandlerObj, ResponseFuture, Router, RouterError};
+use crate::util::Mutex;
 use crate::web::response;
 use failure::{Backtrace, Context, Fail, ResultExt};
 use futures::channel::oneshot;
 use futures::TryStreamExt;
+use hyper::header::{self, HeaderValue};
 use hyper::server::accept;
-use hyper::service::make_service_fn;
-use hyper::{Body, Request, Server, StatusCode};
+use hyper::server::conn::AddrStream;
+use hyper::service::{make_service_fn, service_fn, Service};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
 use rustls;
 use rustls::internal::pemfile;
+use std::collections::HashMap;
 use std::convert::Infallible;
 use std::fmt::{self, Display};
 use std::fs::File;
-use std::net::SocketAddr;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use std::{io, thread};
-use tokio::net::TcpListener;
-use tokio::runtime::Runtime;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::{Handle, Runtime};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio_rustls::server::TlsStream;
 use tokio_rustls::TlsAcceptor;
 
 /// Errors that can be returned by an ApiEndpoint implementation.
@@ -157,31 +167,150 @@ impl TLSConfig {
 	}
 }
 
+/// Seconds a client is asked to wait (via Retry-After) before retrying a
+/// request rejected while the server is draining.
+const DRAIN_RETRY_AFTER_SECS: u64 = 5;
+
+/// Most clients the per client rate limit keeps track of, the idle ones get
+/// forgotten past that.
+const MAX_RATE_LIMITED_CLIENTS: usize = 10_000;
+
+/// Requests per second served by a public node to a single client IP,
+/// unless limited otherwise.
+const PUBLIC_NODE_MAX_REQUESTS_PER_IP: u32 = 10;
+
+/// Methods and headers the allowed origins may use in cross-origin requests.
+const CORS_ALLOWED_METHODS: &str = "GET, POST, OPTIONS";
+const CORS_ALLOWED_HEADERS: &str = "Content-Type, Authorization";
+
+/// How long browsers may cache the answer to a CORS preflight request.
+const CORS_MAX_AGE_SECS: u64 = 3600;
+
+/// First file descriptor passed via systemd socket activation, see sd_listen_fds(3).
+#[cfg(unix)]
+const SD_LISTEN_FDS_START: i32 = 3;
+
+/// Address of the client a request comes from, set on the requests served
+/// by the ApiServer for the middlewares to use.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RemoteAddr(pub SocketAddr);
+
+/// Set on the requests served by the ApiServer, resolves once it is asked
+/// to stop so the responses streaming for as long as the client listens can
+/// end instead of holding up its shutdown.
+#[derive(Clone)]
+pub struct Stopping(watch::Receiver<bool>);
+
+impl Stopping {
+	/// Resolves once the ApiServer is stopping.
+	pub async fn wait(mut self) {
+		loop {
+			match self.0.recv().await {
+				Some(true) => return,
+				Some(false) => (),
+				// Dropped without being stopped, the server keeps running.
+				None => futures::future::pending::<()>().await,
+			}
+		}
+	}
+}
+
+/// Limits on the requests served, to keep misbehaving clients from hogging
+/// the node. None means unlimited.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RequestLimits {
+	/// Most requests served per second to a single client IP.
+	pub max_per_ip_per_sec: Option<u32>,
+	/// Most requests served at once, across all clients.
+	pub max_in_flight: Option<usize>,
+	/// Threads the requests are handled on, on the async runtime if None.
+	pub worker_threads: Option<usize>,
+}
+
+impl RequestLimits {
+	/// The limits of a public node are always rate limited per client IP,
+	/// so no client can starve the others.
+	pub(crate) fn for_public_node(&self, public_node: bool) -> RequestLimits {
+		let mut limits = self.clone();
+		if public_node && limits.max_per_ip_per_sec.is_none() {
+			limits.max_per_ip_per_sec = Some(PUBLIC_NODE_MAX_REQUESTS_PER_IP);
+		}
+		limits
+	}
+
+	/// Add the middlewares enforcing the limits to the router.
+	pub fn apply(&self, router: &mut Router) {
+		if let Some(max_per_sec) = self.max_per_ip_per_sec {
+			router.add_middleware(Arc::new(IpRateLimitMiddleware::new(max_per_sec)));
+		}
+		if let Some(max_in_flight) = self.max_in_flight {
+			router.add_middleware(Arc::new(InFlightLimitMiddleware::new(max_in_flight)));
+		}
+		if let Some(worker_threads) = self.worker_threads {
+			router.set_workers(worker_threads);
+		}
+	}
+}
+
 /// HTTP server allowing the registration of ApiEndpoint implementations.
 pub struct ApiServer {
 	shutdown_sender: Option<oneshot::Sender<()>>,
+	draining: Arc<AtomicBool>,
+	stop_sender: watch::Sender<bool>,
+	stopping: Stopping,
+	runtime: Option<Handle>,
 }
 
 impl ApiServer {
 	/// Creates a new ApiServer that will serve ApiEndpoint implementations
 	/// under the root URL.
 	pub fn new() -> ApiServer {
+		let (stop_sender, stopping) = watch::channel(false);
 		ApiServer {
 			shutdown_sender: None,
+			draining: Arc::new(AtomicBool::new(false)),
+			stop_sender,
+			stopping: Stopping(stopping),
+			runtime: None,
 		}
 	}
 
+	/// Serve the requests on the provided runtime, shared with the rest of
+	/// the application, instead of a runtime of its own.
+	pub fn with_runtime(mut self, runtime: Handle) -> ApiServer {
+		self.runtime = Some(runtime);
+		self
+	}
+
 	/// Starts ApiServer at the provided address.
-	/// TODO support stop operation
+	/// If a listening socket was passed in via systemd socket activation it is
+	/// used instead of binding to the address, so the API can be restarted
+	/// without refusing connections.
 	pub fn start(
 		&mut self,
 		addr: SocketAddr,
-		router: Router,
+		mut router: Router,
 		conf: Option<TLSConfig>,
-	) -> Result<thread::JoinHandle<()>, Error> {
+	) -> Result<ApiServerHandle, Error> {
+		if self.shutdown_sender.is_some() {
+			return Err(ErrorKind::Internal(
+				"Can't start HTTP API server, it's running already".to_string(),
+			)
+			.into());
+		}
+		router.add_middleware(Arc::new(DrainingMiddleware {
+			draining: self.draining.clone(),
+		}));
+		let listener = inherited_listener();
+		if listener.is_some() {
+			info!("API server using listener inherited via socket activation");
+		}
+		let (tx, rx) = oneshot::channel::<()>();
+		self.shutdown_sender = Some(tx);
+		let _ = self.stop_sender.broadcast(false);
 		match conf {
-			Some(conf) => self.start_tls(addr, router, conf),
-			None => self.start_no_tls(addr, router),
+			Some(conf) => self.start_tls(addr, listener, router, conf, rx),
+			None => self.start_no_tls(addr, listener, router, rx),
 		}
 	}
 
@@ -189,99 +318,217 @@ impl ApiServer {
 	fn start_no_tls(
 		&mut self,
 		addr: SocketAddr,
+		listener: Option<std::net::TcpListener>,
 		router: Router,
-	) -> Result<thread::JoinHandle<()>, Error> {
-		if self.shutdown_sender.is_some() {
-			return Err(ErrorKind::Internal(
-				"Can't start HTTP API server, it's running already".to_string(),
-			)
-			.into());
-		}
-		let (tx, _rx) = oneshot::channel::<()>();
-		self.shutdown_sender = Some(tx);
-		thread::Builder::new()
-			.name("apis".to_string())
-			.spawn(move || {
-				let server = async move {
-					let server = Server::bind(&addr).serve(make_service_fn(move |_| {
-						let router = router.clone();
-						async move { Ok::<_, Infallible>(router) }
-					}));
-					// TODO graceful shutdown is unstable, investigate
-					//.with_graceful_shutdown(rx)
-
-					server.await
-				};
-
-				let mut rt = Runtime::new()
-					.map_err(|e| eprintln!("HTTP API server error: {}", e))
-					.unwrap();
-				if let Err(e) = rt.block_on(server) {
-					eprintln!("HTTP API server error: {}", e)
-				}
-			})
-			.map_err(|_| ErrorKind::Internal("failed to spawn API thread".to_string()).into())
+		rx: oneshot::Receiver<()>,
+	) -> Result<ApiServerHandle, Error> {
+		let stopping = self.stopping.clone();
+		let server = async move {
+			let builder = match listener {
+				Some(listener) => Server::from_tcp(listener)?,
+				None => Server::try_bind(&addr)?,
+			};
+			let server = builder
+				.serve(make_service_fn(move |conn: &AddrStream| {
+					let service = connection_service(
+						router.clone(),
+						Some(conn.remote_addr()),
+						stopping.clone(),
+					);
+					async move { Ok::<_, Infallible>(service) }
+				}))
+				.with_graceful_shutdown(shutdown_signal(rx));
+
+			server.await
+		};
+
+		run_server(self.runtime.clone(), server)
 	}
 
 	/// Starts the TLS ApiServer at the provided address.
-	/// TODO support stop operation
 	fn start_tls(
 		&mut self,
 		addr: SocketAddr,
+		listener: Option<std::net::TcpListener>,
 		router: Router,
 		conf: TLSConfig,
-	) -> Result<thread::JoinHandle<()>, Error> {
-		if self.shutdown_sender.is_some() {
-			return Err(ErrorKind::Internal(
-				"Can't start HTTPS API server, it's running already".to_string(),
-			)
-			.into());
-		}
-
+		rx: oneshot::Receiver<()>,
+	) -> Result<ApiServerHandle, Error> {
 		let acceptor = TlsAcceptor::from(conf.build_server_config()?);
-
-		thread::Builder::new()
-			.name("apis".to_string())
-			.spawn(move || {
-				let server = async move {
-					let mut listener = TcpListener::bind(&addr).await.expect("failed to bind");
-					let listener = listener.incoming().and_then(move |s| acceptor.accept(s));
-
-					let server = Server::builder(accept::from_stream(listener)).serve(
-						make_service_fn(move |_| {
-							let router = router.clone();
-							async move { Ok::<_, Infallible>(router) }
-						}),
-					);
-
-					server.await
-				};
-
-				let mut rt = Runtime::new()
-					.map_err(|e| eprintln!("HTTP API server error: {}", e))
-					.unwrap();
-				if let Err(e) = rt.block_on(server) {
-					eprintln!("HTTP API server error: {}", e)
+		let stopping = self.stopping.clone();
+		let server = async move {
+			let mut listener = match listener {
+				Some(listener) => {
+					TcpListener::from_std(listener).expect("failed to use inherited listener")
 				}
-			})
-			.map_err(|_| ErrorKind::Internal("failed to spawn API thread".to_string()).into())
+				None => TcpListener::bind(&addr).await.expect("failed to bind"),
+			};
+			let listener = listener.incoming().and_then(move |s| acceptor.accept(s));
+
+			let server = Server::builder(accept::from_stream(listener))
+				.serve(make_service_fn(move |conn: &TlsStream<TcpStream>| {
+					let remote_addr = conn.get_ref().0.peer_addr().ok();
+					let service = connection_service(router.clone(), remote_addr, stopping.clone());
+					async move { Ok::<_, Infallible>(service) }
+				}))
+				.with_graceful_shutdown(shutdown_signal(rx));
+
+			server.await
+		};
+
+		run_server(self.runtime.clone(), server)
+	}
+
+	/// Resolves once the API server is asked to stop, for the services
+	/// running alongside it to stop too.
+	pub fn stopping(&self) -> Stopping {
+		self.stopping.clone()
 	}
 
-	/// Stops the API server, it panics in case of error
+	/// Stops the API server. New connections are no longer accepted and new
+	/// requests on open connections are rejected with a Retry-After, while
+	/// in-flight requests are allowed to complete.
 	pub fn stop(&mut self) -> bool {
-		if self.shutdown_sender.is_some() {
-			// TODO re-enable stop after investigation
-			//let tx = mem::replace(&mut self.shutdown_sender, None).unwrap();
-			//tx.send(()).expect("Failed to stop API server");
-			info!("API server has been stopped");
+		if let Some(tx) = self.shutdown_sender.take() {
+			self.draining.store(true, Ordering::Relaxed);
+			let _ = self.stop_sender.broadcast(true);
+			let _ = tx.send(());
+			info!("API server has been stopped, draining in-flight requests");
 			true
 		} else {
-			error!("Can't stop API server, it's not running or doesn't spport stop operation");
+			error!("Can't stop API server, it's not running");
 			false
 		}
 	}
 }
 
+// Serves the requests of a connection with the router, letting the
+// middlewares know which client they come from and the handlers when the
+// server is stopping.
+fn connection_service(
+	mut router: Router,
+	remote_addr: Option<SocketAddr>,
+	stopping: Stopping,
+) -> impl Service<Request<Body>, Response = Response<Body>, Error = hyper::Error, Future = ResponseFuture>
+{
+	service_fn(move |mut req: Request<Body>| {
+		if let Some(addr) = remote_addr {
+			req.extensions_mut().insert(RemoteAddr(addr));
+		}
+		req.extensions_mut().insert(stopping.clone());
+		router.call(req)
+	})
+}
+
+/// Handle to a running API server, to wait for it to be done draining once
+/// stopped.
+pub enum ApiServerHandle {
+	/// Server running as a task of the shared runtime.
+	Task(JoinHandle<()>),
+	/// Server running on a runtime of its own, on a dedicated thread.
+	Thread(thread::JoinHandle<()>),
+}
+
+impl ApiServerHandle {
+	/// Block until the server is done. Not to be called from a task running
+	/// on the shared runtime.
+	pub fn join(self) -> Result<(), Error> {
+		match self {
+			ApiServerHandle::Task(handle) => futures::executor::block_on(handle)
+				.map_err(|e| ErrorKind::Internal(format!("API server task failed: {}", e)).into()),
+			ApiServerHandle::Thread(handle) => handle
+				.join()
+				.map_err(|_| ErrorKind::Internal("API server thread panicked".to_string()).into()),
+		}
+	}
+}
+
+// Runs the server until it stops, as a task of the provided runtime if any,
+// or on a runtime of its own on a dedicated thread.
+fn run_server<F>(runtime: Option<Handle>, server: F) -> Result<ApiServerHandle, Error>
+where
+	F: Future<Output = Result<(), hyper::Error>> + Send + 'static,
+{
+	let server = async move {
+		if let Err(e) = server.await {
+			eprintln!("HTTP API server error: {}", e)
+		}
+	};
+	match runtime {
+		Some(runtime) => Ok(ApiServerHandle::Task(runtime.spawn(server))),
+		None => thread::Builder::new()
+			.name("apis".to_string())
+			.spawn(move || match Runtime::new() {
+				Ok(mut rt) => rt.block_on(server),
+				Err(e) => eprintln!("HTTP API server error: {}", e),
+			})
+			.map(ApiServerHandle::Thread)
+			.map_err(|_| ErrorKind::Internal("failed to spawn API thread".to_string()).into()),
+	}
+}
+
+// Resolves when the API server is asked to stop.
+// The ApiServer being dropped without calling stop does not stop the server.
+async fn shutdown_signal(rx: oneshot::Receiver<()>) {
+	if rx.await.is_err() {
+		futures::future::pending::<()>().await;
+	}
+}
+
+// Listening socket passed to us by systemd socket activation, if any.
+// We only ever use the first socket passed in.
+#[cfg(unix)]
+fn inherited_listener() -> Option<std::net::TcpListener> {
+	use std::os::unix::io::FromRawFd;
+
+	use std::env;
+
+	let pid: u32 = env::var("LISTEN_PID").ok()?.parse().ok()?;
+	let fds: u32 = env::var("LISTEN_FDS").ok()?.parse().ok()?;
+	if pid != std::process::id() || fds == 0 {
+		return None;
+	}
+	// Make sure the socket is only ever taken over once.
+	env::remove_var("LISTEN_PID");
+	env::remove_var("LISTEN_FDS");
+
+	let listener = unsafe { std::net::TcpListener::from_raw_fd(SD_LISTEN_FDS_START) };
+	listener.set_nonblocking(true).ok()?;
+	Some(listener)
+}
+
+#[cfg(not(unix))]
+fn inherited_listener() -> Option<std::net::TcpListener> {
+	None
+}
+
+// Rejects requests once the server is draining, asking the client to retry
+// shortly (against the restarted server).
+struct DrainingMiddleware {
+	draining: Arc<AtomicBool>,
+}
+
+impl Handler for DrainingMiddleware {
+	fn call(
+		&self,
+		req: Request<Body>,
+		mut handlers: Box<dyn Iterator<Item = HandlerObj>>,
+	) -> ResponseFuture {
+		if self.draining.load(Ordering::Relaxed) {
+			let resp = Response::builder()
+				.status(StatusCode::SERVICE_UNAVAILABLE)
+				.header(hyper::header::RETRY_AFTER, DRAIN_RETRY_AFTER_SECS)
+				.body(Body::empty())
+				.unwrap();
+			return Box::pin(async { Ok(resp) });
+		}
+		match handlers.next() {
+			Some(handler) => handler.call(req, handlers),
+			None => response(StatusCode::INTERNAL_SERVER_ERROR, "no handler found"),
+		}
+	}
+}
+
 pub struct LoggingMiddleware {}
 
 impl Handler for LoggingMiddleware {
@@ -297,3 +544,204 @@ impl Handler for LoggingMiddleware {
 		}
 	}
 }
+
+/// Limits the rate of requests served to each client IP, rejecting requests
+/// over the limit with a 429 until the current one second window of the
+/// client is over. Requests from an unknown address aren't limited.
+pub struct IpRateLimitMiddleware {
+	max_per_sec: u32,
+	// start of the current window and requests served in it, per client
+	windows: Mutex<HashMap<IpAddr, (Instant, u32)>>,
+}
+
+impl IpRateLimitMiddleware {
+	pub fn new(max_per_sec: u32) -> IpRateLimitMiddleware {
+		IpRateLimitMiddleware {
+			max_per_sec,
+			windows: Mutex::new(HashMap::new()),
+		}
+	}
+
+	pub(crate) fn allow(&self, ip: IpAddr) -> bool {
+		let mut windows = self.windows.lock();
+		if windows.len() >= MAX_RATE_LIMITED_CLIENTS && !windows.contains_key(&ip) {
+			windows.retain(|_, window| window.0.elapsed() < Duration::from_secs(1));
+		}
+		let window = windows.entry(ip).or_insert_with(|| (Instant::now(), 0));
+		if window.0.elapsed() >= Duration::from_secs(1) {
+			*window = (Instant::now(), 0);
+		}
+		if window.1 >= self.max_per_sec {
+			return false;
+		}
+		window.1 += 1;
+		true
+	}
+}
+
+impl Handler for IpRateLimitMiddleware {
+	fn call(
+		&self,
+		req: Request<Body>,
+		mut handlers: Box<dyn Iterator<Item = HandlerObj>>,
+	) -> ResponseFuture {
+		let ip = req.extensions().get::<RemoteAddr>().map(|addr| addr.0.ip());
+		if let Some(ip) = ip {
+			if !self.allow(ip) {
+				debug!("rate limiting API requests from {}", ip);
+				return retry_later(StatusCode::TOO_MANY_REQUESTS);
+			}
+		}
+		match handlers.next() {
+			Some(handler) => handler.call(req, handlers),
+			None => response(StatusCode::INTERNAL_SERVER_ERROR, "no handler found"),
+		}
+	}
+}
+
+/// Caps the number of requests being served at once across all clients,
+/// rejecting requests over the cap with a 503, so a burst of expensive
+/// queries can't tie up the node. A request counts until its response is
+/// ready, a streamed body doesn't count while it's being sent.
+pub struct InFlightLimitMiddleware {
+	max_in_flight: usize,
+	in_flight: Arc<AtomicUsize>,
+}
+
+impl InFlightLimitMiddleware {
+	pub fn new(max_in_flight: usize) -> InFlightLimitMiddleware {
+		InFlightLimitMiddleware {
+			max_in_flight,
+			in_flight: Arc::new(AtomicUsize::new(0)),
+		}
+	}
+}
+
+impl Handler for InFlightLimitMiddleware {
+	fn call(
+		&self,
+		req: Request<Body>,
+		mut handlers: Box<dyn Iterator<Item = HandlerObj>>,
+	) -> ResponseFuture {
+		if self.in_flight.fetch_add(1, Ordering::SeqCst) >= self.max_in_flight {
+			self.in_flight.fetch_sub(1, Ordering::SeqCst);
+			return retry_later(StatusCode::SERVICE_UNAVAILABLE);
+		}
+		let in_flight = InFlight(self.in_flight.clone());
+		let fut = match handlers.next() {
+			Some(handler) => handler.call(req, handlers),
+			None => response(StatusCode::INTERNAL_SERVER_ERROR, "no handler found"),
+		};
+		Box::pin(async move {
+			let res = fut.await;
+			drop(in_flight);
+			res
+		})
+	}
+}
+
+/// Lets the pages of the allowed origins query the node from a browser,
+/// answering the CORS preflight requests and tagging the responses to their
+/// requests. Responses to other origins get no CORS headers, the browsers
+/// then keep the pages from reading them. Origins are given as sent by the
+/// browsers, e.g. "https://explorer.example.com", or "*" for any.
+/// Applied by the router around every response, including the ones it
+/// answers itself, see Router::set_cors.
+pub struct CorsPolicy {
+	allowed_origins: Vec<String>,
+}
+
+impl CorsPolicy {
+	pub fn new(allowed_origins: Vec<String>) -> CorsPolicy {
+		CorsPolicy {
+			allowed_origins: allowed_origins
+				.iter()
+				.map(|origin| origin.trim_end_matches('/').to_owned())
+				.collect(),
+		}
+	}
+
+	/// The Access-Control-Allow-Origin answered to the request, if its origin
+	/// is allowed.
+	pub fn allow_origin(&self, req: &Request<Body>) -> Option<HeaderValue> {
+		let origin = req.headers().get(header::ORIGIN)?;
+		if self.allowed_origins.iter().any(|o| o == "*") {
+			return Some(HeaderValue::from_static("*"));
+		}
+		let allowed = self
+			.allowed_origins
+			.iter()
+			.any(|o| o.as_bytes() == origin.as_bytes());
+		if allowed {
+			Some(origin.clone())
+		} else {
+			None
+		}
+	}
+
+	/// The answer to the request if it's a preflight request, to be sent
+	/// without reaching the handlers.
+	pub fn preflight(
+		&self,
+		req: &Request<Body>,
+		allow_origin: Option<HeaderValue>,
+	) -> Option<Response<Body>> {
+		let preflight = req.method() == Method::OPTIONS
+			&& req
+				.headers()
+				.contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+		if !preflight {
+			return None;
+		}
+		let mut builder = Response::builder()
+			.status(StatusCode::NO_CONTENT)
+			.header(header::VARY, "Origin");
+		if let Some(origin) = allow_origin {
+			builder = builder
+				.header(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin)
+				.header(header::ACCESS_CONTROL_ALLOW_METHODS, CORS_ALLOWED_METHODS)
+				.header(header::ACCESS_CONTROL_ALLOW_HEADERS, CORS_ALLOWED_HEADERS)
+				.header(header::ACCESS_CONTROL_MAX_AGE, CORS_MAX_AGE_SECS);
+		}
+		Some(builder.body(Body::empty()).unwrap())
+	}
+
+	/// Tag the response with the CORS headers of its request.
+	pub fn tag(fut: ResponseFuture, allow_origin: Option<HeaderValue>) -> ResponseFuture {
+		Box::pin(async move {
+			let mut resp = match fut.await {
+				Ok(resp) => resp,
+				Err(e) => return Err(e),
+			};
+			let headers = resp.headers_mut();
+			// The JSON-RPC apis allow any origin on their own, the policy
+			// overrides that.
+			headers.remove(header::ACCESS_CONTROL_ALLOW_ORIGIN);
+			headers.remove(header::ACCESS_CONTROL_ALLOW_HEADERS);
+			if let Some(origin) = allow_origin {
+				headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+			}
+			headers.append(header::VARY, HeaderValue::from_static("Origin"));
+			Ok(resp)
+		})
+	}
+}
+
+// Counts a request in flight until dropped, whether served or cancelled.
+struct InFlight(Arc<AtomicUsize>);
+
+impl Drop for InFlight {
+	fn drop(&mut self) {
+		self.0.fetch_sub(1, Ordering::SeqCst);
+	}
+}
+
+// Rejects a request, asking the client to retry in a second.
+fn retry_later(status: StatusCode) -> ResponseFuture {
+	let resp = Response::builder()
+		.status(status)
+		.header(hyper::header::RETRY_AFTER, 1)
+		.body(Body::empty())
+		.unwrap();
+	Box::pin(async { Ok(resp) })
+}
@@ -0,0 +1,162 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Per-route request counters and latencies, recorded by the router for
+//! every request it serves.
+
+use crate::types::RouteStatsPrintable;
+use crate::util::RwLock;
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+/// Number of latest request latencies kept per route to compute the
+/// percentiles.
+const LATENCY_SAMPLES: usize = 1_000;
+
+/// Route key of the requests not matching any route.
+pub const UNMATCHED_ROUTE: &str = "unmatched";
+
+#[derive(Default)]
+struct RouteRecord {
+	requests: u64,
+	client_errors: u64,
+	server_errors: u64,
+	// latest latencies in microseconds, oldest first
+	latencies: VecDeque<u64>,
+}
+
+impl RouteRecord {
+	fn record(&mut self, status: u16, latency: Duration) {
+		self.requests += 1;
+		match status {
+			400..=499 => self.client_errors += 1,
+			500..=599 => self.server_errors += 1,
+			_ => {}
+		}
+		if self.latencies.len() == LATENCY_SAMPLES {
+			self.latencies.pop_front();
+		}
+		self.latencies.push_back(latency.as_micros() as u64);
+	}
+
+	fn printable(&self, route: &str) -> RouteStatsPrintable {
+		let mut latencies: Vec<u64> = self.latencies.iter().cloned().collect();
+		latencies.sort_unstable();
+		let percentile = |p: usize| match latencies.len() {
+			0 => 0.0,
+			n => latencies[(n * p / 100).min(n - 1)] as f64 / 1_000.0,
+		};
+		let errors = self.client_errors + self.server_errors;
+		RouteStatsPrintable {
+			route: route.to_owned(),
+			requests: self.requests,
+			client_errors: self.client_errors,
+			server_errors: self.server_errors,
+			error_rate: errors as f64 / self.requests.max(1) as f64,
+			latency_p50_ms: percentile(50),
+			latency_p90_ms: percentile(90),
+			latency_p99_ms: percentile(99),
+		}
+	}
+}
+
+/// Request count, error count and latencies of each route served by a
+/// router, keyed by method and route pattern (e.g. "GET /v1/blocks/*"),
+/// extension methods all recorded as "OTHER".
+#[derive(Default)]
+pub struct RouteStats {
+	routes: RwLock<HashMap<String, RouteRecord>>,
+}
+
+impl RouteStats {
+	pub fn new() -> RouteStats {
+		RouteStats::default()
+	}
+
+	/// Record a request to the route, with the status it got answered with
+	/// and how long it took.
+	pub fn record(&self, route: &str, status: u16, latency: Duration) {
+		let mut routes = self.routes.write();
+		match routes.get_mut(route) {
+			Some(record) => record.record(status, latency),
+			None => {
+				let mut record = RouteRecord::default();
+				record.record(status, latency);
+				routes.insert(route.to_owned(), record);
+			}
+		}
+	}
+
+	/// Stats of all the routes requested so far, most requested first.
+	pub fn snapshot(&self) -> Vec<RouteStatsPrintable> {
+		let mut stats: Vec<_> = self
+			.routes
+			.read()
+			.iter()
+			.map(|(route, record)| record.printable(route))
+			.collect();
+		stats.sort_by(|a, b| b.requests.cmp(&a.requests).then(a.route.cmp(&b.route)));
+		stats
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_route_stats() {
+		let stats = RouteStats::new();
+		assert!(stats.snapshot().is_empty());
+
+		for i in 1..=100 {
+			stats.record("GET /v1/blocks/*", 200, Duration::from_millis(i));
+		}
+		stats.record("GET /v1/blocks/*", 400, Duration::from_millis(1));
+		stats.record("GET /v1/blocks/*", 500, Duration::from_millis(1));
+		stats.record("GET /v1/status", 429, Duration::from_millis(1));
+		stats.record("GET /v1/status", 200, Duration::from_millis(3));
+
+		let snapshot = stats.snapshot();
+		assert_eq!(snapshot.len(), 2);
+		let blocks = &snapshot[0];
+		assert_eq!(blocks.route, "GET /v1/blocks/*");
+		assert_eq!(blocks.requests, 102);
+		assert_eq!(blocks.client_errors, 1);
+		assert_eq!(blocks.server_errors, 1);
+		assert!((blocks.error_rate - 2.0 / 102.0).abs() < 1e-9);
+		assert_eq!(blocks.latency_p50_ms, 50.0);
+		assert_eq!(blocks.latency_p90_ms, 90.0);
+		assert_eq!(blocks.latency_p99_ms, 99.0);
+
+		let status = &snapshot[1];
+		assert_eq!(status.route, "GET /v1/status");
+		assert_eq!(status.requests, 2);
+		assert_eq!(status.error_rate, 0.5);
+		assert_eq!(status.latency_p99_ms, 3.0);
+
+		// Only the latest latencies are kept.
+		for _ in 0..LATENCY_SAMPLES {
+			stats.record("GET /v1/status", 200, Duration::from_millis(7));
+		}
+		let status = stats
+			.snapshot()
+			.into_iter()
+			.find(|s| s.route == "GET /v1/status")
+			.unwrap();
+		assert_eq!(status.requests, 2 + LATENCY_SAMPLES as u64);
+		assert_eq!(status.latency_p50_ms, 7.0);
+		assert_eq!(status.latency_p99_ms, 7.0);
+	}
+}
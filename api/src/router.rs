@@ -12,15 +12,26 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::auth::unauthorized_response;
+use crate::rest::CorsPolicy;
+use crate::route_stats::{RouteStats, UNMATCHED_ROUTE};
 use futures::future::{self, Future};
 use hyper;
+use hyper::body::HttpBody;
+use hyper::header::{HeaderValue, AUTHORIZATION, CONTENT_LENGTH};
 use hyper::service::Service;
 use hyper::{Body, Method, Request, Response, StatusCode};
+use ring::constant_time::verify_slices_are_equal;
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
+use std::iter;
 use std::pin::Pin;
 use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+use tokio::task;
+use tokio::time::timeout;
 
 lazy_static! {
 	static ref WILDCARD_HASH: u64 = calculate_hash(&"*");
@@ -97,9 +108,81 @@ pub enum RouterError {
 	NoValue,
 }
 
+/// Which read-only requests are served without the api secret. Requests
+/// changing the state of the node always need it. Routes are given as
+/// registered, e.g. "/v1/chain" or "/v1/peers/**".
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RouteAuthConfig {
+	/// Serve all the read-only requests without the secret.
+	pub open_reads: bool,
+	/// Routes whose read-only requests are served without the secret.
+	pub open_routes: Vec<String>,
+	/// Routes needing the secret even to read, whatever `open_reads` says.
+	pub protected_routes: Vec<String>,
+}
+
+/// Basic auth on the routes of a router. GET and HEAD requests only read,
+/// as do the requests of any method on a route marked read-only, all the
+/// others are taken as changing the state of the node.
+pub struct RouteAuth {
+	basic_auth: String,
+	basic_realm: &'static HeaderValue,
+	config: RouteAuthConfig,
+	exempt_routes: Vec<String>,
+}
+
+impl RouteAuth {
+	pub fn new(
+		basic_auth: String,
+		basic_realm: &'static HeaderValue,
+		config: RouteAuthConfig,
+	) -> RouteAuth {
+		RouteAuth {
+			basic_auth,
+			basic_realm,
+			config,
+			exempt_routes: vec![],
+		}
+	}
+
+	/// Never ask for the secret on this route, authenticated on its own.
+	pub fn exempt(mut self, route: &str) -> RouteAuth {
+		self.exempt_routes.push(route.to_owned());
+		self
+	}
+
+	fn needs_secret(&self, method: &Method, node: &Node) -> bool {
+		let route = node.route.unwrap_or_default();
+		if *method == Method::OPTIONS || self.exempt_routes.iter().any(|r| r == route) {
+			return false;
+		}
+		let read = match *method {
+			Method::GET | Method::HEAD => true,
+			_ => node.read_only,
+		};
+		if !read || self.config.protected_routes.iter().any(|r| r == route) {
+			return true;
+		}
+		!self.config.open_reads && !self.config.open_routes.iter().any(|r| r == route)
+	}
+
+	fn authorized(&self, req: &Request<Body>) -> bool {
+		match req.headers().get(AUTHORIZATION) {
+			Some(auth) => {
+				verify_slices_are_equal(auth.as_bytes(), self.basic_auth.as_bytes()).is_ok()
+			}
+			None => false,
+		}
+	}
+}
+
 #[derive(Clone)]
 pub struct Router {
 	nodes: Vec<Node>,
+	stats: Option<Arc<RouteStats>>,
+	auth: Option<Arc<RouteAuth>>,
+	cors: Option<Arc<CorsPolicy>>,
+	workers: Option<Arc<Semaphore>>,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -113,6 +196,8 @@ pub type HandlerObj = Arc<dyn Handler + Send + Sync>;
 pub struct Node {
 	key: u64,
 	value: Option<HandlerObj>,
+	route: Option<&'static str>,
+	read_only: bool,
 	children: [NodeId; MAX_CHILDREN],
 	children_count: usize,
 	mws: Option<Vec<HandlerObj>>,
@@ -123,7 +208,47 @@ impl Router {
 		let root = Node::new(calculate_hash(&""), None);
 		let mut nodes = vec![];
 		nodes.push(root);
-		Router { nodes }
+		Router {
+			nodes,
+			stats: None,
+			auth: None,
+			cors: None,
+			workers: None,
+		}
+	}
+
+	/// Handle the requests on at most that many threads of the blocking
+	/// pool of the runtime, the others waiting for one to be free. The
+	/// middlewares still run on the runtime, so the requests they reject
+	/// don't wait, and so does the reading of the request bodies, up to
+	/// MAX_WORKER_BODY_SIZE.
+	pub fn set_workers(&mut self, workers: usize) {
+		self.workers = Some(Arc::new(Semaphore::new(workers)));
+	}
+
+	/// Let the browsers query the api as per the provided CORS policy. Every
+	/// response gets its CORS headers, including the ones answered before
+	/// reaching the middlewares and handlers.
+	pub fn set_cors(&mut self, cors: CorsPolicy) {
+		self.cors = Some(Arc::new(cors));
+	}
+
+	/// Record the count, status and latency of the requests served by each
+	/// route in the provided stats.
+	pub fn set_stats(&mut self, stats: Arc<RouteStats>) {
+		self.stats = Some(stats);
+	}
+
+	/// Ask for the api secret as configured in the provided auth, checked
+	/// before any middleware. To be set once all the routes are added.
+	pub fn set_auth(&mut self, auth: RouteAuth) {
+		let config = &auth.config;
+		for route in config.open_routes.iter().chain(&config.protected_routes) {
+			if !self.nodes.iter().any(|n| n.route == Some(route.as_str())) {
+				warn!("api auth: no route {}, ignored", route);
+			}
+		}
+		self.auth = Some(Arc::new(auth));
 	}
 
 	pub fn add_middleware(&mut self, mw: HandlerObj) {
@@ -175,7 +300,7 @@ impl Router {
 		match self.node(node_id).value() {
 			None => {
 				let node = self.node_mut(node_id);
-				node.set_value(value);
+				node.set_value(route, value);
 				Ok(node)
 			}
 			Some(_) => Err(RouterError::RouteAlreadyExists),
@@ -183,6 +308,11 @@ impl Router {
 	}
 
 	pub fn get(&self, path: &str) -> Result<impl Iterator<Item = HandlerObj>, RouterError> {
+		self.lookup(path).map(|(handlers, _)| handlers.into_iter())
+	}
+
+	// Handlers of the path along with the node of the route it matched.
+	fn lookup(&self, path: &str) -> Result<(Vec<HandlerObj>, &Node), RouterError> {
 		let keys = generate_path(path);
 		let mut handlers = vec![];
 		let mut node_id = self.root();
@@ -196,15 +326,100 @@ impl Router {
 			}
 		}
 
-		if let Some(h) = self.node(node_id).value() {
-			handlers.push(h);
-			Ok(handlers.into_iter())
-		} else {
-			Err(RouterError::NoValue)
+		let node = self.node(node_id);
+		match node.value() {
+			Some(h) if node.route.is_some() => {
+				match self.workers {
+					Some(ref workers) => handlers.push(Arc::new(OnWorker {
+						handler: h,
+						workers: workers.clone(),
+					})),
+					None => handlers.push(h),
+				}
+				Ok((handlers, node))
+			}
+			_ => Err(RouterError::NoValue),
 		}
 	}
 }
 
+/// Largest request body read for a handler running on a worker.
+pub const MAX_WORKER_BODY_SIZE: usize = 16 * 1024 * 1024;
+
+/// Time a client has to send the body of a request before it's dropped,
+/// for a handler running on a worker.
+pub const WORKER_BODY_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Runs the handler of a route on a worker thread, once one is free. The
+// body of the request is read on the runtime beforehand, so a slow client
+// doesn't hold a worker, and its future is driven on the worker, as the
+// JSON-RPC handlers only get to work once the body is read.
+struct OnWorker {
+	handler: HandlerObj,
+	workers: Arc<Semaphore>,
+}
+
+impl Handler for OnWorker {
+	fn call(
+		&self,
+		req: Request<Body>,
+		_handlers: Box<dyn Iterator<Item = HandlerObj>>,
+	) -> ResponseFuture {
+		let handler = self.handler.clone();
+		let workers = self.workers.clone();
+		Box::pin(async move {
+			let (parts, body) = req.into_parts();
+			let length = parts
+				.headers
+				.get(CONTENT_LENGTH)
+				.and_then(|l| l.to_str().ok())
+				.and_then(|l| l.parse::<usize>().ok());
+			if length.map_or(false, |l| l > MAX_WORKER_BODY_SIZE) {
+				return Ok(status_response(StatusCode::PAYLOAD_TOO_LARGE));
+			}
+			let body = match timeout(WORKER_BODY_TIMEOUT, read_body(body)).await {
+				Ok(Ok(Some(body))) => body,
+				Ok(Ok(None)) => return Ok(status_response(StatusCode::PAYLOAD_TOO_LARGE)),
+				Ok(Err(e)) => return Err(e),
+				Err(_) => return Ok(status_response(StatusCode::REQUEST_TIMEOUT)),
+			};
+			let req = Request::from_parts(parts, Body::from(body));
+
+			let _permit = workers.acquire().await;
+			let res = task::spawn_blocking(move || {
+				futures::executor::block_on(handler.call(req, Box::new(iter::empty())))
+			})
+			.await;
+			match res {
+				Ok(res) => res,
+				Err(e) => {
+					error!("API handler failed: {}", e);
+					Ok(status_response(StatusCode::INTERNAL_SERVER_ERROR))
+				}
+			}
+		})
+	}
+}
+
+// The whole body, None if larger than MAX_WORKER_BODY_SIZE.
+async fn read_body(mut body: Body) -> Result<Option<Vec<u8>>, hyper::Error> {
+	let mut bytes = vec![];
+	while let Some(chunk) = body.data().await {
+		let chunk = chunk?;
+		if bytes.len() + chunk.len() > MAX_WORKER_BODY_SIZE {
+			return Ok(None);
+		}
+		bytes.extend_from_slice(&chunk);
+	}
+	Ok(Some(bytes))
+}
+
+fn status_response(status: StatusCode) -> Response<Body> {
+	let mut response = Response::new(Body::empty());
+	*response.status_mut() = status;
+	response
+}
+
 impl Service<Request<Body>> for Router {
 	type Response = Response<Body>;
 	type Error = hyper::Error;
@@ -215,12 +430,55 @@ impl Service<Request<Body>> for Router {
 	}
 
 	fn call(&mut self, req: Request<Body>) -> Self::Future {
-		match self.get(req.uri().path()) {
-			Err(_) => not_found(),
-			Ok(mut handlers) => match handlers.next() {
-				None => not_found(),
-				Some(h) => h.call(req, Box::new(handlers)),
-			},
+		let start = Instant::now();
+		let allow_origin = self.cors.as_ref().and_then(|cors| cors.allow_origin(&req));
+		let mut preflight = self
+			.cors
+			.as_ref()
+			.and_then(|cors| cors.preflight(&req, allow_origin.clone()));
+		let (route, fut) = match self.lookup(req.uri().path()) {
+			Err(_) => (None, not_found()),
+			Ok((handlers, node)) => {
+				let route = format!(
+					"{} {}",
+					method_key(req.method()),
+					node.route.unwrap_or_default()
+				);
+				// Realm to answer with when the secret is needed but missing.
+				let denied = self
+					.auth
+					.as_ref()
+					.filter(|auth| auth.needs_secret(req.method(), node) && !auth.authorized(&req))
+					.map(|auth| auth.basic_realm);
+				let mut handlers = handlers.into_iter();
+				match (handlers.next(), denied, preflight.take()) {
+					(None, _, _) => (None, not_found()),
+					// Browsers don't send the secret along with a preflight.
+					(Some(_), _, Some(resp)) => {
+						let fut: ResponseFuture = Box::pin(future::ok(resp));
+						(Some(route), fut)
+					}
+					(Some(_), Some(realm), None) => (Some(route), unauthorized_response(realm)),
+					(Some(h), None, None) => (Some(route), h.call(req, Box::new(handlers))),
+				}
+			}
+		};
+		let fut = match self.cors {
+			Some(_) => CorsPolicy::tag(fut, allow_origin),
+			None => fut,
+		};
+		match self.stats.clone() {
+			None => fut,
+			Some(stats) => Box::pin(async move {
+				let res = fut.await;
+				let status = match res {
+					Ok(ref resp) => resp.status().as_u16(),
+					Err(_) => StatusCode::INTERNAL_SERVER_ERROR.as_u16(),
+				};
+				let route = route.as_ref().map_or(UNMATCHED_ROUTE, |r| r.as_str());
+				stats.record(route, status, start.elapsed());
+				res
+			}),
 		}
 	}
 }
@@ -230,6 +488,8 @@ impl Node {
 		Node {
 			key,
 			value,
+			route: None,
+			read_only: false,
 			children: [NodeId(0); MAX_CHILDREN],
 			children_count: 0,
 			mws: None,
@@ -246,6 +506,13 @@ impl Node {
 		self
 	}
 
+	/// Requests of any method on this route only query the node, they
+	/// don't change its state.
+	pub fn read_only(&mut self) -> &mut Node {
+		self.read_only = true;
+		self
+	}
+
 	fn value(&self) -> Option<HandlerObj> {
 		match &self.value {
 			None => None,
@@ -253,8 +520,9 @@ impl Node {
 		}
 	}
 
-	fn set_value(&mut self, value: HandlerObj) {
+	fn set_value(&mut self, route: &'static str, value: HandlerObj) {
 		self.value = Some(value);
+		self.route = Some(route);
 	}
 
 	fn add_child(&mut self, child_id: NodeId) {
@@ -266,6 +534,23 @@ impl Node {
 	}
 }
 
+// The method a route is recorded under, the ones not served by a handler
+// all under the same key so clients can't grow the stats at will.
+fn method_key(method: &Method) -> &'static str {
+	match *method {
+		Method::GET => "GET",
+		Method::POST => "POST",
+		Method::PUT => "PUT",
+		Method::DELETE => "DELETE",
+		Method::PATCH => "PATCH",
+		Method::OPTIONS => "OPTIONS",
+		Method::CONNECT => "CONNECT",
+		Method::TRACE => "TRACE",
+		Method::HEAD => "HEAD",
+		_ => "OTHER",
+	}
+}
+
 pub fn not_found() -> ResponseFuture {
 	let mut response = Response::new(Body::empty());
 	*response.status_mut() = StatusCode::NOT_FOUND;
@@ -298,7 +583,10 @@ fn collect_node_middleware(handlers: &mut Vec<HandlerObj>, node: &Node) {
 mod tests {
 
 	use super::*;
+	use crate::auth::KEPLER_BASIC_REALM;
+	use crate::util::Mutex;
 	use futures::executor::block_on;
+	use std::sync::mpsc;
 
 	struct HandlerImpl(u16);
 
@@ -375,4 +663,182 @@ mod tests {
 		assert_eq!(call_handler("/v1/zzz/2"), 103);
 		assert_eq!(call_handler("/v1/zzz/2/zzz"), 106);
 	}
+
+	// Answers once told to, blocking its thread meanwhile.
+	struct BlockingHandler(Mutex<mpsc::Receiver<()>>);
+
+	impl Handler for BlockingHandler {
+		fn get(&self, _req: Request<Body>) -> ResponseFuture {
+			self.0.lock().recv().unwrap();
+			Box::pin(future::ok(Response::new(Body::default())))
+		}
+	}
+
+	#[test]
+	fn test_workers() {
+		let (tx, rx) = mpsc::channel();
+		let mut routes = Router::new();
+		routes
+			.add_route("/v1/slow", Arc::new(BlockingHandler(Mutex::new(rx))))
+			.unwrap();
+		routes
+			.add_route("/v1/fast", Arc::new(HandlerImpl(200)))
+			.unwrap();
+		routes.set_workers(2);
+
+		// A single threaded runtime, the slow handler would block it.
+		let mut rt = tokio::runtime::Builder::new()
+			.basic_scheduler()
+			.enable_all()
+			.build()
+			.unwrap();
+		let get = |url: &str| Request::get(url).body(Body::default()).unwrap();
+		rt.block_on(async {
+			let slow = tokio::spawn(routes.clone().call(get("/v1/slow")));
+			let fast = routes.clone().call(get("/v1/fast")).await.unwrap();
+			assert_eq!(fast.status().as_u16(), 200);
+			tx.send(()).unwrap();
+			let slow = slow.await.unwrap().unwrap();
+			assert_eq!(slow.status().as_u16(), 200);
+		});
+	}
+
+	#[test]
+	fn test_workers_slow_body() {
+		let mut routes = Router::new();
+		routes
+			.add_route("/v1/fast", Arc::new(HandlerImpl(200)))
+			.unwrap();
+		routes.set_workers(1);
+
+		let mut rt = tokio::runtime::Builder::new()
+			.basic_scheduler()
+			.enable_all()
+			.build()
+			.unwrap();
+		rt.block_on(async {
+			// A client never done sending its body doesn't hold the worker.
+			let (_sender, body) = Body::channel();
+			let req = Request::get("/v1/fast").body(body).unwrap();
+			let _slow = tokio::spawn(routes.clone().call(req));
+			let req = Request::get("/v1/fast").body(Body::default()).unwrap();
+			let fast = routes.clone().call(req).await.unwrap();
+			assert_eq!(fast.status(), StatusCode::OK);
+
+			// Nor is a body too large read.
+			let req = Request::get("/v1/fast")
+				.header(CONTENT_LENGTH, MAX_WORKER_BODY_SIZE + 1)
+				.body(Body::default())
+				.unwrap();
+			let res = routes.clone().call(req).await.unwrap();
+			assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+		});
+	}
+
+	#[test]
+	fn test_route_stats() {
+		let mut routes = Router::new();
+		routes
+			.add_route("/v1/users", Arc::new(HandlerImpl(200)))
+			.unwrap();
+		routes
+			.add_route("/v1/zzz/*", Arc::new(HandlerImpl(500)))
+			.unwrap();
+		let stats = Arc::new(RouteStats::new());
+		routes.set_stats(stats.clone());
+
+		let mut call = |url: &str| {
+			let req = Request::get(url).body(Body::default()).unwrap();
+			block_on(routes.call(req)).unwrap().status().as_u16()
+		};
+		assert_eq!(call("/v1/users"), 200);
+		assert_eq!(call("/v1/users"), 200);
+		assert_eq!(call("/v1/zzz/1"), 500);
+		assert_eq!(call("/v1/zzz/2"), 500);
+		assert_eq!(call("/v1/zzz/3"), 500);
+		assert_eq!(call("/v1/nothing"), 404);
+
+		// Extension methods all count as one.
+		for method in &["FOO", "BAR"] {
+			let req = Request::builder()
+				.method(*method)
+				.uri("/v1/users")
+				.body(Body::default())
+				.unwrap();
+			assert_eq!(block_on(routes.call(req)).unwrap().status(), 404);
+		}
+
+		let snapshot = stats.snapshot();
+		let routes: Vec<_> = snapshot
+			.iter()
+			.map(|s| (s.route.as_str(), s.requests, s.server_errors))
+			.collect();
+		assert_eq!(
+			routes,
+			vec![
+				("GET /v1/zzz/*", 3, 3),
+				("GET /v1/users", 2, 0),
+				("OTHER /v1/users", 2, 0),
+				(UNMATCHED_ROUTE, 1, 0),
+			]
+		);
+		assert_eq!(snapshot[3].client_errors, 1);
+	}
+
+	#[test]
+	fn test_route_auth() {
+		let mut routes = Router::new();
+		routes
+			.add_route("/v1/chain", Arc::new(HandlerImpl(200)))
+			.unwrap();
+		routes
+			.add_route("/v1/peers/**", Arc::new(HandlerImpl(200)))
+			.unwrap();
+		routes
+			.add_route("/v1/outputs/batch", Arc::new(HandlerImpl(200)))
+			.unwrap()
+			.read_only();
+		routes
+			.add_route("/v2/foreign", Arc::new(HandlerImpl(200)))
+			.unwrap();
+		let config = RouteAuthConfig {
+			open_reads: false,
+			open_routes: vec!["/v1/chain".to_owned()],
+			protected_routes: vec![],
+		};
+		routes.set_auth(
+			RouteAuth::new("Basic secret".to_owned(), &KEPLER_BASIC_REALM, config)
+				.exempt("/v2/foreign"),
+		);
+
+		let mut call = |method: Method, url: &str, auth: Option<&str>| {
+			let mut req = Request::builder().method(method).uri(url);
+			if let Some(auth) = auth {
+				req = req.header(AUTHORIZATION, auth);
+			}
+			let req = req.body(Body::default()).unwrap();
+			block_on(routes.call(req)).unwrap().status().as_u16()
+		};
+		// Reads are open on the listed route only.
+		assert_eq!(call(Method::GET, "/v1/chain", None), 200);
+		assert_eq!(call(Method::GET, "/v1/peers/a.b.c.d", None), 401);
+		assert_eq!(
+			call(Method::GET, "/v1/peers/a.b.c.d", Some("Basic secret")),
+			200
+		);
+		assert_eq!(call(Method::OPTIONS, "/v1/peers/a.b.c.d", None), 404);
+
+		// Writes always need the secret, no handler for them past it.
+		assert_eq!(call(Method::POST, "/v1/chain", None), 401);
+		assert_eq!(call(Method::POST, "/v1/chain", Some("Basic wrong")), 401);
+		assert_eq!(call(Method::POST, "/v1/chain", Some("Basic secret")), 404);
+
+		// Queries through POST are reads.
+		assert_eq!(call(Method::POST, "/v1/outputs/batch", None), 401);
+		assert_eq!(
+			call(Method::POST, "/v1/outputs/batch", Some("Basic secret")),
+			404
+		);
+		assert_eq!(call(Method::POST, "/v2/foreign", None), 404);
+	}
 }
@@ -12,14 +12,17 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use crate::chain;
+use crate::core::consensus::MAX_BLOCK_WEIGHT;
 use crate::core::core::hash::Hashed;
 use crate::core::core::merkle_proof::MerkleProof;
 use crate::core::core::{KernelFeatures, TxKernel};
 use crate::core::{core, ser};
 use crate::p2p;
+use crate::pool;
 use crate::util;
 use crate::util::secp::pedersen;
 use serde;
@@ -68,6 +71,51 @@ impl Tip {
 	}
 }
 
+/// A reorg of the chain, the blocks taken off it having to be forgotten
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Reorg {
+	/// When the reorg happened (seconds since epoch)
+	pub timestamp: i64,
+	/// Chain head before the reorg
+	pub old_tip: Tip,
+	/// Chain head after the reorg
+	pub new_tip: Tip,
+	/// Hash of the last block both chains have in common
+	pub fork_point: String,
+	/// Number of blocks taken off the chain
+	pub depth: u64,
+	/// Hashes of the blocks taken off the chain, highest first
+	pub disconnected: Vec<String>,
+}
+
+impl Reorg {
+	pub fn from_reorg(reorg: chain::ReorgRecord) -> Reorg {
+		Reorg {
+			timestamp: reorg.timestamp,
+			old_tip: Tip::from_tip(reorg.old_tip),
+			new_tip: Tip::from_tip(reorg.new_tip),
+			fork_point: reorg.fork_point.to_hex(),
+			depth: reorg.depth,
+			disconnected: reorg.disconnected.iter().map(|h| h.to_hex()).collect(),
+		}
+	}
+}
+
+/// State of a chain validation job
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ValidationJob {
+	/// Id the job is polled by
+	pub id: u64,
+	/// "running", "done" or "failed"
+	pub state: String,
+	/// What is being validated: "setup", "mmrs", "rangeproofs" or "kernels"
+	pub stage: String,
+	/// Overall progress (percent)
+	pub progress: u8,
+	/// Why the validation failed, if it did
+	pub error: Option<String>,
+}
+
 /// Status page containing different server information
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Status {
@@ -84,6 +132,15 @@ pub struct Status {
 	// Additional sync information
 	#[serde(skip_serializing_if = "Option::is_none")]
 	pub sync_info: Option<serde_json::Value>,
+	// Seconds the network time estimated from the peers clocks is ahead of
+	// ours, when enough peers sent their clock
+	pub network_time_offset: Option<i64>,
+	// Whether we have less outbound peers than the configured minimum
+	#[serde(default)]
+	pub insufficient_peers: bool,
+	// The blocks waiting for their parent
+	#[serde(default)]
+	pub orphans: OrphanCounts,
 }
 
 impl Status {
@@ -92,6 +149,9 @@ impl Status {
 		connections: u32,
 		sync_status: String,
 		sync_info: Option<serde_json::Value>,
+		network_time_offset: Option<i64>,
+		insufficient_peers: bool,
+		orphans: OrphanCounts,
 	) -> Status {
 		Status {
 			protocol_version: ser::ProtocolVersion::local().into(),
@@ -100,10 +160,106 @@ impl Status {
 			tip: Tip::from_tip(current_tip),
 			sync_status,
 			sync_info,
+			network_time_offset,
+			insufficient_peers,
+			orphans,
 		}
 	}
 }
 
+/// Number of blocks in the orphan pool, and evicted from it
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct OrphanCounts {
+	/// Blocks waiting for their parent
+	pub blocks: usize,
+	/// Most blocks the pool holds
+	pub max_blocks: usize,
+	/// Blocks evicted since the node started, for any reason
+	pub evicted: usize,
+	/// Evicted for being too old
+	pub evicted_expired: usize,
+	/// Evicted for being too far ahead of the chain head
+	pub evicted_too_far: usize,
+	/// Evicted for the pool being full
+	pub evicted_full: usize,
+}
+
+impl OrphanCounts {
+	pub fn from_stats(stats: &chain::OrphanPoolStats) -> OrphanCounts {
+		OrphanCounts {
+			blocks: stats.len,
+			max_blocks: stats.max_orphans,
+			evicted: stats.evicted(),
+			evicted_expired: stats.evicted_expired,
+			evicted_too_far: stats.evicted_too_far,
+			evicted_full: stats.evicted_full,
+		}
+	}
+}
+
+/// A block waiting in the orphan pool for its parent
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OrphanBlock {
+	/// Hash of the block
+	pub hash: String,
+	/// Height of the block
+	pub height: u64,
+	/// Hash of the missing parent
+	pub previous: String,
+	/// Number of inputs
+	pub inputs: usize,
+	/// Number of outputs
+	pub outputs: usize,
+	/// Number of kernels
+	pub kernels: usize,
+	/// When the block was received, RFC3339
+	pub arrived: String,
+	/// Address of the peer the block was received from, if any
+	pub peer: Option<String>,
+}
+
+impl OrphanBlock {
+	pub fn from_orphan(orphan: &chain::OrphanInfo) -> OrphanBlock {
+		OrphanBlock {
+			hash: orphan.header.hash().to_hex(),
+			height: orphan.header.height,
+			previous: orphan.header.prev_hash.to_hex(),
+			inputs: orphan.inputs,
+			outputs: orphan.outputs,
+			kernels: orphan.kernels,
+			arrived: orphan.arrived.to_rfc3339(),
+			peer: orphan.peer.clone(),
+		}
+	}
+}
+
+/// Health of the node, as reported to the liveness and readiness probes
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Health {
+	/// Whether the probe passed
+	pub healthy: bool,
+	/// Why the probe failed, if it did
+	pub failures: Vec<String>,
+	/// The current sync status
+	pub sync_status: String,
+	/// Whether the node is syncing
+	pub syncing: bool,
+	/// Height of the chain head
+	pub height: u64,
+	/// Height of the most-work header chain
+	pub header_height: u64,
+	/// Blocks the chain head is behind the most-work header chain
+	pub blocks_behind: u64,
+	/// Whether the chain data can be written to, as last probed
+	pub db_writable: bool,
+	/// Whether the chain was opened read-only on purpose
+	pub read_only: bool,
+	/// The current number of connections
+	pub connections: u32,
+	/// Whether we have less outbound peers than the configured minimum
+	pub insufficient_peers: bool,
+}
+
 /// TxHashSet
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TxHashSet {
@@ -198,6 +354,207 @@ impl Output {
 	}
 }
 
+/// Commitments of the outputs to look up in a single request.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OutputLookupRequest {
+	pub commits: Vec<String>,
+}
+
+/// Whether a looked up output is in the UTXO set. Outputs spent by a block
+/// compacted away (never on archive nodes) are unknown like outputs never
+/// seen.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum OutputStatus {
+	Unspent,
+	Spent,
+	Unknown,
+}
+
+/// Result of an output lookup by commitment.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OutputLookup {
+	/// The output commitment, as provided
+	pub commit: String,
+	/// Whether the output is unspent
+	pub status: OutputStatus,
+	/// Height of the block which contains the output, if known
+	pub height: Option<u64>,
+	/// MMR Index of the output, if known
+	pub mmr_index: Option<u64>,
+}
+
+/// Result of an output lookup by commitment, along with the block holding
+/// the output.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OutputLookupV2 {
+	/// The output commitment, as provided
+	pub commit: String,
+	/// Whether the output is unspent
+	pub status: OutputStatus,
+	/// The type of output Coinbase|Transaction, if unspent
+	pub output_type: Option<OutputType>,
+	/// Height of the block which contains the output, if known
+	pub height: Option<u64>,
+	/// Hash of the block which contains the output, if known
+	pub block_hash: Option<String>,
+	/// MMR Index of the output, if known
+	pub mmr_index: Option<u64>,
+}
+
+/// Where an output stands once the transactions of the pool are accounted
+/// for, along with its on chain status.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum MergedOutputStatus {
+	/// Unspent on chain and not spent by a pool transaction
+	Unspent,
+	/// Created by a pool transaction, not on chain yet
+	PoolCreated,
+	/// Spent by a pool transaction, unspent on chain or created in the pool
+	PoolSpent,
+	/// Neither unspent on chain nor in the pool
+	Unknown,
+}
+
+/// Status of an output merging the chain and the transaction pool, so
+/// wallets can tell pending balances in a single call.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MergedOutput {
+	/// The output commitment, as provided
+	pub commit: String,
+	/// Where the output stands
+	pub status: MergedOutputStatus,
+	/// Height of the block which contains the output, if unspent on chain
+	pub height: Option<u64>,
+	/// MMR Index of the output, if unspent on chain
+	pub mmr_index: Option<u64>,
+	/// Hash of the pool transaction creating the output, if any
+	pub created_by: Option<String>,
+	/// Hash of the pool transaction spending the output, if any
+	pub spent_by: Option<String>,
+}
+
+/// Where a transaction stands, looked up by kernel excess or tx hash.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum TxStatus {
+	/// In the stempool, being relayed along its Dandelion stem
+	Stem,
+	/// In the txpool, broadcast and waiting to be mined
+	Fluff,
+	/// Its kernel is in a block of the chain
+	Confirmed,
+	/// Neither in the pool nor on chain
+	Unknown,
+}
+
+/// Status of a transaction merging the chain with the transaction pool.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TxStatusLookup {
+	/// The kernel excess or tx hash, as provided
+	pub id: String,
+	/// Where the transaction stands
+	pub status: TxStatus,
+	/// Hash of the pool transaction holding the kernel, if in the pool
+	pub tx_hash: Option<String>,
+	/// Height of the block holding the kernel, if confirmed
+	pub height: Option<u64>,
+	/// Hash of the block holding the kernel, if confirmed
+	pub block_hash: Option<String>,
+	/// Blocks on top of the kernel, that block included, if confirmed
+	pub confirmations: Option<u64>,
+}
+
+/// Excesses of the kernels to search in a single request, optionally
+/// restricted to the blocks of a height range.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct KernelSearchRequest {
+	pub excesses: Vec<String>,
+	#[serde(default)]
+	pub min_height: Option<u64>,
+	#[serde(default)]
+	pub max_height: Option<u64>,
+}
+
+/// Result of a kernel search by excess.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct KernelLookup {
+	/// The kernel excess, as provided
+	pub excess: String,
+	/// Height of the block which contains the kernel, if found
+	pub height: Option<u64>,
+	/// MMR Index of the kernel, if found
+	pub mmr_index: Option<u64>,
+}
+
+/// Rangeproof of an unspent output, along with its position in the output
+/// MMR at the chain tip it was looked up at.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OutputRangeProof {
+	/// The output commitment (as hex string)
+	pub commit: String,
+	/// Rangeproof (as hex string)
+	pub proof: String,
+	/// Rangeproof hash (as hex string)
+	pub proof_hash: String,
+	/// Height of the block which contains the output
+	pub height: u64,
+	/// MMR Index of the output
+	pub mmr_index: u64,
+	/// Height of the chain tip the output was looked up at
+	pub tip_height: u64,
+	/// Hash of the chain tip the output was looked up at
+	pub tip_hash: String,
+}
+
+/// Merkle proof of an unspent output being in the output MMR committed to
+/// by the output root of a block header.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OutputMerkleProof {
+	/// The output commitment (as hex string)
+	pub commit: String,
+	/// Output type, the features the MMR leaf is hashed with
+	pub output_type: OutputType,
+	/// MMR Index of the output
+	pub mmr_index: u64,
+	/// Height of the header the proof is against
+	pub header_height: u64,
+	/// Hash of the header the proof is against
+	pub header_hash: String,
+	/// Output root of the header (as hex string)
+	pub output_root: String,
+	/// Merkle proof, its binary serialization (as hex string)
+	pub merkle_proof: String,
+}
+
+/// Unspent output whose rangeproof rewinds with the rewind hash a wallet
+/// scanned with, a candidate for the wallet to restore.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ScannedOutput {
+	/// The output commitment (as hex string)
+	pub commit: String,
+	/// Output type, coinbase or transaction
+	pub output_type: OutputType,
+	/// Amount rewound from the rangeproof
+	pub amount: u64,
+	/// Message rewound from the rangeproof, holding the key derivation path
+	/// (as hex string)
+	pub proof_message: String,
+	/// MMR Index of the output
+	pub mmr_index: u64,
+	/// Height of the block the output was created in
+	pub height: u64,
+}
+
+/// Outputs found by a scan of the UTXO set, a page at a time.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OutputScan {
+	/// The last available output index
+	pub highest_index: u64,
+	/// The last insertion index scanned, to continue the scan from
+	pub last_retrieved_index: u64,
+	/// The outputs found
+	pub outputs: Vec<ScannedOutput>,
+}
+
 #[derive(Debug, Clone)]
 pub struct PrintableCommitment {
 	pub commit: pedersen::Commitment,
@@ -572,6 +929,14 @@ pub struct BlockHeaderPrintable {
 	pub secondary_scaling: u32,
 	/// Total kernel offset since genesis block
 	pub total_kernel_offset: String,
+	/// Serialized header up to the proof of work (hex), the bytes miners
+	/// hash along with the nonce, as in stratum jobs
+	#[serde(default)]
+	pub pre_pow: String,
+	/// Hash of the block following this one on the main chain, None for the
+	/// head or a block on a fork
+	#[serde(default)]
+	pub next: Option<String>,
 }
 
 impl BlockHeaderPrintable {
@@ -592,8 +957,36 @@ impl BlockHeaderPrintable {
 			total_difficulty: header.pow.total_difficulty.to_num(),
 			secondary_scaling: header.pow.secondary_scaling,
 			total_kernel_offset: header.total_kernel_offset.to_hex(),
+			pre_pow: pre_pow_hex(header),
+			next: None,
 		}
 	}
+
+	/// Printable header along with the hash of the next block on the main
+	/// chain, to link to it.
+	pub fn from_header_in_chain(
+		header: &core::BlockHeader,
+		chain: &chain::Chain,
+	) -> BlockHeaderPrintable {
+		let mut printable = BlockHeaderPrintable::from_header(header);
+		printable.next = chain
+			.get_header_by_height(header.height + 1)
+			.ok()
+			.filter(|next| next.prev_hash == header.hash())
+			.map(|next| util::to_hex(next.hash().to_vec()));
+		printable
+	}
+}
+
+// Serialized header up to the nonce, as sent to miners in stratum jobs.
+fn pre_pow_hex(header: &core::BlockHeader) -> String {
+	let mut header_buf = vec![];
+	{
+		let mut writer = ser::BinWriter::default(&mut header_buf);
+		header.write_pre_pow(&mut writer).unwrap();
+		header.pow.write_pre_pow(&mut writer).unwrap();
+	}
+	util::to_hex(header_buf)
 }
 
 // Printable representation of a block
@@ -607,6 +1000,12 @@ pub struct BlockPrintable {
 	pub outputs: Vec<OutputPrintable>,
 	/// A printable version of the transaction kernels
 	pub kernels: Vec<TxKernelPrintable>,
+	/// Consensus weight of the block body
+	#[serde(default)]
+	pub weight: u64,
+	/// Size of the serialized block in bytes
+	#[serde(default)]
+	pub size: u64,
 }
 
 impl BlockPrintable {
@@ -640,15 +1039,91 @@ impl BlockPrintable {
 			.iter()
 			.map(|kernel| TxKernelPrintable::from_txkernel(kernel))
 			.collect();
+		let weight = core::TransactionBody::weight_as_block(
+			block.inputs().len(),
+			block.outputs().len(),
+			block.kernels().len(),
+		);
+		let size = ser::ser_vec(block, ser::ProtocolVersion::local())
+			.map_err(chain::ErrorKind::SerErr)?
+			.len();
 		Ok(BlockPrintable {
-			header: BlockHeaderPrintable::from_header(&block.header),
+			header: BlockHeaderPrintable::from_header_in_chain(&block.header, &chain),
 			inputs: inputs,
 			outputs: outputs,
 			kernels: kernels,
+			weight: weight as u64,
+			size: size as u64,
 		})
 	}
 }
 
+/// A full block in its binary wire serialization, hex encoded. Lets another
+/// node fetch blocks over the api and validate them as if received over p2p.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RawBlock {
+	/// Hash of the block
+	pub hash: String,
+	/// Height of the block
+	pub height: u64,
+	/// Protocol version used to serialize the block
+	pub version: u32,
+	/// Hex encoded serialized block
+	pub data: String,
+}
+
+impl RawBlock {
+	pub fn from_block(
+		block: &core::Block,
+		version: ser::ProtocolVersion,
+	) -> Result<RawBlock, ser::Error> {
+		let data = ser::ser_vec(block, version)?;
+		Ok(RawBlock {
+			hash: util::to_hex(block.hash().to_vec()),
+			height: block.header.height,
+			version: version.0,
+			data: util::to_hex(data),
+		})
+	}
+
+	/// Deserialize the block, the caller is responsible for validating it.
+	pub fn to_block(&self) -> Result<core::Block, ser::Error> {
+		let data = util::from_hex(self.data.clone())
+			.map_err(|e| ser::Error::HexError(format!("{}", e)))?;
+		ser::deserialize(&mut &data[..], ser::ProtocolVersion(self.version))
+	}
+}
+
+/// Root of the header MMR at the chain head, committing to all the headers
+/// up to the head. It is the prev_root of the next block.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HeaderRoot {
+	/// Height of the chain head
+	pub height: u64,
+	/// Hash of the chain head
+	pub hash: String,
+	/// Size of the header MMR
+	pub mmr_size: u64,
+	/// Root of the header MMR (as hex string)
+	pub header_root: String,
+}
+
+/// Merkle proof of a header being included in the header MMR at the chain
+/// head, verified with core::core::merkle_proof::verify_header_inclusion.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HeaderInclusionProof {
+	/// Height of the header
+	pub height: u64,
+	/// Hash of the header
+	pub hash: String,
+	/// Hex encoded serialized header, the MMR leaf is hashed from it
+	pub header: String,
+	/// The root and the chain head it was computed at
+	pub root: HeaderRoot,
+	/// Merkle proof, its binary serialization (as hex string)
+	pub merkle_proof: String,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct CompactBlockPrintable {
 	/// The block header
@@ -682,7 +1157,7 @@ impl CompactBlockPrintable {
 			.map(|x| TxKernelPrintable::from_txkernel(x))
 			.collect();
 		Ok(CompactBlockPrintable {
-			header: BlockHeaderPrintable::from_header(&cb.header),
+			header: BlockHeaderPrintable::from_header_in_chain(&cb.header, &chain),
 			out_full,
 			kern_full,
 			kern_ids: cb.kern_ids().iter().map(|x| x.to_hex()).collect(),
@@ -700,6 +1175,24 @@ pub struct BlockOutputs {
 	pub outputs: Vec<OutputPrintable>,
 }
 
+// For paging through a range of blocks of the main chain
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BlockListing {
+	/// Height the next page starts at, None on the last page
+	pub next_height: Option<u64>,
+	/// The blocks of this page, by increasing height
+	pub blocks: Vec<BlockPrintable>,
+}
+
+// For paging through a range of headers of the main chain
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HeaderListing {
+	/// Height the next page starts at, None on the last page
+	pub next_height: Option<u64>,
+	/// The headers of this page, by increasing height
+	pub headers: Vec<BlockHeaderPrintable>,
+}
+
 // For traversing all outputs in the UTXO set
 // transactions in the block
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -717,6 +1210,23 @@ pub struct LocatedTxKernel {
 	pub tx_kernel: TxKernel,
 	pub height: u64,
 	pub mmr_index: u64,
+	/// Hash of the block the kernel was included in, on single kernel lookups
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub block_hash: Option<String>,
+	/// When the node first saw the kernel, in a transaction or a block
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub first_seen: Option<String>,
+}
+
+// For paging through the kernels of a range of blocks
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct KernelListing {
+	/// The last kernel MMR index in the requested range of blocks
+	pub highest_index: u64,
+	/// The last kernel MMR index retrieved
+	pub last_retrieved_index: u64,
+	/// The kernels along with the height of their block
+	pub kernels: Vec<LocatedTxKernel>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -725,6 +1235,581 @@ pub struct PoolInfo {
 	pub pool_size: usize,
 }
 
+/// Transaction of the pool with its kernels, linked to the other pool
+/// transactions it spends outputs of or has outputs spent by
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PoolTxPrintable {
+	/// Hash of the transaction
+	pub tx_hash: String,
+	/// Whether the transaction is in the stempool or the txpool
+	pub status: TxStatus,
+	/// How the transaction reached the pool
+	pub source: pool::TxSource,
+	/// Kernels of the transaction
+	pub kernels: Vec<TxKernelPrintable>,
+	/// Fee of the transaction
+	pub fee: u64,
+	/// Weight of the transaction
+	pub weight: usize,
+	/// Number of inputs of the transaction
+	pub inputs: usize,
+	/// Number of outputs of the transaction
+	pub outputs: usize,
+	/// When the transaction entered the pool
+	pub tx_at: String,
+	/// Hashes of the pool transactions creating outputs this one spends
+	pub depends_on: Vec<String>,
+	/// Hashes of the pool transactions spending outputs of this one
+	pub spent_by: Vec<String>,
+}
+
+impl PoolTxPrintable {
+	/// The printable pool entries, each linked to the others of the list.
+	pub fn from_entries(entries: &[(&pool::PoolEntry, TxStatus)]) -> Vec<PoolTxPrintable> {
+		let hashes: Vec<String> = entries
+			.iter()
+			.map(|(entry, _)| entry.tx.hash().to_hex())
+			.collect();
+		let mut created_by = HashMap::new();
+		for (i, (entry, _)) in entries.iter().enumerate() {
+			for output in entry.tx.outputs() {
+				created_by.insert(output.commitment(), i);
+			}
+		}
+		let mut depends_on = vec![vec![]; entries.len()];
+		let mut spent_by = vec![vec![]; entries.len()];
+		for (i, (entry, _)) in entries.iter().enumerate() {
+			for input in entry.tx.inputs() {
+				if let Some(&j) = created_by.get(&input.commitment()) {
+					if j != i && !depends_on[i].contains(&hashes[j]) {
+						depends_on[i].push(hashes[j].clone());
+						spent_by[j].push(hashes[i].clone());
+					}
+				}
+			}
+		}
+
+		entries
+			.iter()
+			.zip(hashes)
+			.zip(depends_on.into_iter().zip(spent_by))
+			.map(|(((entry, status), tx_hash), (depends_on, spent_by))| {
+				let tx = &entry.tx;
+				PoolTxPrintable {
+					tx_hash,
+					status: *status,
+					source: entry.src.clone(),
+					kernels: tx
+						.kernels()
+						.iter()
+						.map(TxKernelPrintable::from_txkernel)
+						.collect(),
+					fee: tx.fee(),
+					weight: tx.tx_weight(),
+					inputs: tx.inputs().len(),
+					outputs: tx.outputs().len(),
+					tx_at: entry.tx_at.to_rfc3339(),
+					depends_on,
+					spent_by,
+				}
+			})
+			.collect()
+	}
+}
+
+/// Outcome of checking a transaction against the pool admission rules
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PoolCheck {
+	/// Hash of the transaction
+	pub tx_hash: String,
+	/// Whether the pool would accept the transaction
+	pub accept: bool,
+	/// Why the pool would reject the transaction
+	pub error: Option<String>,
+}
+
+/// Request for test coins from the faucet
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FaucetRequest {
+	/// Where the wallet sends the coins to, the http(s) url of the
+	/// requester's wallet listener, on a public address
+	pub dest: String,
+	/// Captcha response, checked by the captcha hook if one is configured
+	pub captcha: Option<String>,
+}
+
+/// Coins sent by the faucet
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FaucetPayout {
+	/// Amount sent, in nanokeplers
+	pub amount: u64,
+	/// Id of the slate of the transaction, as reported by the wallet
+	pub slate_id: Option<String>,
+}
+
+/// Block accepted by the chain, pushed to the block subscribers
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BlockNotification {
+	/// How the block was accepted: "next", "fork" or "reorg"
+	pub status: String,
+	/// Depth of the reorg, when accepting the block caused one
+	pub reorg_depth: Option<u64>,
+	/// Header of the accepted block
+	pub header: BlockHeaderPrintable,
+	/// New chain head, when accepting the block moved it
+	pub head: Option<Tip>,
+}
+
+/// Transaction entering or leaving the pool, pushed to the pool subscribers
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PoolNotification {
+	/// What happened to the transaction: "added", "confirmed" or "evicted"
+	pub event: String,
+	/// Hash of the transaction
+	pub tx_hash: String,
+	/// Excess commitments of the transaction kernels
+	pub kernels: Vec<String>,
+	/// Fee of the transaction
+	pub fee: u64,
+	/// Weight of the transaction
+	pub weight: usize,
+	/// Number of inputs of the transaction
+	pub inputs: usize,
+	/// Number of outputs of the transaction
+	pub outputs: usize,
+	/// When the transaction entered the pool
+	pub tx_at: String,
+}
+
+impl PoolNotification {
+	pub fn new(event: &str, entry: &pool::PoolEntry) -> PoolNotification {
+		let tx = &entry.tx;
+		PoolNotification {
+			event: event.to_owned(),
+			tx_hash: tx.hash().to_hex(),
+			kernels: tx
+				.kernels()
+				.iter()
+				.map(|k| util::to_hex(k.excess.0.to_vec()))
+				.collect(),
+			fee: tx.fee(),
+			weight: tx.tx_weight(),
+			inputs: tx.inputs().len(),
+			outputs: tx.outputs().len(),
+			tx_at: entry.tx_at.to_rfc3339(),
+		}
+	}
+}
+
+/// Peer connecting or disconnecting, pushed to the event subscribers
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PeerNotification {
+	/// What happened to the peer: "connected" or "disconnected"
+	pub event: String,
+	/// Address of the peer
+	pub addr: String,
+	/// User agent of the peer, on connection
+	pub user_agent: Option<String>,
+	/// Capabilities advertised by the peer, on connection
+	pub capabilities: Option<u32>,
+	/// Whether the peer connected to us or we to it, on connection
+	pub direction: Option<p2p::Direction>,
+}
+
+impl PeerNotification {
+	pub fn connected(info: &p2p::PeerInfo) -> PeerNotification {
+		PeerNotification {
+			event: "connected".to_owned(),
+			addr: info.addr.to_string(),
+			user_agent: Some(info.user_agent.clone()),
+			capabilities: Some(info.capabilities.bits()),
+			direction: Some(info.direction),
+		}
+	}
+
+	pub fn disconnected(addr: p2p::PeerAddr) -> PeerNotification {
+		PeerNotification {
+			event: "disconnected".to_owned(),
+			addr: addr.to_string(),
+			user_agent: None,
+			capabilities: None,
+			direction: None,
+		}
+	}
+}
+
+/// Change of the sync status, pushed to the event subscribers
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SyncNotification {
+	/// Sync status, as in the node status
+	pub sync_status: String,
+	/// Progress of the sync, as in the node status
+	pub sync_info: Option<serde_json::Value>,
+}
+
+/// Event of the node pushed to the event subscribers, tagged with its type:
+/// "block", "pool", "peer" or "sync"
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", content = "data", rename_all = "lowercase")]
+pub enum NodeEvent {
+	Block(BlockNotification),
+	Pool(PoolNotification),
+	Peer(PeerNotification),
+	Sync(SyncNotification),
+}
+
+impl NodeEvent {
+	/// Type of the event, as tagged
+	pub fn name(&self) -> &'static str {
+		match self {
+			NodeEvent::Block(_) => "block",
+			NodeEvent::Pool(_) => "pool",
+			NodeEvent::Peer(_) => "peer",
+			NodeEvent::Sync(_) => "sync",
+		}
+	}
+}
+
+/// Local receive latency of a single block
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BlockPropagation {
+	/// Hash of the block
+	pub hash: String,
+	/// Height of the block
+	pub height: u64,
+	/// rfc3339 timestamp from the block header
+	pub timestamp: String,
+	/// rfc3339 timestamp at which the block arrived locally, if recorded
+	pub arrival: Option<String>,
+	/// Arrival minus header timestamp in millis, if the arrival was recorded
+	pub latency_ms: Option<i64>,
+}
+
+/// Block propagation statistics, per block and aggregated per day
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PropagationStats {
+	/// Latency of the most recent blocks, newest first
+	pub blocks: Vec<BlockPropagation>,
+	/// Median latency in millis over the last day of blocks
+	pub median_latency_ms: Option<i64>,
+	/// Median latency in millis over the day of blocks before that
+	pub prev_median_latency_ms: Option<i64>,
+}
+
+/// The original output spent by a block input, as seen by an archive node
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SpentOutputPrintable {
+	/// The type of output Coinbase|Transaction
+	pub output_type: OutputType,
+	/// The commitment of the spent output
+	pub commit: PrintableCommitment,
+	/// Height of the block the output was created in
+	pub height: u64,
+	/// MMR Index of the output
+	pub mmr_index: u64,
+}
+
+impl SpentOutputPrintable {
+	pub fn new(out: &core::OutputIdentifier, pos: &chain::CommitPos) -> SpentOutputPrintable {
+		let output_type = if out.features.is_coinbase() {
+			OutputType::Coinbase
+		} else {
+			OutputType::Transaction
+		};
+		SpentOutputPrintable {
+			output_type,
+			commit: PrintableCommitment { commit: out.commit },
+			height: pos.height,
+			mmr_index: pos.pos,
+		}
+	}
+}
+
+/// The compact filter of the commitments of a block
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BlockFilterPrintable {
+	/// Hash of the block, also the key of the filter
+	pub hash: String,
+	/// Height of the block
+	pub height: u64,
+	/// Number of distinct commitments in the filter
+	pub n: u32,
+	/// Bits of the remainder of the Golomb-Rice coded values
+	pub p: u8,
+	/// Inverse of the false positive rate
+	pub m: u64,
+	/// Hex encoded Golomb-Rice coded set
+	pub filter: String,
+}
+
+impl BlockFilterPrintable {
+	pub fn new(header: &core::BlockHeader, filter: &core::BlockFilter) -> BlockFilterPrintable {
+		BlockFilterPrintable {
+			hash: header.hash().to_hex(),
+			height: header.height,
+			n: filter.n,
+			p: core::block_filter::FILTER_P,
+			m: core::block_filter::FILTER_M,
+			filter: util::to_hex(filter.data.clone()),
+		}
+	}
+}
+
+/// A page of the known peers matching a query
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PeerListing {
+	/// Number of known peers matching the query
+	pub total: usize,
+	/// Number of matching peers skipped before this page
+	pub offset: usize,
+	/// The peers of this page
+	pub peers: Vec<p2p::PeerData>,
+}
+
+/// Requests served by a single API route since the node started
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RouteStatsPrintable {
+	/// Method and route pattern, "unmatched" for requests to no known route
+	pub route: String,
+	/// Number of requests
+	pub requests: u64,
+	/// Number of requests answered with a 4xx status
+	pub client_errors: u64,
+	/// Number of requests answered with a 5xx status
+	pub server_errors: u64,
+	/// Share of the requests answered with an error status
+	pub error_rate: f64,
+	/// Median latency in millis over the latest requests
+	pub latency_p50_ms: f64,
+	/// 90th percentile latency in millis over the latest requests
+	pub latency_p90_ms: f64,
+	/// 99th percentile latency in millis over the latest requests
+	pub latency_p99_ms: f64,
+}
+
+/// Condition raised by the node for its operator, active until it clears
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Alert {
+	/// What the alert is about, e.g. "disk_space" or "sync_stalled"
+	pub kind: String,
+	/// Details of the condition, as of the last check
+	pub message: String,
+	/// rfc3339 timestamp at which the alert was raised
+	pub raised_at: String,
+}
+
+/// Next scheduled hard fork
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HardForkPrintable {
+	/// Height the hard fork activates at
+	pub height: u64,
+	/// Header version required from that height
+	pub header_version: u16,
+	/// Number of blocks left before the hard fork
+	pub blocks_left: u64,
+	/// Whether this node validates the blocks past the hard fork
+	pub supported: bool,
+}
+
+/// Connected peers running a given user agent
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UserAgentCount {
+	pub user_agent: String,
+	/// Protocol version the peers handshaked with
+	pub protocol_version: u32,
+	/// Number of connected peers
+	pub peers: u64,
+	/// Whether the user agent is at least the ready version
+	pub ready: bool,
+}
+
+/// Recent blocks with a given header version
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HeaderVersionCount {
+	pub header_version: u16,
+	pub blocks: u64,
+}
+
+/// Readiness of the network for the next scheduled hard fork, from the user
+/// agents of the connected peers and the header versions of recent blocks
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UpgradeStatus {
+	/// Height of the chain head
+	pub height: u64,
+	/// Header version of the chain head
+	pub header_version: u16,
+	/// Next scheduled hard fork, None past the last one
+	pub next_hard_fork: Option<HardForkPrintable>,
+	/// Lowest node version considered ready for the next hard fork
+	pub ready_version: String,
+	/// Number of connected peers
+	pub peers: u64,
+	/// Number of connected peers running at least the ready version
+	pub ready_peers: u64,
+	/// Percentage of the connected peers running at least the ready version
+	pub ready_percentage: f64,
+	/// Connected peers by user agent, most common first
+	pub user_agents: Vec<UserAgentCount>,
+	/// Recent blocks by header version, over the latest `recent_blocks`
+	pub header_versions: Vec<HeaderVersionCount>,
+	/// Number of recent blocks counted
+	pub recent_blocks: u64,
+}
+
+/// Number of buckets of the block weight histogram, of 10% utilization each
+const WEIGHT_HISTOGRAM_BUCKETS: usize = 10;
+
+/// Fee market statistics over the latest blocks
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct FeeMarketStats {
+	/// Height of the latest block counted
+	pub height: u64,
+	/// Number of blocks counted
+	pub blocks: u64,
+	/// Maximum weight of a block
+	pub max_block_weight: u64,
+	/// Average block weight, as a percentage of the maximum
+	pub avg_weight_utilization: f64,
+	/// Sum of the fees of the blocks
+	pub total_fees: u64,
+	/// Number of kernels of the blocks, not counting the coinbase ones
+	pub total_kernels: u64,
+	/// Fee per weight unit percentiles (10th, 25th, 50th, 75th and 90th),
+	/// over the blocks with transactions, empty if there are none
+	pub fee_per_weight: Vec<f64>,
+	/// Number of blocks by weight utilization, in buckets of 10%
+	pub weight_histogram: Vec<u64>,
+}
+
+impl FeeMarketStats {
+	pub fn from_blocks(blocks: &[chain::BlockFees]) -> FeeMarketStats {
+		let max_weight = MAX_BLOCK_WEIGHT as u64;
+		let mut weight_histogram = vec![0; WEIGHT_HISTOGRAM_BUCKETS];
+		let mut fee_rates = vec![];
+		for b in blocks {
+			let bucket = (b.weight * WEIGHT_HISTOGRAM_BUCKETS as u64 / max_weight) as usize;
+			weight_histogram[bucket.min(WEIGHT_HISTOGRAM_BUCKETS - 1)] += 1;
+			if b.kernels > 0 && b.weight > 0 {
+				fee_rates.push(b.fees as f64 / b.weight as f64);
+			}
+		}
+		fee_rates.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+		// Nearest rank percentiles.
+		let fee_per_weight = if fee_rates.is_empty() {
+			vec![]
+		} else {
+			[10, 25, 50, 75, 90]
+				.iter()
+				.map(|p| {
+					let rank = (p * fee_rates.len() + 99) / 100;
+					fee_rates[rank.max(1) - 1]
+				})
+				.collect()
+		};
+
+		let total_weight: u64 = blocks.iter().map(|b| b.weight).sum();
+		let avg_weight_utilization = if blocks.is_empty() {
+			0.0
+		} else {
+			total_weight as f64 * 100.0 / (max_weight * blocks.len() as u64) as f64
+		};
+
+		FeeMarketStats {
+			height: blocks.iter().map(|b| b.height).max().unwrap_or(0),
+			blocks: blocks.len() as u64,
+			max_block_weight: max_weight,
+			avg_weight_utilization,
+			total_fees: blocks.iter().map(|b| b.fees).sum(),
+			total_kernels: blocks.iter().map(|b| b.kernels).sum(),
+			fee_per_weight,
+			weight_histogram,
+		}
+	}
+}
+
+/// Rolling statistics over the latest blocks of the chain.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BlockStats {
+	/// Height of the latest block counted
+	pub height: u64,
+	/// Hash of the latest block counted
+	pub hash: String,
+	/// Number of blocks counted
+	pub blocks: u64,
+	/// Average time between two blocks, in seconds
+	pub avg_block_interval: f64,
+	/// Average number of kernels per block, not counting the coinbase ones
+	pub avg_kernels: f64,
+	/// Sum of the fees of the blocks
+	pub total_fees: u64,
+	/// Difficulty of the latest block
+	pub difficulty: u64,
+	/// Average difficulty of the blocks
+	pub avg_difficulty: f64,
+	/// Share of the blocks mined with the secondary proof of work, from 0 to 1
+	pub secondary_pow_ratio: f64,
+}
+
+impl BlockStats {
+	/// Statistics of the provided headers, from earliest to latest. The first
+	/// header is only the parent of the earliest block counted, the one its
+	/// interval and difficulty are taken against. The fees are those of the
+	/// blocks we still have, which may be fewer once they got compacted, the
+	/// ones of blocks outside of the headers aren't counted.
+	pub fn from_headers(headers: &[core::BlockHeader], fees: &[chain::BlockFees]) -> BlockStats {
+		let (first, latest) = match (headers.first(), headers.last()) {
+			(Some(first), Some(latest)) if headers.len() > 1 => (first, latest),
+			_ => {
+				return BlockStats {
+					height: headers.last().map(|h| h.height).unwrap_or(0),
+					hash: headers
+						.last()
+						.map(|h| h.hash().to_hex())
+						.unwrap_or_default(),
+					blocks: 0,
+					avg_block_interval: 0.0,
+					avg_kernels: 0.0,
+					total_fees: 0,
+					difficulty: 0,
+					avg_difficulty: 0.0,
+					secondary_pow_ratio: 0.0,
+				}
+			}
+		};
+		let blocks = headers.len() as u64 - 1;
+		let difficulty = |pair: &[core::BlockHeader]| {
+			pair[1]
+				.total_difficulty()
+				.to_num()
+				.saturating_sub(pair[0].total_difficulty().to_num())
+		};
+		let interval = latest.timestamp.timestamp() - first.timestamp.timestamp();
+		let secondary = headers[1..].iter().filter(|h| h.pow.is_secondary()).count();
+		let fees: Vec<_> = fees
+			.iter()
+			.filter(|b| b.height > first.height && b.height <= latest.height)
+			.collect();
+		let total_kernels: u64 = fees.iter().map(|b| b.kernels).sum();
+
+		BlockStats {
+			height: latest.height,
+			hash: latest.hash().to_hex(),
+			blocks,
+			avg_block_interval: interval.max(0) as f64 / blocks as f64,
+			avg_kernels: if fees.is_empty() {
+				0.0
+			} else {
+				total_kernels as f64 / fees.len() as f64
+			},
+			total_fees: fees.iter().map(|b| b.fees).sum(),
+			difficulty: difficulty(&headers[headers.len() - 2..]),
+			avg_difficulty: headers.windows(2).map(difficulty).sum::<u64>() as f64 / blocks as f64,
+			secondary_pow_ratio: secondary as f64 / blocks as f64,
+		}
+	}
+}
+
 #[cfg(test)]
 mod test {
 	use super::*;
@@ -758,4 +1843,184 @@ mod test {
 		let serialized = serde_json::to_string(&deserialized).unwrap();
 		assert_eq!(serialized, hex_commit);
 	}
+
+	#[test]
+	fn serialize_output_lookup() {
+		let hex_lookup = "{\
+			 \"commit\":\"083eafae5d61a85ab07b12e1a51b3918d8e6de11fc6cde641d54af53608aa77b9f\",\
+			 \"status\":\"Unknown\",\
+			 \"height\":null,\
+			 \"mmr_index\":null\
+			 }";
+		let deserialized: OutputLookup = serde_json::from_str(&hex_lookup).unwrap();
+		assert_eq!(deserialized.status, OutputStatus::Unknown);
+		let serialized = serde_json::to_string(&deserialized).unwrap();
+		assert_eq!(serialized, hex_lookup);
+	}
+
+	#[test]
+	fn serialize_output_lookup_v2() {
+		let hex_lookup = "{\
+			 \"commit\":\"083eafae5d61a85ab07b12e1a51b3918d8e6de11fc6cde641d54af53608aa77b9f\",\
+			 \"status\":\"Unspent\",\
+			 \"output_type\":\"Coinbase\",\
+			 \"height\":3,\
+			 \"block_hash\":\"0b6e6d57c8c3b4e6c1f0a2e1b3e3f3cfc3a1e3d0b4b1f0c6c2c3e4f5a6b7c8d9\",\
+			 \"mmr_index\":7\
+			 }";
+		let deserialized: OutputLookupV2 = serde_json::from_str(&hex_lookup).unwrap();
+		assert_eq!(deserialized.status, OutputStatus::Unspent);
+		assert_eq!(deserialized.height, Some(3));
+		let serialized = serde_json::to_string(&deserialized).unwrap();
+		assert_eq!(serialized, hex_lookup);
+	}
+
+	#[test]
+	fn serialize_merged_output() {
+		let hex_merged = "{\
+			 \"commit\":\"083eafae5d61a85ab07b12e1a51b3918d8e6de11fc6cde641d54af53608aa77b9f\",\
+			 \"status\":\"PoolSpent\",\
+			 \"height\":3,\
+			 \"mmr_index\":7,\
+			 \"created_by\":null,\
+			 \"spent_by\":\"0b6e6d57c8c3b4e6c1f0a2e1b3e3f3cfc3a1e3d0b4b1f0c6c2c3e4f5a6b7c8d9\"\
+			 }";
+		let deserialized: MergedOutput = serde_json::from_str(&hex_merged).unwrap();
+		assert_eq!(deserialized.status, MergedOutputStatus::PoolSpent);
+		assert_eq!(deserialized.created_by, None);
+		let serialized = serde_json::to_string(&deserialized).unwrap();
+		assert_eq!(serialized, hex_merged);
+	}
+
+	#[test]
+	fn serialize_pool_tx() {
+		let hex_pool_tx = "{\
+			 \"tx_hash\":\"0b6e6d57c8c3b4e6c1f0a2e1b3e3f3cfc3a1e3d0b4b1f0c6c2c3e4f5a6b7c8d9\",\
+			 \"status\":\"Stem\",\
+			 \"source\":\"Broadcast\",\
+			 \"kernels\":[],\
+			 \"fee\":8000000,\
+			 \"weight\":46,\
+			 \"inputs\":1,\
+			 \"outputs\":2,\
+			 \"tx_at\":\"2020-06-01T12:00:00+00:00\",\
+			 \"depends_on\":[\"ed6ba96009b86173bade6a9227ed60422916593fa32dd6d78b25b7a4eeef4946\"],\
+			 \"spent_by\":[]\
+			 }";
+		let deserialized: PoolTxPrintable = serde_json::from_str(&hex_pool_tx).unwrap();
+		assert_eq!(deserialized.status, TxStatus::Stem);
+		assert_eq!(deserialized.source, pool::TxSource::Broadcast);
+		let serialized = serde_json::to_string(&deserialized).unwrap();
+		assert_eq!(serialized, hex_pool_tx);
+	}
+
+	#[test]
+	fn serialize_orphan_block() {
+		let hex_orphan = "{\
+			 \"hash\":\"0b6e6d57c8c3b4e6c1f0a2e1b3e3f3cfc3a1e3d0b4b1f0c6c2c3e4f5a6b7c8d9\",\
+			 \"height\":12,\
+			 \"previous\":\"ed6ba96009b86173bade6a9227ed60422916593fa32dd6d78b25b7a4eeef4946\",\
+			 \"inputs\":2,\
+			 \"outputs\":3,\
+			 \"kernels\":2,\
+			 \"arrived\":\"2020-06-01T12:00:00+00:00\"\
+			 }";
+		let deserialized: OrphanBlock = serde_json::from_str(&hex_orphan).unwrap();
+		assert_eq!(deserialized.height, 12);
+		let serialized = serde_json::to_string(&deserialized).unwrap();
+		assert_eq!(serialized, hex_orphan);
+	}
+
+	#[test]
+	fn header_printable_pre_pow() {
+		let mut header = core::BlockHeader::default();
+		header.height = 42;
+		header.pow.nonce = 7;
+		let printable = BlockHeaderPrintable::from_header(&header);
+		let rebuilt = core::BlockHeader::from_pre_pow_and_proof(
+			printable.pre_pow,
+			printable.nonce,
+			header.pow.proof.clone(),
+		)
+		.unwrap();
+		assert_eq!(rebuilt.hash(), header.hash());
+	}
+
+	#[test]
+	fn fee_market_stats() {
+		let stats = FeeMarketStats::from_blocks(&[]);
+		assert_eq!(stats.blocks, 0);
+		assert_eq!(stats.avg_weight_utilization, 0.0);
+		assert!(stats.fee_per_weight.is_empty());
+
+		let block = |height, weight, fees, kernels| chain::BlockFees {
+			height,
+			weight,
+			fees,
+			kernels,
+		};
+		let stats = FeeMarketStats::from_blocks(&[
+			block(4, 40_000, 80_000, 10),
+			block(3, 10_000, 10_000, 2),
+			block(2, 0, 0, 0),
+			block(1, 1_000, 0, 0),
+		]);
+		assert_eq!(stats.height, 4);
+		assert_eq!(stats.blocks, 4);
+		assert_eq!(stats.avg_weight_utilization, 31.875);
+		assert_eq!(stats.total_fees, 90_000);
+		assert_eq!(stats.total_kernels, 12);
+		assert_eq!(stats.fee_per_weight, vec![1.0, 1.0, 1.0, 2.0, 2.0]);
+		assert_eq!(stats.weight_histogram, vec![2, 0, 1, 0, 0, 0, 0, 0, 0, 1]);
+	}
+
+	#[test]
+	fn block_stats() {
+		let stats = BlockStats::from_headers(&[], &[]);
+		assert_eq!(stats.blocks, 0);
+		assert_eq!(stats.avg_block_interval, 0.0);
+
+		let header = |height, total_difficulty, secondary| {
+			let mut h = core::BlockHeader::default();
+			h.height = height;
+			h.pow.total_difficulty = crate::core::pow::Difficulty::from_num(total_difficulty);
+			if secondary {
+				h.pow.proof.edge_bits = crate::core::consensus::SECOND_POW_EDGE_BITS;
+			}
+			h
+		};
+		let fees = |height, fees, kernels| chain::BlockFees {
+			height,
+			weight: 0,
+			fees,
+			kernels,
+		};
+		let headers = vec![
+			header(10, 1_000, false),
+			header(11, 1_100, true),
+			header(12, 1_300, false),
+			header(13, 1_600, true),
+			header(14, 2_000, true),
+		];
+		// The earliest block was compacted away already.
+		let stats = BlockStats::from_headers(
+			&headers,
+			&[fees(14, 4_000, 2), fees(13, 0, 0), fees(12, 2_000, 4)],
+		);
+		assert_eq!(stats.height, 14);
+		assert_eq!(stats.hash, headers[4].hash().to_hex());
+		assert_eq!(stats.blocks, 4);
+		assert_eq!(stats.avg_kernels, 2.0);
+		assert_eq!(stats.total_fees, 6_000);
+		assert_eq!(stats.difficulty, 400);
+		assert_eq!(stats.avg_difficulty, 250.0);
+		assert_eq!(stats.secondary_pow_ratio, 0.75);
+
+		// The fees of the parent of the earliest block aren't counted.
+		let all_fees: Vec<_> = (10..=14).rev().map(|h| fees(h, 1_000, 1)).collect();
+		let stats = BlockStats::from_headers(&headers, &all_fees);
+		assert_eq!(stats.blocks, 4);
+		assert_eq!(stats.total_fees, 4_000);
+		assert_eq!(stats.avg_kernels, 1.0);
+	}
 }
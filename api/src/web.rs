@@ -1,8 +1,11 @@
+use crate::core::core::hash::Hash;
+use crate::core::ser::{self, ProtocolVersion, Writeable};
 use crate::rest::*;
 use crate::router::ResponseFuture;
 use bytes::Buf;
 use futures::future::ok;
 use hyper::body;
+use hyper::header::{HeaderValue, CONTENT_TYPE, ETAG, IF_NONE_MATCH};
 use hyper::{Body, Request, Response, StatusCode};
 use serde::{Deserialize, Serialize};
 use serde_json;
@@ -82,6 +85,94 @@ pub fn response<T: Into<Body> + Debug>(status: StatusCode, text: T) -> ResponseF
 	Box::pin(ok(just_response(status, text)))
 }
 
+/// Header a client sends the highest ser protocol version it reads with,
+/// binary responses being tagged with the version they are serialized with.
+pub const SER_VERSION_HEADER: &str = "x-kepler-ser-version";
+
+/// The ser protocol version to serialize the response to the request with,
+/// the highest one known to both the client and the node. Clients not sending
+/// theirs get the first version, the one they were built against.
+pub fn ser_version(req: &Request<Body>) -> ProtocolVersion {
+	let version = req
+		.headers()
+		.get(SER_VERSION_HEADER)
+		.and_then(|v| v.to_str().ok())
+		.and_then(|v| v.trim().parse::<u32>().ok())
+		.unwrap_or(1);
+	ProtocolVersion(version.max(1).min(ProtocolVersion::local().0))
+}
+
+/// Binary response of the data serialized with the ser protocol version,
+/// tagged with it.
+pub fn ser_response<T: Writeable>(data: &T, version: ProtocolVersion) -> ResponseFuture {
+	match ser::ser_vec(data, version) {
+		Ok(bytes) => {
+			let mut resp = just_response(StatusCode::OK, bytes);
+			let headers = resp.headers_mut();
+			headers.insert(
+				CONTENT_TYPE,
+				HeaderValue::from_static("application/octet-stream"),
+			);
+			headers.insert(SER_VERSION_HEADER, HeaderValue::from(version.0));
+			Box::pin(ok(resp))
+		}
+		Err(e) => response(
+			StatusCode::INTERNAL_SERVER_ERROR,
+			format!("can't serialize response: {}", e),
+		),
+	}
+}
+
+/// Entity tag identifying a response by the hash of the chain data it was
+/// built from.
+pub fn etag(hash: &Hash) -> String {
+	format!("\"{}\"", hash.to_hex())
+}
+
+/// Whether the If-None-Match header of the request holds the entity tag,
+/// meaning the client already has the response.
+pub fn etag_matches(req: &Request<Body>, etag: &str) -> bool {
+	req.headers()
+		.get_all(IF_NONE_MATCH)
+		.iter()
+		.filter_map(|v| v.to_str().ok())
+		.flat_map(|v| v.split(','))
+		.map(|t| t.trim())
+		.any(|t| t == "*" || t.trim_start_matches("W/") == etag)
+}
+
+/// Respond to a conditional GET request: not modified if the client already
+/// has the response with the provided entity tag, otherwise the response
+/// built by `f`, tagged if successful.
+pub fn conditional_response<F>(req: &Request<Body>, etag: Option<String>, f: F) -> ResponseFuture
+where
+	F: FnOnce() -> ResponseFuture,
+{
+	let etag = match etag {
+		Some(etag) => etag,
+		None => return f(),
+	};
+	if etag_matches(req, &etag) {
+		let mut resp = just_response(StatusCode::NOT_MODIFIED, "");
+		set_etag(&mut resp, &etag);
+		return Box::pin(ok(resp));
+	}
+	let resp = f();
+	Box::pin(async move {
+		let mut resp = resp.await?;
+		if resp.status().is_success() {
+			set_etag(&mut resp, &etag);
+		}
+		Ok(resp)
+	})
+}
+
+fn set_etag(resp: &mut Response<Body>, etag: &str) {
+	if let Ok(value) = HeaderValue::from_str(etag) {
+		resp.headers_mut().insert(ETAG, value);
+	}
+}
+
 pub struct QueryParams {
 	params: HashMap<String, Vec<String>>,
 }
@@ -184,3 +275,79 @@ macro_rules! w_fut(
 			Err(_) => return response(StatusCode::INTERNAL_SERVER_ERROR, "weak reference upgrade failed" ),
 		}
 	));
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::executor::block_on;
+
+	fn request(if_none_match: Option<&str>) -> Request<Body> {
+		let mut builder = Request::builder().uri("/v1/chain");
+		if let Some(tags) = if_none_match {
+			builder = builder.header(IF_NONE_MATCH, tags);
+		}
+		builder.body(Body::empty()).unwrap()
+	}
+
+	#[test]
+	fn test_conditional_response() {
+		let tag = etag(&Hash::from_vec(&[1; 32]));
+
+		// Tagged when the client doesn't have the response yet.
+		let req = request(None);
+		let resp = block_on(conditional_response(&req, Some(tag.clone()), || {
+			response(StatusCode::OK, "tip")
+		}))
+		.unwrap();
+		assert_eq!(resp.status(), StatusCode::OK);
+		assert_eq!(resp.headers()[ETAG], tag);
+
+		// Not modified, without building the response, when it does.
+		let req = request(Some(&format!("\"aa\", W/{}", tag)));
+		let resp = block_on(conditional_response(
+			&req,
+			Some(tag.clone()),
+			|| unreachable!(),
+		))
+		.unwrap();
+		assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+		assert_eq!(resp.headers()[ETAG], tag);
+
+		// Errors aren't tagged.
+		let req = request(Some("\"aa\""));
+		let resp = block_on(conditional_response(&req, Some(tag.clone()), || {
+			response(StatusCode::NOT_FOUND, "")
+		}))
+		.unwrap();
+		assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+		assert!(resp.headers().get(ETAG).is_none());
+	}
+
+	#[test]
+	fn test_ser_version() {
+		let request = |version: Option<&str>| {
+			let mut builder = Request::builder().uri("/v1/blocks/1?raw");
+			if let Some(version) = version {
+				builder = builder.header(SER_VERSION_HEADER, version);
+			}
+			builder.body(Body::empty()).unwrap()
+		};
+		let local = ProtocolVersion::local();
+
+		// Older clients, telling or not, get the version they know.
+		assert_eq!(ser_version(&request(None)), ProtocolVersion(1));
+		assert_eq!(ser_version(&request(Some("1"))), ProtocolVersion(1));
+		assert_eq!(ser_version(&request(Some("0"))), ProtocolVersion(1));
+		assert_eq!(ser_version(&request(Some("garbage"))), ProtocolVersion(1));
+
+		// Newer ones get ours.
+		assert_eq!(ser_version(&request(Some(&local.0.to_string()))), local);
+		let newer = (local.0 + 1).to_string();
+		assert_eq!(ser_version(&request(Some(&newer))), local);
+
+		let hash = Hash::from_vec(&[1; 32]);
+		let resp = block_on(ser_response(&hash, local)).unwrap();
+		assert_eq!(resp.status(), StatusCode::OK);
+		assert_eq!(resp.headers()[SER_VERSION_HEADER], local.0.to_string());
+	}
+}
@@ -2,10 +2,13 @@ use kepler_api as api;
 use kepler_util as util;
 
 use crate::api::*;
-use hyper::{Body, Request, StatusCode};
-use std::net::SocketAddr;
+use hyper::header;
+use hyper::service::Service;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Arc;
+use std::sync::{mpsc, Arc};
 use std::{thread, time};
 
 struct IndexHandler {
@@ -80,6 +83,161 @@ fn test_start_api() {
 	thread::sleep(time::Duration::from_millis(1_000));
 }
 
+#[test]
+fn test_ip_rate_limit() {
+	util::init_test_logger();
+	let mut server = ApiServer::new();
+	let mut router = build_router();
+	let counter = Arc::new(CounterMiddleware::new());
+	router.add_middleware(Arc::new(IpRateLimitMiddleware::new(1)));
+	router.add_middleware(counter.clone());
+	let server_addr = "127.0.0.1:14436";
+	let addr: SocketAddr = server_addr.parse().expect("unable to parse server address");
+	assert!(server.start(addr, router, None).is_ok());
+	let url = format!("http://{}/v1/", server_addr);
+	assert!(request_with_retry(url.as_str()).is_ok());
+	// Same client over the limit, rejected before reaching the counter.
+	assert!(api::client::get::<Vec<String>>(url.as_str(), None).is_err());
+	assert_eq!(counter.value(), 1);
+	// Served again once the window is over.
+	thread::sleep(time::Duration::from_millis(1_100));
+	assert!(api::client::get::<Vec<String>>(url.as_str(), None).is_ok());
+	assert_eq!(counter.value(), 2);
+	assert!(server.stop());
+	thread::sleep(time::Duration::from_millis(1_000));
+}
+
+struct SlowHandler;
+
+impl Handler for SlowHandler {
+	fn get(&self, _req: Request<Body>) -> ResponseFuture {
+		Box::pin(async {
+			tokio::time::delay_for(time::Duration::from_millis(1_500)).await;
+			Ok(just_response(StatusCode::OK, "[]"))
+		})
+	}
+}
+
+#[test]
+fn test_in_flight_limit() {
+	util::init_test_logger();
+	let mut server = ApiServer::new();
+	let mut router = build_router();
+	router
+		.add_route("/v2/slow", Arc::new(SlowHandler))
+		.expect("add_route failed");
+	router.add_middleware(Arc::new(InFlightLimitMiddleware::new(1)));
+	let server_addr = "127.0.0.1:14437";
+	let addr: SocketAddr = server_addr.parse().expect("unable to parse server address");
+	assert!(server.start(addr, router, None).is_ok());
+	let url = format!("http://{}/v1/", server_addr);
+	assert!(request_with_retry(url.as_str()).is_ok());
+
+	let slow_url = format!("http://{}/v2/slow", server_addr);
+	let slow = thread::spawn(move || api::client::get::<Vec<String>>(slow_url.as_str(), None));
+	thread::sleep(time::Duration::from_millis(500));
+	// The slow request takes the only slot.
+	assert!(api::client::get::<Vec<String>>(url.as_str(), None).is_err());
+	assert!(slow.join().unwrap().is_ok());
+	assert!(api::client::get::<Vec<String>>(url.as_str(), None).is_ok());
+	assert!(server.stop());
+	thread::sleep(time::Duration::from_millis(1_000));
+}
+
+#[test]
+fn test_stop_with_subscriber() {
+	util::init_test_logger();
+	let mut server = ApiServer::new();
+	let mut router = build_router();
+	let stream_handler = PoolStreamHandler {
+		events: Arc::new(PoolEvents::new()),
+		subscribers: Arc::new(AtomicUsize::new(0)),
+	};
+	router
+		.add_route("/v2/stream", Arc::new(stream_handler))
+		.expect("add_route failed");
+	let server_addr = "127.0.0.1:14438";
+	let addr: SocketAddr = server_addr.parse().expect("unable to parse server address");
+	let handle = server.start(addr, router, None).unwrap();
+	let url = format!("http://{}/v1/", server_addr);
+	assert!(request_with_retry(url.as_str()).is_ok());
+
+	// Subscribe and wait for the stream to start, nothing to read after.
+	let mut stream = TcpStream::connect(server_addr).unwrap();
+	let req = format!("GET /v2/stream HTTP/1.1\r\nHost: {}\r\n\r\n", server_addr);
+	stream.write_all(req.as_bytes()).unwrap();
+	let mut buf = [0u8; 1024];
+	let n = stream.read(&mut buf).unwrap();
+	assert!(String::from_utf8_lossy(&buf[..n]).contains("text/event-stream"));
+
+	// The server stops with the subscriber still listening.
+	assert!(server.stop());
+	let (tx, rx) = mpsc::channel();
+	thread::spawn(move || tx.send(handle.join().is_ok()));
+	assert_eq!(rx.recv_timeout(time::Duration::from_secs(5)), Ok(true));
+}
+
+#[test]
+fn test_cors() {
+	let mut router = build_router();
+	router.set_cors(CorsPolicy::new(
+		vec!["https://explorer.example/".to_owned()],
+	));
+	let mut rt = tokio::runtime::Builder::new()
+		.basic_scheduler()
+		.enable_all()
+		.build()
+		.unwrap();
+	let mut call = |method: Method, uri: &str, origin: &str| {
+		let req = Request::builder()
+			.method(method)
+			.uri(uri)
+			.header(header::ORIGIN, origin)
+			.header(header::ACCESS_CONTROL_REQUEST_METHOD, "GET")
+			.body(Body::empty())
+			.unwrap();
+		rt.block_on(router.call(req)).unwrap()
+	};
+	let allow_origin = |resp: &Response<Body>| {
+		resp.headers()
+			.get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+			.map(|origin| origin.to_str().unwrap().to_owned())
+	};
+
+	// Preflight requests are answered without reaching the handlers.
+	let resp = call(Method::OPTIONS, "/v1/", "https://explorer.example");
+	assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+	assert_eq!(
+		allow_origin(&resp),
+		Some("https://explorer.example".to_owned())
+	);
+	assert!(resp
+		.headers()
+		.contains_key(header::ACCESS_CONTROL_ALLOW_METHODS));
+
+	let resp = call(Method::GET, "/v1/", "https://explorer.example");
+	assert_eq!(resp.status(), StatusCode::OK);
+	assert_eq!(
+		allow_origin(&resp),
+		Some("https://explorer.example".to_owned())
+	);
+
+	// Responses answered by the router itself are tagged too.
+	let resp = call(Method::GET, "/v2/unknown", "https://explorer.example");
+	assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+	assert_eq!(
+		allow_origin(&resp),
+		Some("https://explorer.example".to_owned())
+	);
+
+	// Other origins are still served, without CORS headers.
+	let resp = call(Method::OPTIONS, "/v1/", "https://evil.example");
+	assert_eq!(allow_origin(&resp), None);
+	let resp = call(Method::GET, "/v1/", "https://evil.example");
+	assert_eq!(resp.status(), StatusCode::OK);
+	assert_eq!(allow_origin(&resp), None);
+}
+
 // To enable this test you need a trusted PKCS12 (p12) certificate bundle
 // Hyper-tls client doesn't accept self-signed certificates. The easiest way is to use mkcert
 // https://github.com/FiloSottile/mkcert to install CA and generate a certificate on your local machine.
@@ -99,7 +257,7 @@ fn test_start_api_tls() {
 	assert!(server.start(addr, router, Some(tls_conf)).is_ok());
 	let index = request_with_retry("https://yourdomain.com:14444/v1/").unwrap();
 	assert_eq!(index.len(), 2);
-	assert!(!server.stop());
+	assert!(server.stop());
 }
 
 fn request_with_retry(url: &str) -> Result<Vec<String>, api::Error> {
@@ -0,0 +1,263 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Flat files of raw blocks, independent of the db. Blocks get appended to
+//! blk00000.dat, blk00001.dat, ... in a directory, each file holding up to
+//! `MAX_BLOCK_FILE_SIZE` bytes of blocks. Every block is stored as a record
+//! made of the `BLOCK_FILE_MAGIC` bytes, the protocol version the block is
+//! serialized with (u32), the size of the serialized block (u32), all big
+//! endian, followed by the serialized block.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::core::core::Block;
+use crate::core::global;
+use crate::core::ser::{self, ProtocolVersion};
+use crate::error::{Error, ErrorKind};
+
+/// Marks the start of each block record.
+pub const BLOCK_FILE_MAGIC: [u8; 4] = *b"KBLK";
+
+/// A new block file gets started once the current one reaches that size.
+pub const MAX_BLOCK_FILE_SIZE: u64 = 128 * 1024 * 1024;
+
+const RECORD_HEADER_SIZE: u64 = 12;
+
+/// No input, output or kernel takes more than that many bytes serialized
+/// per unit of block weight.
+const MAX_BYTES_PER_WEIGHT: u64 = 64;
+
+/// Room for the header and the coinbase of a block, besides its weight.
+const BLOCK_SIZE_OVERHEAD: u64 = 4 * 1024;
+
+// Upper bound of the size of a valid serialized block, the size of a record
+// gets checked against before reading it.
+fn max_block_size() -> u64 {
+	global::max_block_weight() as u64 * MAX_BYTES_PER_WEIGHT + BLOCK_SIZE_OVERHEAD
+}
+
+fn block_file_path(dir: &Path, index: u32) -> PathBuf {
+	dir.join(format!("blk{:05}.dat", index))
+}
+
+fn block_file_index(path: &Path) -> Option<u32> {
+	let name = path.file_name()?.to_str()?;
+	if name.len() > 7 && name.starts_with("blk") && name.ends_with(".dat") {
+		name[3..name.len() - 4].parse().ok()
+	} else {
+		None
+	}
+}
+
+/// Block files in the directory, ordered by index.
+pub fn block_files(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+	let mut files = vec![];
+	for entry in fs::read_dir(dir).map_err(|e| file_err(dir, e))? {
+		let path = entry.map_err(|e| file_err(dir, e))?.path();
+		if let Some(index) = block_file_index(&path) {
+			files.push((index, path));
+		}
+	}
+	files.sort();
+	Ok(files.into_iter().map(|(_, path)| path).collect())
+}
+
+fn file_err(path: &Path, e: io::Error) -> Error {
+	ErrorKind::FileReadErr(format!("{}: {}", path.display(), e)).into()
+}
+
+/// Appends blocks to the block files of a directory.
+pub struct BlockFileWriter {
+	dir: PathBuf,
+	index: u32,
+	file: File,
+	size: u64,
+}
+
+impl BlockFileWriter {
+	/// Open the block files of the directory, creating it if needed, to
+	/// append to the last one. A record left incomplete by a crash at the
+	/// end of the last file gets truncated.
+	pub fn open<P: AsRef<Path>>(dir: P) -> Result<BlockFileWriter, Error> {
+		let dir = dir.as_ref().to_path_buf();
+		fs::create_dir_all(&dir).map_err(|e| file_err(&dir, e))?;
+		let index = block_files(&dir)?
+			.last()
+			.and_then(|path| block_file_index(path))
+			.unwrap_or(0);
+		let path = block_file_path(&dir, index);
+		let file = OpenOptions::new()
+			.read(true)
+			.append(true)
+			.create(true)
+			.open(&path)
+			.map_err(|e| file_err(&path, e))?;
+		let size = complete_records_len(&file).map_err(|e| file_err(&path, e))?;
+		if size < file.metadata().map_err(|e| file_err(&path, e))?.len() {
+			warn!(
+				"block files: truncating incomplete block record at {} in {}",
+				size,
+				path.display()
+			);
+			file.set_len(size).map_err(|e| file_err(&path, e))?;
+		}
+		Ok(BlockFileWriter {
+			dir,
+			index,
+			file,
+			size,
+		})
+	}
+
+	/// Append the block to the current block file, starting a new file if
+	/// the current one is full.
+	pub fn append(&mut self, block: &Block) -> Result<(), Error> {
+		let version = ProtocolVersion::local();
+		let bytes = ser::ser_vec(block, version).map_err(ErrorKind::SerErr)?;
+		let mut record = Vec::with_capacity(RECORD_HEADER_SIZE as usize + bytes.len());
+		record.extend_from_slice(&BLOCK_FILE_MAGIC);
+		record.extend_from_slice(&version.0.to_be_bytes());
+		record.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+		record.extend_from_slice(&bytes);
+
+		if self.size > 0 && self.size + record.len() as u64 > MAX_BLOCK_FILE_SIZE {
+			let path = block_file_path(&self.dir, self.index + 1);
+			self.file = OpenOptions::new()
+				.append(true)
+				.create(true)
+				.open(&path)
+				.map_err(|e| file_err(&path, e))?;
+			self.index += 1;
+			self.size = 0;
+		}
+		let path = block_file_path(&self.dir, self.index);
+		self.file
+			.write_all(&record)
+			.map_err(|e| file_err(&path, e))?;
+		self.size += record.len() as u64;
+		Ok(())
+	}
+
+	/// Flush the appended blocks to disk.
+	pub fn sync(&self) -> Result<(), Error> {
+		self.file
+			.sync_data()
+			.map_err(|e| file_err(&block_file_path(&self.dir, self.index), e))
+	}
+}
+
+// Length of the complete records at the start of the file.
+fn complete_records_len(file: &File) -> io::Result<u64> {
+	let len = file.metadata()?.len();
+	let mut reader = BufReader::new(file);
+	reader.seek(SeekFrom::Start(0))?;
+	let mut pos = 0;
+	let mut header = [0u8; RECORD_HEADER_SIZE as usize];
+	while pos + RECORD_HEADER_SIZE <= len {
+		reader.read_exact(&mut header)?;
+		if header[..4] != BLOCK_FILE_MAGIC {
+			break;
+		}
+		let size = u32::from_be_bytes([header[8], header[9], header[10], header[11]]) as u64;
+		if pos + RECORD_HEADER_SIZE + size > len {
+			break;
+		}
+		reader.seek(SeekFrom::Current(size as i64))?;
+		pos += RECORD_HEADER_SIZE + size;
+	}
+	Ok(pos)
+}
+
+/// Reads the blocks of all the block files of a directory, in the order they
+/// were appended.
+pub struct BlockFileReader {
+	files: Vec<PathBuf>,
+	current: Option<(PathBuf, BufReader<File>)>,
+}
+
+impl BlockFileReader {
+	/// Reader of the block files of the directory.
+	pub fn open<P: AsRef<Path>>(dir: P) -> Result<BlockFileReader, Error> {
+		let mut files = block_files(dir.as_ref())?;
+		files.reverse();
+		Ok(BlockFileReader {
+			files,
+			current: None,
+		})
+	}
+
+	// Next block of the current file, None at the end of the file.
+	fn read_block(path: &Path, reader: &mut BufReader<File>) -> Result<Option<Block>, Error> {
+		let mut header = [0u8; RECORD_HEADER_SIZE as usize];
+		match reader.read_exact(&mut header[..1]) {
+			Ok(()) => {}
+			Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+			Err(e) => return Err(file_err(path, e)),
+		}
+		reader
+			.read_exact(&mut header[1..])
+			.map_err(|e| file_err(path, e))?;
+		if header[..4] != BLOCK_FILE_MAGIC {
+			return Err(
+				ErrorKind::FileReadErr(format!("{}: not a block record", path.display())).into(),
+			);
+		}
+		let version = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+		let size = u32::from_be_bytes([header[8], header[9], header[10], header[11]]);
+		if size as u64 > max_block_size() {
+			return Err(ErrorKind::FileReadErr(format!(
+				"{}: block record of {} bytes too large",
+				path.display(),
+				size
+			))
+			.into());
+		}
+		let mut bytes = vec![0u8; size as usize];
+		reader
+			.read_exact(&mut bytes)
+			.map_err(|e| file_err(path, e))?;
+		let block = ser::deserialize(&mut &bytes[..], ProtocolVersion(version))
+			.map_err(ErrorKind::SerErr)?;
+		Ok(Some(block))
+	}
+}
+
+impl Iterator for BlockFileReader {
+	type Item = Result<Block, Error>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		loop {
+			if self.current.is_none() {
+				let path = self.files.pop()?;
+				match File::open(&path) {
+					Ok(file) => self.current = Some((path, BufReader::new(file))),
+					Err(e) => return Some(Err(file_err(&path, e))),
+				}
+			}
+			let (path, reader) = self.current.as_mut()?;
+			match BlockFileReader::read_block(path, reader) {
+				Ok(Some(block)) => return Some(Ok(block)),
+				Ok(None) => self.current = None,
+				Err(e) => {
+					// Don't read past a corrupted record.
+					self.current = None;
+					self.files.clear();
+					return Some(Err(e));
+				}
+			}
+		}
+	}
+}
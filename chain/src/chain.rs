@@ -15,63 +15,91 @@
 //! Facade and handler for the rest of the blockchain implementation
 //! and mostly the chain pipeline.
 
-use crate::core::core::hash::{Hash, Hashed, ZERO_HASH};
+use crate::checkpoint::{operator_id, Checkpoint, SignedCheckpoint};
+use crate::core::clock::{Clock, SystemClock};
+use crate::core::consensus::DAY_HEIGHT;
+use crate::core::core::hash::{Hash, HashWriter, Hashed, ZERO_HASH};
 use crate::core::core::merkle_proof::MerkleProof;
+use crate::core::core::pmmr::Segment;
 use crate::core::core::verifier_cache::VerifierCache;
 use crate::core::core::{
-	Block, BlockHeader, BlockSums, Committed, Output, OutputIdentifier, Transaction, TxKernel,
+	transaction, Block, BlockFilter, BlockHeader, BlockSums, Committed, Output, OutputIdentifier,
+	Transaction, TxKernel,
 };
 use crate::core::global;
 use crate::core::pow;
-use crate::core::ser::{ProtocolVersion, Readable, StreamingReader};
+use crate::core::ser::{ProtocolVersion, Readable, StreamingReader, Writer};
 use crate::error::{Error, ErrorKind};
 use crate::pipe;
-use crate::store;
+use crate::store::{self, HeightIter};
 use crate::txhashset;
 use crate::txhashset::{PMMRHandle, TxHashSet};
 use crate::types::{
-	BlockStatus, ChainAdapter, CommitPos, NoStatus, Options, Tip, TxHashsetWriteStatus,
+	BadTxHashSet, BlockFees, BlockStatus, ChainAdapter, CommitPos, CompactionStatus, DataVersion,
+	HeadRacePolicy, NoStatus, Options, OrphanInfo, OrphanPoolLimits, OrphanPoolStats, ReorgRecord,
+	StatsSnapshot, Tip, TxHashsetWriteStatus,
 };
+use crate::util::secp::key::PublicKey;
 use crate::util::secp::pedersen::{Commitment, RangeProof};
-use crate::util::RwLock;
+use crate::util::{file, RwLock};
+use chrono::prelude::{DateTime, TimeZone, Utc};
 use kepler_store::Error::NotFoundErr;
-use std::collections::HashMap;
+use lru_cache::LruCache;
+use std::cmp;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::fs::{self, File};
-use std::io::Read;
-use std::path::PathBuf;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
-/// Orphan pool size is limited by MAX_ORPHAN_SIZE
+/// Orphan pool size is limited by MAX_ORPHAN_SIZE, unless configured
+/// otherwise
 pub const MAX_ORPHAN_SIZE: usize = 200;
 
-/// When evicting, very old orphans are evicted first
-const MAX_ORPHAN_AGE_SECS: u64 = 300;
+/// Headers validated against the kernel MMR between two progress updates.
+const KERNEL_HISTORY_PROGRESS_INTERVAL: u64 = 1_000;
+
+/// Number of blocks we keep the weight and fees of, enough for a day.
+const BLOCK_FEES_CACHE_SIZE: usize = DAY_HEIGHT as usize;
+
+/// Number of downloaded txhashsets that failed validation we remember,
+/// along with their senders, the oldest forgotten first.
+const MAX_BAD_TXHASHSETS: usize = 100;
 
 #[derive(Debug, Clone)]
 struct Orphan {
 	block: Block,
 	opts: Options,
 	added: Instant,
+	arrived: DateTime<Utc>,
+	peer: Option<String>,
 }
 
 pub struct OrphanBlockPool {
+	limits: OrphanPoolLimits,
 	// blocks indexed by their hash
 	orphans: RwLock<HashMap<Hash, Orphan>>,
 	// additional index of height -> hash
 	// so we can efficiently identify a child block (ex-orphan) after processing a block
 	height_idx: RwLock<HashMap<u64, Vec<Hash>>>,
-	// accumulated number of evicted block because of MAX_ORPHAN_SIZE limitation
-	evicted: AtomicUsize,
+	// accumulated number of evicted blocks, too old, too far ahead of the
+	// head and because of the pool size limitation
+	evicted_expired: AtomicUsize,
+	evicted_too_far: AtomicUsize,
+	evicted_full: AtomicUsize,
 }
 
 impl OrphanBlockPool {
-	fn new() -> OrphanBlockPool {
+	fn new(limits: OrphanPoolLimits) -> OrphanBlockPool {
 		OrphanBlockPool {
+			limits,
 			orphans: RwLock::new(HashMap::new()),
 			height_idx: RwLock::new(HashMap::new()),
-			evicted: AtomicUsize::new(0),
+			evicted_expired: AtomicUsize::new(0),
+			evicted_too_far: AtomicUsize::new(0),
+			evicted_full: AtomicUsize::new(0),
 		}
 	}
 
@@ -80,28 +108,46 @@ impl OrphanBlockPool {
 		orphans.len()
 	}
 
-	fn len_evicted(&self) -> usize {
-		self.evicted.load(Ordering::Relaxed)
+	fn stats(&self) -> OrphanPoolStats {
+		OrphanPoolStats {
+			len: self.len(),
+			max_orphans: self.limits.max_orphans,
+			evicted_expired: self.evicted_expired.load(Ordering::Relaxed),
+			evicted_too_far: self.evicted_too_far.load(Ordering::Relaxed),
+			evicted_full: self.evicted_full.load(Ordering::Relaxed),
+		}
 	}
 
-	fn add(&self, orphan: Orphan) {
+	/// Add an orphan, unless too far ahead of the chain head, evicting the
+	/// ones too old and then the highest ones while the pool is full.
+	/// Returns whether the orphan was kept.
+	fn add(&self, orphan: Orphan, head_height: u64) -> bool {
+		let height = orphan.block.header.height;
+		if height > head_height.saturating_add(self.limits.max_height_ahead) {
+			self.evicted_too_far.fetch_add(1, Ordering::Relaxed);
+			return false;
+		}
+
 		let mut orphans = self.orphans.write();
 		let mut height_idx = self.height_idx.write();
-		{
-			let height_hashes = height_idx
-				.entry(orphan.block.header.height)
-				.or_insert_with(|| vec![]);
-			height_hashes.push(orphan.block.hash());
-			orphans.insert(orphan.block.hash(), orphan);
+		let hash = orphan.block.hash();
+		if orphans.insert(hash, orphan).is_none() {
+			height_idx
+				.entry(height)
+				.or_insert_with(|| vec![])
+				.push(hash);
 		}
 
-		if orphans.len() > MAX_ORPHAN_SIZE {
+		// evict too old
+		let max_age = Duration::from_secs(self.limits.max_age_secs);
+		let old_len = orphans.len();
+		orphans.retain(|_, ref mut x| x.added.elapsed() < max_age);
+		self.evicted_expired
+			.fetch_add(old_len - orphans.len(), Ordering::Relaxed);
+
+		if orphans.len() > self.limits.max_orphans {
 			let old_len = orphans.len();
 
-			// evict too old
-			orphans.retain(|_, ref mut x| {
-				x.added.elapsed() < Duration::from_secs(MAX_ORPHAN_AGE_SECS)
-			});
 			// evict too far ahead
 			let mut heights = height_idx.keys().cloned().collect::<Vec<u64>>();
 			heights.sort_unstable();
@@ -111,16 +157,21 @@ impl OrphanBlockPool {
 						let _ = orphans.remove(&h);
 					}
 				}
-				if orphans.len() < MAX_ORPHAN_SIZE {
+				if orphans.len() <= self.limits.max_orphans {
 					break;
 				}
 			}
-			// cleanup index
-			height_idx.retain(|_, ref mut xs| xs.iter().any(|x| orphans.contains_key(&x)));
 
-			self.evicted
+			self.evicted_full
 				.fetch_add(old_len - orphans.len(), Ordering::Relaxed);
 		}
+		// cleanup index
+		height_idx.retain(|_, ref mut xs| {
+			xs.retain(|x| orphans.contains_key(x));
+			!xs.is_empty()
+		});
+
+		orphans.contains_key(&hash)
 	}
 
 	/// Get an orphan from the pool indexed by the hash of its parent, removing
@@ -137,6 +188,108 @@ impl OrphanBlockPool {
 		let orphans = self.orphans.read();
 		orphans.contains_key(hash)
 	}
+
+	/// The orphans in the pool, lowest first.
+	fn list(&self) -> Vec<OrphanInfo> {
+		let orphans = self.orphans.read();
+		let mut list = orphans
+			.values()
+			.map(|x| OrphanInfo {
+				header: x.block.header.clone(),
+				inputs: x.block.inputs().len(),
+				outputs: x.block.outputs().len(),
+				kernels: x.block.kernels().len(),
+				arrived: x.arrived,
+				peer: x.peer.clone(),
+			})
+			.collect::<Vec<_>>();
+		list.sort_by_key(|x| (x.header.height, x.arrived));
+		list
+	}
+
+	/// Remove an orphan from the pool, returning whether it was there.
+	fn remove(&self, hash: &Hash) -> bool {
+		let mut orphans = self.orphans.write();
+		let mut height_idx = self.height_idx.write();
+		let orphan = match orphans.remove(hash) {
+			Some(orphan) => orphan,
+			None => return false,
+		};
+		let height = orphan.block.header.height;
+		if let Some(hs) = height_idx.get_mut(&height) {
+			hs.retain(|h| h != hash);
+			if hs.is_empty() {
+				height_idx.remove(&height);
+			}
+		}
+		true
+	}
+
+	/// Remove all the orphans from the pool, returning how many there were.
+	fn clear(&self) -> usize {
+		let mut orphans = self.orphans.write();
+		let mut height_idx = self.height_idx.write();
+		let len = orphans.len();
+		orphans.clear();
+		height_idx.clear();
+		len
+	}
+}
+
+// The latest downloaded txhashsets that failed validation, indexed by
+// archive and sender.
+struct BadTxHashSets {
+	// oldest first
+	rejected: VecDeque<BadTxHashSet>,
+	// number of rejected txhashsets by archive and by sender
+	archives: HashMap<Hash, usize>,
+	peers: HashMap<String, usize>,
+}
+
+impl BadTxHashSets {
+	fn new() -> BadTxHashSets {
+		BadTxHashSets {
+			rejected: VecDeque::new(),
+			archives: HashMap::new(),
+			peers: HashMap::new(),
+		}
+	}
+
+	fn add(&mut self, bad: BadTxHashSet) {
+		if self.rejected.contains(&bad) {
+			return;
+		}
+		if let Some(archive) = bad.archive {
+			*self.archives.entry(archive).or_insert(0) += 1;
+		}
+		if let Some(ref peer) = bad.peer {
+			*self.peers.entry(peer.clone()).or_insert(0) += 1;
+		}
+		self.rejected.push_back(bad);
+
+		while self.rejected.len() > MAX_BAD_TXHASHSETS {
+			let oldest = match self.rejected.pop_front() {
+				Some(oldest) => oldest,
+				None => break,
+			};
+			if let Some(archive) = oldest.archive {
+				forget(&mut self.archives, &archive);
+			}
+			if let Some(peer) = oldest.peer {
+				forget(&mut self.peers, &peer);
+			}
+		}
+	}
+}
+
+// Decrement the count of the key, removing it once down to zero.
+fn forget<K: Eq + std::hash::Hash>(counts: &mut HashMap<K, usize>, key: &K) {
+	if let Some(count) = counts.get_mut(key) {
+		*count -= 1;
+		if *count == 0 {
+			counts.remove(key);
+		}
+	}
 }
 
 /// Facade to the blockchain block processing pipeline and storage. Provides
@@ -151,16 +304,39 @@ pub struct Chain {
 	header_pmmr: Arc<RwLock<txhashset::PMMRHandle<BlockHeader>>>,
 	sync_pmmr: Arc<RwLock<txhashset::PMMRHandle<BlockHeader>>>,
 	verifier_cache: Arc<RwLock<dyn VerifierCache>>,
+	difficulty_cache: Arc<RwLock<store::DifficultyCache>>,
+	// weight and fees of the latest blocks, for the fee market stats
+	block_fees: RwLock<LruCache<Hash, BlockFees>>,
 	// POW verification function
 	pow_verifier: fn(&BlockHeader) -> Result<(), pow::Error>,
 	archive_mode: bool,
+	head_race_policy: HeadRacePolicy,
+	// rejects all writes, see init_read_only
+	read_only: bool,
+	// number of blocks received with the same total difficulty as our head
+	tip_races: AtomicUsize,
+	// downloaded txhashsets that failed validation, with their senders
+	bad_txhashsets: RwLock<BadTxHashSets>,
+	compaction: RwLock<CompactionStatus>,
+	// checkpoints imported from trusted operators, by height
+	checkpoints: RwLock<BTreeMap<u64, SignedCheckpoint>>,
+	// hashes of the headers at the configured hard checkpoint heights
+	hard_checkpoints: Arc<BTreeMap<u64, Hash>>,
+	// rangeproofs of our header chain taken as verified up to that height
+	assume_valid_height: Option<u64>,
+	// segments of the txhashset at the archive header, served to our peers
+	segmenter: RwLock<Option<Arc<txhashset::Segmenter>>>,
+	// txhashset being rebuilt from the segments received from our peers
+	desegmenter: RwLock<Option<txhashset::Desegmenter>>,
 	genesis: BlockHeader,
+	clock: Arc<dyn Clock>,
 }
 
 impl Chain {
 	/// Initializes the blockchain and returns a new Chain instance. Does a
 	/// check on the current chain head to make sure it exists and creates one
-	/// based on the genesis block if necessary.
+	/// based on the genesis block if necessary. Refuses to open a data dir
+	/// written by a newer binary rather than running migrations on it.
 	pub fn init(
 		db_root: String,
 		adapter: Arc<dyn ChainAdapter + Send + Sync>,
@@ -168,9 +344,76 @@ impl Chain {
 		pow_verifier: fn(&BlockHeader) -> Result<(), pow::Error>,
 		verifier_cache: Arc<RwLock<dyn VerifierCache>>,
 		archive_mode: bool,
+		head_race_policy: HeadRacePolicy,
+	) -> Result<Chain, Error> {
+		Chain::open(
+			db_root,
+			adapter,
+			genesis,
+			pow_verifier,
+			verifier_cache,
+			archive_mode,
+			head_race_policy,
+			false,
+		)
+	}
+
+	/// Opens the blockchain without writing to the data dir, even when it
+	/// was written by a newer binary. The head isn't checked against the
+	/// txhashset and no migration is run, blocks, headers and txhashsets
+	/// are all rejected. Meant to keep serving queries from a data dir
+	/// that can't be safely opened otherwise.
+	pub fn init_read_only(
+		db_root: String,
+		adapter: Arc<dyn ChainAdapter + Send + Sync>,
+		genesis: Block,
+		pow_verifier: fn(&BlockHeader) -> Result<(), pow::Error>,
+		verifier_cache: Arc<RwLock<dyn VerifierCache>>,
+		archive_mode: bool,
+		head_race_policy: HeadRacePolicy,
+	) -> Result<Chain, Error> {
+		Chain::open(
+			db_root,
+			adapter,
+			genesis,
+			pow_verifier,
+			verifier_cache,
+			archive_mode,
+			head_race_policy,
+			true,
+		)
+	}
+
+	#[allow(clippy::too_many_arguments)]
+	fn open(
+		db_root: String,
+		adapter: Arc<dyn ChainAdapter + Send + Sync>,
+		genesis: Block,
+		pow_verifier: fn(&BlockHeader) -> Result<(), pow::Error>,
+		verifier_cache: Arc<RwLock<dyn VerifierCache>>,
+		archive_mode: bool,
+		head_race_policy: HeadRacePolicy,
+		read_only: bool,
 	) -> Result<Chain, Error> {
 		let store = Arc::new(store::ChainStore::new(&db_root)?);
 
+		let data_version = store.data_version()?;
+		if let Some(version) = data_version {
+			if version.is_newer() {
+				if !read_only {
+					return Err(ErrorKind::DataVersionTooNew(
+						version.db_schema,
+						version.txhashset_format,
+					)
+					.into());
+				}
+				warn!(
+					"init: data written by a newer version ({:?}), reading it as is",
+					version
+				);
+			}
+		}
+
 		// open the txhashset, creating a new one if necessary
 		let mut txhashset = txhashset::TxHashSet::open(db_root.clone(), store.clone(), None)?;
 
@@ -191,18 +434,18 @@ impl Chain {
 			None,
 		)?;
 
-		setup_head(
-			&genesis,
-			&store,
-			&mut header_pmmr,
-			&mut sync_pmmr,
-			&mut txhashset,
-		)?;
+		if !read_only {
+			setup_head(
+				&genesis,
+				&store,
+				&mut header_pmmr,
+				&mut sync_pmmr,
+				&mut txhashset,
+			)?;
 
-		// Initialize the output_pos index based on UTXO set.
-		// This is fast as we only look for stale and missing entries
-		// and do not need to rebuild the entire index.
-		{
+			// Initialize the output_pos index based on UTXO set.
+			// This is fast as we only look for stale and missing entries
+			// and do not need to rebuild the entire index.
 			let batch = store.batch()?;
 			txhashset.init_output_pos_index(&header_pmmr, &batch)?;
 			batch.commit()?;
@@ -212,20 +455,44 @@ impl Chain {
 			db_root,
 			store,
 			adapter,
-			orphans: Arc::new(OrphanBlockPool::new()),
+			orphans: Arc::new(OrphanBlockPool::new(OrphanPoolLimits::default())),
 			txhashset: Arc::new(RwLock::new(txhashset)),
 			header_pmmr: Arc::new(RwLock::new(header_pmmr)),
 			sync_pmmr: Arc::new(RwLock::new(sync_pmmr)),
 			pow_verifier,
 			verifier_cache,
+			difficulty_cache: Arc::new(RwLock::new(store::DifficultyCache::new())),
+			block_fees: RwLock::new(LruCache::new(BLOCK_FEES_CACHE_SIZE)),
 			archive_mode,
+			head_race_policy,
+			read_only,
+			tip_races: AtomicUsize::new(0),
+			bad_txhashsets: RwLock::new(BadTxHashSets::new()),
+			compaction: RwLock::new(CompactionStatus::default()),
+			checkpoints: RwLock::new(BTreeMap::new()),
+			hard_checkpoints: Arc::new(BTreeMap::new()),
+			assume_valid_height: None,
+			segmenter: RwLock::new(None),
+			desegmenter: RwLock::new(None),
 			genesis: genesis.header,
+			clock: Arc::new(SystemClock),
 		};
 
-		// DB migrations to be run prior to the chain being used.
-		{
-			// Migrate full blocks to protocol version v2.
-			chain.migrate_db_v1_v2()?;
+		if !read_only {
+			// DB migrations to be run prior to the chain being used.
+			{
+				// Migrate full blocks to protocol version v2.
+				chain.migrate_db_v1_v2()?;
+			}
+			if data_version.map_or(true, |v| v.db_schema < 3) {
+				// Build the kernel_pos index, missing before schema v3.
+				chain.init_kernel_pos_index()?;
+			}
+
+			// Record the versions the data is now written with.
+			let batch = chain.store.batch()?;
+			batch.save_data_version(&DataVersion::current())?;
+			batch.commit()?;
 		}
 
 		chain.log_heads()?;
@@ -233,6 +500,47 @@ impl Chain {
 		Ok(chain)
 	}
 
+	/// Whether the chain was opened read-only, rejecting all writes.
+	pub fn is_read_only(&self) -> bool {
+		self.read_only
+	}
+
+	fn check_writable(&self) -> Result<(), Error> {
+		if self.read_only {
+			return Err(ErrorKind::ReadOnly.into());
+		}
+		Ok(())
+	}
+
+	/// Use the provided clock instead of the system time for the block
+	/// timestamp checks and arrival times, to control the time in tests.
+	pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Chain {
+		self.clock = clock;
+		self
+	}
+
+	/// Reject the headers at the heights of the hard checkpoints other than
+	/// the ones checkpointed, and skip the rangeproof verification of the
+	/// blocks of our header chain up to the assume-valid height, in blocks
+	/// as in a downloaded txhashset. The assume-valid height must be the
+	/// height of one of the hard checkpoints, nothing is assumed valid
+	/// otherwise. Kernel signatures are still verified.
+	pub fn with_checkpoints(
+		mut self,
+		hard_checkpoints: BTreeMap<u64, Hash>,
+		assume_valid_height: Option<u64>,
+	) -> Chain {
+		self.hard_checkpoints = Arc::new(hard_checkpoints);
+		self.assume_valid_height = assume_valid_height;
+		self
+	}
+
+	/// Limit the orphan pool as configured rather than to the defaults.
+	pub fn with_orphan_limits(mut self, limits: OrphanPoolLimits) -> Chain {
+		self.orphans = Arc::new(OrphanBlockPool::new(limits));
+		self
+	}
+
 	/// Return our shared header MMR handle.
 	pub fn header_pmmr(&self) -> Arc<RwLock<PMMRHandle<BlockHeader>>> {
 		self.header_pmmr.clone()
@@ -267,14 +575,60 @@ impl Chain {
 	/// Processes a single block, then checks for orphans, processing
 	/// those as well if they're found
 	pub fn process_block(&self, b: Block, opts: Options) -> Result<Option<Tip>, Error> {
+		self.process_block_from(b, opts, None)
+	}
+
+	/// Processes a single block received from the provided peer, which the
+	/// block is recorded with should it be an orphan.
+	pub fn process_block_from(
+		&self,
+		b: Block,
+		opts: Options,
+		peer: Option<String>,
+	) -> Result<Option<Tip>, Error> {
 		let height = b.header.height;
-		let res = self.process_block_single(b, opts);
+		let res = self.process_block_single(b, opts, self.clock.now(), peer);
 		if res.is_ok() {
 			self.check_orphans(height + 1);
 		}
 		res
 	}
 
+	/// Processes a block through the pipeline, adding the time spent in each
+	/// of its stages to the provided timings. Meant for benchmarking, orphans
+	/// aren't handled and the adapter isn't notified.
+	pub fn process_block_timed(
+		&self,
+		b: &Block,
+		opts: Options,
+		timings: &mut pipe::PipeTimings,
+	) -> Result<Option<Tip>, Error> {
+		let mut header_pmmr = self.header_pmmr.write();
+		let mut txhashset = self.txhashset.write();
+		let batch = self.store.batch()?;
+		let mut ctx = self.new_ctx(opts, batch, &mut header_pmmr, &mut txhashset)?;
+		ctx.timings = Some(timings);
+		let head = pipe::process_block(b, &mut ctx)?;
+
+		let start = Instant::now();
+		ctx.batch.commit()?;
+		if let Some(timings) = ctx.timings {
+			timings.commit += start.elapsed();
+		}
+		Ok(head)
+	}
+
+	/// Validates a chain of headers against the consensus rules (linkage,
+	/// version, timestamps, proof of work and difficulty), without any chain
+	/// state. For external tools reusing the consensus logic, the first header
+	/// is trusted and should be genesis or a known checkpoint.
+	pub fn verify_block_headers_chain(
+		headers: &[BlockHeader],
+		pow_verifier: fn(&BlockHeader) -> Result<(), pow::Error>,
+	) -> Result<(), Error> {
+		pipe::validate_header_chain(headers, pow_verifier)
+	}
+
 	fn determine_status(&self, head: Option<Tip>, prev_head: Tip) -> BlockStatus {
 		// We have more work if the chain head is updated.
 		let is_more_work = head.is_some();
@@ -299,7 +653,17 @@ impl Chain {
 	/// Attempt to add a new block to the chain.
 	/// Returns true if it has been added to the longest chain
 	/// or false if it has added to a fork (or orphan?).
-	fn process_block_single(&self, b: Block, opts: Options) -> Result<Option<Tip>, Error> {
+	/// The arrival time is when we first received the block locally, which
+	/// for an orphan is earlier than when it is finally processed.
+	fn process_block_single(
+		&self,
+		b: Block,
+		opts: Options,
+		arrived: DateTime<Utc>,
+		peer: Option<String>,
+	) -> Result<Option<Tip>, Error> {
+		self.check_checkpoints(std::slice::from_ref(&b.header))?;
+
 		let (maybe_new_head, prev_head) = {
 			let mut header_pmmr = self.header_pmmr.write();
 			let mut txhashset = self.txhashset.write();
@@ -315,6 +679,17 @@ impl Chain {
 			// A node shutdown at this point can be catastrophic...
 			// We prevent this via the stop_lock (see above).
 			if maybe_new_head.is_ok() {
+				if !opts.intersects(Options::FROM_LOCAL | Options::HEADER_ONLY) {
+					ctx.batch
+						.save_block_arrival(&b.hash(), arrived.timestamp_millis())?;
+				}
+				// Kernels we sync through aren't new to the network.
+				if !opts.intersects(Options::SYNC | Options::HEADER_ONLY) {
+					for kernel in b.kernels() {
+						ctx.batch
+							.save_kernel_first_seen(&kernel.excess, arrived.timestamp_millis())?;
+					}
+				}
 				ctx.batch.commit()?;
 			}
 
@@ -323,7 +698,18 @@ impl Chain {
 		};
 
 		match maybe_new_head {
+			// Nothing to notify, the block itself was not processed.
+			Ok(head) if opts.contains(Options::HEADER_ONLY) => Ok(head),
 			Ok(head) => {
+				if b.header.total_difficulty() == prev_head.total_difficulty {
+					self.tip_races.fetch_add(1, Ordering::Relaxed);
+					self.adapter.head_race(&b, head.is_some());
+				}
+
+				self.block_fees
+					.write()
+					.insert(b.hash(), BlockFees::from_block(&b));
+
 				let status = self.determine_status(head.clone(), prev_head);
 
 				// notifying other parts of the system of the update
@@ -338,16 +724,21 @@ impl Chain {
 						block: b,
 						opts: opts,
 						added: Instant::now(),
+						arrived,
+						peer,
 					};
 
-					self.orphans.add(orphan);
+					let head_height = self.head()?.height;
+					let kept = self.orphans.add(orphan, head_height);
 
+					let stats = self.orphans.stats();
 					debug!(
-						"process_block: orphan: {:?}, # orphans {}{}",
+						"process_block: orphan: {:?}{}, # orphans {}{}",
 						block_hash,
-						self.orphans.len(),
-						if self.orphans.len_evicted() > 0 {
-							format!(", # evicted {}", self.orphans.len_evicted())
+						if kept { "" } else { " (evicted)" },
+						stats.len,
+						if stats.evicted() > 0 {
+							format!(", # evicted {}", stats.evicted())
 						} else {
 							String::new()
 						},
@@ -363,6 +754,15 @@ impl Chain {
 					);
 					Err(ErrorKind::Unfit(msg.clone()).into())
 				}
+				ErrorKind::Known(ref msg) => {
+					debug!(
+						"Block {} at {} is already known: {}",
+						b.hash(),
+						b.header.height,
+						msg
+					);
+					Err(ErrorKind::Known(msg.clone()).into())
+				}
 				_ => {
 					info!(
 						"Rejected block {} at {}: {:?}",
@@ -380,6 +780,8 @@ impl Chain {
 	/// Note: This will update header MMR and corresponding header_head
 	/// if total work increases (on the header chain).
 	pub fn process_block_header(&self, bh: &BlockHeader, opts: Options) -> Result<(), Error> {
+		self.check_checkpoints(std::slice::from_ref(bh))?;
+
 		let mut header_pmmr = self.header_pmmr.write();
 		let mut txhashset = self.txhashset.write();
 		let batch = self.store.batch()?;
@@ -393,6 +795,8 @@ impl Chain {
 	/// This is only ever used during sync and is based on sync_head.
 	/// We update header_head here if our total work increases.
 	pub fn sync_block_headers(&self, headers: &[BlockHeader], opts: Options) -> Result<(), Error> {
+		self.check_checkpoints(headers)?;
+
 		let mut sync_pmmr = self.sync_pmmr.write();
 		let mut header_pmmr = self.header_pmmr.write();
 		let mut txhashset = self.txhashset.write();
@@ -423,13 +827,20 @@ impl Chain {
 		header_pmmr: &'a mut txhashset::PMMRHandle<BlockHeader>,
 		txhashset: &'a mut txhashset::TxHashSet,
 	) -> Result<pipe::BlockContext<'a>, Error> {
+		self.check_writable()?;
 		Ok(pipe::BlockContext {
 			opts,
 			pow_verifier: self.pow_verifier,
 			verifier_cache: self.verifier_cache.clone(),
+			difficulty_cache: self.difficulty_cache.clone(),
+			head_race_policy: self.head_race_policy,
 			header_pmmr,
 			txhashset,
 			batch,
+			timings: None,
+			clock: self.clock.clone(),
+			hard_checkpoints: self.hard_checkpoints.clone(),
+			assume_valid_height: self.assume_valid_height,
 		})
 	}
 
@@ -440,7 +851,164 @@ impl Chain {
 
 	/// Get the OrphanBlockPool accumulated evicted number of blocks
 	pub fn orphans_evicted_len(&self) -> usize {
-		self.orphans.len_evicted()
+		self.orphans.stats().evicted()
+	}
+
+	/// Size and limit of the orphan pool, with the blocks evicted from it.
+	pub fn orphan_stats(&self) -> OrphanPoolStats {
+		self.orphans.stats()
+	}
+
+	/// The blocks in the orphan pool, waiting for their parent, lowest first.
+	pub fn orphans(&self) -> Vec<OrphanInfo> {
+		self.orphans.list()
+	}
+
+	/// Drop a block from the orphan pool, so it can be received again.
+	/// Returns whether it was an orphan.
+	pub fn remove_orphan(&self, hash: &Hash) -> bool {
+		let removed = self.orphans.remove(hash);
+		if removed {
+			info!("remove_orphan: {} removed from the orphan pool", hash);
+		}
+		removed
+	}
+
+	/// Drop all the blocks from the orphan pool, returning how many were.
+	pub fn clear_orphans(&self) -> usize {
+		let len = self.orphans.clear();
+		info!("clear_orphans: {} orphans removed", len);
+		len
+	}
+
+	/// The checkpoint of our chain at the given height, or at the head.
+	pub fn checkpoint(&self, height: Option<u64>) -> Result<Checkpoint, Error> {
+		let head = self.head()?;
+		let height = height.unwrap_or(head.height);
+		if height > head.height {
+			return Err(ErrorKind::Other(format!(
+				"no block at {}, head at {}",
+				height, head.height
+			))
+			.into());
+		}
+		let header = self.get_header_by_height(height)?;
+		Ok(Checkpoint::from_header(&header))
+	}
+
+	/// Import a checkpoint signed by one of the trusted operators. A bad
+	/// signature, an operator not in the list, or a checkpoint conflicting
+	/// with our header chain is refused. Headers conflicting with an
+	/// imported checkpoint are then rejected, and a txhashset below one is
+	/// accepted without checking its rangeproofs and kernel signatures.
+	pub fn add_checkpoint(
+		&self,
+		signed: SignedCheckpoint,
+		trusted: &[PublicKey],
+	) -> Result<(), Error> {
+		let cp = &signed.checkpoint;
+		if let Err(e) = signed.verify(&self.genesis.hash()) {
+			warn!(
+				"checkpoint: {} at {} has a bad signature, refused",
+				cp.hash, cp.height
+			);
+			return Err(e);
+		}
+		if !trusted.contains(&signed.operator) {
+			warn!(
+				"checkpoint: {} at {} signed by {}, not a trusted operator, refused",
+				cp.hash,
+				cp.height,
+				operator_id(&signed.operator)
+			);
+			return Err(ErrorKind::InvalidCheckpoint("operator not trusted".to_owned()).into());
+		}
+		if cp.height <= self.header_head()?.height {
+			let header = self.get_header_by_height(cp.height)?;
+			if !cp.matches(&header) {
+				warn!(
+					"checkpoint: {} at {} from {} conflicts with our header {}, refused",
+					cp.hash,
+					cp.height,
+					operator_id(&signed.operator),
+					header.hash()
+				);
+				return Err(ErrorKind::CheckpointMismatch(cp.height).into());
+			}
+		}
+		info!(
+			"checkpoint: trusting {} at {}, signed by operator {}",
+			cp.hash,
+			cp.height,
+			operator_id(&signed.operator)
+		);
+		self.checkpoints.write().insert(cp.height, signed);
+		Ok(())
+	}
+
+	/// The checkpoints imported, lowest first.
+	pub fn checkpoints(&self) -> Vec<SignedCheckpoint> {
+		self.checkpoints.read().values().cloned().collect()
+	}
+
+	// Reject any header at the height of a checkpoint that isn't the one
+	// checkpointed.
+	fn check_checkpoints(&self, headers: &[BlockHeader]) -> Result<(), Error> {
+		let checkpoints = self.checkpoints.read();
+		if checkpoints.is_empty() {
+			return Ok(());
+		}
+		for header in headers {
+			if let Some(signed) = checkpoints.get(&header.height) {
+				if !signed.checkpoint.matches(header) {
+					debug!(
+						"check_checkpoints: header {} at {} conflicts with checkpoint {}",
+						header.hash(),
+						header.height,
+						signed.checkpoint.hash
+					);
+					return Err(ErrorKind::CheckpointMismatch(header.height).into());
+				}
+			}
+		}
+		Ok(())
+	}
+
+	// The lowest imported checkpoint at or above the header, if the header is
+	// on the header chain leading to it. The chain state at the header is
+	// then vouched for by the operator who signed the checkpoint.
+	fn trusted_checkpoint(&self, header: &BlockHeader) -> Option<SignedCheckpoint> {
+		let checkpoints = self.checkpoints.read();
+		let header_pmmr = self.header_pmmr.read();
+		if header_pmmr.get_header_hash_by_height(header.height).ok() != Some(header.hash()) {
+			return None;
+		}
+		checkpoints
+			.range(header.height..)
+			.map(|(_, signed)| signed)
+			.find(|signed| {
+				let cp = &signed.checkpoint;
+				header_pmmr.get_header_hash_by_height(cp.height).ok() == Some(cp.hash)
+			})
+			.cloned()
+	}
+
+	// Whether the header is at or below the assume-valid height on our header
+	// chain, going through the hard checkpoint at that height.
+	fn is_assumed_valid(&self, header: &BlockHeader) -> bool {
+		let header_pmmr = self.header_pmmr.read();
+		pipe::assumed_valid(
+			header,
+			self.assume_valid_height,
+			&self.hard_checkpoints,
+			&header_pmmr,
+		)
+	}
+
+	/// Number of blocks received with the same total difficulty as our
+	/// chain head at the time, resolved via the configured HeadRacePolicy.
+	pub fn tip_races(&self) -> usize {
+		self.tip_races.load(Ordering::Relaxed)
 	}
 
 	/// Check for orphans, once a block is successfully added
@@ -472,7 +1040,12 @@ impl Chain {
 						},
 					);
 					let height = orphan.block.header.height;
-					let res = self.process_block_single(orphan.block, orphan.opts);
+					let res = self.process_block_single(
+						orphan.block,
+						orphan.opts,
+						orphan.arrived,
+						orphan.peer,
+					);
 					if res.is_ok() {
 						orphan_accepted = true;
 						height_accepted = height;
@@ -557,6 +1130,20 @@ impl Chain {
 
 	/// Validate the current chain state.
 	pub fn validate(&self, fast_validation: bool) -> Result<(), Error> {
+		self.validate_with_status(fast_validation, &NoStatus)
+	}
+
+	/// Validate the current chain state, reporting the progress of the
+	/// validation of the MMRs, rangeproofs and kernel signatures to the
+	/// provided status.
+	pub fn validate_with_status(
+		&self,
+		fast_validation: bool,
+		status: &dyn TxHashsetWriteStatus,
+	) -> Result<(), Error> {
+		// The txhashset stays at the head as long as we hold the lock, so
+		// validate it in place under the read lock, without rewinding.
+		let txhashset = self.txhashset.read();
 		let header = self.store.head_header()?;
 
 		// Lets just treat an "empty" node that just got started up as valid.
@@ -564,18 +1151,8 @@ impl Chain {
 			return Ok(());
 		}
 
-		let mut header_pmmr = self.header_pmmr.write();
-		let mut txhashset = self.txhashset.write();
-
-		// Now create an extension from the txhashset and validate against the
-		// latest block header. Rewind the extension to the specified header to
-		// ensure the view is consistent.
-		txhashset::extending_readonly(&mut header_pmmr, &mut txhashset, |ext, batch| {
-			pipe::rewind_and_apply_fork(&header, ext, batch)?;
-			ext.extension
-				.validate(&self.genesis, fast_validation, &NoStatus, &header)?;
-			Ok(())
-		})
+		txhashset.validate(&self.genesis, fast_validation, status, &header)?;
+		Ok(())
 	}
 
 	/// Sets the txhashset roots on a brand new block by applying the block on
@@ -639,6 +1216,42 @@ impl Chain {
 		Ok(merkle_proof)
 	}
 
+	/// Return the header of the chain head along with a merkle proof of the
+	/// output against its output root. Doesn't rewind, the head can't move
+	/// while we hold the txhashset.
+	pub fn get_merkle_proof_at_head(
+		&self,
+		output: &OutputIdentifier,
+	) -> Result<(BlockHeader, MerkleProof), Error> {
+		let mut txhashset = self.txhashset.write();
+		let head = self.head_header()?;
+		let merkle_proof = txhashset.merkle_proof(output.commit)?;
+		Ok((head, merkle_proof))
+	}
+
+	/// Return the head of the chain, the root of the header MMR committing to
+	/// all the headers up to the head and a Merkle proof of the given header
+	/// being one of them. The root is the prev_root of the blocks built on the
+	/// head, see core::core::merkle_proof::verify_header_inclusion.
+	pub fn get_header_inclusion_proof(
+		&self,
+		header: &BlockHeader,
+	) -> Result<(BlockHeader, Hash, MerkleProof), Error> {
+		let head = self.head_header()?;
+		let mut header_pmmr = self.header_pmmr.write();
+		let mut batch = self.store.batch()?;
+		let (root, merkle_proof) =
+			txhashset::header_extending(&mut header_pmmr, &mut batch, |ext, batch| {
+				ext.force_rollback();
+				// The header MMR follows the header head, possibly on a fork.
+				pipe::rewind_and_apply_header_fork(&head, ext, batch)?;
+				ext.is_on_current_chain(header, batch)?;
+				Ok((ext.root()?, ext.merkle_proof(header.height)?))
+			})?;
+
+		Ok((head, root, merkle_proof))
+	}
+
 	/// Return a merkle proof valid for the current output pmmr state at the
 	/// given pos
 	pub fn get_merkle_proof_for_pos(&self, commit: Commitment) -> Result<MerkleProof, Error> {
@@ -712,6 +1325,46 @@ impl Chain {
 		self.get_header_by_height(txhashset_height)
 	}
 
+	/// The segments of the txhashset at the archive header, served to the
+	/// peers syncing their state in segments. Built again whenever the archive
+	/// header moves, which takes a rewind of the txhashset to it.
+	pub fn segmenter(&self) -> Result<Arc<txhashset::Segmenter>, Error> {
+		let header = self.txhashset_archive_header()?;
+		if let Some(segmenter) = self.segmenter.read().as_ref() {
+			if segmenter.header().hash() == header.hash() {
+				return Ok(segmenter.clone());
+			}
+		}
+		if !txhashset::segments_supported(&header) {
+			return Err(ErrorKind::SegmentsUnavailable(format!(
+				"header version {:?} at {}",
+				header.version, header.height
+			))
+			.into());
+		}
+
+		let bitmap = {
+			let mut header_pmmr = self.header_pmmr.write();
+			let mut txhashset = self.txhashset.write();
+			txhashset::extending_readonly(&mut header_pmmr, &mut txhashset, |ext, batch| {
+				pipe::rewind_and_apply_fork(&header, ext, batch)?;
+				Ok(ext.extension.unspent_output_bitmap())
+			})?
+		};
+		let segmenter = Arc::new(txhashset::Segmenter::new(
+			self.txhashset.clone(),
+			header,
+			bitmap,
+		)?);
+		debug!(
+			"segmenter: serving the txhashset at {} at {}",
+			segmenter.header().hash(),
+			segmenter.header().height
+		);
+		*self.segmenter.write() = Some(segmenter.clone());
+		Ok(segmenter)
+	}
+
 	// Special handling to make sure the whole kernel set matches each of its
 	// roots in each block header, without truncation. We go back header by
 	// header, rewind and check each root. This fixes a potential weakness in
@@ -721,17 +1374,22 @@ impl Chain {
 		&self,
 		header: &BlockHeader,
 		txhashset: &txhashset::TxHashSet,
+		status: &dyn TxHashsetWriteStatus,
 	) -> Result<(), Error> {
 		debug!("validate_kernel_history: rewinding and validating kernel history (readonly)");
 
 		let mut count = 0;
 		let mut current = header.clone();
+		status.on_validation_kernel_history(0, header.height);
 		txhashset::rewindable_kernel_view(&txhashset, |view, batch| {
 			while current.height > 0 {
 				view.rewind(&current)?;
 				view.validate_root()?;
 				current = batch.get_previous_header(&current)?;
 				count += 1;
+				if count % KERNEL_HISTORY_PROGRESS_INTERVAL == 0 || current.height == 0 {
+					status.on_validation_kernel_history(count, header.height);
+				}
 			}
 			Ok(())
 		})?;
@@ -748,6 +1406,7 @@ impl Chain {
 	/// We rebuild the sync MMR when first entering sync mode so ensure we
 	/// have an MMR we can safely rewind based on the headers received from a peer.
 	pub fn rebuild_sync_mmr(&self, head: &Tip) -> Result<(), Error> {
+		self.check_writable()?;
 		let mut sync_pmmr = self.sync_pmmr.write();
 		let mut batch = self.store.batch()?;
 		let header = batch.get_block_header(&head.hash())?;
@@ -834,6 +1493,12 @@ impl Chain {
 			}
 		}
 
+		// Archive nodes keep all the full blocks, they get them all rather
+		// than the txhashset at the horizon.
+		if self.archive_mode {
+			return Ok(false);
+		}
+
 		if oldest_height < header_head.height.saturating_sub(horizon) {
 			if oldest_hash != ZERO_HASH {
 				// this is the normal case. for example:
@@ -901,6 +1566,20 @@ impl Chain {
 		txhashset_data: File,
 		status: &dyn TxHashsetWriteStatus,
 	) -> Result<bool, Error> {
+		self.txhashset_write_from(h, txhashset_data, status, None)
+	}
+
+	/// Same as `txhashset_write`, recording the peer that sent the txhashset
+	/// if it's bad. An archive that already failed validation is turned down
+	/// again without unzipping it.
+	pub fn txhashset_write_from(
+		&self,
+		h: Hash,
+		mut txhashset_data: File,
+		status: &dyn TxHashsetWriteStatus,
+		peer: Option<String>,
+	) -> Result<bool, Error> {
+		self.check_writable()?;
 		status.on_setup();
 
 		// Initial check whether this txhashset is needed or not
@@ -919,40 +1598,114 @@ impl Chain {
 			}
 		};
 
+		let bad = BadTxHashSet {
+			header: h,
+			archive: Some(archive_hash(&mut txhashset_data)?),
+			peer,
+		};
+		let rejected = match bad.archive {
+			Some(ref archive) => self.bad_txhashsets.read().archives.contains_key(archive),
+			None => false,
+		};
+		if rejected {
+			warn!("txhashset_write: archive for {} already rejected", h);
+			self.add_bad_txhashset(bad);
+			return Ok(true);
+		}
+
 		// Write txhashset to sandbox (in the Kepler specific tmp dir)
 		let sandbox_dir = self.get_tmp_dir();
 		txhashset::clean_txhashset_folder(&sandbox_dir);
-		txhashset::zip_write(sandbox_dir.clone(), txhashset_data.try_clone()?, &header)?;
+		txhashset::zip_write(
+			sandbox_dir.clone(),
+			txhashset_data.try_clone()?,
+			&header,
+			status,
+		)?;
+
+		let is_bad_data = self.txhashset_validate_and_replace(h, &header, sandbox_dir, status)?;
+		if is_bad_data {
+			self.add_bad_txhashset(bad);
+		}
+		Ok(is_bad_data)
+	}
 
+	// Validate the txhashset at the header written to the sandbox folder,
+	// downloaded as a zip or in segments, then move it in place of ours.
+	// Returns true if the txhashset is invalid, the caller banning its
+	// providers.
+	fn txhashset_validate_and_replace(
+		&self,
+		h: Hash,
+		header: &BlockHeader,
+		sandbox_dir: PathBuf,
+		status: &dyn TxHashsetWriteStatus,
+	) -> Result<bool, Error> {
 		let mut txhashset = txhashset::TxHashSet::open(
 			sandbox_dir
 				.to_str()
 				.expect("invalid sandbox folder")
 				.to_owned(),
 			self.store.clone(),
-			Some(&header),
+			Some(header),
 		)?;
 
 		// Validate the full kernel history (kernel MMR root for every block header).
-		self.validate_kernel_history(&header, &txhashset)?;
+		if let Err(e) = self.validate_kernel_history(header, &txhashset, status) {
+			return self.reject_txhashset(h, e);
+		}
+
+		// Below a checkpoint of a trusted operator the rangeproofs and kernel
+		// signatures were already verified by them.
+		let trusted = self.trusted_checkpoint(header);
+		if let Some(ref signed) = trusted {
+			info!(
+				"txhashset_write: {} at {} is below checkpoint {} at {} signed by {}, \
+				 skipping rangeproof and kernel signature verification",
+				header.hash(),
+				header.height,
+				signed.checkpoint.hash,
+				signed.checkpoint.height,
+				operator_id(&signed.operator)
+			);
+		}
+		let assumed_valid = trusted.is_none() && self.is_assumed_valid(header);
+		if assumed_valid {
+			info!(
+				"txhashset_write: {} at {} is below the assume-valid height, \
+				 skipping rangeproof verification",
+				header.hash(),
+				header.height,
+			);
+		}
 
 		// all good, prepare a new batch and update all the required records
 		debug!("txhashset_write: rewinding a 2nd time (writeable)");
 
 		let mut header_pmmr = self.header_pmmr.write();
 		let mut batch = self.store.batch()?;
-		txhashset::extending(
+		let res = txhashset::extending(
 			&mut header_pmmr,
 			&mut txhashset,
 			&mut batch,
 			|ext, batch| {
 				let extension = &mut ext.extension;
-				extension.rewind(&header, batch)?;
+				extension.rewind(header, batch)?;
 
 				// Validate the extension, generating the utxo_sum and kernel_sum.
-				// Full validation, including rangeproofs and kernel signature verification.
-				let (utxo_sum, kernel_sum) =
-					extension.validate(&self.genesis, false, status, &header)?;
+				// Full validation, including rangeproofs and kernel signature
+				// verification, unless below a trusted checkpoint or the
+				// assume-valid height. Only the sync waits on the sandbox, so
+				// it's verified on the background threads.
+				let (utxo_sum, kernel_sum) = transaction::in_background(|| {
+					extension.validate(
+						&self.genesis,
+						trusted.is_some(),
+						assumed_valid,
+						status,
+						header,
+					)
+				})?;
 
 				// Save the block_sums (utxo_sum, kernel_sum) to the db for use later.
 				batch.save_block_sums(
@@ -965,7 +1718,10 @@ impl Chain {
 
 				Ok(())
 			},
-		)?;
+		);
+		if let Err(e) = res {
+			return self.reject_txhashset(h, e);
+		}
 
 		debug!("txhashset_write: finished validating and rebuilding");
 
@@ -973,7 +1729,7 @@ impl Chain {
 
 		// Save the new head to the db and rebuild the header by height index.
 		{
-			let tip = Tip::from_header(&header);
+			let tip = Tip::from_header(header);
 			batch.save_body_head(&tip)?;
 
 			// Reset the body tail to the body head after a txhashset write
@@ -983,6 +1739,9 @@ impl Chain {
 		// Rebuild our output_pos index in the db based on fresh UTXO set.
 		txhashset.init_output_pos_index(&header_pmmr, &batch)?;
 
+		// Rebuild our kernel_pos index in the db based on the fresh kernel MMR.
+		txhashset.init_kernel_pos_index(&header_pmmr, &batch)?;
+
 		// Commit all the changes to the db.
 		batch.commit()?;
 
@@ -1000,11 +1759,8 @@ impl Chain {
 			txhashset::txhashset_replace(sandbox_dir, PathBuf::from(self.db_root.clone()))?;
 
 			// Re-open on db root dir
-			txhashset = txhashset::TxHashSet::open(
-				self.db_root.clone(),
-				self.store.clone(),
-				Some(&header),
-			)?;
+			txhashset =
+				txhashset::TxHashSet::open(self.db_root.clone(), self.store.clone(), Some(header))?;
 
 			// Replace the chain txhashset with the newly built one.
 			*txhashset_ref = txhashset;
@@ -1017,6 +1773,184 @@ impl Chain {
 		Ok(false)
 	}
 
+	/// Start rebuilding the txhashset at the header from its segments. Keeps
+	/// the segments already received if it's the header being rebuilt,
+	/// otherwise starts over.
+	pub fn init_desegmenter(&self, header: &BlockHeader) -> Result<(), Error> {
+		self.check_writable()?;
+		let mut desegmenter = self.desegmenter.write();
+		if let Some(ref d) = *desegmenter {
+			if d.header().hash() == header.hash() {
+				return Ok(());
+			}
+		}
+		*desegmenter = Some(txhashset::Desegmenter::new(
+			header.clone(),
+			self.get_tmp_dir(),
+		)?);
+		Ok(())
+	}
+
+	/// Drop the txhashset being rebuilt from its segments, if any.
+	pub fn reset_desegmenter(&self) {
+		if self.desegmenter.write().take().is_some() {
+			self.clean_txhashset_sandbox();
+		}
+	}
+
+	/// The header of the txhashset being rebuilt from its segments, if any.
+	pub fn desegmenter_header(&self) -> Option<BlockHeader> {
+		self.desegmenter.read().as_ref().map(|d| d.header().clone())
+	}
+
+	/// Segments received and total number of segments of the txhashset being
+	/// rebuilt, see `Desegmenter::progress`.
+	pub fn desegmenter_progress(&self) -> Option<(u64, Option<u64>)> {
+		self.desegmenter.read().as_ref().map(|d| d.progress())
+	}
+
+	/// Whether all the segments of the txhashset being rebuilt are in.
+	pub fn desegmenter_complete(&self) -> bool {
+		self.desegmenter
+			.read()
+			.as_ref()
+			.map(|d| d.is_complete())
+			.unwrap_or(false)
+	}
+
+	/// Up to max segments of the txhashset being rebuilt to request next,
+	/// skipping the ones in flight.
+	pub fn next_desired_segments(
+		&self,
+		max: usize,
+		in_flight: &HashSet<txhashset::SegmentId>,
+	) -> Vec<txhashset::SegmentId> {
+		self.desegmenter
+			.read()
+			.as_ref()
+			.map(|d| d.next_desired_segments(max, in_flight))
+			.unwrap_or_default()
+	}
+
+	/// Add a segment of the bitmap accumulator at the block, with the root of
+	/// the output MMR and the size of the accumulator MMR sent along. Errors
+	/// if the segment is invalid, segments for another block are ignored.
+	pub fn add_bitmap_segment(
+		&self,
+		block_hash: Hash,
+		segment: Segment<txhashset::BitmapChunk>,
+		output_pmmr_root: Hash,
+		bitmap_mmr_size: u64,
+	) -> Result<(), Error> {
+		self.with_desegmenter(block_hash, |d| {
+			d.add_bitmap_segment(segment, output_pmmr_root, bitmap_mmr_size)
+		})
+	}
+
+	/// Add a segment of the output MMR at the block.
+	pub fn add_output_segment(
+		&self,
+		block_hash: Hash,
+		segment: Segment<OutputIdentifier>,
+	) -> Result<(), Error> {
+		self.with_desegmenter(block_hash, |d| d.add_output_segment(segment))
+	}
+
+	/// Add a segment of the rangeproof MMR at the block.
+	pub fn add_rangeproof_segment(
+		&self,
+		block_hash: Hash,
+		segment: Segment<RangeProof>,
+	) -> Result<(), Error> {
+		self.with_desegmenter(block_hash, |d| d.add_rangeproof_segment(segment))
+	}
+
+	/// Add a segment of the kernel MMR at the block.
+	pub fn add_kernel_segment(
+		&self,
+		block_hash: Hash,
+		segment: Segment<TxKernel>,
+	) -> Result<(), Error> {
+		self.with_desegmenter(block_hash, |d| d.add_kernel_segment(segment))
+	}
+
+	fn with_desegmenter<F>(&self, block_hash: Hash, f: F) -> Result<(), Error>
+	where
+		F: FnOnce(&mut txhashset::Desegmenter) -> Result<(), Error>,
+	{
+		match self.desegmenter.write().as_mut() {
+			Some(d) if d.header().hash() == block_hash => f(d),
+			_ => Ok(()),
+		}
+	}
+
+	/// Validate the txhashset rebuilt from its segments and make it ours, as
+	/// `txhashset_write` does for a zip. Returns true if it's invalid, the
+	/// segments having been checked against the header as they came the
+	/// header chain is the one to blame then.
+	pub fn txhashset_write_segments(
+		&self,
+		status: &dyn TxHashsetWriteStatus,
+	) -> Result<bool, Error> {
+		self.check_writable()?;
+		status.on_setup();
+
+		let desegmenter = self.desegmenter.write().take().ok_or_else(|| {
+			ErrorKind::TxHashSetErr("no txhashset rebuilt from segments".to_owned())
+		})?;
+		let header = desegmenter.header().clone();
+
+		let mut hashes: Option<Vec<Hash>> = None;
+		if !self.check_txhashset_needed("txhashset_write_segments".to_owned(), &mut hashes)? {
+			warn!("txhashset_write_segments: txhashset rebuilt but it's not needed! ignored.");
+			self.clean_txhashset_sandbox();
+			return Err(ErrorKind::InvalidTxHashSet("not needed".to_owned()).into());
+		}
+
+		let sandbox_dir = desegmenter.finalize()?;
+		let h = header.hash();
+		let is_bad_data = self.txhashset_validate_and_replace(h, &header, sandbox_dir, status)?;
+		if is_bad_data {
+			self.add_bad_txhashset(BadTxHashSet {
+				header: h,
+				archive: None,
+				peer: None,
+			});
+		}
+		Ok(is_bad_data)
+	}
+
+	/// Downloaded txhashsets that failed validation.
+	pub fn bad_txhashsets(&self) -> Vec<BadTxHashSet> {
+		self.bad_txhashsets
+			.read()
+			.rejected
+			.iter()
+			.cloned()
+			.collect()
+	}
+
+	/// Whether the peer sent us a txhashset that failed validation, not to
+	/// be asked for one again.
+	pub fn is_bad_txhashset_provider(&self, peer: &str) -> bool {
+		self.bad_txhashsets.read().peers.contains_key(peer)
+	}
+
+	fn add_bad_txhashset(&self, bad: BadTxHashSet) {
+		self.bad_txhashsets.write().add(bad);
+	}
+
+	// A downloaded txhashset that fails validation is the fault of the peer that
+	// provided it, reported as bad data so the provider is banned. Other errors
+	// (db, io) are returned as is.
+	fn reject_txhashset(&self, h: Hash, e: Error) -> Result<bool, Error> {
+		if !e.is_bad_data() {
+			return Err(e);
+		}
+		error!("txhashset_write: invalid txhashset for {}: {}", h, e);
+		Ok(true)
+	}
+
 	/// Cleanup old blocks from the db.
 	/// Determine the cutoff height from the horizon and the current block height.
 	/// *Only* runs if we are not in archive mode.
@@ -1056,6 +1990,7 @@ impl Chain {
 		// here b is a block
 		for (_, b) in batch.blocks_iter()? {
 			if b.header.height < tail.height {
+				let _ = batch.delete_spent_outputs_pos_height(&b);
 				let _ = batch.delete_block(&b.hash());
 				count += 1;
 			}
@@ -1071,12 +2006,26 @@ impl Chain {
 		Ok(())
 	}
 
+	/// Close and reopen the files of all the MMRs, handing the pages read
+	/// so far through their memory maps back to the OS. Those only grow
+	/// otherwise, as the node keeps serving old outputs, proofs and headers.
+	pub fn reopen_mmr_files(&self) -> Result<(), Error> {
+		let mut sync_pmmr = self.sync_pmmr.write();
+		let mut header_pmmr = self.header_pmmr.write();
+		let mut txhashset = self.txhashset.write();
+		sync_pmmr.backend.reopen_files()?;
+		header_pmmr.backend.reopen_files()?;
+		txhashset.reopen_backend_files()
+	}
+
 	/// Triggers chain compaction.
 	///
 	/// * compacts the txhashset based on current prune_list
 	/// * removes historical blocks and associated data from the db (unless archive mode)
 	///
+	/// Skipped if another compaction is running, see `compaction_status`.
 	pub fn compact(&self) -> Result<(), Error> {
+		self.check_writable()?;
 		// A node may be restarted multiple times in a short period of time.
 		// We compact at most once per 60 blocks in this situation by comparing
 		// current "head" and "tail" height to our cut-through horizon and
@@ -1094,23 +2043,56 @@ impl Chain {
 			}
 		}
 
+		{
+			let mut status = self.compaction.write();
+			if status.running {
+				debug!("compact: skipping, a compaction is already running");
+				return Ok(());
+			}
+			status.running = true;
+			status.last_started = Some(self.clock.now().timestamp());
+		}
+		let size_before = file::dir_size(Path::new(&self.db_root));
+		let res = self.compact_state();
+		let size_after = file::dir_size(Path::new(&self.db_root));
+
+		let mut status = self.compaction.write();
+		status.running = false;
+		status.last_finished = Some(self.clock.now().timestamp());
+		status.bytes_reclaimed = Some(size_before.saturating_sub(size_after));
+		status.last_error = res.as_ref().err().map(|e| e.to_string());
+		res
+	}
+
+	/// The last compaction of the chain state and whether one is running.
+	pub fn compaction_status(&self) -> CompactionStatus {
+		self.compaction.read().clone()
+	}
+
+	fn compact_state(&self) -> Result<(), Error> {
 		// Take a write lock on the txhashet and start a new writeable db batch.
 		let header_pmmr = self.header_pmmr.read();
 		let mut txhashset = self.txhashset.write();
 		let batch = self.store.batch()?;
 
-		// Compact the txhashset itself (rewriting the pruned backend files).
-		{
-			let head_header = batch.head_header()?;
-			let current_height = head_header.height;
-			let horizon_height =
-				current_height.saturating_sub(global::cut_through_horizon().into());
-			let horizon_hash = header_pmmr.get_header_hash_by_height(horizon_height)?;
-			let horizon_header = batch.get_block_header(&horizon_hash)?;
+		let head_header = batch.head_header()?;
+		let horizon_height = head_header
+			.height
+			.saturating_sub(global::cut_through_horizon().into());
+		let horizon_hash = header_pmmr.get_header_hash_by_height(horizon_height)?;
+		let horizon_header = batch.get_block_header(&horizon_hash)?;
 
+		// Compact the txhashset itself (rewriting the pruned backend files),
+		// unless in archive mode where the spent outputs are kept.
+		if !self.archive_mode {
 			txhashset.compact(&horizon_header, &batch)?;
 		}
 
+		// Kernels seen before the horizon are long confirmed, or never will be.
+		let pruned =
+			batch.delete_kernels_first_seen_before(horizon_header.timestamp.timestamp_millis())?;
+		debug!("compact: pruned {} kernel first seen times", pruned);
+
 		// If we are not in archival mode remove historical blocks from the db.
 		if !self.archive_mode {
 			self.remove_historical_blocks(&header_pmmr, &batch)?;
@@ -1145,6 +2127,16 @@ impl Chain {
 		Ok(self.txhashset.read().get_output_pos(commit)?)
 	}
 
+	/// MMR position and height of a spent output, as long as the block
+	/// spending it is still around, so always on archive nodes.
+	pub fn get_spent_output_pos(&self, commit: &Commitment) -> Result<CommitPos, Error> {
+		let (pos, height) = self
+			.store
+			.get_spent_output_pos_height(commit)
+			.map_err(|e| ErrorKind::StoreErr(e, "chain get spent output".to_owned()))?;
+		Ok(CommitPos { pos, height })
+	}
+
 	/// outputs by insertion index
 	pub fn unspent_outputs_by_pmmr_index(
 		&self,
@@ -1197,6 +2189,70 @@ impl Chain {
 		))
 	}
 
+	/// First and last kernel MMR indices for the blocks in the given height
+	/// range (inclusive), the end defaulting to the current head.
+	pub fn block_height_range_to_kernel_indices(
+		&self,
+		start_block_height: u64,
+		end_block_height: Option<u64>,
+	) -> Result<(u64, u64), Error> {
+		let end_block_height = match end_block_height {
+			Some(h) => h,
+			None => self.head_header()?.height,
+		};
+		let start_index = if start_block_height == 0 {
+			1
+		} else {
+			self.get_header_by_height(start_block_height - 1)?
+				.kernel_mmr_size
+				+ 1
+		};
+		let end_header = self.get_header_by_height(end_block_height)?;
+		Ok((start_index, end_header.kernel_mmr_size))
+	}
+
+	/// Kernels included in the blocks in the given height range (inclusive), each
+	/// with the height of its block and its kernel MMR index.
+	/// Returns at most max_count kernels, starting from start_index if provided,
+	/// so large ranges can be paged through. Also returns the last kernel MMR
+	/// index in the range.
+	pub fn kernels_by_block_height(
+		&self,
+		start_block_height: u64,
+		end_block_height: Option<u64>,
+		start_index: Option<u64>,
+		max_count: u64,
+	) -> Result<(u64, Vec<(TxKernel, u64, u64)>), Error> {
+		let (first_index, last_index) =
+			self.block_height_range_to_kernel_indices(start_block_height, end_block_height)?;
+		let start_index = start_index.map_or(first_index, |i| i.max(first_index));
+		let kernels =
+			self.txhashset
+				.read()
+				.kernels_by_pmmr_index(start_index, max_count, last_index);
+
+		let mut res = vec![];
+		let mut header: Option<BlockHeader> = None;
+		for (kernel, mmr_index) in kernels {
+			let h = match header.take() {
+				Some(mut h) => {
+					while mmr_index > h.kernel_mmr_size {
+						h = self.get_header_by_height(h.height + 1)?;
+					}
+					h
+				}
+				None => self.get_header_for_kernel_index(
+					mmr_index,
+					Some(start_block_height),
+					end_block_height,
+				)?,
+			};
+			res.push((kernel, h.height, mmr_index));
+			header = Some(h);
+		}
+		Ok((last_index, res))
+	}
+
 	/// Orphans pool size
 	pub fn orphans_len(&self) -> usize {
 		self.orphans.len()
@@ -1251,6 +2307,16 @@ impl Chain {
 			.map_err(|e| ErrorKind::StoreErr(e, "chain get block".to_owned()).into())
 	}
 
+	/// Gets the compact filter of the commitments of a block by hash. Built
+	/// from the block itself if it was stored before filters were.
+	pub fn get_block_filter(&self, h: &Hash) -> Result<BlockFilter, Error> {
+		match self.store.get_block_filter(h) {
+			Ok(filter) => Ok(filter),
+			Err(NotFoundErr(_)) => Ok(BlockFilter::from_block(&self.get_block(h)?)),
+			Err(e) => Err(ErrorKind::StoreErr(e, "chain get block filter".to_owned()).into()),
+		}
+	}
+
 	/// Gets a block header by hash
 	pub fn get_block_header(&self, h: &Hash) -> Result<BlockHeader, Error> {
 		self.store
@@ -1272,6 +2338,162 @@ impl Chain {
 			.map_err(|e| ErrorKind::StoreErr(e, "chain get block_sums".to_owned()).into())
 	}
 
+	/// Get the local arrival time of a block by hash.
+	/// Blocks received via txhashset sync will have no arrival time recorded.
+	pub fn get_block_arrival(&self, h: &Hash) -> Result<DateTime<Utc>, Error> {
+		let millis = self
+			.store
+			.get_block_arrival(h)
+			.map_err(|e| ErrorKind::StoreErr(e, "chain get block arrival".to_owned()))?;
+		Ok(Utc.timestamp_millis(millis))
+	}
+
+	/// Record the kernels with the excesses as seen at the provided times,
+	/// all in a single db transaction. Those seen before keep their first
+	/// seen time.
+	pub fn record_kernels_seen(&self, seen: &[(Commitment, DateTime<Utc>)]) -> Result<(), Error> {
+		let batch = self.store.batch()?;
+		for (excess, at) in seen {
+			batch.save_kernel_first_seen(excess, at.timestamp_millis())?;
+		}
+		batch.commit()?;
+		Ok(())
+	}
+
+	/// Get the local time the kernel with the excess was first seen at, in a
+	/// transaction entering our pool or in a block.
+	pub fn get_kernel_first_seen(&self, excess: &Commitment) -> Result<DateTime<Utc>, Error> {
+		let millis = self
+			.store
+			.get_kernel_first_seen(excess)
+			.map_err(|e| ErrorKind::StoreErr(e, "chain get kernel first seen".to_owned()))?;
+		Ok(Utc.timestamp_millis(millis))
+	}
+
+	/// Receive latency of a block in millis, the local arrival time minus
+	/// the timestamp in the block header. Can be negative if the header
+	/// timestamp is ahead of our local clock.
+	pub fn get_block_latency(&self, header: &BlockHeader) -> Result<i64, Error> {
+		let arrived = self.get_block_arrival(&header.hash())?;
+		Ok(arrived.timestamp_millis() - header.timestamp.timestamp_millis())
+	}
+
+	/// Median receive latency in millis for the blocks on the current chain in
+	/// the window of heights (end_height - window, end_height].
+	/// Blocks without a recorded arrival time are skipped, returns None if
+	/// there are no blocks with an arrival time in the window.
+	pub fn median_block_latency(&self, end_height: u64, window: u64) -> Result<Option<i64>, Error> {
+		let start_height = end_height.saturating_sub(window);
+		let mut latencies = vec![];
+		for header in self.headers_by_height(start_height + 1, end_height)? {
+			if let Ok(latency) = self.get_block_latency(&header?) {
+				latencies.push(latency);
+			}
+		}
+		if latencies.is_empty() {
+			return Ok(None);
+		}
+		latencies.sort_unstable();
+		let mid = latencies.len() / 2;
+		let median = if latencies.len() % 2 == 0 {
+			(latencies[mid - 1] + latencies[mid]) / 2
+		} else {
+			latencies[mid]
+		};
+		Ok(Some(median))
+	}
+
+	/// Weight and fees of the last n blocks of the current chain, from latest
+	/// to earliest. Stops early at the genesis or past the blocks we have.
+	pub fn latest_block_fees(&self, n: u64) -> Result<Vec<BlockFees>, Error> {
+		let head = self.head()?;
+		let start_height = (head.height + 1).saturating_sub(n).max(1);
+		let mut fees = vec![];
+		for header in self.headers_by_height(start_height, head.height)? {
+			let hash = header?.hash();
+			if let Some(x) = self.block_fees.write().get_mut(&hash) {
+				fees.push(*x);
+				continue;
+			}
+			match self.get_block(&hash) {
+				Ok(b) => {
+					let x = BlockFees::from_block(&b);
+					self.block_fees.write().insert(hash, x);
+					fees.push(x);
+				}
+				Err(e) => match e.kind() {
+					// Pruned already, only the later blocks are left.
+					ErrorKind::StoreErr(NotFoundErr(_), _) => fees.clear(),
+					_ => return Err(e),
+				},
+			}
+		}
+		fees.reverse();
+		Ok(fees)
+	}
+
+	/// Save an hourly node statistics snapshot, deleting the snapshots taken
+	/// before the provided time (seconds since epoch).
+	pub fn save_stats_snapshot(
+		&self,
+		snapshot: &StatsSnapshot,
+		prune_before: i64,
+	) -> Result<(), Error> {
+		self.check_writable()?;
+		let expired: Vec<i64> = self
+			.store
+			.stats_snapshots_from(0)?
+			.map(|old| old.timestamp)
+			.take_while(|t| *t < prune_before)
+			.collect();
+		let batch = self.store.batch()?;
+		for timestamp in expired {
+			batch.delete_stats_snapshot(timestamp)?;
+		}
+		batch.save_stats_snapshot(snapshot)?;
+		batch.commit()?;
+		Ok(())
+	}
+
+	/// Node statistics snapshots taken since the provided time (seconds since
+	/// epoch), oldest first.
+	pub fn stats_history(&self, since: i64) -> Result<Vec<StatsSnapshot>, Error> {
+		Ok(self.store.stats_snapshots_from(since)?.collect())
+	}
+
+	/// Reorgs of our chain since the provided time (seconds since epoch),
+	/// oldest first. Only the most recent ones are kept.
+	pub fn reorgs(&self, since: i64) -> Result<Vec<ReorgRecord>, Error> {
+		Ok(self.store.reorgs_from(since)?.collect())
+	}
+
+	/// Whether this node retains full block history.
+	pub fn archive_mode(&self) -> bool {
+		self.archive_mode
+	}
+
+	/// The outputs spent by the inputs of the given block, in input order,
+	/// along with the MMR position and height each output was created at.
+	/// Only available on archive nodes as the spent index and spent output
+	/// data are otherwise removed once beyond the horizon.
+	pub fn get_spent_outputs(&self, h: &Hash) -> Result<Vec<(OutputIdentifier, CommitPos)>, Error> {
+		if !self.archive_mode {
+			return Err(ErrorKind::Other("spent outputs require archive mode".to_owned()).into());
+		}
+		let spent = self
+			.store
+			.get_spent_index(h)
+			.map_err(|e| ErrorKind::StoreErr(e, "chain get spent index".to_owned()))?;
+		let txhashset = self.txhashset.read();
+		spent
+			.into_iter()
+			.map(|pos| match txhashset.get_output_from_file(pos.pos) {
+				Some(out) => Ok((out, pos)),
+				None => Err(ErrorKind::OutputNotFound.into()),
+			})
+			.collect()
+	}
+
 	/// Gets the block header at the provided height.
 	/// Note: Takes a read lock on the header_pmmr.
 	pub fn get_header_by_height(&self, height: u64) -> Result<BlockHeader, Error> {
@@ -1285,6 +2507,44 @@ impl Chain {
 		self.header_pmmr.read().get_header_hash_by_height(height)
 	}
 
+	/// Iterator over the headers of the header chain in the provided
+	/// (inclusive) height range, capped at the header head.
+	/// Note: Takes a read lock on the header_pmmr while resolving the hashes,
+	/// headers are read lazily afterwards.
+	pub fn headers_by_height(
+		&self,
+		start_height: u64,
+		end_height: u64,
+	) -> Result<HeightIter<BlockHeader>, Error> {
+		let hashes = self.header_hashes_by_height(start_height, end_height)?;
+		Ok(HeightIter::headers(self.store.clone(), hashes))
+	}
+
+	/// Iterator over the full blocks of the chain in the provided (inclusive)
+	/// height range, capped at the chain head. Blocks that were compacted away
+	/// are returned as errors.
+	pub fn blocks_by_height(
+		&self,
+		start_height: u64,
+		end_height: u64,
+	) -> Result<HeightIter<Block>, Error> {
+		let end_height = cmp::min(end_height, self.head()?.height);
+		let hashes = self.header_hashes_by_height(start_height, end_height)?;
+		Ok(HeightIter::blocks(self.store.clone(), hashes))
+	}
+
+	fn header_hashes_by_height(
+		&self,
+		start_height: u64,
+		end_height: u64,
+	) -> Result<Vec<Hash>, Error> {
+		let header_pmmr = self.header_pmmr.read();
+		let end_height = cmp::min(end_height, self.read_header_head(&header_pmmr)?.height);
+		(start_height..=end_height)
+			.map(|height| header_pmmr.get_header_hash_by_height(height))
+			.collect()
+	}
+
 	/// Migrate our local db from v1 to v2.
 	/// This covers blocks which themselves contain transactions.
 	/// Transaction kernels changed in v2 due to "variable size kernels".
@@ -1298,6 +2558,15 @@ impl Chain {
 		Ok(())
 	}
 
+	fn init_kernel_pos_index(&self) -> Result<(), Error> {
+		let header_pmmr = self.header_pmmr.read();
+		let txhashset = self.txhashset.read();
+		let batch = self.store.batch()?;
+		txhashset.init_kernel_pos_index(&header_pmmr, &batch)?;
+		batch.commit()?;
+		Ok(())
+	}
+
 	/// Gets the block header in which a given output appears in the txhashset.
 	pub fn get_header_for_output(
 		&self,
@@ -1310,13 +2579,13 @@ impl Chain {
 		Ok(self.get_block_header(&hash)?)
 	}
 
-	/// Gets the kernel with a given excess and the block height it is included in.
-	pub fn get_kernel_height(
+	// Kernel MMR indices bounding the kernels of the blocks between the
+	// provided heights.
+	fn kernel_index_range(
 		&self,
-		excess: &Commitment,
 		min_height: Option<u64>,
 		max_height: Option<u64>,
-	) -> Result<Option<(TxKernel, u64, u64)>, Error> {
+	) -> Result<(Option<u64>, Option<u64>), Error> {
 		let min_index = match min_height {
 			Some(h) => Some(self.get_header_by_height(h - 1)?.kernel_mmr_size + 1),
 			None => None,
@@ -1326,6 +2595,17 @@ impl Chain {
 			Some(h) => Some(self.get_header_by_height(h)?.kernel_mmr_size),
 			None => None,
 		};
+		Ok((min_index, max_index))
+	}
+
+	/// Gets the kernel with a given excess and the block height it is included in.
+	pub fn get_kernel_height(
+		&self,
+		excess: &Commitment,
+		min_height: Option<u64>,
+		max_height: Option<u64>,
+	) -> Result<Option<(TxKernel, u64, u64)>, Error> {
+		let (min_index, max_index) = self.kernel_index_range(min_height, max_height)?;
 
 		let (kernel, mmr_index) = match self
 			.txhashset
@@ -1340,6 +2620,64 @@ impl Chain {
 
 		Ok(Some((kernel, header.height, mmr_index)))
 	}
+
+	/// Gets the latest kernel with the given excess, along with the header of
+	/// the block it was included in and its kernel MMR index. Looked up in the
+	/// kernel_pos index rather than searching the kernel MMR.
+	pub fn get_kernel_by_excess(
+		&self,
+		excess: &Commitment,
+	) -> Result<Option<(TxKernel, BlockHeader, u64)>, Error> {
+		let (pos, height) = match self.store.get_kernel_pos_height(excess) {
+			Ok(x) => x,
+			Err(NotFoundErr(_)) => return Ok(None),
+			Err(e) => {
+				return Err(ErrorKind::StoreErr(e, "chain get kernel pos".to_owned()).into());
+			}
+		};
+		let kernel = match self
+			.txhashset
+			.read()
+			.kernels_by_pmmr_index(pos, 1, pos)
+			.pop()
+		{
+			Some((kernel, _)) if kernel.excess == *excess => kernel,
+			// Left over from a block that isn't on the chain anymore.
+			_ => return Ok(None),
+		};
+		let header = self.get_header_by_height(height)?;
+		Ok(Some((kernel, header, pos)))
+	}
+
+	/// Gets the kernels with the given excesses along with the block heights
+	/// they are included in, in a single pass over the kernel MMR. Results
+	/// are in the order of the excesses, None for the ones not found.
+	pub fn get_blocks_by_kernel(
+		&self,
+		excesses: &[Commitment],
+		min_height: Option<u64>,
+		max_height: Option<u64>,
+	) -> Result<Vec<Option<(TxKernel, u64, u64)>>, Error> {
+		let (min_index, max_index) = self.kernel_index_range(min_height, max_height)?;
+
+		let search: HashSet<Commitment> = excesses.iter().cloned().collect();
+		let found = self
+			.txhashset
+			.read()
+			.find_kernels(&search, min_index, max_index);
+
+		excesses
+			.iter()
+			.map(|excess| match found.get(excess) {
+				Some((kernel, mmr_index)) => {
+					let header =
+						self.get_header_for_kernel_index(*mmr_index, min_height, max_height)?;
+					Ok(Some((kernel.clone(), header.height, *mmr_index)))
+				}
+				None => Ok(None),
+			})
+			.collect()
+	}
 	/// Gets the block header in which a given kernel mmr index appears in the txhashset.
 	pub fn get_header_for_kernel_index(
 		&self,
@@ -1548,3 +2886,61 @@ fn setup_head(
 	batch.commit()?;
 	Ok(())
 }
+
+// Hash of a txhashset zip archive, read from its start, leaving the file at
+// its start again.
+fn archive_hash(file: &mut File) -> Result<Hash, Error> {
+	let mut hasher = HashWriter::default();
+	let mut buf = vec![0; 64 * 1024];
+	file.seek(SeekFrom::Start(0))?;
+	loop {
+		let n = file.read(&mut buf)?;
+		if n == 0 {
+			break;
+		}
+		hasher
+			.write_fixed_bytes(&buf[..n])
+			.map_err(ErrorKind::SerErr)?;
+	}
+	file.seek(SeekFrom::Start(0))?;
+	Ok(hasher.into_hash())
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn test_bad_txhashsets() {
+		let bad = |i: u64| BadTxHashSet {
+			header: Hash::from_vec(&i.to_be_bytes()),
+			archive: Some(Hash::from_vec(&(i % 2).to_be_bytes())),
+			peer: Some(format!("10.0.0.{}:3414", i % 3)),
+		};
+
+		let mut bad_txhashsets = BadTxHashSets::new();
+		bad_txhashsets.add(bad(0));
+		bad_txhashsets.add(bad(0));
+		assert_eq!(bad_txhashsets.rejected.len(), 1);
+
+		for i in 1..=MAX_BAD_TXHASHSETS as u64 {
+			bad_txhashsets.add(bad(i));
+		}
+		assert_eq!(bad_txhashsets.rejected.len(), MAX_BAD_TXHASHSETS);
+		assert_eq!(bad_txhashsets.rejected[0], bad(1));
+		assert_eq!(
+			bad_txhashsets.archives.values().sum::<usize>(),
+			MAX_BAD_TXHASHSETS
+		);
+		assert_eq!(bad_txhashsets.peers.len(), 3);
+
+		// The senders are forgotten along with their last txhashset.
+		let mut bad_txhashsets = BadTxHashSets::new();
+		bad_txhashsets.add(bad(1));
+		for i in 0..MAX_BAD_TXHASHSETS as u64 {
+			bad_txhashsets.add(bad(3 * i + 3));
+		}
+		assert!(!bad_txhashsets.peers.contains_key("10.0.0.1:3414"));
+		assert!(bad_txhashsets.peers.contains_key("10.0.0.0:3414"));
+	}
+}
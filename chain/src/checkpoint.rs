@@ -0,0 +1,209 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Checkpoints of the chain state signed by a node operator. Nodes of a
+//! federation trusting the operator can import them to skip the most
+//! expensive checks of a txhashset matching one, and to refuse any header
+//! conflicting with them.
+
+use crate::core::core::hash::{DefaultHashable, Hash, Hashed};
+use crate::core::core::BlockHeader;
+use crate::core::global;
+use crate::core::ser::{self, Readable, Reader, Writeable, Writer};
+use crate::error::{Error, ErrorKind};
+use crate::util::secp::key::{PublicKey, SecretKey};
+use crate::util::secp::{Message, Signature};
+use crate::util::{static_secp_instance, to_hex};
+
+/// The hash of a block and the roots and MMR sizes of the chain state at
+/// that block, as committed to by its header.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checkpoint {
+	/// Height of the block
+	pub height: u64,
+	/// Hash of the block
+	pub hash: Hash,
+	/// Root of the header MMR up to the previous block
+	pub prev_root: Hash,
+	/// Root of the output MMR
+	pub output_root: Hash,
+	/// Root of the rangeproof MMR
+	pub range_proof_root: Hash,
+	/// Root of the kernel MMR
+	pub kernel_root: Hash,
+	/// Size of the output MMR
+	pub output_mmr_size: u64,
+	/// Size of the kernel MMR
+	pub kernel_mmr_size: u64,
+}
+
+impl DefaultHashable for Checkpoint {}
+
+impl Checkpoint {
+	/// The checkpoint of the given header.
+	pub fn from_header(header: &BlockHeader) -> Checkpoint {
+		Checkpoint {
+			height: header.height,
+			hash: header.hash(),
+			prev_root: header.prev_root,
+			output_root: header.output_root,
+			range_proof_root: header.range_proof_root,
+			kernel_root: header.kernel_root,
+			output_mmr_size: header.output_mmr_size,
+			kernel_mmr_size: header.kernel_mmr_size,
+		}
+	}
+
+	/// Whether the header is the one checkpointed. The hash commits to the
+	/// roots and sizes, they're compared too so a checkpoint built by hand
+	/// with a stale root doesn't pass.
+	pub fn matches(&self, header: &BlockHeader) -> bool {
+		*self == Checkpoint::from_header(header)
+	}
+}
+
+impl Writeable for Checkpoint {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_u64(self.height)?;
+		self.hash.write(writer)?;
+		self.prev_root.write(writer)?;
+		self.output_root.write(writer)?;
+		self.range_proof_root.write(writer)?;
+		self.kernel_root.write(writer)?;
+		writer.write_u64(self.output_mmr_size)?;
+		writer.write_u64(self.kernel_mmr_size)?;
+		Ok(())
+	}
+}
+
+impl Readable for Checkpoint {
+	fn read(reader: &mut dyn Reader) -> Result<Checkpoint, ser::Error> {
+		Ok(Checkpoint {
+			height: reader.read_u64()?,
+			hash: Hash::read(reader)?,
+			prev_root: Hash::read(reader)?,
+			output_root: Hash::read(reader)?,
+			range_proof_root: Hash::read(reader)?,
+			kernel_root: Hash::read(reader)?,
+			output_mmr_size: reader.read_u64()?,
+			kernel_mmr_size: reader.read_u64()?,
+		})
+	}
+}
+
+// What an operator actually signs: the checkpoint along with the chain type
+// and genesis hash of its chain, so a checkpoint of one chain doesn't verify
+// on another.
+struct CheckpointMessage<'a> {
+	chain_type: String,
+	genesis: &'a Hash,
+	checkpoint: &'a Checkpoint,
+}
+
+impl<'a> DefaultHashable for CheckpointMessage<'a> {}
+
+impl<'a> Writeable for CheckpointMessage<'a> {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_bytes(&self.chain_type)?;
+		self.genesis.write(writer)?;
+		self.checkpoint.write(writer)?;
+		Ok(())
+	}
+}
+
+impl<'a> CheckpointMessage<'a> {
+	fn new(checkpoint: &'a Checkpoint, genesis: &'a Hash) -> CheckpointMessage<'a> {
+		CheckpointMessage {
+			chain_type: global::CHAIN_TYPE.read().shortname(),
+			genesis,
+			checkpoint,
+		}
+	}
+
+	fn to_message(&self) -> Result<Message, Error> {
+		Ok(Message::from_slice(self.hash().as_bytes())?)
+	}
+}
+
+/// The compressed public key of an operator in hex, as listed in the trusted
+/// operators of the nodes importing their checkpoints.
+pub fn operator_id(operator: &PublicKey) -> String {
+	let secp = static_secp_instance();
+	let secp = secp.lock();
+	to_hex(operator.serialize_vec(&secp, true).to_vec())
+}
+
+/// A checkpoint signed with the identity key of the node that exported it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignedCheckpoint {
+	/// The checkpoint signed
+	pub checkpoint: Checkpoint,
+	/// Identity key of the operator's node
+	pub operator: PublicKey,
+	/// Signature by the operator of the checkpoint, bound to the chain type
+	/// and genesis hash of its chain
+	pub signature: Signature,
+}
+
+impl SignedCheckpoint {
+	/// Sign the checkpoint of the chain with the given genesis hash with the
+	/// node identity key.
+	pub fn sign(
+		checkpoint: Checkpoint,
+		genesis: &Hash,
+		key: &SecretKey,
+	) -> Result<SignedCheckpoint, Error> {
+		let msg = CheckpointMessage::new(&checkpoint, genesis).to_message()?;
+		let secp = static_secp_instance();
+		let secp = secp.lock();
+		let signature = secp.sign(&msg, key)?;
+		let operator = PublicKey::from_secret_key(&secp, key)?;
+		Ok(SignedCheckpoint {
+			checkpoint,
+			operator,
+			signature,
+		})
+	}
+
+	/// Check the checkpoint was signed by the key it names, for our chain type
+	/// and the chain with the given genesis hash. Says nothing of whether that
+	/// key is trusted.
+	pub fn verify(&self, genesis: &Hash) -> Result<(), Error> {
+		let msg = CheckpointMessage::new(&self.checkpoint, genesis).to_message()?;
+		let secp = static_secp_instance();
+		let secp = secp.lock();
+		secp.verify(&msg, &self.signature, &self.operator)
+			.map_err(|_| ErrorKind::InvalidCheckpoint("bad signature".to_owned()))?;
+		Ok(())
+	}
+}
+
+impl Writeable for SignedCheckpoint {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.checkpoint.write(writer)?;
+		self.operator.write(writer)?;
+		self.signature.write(writer)?;
+		Ok(())
+	}
+}
+
+impl Readable for SignedCheckpoint {
+	fn read(reader: &mut dyn Reader) -> Result<SignedCheckpoint, ser::Error> {
+		Ok(SignedCheckpoint {
+			checkpoint: Checkpoint::read(reader)?,
+			operator: PublicKey::read(reader)?,
+			signature: Signature::read(reader)?,
+		})
+	}
+}
@@ -35,42 +35,83 @@ pub enum ErrorKind {
 	/// The block doesn't fit anywhere in our chain
 	#[fail(display = "Block is unfit: {}", _0)]
 	Unfit(String),
+	/// The block was already processed
+	#[fail(display = "Block is already known: {}", _0)]
+	Known(String),
 	/// Special case of orphan blocks
 	#[fail(display = "Orphan")]
 	Orphan,
 	/// Difficulty is too low either compared to ours or the block PoW hash
-	#[fail(display = "Difficulty is too low compared to ours or the block PoW hash")]
-	DifficultyTooLow,
+	#[fail(
+		display = "Difficulty is too low compared to ours or the block PoW hash: {} < {}",
+		actual, expected
+	)]
+	DifficultyTooLow {
+		/// Minimum difficulty required
+		expected: u64,
+		/// Difficulty of the block
+		actual: u64,
+	},
 	/// Addition of difficulties on all previous block is wrong
-	#[fail(display = "Addition of difficulties on all previous blocks is wrong")]
-	WrongTotalDifficulty,
+	#[fail(
+		display = "Addition of difficulties on all previous blocks is wrong: {} != {}",
+		actual, expected
+	)]
+	WrongTotalDifficulty {
+		/// Network difficulty the block should add
+		expected: u64,
+		/// Difficulty the block adds
+		actual: u64,
+	},
 	/// Block header edge_bits is lower than our min
-	#[fail(display = "Cuckoo Size too small")]
-	LowEdgebits,
+	#[fail(display = "Cuckoo Size too small: {}", _0)]
+	LowEdgebits(u8),
 	/// Scaling factor between primary and secondary PoW is invalid
-	#[fail(display = "Wrong scaling factor")]
-	InvalidScaling,
+	#[fail(display = "Wrong scaling factor: {} != {}", actual, expected)]
+	InvalidScaling {
+		/// Network secondary scaling factor
+		expected: u32,
+		/// Secondary scaling factor of the block
+		actual: u32,
+	},
 	/// The proof of work is invalid
-	#[fail(display = "Invalid PoW")]
-	InvalidPow,
+	#[fail(display = "Invalid PoW with edge_bits {}", _0)]
+	InvalidPow(u8),
 	/// Peer abusively sending us an old block we already have
 	#[fail(display = "Old Block")]
 	OldBlock,
 	/// The block doesn't sum correctly or a tx signature is invalid
 	#[fail(display = "Invalid Block Proof")]
 	InvalidBlockProof(block::Error),
-	/// Block time is too old
-	#[fail(display = "Invalid Block Time")]
-	InvalidBlockTime,
+	/// Block time is too old or too far in the future
+	#[fail(display = "Invalid Block Time: {}", _0)]
+	InvalidBlockTime(String),
 	/// Block height is invalid (not previous + 1)
-	#[fail(display = "Invalid Block Height")]
-	InvalidBlockHeight,
-	/// One of the root hashes in the block is invalid
-	#[fail(display = "Invalid Root")]
-	InvalidRoot,
+	#[fail(display = "Invalid Block Height: {} != {}", actual, expected)]
+	InvalidBlockHeight {
+		/// Height following the previous block
+		expected: u64,
+		/// Height of the block
+		actual: u64,
+	},
+	/// One of the root hashes in the block is invalid, or couldn't be computed
+	#[fail(display = "Invalid Root: {}", _0)]
+	InvalidRoot(String),
 	/// One of the MMR sizes in the block header is invalid
-	#[fail(display = "Invalid MMR Size")]
-	InvalidMMRSize,
+	#[fail(display = "Invalid MMR Size: {}", _0)]
+	InvalidMMRSize(String),
+	/// The kernel sums don't match the sum of the UTXO set commitments once
+	/// the block is applied
+	#[fail(
+		display = "Kernel sum mismatch: utxo sum {:?}, kernel sum {:?}",
+		utxo_sum, kernel_sum
+	)]
+	KernelSumMismatch {
+		/// Sum of the UTXO set commitments, accounting for the overage
+		utxo_sum: Commitment,
+		/// Sum of the kernel excesses, accounting for the offset
+		kernel_sum: Commitment,
+	},
 	/// Error from underlying keychain impl
 	#[fail(display = "Keychain Error")]
 	Keychain(keychain::Error),
@@ -143,6 +184,32 @@ pub enum ErrorKind {
 	/// Error during chain sync
 	#[fail(display = "Sync error")]
 	SyncError(String),
+	/// The block doesn't apply to the chain state, its kernel sums, roots or
+	/// MMR sizes don't match. Only a malicious peer can send us such a block.
+	#[fail(display = "Invalid Block State: {}", _0)]
+	InvalidBlockState(String),
+	/// The data dir was written by a newer binary, opening it could run
+	/// destructive migrations
+	#[fail(
+		display = "Data written by a newer version: db schema {}, txhashset format {}",
+		_0, _1
+	)]
+	DataVersionTooNew(u32, u32),
+	/// The chain was opened read-only
+	#[fail(display = "Chain opened read-only")]
+	ReadOnly,
+	/// A checkpoint with a bad signature, or not signed by a trusted operator
+	#[fail(display = "Invalid Checkpoint: {}", _0)]
+	InvalidCheckpoint(String),
+	/// A header conflicts with the checkpoint at its height
+	#[fail(display = "Header conflicts with the checkpoint at {}", _0)]
+	CheckpointMismatch(u64),
+	/// A txhashset segment not matching the header it was requested for
+	#[fail(display = "Invalid Segment: {}", _0)]
+	InvalidSegment(String),
+	/// The txhashset segments at a header can't be served
+	#[fail(display = "Segments Unavailable: {}", _0)]
+	SegmentsUnavailable(String),
 }
 
 impl Display for Error {
@@ -182,15 +249,37 @@ impl Error {
 		// shorter to match on all the "not the block's fault" errors
 		match self.kind() {
 			ErrorKind::Unfit(_)
+			| ErrorKind::Known(_)
 			| ErrorKind::Orphan
 			| ErrorKind::StoreErr(_, _)
 			| ErrorKind::SerErr(_)
 			| ErrorKind::TxHashSetErr(_)
 			| ErrorKind::GenesisBlockRequired
+			| ErrorKind::ReadOnly
+			| ErrorKind::InvalidCheckpoint(_)
+			| ErrorKind::SegmentsUnavailable(_)
 			| ErrorKind::Other(_) => false,
 			_ => true,
 		}
 	}
+
+	/// Whether the error is due to a block we already processed, see the
+	/// known block checks of the pipe
+	pub fn is_known(&self) -> bool {
+		match self.kind() {
+			ErrorKind::Known(_) => true,
+			_ => false,
+		}
+	}
+
+	/// Whether the error is due to a block with a valid PoW that doesn't
+	/// apply to the chain state, which can only be crafted on purpose
+	pub fn is_invalid_block_state(&self) -> bool {
+		match self.kind() {
+			ErrorKind::InvalidBlockState(_) => true,
+			_ => false,
+		}
+	}
 }
 
 impl From<ErrorKind> for Error {
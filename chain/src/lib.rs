@@ -33,7 +33,9 @@ use kepler_core as core;
 use kepler_keychain as keychain;
 use kepler_util as util;
 
+pub mod block_files;
 mod chain;
+pub mod checkpoint;
 mod error;
 pub mod pipe;
 pub mod store;
@@ -43,8 +45,12 @@ pub mod types;
 // Re-export the base interface
 
 pub use crate::chain::{Chain, MAX_ORPHAN_SIZE};
+pub use crate::checkpoint::{Checkpoint, SignedCheckpoint};
 pub use crate::error::{Error, ErrorKind};
 pub use crate::store::ChainStore;
 pub use crate::types::{
-	BlockStatus, ChainAdapter, Options, SyncState, SyncStatus, Tip, TxHashsetWriteStatus,
+	BadTxHashSet, BlockFees, BlockStatus, ChainAdapter, ChainEvent, CommitPos, CompactionStatus,
+	DataVersion, HeadRacePolicy, Options, OrphanInfo, OrphanPoolLimits, OrphanPoolStats,
+	ReorgRecord, StatsSnapshot, SyncState, SyncStatus, Tip, TxHashsetWriteStatus,
+	DB_SCHEMA_VERSION, TXHASHSET_FORMAT_VERSION,
 };
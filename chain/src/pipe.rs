@@ -14,19 +14,25 @@
 
 //! Implementation of the chain block acceptance (or refusal) pipeline.
 
-use crate::core::consensus;
-use crate::core::core::hash::Hashed;
+use crate::core::clock::{self, Clock, SystemClock};
+use crate::core::consensus::{self, HeaderInfo};
+use crate::core::core::committed;
+use crate::core::core::hash::{Hash, Hashed};
 use crate::core::core::verifier_cache::VerifierCache;
 use crate::core::core::Committed;
-use crate::core::core::{Block, BlockHeader, BlockSums};
-use crate::core::pow;
+use crate::core::core::{Block, BlockFilter, BlockHeader, BlockSums, Output, TxKernel};
+use crate::core::pow::{self, Difficulty};
 use crate::error::{Error, ErrorKind};
+use crate::keychain::BlindingFactor;
 use crate::store;
 use crate::txhashset;
-use crate::types::{CommitPos, Options, Tip};
+use crate::types::{CommitPos, HeadRacePolicy, Options, ReorgRecord, Tip};
 use crate::util::RwLock;
+use chrono::prelude::{DateTime, Utc};
 use kepler_store;
+use std::collections::BTreeMap;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 /// Contextual information required to process a new block and either reject or
 /// accept it.
@@ -43,6 +49,108 @@ pub struct BlockContext<'a> {
 	pub batch: store::Batch<'a>,
 	/// The verifier cache (caching verifier for rangeproofs and kernel signatures)
 	pub verifier_cache: Arc<RwLock<dyn VerifierCache>>,
+	/// The difficulty window cache, shared across consecutive header validations.
+	pub difficulty_cache: Arc<RwLock<store::DifficultyCache>>,
+	/// How to resolve a race between blocks of equal total difficulty.
+	pub head_race_policy: HeadRacePolicy,
+	/// Time spent in each stage of the pipeline, only tracked if provided.
+	pub timings: Option<&'a mut PipeTimings>,
+	/// Clock the header timestamps are checked against.
+	pub clock: Arc<dyn Clock>,
+	/// Hashes of the headers at the hard checkpoint heights.
+	pub hard_checkpoints: Arc<BTreeMap<u64, Hash>>,
+	/// Height up to which the rangeproofs of the blocks on the header chain
+	/// are assumed valid.
+	pub assume_valid_height: Option<u64>,
+}
+
+/// Time spent in each stage of the block processing pipeline, accumulated
+/// over all the blocks processed with it.
+#[derive(Clone, Debug, Default)]
+pub struct PipeTimings {
+	/// Number of blocks processed
+	pub blocks: u64,
+	/// Known block, proof of work and orphan checks
+	pub check: Duration,
+	/// Header validation and processing
+	pub header: Duration,
+	/// Block self-consistency validation (rangeproofs, kernel signatures)
+	pub validate: Duration,
+	/// Rewind of the txhashset to the previous block
+	pub rewind: Duration,
+	/// Coinbase maturity and UTXO validation
+	pub utxo: Duration,
+	/// Kernel sums verification
+	pub sums: Duration,
+	/// Application of the block to the txhashset, roots and sizes checks
+	pub apply: Duration,
+	/// Flush of the txhashset extension to disk
+	pub flush: Duration,
+	/// Saving of the block, its sums and the new head
+	pub store: Duration,
+	/// Commit of the db batch
+	pub commit: Duration,
+}
+
+impl PipeTimings {
+	/// Time spent in all the stages.
+	pub fn total(&self) -> Duration {
+		self.check
+			+ self.header
+			+ self.validate
+			+ self.rewind
+			+ self.utxo
+			+ self.sums
+			+ self.apply
+			+ self.flush
+			+ self.store
+			+ self.commit
+	}
+
+	/// Add the timings of other processed blocks to these ones.
+	pub fn merge(&mut self, other: &PipeTimings) {
+		self.blocks += other.blocks;
+		self.check += other.check;
+		self.header += other.header;
+		self.validate += other.validate;
+		self.rewind += other.rewind;
+		self.utxo += other.utxo;
+		self.sums += other.sums;
+		self.apply += other.apply;
+		self.flush += other.flush;
+		self.store += other.store;
+		self.commit += other.commit;
+	}
+
+	/// Stage names along with the time spent in each.
+	pub fn stages(&self) -> Vec<(&'static str, Duration)> {
+		vec![
+			("check", self.check),
+			("header", self.header),
+			("validate", self.validate),
+			("rewind", self.rewind),
+			("utxo", self.utxo),
+			("sums", self.sums),
+			("apply", self.apply),
+			("flush", self.flush),
+			("store", self.store),
+			("commit", self.commit),
+		]
+	}
+}
+
+// Adds the time elapsed since the last lap to a pipeline stage, if we are
+// tracking timings.
+fn lap(
+	timings: &mut Option<&mut PipeTimings>,
+	last: &mut Instant,
+	stage: fn(&mut PipeTimings) -> &mut Duration,
+) {
+	let now = Instant::now();
+	if let Some(timings) = timings {
+		*stage(timings) += now - *last;
+	}
+	*last = now;
 }
 
 // Check if we already know about this block for various reasons
@@ -60,15 +168,22 @@ fn validate_pow_only(header: &BlockHeader, ctx: &mut BlockContext<'_>) -> Result
 		// Some of our tests require this check to be skipped (we should revisit this).
 		return Ok(());
 	}
+	validate_pow(header, ctx.pow_verifier)
+}
+
+fn validate_pow(
+	header: &BlockHeader,
+	pow_verifier: fn(&BlockHeader) -> Result<(), pow::Error>,
+) -> Result<(), Error> {
 	if !header.pow.is_primary() && !header.pow.is_secondary() {
-		return Err(ErrorKind::LowEdgebits.into());
+		return Err(ErrorKind::LowEdgebits(header.pow.edge_bits()).into());
 	}
-	if (ctx.pow_verifier)(header).is_err() {
+	if pow_verifier(header).is_err() {
 		error!(
 			"pipe: error validating header with cuckoo edge_bits {}",
 			header.pow.edge_bits(),
 		);
-		return Err(ErrorKind::InvalidPow.into());
+		return Err(ErrorKind::InvalidPow(header.pow.edge_bits()).into());
 	}
 	Ok(())
 }
@@ -86,6 +201,8 @@ pub fn process_block(b: &Block, ctx: &mut BlockContext<'_>) -> Result<Option<Tip
 		b.kernels().len(),
 	);
 
+	let mut last = Instant::now();
+
 	// Check if we have already processed this block previously.
 	check_known(&b.header, ctx)?;
 
@@ -94,6 +211,18 @@ pub fn process_block(b: &Block, ctx: &mut BlockContext<'_>) -> Result<Option<Tip
 	// want to do this now and not later during header validation.
 	validate_pow_only(&b.header, ctx)?;
 
+	// Same for blocks too far in the future (as in bitcoin), checked here
+	// against the chain clock rather than when deserializing the block.
+	validate_header_time(&b.header, clock::max_header_time(ctx.clock.as_ref()))?;
+
+	// Only the header was asked for, the body is left aside and the block
+	// doesn't move our chain head.
+	if ctx.opts.contains(Options::HEADER_ONLY) {
+		process_block_header(&b.header, ctx)?;
+		lap(&mut ctx.timings, &mut last, |t| &mut t.header);
+		return Ok(None);
+	}
+
 	let head = ctx.batch.head()?;
 	let prev = prev_header_store(&b.header, &mut ctx.batch)?;
 
@@ -106,23 +235,29 @@ pub fn process_block(b: &Block, ctx: &mut BlockContext<'_>) -> Result<Option<Tip
 			return Err(ErrorKind::Orphan.into());
 		}
 	}
+	lap(&mut ctx.timings, &mut last, |t| &mut t.check);
 
 	// Process the header for the block.
 	// Note: We still want to process the full block if we have seen this header before
 	// as we may have processed it "header first" and not yet processed the full block.
 	process_block_header(&b.header, ctx)?;
+	lap(&mut ctx.timings, &mut last, |t| &mut t.header);
 
 	// Validate the block itself, make sure it is internally consistent.
 	// Use the verifier_cache for verifying rangeproofs and kernel signatures.
 	validate_block(b, ctx)?;
+	lap(&mut ctx.timings, &mut last, |t| &mut t.validate);
 
 	// Start a chain extension unit of work dependent on the success of the
 	// internal validation and saving operations
+	let head_race_policy = ctx.head_race_policy;
 	let ref mut header_pmmr = &mut ctx.header_pmmr;
 	let ref mut txhashset = &mut ctx.txhashset;
 	let ref mut batch = &mut ctx.batch;
+	let ref mut timings = &mut ctx.timings;
 	let (block_sums, spent) = txhashset::extending(header_pmmr, txhashset, batch, |ext, batch| {
 		rewind_and_apply_fork(&prev, ext, batch)?;
+		lap(timings, &mut last, |t| &mut t.rewind);
 
 		// Check any coinbase being spent have matured sufficiently.
 		// This needs to be done within the context of a potentially
@@ -132,30 +267,38 @@ pub fn process_block(b: &Block, ctx: &mut BlockContext<'_>) -> Result<Option<Tip
 
 		// Validate the block against the UTXO set.
 		validate_utxo(b, ext, batch)?;
+		lap(timings, &mut last, |t| &mut t.utxo);
 
 		// Using block_sums (utxo_sum, kernel_sum) for the previous block from the db
 		// we can verify_kernel_sums across the full UTXO sum and full kernel sum
 		// accounting for inputs/outputs/kernels in this new block.
 		// We know there are no double-spends etc. if this verifies successfully.
 		// Remember to save these to the db later on (regardless of extension rollback)
-		let block_sums = verify_block_sums(b, batch)?;
+		let block_sums = verify_block_sums(b, batch).map_err(invalid_block_state)?;
+		lap(timings, &mut last, |t| &mut t.sums);
 
 		// Apply the block to the txhashset state.
 		// Validate the txhashset roots and sizes against the block header.
 		// Block is invalid if there are any discrepencies.
-		let spent = apply_block_to_txhashset(b, ext, batch)?;
+		let spent = apply_block_to_txhashset(b, ext, batch).map_err(invalid_block_state)?;
+		lap(timings, &mut last, |t| &mut t.apply);
 
 		// If applying this block does not increase the work on the chain then
 		// we know we have not yet updated the chain to produce a new chain head.
 		// We discard the "child" batch used in this extension (original ctx batch still active).
 		// We discard any MMR modifications applied in this extension.
 		let head = batch.head()?;
-		if !has_more_work(&b.header, &head) {
+		if !wins_head(&b.header, &head, head_race_policy) {
 			ext.extension.force_rollback();
 		}
 
 		Ok((block_sums, spent))
 	})?;
+	lap(&mut ctx.timings, &mut last, |t| &mut t.flush);
+
+	// The head read at the start of the pipeline may be stale by now, the
+	// block races against the current one, as in the extension above.
+	let head = ctx.batch.head()?;
 
 	// Add the validated block to the db along with the corresponding block_sums.
 	// We do this even if we have not increased the total cumulative work
@@ -167,13 +310,21 @@ pub fn process_block(b: &Block, ctx: &mut BlockContext<'_>) -> Result<Option<Tip
 		update_body_tail(&b.header, &ctx.batch)?;
 	}
 
-	if has_more_work(&b.header, &head) {
-		let head = Tip::from_header(&b.header);
-		update_head(&head, &mut ctx.batch)?;
-		Ok(Some(head))
+	let new_head = if wins_head(&b.header, &head, head_race_policy) {
+		let new_head = Tip::from_header(&b.header);
+		update_head(&new_head, &mut ctx.batch)?;
+		if b.header.prev_hash != head.last_block_h {
+			record_reorg(&head, &new_head, &prev, ctx)?;
+		}
+		Some(new_head)
 	} else {
-		Ok(None)
+		None
+	};
+	lap(&mut ctx.timings, &mut last, |t| &mut t.store);
+	if let Some(ref mut timings) = ctx.timings {
+		timings.blocks += 1;
 	}
+	Ok(new_head)
 }
 
 /// Sync a chunk of block headers.
@@ -270,7 +421,7 @@ fn check_known_head(header: &BlockHeader, ctx: &mut BlockContext<'_>) -> Result<
 	let head = ctx.batch.head()?;
 	let bh = header.hash();
 	if bh == head.last_block_h || bh == head.prev_block_h {
-		return Err(ErrorKind::Unfit("already known in head".to_string()).into());
+		return Err(ErrorKind::Known("in head".to_string()).into());
 	}
 	Ok(())
 }
@@ -286,7 +437,7 @@ fn check_known_store(header: &BlockHeader, ctx: &mut BlockContext<'_>) -> Result
 				// So this is not a particularly exhaustive check.
 				Err(ErrorKind::OldBlock.into())
 			} else {
-				Err(ErrorKind::Unfit("already known in store".to_string()).into())
+				Err(ErrorKind::Known("in store".to_string()).into())
 			}
 		}
 		Ok(false) => {
@@ -314,12 +465,51 @@ fn prev_header_store(
 /// to make it as cheap as possible. The different validations are also
 /// arranged by order of cost to have as little DoS surface as possible.
 fn validate_header(header: &BlockHeader, ctx: &mut BlockContext<'_>) -> Result<(), Error> {
+	// A header other than the one at a hard checkpoint can't be on our chain.
+	if let Some(hash) = ctx.hard_checkpoints.get(&header.height) {
+		if *hash != header.hash() {
+			return Err(ErrorKind::CheckpointMismatch(header.height).into());
+		}
+	}
+
 	// First I/O cost, delayed as late as possible.
 	let prev = prev_header_store(header, &mut ctx.batch)?;
 
+	validate_header_progression(header, &prev)?;
+
+	// refuse headers more than 12 blocks intervals in the future
+	validate_header_time(header, clock::max_header_time(ctx.clock.as_ref()))?;
+
+	// verify the proof of work and related parameters
+	// at this point we have a previous block header
+	// we know the height increased by one
+	// so now we can check the total_difficulty increase is also valid
+	// check the pow hash shows a difficulty at least as large
+	// as the target difficulty
+	if !ctx.opts.contains(Options::SKIP_POW) {
+		// Quick check of this header in isolation. No point proceeding if this fails.
+		// We can do this without needing to iterate over previous headers.
+		validate_pow_only(header, ctx)?;
+
+		let target_difficulty = validate_target_difficulty(header, &prev)?;
+
+		let diff_window = ctx.difficulty_cache.write().window(&prev, &mut ctx.batch)?;
+		validate_network_difficulty(header, target_difficulty, diff_window)?;
+	}
+
+	Ok(())
+}
+
+// Checks of the height, version and timestamp of a header against the
+// previous header.
+fn validate_header_progression(header: &BlockHeader, prev: &BlockHeader) -> Result<(), Error> {
 	// This header height must increase the height from the previous header by exactly 1.
 	if header.height != prev.height + 1 {
-		return Err(ErrorKind::InvalidBlockHeight.into());
+		return Err(ErrorKind::InvalidBlockHeight {
+			expected: prev.height + 1,
+			actual: header.height,
+		}
+		.into());
 	}
 
 	// This header must have a valid header version for its height.
@@ -330,65 +520,238 @@ fn validate_header(header: &BlockHeader, ctx: &mut BlockContext<'_>) -> Result<(
 	if header.timestamp <= prev.timestamp {
 		// prevent time warp attacks and some timestamp manipulations by forcing strict
 		// time progression
-		return Err(ErrorKind::InvalidBlockTime.into());
+		return Err(ErrorKind::InvalidBlockTime(format!(
+			"{} not after the previous block time {}",
+			header.timestamp, prev.timestamp
+		))
+		.into());
 	}
+	Ok(())
+}
 
-	// verify the proof of work and related parameters
-	// at this point we have a previous block header
-	// we know the height increased by one
-	// so now we can check the total_difficulty increase is also valid
-	// check the pow hash shows a difficulty at least as large
-	// as the target difficulty
-	if !ctx.opts.contains(Options::SKIP_POW) {
-		// Quick check of this header in isolation. No point proceeding if this fails.
-		// We can do this without needing to iterate over previous headers.
-		validate_pow_only(header, ctx)?;
+fn validate_header_time(header: &BlockHeader, max_future: DateTime<Utc>) -> Result<(), Error> {
+	if header.timestamp > max_future {
+		return Err(ErrorKind::InvalidBlockTime(format!(
+			"{} later than the maximum {}",
+			header.timestamp, max_future
+		))
+		.into());
+	}
+	Ok(())
+}
 
-		if header.total_difficulty() <= prev.total_difficulty() {
-			return Err(ErrorKind::DifficultyTooLow.into());
+// Checks the total difficulty increased and the pow hash shows a difficulty
+// at least as large as the increase, returning the header target difficulty.
+fn validate_target_difficulty(
+	header: &BlockHeader,
+	prev: &BlockHeader,
+) -> Result<Difficulty, Error> {
+	if header.total_difficulty() <= prev.total_difficulty() {
+		return Err(ErrorKind::DifficultyTooLow {
+			expected: prev.total_difficulty().to_num() + 1,
+			actual: header.total_difficulty().to_num(),
 		}
+		.into());
+	}
 
-		let target_difficulty = header.total_difficulty() - prev.total_difficulty();
+	let target_difficulty = header.total_difficulty() - prev.total_difficulty();
 
-		if header.pow.to_difficulty(header.height) < target_difficulty {
-			return Err(ErrorKind::DifficultyTooLow.into());
+	let pow_difficulty = header.pow.to_difficulty(header.height);
+	if pow_difficulty < target_difficulty {
+		return Err(ErrorKind::DifficultyTooLow {
+			expected: target_difficulty.to_num(),
+			actual: pow_difficulty.to_num(),
 		}
+		.into());
+	}
+	Ok(target_difficulty)
+}
 
-		// explicit check to ensure total_difficulty has increased by exactly
-		// the _network_ difficulty of the previous block
-		// (during testnet1 we use _block_ difficulty here)
-		let child_batch = ctx.batch.child()?;
-		let diff_iter = store::DifficultyIter::from_batch(prev.hash(), child_batch);
-		let next_header_info = consensus::next_difficulty(header.height, diff_iter);
-		if target_difficulty != next_header_info.difficulty {
-			info!(
-				"validate_header: header target difficulty {} != {}",
-				target_difficulty.to_num(),
-				next_header_info.difficulty.to_num()
-			);
-			return Err(ErrorKind::WrongTotalDifficulty.into());
+// Explicit check to ensure total_difficulty has increased by exactly
+// the _network_ difficulty of the previous block, as computed over the
+// provided difficulty window ending at the previous header.
+fn validate_network_difficulty<T>(
+	header: &BlockHeader,
+	target_difficulty: Difficulty,
+	diff_window: T,
+) -> Result<(), Error>
+where
+	T: IntoIterator<Item = HeaderInfo>,
+{
+	let next_header_info = consensus::next_difficulty(header.height, diff_window);
+	if target_difficulty != next_header_info.difficulty {
+		info!(
+			"validate_header: header target difficulty {} != {}",
+			target_difficulty.to_num(),
+			next_header_info.difficulty.to_num()
+		);
+		return Err(ErrorKind::WrongTotalDifficulty {
+			expected: next_header_info.difficulty.to_num(),
+			actual: target_difficulty.to_num(),
 		}
-		// check the secondary PoW scaling factor if applicable
-		if header.pow.secondary_scaling != next_header_info.secondary_scaling {
-			info!(
-				"validate_header: header secondary scaling {} != {}",
-				header.pow.secondary_scaling, next_header_info.secondary_scaling
-			);
-			return Err(ErrorKind::InvalidScaling.into());
+		.into());
+	}
+	// check the secondary PoW scaling factor if applicable
+	if header.pow.secondary_scaling != next_header_info.secondary_scaling {
+		info!(
+			"validate_header: header secondary scaling {} != {}",
+			header.pow.secondary_scaling, next_header_info.secondary_scaling
+		);
+		return Err(ErrorKind::InvalidScaling {
+			expected: next_header_info.secondary_scaling,
+			actual: header.pow.secondary_scaling,
 		}
+		.into());
+	}
+	Ok(())
+}
+
+/// Validates a chain of headers against the consensus rules on its own,
+/// without any chain state: each header has to build on the previous one,
+/// with a valid version, timestamp, proof of work and difficulty. The first
+/// header is trusted. Network difficulties are only checked once a full
+/// difficulty window precedes the header, or from the start of a chain
+/// starting at genesis.
+pub fn validate_header_chain(
+	headers: &[BlockHeader],
+	pow_verifier: fn(&BlockHeader) -> Result<(), pow::Error>,
+) -> Result<(), Error> {
+	let max_future = clock::max_header_time(&SystemClock);
+	let from_genesis = headers.first().map_or(false, |h| h.height == 0);
+
+	// Difficulty infos of the headers validated so far, latest last. The
+	// difficulty of the first header is only known at genesis.
+	let mut infos = vec![];
+	if let Some(genesis) = headers.first().filter(|_| from_genesis) {
+		infos.push(header_info(genesis, genesis.total_difficulty()));
 	}
 
+	for pair in headers.windows(2) {
+		let (prev, header) = (&pair[0], &pair[1]);
+		let res =
+			validate_chained_header(header, prev, &infos, from_genesis, max_future, pow_verifier);
+		let target_difficulty = res.map_err(|e| {
+			error!(
+				"validate_header_chain: invalid header {} at {}: {}",
+				header.hash(),
+				header.height,
+				e.kind()
+			);
+			e
+		})?;
+		infos.push(header_info(header, target_difficulty));
+	}
 	Ok(())
 }
 
+fn validate_chained_header(
+	header: &BlockHeader,
+	prev: &BlockHeader,
+	infos: &[HeaderInfo],
+	from_genesis: bool,
+	max_future: DateTime<Utc>,
+	pow_verifier: fn(&BlockHeader) -> Result<(), pow::Error>,
+) -> Result<Difficulty, Error> {
+	if header.prev_hash != prev.hash() {
+		return Err(
+			ErrorKind::Unfit("header doesn't build on the previous one".to_string()).into(),
+		);
+	}
+	validate_header_progression(header, prev)?;
+	validate_header_time(header, max_future)?;
+	validate_pow(header, pow_verifier)?;
+	let target_difficulty = validate_target_difficulty(header, prev)?;
+
+	let needed = consensus::DIFFICULTY_ADJUST_WINDOW as usize + 1;
+	if from_genesis || infos.len() >= needed {
+		let diff_window = infos.iter().rev().take(needed).cloned();
+		validate_network_difficulty(header, target_difficulty, diff_window)?;
+	}
+	Ok(target_difficulty)
+}
+
+fn header_info(header: &BlockHeader, difficulty: Difficulty) -> HeaderInfo {
+	HeaderInfo::new(
+		header.hash(),
+		header.timestamp.timestamp() as u64,
+		difficulty,
+		header.pow.secondary_scaling,
+		header.pow.is_secondary(),
+	)
+}
+
 fn validate_block(block: &Block, ctx: &mut BlockContext<'_>) -> Result<(), Error> {
 	let prev = ctx.batch.get_previous_header(&block.header)?;
+	let verifier_cache: Arc<RwLock<dyn VerifierCache>> =
+		if ctx.opts.contains(Options::SKIP_RANGEPROOF) || is_assumed_valid(&block.header, ctx) {
+			Arc::new(RwLock::new(TrustedRangeProofs(ctx.verifier_cache.clone())))
+		} else {
+			ctx.verifier_cache.clone()
+		};
 	block
-		.validate(&prev.total_kernel_offset, ctx.verifier_cache.clone())
+		.validate(&prev.total_kernel_offset, verifier_cache)
 		.map_err(ErrorKind::InvalidBlockProof)?;
 	Ok(())
 }
 
+fn is_assumed_valid(header: &BlockHeader, ctx: &BlockContext<'_>) -> bool {
+	assumed_valid(
+		header,
+		ctx.assume_valid_height,
+		&ctx.hard_checkpoints,
+		&ctx.header_pmmr,
+	)
+}
+
+/// Whether the header is at or below the assume-valid height on the header
+/// chain, the one with the most work. The assume-valid height only counts
+/// when it's one of the hard checkpoints and the header chain goes through
+/// the checkpointed block, so a peer can't get our rangeproof checks
+/// skipped with a chain of its own. Kernel sums don't make up for the
+/// skipped rangeproofs: an output with a negative value balances them,
+/// the hard checkpoint is what we trust instead.
+pub(crate) fn assumed_valid(
+	header: &BlockHeader,
+	assume_valid_height: Option<u64>,
+	hard_checkpoints: &BTreeMap<u64, Hash>,
+	header_pmmr: &txhashset::PMMRHandle<BlockHeader>,
+) -> bool {
+	let height = match assume_valid_height {
+		Some(height) if header.height <= height => height,
+		_ => return false,
+	};
+	let checkpoint = match hard_checkpoints.get(&height) {
+		Some(hash) => *hash,
+		None => return false,
+	};
+	header_pmmr.get_header_hash_by_height(height).ok() == Some(checkpoint)
+		&& header_pmmr.get_header_hash_by_height(header.height).ok() == Some(header.hash())
+}
+
+// Verifier cache considering all rangeproofs verified, for blocks processed
+// with SKIP_RANGEPROOF or assumed valid. Kernel signatures still go through the actual cache.
+struct TrustedRangeProofs(Arc<RwLock<dyn VerifierCache>>);
+
+impl VerifierCache for TrustedRangeProofs {
+	fn filter_kernel_sig_unverified(&mut self, kernels: &[TxKernel]) -> Vec<TxKernel> {
+		self.0.write().filter_kernel_sig_unverified(kernels)
+	}
+
+	fn filter_rangeproof_unverified(&mut self, _outputs: &[Output]) -> Vec<Output> {
+		vec![]
+	}
+
+	fn add_kernel_sig_verified(&mut self, kernels: Vec<TxKernel>) {
+		self.0.write().add_kernel_sig_verified(kernels)
+	}
+
+	fn add_rangeproof_verified(&mut self, _outputs: Vec<Output>) {}
+
+	fn shrink(&mut self, keep: usize) {
+		self.0.write().shrink(keep)
+	}
+}
+
 /// Verify the block is not spending coinbase outputs before they have sufficiently matured.
 fn verify_coinbase_maturity(
 	block: &Block,
@@ -416,8 +779,13 @@ fn verify_block_sums(b: &Block, batch: &store::Batch<'_>) -> Result<BlockSums, E
 	let offset = b.header.total_kernel_offset();
 
 	// Verify the kernel sums for the block_sums with the new block applied.
-	let (utxo_sum, kernel_sum) =
-		(block_sums, b as &dyn Committed).verify_kernel_sums(overage, offset)?;
+	let sums = (block_sums, b as &dyn Committed);
+	let (utxo_sum, kernel_sum) = sums
+		.verify_kernel_sums(overage, offset.clone())
+		.map_err(|e| match e {
+			committed::Error::KernelSumMismatch => kernel_sum_mismatch(&sums, overage, &offset),
+			e => e.into(),
+		})?;
 
 	Ok(BlockSums {
 		utxo_sum,
@@ -425,6 +793,24 @@ fn verify_block_sums(b: &Block, batch: &store::Batch<'_>) -> Result<BlockSums, E
 	})
 }
 
+// Both sides of a kernel sums mismatch, for the error to tell what didn't add
+// up.
+fn kernel_sum_mismatch(committed: &dyn Committed, overage: i64, offset: &BlindingFactor) -> Error {
+	let sums = committed.sum_commitments(overage).and_then(|utxo_sum| {
+		committed
+			.sum_kernel_excesses(offset)
+			.map(|(_, kernel_sum)| (utxo_sum, kernel_sum))
+	});
+	match sums {
+		Ok((utxo_sum, kernel_sum)) => ErrorKind::KernelSumMismatch {
+			utxo_sum,
+			kernel_sum,
+		}
+		.into(),
+		Err(e) => e.into(),
+	}
+}
+
 /// Fully validate the block by applying it to the txhashset extension.
 /// Check both the txhashset roots and sizes are correct after applying the block.
 fn apply_block_to_txhashset(
@@ -438,8 +824,23 @@ fn apply_block_to_txhashset(
 	Ok(spent)
 }
 
+/// Classify the errors of a block not applying to the chain state as such,
+/// leaving any other (internal) error untouched.
+fn invalid_block_state(e: Error) -> Error {
+	let kind = e.kind();
+	match kind {
+		ErrorKind::Committed(_)
+		| ErrorKind::KernelSumMismatch { .. }
+		| ErrorKind::InvalidRoot(_)
+		| ErrorKind::InvalidMMRSize(_)
+		| ErrorKind::AlreadySpent(_)
+		| ErrorKind::DuplicateCommitment(_) => ErrorKind::InvalidBlockState(kind.to_string()).into(),
+		_ => e,
+	}
+}
+
 /// Officially adds the block to our chain (possibly on a losing fork).
-/// Adds the associated block_sums and spent_index as well.
+/// Adds the associated block_sums, spent_index and filter as well.
 /// Header must be added separately (assume this has been done previously).
 fn add_block(
 	b: &Block,
@@ -450,6 +851,7 @@ fn add_block(
 	batch.save_block(b)?;
 	batch.save_block_sums(&b.hash(), block_sums)?;
 	batch.save_spent_index(&b.hash(), spent)?;
+	batch.save_block_filter(&b.hash(), &BlockFilter::from_block(b))?;
 	Ok(())
 }
 
@@ -481,11 +883,71 @@ fn update_head(head: &Tip, batch: &mut store::Batch<'_>) -> Result<(), Error> {
 	Ok(())
 }
 
+// Record the reorg from the old head to the new one, the blocks of the old
+// chain back to the fork point with the new one no longer being on our chain.
+fn record_reorg(
+	old_head: &Tip,
+	new_head: &Tip,
+	prev: &BlockHeader,
+	ctx: &BlockContext<'_>,
+) -> Result<(), Error> {
+	let mut old = ctx.batch.get_block_header(&old_head.last_block_h)?;
+	let mut new = prev.clone();
+	let mut disconnected = vec![];
+	while old.hash() != new.hash() {
+		if old.height >= new.height {
+			disconnected.push(old.hash());
+			old = ctx.batch.get_previous_header(&old)?;
+		} else {
+			new = ctx.batch.get_previous_header(&new)?;
+		}
+	}
+	// The old head being an ancestor of the new one is no reorg.
+	if disconnected.is_empty() {
+		return Ok(());
+	}
+
+	info!(
+		"pipe: reorg from {} at {} to {} at {}, {} blocks deep from {} at {}",
+		old_head.last_block_h,
+		old_head.height,
+		new_head.last_block_h,
+		new_head.height,
+		disconnected.len(),
+		old.hash(),
+		old.height,
+	);
+	let reorg = ReorgRecord {
+		timestamp: ctx.clock.now().timestamp(),
+		old_tip: old_head.clone(),
+		new_tip: new_head.clone(),
+		fork_point: old.hash(),
+		depth: disconnected.len() as u64,
+		disconnected,
+	};
+	ctx.batch
+		.save_reorg(&reorg)
+		.map_err(|e| ErrorKind::StoreErr(e, "pipe save reorg".to_owned()))?;
+	Ok(())
+}
+
 // Whether the provided block totals more work than the chain tip
 fn has_more_work(header: &BlockHeader, head: &Tip) -> bool {
 	header.total_difficulty() > head.total_difficulty
 }
 
+// Whether the provided block should become the new chain head, either by having
+// more work than the chain tip or by winning a race against a tip of equal work.
+fn wins_head(header: &BlockHeader, head: &Tip, policy: HeadRacePolicy) -> bool {
+	if header.total_difficulty() != head.total_difficulty {
+		return has_more_work(header, head);
+	}
+	match policy {
+		HeadRacePolicy::FirstSeen => false,
+		HeadRacePolicy::LowestHash => header.hash() < head.last_block_h,
+	}
+}
+
 /// Rewind the header chain and reapply headers on a fork.
 pub fn rewind_and_apply_header_fork(
 	header: &BlockHeader,
@@ -14,16 +14,18 @@
 
 //! Implements storage primitives required by the chain
 
-use crate::core::consensus::HeaderInfo;
+use crate::core::consensus::{HeaderInfo, DIFFICULTY_ADJUST_WINDOW};
 use crate::core::core::hash::{Hash, Hashed};
-use crate::core::core::{Block, BlockHeader, BlockSums};
+use crate::core::core::{Block, BlockFilter, BlockHeader, BlockSums};
 use crate::core::pow::Difficulty;
 use crate::core::ser::ProtocolVersion;
-use crate::types::{CommitPos, Tip};
+use crate::types::{CommitPos, DataVersion, ReorgRecord, StatsSnapshot, Tip};
 use crate::util::secp::pedersen::Commitment;
 use croaring::Bitmap;
 use kepler_store as store;
 use kepler_store::{option_to_not_found, to_key, Error, SerIterator};
+use lru_cache::LruCache;
+use std::cmp;
 use std::convert::TryInto;
 use std::sync::Arc;
 
@@ -34,9 +36,24 @@ const BLOCK_PREFIX: u8 = b'b';
 const HEAD_PREFIX: u8 = b'H';
 const TAIL_PREFIX: u8 = b'T';
 const OUTPUT_POS_PREFIX: u8 = b'p';
+const SPENT_OUTPUT_POS_PREFIX: u8 = b'P';
+const KERNEL_POS_PREFIX: u8 = b'k';
 const BLOCK_INPUT_BITMAP_PREFIX: u8 = b'B';
 const BLOCK_SUMS_PREFIX: u8 = b'M';
 const BLOCK_SPENT_PREFIX: u8 = b'S';
+const BLOCK_ARRIVAL_PREFIX: u8 = b'a';
+const STATS_SNAPSHOT_PREFIX: u8 = b'N';
+const DATA_VERSION_PREFIX: u8 = b'V';
+const BLOCK_FILTER_PREFIX: u8 = b'F';
+const KERNEL_FIRST_SEEN_PREFIX: u8 = b'f';
+const REORG_PREFIX: u8 = b'R';
+
+/// Number of the most recent reorgs kept.
+const MAX_REORGS: usize = 100;
+
+/// Number of difficulty windows to keep in the cache.
+/// Enough to cover a few competing forks being processed concurrently.
+const DIFFICULTY_CACHE_SIZE: usize = 32;
 
 /// All chain-related database operations
 pub struct ChainStore {
@@ -99,6 +116,58 @@ impl ChainStore {
 		)
 	}
 
+	/// Get the local arrival time (millis since epoch) recorded for the block.
+	pub fn get_block_arrival(&self, h: &Hash) -> Result<i64, Error> {
+		option_to_not_found(
+			self.db
+				.get_ser(&to_key(BLOCK_ARRIVAL_PREFIX, &mut h.to_vec())),
+			|| format!("Block arrival for block: {}", h),
+		)
+	}
+
+	/// Get the time (millis since epoch) the kernel with the excess was first
+	/// seen at, in a tx or a block.
+	pub fn get_kernel_first_seen(&self, excess: &Commitment) -> Result<i64, Error> {
+		option_to_not_found(
+			self.db.get_ser(&to_key(
+				KERNEL_FIRST_SEEN_PREFIX,
+				&mut excess.as_ref().to_vec(),
+			)),
+			|| format!("Kernel first seen for excess: {:?}", excess),
+		)
+	}
+
+	/// Get the compact filter of the commitments of the block.
+	pub fn get_block_filter(&self, h: &Hash) -> Result<BlockFilter, Error> {
+		option_to_not_found(
+			self.db
+				.get_ser(&to_key(BLOCK_FILTER_PREFIX, &mut h.to_vec())),
+			|| format!("Block filter for block: {}", h),
+		)
+	}
+
+	/// Get the "spent index" for the specified block, the position and height
+	/// of every output spent by the block in input order.
+	pub fn get_spent_index(&self, bh: &Hash) -> Result<Vec<CommitPos>, Error> {
+		option_to_not_found(
+			self.db
+				.get_ser(&to_key(BLOCK_SPENT_PREFIX, &mut bh.to_vec())),
+			|| format!("spent index: {}", bh),
+		)
+	}
+
+	/// Get the MMR position and height a spent output was created at, as
+	/// long as the block spending it wasn't removed yet.
+	pub fn get_spent_output_pos_height(&self, commit: &Commitment) -> Result<(u64, u64), Error> {
+		option_to_not_found(
+			self.db.get_ser(&to_key(
+				SPENT_OUTPUT_POS_PREFIX,
+				&mut commit.as_ref().to_vec(),
+			)),
+			|| format!("Spent output position for commit: {:?}", commit),
+		)
+	}
+
 	/// Get previous header.
 	pub fn get_previous_header(&self, header: &BlockHeader) -> Result<BlockHeader, Error> {
 		self.get_block_header(&header.prev_hash)
@@ -127,6 +196,57 @@ impl ChainStore {
 		)
 	}
 
+	/// Get the kernel MMR pos and block height of the latest kernel with the
+	/// given excess commitment.
+	pub fn get_kernel_pos_height(&self, excess: &Commitment) -> Result<(u64, u64), Error> {
+		option_to_not_found(
+			self.db
+				.get_ser(&to_key(KERNEL_POS_PREFIX, &mut excess.as_ref().to_vec())),
+			|| format!("Kernel position for: {:?}", excess),
+		)
+	}
+
+	/// Range scan over the output_pos index, in commitment order, starting
+	/// at the provided commitment or at the beginning of the index.
+	pub fn output_pos_iter_from(&self, from: Option<&Commitment>) -> Result<OutputPosIter, Error> {
+		let prefix = to_key(OUTPUT_POS_PREFIX, &mut vec![]);
+		let start = match from {
+			Some(commit) => to_key(OUTPUT_POS_PREFIX, &mut commit.as_ref().to_vec()),
+			None => prefix.clone(),
+		};
+		Ok(OutputPosIter {
+			iter: self.db.iter_range(&prefix, &start)?,
+		})
+	}
+
+	/// Node statistics snapshots in time order, starting at the provided
+	/// time (seconds since epoch).
+	pub fn stats_snapshots_from(
+		&self,
+		since: i64,
+	) -> Result<impl Iterator<Item = StatsSnapshot>, Error> {
+		let prefix = to_key(STATS_SNAPSHOT_PREFIX, &mut vec![]);
+		let start = stats_snapshot_key(since);
+		Ok(self
+			.db
+			.iter_range(&prefix, &start)?
+			.map(|(_, snapshot)| snapshot))
+	}
+
+	/// Reorgs of the chain in time order, starting at the provided time
+	/// (seconds since epoch).
+	pub fn reorgs_from(&self, since: i64) -> Result<impl Iterator<Item = ReorgRecord>, Error> {
+		let prefix = to_key(REORG_PREFIX, &mut vec![]);
+		let start = reorg_key(since, &[]);
+		Ok(self.db.iter_range(&prefix, &start)?.map(|(_, reorg)| reorg))
+	}
+
+	/// Db schema and txhashset format versions the data was last written
+	/// with, None if written before they were recorded.
+	pub fn data_version(&self) -> Result<Option<DataVersion>, Error> {
+		self.db.get_ser(&[DATA_VERSION_PREFIX])
+	}
+
 	/// Builds a new batch to be used with this store.
 	pub fn batch(&self) -> Result<Batch<'_>, Error> {
 		Ok(Batch {
@@ -223,6 +343,8 @@ impl<'a> Batch<'a> {
 		{
 			let _ = self.delete_block_sums(bh);
 			let _ = self.delete_spent_index(bh);
+			let _ = self.delete_block_arrival(bh);
+			let _ = self.delete_block_filter(bh);
 		}
 
 		Ok(())
@@ -266,6 +388,55 @@ impl<'a> Batch<'a> {
 		commit_key == key
 	}
 
+	/// Save the MMR position and height of an output when spent, so it can
+	/// still be told from an output we never saw.
+	pub fn save_spent_output_pos_height(
+		&self,
+		commit: &Commitment,
+		pos: u64,
+		height: u64,
+	) -> Result<(), Error> {
+		self.db.put_ser(
+			&to_key(SPENT_OUTPUT_POS_PREFIX, &mut commit.as_ref().to_vec())[..],
+			&(pos, height),
+		)
+	}
+
+	/// Delete the spent output index entry of an output unspent by a rewind.
+	pub fn delete_spent_output_pos_height(&self, commit: &Commitment) -> Result<(), Error> {
+		self.db.delete(&to_key(
+			SPENT_OUTPUT_POS_PREFIX,
+			&mut commit.as_ref().to_vec(),
+		))
+	}
+
+	/// Delete the spent output index entries of the outputs spent by the
+	/// provided block, when removing it. Entries since overwritten by a later
+	/// spend of the same commitment are left alone.
+	pub fn delete_spent_outputs_pos_height(&self, b: &Block) -> Result<(), Error> {
+		let spent = self.get_spent_index(&b.hash())?;
+		for (input, pos) in b.inputs().into_iter().zip(spent) {
+			let commit = input.commitment();
+			if let Ok((spent_pos, _)) = self.get_spent_output_pos_height(&commit) {
+				if spent_pos == pos.pos {
+					self.delete_spent_output_pos_height(&commit)?;
+				}
+			}
+		}
+		Ok(())
+	}
+
+	/// Get the MMR position and height a spent output was created at.
+	pub fn get_spent_output_pos_height(&self, commit: &Commitment) -> Result<(u64, u64), Error> {
+		option_to_not_found(
+			self.db.get_ser(&to_key(
+				SPENT_OUTPUT_POS_PREFIX,
+				&mut commit.as_ref().to_vec(),
+			)),
+			|| format!("Spent output position for commit: {:?}", commit),
+		)
+	}
+
 	/// Iterator over the output_pos index.
 	pub fn output_pos_iter(&self) -> Result<SerIterator<(u64, u64)>, Error> {
 		let key = to_key(OUTPUT_POS_PREFIX, &mut "".to_string().into_bytes());
@@ -286,6 +457,40 @@ impl<'a> Batch<'a> {
 		)
 	}
 
+	/// Save kernel_pos and block height to index.
+	pub fn save_kernel_pos_height(
+		&self,
+		excess: &Commitment,
+		pos: u64,
+		height: u64,
+	) -> Result<(), Error> {
+		self.db.put_ser(
+			&to_key(KERNEL_POS_PREFIX, &mut excess.as_ref().to_vec())[..],
+			&(pos, height),
+		)
+	}
+
+	/// Delete the kernel_pos index entry for a rewound kernel.
+	pub fn delete_kernel_pos_height(&self, excess: &Commitment) -> Result<(), Error> {
+		self.db
+			.delete(&to_key(KERNEL_POS_PREFIX, &mut excess.as_ref().to_vec()))
+	}
+
+	/// Iterator over the kernel_pos index.
+	pub fn kernel_pos_iter(&self) -> Result<SerIterator<(u64, u64)>, Error> {
+		let key = to_key(KERNEL_POS_PREFIX, &mut "".to_string().into_bytes());
+		self.db.iter(&key)
+	}
+
+	/// Get kernel_pos and block height from index.
+	pub fn get_kernel_pos_height(&self, excess: &Commitment) -> Result<(u64, u64), Error> {
+		option_to_not_found(
+			self.db
+				.get_ser(&to_key(KERNEL_POS_PREFIX, &mut excess.as_ref().to_vec())),
+			|| format!("Kernel position for excess: {:?}", excess),
+		)
+	}
+
 	/// Get the previous header.
 	pub fn get_previous_header(&self, header: &BlockHeader) -> Result<BlockHeader, Error> {
 		self.get_block_header(&header.prev_hash)
@@ -330,6 +535,110 @@ impl<'a> Batch<'a> {
 		self.db.delete(&to_key(BLOCK_SUMS_PREFIX, &mut bh.to_vec()))
 	}
 
+	/// Save the db schema and txhashset format versions the data is written
+	/// with.
+	pub fn save_data_version(&self, version: &DataVersion) -> Result<(), Error> {
+		self.db.put_ser(&[DATA_VERSION_PREFIX], version)
+	}
+
+	/// Save a node statistics snapshot.
+	pub fn save_stats_snapshot(&self, snapshot: &StatsSnapshot) -> Result<(), Error> {
+		self.db
+			.put_ser(&stats_snapshot_key(snapshot.timestamp)[..], snapshot)
+	}
+
+	/// Delete the node statistics snapshot taken at the provided time.
+	pub fn delete_stats_snapshot(&self, timestamp: i64) -> Result<(), Error> {
+		self.db.delete(&stats_snapshot_key(timestamp))
+	}
+
+	/// Save the local arrival time (millis since epoch) for the block.
+	/// This is when we first saw the block, not the timestamp in its header.
+	pub fn save_block_arrival(&self, h: &Hash, arrival: i64) -> Result<(), Error> {
+		self.db
+			.put_ser(&to_key(BLOCK_ARRIVAL_PREFIX, &mut h.to_vec())[..], &arrival)
+	}
+
+	/// Get the local arrival time (millis since epoch) for the block.
+	pub fn get_block_arrival(&self, h: &Hash) -> Result<i64, Error> {
+		option_to_not_found(
+			self.db
+				.get_ser(&to_key(BLOCK_ARRIVAL_PREFIX, &mut h.to_vec())),
+			|| format!("Block arrival for block: {}", h),
+		)
+	}
+
+	/// Delete the arrival time for the block.
+	fn delete_block_arrival(&self, bh: &Hash) -> Result<(), Error> {
+		self.db
+			.delete(&to_key(BLOCK_ARRIVAL_PREFIX, &mut bh.to_vec()))
+	}
+
+	/// Save the time (millis since epoch) the kernel with the excess was
+	/// first seen at, unless it was seen before.
+	pub fn save_kernel_first_seen(&self, excess: &Commitment, seen: i64) -> Result<(), Error> {
+		let key = to_key(KERNEL_FIRST_SEEN_PREFIX, &mut excess.as_ref().to_vec());
+		if self.db.exists(&key)? {
+			return Ok(());
+		}
+		self.db.put_ser(&key[..], &seen)
+	}
+
+	/// Delete the first seen times of the kernels seen before the provided
+	/// time (millis since epoch). Returns the number of them deleted.
+	pub fn delete_kernels_first_seen_before(&self, before: i64) -> Result<usize, Error> {
+		let prefix = to_key(KERNEL_FIRST_SEEN_PREFIX, &mut vec![]);
+		let keys: Vec<Vec<u8>> = self
+			.db
+			.iter_range::<i64>(&prefix, &prefix)?
+			.filter(|(_, seen)| *seen < before)
+			.map(|(key, _)| key)
+			.collect();
+		for key in &keys {
+			self.db.delete(key)?;
+		}
+		Ok(keys.len())
+	}
+
+	/// Save a reorg of the chain, dropping the oldest ones beyond the
+	/// MAX_REORGS most recent.
+	pub fn save_reorg(&self, reorg: &ReorgRecord) -> Result<(), Error> {
+		let prefix = to_key(REORG_PREFIX, &mut vec![]);
+		let keys: Vec<Vec<u8>> = self
+			.db
+			.iter_range::<ReorgRecord>(&prefix, &prefix)?
+			.map(|(key, _)| key)
+			.collect();
+		let expired = (keys.len() + 1).saturating_sub(MAX_REORGS);
+		for key in keys.iter().take(expired) {
+			self.db.delete(key)?;
+		}
+		self.db.put_ser(
+			&reorg_key(reorg.timestamp, &reorg.new_tip.last_block_h.to_vec())[..],
+			reorg,
+		)
+	}
+
+	/// Save the compact filter of the commitments of the block.
+	pub fn save_block_filter(&self, h: &Hash, filter: &BlockFilter) -> Result<(), Error> {
+		self.db
+			.put_ser(&to_key(BLOCK_FILTER_PREFIX, &mut h.to_vec())[..], filter)
+	}
+
+	/// Get the compact filter of the commitments of the block.
+	pub fn get_block_filter(&self, h: &Hash) -> Result<BlockFilter, Error> {
+		option_to_not_found(
+			self.db
+				.get_ser(&to_key(BLOCK_FILTER_PREFIX, &mut h.to_vec())),
+			|| format!("Block filter for block: {}", h),
+		)
+	}
+
+	fn delete_block_filter(&self, bh: &Hash) -> Result<(), Error> {
+		self.db
+			.delete(&to_key(BLOCK_FILTER_PREFIX, &mut bh.to_vec()))
+	}
+
 	/// Get the block input bitmap based on our spent index.
 	/// Fallback to legacy block input bitmap from the db.
 	pub fn get_block_input_bitmap(&self, bh: &Hash) -> Result<Bitmap, Error> {
@@ -386,6 +695,90 @@ impl<'a> Batch<'a> {
 	}
 }
 
+/// Typed iterator over the output_pos index, yielding the commitment along
+/// with the output MMR position and height it was created at.
+pub struct OutputPosIter {
+	iter: SerIterator<(u64, u64)>,
+}
+
+impl Iterator for OutputPosIter {
+	type Item = (Commitment, CommitPos);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.iter.next().map(|(key, (pos, height))| {
+			// Skip the prefix and separator to get back the commitment bytes.
+			let commit = Commitment::from_vec(key[2..].to_vec());
+			(commit, CommitPos { pos, height })
+		})
+	}
+}
+
+// Snapshots are keyed by big endian timestamp so they are iterated in time
+// order. Timestamps before the epoch are not expected.
+fn stats_snapshot_key(timestamp: i64) -> Vec<u8> {
+	let timestamp = cmp::max(timestamp, 0) as u64;
+	to_key(STATS_SNAPSHOT_PREFIX, &mut timestamp.to_be_bytes().to_vec())
+}
+
+// Reorgs are keyed by time then by the hash of the new head, two reorgs to
+// different heads within the same second are both kept.
+fn reorg_key(timestamp: i64, hash: &[u8]) -> Vec<u8> {
+	let mut key = (cmp::max(timestamp, 0) as u64).to_be_bytes().to_vec();
+	key.extend_from_slice(hash);
+	to_key(REORG_PREFIX, &mut key)
+}
+
+/// Iterator over the headers (or full blocks) of a chain by increasing
+/// height. Built from the hashes at each height, reading each item lazily
+/// from the db as the iteration progresses.
+pub struct HeightIter<T> {
+	store: Arc<ChainStore>,
+	hashes: std::vec::IntoIter<Hash>,
+	get: fn(&ChainStore, &Hash) -> Result<T, Error>,
+}
+
+impl HeightIter<BlockHeader> {
+	/// Iterate over the headers with the provided hashes, in order.
+	pub fn headers(store: Arc<ChainStore>, hashes: Vec<Hash>) -> HeightIter<BlockHeader> {
+		HeightIter {
+			store,
+			hashes: hashes.into_iter(),
+			get: ChainStore::get_block_header,
+		}
+	}
+}
+
+impl HeightIter<Block> {
+	/// Iterate over the full blocks with the provided hashes, in order.
+	pub fn blocks(store: Arc<ChainStore>, hashes: Vec<Hash>) -> HeightIter<Block> {
+		HeightIter {
+			store,
+			hashes: hashes.into_iter(),
+			get: ChainStore::get_block,
+		}
+	}
+}
+
+impl<T> Iterator for HeightIter<T> {
+	type Item = Result<T, Error>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		let hash = self.hashes.next()?;
+		Some((self.get)(&self.store, &hash))
+	}
+
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		self.hashes.size_hint()
+	}
+}
+
+impl<T> DoubleEndedIterator for HeightIter<T> {
+	fn next_back(&mut self) -> Option<Self::Item> {
+		let hash = self.hashes.next_back()?;
+		Some((self.get)(&self.store, &hash))
+	}
+}
+
 /// An iterator on blocks, from latest to earliest, specialized to return
 /// information pertaining to block difficulty calculation (timestamp and
 /// previous difficulties). Mostly used by the consensus next difficulty
@@ -477,3 +870,76 @@ impl<'a> Iterator for DifficultyIter<'a> {
 		}
 	}
 }
+
+// A difficulty window along with the total difficulty of the latest header in it.
+// The total difficulty lets us compute the difficulty of a child header
+// without reading its parent from the db.
+struct DifficultyWindow {
+	total_difficulty: Difficulty,
+	infos: Vec<HeaderInfo>,
+}
+
+/// Cache of difficulty windows (the header info required by the next difficulty
+/// calculation), keyed by the hash of the latest header in the window.
+/// Consecutive headers share all but one entry of their windows so a cached
+/// window for a parent can be extended without iterating back through the db.
+pub struct DifficultyCache {
+	windows: LruCache<Hash, DifficultyWindow>,
+}
+
+impl Default for DifficultyCache {
+	fn default() -> DifficultyCache {
+		DifficultyCache::new()
+	}
+}
+
+impl DifficultyCache {
+	/// Create a new empty difficulty cache.
+	pub fn new() -> DifficultyCache {
+		DifficultyCache {
+			windows: LruCache::new(DIFFICULTY_CACHE_SIZE),
+		}
+	}
+
+	/// Header info for the difficulty window ending at the provided header,
+	/// from latest to earliest. Suitable for passing to `next_difficulty` to
+	/// determine the difficulty of a child of this header.
+	pub fn window(
+		&mut self,
+		header: &BlockHeader,
+		batch: &mut Batch<'_>,
+	) -> Result<Vec<HeaderInfo>, Error> {
+		let hash = header.hash();
+		if let Some(window) = self.windows.get_mut(&hash) {
+			return Ok(window.infos.clone());
+		}
+
+		let needed = DIFFICULTY_ADJUST_WINDOW as usize + 1;
+		let infos = if let Some(prev) = self.windows.get_mut(&header.prev_hash) {
+			let mut infos = Vec::with_capacity(needed);
+			infos.push(HeaderInfo::new(
+				hash,
+				header.timestamp.timestamp() as u64,
+				header.total_difficulty() - prev.total_difficulty,
+				header.pow.secondary_scaling,
+				header.pow.is_secondary(),
+			));
+			infos.extend(prev.infos.iter().take(needed - 1).cloned());
+			infos
+		} else {
+			let child_batch = batch.child()?;
+			DifficultyIter::from_batch(hash, child_batch)
+				.take(needed)
+				.collect()
+		};
+
+		self.windows.insert(
+			hash,
+			DifficultyWindow {
+				total_difficulty: header.total_difficulty(),
+				infos: infos.clone(),
+			},
+		);
+		Ok(infos)
+	}
+}
@@ -54,6 +54,14 @@ impl BitmapAccumulator {
 		}
 	}
 
+	/// Create a new empty bitmap accumulator keeping its chunks, for them to
+	/// be served in segments.
+	pub fn new_with_chunks() -> BitmapAccumulator {
+		BitmapAccumulator {
+			backend: VecBackend::new(),
+		}
+	}
+
 	/// Initialize a bitmap accumulator given the provided idx iterator.
 	pub fn init<T: IntoIterator<Item = u64>>(&mut self, idx: T, size: u64) -> Result<(), Error> {
 		self.apply_from(idx, 0, size)
@@ -176,7 +184,28 @@ impl BitmapAccumulator {
 
 	/// The root hash of the bitmap accumulator MMR.
 	pub fn root(&self) -> Hash {
-		ReadonlyPMMR::at(&self.backend, self.backend.size()).root()
+		self.readonly_pmmr().root()
+	}
+
+	/// Size of the bitmap accumulator MMR.
+	pub fn size(&self) -> u64 {
+		self.backend.size()
+	}
+
+	/// Size of the bitmap accumulator MMR covering an output MMR with the
+	/// given number of leaves. The last output of a block is always unspent
+	/// so the accumulator holds exactly one chunk per started 1024 outputs.
+	pub fn size_for_leaves(n_leaves: u64) -> u64 {
+		if n_leaves == 0 {
+			return 0;
+		}
+		let n_chunks = BitmapAccumulator::chunk_idx(n_leaves - 1) + 1;
+		pmmr::insertion_to_pmmr_index(n_chunks + 1) - 1
+	}
+
+	/// Readonly view of the bitmap accumulator MMR.
+	pub fn readonly_pmmr(&self) -> ReadonlyPMMR<'_, BitmapChunk, VecBackend<BitmapChunk>> {
+		ReadonlyPMMR::at(&self.backend, self.backend.size())
 	}
 }
 
@@ -207,6 +236,16 @@ impl BitmapChunk {
 	pub fn any(&self) -> bool {
 		self.0.any()
 	}
+
+	/// The bitmap idx of the bits set, for the chunk starting at the given
+	/// idx.
+	pub fn set_iter(&self, idx_offset: u64) -> impl Iterator<Item = u64> + '_ {
+		self.0
+			.iter()
+			.enumerate()
+			.filter(|(_, bit)| *bit)
+			.map(move |(idx, _)| idx_offset + idx as u64)
+	}
 }
 
 impl PMMRable for BitmapChunk {
@@ -230,10 +269,10 @@ impl Writeable for BitmapChunk {
 }
 
 impl Readable for BitmapChunk {
-	/// Reading is not currently supported, just return an empty one for now.
-	/// We store the underlying roaring bitmap externally for the bitmap accumulator
-	/// and the "hash only" backend means we never actually read these chunks.
-	fn read(_reader: &mut dyn Reader) -> Result<BitmapChunk, ser::Error> {
-		Ok(BitmapChunk::new())
+	/// Chunks are only read from the segments of the accumulator received
+	/// during a fast sync, the "hash only" backend never stores them.
+	fn read(reader: &mut dyn Reader) -> Result<BitmapChunk, ser::Error> {
+		let bytes = reader.read_fixed_bytes(Self::LEN_BYTES)?;
+		Ok(BitmapChunk(BitVec::from_bytes(&bytes)))
 	}
 }
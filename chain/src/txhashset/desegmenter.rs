@@ -0,0 +1,463 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Rebuilding the txhashset at a header from its segments, downloaded from
+//! several peers at once. Each segment is checked against the roots of the
+//! header as it arrives, then appended to the MMRs in a sandbox once the
+//! ones before it are in.
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::path::PathBuf;
+
+use croaring::Bitmap;
+
+use crate::core::core::hash::Hash;
+use crate::core::core::pmmr::{self, Segment, SegmentError, SegmentIdentifier};
+use crate::core::core::{BlockHeader, Output, OutputIdentifier, TxKernel};
+use crate::core::ser::{PMMRIndexHashable, PMMRable, ProtocolVersion};
+use crate::error::{Error, ErrorKind};
+use crate::txhashset::txhashset::{
+	clean_txhashset_folder, PMMRHandle, KERNEL_SUBDIR, OUTPUT_SUBDIR, RANGE_PROOF_SUBDIR,
+	TXHASHSET_SUBDIR,
+};
+use crate::txhashset::{BitmapAccumulator, BitmapChunk, SegmentType};
+use crate::types::OutputRoots;
+use crate::util::secp::pedersen::RangeProof;
+
+/// Most segments of an MMR received ahead of the next one to append, the
+/// segments past them aren't requested yet.
+pub const MAX_PENDING_SEGMENTS: u64 = 64;
+
+/// A segment received, by MMR.
+pub type SegmentId = (SegmentType, SegmentIdentifier);
+
+// The bitmap accumulator segments. Only the unspent bitmap they make up is
+// kept, it's needed in full before any output or rangeproof segment can be
+// checked.
+struct BitmapSegments {
+	// Root of the output MMR, root and size of the accumulator MMR, once
+	// checked against the output root of the header.
+	roots: Option<(Hash, Hash, u64)>,
+	received: BTreeSet<u64>,
+	unspent: Bitmap,
+}
+
+impl BitmapSegments {
+	fn count(&self) -> Option<u64> {
+		self.roots
+			.map(|(_, _, size)| SegmentIdentifier::count(size, SegmentType::Bitmap.height()))
+	}
+
+	fn is_complete(&self) -> bool {
+		self.count() == Some(self.received.len() as u64)
+	}
+}
+
+// An MMR rebuilt from its segments, appended in order.
+struct SegmentedPMMR<T: PMMRable> {
+	handle: PMMRHandle<T>,
+	segment_type: SegmentType,
+	mmr_size: u64,
+	next_idx: u64,
+	pending: BTreeMap<u64, Segment<T::E>>,
+	// Positions and hashes of the peaks appended so far, to hash the parents
+	// of the next nodes with.
+	peaks: Vec<(u64, Hash)>,
+}
+
+impl<T> SegmentedPMMR<T>
+where
+	T: PMMRable,
+	T::E: PMMRIndexHashable,
+{
+	fn new(
+		sandbox_dir: &str,
+		sub_dir: &str,
+		segment_type: SegmentType,
+		prunable: bool,
+		version: ProtocolVersion,
+		mmr_size: u64,
+	) -> Result<SegmentedPMMR<T>, Error> {
+		let handle = PMMRHandle::new(
+			sandbox_dir,
+			TXHASHSET_SUBDIR,
+			sub_dir,
+			prunable,
+			version,
+			None,
+		)?;
+		Ok(SegmentedPMMR {
+			handle,
+			segment_type,
+			mmr_size,
+			next_idx: 0,
+			pending: BTreeMap::new(),
+			peaks: vec![],
+		})
+	}
+
+	fn count(&self) -> u64 {
+		SegmentIdentifier::count(self.mmr_size, self.segment_type.height())
+	}
+
+	fn is_complete(&self) -> bool {
+		self.next_idx == self.count()
+	}
+
+	// The segments still to receive, in order, up to the pending limit.
+	fn missing(&self) -> impl Iterator<Item = SegmentId> + '_ {
+		let height = self.segment_type.height();
+		let end = self.count().min(self.next_idx + MAX_PENDING_SEGMENTS);
+		(self.next_idx..end)
+			.filter(move |idx| !self.pending.contains_key(idx))
+			.map(move |idx| (self.segment_type, SegmentIdentifier { height, idx }))
+	}
+
+	// Check the segment against the root and append it along with the ones
+	// received after it, once the ones before it are in.
+	fn add(
+		&mut self,
+		segment: Segment<T::E>,
+		unspent: Option<&Bitmap>,
+		root: Hash,
+	) -> Result<(), Error> {
+		let id = segment.identifier;
+		check_height(self.segment_type, id)?;
+		if id.idx < self.next_idx || self.pending.contains_key(&id.idx) {
+			return Ok(());
+		}
+		segment
+			.validate(self.mmr_size, unspent, root)
+			.map_err(|e| invalid_segment(self.segment_type, id, e))?;
+		self.pending.insert(id.idx, segment);
+
+		let mut appended = false;
+		while let Some(segment) = self.pending.remove(&self.next_idx) {
+			self.append(segment, unspent)?;
+			self.next_idx += 1;
+			appended = true;
+		}
+		if appended {
+			self.handle.backend.sync()?;
+		}
+		Ok(())
+	}
+
+	// Append the nodes of the segment in position order, the hashes of the
+	// pruned subtrees interleaved with the leaves.
+	fn append(&mut self, segment: Segment<T::E>, unspent: Option<&Bitmap>) -> Result<(), Error> {
+		let mut hashes = segment.hashes.into_iter().peekable();
+		let mut leaves = segment.leaf_data.into_iter().peekable();
+		loop {
+			let next_hash = hashes.peek().map(|(pos, _)| *pos);
+			let next_leaf = leaves.peek().map(|(pos, _)| *pos);
+			match (next_hash, next_leaf) {
+				(None, None) => break,
+				(Some(hash_pos), leaf_pos) if leaf_pos.map(|p| hash_pos < p).unwrap_or(true) => {
+					let (pos, hash) = hashes.next().expect("next after peek");
+					// The root of a pruned subtree spanning several segments
+					// comes with each of them.
+					if pos <= self.handle.last_pos {
+						continue;
+					}
+					let hashes = self.parent_hashes(pos, hash)?;
+					self.handle.backend.append_pruned_root(pos, &hashes)?;
+				}
+				_ => {
+					let (pos, data) = leaves.next().expect("next after peek");
+					let hashes = self.parent_hashes(pos, data.hash_with_index(pos - 1))?;
+					let unspent = unspent.map(|b| b.contains(pos as u32)).unwrap_or(true);
+					self.handle
+						.backend
+						.append_leaf(pos, &data, &hashes, unspent)?;
+				}
+			}
+		}
+		Ok(())
+	}
+
+	// The hash of the node followed by the hashes of the parents it
+	// completes, all to be appended after it.
+	fn parent_hashes(&mut self, pos: u64, hash: Hash) -> Result<Vec<Hash>, Error> {
+		let (mut pos, mut hash) = (pos, hash);
+		let mut hashes = vec![hash];
+		while !pmmr::is_left_sibling(pos) {
+			let (_, left_hash) = self
+				.peaks
+				.pop()
+				.ok_or_else(|| ErrorKind::InvalidSegment(format!("no left sibling for {}", pos)))?;
+			pos += 1;
+			hash = (left_hash, hash).hash_with_index(pos - 1);
+			hashes.push(hash);
+		}
+		self.peaks.push((pos, hash));
+		self.handle.last_pos = pos;
+		Ok(hashes)
+	}
+}
+
+/// The txhashset at a header being rebuilt from its segments, in a sandbox
+/// folder. Checked against the header segment by segment, it still needs
+/// the full validation of a downloaded txhashset once complete.
+pub struct Desegmenter {
+	header: BlockHeader,
+	sandbox_dir: PathBuf,
+	bitmap: BitmapSegments,
+	outputs: SegmentedPMMR<Output>,
+	rangeproofs: SegmentedPMMR<RangeProof>,
+	kernels: SegmentedPMMR<TxKernel>,
+}
+
+impl Desegmenter {
+	/// Start rebuilding the txhashset at the header in the sandbox folder,
+	/// cleaning up whatever was there.
+	pub fn new(header: BlockHeader, sandbox_dir: PathBuf) -> Result<Desegmenter, Error> {
+		clean_txhashset_folder(&sandbox_dir);
+		let dir = sandbox_dir
+			.to_str()
+			.ok_or_else(|| ErrorKind::Other("invalid sandbox folder".to_owned()))?
+			.to_owned();
+
+		let outputs = SegmentedPMMR::new(
+			&dir,
+			OUTPUT_SUBDIR,
+			SegmentType::Output,
+			true,
+			ProtocolVersion(1),
+			header.output_mmr_size,
+		)?;
+		let rangeproofs = SegmentedPMMR::new(
+			&dir,
+			RANGE_PROOF_SUBDIR,
+			SegmentType::RangeProof,
+			true,
+			ProtocolVersion(1),
+			header.output_mmr_size,
+		)?;
+		let kernels = SegmentedPMMR::new(
+			&dir,
+			KERNEL_SUBDIR,
+			SegmentType::Kernel,
+			false,
+			ProtocolVersion(2),
+			header.kernel_mmr_size,
+		)?;
+
+		Ok(Desegmenter {
+			header,
+			sandbox_dir,
+			bitmap: BitmapSegments {
+				roots: None,
+				received: BTreeSet::new(),
+				unspent: Bitmap::create(),
+			},
+			outputs,
+			rangeproofs,
+			kernels,
+		})
+	}
+
+	/// The header the txhashset is rebuilt at.
+	pub fn header(&self) -> &BlockHeader {
+		&self.header
+	}
+
+	/// Whether all the segments were received and appended.
+	pub fn is_complete(&self) -> bool {
+		self.bitmap.is_complete()
+			&& self.outputs.is_complete()
+			&& self.rangeproofs.is_complete()
+			&& self.kernels.is_complete()
+	}
+
+	/// Number of segments received so far and number of segments in total,
+	/// the latter only known once the first bitmap segment is in.
+	pub fn progress(&self) -> (u64, Option<u64>) {
+		let received = self.bitmap.received.len() as u64
+			+ self.outputs.next_idx
+			+ self.outputs.pending.len() as u64
+			+ self.rangeproofs.next_idx
+			+ self.rangeproofs.pending.len() as u64
+			+ self.kernels.next_idx
+			+ self.kernels.pending.len() as u64;
+		let total = self.bitmap.count().map(|count| {
+			count + self.outputs.count() + self.rangeproofs.count() + self.kernels.count()
+		});
+		(received, total)
+	}
+
+	/// Up to max segments to request next, skipping the ones in flight. The
+	/// first bitmap segment comes alone, the roots it carries are needed to
+	/// check the others. The outputs and rangeproofs wait for the whole
+	/// bitmap, the kernels don't depend on it.
+	pub fn next_desired_segments(
+		&self,
+		max: usize,
+		in_flight: &HashSet<SegmentId>,
+	) -> Vec<SegmentId> {
+		let first = (
+			SegmentType::Bitmap,
+			SegmentIdentifier {
+				height: SegmentType::Bitmap.height(),
+				idx: 0,
+			},
+		);
+		let count = match self.bitmap.count() {
+			Some(count) => count,
+			None if in_flight.contains(&first) => return vec![],
+			None => return vec![first],
+		};
+
+		let height = SegmentType::Bitmap.height();
+		let bitmap = (0..count)
+			.filter(|idx| !self.bitmap.received.contains(idx))
+			.map(|idx| (SegmentType::Bitmap, SegmentIdentifier { height, idx }));
+		let mut desired: Box<dyn Iterator<Item = SegmentId> + '_> = Box::new(bitmap);
+		if self.bitmap.is_complete() {
+			desired = Box::new(
+				desired
+					.chain(self.outputs.missing())
+					.chain(self.rangeproofs.missing()),
+			);
+		}
+		desired
+			.chain(self.kernels.missing())
+			.filter(|id| !in_flight.contains(id))
+			.take(max)
+			.collect()
+	}
+
+	/// Add a segment of the bitmap accumulator, along with the root of the
+	/// output MMR and the size of the accumulator MMR sent with it. The first
+	/// one received is checked against the output root of the header, the
+	/// others against it.
+	pub fn add_bitmap_segment(
+		&mut self,
+		segment: Segment<BitmapChunk>,
+		output_pmmr_root: Hash,
+		bitmap_mmr_size: u64,
+	) -> Result<(), Error> {
+		let id = segment.identifier;
+		check_height(SegmentType::Bitmap, id)?;
+		if self.bitmap.received.contains(&id.idx) {
+			return Ok(());
+		}
+		// The accumulator size follows from the header, don't trust the peer on it.
+		let n_leaves = pmmr::n_leaves(self.header.output_mmr_size);
+		if bitmap_mmr_size != BitmapAccumulator::size_for_leaves(n_leaves) {
+			return Err(invalid_segment(
+				SegmentType::Bitmap,
+				id,
+				SegmentError::RootMismatch,
+			));
+		}
+		let bitmap_root = segment
+			.root(bitmap_mmr_size, None)
+			.map_err(|e| invalid_segment(SegmentType::Bitmap, id, e))?;
+		let roots = (output_pmmr_root, bitmap_root, bitmap_mmr_size);
+		match self.bitmap.roots {
+			Some(expected) if expected != roots => {
+				return Err(invalid_segment(
+					SegmentType::Bitmap,
+					id,
+					SegmentError::RootMismatch,
+				));
+			}
+			Some(_) => {}
+			None => {
+				let output_roots = OutputRoots {
+					pmmr_root: output_pmmr_root,
+					bitmap_root,
+				};
+				if output_roots.root(&self.header) != self.header.output_root {
+					return Err(invalid_segment(
+						SegmentType::Bitmap,
+						id,
+						SegmentError::RootMismatch,
+					));
+				}
+				self.bitmap.roots = Some(roots);
+			}
+		}
+
+		for (pos, chunk) in &segment.leaf_data {
+			let offset = (pmmr::n_leaves(*pos) - 1) * 1024;
+			for idx in chunk.set_iter(offset).filter(|idx| *idx < n_leaves) {
+				self.bitmap
+					.unspent
+					.add(pmmr::insertion_to_pmmr_index(idx + 1) as u32);
+			}
+		}
+		self.bitmap.received.insert(id.idx);
+		Ok(())
+	}
+
+	/// Add a segment of the output MMR. Ignored until the whole bitmap is in.
+	pub fn add_output_segment(&mut self, segment: Segment<OutputIdentifier>) -> Result<(), Error> {
+		match self.bitmap.roots {
+			Some((output_pmmr_root, _, _)) if self.bitmap.is_complete() => {
+				self.outputs
+					.add(segment, Some(&self.bitmap.unspent), output_pmmr_root)
+			}
+			_ => Ok(()),
+		}
+	}
+
+	/// Add a segment of the rangeproof MMR. Ignored until the whole bitmap is
+	/// in.
+	pub fn add_rangeproof_segment(&mut self, segment: Segment<RangeProof>) -> Result<(), Error> {
+		if !self.bitmap.is_complete() {
+			return Ok(());
+		}
+		self.rangeproofs.add(
+			segment,
+			Some(&self.bitmap.unspent),
+			self.header.range_proof_root,
+		)
+	}
+
+	/// Add a segment of the kernel MMR.
+	pub fn add_kernel_segment(&mut self, segment: Segment<TxKernel>) -> Result<(), Error> {
+		self.kernels.add(segment, None, self.header.kernel_root)
+	}
+
+	/// Save the rebuilt MMRs in the sandbox folder, returned to open the
+	/// txhashset from. Only once complete.
+	pub fn finalize(mut self) -> Result<PathBuf, Error> {
+		if !self.is_complete() {
+			return Err(ErrorKind::TxHashSetErr("segments missing".to_owned()).into());
+		}
+		self.outputs.handle.backend.sync()?;
+		self.outputs.handle.backend.sync_prune_list()?;
+		self.rangeproofs.handle.backend.sync()?;
+		self.rangeproofs.handle.backend.sync_prune_list()?;
+		self.kernels.handle.backend.sync()?;
+		Ok(self.sandbox_dir)
+	}
+}
+
+fn check_height(segment_type: SegmentType, id: SegmentIdentifier) -> Result<(), Error> {
+	if id.height != segment_type.height() {
+		return Err(ErrorKind::InvalidSegment(format!(
+			"{:?} segment {} of height {}",
+			segment_type, id.idx, id.height
+		))
+		.into());
+	}
+	Ok(())
+}
+
+fn invalid_segment(segment_type: SegmentType, id: SegmentIdentifier, e: SegmentError) -> Error {
+	ErrorKind::InvalidSegment(format!("{:?} segment {}: {:?}", segment_type, id.idx, e)).into()
+}
@@ -0,0 +1,193 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Serving the txhashset at the archive header in segments, to the peers
+//! syncing their chain state from several peers at once.
+
+use std::sync::Arc;
+
+use croaring::Bitmap;
+
+use crate::core::core::hash::{Hash, Hashed};
+use crate::core::core::pmmr::{self, Segment, SegmentError, SegmentIdentifier};
+use crate::core::core::{BlockHeader, HeaderVersion, OutputIdentifier, TxKernel};
+use crate::core::global;
+use crate::error::{Error, ErrorKind};
+use crate::txhashset::{BitmapAccumulator, BitmapChunk, TxHashSet};
+use crate::types::OutputRoots;
+use crate::util::secp::pedersen::RangeProof;
+use crate::util::RwLock;
+
+/// Height of all the segments in the test chains, small enough for their
+/// few outputs to span several segments.
+const TESTING_SEGMENT_HEIGHT: u8 = 2;
+
+/// The MMRs of the txhashset, as downloaded in segments. The bitmap is the
+/// accumulator of the unspent outputs, needed before the outputs and the
+/// rangeproofs as it tells which of their leaves are pruned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SegmentType {
+	/// Bitmap accumulator of the unspent outputs
+	Bitmap,
+	/// Output MMR
+	Output,
+	/// Rangeproof MMR
+	RangeProof,
+	/// Kernel MMR
+	Kernel,
+}
+
+impl SegmentType {
+	/// Height of the segments of this MMR, the only one served. Rangeproofs
+	/// being large their segments are smaller.
+	pub fn height(self) -> u8 {
+		if !global::is_production_mode() {
+			return TESTING_SEGMENT_HEIGHT;
+		}
+		match self {
+			SegmentType::Bitmap => 9,
+			SegmentType::Output => 11,
+			SegmentType::RangeProof => 7,
+			SegmentType::Kernel => 11,
+		}
+	}
+}
+
+/// Whether the txhashset at the header can be synced in segments, the header
+/// committing to the bitmap accumulator along with the output MMR.
+pub fn segments_supported(header: &BlockHeader) -> bool {
+	header.version >= HeaderVersion(3)
+}
+
+/// The segments of the txhashset at a header. Outputs spent at the header
+/// are pruned, whether they're compacted in the txhashset or not, so the
+/// segments only depend on the header.
+pub struct Segmenter {
+	txhashset: Arc<RwLock<TxHashSet>>,
+	header: BlockHeader,
+	bitmap: Bitmap,
+	bitmap_accumulator: BitmapAccumulator,
+	output_pmmr_root: Hash,
+}
+
+impl Segmenter {
+	/// Segmenter of the txhashset at the header, given the positions of the
+	/// outputs unspent at the header. Only works for the headers committing
+	/// to the bitmap accumulator, checked to match the output root.
+	pub fn new(
+		txhashset: Arc<RwLock<TxHashSet>>,
+		header: BlockHeader,
+		bitmap: Bitmap,
+	) -> Result<Segmenter, Error> {
+		let mut bitmap_accumulator = BitmapAccumulator::new_with_chunks();
+		bitmap_accumulator.init(
+			bitmap.iter().map(|pos| pmmr::n_leaves(pos as u64) - 1),
+			pmmr::n_leaves(header.output_mmr_size),
+		)?;
+		let output_pmmr_root = txhashset.read().output_pmmr_at(&header).root();
+
+		let roots = OutputRoots {
+			pmmr_root: output_pmmr_root,
+			bitmap_root: bitmap_accumulator.root(),
+		};
+		if !segments_supported(&header) || roots.root(&header) != header.output_root {
+			return Err(ErrorKind::SegmentsUnavailable(format!(
+				"output root mismatch at {}",
+				header.hash()
+			))
+			.into());
+		}
+
+		Ok(Segmenter {
+			txhashset,
+			header,
+			bitmap,
+			bitmap_accumulator,
+			output_pmmr_root,
+		})
+	}
+
+	/// The header the segments are at.
+	pub fn header(&self) -> &BlockHeader {
+		&self.header
+	}
+
+	/// Segment of the bitmap accumulator, along with the root of the output
+	/// MMR and the size of the accumulator MMR, needed to check it against
+	/// the output root of the header.
+	pub fn bitmap_segment(
+		&self,
+		id: SegmentIdentifier,
+	) -> Result<(Segment<BitmapChunk>, Hash, u64), Error> {
+		check_height(SegmentType::Bitmap, id)?;
+		let segment = Segment::from_pmmr(id, &self.bitmap_accumulator.readonly_pmmr(), None)
+			.map_err(segment_error)?;
+		Ok((
+			segment,
+			self.output_pmmr_root,
+			self.bitmap_accumulator.size(),
+		))
+	}
+
+	/// Segment of the output MMR, the spent outputs pruned.
+	pub fn output_segment(
+		&self,
+		id: SegmentIdentifier,
+	) -> Result<Segment<OutputIdentifier>, Error> {
+		check_height(SegmentType::Output, id)?;
+		let txhashset = self.txhashset.read();
+		Segment::from_pmmr(
+			id,
+			&txhashset.output_pmmr_at(&self.header),
+			Some(&self.bitmap),
+		)
+		.map_err(segment_error)
+	}
+
+	/// Segment of the rangeproof MMR, the proofs of the spent outputs pruned.
+	pub fn rangeproof_segment(&self, id: SegmentIdentifier) -> Result<Segment<RangeProof>, Error> {
+		check_height(SegmentType::RangeProof, id)?;
+		let txhashset = self.txhashset.read();
+		Segment::from_pmmr(
+			id,
+			&txhashset.rproof_pmmr_at(&self.header),
+			Some(&self.bitmap),
+		)
+		.map_err(segment_error)
+	}
+
+	/// Segment of the kernel MMR, never pruned.
+	pub fn kernel_segment(&self, id: SegmentIdentifier) -> Result<Segment<TxKernel>, Error> {
+		check_height(SegmentType::Kernel, id)?;
+		let txhashset = self.txhashset.read();
+		Segment::from_pmmr(id, &txhashset.kernel_pmmr_at(&self.header), None).map_err(segment_error)
+	}
+}
+
+fn check_height(segment_type: SegmentType, id: SegmentIdentifier) -> Result<(), Error> {
+	if id.height != segment_type.height() {
+		return Err(ErrorKind::SegmentsUnavailable(format!(
+			"{:?} segments of height {} not served",
+			segment_type, id.height
+		))
+		.into());
+	}
+	Ok(())
+}
+
+// A segment we can't build, the txhashset being compacted past the header
+// or the segment out of range.
+fn segment_error(e: SegmentError) -> Error {
+	ErrorKind::SegmentsUnavailable(format!("{:?}", e)).into()
+}
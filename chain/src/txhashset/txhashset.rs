@@ -31,17 +31,17 @@ use crate::util::{file, secp_static, zip};
 use croaring::Bitmap;
 use kepler_store;
 use kepler_store::pmmr::{clean_files_by_prefix, PMMRBackend};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fs::{self, File};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Instant;
 
-const TXHASHSET_SUBDIR: &str = "txhashset";
+pub(crate) const TXHASHSET_SUBDIR: &str = "txhashset";
 
-const OUTPUT_SUBDIR: &str = "output";
-const RANGE_PROOF_SUBDIR: &str = "rangeproof";
-const KERNEL_SUBDIR: &str = "kernel";
+pub(crate) const OUTPUT_SUBDIR: &str = "output";
+pub(crate) const RANGE_PROOF_SUBDIR: &str = "rangeproof";
+pub(crate) const KERNEL_SUBDIR: &str = "kernel";
 
 const TXHASHSET_ZIP: &str = "txhashset_snapshot";
 
@@ -221,6 +221,15 @@ impl TxHashSet {
 		self.kernel_pmmr_h.backend.release_files();
 	}
 
+	/// Close and reopen all backend files, releasing the memory taken by
+	/// their memory maps. Nothing must be pending a sync.
+	pub fn reopen_backend_files(&mut self) -> Result<(), Error> {
+		self.output_pmmr_h.backend.reopen_files()?;
+		self.rproof_pmmr_h.backend.reopen_files()?;
+		self.kernel_pmmr_h.backend.reopen_files()?;
+		Ok(())
+	}
+
 	/// Check if an output is unspent.
 	/// We look in the index to find the output MMR pos.
 	/// Then we check the entry in the output MMR and confirm the hash matches.
@@ -254,6 +263,13 @@ impl TxHashSet {
 			.get_last_n_insertions(distance)
 	}
 
+	/// Output identifier at the given pos, regardless of whether it has been spent.
+	/// Returns None if the output has already been pruned from the MMR files.
+	pub fn get_output_from_file(&self, pos: u64) -> Option<OutputIdentifier> {
+		ReadonlyPMMR::at(&self.output_pmmr_h.backend, self.output_pmmr_h.last_pos)
+			.get_data_from_file(pos)
+	}
+
 	/// as above, for range proofs
 	pub fn last_n_rangeproof(&self, distance: u64) -> Vec<(Hash, RangeProof)> {
 		ReadonlyPMMR::at(&self.rproof_pmmr_h.backend, self.rproof_pmmr_h.last_pos)
@@ -300,6 +316,26 @@ impl TxHashSet {
 			.elements_from_pmmr_index(start_index, max_count, max_index)
 	}
 
+	/// Kernels along with their MMR index, from the given pmmr index up to the
+	/// specified limit. max_index is the last PMMR index to consider.
+	pub fn kernels_by_pmmr_index(
+		&self,
+		start_index: u64,
+		max_count: u64,
+		max_index: u64,
+	) -> Vec<(TxKernel, u64)> {
+		let pmmr = ReadonlyPMMR::at(&self.kernel_pmmr_h.backend, self.kernel_pmmr_h.last_pos);
+		let mut kernels = vec![];
+		let mut index = start_index.max(1);
+		while index <= max_index && (kernels.len() as u64) < max_count {
+			if let Some(kernel) = pmmr.get_data(index) {
+				kernels.push((kernel, index));
+			}
+			index += 1;
+		}
+		kernels
+	}
+
 	/// Find a kernel with a given excess. Work backwards from `max_index` to `min_index`
 	pub fn find_kernel(
 		&self,
@@ -323,25 +359,88 @@ impl TxHashSet {
 		None
 	}
 
+	/// Find the latest kernels with the provided excesses between the
+	/// provided kernel MMR indices, scanning the kernel MMR once for all of
+	/// them. Excesses not found are left out of the result.
+	pub fn find_kernels(
+		&self,
+		excesses: &HashSet<Commitment>,
+		min_index: Option<u64>,
+		max_index: Option<u64>,
+	) -> HashMap<Commitment, (TxKernel, u64)> {
+		let min_index = min_index.unwrap_or(1);
+		let max_index = max_index.unwrap_or(self.kernel_pmmr_h.last_pos);
+
+		let pmmr = ReadonlyPMMR::at(&self.kernel_pmmr_h.backend, self.kernel_pmmr_h.last_pos);
+		let mut found = HashMap::new();
+		let mut index = max_index + 1;
+		while index > min_index && found.len() < excesses.len() {
+			index -= 1;
+			if let Some(kernel) = pmmr.get_data(index) {
+				if excesses.contains(&kernel.excess) && !found.contains_key(&kernel.excess) {
+					found.insert(kernel.excess, (kernel, index));
+				}
+			}
+		}
+		found
+	}
+
 	/// Get MMR roots.
 	pub fn roots(&self) -> TxHashSetRoots {
-		let output_pmmr =
-			ReadonlyPMMR::at(&self.output_pmmr_h.backend, self.output_pmmr_h.last_pos);
-		let rproof_pmmr =
-			ReadonlyPMMR::at(&self.rproof_pmmr_h.backend, self.rproof_pmmr_h.last_pos);
-		let kernel_pmmr =
-			ReadonlyPMMR::at(&self.kernel_pmmr_h.backend, self.kernel_pmmr_h.last_pos);
+		self.view().roots()
+	}
 
-		TxHashSetRoots {
-			output_roots: OutputRoots {
-				pmmr_root: output_pmmr.root(),
-				bitmap_root: self.bitmap_accumulator.root(),
-			},
-			rproof_root: rproof_pmmr.root(),
-			kernel_root: kernel_pmmr.root(),
+	/// Validate the txhashset state against the provided block header, the
+	/// one it's at. Unlike the validation of an extension nothing is rewound,
+	/// the MMRs are only read.
+	/// A "fast validation" will skip rangeproof verification and kernel
+	/// signature verification.
+	pub fn validate(
+		&self,
+		genesis: &BlockHeader,
+		fast_validation: bool,
+		status: &dyn TxHashsetWriteStatus,
+		header: &BlockHeader,
+	) -> Result<(Commitment, Commitment), Error> {
+		self.view()
+			.validate(genesis, fast_validation, false, status, header)
+	}
+
+	// Readonly view of the MMRs, to validate them.
+	fn view(&self) -> TxHashSetView<'_> {
+		TxHashSetView {
+			output_pmmr: ReadonlyPMMR::at(&self.output_pmmr_h.backend, self.output_pmmr_h.last_pos),
+			rproof_pmmr: ReadonlyPMMR::at(&self.rproof_pmmr_h.backend, self.rproof_pmmr_h.last_pos),
+			kernel_pmmr: ReadonlyPMMR::at(&self.kernel_pmmr_h.backend, self.kernel_pmmr_h.last_pos),
+			bitmap_accumulator: &self.bitmap_accumulator,
 		}
 	}
 
+	/// Readonly view of the output MMR at the size committed to by the header.
+	pub fn output_pmmr_at(
+		&self,
+		header: &BlockHeader,
+	) -> ReadonlyPMMR<'_, Output, PMMRBackend<Output>> {
+		ReadonlyPMMR::at(&self.output_pmmr_h.backend, header.output_mmr_size)
+	}
+
+	/// Readonly view of the rangeproof MMR at the size committed to by the
+	/// header.
+	pub fn rproof_pmmr_at(
+		&self,
+		header: &BlockHeader,
+	) -> ReadonlyPMMR<'_, RangeProof, PMMRBackend<RangeProof>> {
+		ReadonlyPMMR::at(&self.rproof_pmmr_h.backend, header.output_mmr_size)
+	}
+
+	/// Readonly view of the kernel MMR at the size committed to by the header.
+	pub fn kernel_pmmr_at(
+		&self,
+		header: &BlockHeader,
+	) -> ReadonlyPMMR<'_, TxKernel, PMMRBackend<TxKernel>> {
+		ReadonlyPMMR::at(&self.kernel_pmmr_h.backend, header.kernel_mmr_size)
+	}
+
 	/// Return Commit's MMR position
 	pub fn get_output_pos(&self, commit: &Commitment) -> Result<u64, Error> {
 		Ok(self.commit_index.get_output_pos(&commit)?)
@@ -461,6 +560,51 @@ impl TxHashSet {
 		);
 		Ok(())
 	}
+
+	/// (Re)build the kernel_pos index from the kernel MMR, indexing every
+	/// kernel of the current chain with the height of its block. Kernels are
+	/// never removed from the MMR so this walks the whole chain.
+	pub fn init_kernel_pos_index(
+		&self,
+		header_pmmr: &PMMRHandle<BlockHeader>,
+		batch: &Batch<'_>,
+	) -> Result<(), Error> {
+		let now = Instant::now();
+
+		let mut removed_count = 0;
+		for (key, _) in batch.kernel_pos_iter()? {
+			batch.delete(&key)?;
+			removed_count += 1;
+		}
+		debug!(
+			"init_kernel_pos_index: removed {} existing index entries",
+			removed_count
+		);
+
+		let kernel_pmmr =
+			ReadonlyPMMR::at(&self.kernel_pmmr_h.backend, self.kernel_pmmr_h.last_pos);
+		let max_height = batch.head()?.height;
+
+		let mut kernel_count = 0;
+		let mut pos = 1;
+		for height in 0..=max_height {
+			let hash = header_pmmr.get_header_hash_by_height(height)?;
+			let h = batch.get_block_header(&hash)?;
+			while pos <= h.kernel_mmr_size {
+				if let Some(kernel) = kernel_pmmr.get_data(pos) {
+					batch.save_kernel_pos_height(&kernel.excess, pos, h.height)?;
+					kernel_count += 1;
+				}
+				pos += 1;
+			}
+		}
+		debug!(
+			"init_kernel_pos_index: added entries for {} kernels, took {}s",
+			kernel_count,
+			now.elapsed().as_secs(),
+		);
+		Ok(())
+	}
 }
 
 /// Starts a new unit of work to extend (or rewind) the chain with additional
@@ -819,7 +963,21 @@ impl<'a> HeaderExtension<'a> {
 
 	/// The root of the header MMR for convenience.
 	pub fn root(&self) -> Result<Hash, Error> {
-		Ok(self.pmmr.root().map_err(|_| ErrorKind::InvalidRoot)?)
+		Ok(self
+			.pmmr
+			.root()
+			.map_err(|e| ErrorKind::InvalidRoot(format!("header MMR root: {}", e)))?)
+	}
+
+	/// Build a Merkle proof of the header at the given height being included
+	/// in the current header MMR.
+	pub fn merkle_proof(&self, height: u64) -> Result<MerkleProof, Error> {
+		let pos = pmmr::insertion_to_pmmr_index(height + 1);
+		let merkle_proof = self
+			.pmmr
+			.merkle_proof(pos)
+			.map_err(&ErrorKind::TxHashSetErr)?;
+		Ok(merkle_proof)
 	}
 
 	/// Validate the prev_root of the header against the root of the current header MMR.
@@ -829,8 +987,14 @@ impl<'a> HeaderExtension<'a> {
 		if header.height == 0 {
 			return Ok(());
 		}
-		if self.root()? != header.prev_root {
-			Err(ErrorKind::InvalidRoot.into())
+		let root = self.root()?;
+		if root != header.prev_root {
+			Err(ErrorKind::InvalidRoot(format!(
+				"header MMR root {} expected, {} computed",
+				header.prev_root.to_hex(),
+				root.to_hex()
+			))
+			.into())
 		} else {
 			Ok(())
 		}
@@ -868,25 +1032,11 @@ impl<'a> Committed for Extension<'a> {
 	}
 
 	fn outputs_committed(&self) -> Vec<Commitment> {
-		let mut commitments = vec![];
-		for pos in self.output_pmmr.leaf_pos_iter() {
-			if let Some(out) = self.output_pmmr.get_data(pos) {
-				commitments.push(out.commit);
-			}
-		}
-		commitments
+		self.view().outputs_committed()
 	}
 
 	fn kernels_committed(&self) -> Vec<Commitment> {
-		let mut commitments = vec![];
-		for n in 1..self.kernel_pmmr.unpruned_size() + 1 {
-			if pmmr::is_leaf(n) {
-				if let Some(kernel) = self.kernel_pmmr.get_data(n) {
-					commitments.push(kernel.excess());
-				}
-			}
-		}
-		commitments
+		self.view().kernels_committed()
 	}
 }
 
@@ -911,6 +1061,14 @@ impl<'a> Extension<'a> {
 		}
 	}
 
+	/// The positions of the unspent outputs.
+	pub fn unspent_output_bitmap(&self) -> Bitmap {
+		self.output_pmmr
+			.leaf_pos_iter()
+			.map(|pos| pos as u32)
+			.collect()
+	}
+
 	/// The head representing the furthest extent of the current extension.
 	pub fn head(&self) -> Tip {
 		self.head.clone()
@@ -944,16 +1102,23 @@ impl<'a> Extension<'a> {
 
 		// Remove the output from the output and rangeproof MMRs.
 		// Add spent_pos to affected_pos to update the accumulator later on.
-		// Remove the spent output from the output_pos index.
+		// Move the spent output from the output_pos index to the spent one.
 		for input in b.inputs() {
 			let spent_pos = self.apply_input(input, batch)?;
 			affected_pos.push(spent_pos.pos);
 			batch.delete_output_pos_height(&input.commitment())?;
+			batch.save_spent_output_pos_height(
+				&input.commitment(),
+				spent_pos.pos,
+				spent_pos.height,
+			)?;
 			spent.push(spent_pos);
 		}
 
+		// Add the new kernel to the kernel_pos index.
 		for kernel in b.kernels() {
-			self.apply_kernel(kernel)?;
+			let pos = self.apply_kernel(kernel)?;
+			batch.save_kernel_pos_height(&kernel.excess, pos, b.header.height)?;
 		}
 
 		// Update our BitmapAccumulator based on affected outputs (both spent and created).
@@ -1052,11 +1217,12 @@ impl<'a> Extension<'a> {
 	}
 
 	/// Push kernel onto MMR (hash and data files).
-	fn apply_kernel(&mut self, kernel: &TxKernel) -> Result<(), Error> {
-		self.kernel_pmmr
+	fn apply_kernel(&mut self, kernel: &TxKernel) -> Result<u64, Error> {
+		let pos = self
+			.kernel_pmmr
 			.push(kernel)
 			.map_err(&ErrorKind::TxHashSetErr)?;
-		Ok(())
+		Ok(pos)
 	}
 
 	/// Build a Merkle proof for the given output and the block
@@ -1154,12 +1320,14 @@ impl<'a> Extension<'a> {
 			bitmap.iter().map(|x| x.into()).collect()
 		};
 
-		if header.height == 0 {
+		let prev_kernel_mmr_size = if header.height == 0 {
 			self.rewind_mmrs_to_pos(0, 0, &spent_pos)?;
+			0
 		} else {
 			let prev = batch.get_previous_header(&header)?;
 			self.rewind_mmrs_to_pos(prev.output_mmr_size, prev.kernel_mmr_size, &spent_pos)?;
-		}
+			prev.kernel_mmr_size
+		};
 
 		// Update our BitmapAccumulator based on affected outputs.
 		// We want to "unspend" every rewound spent output.
@@ -1185,6 +1353,16 @@ impl<'a> Extension<'a> {
 			);
 		}
 
+		// Remove the kernel_pos entries of the kernels of the block being rewound,
+		// leaving alone the ones pointing to an earlier kernel with the same excess.
+		for kernel in block.kernels() {
+			if let Ok((pos, _)) = batch.get_kernel_pos_height(&kernel.excess) {
+				if pos > prev_kernel_mmr_size {
+					batch.delete_kernel_pos_height(&kernel.excess)?;
+				}
+			}
+		}
+
 		// Update output_pos based on "unspending" all spent pos from this block.
 		// This is necessary to ensure the output_pos index correclty reflects a
 		// reused output commitment. For example an output at pos 1, spent, reused at pos 2.
@@ -1192,6 +1370,7 @@ impl<'a> Extension<'a> {
 		if let Ok(spent) = spent {
 			for (x, y) in block.inputs().into_iter().zip(spent) {
 				batch.save_output_pos_height(&x.commitment(), y.pos, y.height)?;
+				let _ = batch.delete_spent_output_pos_height(&x.commitment());
 			}
 		}
 
@@ -1227,17 +1406,17 @@ impl<'a> Extension<'a> {
 				pmmr_root: self
 					.output_pmmr
 					.root()
-					.map_err(|_| ErrorKind::InvalidRoot)?,
+					.map_err(|e| ErrorKind::InvalidRoot(format!("output MMR root: {}", e)))?,
 				bitmap_root: self.bitmap_accumulator.root(),
 			},
 			rproof_root: self
 				.rproof_pmmr
 				.root()
-				.map_err(|_| ErrorKind::InvalidRoot)?,
+				.map_err(|e| ErrorKind::InvalidRoot(format!("rangeproof MMR root: {}", e)))?,
 			kernel_root: self
 				.kernel_pmmr
 				.root()
-				.map_err(|_| ErrorKind::InvalidRoot)?,
+				.map_err(|e| ErrorKind::InvalidRoot(format!("kernel MMR root: {}", e)))?,
 		})
 	}
 
@@ -1251,44 +1430,7 @@ impl<'a> Extension<'a> {
 
 	/// Validate the header, output and kernel MMR sizes against the block header.
 	pub fn validate_sizes(&self, header: &BlockHeader) -> Result<(), Error> {
-		if header.height == 0 {
-			return Ok(());
-		}
-		if (
-			header.output_mmr_size,
-			header.output_mmr_size,
-			header.kernel_mmr_size,
-		) != self.sizes()
-		{
-			Err(ErrorKind::InvalidMMRSize.into())
-		} else {
-			Ok(())
-		}
-	}
-
-	fn validate_mmrs(&self) -> Result<(), Error> {
-		let now = Instant::now();
-
-		// validate all hashes and sums within the trees
-		if let Err(e) = self.output_pmmr.validate() {
-			return Err(ErrorKind::InvalidTxHashSet(e).into());
-		}
-		if let Err(e) = self.rproof_pmmr.validate() {
-			return Err(ErrorKind::InvalidTxHashSet(e).into());
-		}
-		if let Err(e) = self.kernel_pmmr.validate() {
-			return Err(ErrorKind::InvalidTxHashSet(e).into());
-		}
-
-		debug!(
-			"txhashset: validated the output {}, rproof {}, kernel {} mmrs, took {}s",
-			self.output_pmmr.unpruned_size(),
-			self.rproof_pmmr.unpruned_size(),
-			self.kernel_pmmr.unpruned_size(),
-			now.elapsed().as_secs(),
-		);
-
-		Ok(())
+		self.view().validate_sizes(header)
 	}
 
 	/// Validate full kernel sums against the provided header (for overage and kernel_offset).
@@ -1300,53 +1442,22 @@ impl<'a> Extension<'a> {
 		genesis: &BlockHeader,
 		header: &BlockHeader,
 	) -> Result<(Commitment, Commitment), Error> {
-		let now = Instant::now();
-
-		let (utxo_sum, kernel_sum) = self.verify_kernel_sums(
-			header.total_overage(genesis.kernel_mmr_size > 0),
-			header.total_kernel_offset(),
-		)?;
-
-		debug!(
-			"txhashset: validated total kernel sums, took {}s",
-			now.elapsed().as_secs(),
-		);
-
-		Ok((utxo_sum, kernel_sum))
+		self.view().validate_kernel_sums(genesis, header)
 	}
 
 	/// Validate the txhashset state against the provided block header.
 	/// A "fast validation" will skip rangeproof verification and kernel signature verification.
+	/// With skip_rproofs only the rangeproof verification is skipped.
 	pub fn validate(
 		&self,
 		genesis: &BlockHeader,
 		fast_validation: bool,
+		skip_rproofs: bool,
 		status: &dyn TxHashsetWriteStatus,
 		header: &BlockHeader,
 	) -> Result<(Commitment, Commitment), Error> {
-		self.validate_mmrs()?;
-		self.validate_roots(header)?;
-		self.validate_sizes(header)?;
-
-		if self.head.height == 0 {
-			let zero_commit = secp_static::commit_to_zero_value();
-			return Ok((zero_commit, zero_commit));
-		}
-
-		// The real magicking happens here. Sum of kernel excesses should equal
-		// sum of unspent outputs minus total supply.
-		let (output_sum, kernel_sum) = self.validate_kernel_sums(genesis, header)?;
-
-		// These are expensive verification step (skipped for "fast validation").
-		if !fast_validation {
-			// Verify the rangeproof associated with each unspent output.
-			self.verify_rangeproofs(status)?;
-
-			// Verify all the kernel signatures.
-			self.verify_kernel_signatures(status)?;
-		}
-
-		Ok((output_sum, kernel_sum))
+		self.view()
+			.validate(genesis, fast_validation, skip_rproofs, status, header)
 	}
 
 	/// Force the rollback of this extension, no matter the result
@@ -1386,6 +1497,190 @@ impl<'a> Extension<'a> {
 		)
 	}
 
+	// Readonly view of the MMRs of the extension, to validate them.
+	fn view(&self) -> TxHashSetView<'_> {
+		TxHashSetView {
+			output_pmmr: self.output_pmmr.readonly_pmmr(),
+			rproof_pmmr: self.rproof_pmmr.readonly_pmmr(),
+			kernel_pmmr: self.kernel_pmmr.readonly_pmmr(),
+			bitmap_accumulator: &self.bitmap_accumulator,
+		}
+	}
+}
+
+/// Readonly view of the output, rangeproof and kernel MMRs, validated the
+/// same way whether they're those of an extension or the txhashset itself.
+struct TxHashSetView<'a> {
+	output_pmmr: ReadonlyPMMR<'a, Output, PMMRBackend<Output>>,
+	rproof_pmmr: ReadonlyPMMR<'a, RangeProof, PMMRBackend<RangeProof>>,
+	kernel_pmmr: ReadonlyPMMR<'a, TxKernel, PMMRBackend<TxKernel>>,
+	bitmap_accumulator: &'a BitmapAccumulator,
+}
+
+impl<'a> Committed for TxHashSetView<'a> {
+	fn inputs_committed(&self) -> Vec<Commitment> {
+		vec![]
+	}
+
+	fn outputs_committed(&self) -> Vec<Commitment> {
+		let mut commitments = vec![];
+		for pos in self.output_pmmr.leaf_pos_iter() {
+			if let Some(out) = self.output_pmmr.get_data(pos) {
+				commitments.push(out.commit);
+			}
+		}
+		commitments
+	}
+
+	fn kernels_committed(&self) -> Vec<Commitment> {
+		let mut commitments = vec![];
+		for n in 1..self.kernel_pmmr.unpruned_size() + 1 {
+			if pmmr::is_leaf(n) {
+				if let Some(kernel) = self.kernel_pmmr.get_data(n) {
+					commitments.push(kernel.excess());
+				}
+			}
+		}
+		commitments
+	}
+}
+
+impl<'a> TxHashSetView<'a> {
+	fn roots(&self) -> TxHashSetRoots {
+		TxHashSetRoots {
+			output_roots: OutputRoots {
+				pmmr_root: self.output_pmmr.root(),
+				bitmap_root: self.bitmap_accumulator.root(),
+			},
+			rproof_root: self.rproof_pmmr.root(),
+			kernel_root: self.kernel_pmmr.root(),
+		}
+	}
+
+	fn sizes(&self) -> (u64, u64, u64) {
+		(
+			self.output_pmmr.unpruned_size(),
+			self.rproof_pmmr.unpruned_size(),
+			self.kernel_pmmr.unpruned_size(),
+		)
+	}
+
+	fn validate(
+		&self,
+		genesis: &BlockHeader,
+		fast_validation: bool,
+		skip_rproofs: bool,
+		status: &dyn TxHashsetWriteStatus,
+		header: &BlockHeader,
+	) -> Result<(Commitment, Commitment), Error> {
+		self.validate_mmrs(status)?;
+
+		if header.height == 0 {
+			let zero_commit = secp_static::commit_to_zero_value();
+			return Ok((zero_commit, zero_commit));
+		}
+		self.roots().validate(header)?;
+		self.validate_sizes(header)?;
+
+		// The real magicking happens here. Sum of kernel excesses should equal
+		// sum of unspent outputs minus total supply.
+		let (output_sum, kernel_sum) = self.validate_kernel_sums(genesis, header)?;
+
+		// These are expensive verification step (skipped for "fast validation").
+		if !fast_validation {
+			// Verify the rangeproof associated with each unspent output,
+			// unless assumed valid.
+			if !skip_rproofs {
+				self.verify_rangeproofs(status)?;
+			}
+
+			// Verify all the kernel signatures.
+			self.verify_kernel_signatures(status)?;
+		}
+
+		Ok((output_sum, kernel_sum))
+	}
+
+	fn validate_sizes(&self, header: &BlockHeader) -> Result<(), Error> {
+		if header.height == 0 {
+			return Ok(());
+		}
+		let (output_mmr_size, rproof_mmr_size, kernel_mmr_size) = self.sizes();
+		if header.output_mmr_size != output_mmr_size {
+			Err(ErrorKind::InvalidMMRSize(format!(
+				"output MMR size {} expected, {} computed",
+				header.output_mmr_size, output_mmr_size
+			))
+			.into())
+		} else if header.output_mmr_size != rproof_mmr_size {
+			Err(ErrorKind::InvalidMMRSize(format!(
+				"rangeproof MMR size {} expected, {} computed",
+				header.output_mmr_size, rproof_mmr_size
+			))
+			.into())
+		} else if header.kernel_mmr_size != kernel_mmr_size {
+			Err(ErrorKind::InvalidMMRSize(format!(
+				"kernel MMR size {} expected, {} computed",
+				header.kernel_mmr_size, kernel_mmr_size
+			))
+			.into())
+		} else {
+			Ok(())
+		}
+	}
+
+	fn validate_kernel_sums(
+		&self,
+		genesis: &BlockHeader,
+		header: &BlockHeader,
+	) -> Result<(Commitment, Commitment), Error> {
+		let now = Instant::now();
+
+		let (utxo_sum, kernel_sum) = self.verify_kernel_sums(
+			header.total_overage(genesis.kernel_mmr_size > 0),
+			header.total_kernel_offset(),
+		)?;
+
+		debug!(
+			"txhashset: validated total kernel sums, took {}s",
+			now.elapsed().as_secs(),
+		);
+
+		Ok((utxo_sum, kernel_sum))
+	}
+
+	fn validate_mmrs(&self, status: &dyn TxHashsetWriteStatus) -> Result<(), Error> {
+		let now = Instant::now();
+
+		let (output_size, rproof_size, kernel_size) = self.sizes();
+		let total_size = output_size + rproof_size + kernel_size;
+
+		// validate all hashes and sums within the trees
+		status.on_validation_mmrs(0, 3, 0, total_size);
+		if let Err(e) = self.output_pmmr.validate() {
+			return Err(ErrorKind::InvalidTxHashSet(e).into());
+		}
+		status.on_validation_mmrs(1, 3, output_size, total_size);
+		if let Err(e) = self.rproof_pmmr.validate() {
+			return Err(ErrorKind::InvalidTxHashSet(e).into());
+		}
+		status.on_validation_mmrs(2, 3, output_size + rproof_size, total_size);
+		if let Err(e) = self.kernel_pmmr.validate() {
+			return Err(ErrorKind::InvalidTxHashSet(e).into());
+		}
+		status.on_validation_mmrs(3, 3, total_size, total_size);
+
+		debug!(
+			"txhashset: validated the output {}, rproof {}, kernel {} mmrs, took {}s",
+			self.output_pmmr.unpruned_size(),
+			self.rproof_pmmr.unpruned_size(),
+			self.kernel_pmmr.unpruned_size(),
+			now.elapsed().as_secs(),
+		);
+
+		Ok(())
+	}
+
 	fn verify_kernel_signatures(&self, status: &dyn TxHashsetWriteStatus) -> Result<(), Error> {
 		let now = Instant::now();
 		const KERNEL_BATCH_SIZE: usize = 5_000;
@@ -1403,7 +1698,7 @@ impl<'a> Extension<'a> {
 			}
 
 			if tx_kernels.len() >= KERNEL_BATCH_SIZE || n >= self.kernel_pmmr.unpruned_size() {
-				TxKernel::batch_sig_verify(&tx_kernels)?;
+				TxKernel::par_batch_sig_verify(&tx_kernels)?;
 				kern_count += tx_kernels.len() as u64;
 				tx_kernels.clear();
 				status.on_validation_kernels(kern_count, total_kernels);
@@ -1451,7 +1746,7 @@ impl<'a> Extension<'a> {
 			proof_count += 1;
 
 			if proofs.len() >= 1_000 {
-				Output::batch_verify_proofs(&commits, &proofs)?;
+				Output::par_batch_verify_proofs(&commits, &proofs)?;
 				commits.clear();
 				proofs.clear();
 				debug!(
@@ -1466,7 +1761,7 @@ impl<'a> Extension<'a> {
 
 		// remaining part which not full of 1000 range proofs
 		if !proofs.is_empty() {
-			Output::batch_verify_proofs(&commits, &proofs)?;
+			Output::par_batch_verify_proofs(&commits, &proofs)?;
 			commits.clear();
 			proofs.clear();
 			debug!(
@@ -1593,6 +1888,7 @@ pub fn zip_write(
 	root_dir: PathBuf,
 	txhashset_data: File,
 	header: &BlockHeader,
+	status: &dyn TxHashsetWriteStatus,
 ) -> Result<(), Error> {
 	debug!("zip_write on path: {:?}", root_dir);
 	let txhashset_path = root_dir.join(TXHASHSET_SUBDIR);
@@ -1605,7 +1901,9 @@ pub fn zip_write(
 	// No attempt is made to be permissive or forgiving with "alternative" paths.
 	// These are the *only* files we will attempt to extract from the zip file.
 	// If any of these are missing we will attempt to continue as some are potentially optional.
-	zip::extract_files(txhashset_data, &txhashset_path, files)?;
+	zip::extract_files_with_progress(txhashset_data, &txhashset_path, files, |size, total| {
+		status.on_unzip(size, total)
+	})?;
 	Ok(())
 }
 
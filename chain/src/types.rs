@@ -18,11 +18,11 @@ use chrono::prelude::{DateTime, Utc};
 use std::sync::Arc;
 
 use crate::core::core::hash::{Hash, Hashed, ZERO_HASH};
-use crate::core::core::{Block, BlockHeader, HeaderVersion};
+use crate::core::core::{Block, BlockHeader, HeaderVersion, TransactionBody};
 use crate::core::pow::Difficulty;
 use crate::core::ser::{self, PMMRIndexHashable, Readable, Reader, Writeable, Writer};
 use crate::error::{Error, ErrorKind};
-use crate::util::RwLock;
+use crate::util::{RwLock, Watch};
 
 bitflags! {
 /// Options for block validation
@@ -35,6 +35,15 @@ bitflags! {
 		const SYNC = 0b0000_0010;
 		/// Block validation on a block we mined ourselves
 		const MINE = 0b0000_0100;
+		/// Skips the rangeproofs verification, only for blocks we already
+		/// validated once (trusted replay or reindex).
+		const SKIP_RANGEPROOF = 0b0000_1000;
+		/// Only validates and processes the header of the block, leaving
+		/// its body aside.
+		const HEADER_ONLY = 0b0001_0000;
+		/// Block coming from a local source (replay, file import) rather
+		/// than from the network. Its arrival time isn't recorded.
+		const FROM_LOCAL = 0b0010_0000;
 	}
 }
 
@@ -65,6 +74,23 @@ pub enum SyncStatus {
 	},
 	/// Setting up before validation
 	TxHashsetSetup,
+	/// Extracting the downloaded txhashset archive
+	TxHashsetUnzip {
+		unzipped_size: u64,
+		total_size: u64,
+	},
+	/// Validating the kernel MMR root of every header
+	TxHashsetKernelHistoryValidation {
+		headers: u64,
+		headers_total: u64,
+	},
+	/// Validating the hashes of the output, range proof and kernel MMRs
+	TxHashsetMmrsValidation {
+		mmrs: u64,
+		mmrs_total: u64,
+		nodes: u64,
+		nodes_total: u64,
+	},
 	/// Validating the kernels
 	TxHashsetKernelsValidation {
 		kernels: u64,
@@ -87,10 +113,23 @@ pub enum SyncStatus {
 	Shutdown,
 }
 
+/// Change to the chain head or to the sync status, published so internal
+/// components can react to it instead of polling for it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChainEvent {
+	/// Nothing published yet
+	Started,
+	/// The chain head moved to the provided tip
+	TipUpdated(Tip),
+	/// The sync status changed
+	SyncStatusChanged(SyncStatus),
+}
+
 /// Current sync state. Encapsulates the current SyncStatus.
 pub struct SyncState {
 	current: RwLock<SyncStatus>,
 	sync_error: Arc<RwLock<Option<Error>>>,
+	events: Arc<Watch<ChainEvent>>,
 }
 
 impl SyncState {
@@ -99,9 +138,16 @@ impl SyncState {
 		SyncState {
 			current: RwLock::new(SyncStatus::Initial),
 			sync_error: Arc::new(RwLock::new(None)),
+			events: Arc::new(Watch::new(ChainEvent::Started)),
 		}
 	}
 
+	/// Chain events, sync status changes are published there along with the
+	/// chain head updates published by the chain adapter.
+	pub fn events(&self) -> Arc<Watch<ChainEvent>> {
+		self.events.clone()
+	}
+
 	/// Whether the current state matches any active syncing operation.
 	/// Note: This includes our "initial" state.
 	pub fn is_syncing(&self) -> bool {
@@ -124,6 +170,8 @@ impl SyncState {
 		debug!("sync_state: sync_status: {:?} -> {:?}", *status, new_status,);
 
 		*status = new_status;
+		self.events
+			.publish(ChainEvent::SyncStatusChanged(new_status));
 	}
 
 	/// Update txhashset downloading progress
@@ -131,6 +179,8 @@ impl SyncState {
 		if let SyncStatus::TxHashsetDownload { .. } = new_status {
 			let mut status = self.current.write();
 			*status = new_status;
+			self.events
+				.publish(ChainEvent::SyncStatusChanged(new_status));
 			true
 		} else {
 			false
@@ -158,6 +208,29 @@ impl TxHashsetWriteStatus for SyncState {
 		self.update(SyncStatus::TxHashsetSetup);
 	}
 
+	fn on_unzip(&self, unzipped_size: u64, total_size: u64) {
+		self.update(SyncStatus::TxHashsetUnzip {
+			unzipped_size,
+			total_size,
+		});
+	}
+
+	fn on_validation_kernel_history(&self, headers: u64, headers_total: u64) {
+		self.update(SyncStatus::TxHashsetKernelHistoryValidation {
+			headers,
+			headers_total,
+		});
+	}
+
+	fn on_validation_mmrs(&self, mmrs: u64, mmrs_total: u64, nodes: u64, nodes_total: u64) {
+		self.update(SyncStatus::TxHashsetMmrsValidation {
+			mmrs,
+			mmrs_total,
+			nodes,
+			nodes_total,
+		});
+	}
+
 	fn on_validation_kernels(&self, kernels: u64, kernels_total: u64) {
 		self.update(SyncStatus::TxHashsetKernelsValidation {
 			kernels,
@@ -212,13 +285,25 @@ impl TxHashSetRoots {
 			self.output_roots.merged_root(header),
 		);
 
-		if header.output_root != self.output_root(header)
-			|| header.range_proof_root != self.rproof_root
-			|| header.kernel_root != self.kernel_root
-		{
-			Err(ErrorKind::InvalidRoot.into())
+		let output_root = self.output_root(header);
+		let mismatch = if header.output_root != output_root {
+			Some(("output", header.output_root, output_root))
+		} else if header.range_proof_root != self.rproof_root {
+			Some(("rangeproof", header.range_proof_root, self.rproof_root))
+		} else if header.kernel_root != self.kernel_root {
+			Some(("kernel", header.kernel_root, self.kernel_root))
 		} else {
-			Ok(())
+			None
+		};
+		match mismatch {
+			Some((mmr, expected, computed)) => Err(ErrorKind::InvalidRoot(format!(
+				"{} MMR root {} expected, {} computed",
+				mmr,
+				expected.to_hex(),
+				computed.to_hex()
+			))
+			.into()),
+			None => Ok(()),
 		}
 	}
 }
@@ -283,6 +368,277 @@ impl Writeable for CommitPos {
 	}
 }
 
+/// Weight and fees of a block, the fee market statistics are built from.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct BlockFees {
+	/// Height of the block
+	pub height: u64,
+	/// Weight of the block, as counted against the max block weight
+	pub weight: u64,
+	/// Sum of the fees of the block
+	pub fees: u64,
+	/// Number of kernels, not counting the coinbase ones
+	pub kernels: u64,
+}
+
+impl BlockFees {
+	/// Weight and fees of the provided block.
+	pub fn from_block(b: &Block) -> BlockFees {
+		let weight = TransactionBody::weight_as_block(
+			b.inputs().len(),
+			b.outputs().len(),
+			b.kernels().len(),
+		);
+		BlockFees {
+			height: b.header.height,
+			weight: weight as u64,
+			fees: b.total_fees(),
+			kernels: b.kernels().iter().filter(|k| !k.is_coinbase()).count() as u64,
+		}
+	}
+}
+
+/// Hourly snapshot of the node statistics, kept to chart their trends.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct StatsSnapshot {
+	/// Start of the hour covered (seconds since epoch)
+	pub timestamp: i64,
+	/// Number of connected peers at the end of the hour
+	pub peer_count: u32,
+	/// Number of transactions in the mempool at the end of the hour
+	pub mempool_size: u32,
+	/// How many blocks behind our most advanced peer we were
+	pub sync_lag: u64,
+	/// Bytes sent to our peers over the hour
+	pub sent_bytes: u64,
+	/// Bytes received from our peers over the hour
+	pub received_bytes: u64,
+	/// Height of our chain head at the end of the hour
+	pub height: u64,
+	/// Number of blocks added to our chain over the hour
+	pub blocks: u64,
+	/// Average interval between those blocks (seconds), 0 if none
+	pub block_interval: u64,
+}
+
+impl Writeable for StatsSnapshot {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_i64(self.timestamp)?;
+		writer.write_u32(self.peer_count)?;
+		writer.write_u32(self.mempool_size)?;
+		writer.write_u64(self.sync_lag)?;
+		writer.write_u64(self.sent_bytes)?;
+		writer.write_u64(self.received_bytes)?;
+		writer.write_u64(self.height)?;
+		writer.write_u64(self.blocks)?;
+		writer.write_u64(self.block_interval)?;
+		Ok(())
+	}
+}
+
+impl Readable for StatsSnapshot {
+	fn read(reader: &mut dyn Reader) -> Result<StatsSnapshot, ser::Error> {
+		Ok(StatsSnapshot {
+			timestamp: reader.read_i64()?,
+			peer_count: reader.read_u32()?,
+			mempool_size: reader.read_u32()?,
+			sync_lag: reader.read_u64()?,
+			sent_bytes: reader.read_u64()?,
+			received_bytes: reader.read_u64()?,
+			height: reader.read_u64()?,
+			blocks: reader.read_u64()?,
+			block_interval: reader.read_u64()?,
+		})
+	}
+}
+
+/// Compactions of the chain state, the last one and whether one is running.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct CompactionStatus {
+	/// Whether a compaction is running
+	pub running: bool,
+	/// When the running or last compaction started (seconds since epoch)
+	pub last_started: Option<i64>,
+	/// When the last compaction finished (seconds since epoch)
+	pub last_finished: Option<i64>,
+	/// Disk space the last compaction freed (bytes)
+	pub bytes_reclaimed: Option<u64>,
+	/// Why the last compaction failed, if it did
+	pub last_error: Option<String>,
+}
+
+/// A block waiting in the orphan pool for its parent to be processed.
+#[derive(Debug, Clone)]
+pub struct OrphanInfo {
+	/// Header of the block
+	pub header: BlockHeader,
+	/// Number of inputs of the block
+	pub inputs: usize,
+	/// Number of outputs of the block
+	pub outputs: usize,
+	/// Number of kernels of the block
+	pub kernels: usize,
+	/// When the block was received
+	pub arrived: DateTime<Utc>,
+	/// Peer the block was received from, if any
+	pub peer: Option<String>,
+}
+
+/// Limits of the orphan pool, past which orphans get evicted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OrphanPoolLimits {
+	/// Most blocks in the pool, the highest evicted first
+	pub max_orphans: usize,
+	/// Orphans older than that (seconds) are evicted
+	pub max_age_secs: u64,
+	/// Orphans further ahead of our chain head are evicted. Should exceed
+	/// the pool size for body sync to fill it.
+	pub max_height_ahead: u64,
+}
+
+impl Default for OrphanPoolLimits {
+	fn default() -> OrphanPoolLimits {
+		OrphanPoolLimits {
+			max_orphans: crate::chain::MAX_ORPHAN_SIZE,
+			max_age_secs: 300,
+			max_height_ahead: 1_000,
+		}
+	}
+}
+
+/// A downloaded txhashset that failed validation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BadTxHashSet {
+	/// Hash of the header the txhashset was for
+	pub header: Hash,
+	/// Hash of its zip archive, none if rebuilt from segments
+	pub archive: Option<Hash>,
+	/// Peer that sent it, if known
+	pub peer: Option<String>,
+}
+
+/// Number of blocks in the orphan pool, and evicted from it for each reason
+/// since the node started.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OrphanPoolStats {
+	/// Blocks waiting for their parent
+	pub len: usize,
+	/// Most blocks the pool holds
+	pub max_orphans: usize,
+	/// Evicted for being too old
+	pub evicted_expired: usize,
+	/// Evicted for being too far ahead of the chain head
+	pub evicted_too_far: usize,
+	/// Evicted for the pool being full
+	pub evicted_full: usize,
+}
+
+impl OrphanPoolStats {
+	/// Blocks evicted for any reason.
+	pub fn evicted(&self) -> usize {
+		self.evicted_expired + self.evicted_too_far + self.evicted_full
+	}
+}
+
+/// A reorg of our chain, the blocks of the old head back to the fork point
+/// replaced by those of a fork with more work.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ReorgRecord {
+	/// When the reorg happened (seconds since epoch)
+	pub timestamp: i64,
+	/// Chain head before the reorg
+	pub old_tip: Tip,
+	/// Chain head after the reorg
+	pub new_tip: Tip,
+	/// Last block both chains have in common
+	pub fork_point: Hash,
+	/// Number of blocks taken off our chain
+	pub depth: u64,
+	/// Hashes of the blocks taken off our chain, highest first
+	pub disconnected: Vec<Hash>,
+}
+
+impl Writeable for ReorgRecord {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_i64(self.timestamp)?;
+		self.old_tip.write(writer)?;
+		self.new_tip.write(writer)?;
+		self.fork_point.write(writer)?;
+		writer.write_u64(self.depth)?;
+		writer.write_u64(self.disconnected.len() as u64)?;
+		self.disconnected.write(writer)
+	}
+}
+
+impl Readable for ReorgRecord {
+	fn read(reader: &mut dyn Reader) -> Result<ReorgRecord, ser::Error> {
+		let timestamp = reader.read_i64()?;
+		let old_tip = Tip::read(reader)?;
+		let new_tip = Tip::read(reader)?;
+		let fork_point = Hash::read(reader)?;
+		let depth = reader.read_u64()?;
+		let count = reader.read_u64()?;
+		let disconnected = ser::read_multi(reader, count)?;
+		Ok(ReorgRecord {
+			timestamp,
+			old_tip,
+			new_tip,
+			fork_point,
+			depth,
+			disconnected,
+		})
+	}
+}
+
+/// Version of the db schema, bumped with every change to the layout of the
+/// db that needs a migration.
+pub const DB_SCHEMA_VERSION: u32 = 3;
+
+/// Version of the format of the txhashset files.
+pub const TXHASHSET_FORMAT_VERSION: u32 = 1;
+
+/// Db schema and txhashset format versions a data dir was last written with.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct DataVersion {
+	/// Version of the db schema
+	pub db_schema: u32,
+	/// Version of the txhashset files format
+	pub txhashset_format: u32,
+}
+
+impl DataVersion {
+	/// Versions this binary reads and writes.
+	pub fn current() -> DataVersion {
+		DataVersion {
+			db_schema: DB_SCHEMA_VERSION,
+			txhashset_format: TXHASHSET_FORMAT_VERSION,
+		}
+	}
+
+	/// Whether the data was written by a newer binary than this one, which
+	/// may have migrated it to a layout we don't know about.
+	pub fn is_newer(&self) -> bool {
+		self.db_schema > DB_SCHEMA_VERSION || self.txhashset_format > TXHASHSET_FORMAT_VERSION
+	}
+}
+
+impl Writeable for DataVersion {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_u32(self.db_schema)?;
+		writer.write_u32(self.txhashset_format)?;
+		Ok(())
+	}
+}
+
+impl Readable for DataVersion {
+	fn read(reader: &mut dyn Reader) -> Result<DataVersion, ser::Error> {
+		Ok(DataVersion {
+			db_schema: reader.read_u32()?,
+			txhashset_format: reader.read_u32()?,
+		})
+	}
+}
+
 /// The tip of a fork. A handle to the fork ancestry from its leaf in the
 /// blockchain tree. References the max height and the latest and previous
 /// blocks
@@ -361,6 +717,11 @@ pub trait ChainAdapter {
 	/// The blockchain pipeline has accepted this block as valid and added
 	/// it to our chain.
 	fn block_accepted(&self, block: &Block, status: BlockStatus, opts: Options);
+
+	/// A block arrived with the same total difficulty as our chain head.
+	/// The head race was won (block is our new head) or lost based on our
+	/// configured HeadRacePolicy.
+	fn head_race(&self, _block: &Block, _won: bool) {}
 }
 
 /// Inform the caller of the current status of a txhashset write operation,
@@ -371,6 +732,12 @@ pub trait ChainAdapter {
 pub trait TxHashsetWriteStatus {
 	/// First setup of the txhashset
 	fn on_setup(&self);
+	/// Progress extracting the txhashset archive, in bytes
+	fn on_unzip(&self, unzipped_size: u64, total_size: u64);
+	/// Progress validating the kernel root of each header
+	fn on_validation_kernel_history(&self, headers: u64, headers_total: u64);
+	/// Progress validating the MMR hashes, in MMRs and MMR nodes
+	fn on_validation_mmrs(&self, mmrs: u64, mmrs_total: u64, nodes: u64, nodes_total: u64);
 	/// Starting kernel validation
 	fn on_validation_kernels(&self, kernels: u64, kernel_total: u64);
 	/// Starting rproof validation
@@ -386,6 +753,9 @@ pub struct NoStatus;
 
 impl TxHashsetWriteStatus for NoStatus {
 	fn on_setup(&self) {}
+	fn on_unzip(&self, _us: u64, _ts: u64) {}
+	fn on_validation_kernel_history(&self, _hs: u64, _ht: u64) {}
+	fn on_validation_mmrs(&self, _ms: u64, _mt: u64, _ns: u64, _nt: u64) {}
 	fn on_validation_kernels(&self, _ks: u64, _kts: u64) {}
 	fn on_validation_rproofs(&self, _rs: u64, _rt: u64) {}
 	fn on_save(&self) {}
@@ -399,6 +769,23 @@ impl ChainAdapter for NoopAdapter {
 	fn block_accepted(&self, _b: &Block, _status: BlockStatus, _opts: Options) {}
 }
 
+/// Policy used to resolve a race between a new block and the current chain
+/// head when both have the same total difficulty.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum HeadRacePolicy {
+	/// Keep whichever block we saw first (the current head).
+	FirstSeen,
+	/// Prefer the block with the lowest hash, so all nodes following this
+	/// policy converge on the same head regardless of arrival order.
+	LowestHash,
+}
+
+impl Default for HeadRacePolicy {
+	fn default() -> HeadRacePolicy {
+		HeadRacePolicy::FirstSeen
+	}
+}
+
 /// Status of an accepted block.
 #[derive(Debug, Clone, PartialEq)]
 pub enum BlockStatus {
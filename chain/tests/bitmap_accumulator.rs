@@ -14,6 +14,7 @@
 
 use self::chain::txhashset::BitmapAccumulator;
 use self::core::core::hash::Hash;
+use self::core::core::pmmr;
 use self::core::ser::PMMRIndexHashable;
 use bit_vec::BitVec;
 use kepler_chain as chain;
@@ -186,3 +187,24 @@ fn test_bitmap_accumulator() {
 	};
 	assert_eq!(accumulator.root(), expected_hash);
 }
+
+#[test]
+fn test_bitmap_accumulator_size_for_leaves() {
+	assert_eq!(BitmapAccumulator::size_for_leaves(0), 0);
+	for (n_leaves, chunks) in vec![
+		(1, 1),
+		(1024, 1),
+		(1025, 2),
+		(2048, 2),
+		(3000, 3),
+		(5000, 5),
+	] {
+		let mut accumulator = BitmapAccumulator::new();
+		accumulator.init(vec![n_leaves - 1], n_leaves).unwrap();
+		assert_eq!(
+			BitmapAccumulator::size_for_leaves(n_leaves),
+			accumulator.size()
+		);
+		assert_eq!(pmmr::n_leaves(accumulator.size()), chunks);
+	}
+}
@@ -0,0 +1,119 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use self::chain::block_files::{self, BlockFileReader, BlockFileWriter};
+use self::chain::types::Options;
+use self::core::core::hash::Hashed;
+use self::core::global::{self, ChainTypes};
+use kepler_chain as chain;
+use kepler_core as core;
+use kepler_util as util;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+mod chain_test_helper;
+
+use self::chain_test_helper::{clean_output_dir, init_chain, mine_chain};
+
+#[test]
+fn test_block_files_replay() {
+	util::init_test_logger();
+
+	let chain_dir = ".kepler_block_files_chain";
+	let files_dir = ".kepler_block_files";
+	let replay_dir = ".kepler_block_files_replay";
+	clean_output_dir(chain_dir);
+	clean_output_dir(files_dir);
+	clean_output_dir(replay_dir);
+
+	let chain = mine_chain(chain_dir, 6);
+	let head = chain.head().unwrap();
+	let blocks: Vec<_> = chain
+		.blocks_by_height(1, head.height)
+		.unwrap()
+		.map(|b| b.unwrap())
+		.collect();
+
+	{
+		let mut writer = BlockFileWriter::open(files_dir).unwrap();
+		for b in &blocks[..3] {
+			writer.append(b).unwrap();
+		}
+		writer.sync().unwrap();
+	}
+
+	// A record cut short by a crash is dropped when appending again.
+	let files = block_files::block_files(Path::new(files_dir)).unwrap();
+	assert_eq!(files.len(), 1);
+	{
+		let mut file = OpenOptions::new().append(true).open(&files[0]).unwrap();
+		file.write_all(b"KBLK\x00\x00").unwrap();
+	}
+	{
+		let mut writer = BlockFileWriter::open(files_dir).unwrap();
+		for b in &blocks[3..] {
+			writer.append(b).unwrap();
+		}
+	}
+
+	let read: Vec<_> = BlockFileReader::open(files_dir)
+		.unwrap()
+		.map(|b| b.unwrap().hash())
+		.collect();
+	let expected: Vec<_> = blocks.iter().map(|b| b.hash()).collect();
+	assert_eq!(read, expected);
+
+	// Replaying the block files rebuilds the same chain.
+	let genesis = chain
+		.get_block(&chain.get_header_by_height(0).unwrap().hash())
+		.unwrap();
+	let replay = init_chain(replay_dir, genesis);
+	for b in BlockFileReader::open(files_dir).unwrap() {
+		replay
+			.process_block(b.unwrap(), Options::FROM_LOCAL)
+			.unwrap();
+	}
+	assert_eq!(replay.head().unwrap(), head);
+
+	clean_output_dir(chain_dir);
+	clean_output_dir(files_dir);
+	clean_output_dir(replay_dir);
+}
+
+#[test]
+fn test_block_files_oversized_record() {
+	util::init_test_logger();
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+
+	let files_dir = ".kepler_block_files_oversized";
+	clean_output_dir(files_dir);
+	fs::create_dir_all(files_dir).unwrap();
+
+	// A record claiming a size no valid block can have is rejected before
+	// anything gets allocated for it.
+	{
+		let mut file = File::create(Path::new(files_dir).join("blk00000.dat")).unwrap();
+		file.write_all(b"KBLK").unwrap();
+		file.write_all(&1u32.to_be_bytes()).unwrap();
+		file.write_all(&u32::MAX.to_be_bytes()).unwrap();
+		file.write_all(&[0u8; 64]).unwrap();
+	}
+	let mut reader = BlockFileReader::open(files_dir).unwrap();
+	let err = reader.next().unwrap().unwrap_err();
+	assert!(err.to_string().contains("too large"));
+	assert!(reader.next().is_none());
+
+	clean_output_dir(files_dir);
+}
@@ -15,9 +15,10 @@
 use self::chain::types::NoopAdapter;
 use self::chain::types::Options;
 use self::chain::Chain;
+use self::chain::HeadRacePolicy;
 use self::core::core::hash::Hashed;
 use self::core::core::verifier_cache::LruVerifierCache;
-use self::core::core::Block;
+use self::core::core::{Block, BlockBuilder};
 use self::core::genesis;
 use self::core::global::ChainTypes;
 use self::core::libtx::{self, reward};
@@ -45,6 +46,7 @@ pub fn init_chain(dir_name: &str, genesis: Block) -> Chain {
 		pow::verify_size,
 		verifier_cache,
 		false,
+		HeadRacePolicy::default(),
 	)
 	.unwrap()
 }
@@ -87,20 +89,14 @@ where
 		let prev = chain.head_header().unwrap();
 		let next_header_info = consensus::next_difficulty(1, chain.difficulty_iter().unwrap());
 		let pk = ExtKeychainPath::new(1, n as u32, 0, 0, 0).to_identifier();
-		let reward = libtx::reward::output(
-			keychain,
-			&libtx::ProofBuilder::new(keychain),
-			&pk,
-			0,
-			prev.height + 1,
-			false,
-		)
-		.unwrap();
-		let mut b =
-			core::core::Block::new(&prev, vec![], next_header_info.clone().difficulty, reward)
-				.unwrap();
-		b.header.timestamp = prev.timestamp + Duration::seconds(60);
-		b.header.pow.secondary_scaling = next_header_info.secondary_scaling;
+		let mut b = BlockBuilder::new(&prev)
+			.difficulty(next_header_info.difficulty)
+			.secondary_scaling(next_header_info.secondary_scaling)
+			.timestamp(prev.timestamp + Duration::seconds(60))
+			.coinbase(keychain, &libtx::ProofBuilder::new(keychain), &pk)
+			.unwrap()
+			.build()
+			.unwrap();
 
 		chain.set_txhashset_roots(&mut b).unwrap();
 
@@ -0,0 +1,109 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use self::chain::types::{NoopAdapter, Options};
+use self::chain::{Chain, ChainStore, DataVersion, ErrorKind, HeadRacePolicy};
+use self::chain::{DB_SCHEMA_VERSION, TXHASHSET_FORMAT_VERSION};
+use self::core::core::hash::Hashed;
+use self::core::core::verifier_cache::LruVerifierCache;
+use self::core::pow;
+use self::util::RwLock;
+use kepler_chain as chain;
+use kepler_core as core;
+use kepler_util as util;
+use std::sync::Arc;
+
+mod chain_test_helper;
+
+use self::chain_test_helper::{clean_output_dir, mine_chain};
+
+#[test]
+fn test_newer_data_version() {
+	util::init_test_logger();
+
+	let chain_dir = ".kepler_data_version";
+	clean_output_dir(chain_dir);
+
+	let (genesis, head, block) = {
+		let chain = mine_chain(chain_dir, 4);
+		assert_eq!(
+			chain.store().data_version().unwrap(),
+			Some(DataVersion::current())
+		);
+		let genesis = chain
+			.get_block(&chain.get_header_by_height(0).unwrap().hash())
+			.unwrap();
+		let head = chain.head().unwrap();
+		let block = chain.get_block(&head.last_block_h).unwrap();
+		(genesis, head, block)
+	};
+
+	// Pretend a newer binary migrated the db.
+	{
+		let store = ChainStore::new(chain_dir).unwrap();
+		let batch = store.batch().unwrap();
+		batch
+			.save_data_version(&DataVersion {
+				db_schema: DB_SCHEMA_VERSION + 1,
+				txhashset_format: TXHASHSET_FORMAT_VERSION,
+			})
+			.unwrap();
+		batch.commit().unwrap();
+	}
+
+	let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+	let res = Chain::init(
+		chain_dir.to_string(),
+		Arc::new(NoopAdapter {}),
+		genesis.clone(),
+		pow::verify_size,
+		verifier_cache.clone(),
+		false,
+		HeadRacePolicy::default(),
+	);
+	match res {
+		Err(e) => assert_eq!(
+			e.kind(),
+			ErrorKind::DataVersionTooNew(DB_SCHEMA_VERSION + 1, TXHASHSET_FORMAT_VERSION)
+		),
+		Ok(_) => panic!("opened a data dir written by a newer version"),
+	}
+
+	// Still queryable read-only, but nothing gets written.
+	let chain = Chain::init_read_only(
+		chain_dir.to_string(),
+		Arc::new(NoopAdapter {}),
+		genesis,
+		pow::verify_size,
+		verifier_cache,
+		false,
+		HeadRacePolicy::default(),
+	)
+	.unwrap();
+	assert!(chain.is_read_only());
+	assert_eq!(chain.head().unwrap(), head);
+	assert_eq!(
+		chain.get_header_by_height(head.height).unwrap().hash(),
+		head.last_block_h
+	);
+	let res = chain.process_block(block, Options::NONE);
+	assert_eq!(res.unwrap_err().kind(), ErrorKind::ReadOnly);
+	assert_eq!(chain.compact().unwrap_err().kind(), ErrorKind::ReadOnly);
+	assert_eq!(
+		chain.store().data_version().unwrap().unwrap().db_schema,
+		DB_SCHEMA_VERSION + 1
+	);
+
+	clean_output_dir(chain_dir);
+}
@@ -14,18 +14,26 @@
 
 use self::chain::types::{NoopAdapter, Tip};
 use self::chain::Chain;
-use self::core::core::hash::Hashed;
-use self::core::core::verifier_cache::LruVerifierCache;
-use self::core::core::{Block, BlockHeader, KernelFeatures, OutputIdentifier, Transaction};
+use self::core::clock::{self, Clock, ManualClock};
+use self::core::core::hash::{Hashed, ZERO_HASH};
+use self::core::core::verifier_cache::{LruVerifierCache, VerifierCache};
+use self::core::core::{
+	Block, BlockBuilder, BlockHeader, KernelFeatures, OutputIdentifier, Transaction,
+};
 use self::core::global::ChainTypes;
 use self::core::libtx::{self, build, ProofBuilder};
 use self::core::pow::Difficulty;
 use self::core::{consensus, global, pow};
 use self::keychain::{ExtKeychain, ExtKeychainPath, Keychain};
+use self::util::secp::key::SecretKey;
+use self::util::secp::pedersen::Commitment;
 use self::util::RwLock;
 use chrono::Duration;
 use kepler_chain as chain;
-use kepler_chain::{BlockStatus, ChainAdapter, Options};
+use kepler_chain::{
+	BlockStatus, ChainAdapter, Checkpoint, ErrorKind, HeadRacePolicy, Options, OrphanPoolLimits,
+	SignedCheckpoint, StatsSnapshot,
+};
 use kepler_core as core;
 use kepler_keychain as keychain;
 use kepler_util as util;
@@ -64,6 +72,7 @@ fn setup_with_status_adapter(dir_name: &str, genesis: Block, adapter: Arc<Status
 		pow::verify_size,
 		verifier_cache,
 		false,
+		HeadRacePolicy::default(),
 	)
 	.unwrap();
 
@@ -88,6 +97,475 @@ fn mine_short_chain() {
 	clean_output_dir(chain_dir);
 }
 
+#[test]
+fn block_arrival_recorded() {
+	let chain_dir = ".kepler.arrival";
+	clean_output_dir(chain_dir);
+	let chain = mine_chain(chain_dir, 4);
+
+	// Genesis is not "received" so has no arrival time.
+	let genesis = chain.get_header_by_height(0).unwrap();
+	assert!(chain.get_block_arrival(&genesis.hash()).is_err());
+
+	for height in 1..4 {
+		let header = chain.get_header_by_height(height).unwrap();
+		assert!(chain.get_block_arrival(&header.hash()).is_ok());
+		assert!(chain.get_block_latency(&header).is_ok());
+	}
+
+	assert!(chain.median_block_latency(3, 3).unwrap().is_some());
+	assert_eq!(chain.median_block_latency(0, 3).unwrap(), None);
+	clean_output_dir(chain_dir);
+}
+
+#[test]
+fn kernel_first_seen_recorded() {
+	let chain_dir = ".kepler.kernel_first_seen";
+	clean_output_dir(chain_dir);
+	let chain = mine_chain(chain_dir, 3);
+
+	let block = chain
+		.get_block(&chain.get_header_by_height(2).unwrap().hash())
+		.unwrap();
+	let kernel = block.kernels()[0].clone();
+	let seen = chain.get_kernel_first_seen(&kernel.excess).unwrap();
+
+	// Seeing the kernel again keeps the first time.
+	chain
+		.record_kernels_seen(&[(kernel.excess, seen + Duration::seconds(60))])
+		.unwrap();
+	assert_eq!(chain.get_kernel_first_seen(&kernel.excess).unwrap(), seen);
+
+	// Unknown kernels have no first seen time.
+	let genesis = chain
+		.get_block(&chain.get_header_by_height(0).unwrap().hash())
+		.unwrap();
+	assert!(chain
+		.get_kernel_first_seen(&genesis.kernels()[0].excess)
+		.is_err());
+
+	// Only the kernels seen before the time are pruned.
+	let earlier = chain
+		.get_block(&chain.get_header_by_height(1).unwrap().hash())
+		.unwrap()
+		.kernels()[0]
+		.excess;
+	let earlier_seen = chain.get_kernel_first_seen(&earlier).unwrap();
+	assert!(earlier_seen <= seen);
+	{
+		let store = chain.store();
+		let batch = store.batch().unwrap();
+		let pruned = batch
+			.delete_kernels_first_seen_before(seen.timestamp_millis())
+			.unwrap();
+		batch.commit().unwrap();
+		assert_eq!(pruned, if earlier_seen < seen { 1 } else { 0 });
+	}
+	assert!(chain.get_kernel_first_seen(&kernel.excess).is_ok());
+	clean_output_dir(chain_dir);
+}
+
+#[test]
+fn latest_block_fees_count() {
+	let chain_dir = ".kepler.latest_block_fees";
+	clean_output_dir(chain_dir);
+	let chain = mine_chain(chain_dir, 6);
+	assert_eq!(chain.head().unwrap().height, 5);
+
+	let heights = |n| {
+		chain
+			.latest_block_fees(n)
+			.unwrap()
+			.iter()
+			.map(|b| b.height)
+			.collect::<Vec<_>>()
+	};
+	assert_eq!(heights(0), Vec::<u64>::new());
+	assert_eq!(heights(1), vec![5]);
+	assert_eq!(heights(3), vec![5, 4, 3]);
+	// Stops short of the genesis.
+	assert_eq!(heights(5), vec![5, 4, 3, 2, 1]);
+	assert_eq!(heights(10), vec![5, 4, 3, 2, 1]);
+	clean_output_dir(chain_dir);
+}
+
+#[test]
+fn kernels_by_block_height() {
+	let chain_dir = ".kepler.kernels_by_height";
+	clean_output_dir(chain_dir);
+	let chain = mine_chain(chain_dir, 5);
+
+	// Each block has a single coinbase kernel.
+	let (highest_index, kernels) = chain.kernels_by_block_height(1, Some(4), None, 10).unwrap();
+	assert_eq!(kernels.len(), 4);
+	assert_eq!(
+		highest_index,
+		chain.get_header_by_height(4).unwrap().kernel_mmr_size
+	);
+	for (i, (kernel, height, _)) in kernels.iter().enumerate() {
+		assert!(kernel.is_coinbase());
+		assert_eq!(*height, i as u64 + 1);
+	}
+
+	// Page through the same range two kernels at a time.
+	let (_, first_page) = chain.kernels_by_block_height(1, Some(4), None, 2).unwrap();
+	let next_index = first_page.last().unwrap().2 + 1;
+	let (_, second_page) = chain
+		.kernels_by_block_height(1, Some(4), Some(next_index), 2)
+		.unwrap();
+	assert_eq!(first_page.len(), 2);
+	assert_eq!(second_page.len(), 2);
+	assert_eq!(second_page[0].1, 3);
+	assert_eq!(second_page[1].2, highest_index);
+
+	clean_output_dir(chain_dir);
+}
+
+#[test]
+fn blocks_by_kernel() {
+	let chain_dir = ".kepler.blocks_by_kernel";
+	clean_output_dir(chain_dir);
+	let chain = mine_chain(chain_dir, 5);
+
+	// Each block has a single coinbase kernel.
+	let (_, kernels) = chain.kernels_by_block_height(1, Some(4), None, 10).unwrap();
+	let unknown = Commitment::from_vec(vec![9; 33]);
+	let excesses = vec![
+		kernels[2].0.excess,
+		unknown,
+		kernels[0].0.excess,
+		kernels[3].0.excess,
+	];
+
+	let found = chain.get_blocks_by_kernel(&excesses, None, None).unwrap();
+	let heights: Vec<_> = found.iter().map(|k| k.as_ref().map(|k| k.1)).collect();
+	assert_eq!(heights, vec![Some(3), None, Some(1), Some(4)]);
+	for (kernel, _, mmr_index) in found.iter().flatten() {
+		let single = chain
+			.get_kernel_height(&kernel.excess, None, None)
+			.unwrap()
+			.unwrap();
+		assert_eq!(single.2, *mmr_index);
+	}
+
+	// Only the kernels of the blocks in the height range are found.
+	let found = chain
+		.get_blocks_by_kernel(&excesses, Some(2), Some(3))
+		.unwrap();
+	let heights: Vec<_> = found.iter().map(|k| k.as_ref().map(|k| k.1)).collect();
+	assert_eq!(heights, vec![Some(3), None, None, None]);
+
+	clean_output_dir(chain_dir);
+}
+
+#[test]
+fn kernel_by_excess() {
+	let chain_dir = ".kepler.kernel_by_excess";
+	clean_output_dir(chain_dir);
+	let chain = mine_chain(chain_dir, 5);
+
+	// Every kernel is in the index, with the block it was included in.
+	let (_, kernels) = chain.kernels_by_block_height(1, None, None, 10).unwrap();
+	assert_eq!(kernels.len(), 4);
+	for (kernel, height, mmr_index) in kernels {
+		let (found, header, pos) = chain.get_kernel_by_excess(&kernel.excess).unwrap().unwrap();
+		assert_eq!(found, kernel);
+		assert_eq!(header.height, height);
+		assert_eq!(
+			header.hash(),
+			chain.get_header_by_height(height).unwrap().hash()
+		);
+		assert_eq!(pos, mmr_index);
+	}
+
+	let unknown = Commitment::from_vec(vec![9; 33]);
+	assert!(chain.get_kernel_by_excess(&unknown).unwrap().is_none());
+
+	clean_output_dir(chain_dir);
+}
+
+#[test]
+fn range_iterators() {
+	let chain_dir = ".kepler.range_iterators";
+	clean_output_dir(chain_dir);
+	let chain = mine_chain(chain_dir, 5);
+
+	// The end height is capped at the chain head.
+	let headers = chain
+		.headers_by_height(2, 10)
+		.unwrap()
+		.collect::<Result<Vec<_>, _>>()
+		.unwrap();
+	let heights: Vec<u64> = headers.iter().map(|h| h.height).collect();
+	assert_eq!(heights, vec![2, 3, 4]);
+
+	// And can be walked from the latest height down.
+	let heights: Vec<u64> = chain
+		.headers_by_height(1, 4)
+		.unwrap()
+		.rev()
+		.map(|h| h.unwrap().height)
+		.collect();
+	assert_eq!(heights, vec![4, 3, 2, 1]);
+
+	let blocks = chain
+		.blocks_by_height(0, 4)
+		.unwrap()
+		.collect::<Result<Vec<_>, _>>()
+		.unwrap();
+	assert_eq!(blocks.len(), 5);
+	assert_eq!(blocks[3].hash(), headers[1].hash());
+
+	// Scanning the whole output_pos index or from a given commitment.
+	let store = chain.store();
+	let all: Vec<_> = store.output_pos_iter_from(None).unwrap().collect();
+	assert!(all.len() >= 4);
+	for (commit, pos) in &all {
+		let (expected_pos, expected_height) = store.get_output_pos_height(commit).unwrap();
+		assert_eq!((pos.pos, pos.height), (expected_pos, expected_height));
+	}
+	let from: Vec<_> = store
+		.output_pos_iter_from(Some(&all[2].0))
+		.unwrap()
+		.map(|(commit, _)| commit)
+		.collect();
+	let expected: Vec<_> = all[2..].iter().map(|(commit, _)| *commit).collect();
+	assert_eq!(from, expected);
+
+	clean_output_dir(chain_dir);
+}
+
+#[test]
+fn stats_history() {
+	let chain_dir = ".kepler.stats_history";
+	clean_output_dir(chain_dir);
+	let chain = mine_chain(chain_dir, 1);
+
+	let snapshot = |timestamp| StatsSnapshot {
+		timestamp,
+		peer_count: 8,
+		mempool_size: 2,
+		sync_lag: 0,
+		sent_bytes: 1000,
+		received_bytes: 2000,
+		height: 0,
+		blocks: 0,
+		block_interval: 0,
+	};
+	for t in &[7200, 3600, 10800] {
+		chain.save_stats_snapshot(&snapshot(*t), 0).unwrap();
+	}
+
+	// Snapshots are returned in time order.
+	let history = chain.stats_history(0).unwrap();
+	let timestamps: Vec<i64> = history.iter().map(|s| s.timestamp).collect();
+	assert_eq!(timestamps, vec![3600, 7200, 10800]);
+	assert_eq!(history[0], snapshot(3600));
+	assert_eq!(chain.stats_history(7000).unwrap().len(), 2);
+
+	// Older snapshots are pruned when saving a new one.
+	chain.save_stats_snapshot(&snapshot(14400), 7200).unwrap();
+	let history = chain.stats_history(0).unwrap();
+	let timestamps: Vec<i64> = history.iter().map(|s| s.timestamp).collect();
+	assert_eq!(timestamps, vec![7200, 10800, 14400]);
+
+	clean_output_dir(chain_dir);
+}
+
+#[test]
+fn verify_block_headers_chain() {
+	let chain_dir = ".kepler.verify_headers";
+	clean_output_dir(chain_dir);
+	let chain = mine_chain(chain_dir, 5);
+	let headers = chain
+		.headers_by_height(0, 4)
+		.unwrap()
+		.collect::<Result<Vec<_>, _>>()
+		.unwrap();
+
+	Chain::verify_block_headers_chain(&headers, pow::verify_size).unwrap();
+	// The first header is trusted, wherever the chain starts.
+	Chain::verify_block_headers_chain(&headers[2..], pow::verify_size).unwrap();
+
+	// Timestamps have to increase.
+	let mut bad_time = headers.clone();
+	bad_time[3].timestamp = bad_time[2].timestamp;
+	let err = Chain::verify_block_headers_chain(&bad_time, pow::verify_size).unwrap_err();
+	assert_eq!(
+		err.kind(),
+		ErrorKind::InvalidBlockTime(format!(
+			"{} not after the previous block time {}",
+			bad_time[3].timestamp, bad_time[2].timestamp
+		))
+	);
+
+	// Headers have to build on each other.
+	let mut gap = headers.clone();
+	gap.remove(2);
+	let err = Chain::verify_block_headers_chain(&gap, pow::verify_size).unwrap_err();
+	match err.kind() {
+		ErrorKind::Unfit(_) => {}
+		kind => panic!("unexpected error: {}", kind),
+	}
+	assert!(!err.is_known());
+
+	clean_output_dir(chain_dir);
+}
+
+#[test]
+fn process_block_header_only() {
+	let chain_dir = ".kepler.header_only";
+	clean_output_dir(chain_dir);
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+	let kc = ExtKeychain::from_random_seed(false).unwrap();
+	let genesis = pow::mine_genesis_block().unwrap();
+	let chain = init_chain(chain_dir, genesis);
+
+	let block_a = prepare_block(&kc, &chain.head_header().unwrap(), &chain, 1);
+	chain
+		.process_block(block_a.clone(), Options::SKIP_POW | Options::HEADER_ONLY)
+		.unwrap();
+
+	// Only the header was processed.
+	assert_eq!(chain.head().unwrap().height, 0);
+	assert_eq!(
+		chain.header_head().unwrap(),
+		Tip::from_header(&block_a.header)
+	);
+	assert!(chain.get_block(&block_a.hash()).is_err());
+
+	// The full block can still be processed afterwards, trusting its
+	// rangeproofs this time.
+	chain
+		.process_block(
+			block_a.clone(),
+			Options::SKIP_POW | Options::SKIP_RANGEPROOF | Options::FROM_LOCAL,
+		)
+		.unwrap();
+	assert_eq!(chain.head().unwrap(), Tip::from_header(&block_a.header));
+	assert!(chain.get_block_arrival(&block_a.hash()).is_err());
+
+	// Only then is it known.
+	let err = chain.process_block(block_a, Options::SKIP_POW).unwrap_err();
+	assert!(err.is_known());
+
+	clean_output_dir(chain_dir);
+}
+
+#[test]
+fn process_block_invalid_state() {
+	let chain_dir = ".kepler.invalid_state";
+	clean_output_dir(chain_dir);
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+	let kc = ExtKeychain::from_random_seed(false).unwrap();
+	let genesis = pow::mine_genesis_block().unwrap();
+	let chain = init_chain(chain_dir, genesis);
+
+	// A block with a valid header but not applying to the txhashset.
+	let mut block_a = prepare_block(&kc, &chain.head_header().unwrap(), &chain, 1);
+	block_a.header.output_root = ZERO_HASH;
+	let err = chain.process_block(block_a, Options::SKIP_POW).unwrap_err();
+	assert!(err.is_invalid_block_state());
+	assert!(err.is_bad_data());
+	assert!(err.kind().to_string().contains("output MMR root"));
+	assert_eq!(chain.head().unwrap().height, 0);
+
+	clean_output_dir(chain_dir);
+}
+
+#[test]
+fn process_block_bad_body() {
+	let chain_dir = ".kepler.bad_body";
+	clean_output_dir(chain_dir);
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+	let kc = ExtKeychain::from_random_seed(false).unwrap();
+	let genesis = pow::mine_genesis_block().unwrap();
+	let chain = init_chain(chain_dir, genesis);
+
+	// A valid header along with a body that doesn't match it.
+	let block_a = prepare_block(&kc, &chain.head_header().unwrap(), &chain, 1);
+	let mut bad_block = block_a.clone();
+	bad_block.outputs_mut().clear();
+	assert_eq!(bad_block.hash(), block_a.hash());
+	let err = chain
+		.process_block(bad_block, Options::SKIP_POW)
+		.unwrap_err();
+	assert!(err.is_bad_data());
+	assert!(!err.is_known());
+	assert_eq!(chain.head().unwrap().height, 0);
+
+	// The valid block under the same header is still accepted.
+	chain
+		.process_block(block_a.clone(), Options::SKIP_POW)
+		.unwrap();
+	assert_eq!(chain.head().unwrap(), Tip::from_header(&block_a.header));
+
+	clean_output_dir(chain_dir);
+}
+
+#[test]
+fn process_block_future_time() {
+	let chain_dir = ".kepler.future_time";
+	clean_output_dir(chain_dir);
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+	let kc = ExtKeychain::from_random_seed(false).unwrap();
+	let genesis = pow::mine_genesis_block().unwrap();
+	let clock = Arc::new(ManualClock::new(genesis.header.timestamp));
+	let chain = init_chain(chain_dir, genesis).with_clock(clock.clone());
+
+	// Up to 12 block intervals ahead of our clock is fine.
+	let mut block_a = prepare_block(&kc, &chain.head_header().unwrap(), &chain, 1);
+	block_a.header.timestamp = clock.now() + Duration::minutes(12);
+	process_block(&chain, &block_a);
+
+	// Any further ahead is refused, until our clock catches up.
+	let mut block_b = prepare_block(&kc, &block_a.header, &chain, 2);
+	block_b.header.timestamp = clock.now() + Duration::minutes(13);
+	let err = chain
+		.process_block(block_b.clone(), Options::SKIP_POW)
+		.unwrap_err();
+	assert_eq!(
+		err.kind(),
+		ErrorKind::InvalidBlockTime(format!(
+			"{} later than the maximum {}",
+			block_b.header.timestamp,
+			clock::max_header_time(clock.as_ref())
+		))
+	);
+	clock.advance(Duration::minutes(1));
+	process_block(&chain, &block_b);
+
+	// Timestamps have to strictly increase regardless of the clock.
+	let mut block_c = prepare_block(&kc, &block_b.header, &chain, 3);
+	block_c.header.timestamp = block_b.header.timestamp;
+	let err = chain
+		.process_block(block_c.clone(), Options::SKIP_POW)
+		.unwrap_err();
+	assert_eq!(
+		err.kind(),
+		ErrorKind::InvalidBlockTime(format!(
+			"{} not after the previous block time {}",
+			block_c.header.timestamp, block_b.header.timestamp
+		))
+	);
+	assert_eq!(chain.head().unwrap(), Tip::from_header(&block_b.header));
+
+	// Orphans too far ahead are refused as well, not kept around.
+	let block_d = prepare_block(&kc, &block_b.header, &chain, 4);
+	let mut orphan = prepare_block_nosum(&kc, &block_d.header, 5, 5, vec![]);
+	orphan.header.timestamp = clock.now() + Duration::minutes(20);
+	let err = chain
+		.process_block(orphan.clone(), Options::SKIP_POW)
+		.unwrap_err();
+	match err.kind() {
+		ErrorKind::InvalidBlockTime(_) => {}
+		kind => panic!("unexpected error {:?}", kind),
+	}
+	assert!(!chain.is_orphan(&orphan.hash()));
+
+	clean_output_dir(chain_dir);
+}
+
 // Convenience wrapper for processing a full block on the test chain.
 fn process_header(chain: &Chain, header: &BlockHeader) {
 	chain
@@ -154,6 +632,329 @@ fn test_block_a_block_b_block_b_fork_header_c_fork_block_c() {
 	clean_output_dir(chain_dir);
 }
 
+// Blocks received before their parent wait in the orphan pool, from which
+// they can be listed and dropped.
+#[test]
+fn orphan_pool_admin() {
+	let chain_dir = ".kepler.orphan_pool_admin";
+	let other_dir = ".kepler.orphan_pool_admin_other";
+	clean_output_dir(chain_dir);
+	clean_output_dir(other_dir);
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+	let kc = ExtKeychain::from_random_seed(false).unwrap();
+	let genesis = pow::mine_genesis_block().unwrap();
+	let chain = init_chain(chain_dir, genesis.clone());
+
+	let block_a = prepare_block(&kc, &chain.head_header().unwrap(), &chain, 1);
+	process_block(&chain, &block_a);
+	let block_b = prepare_block(&kc, &block_a.header, &chain, 2);
+	process_block(&chain, &block_b);
+	let block_c = prepare_block(&kc, &block_b.header, &chain, 3);
+
+	// Without block a, blocks b and c are orphans on the other chain.
+	let other = init_chain(other_dir, genesis);
+	for b in &[&block_c, &block_b] {
+		let res = other.process_block((*b).clone(), chain::Options::SKIP_POW);
+		assert_eq!(res.map_err(|e| e.kind()), Err(ErrorKind::Orphan));
+	}
+	let orphans = other.orphans();
+	assert_eq!(
+		orphans.iter().map(|o| o.header.hash()).collect::<Vec<_>>(),
+		vec![block_b.hash(), block_c.hash()]
+	);
+	assert_eq!(orphans[0].outputs, 1);
+	assert_eq!(orphans[0].kernels, 1);
+
+	assert!(other.remove_orphan(&block_b.hash()));
+	assert!(!other.remove_orphan(&block_b.hash()));
+	assert!(!other.is_orphan(&block_b.hash()));
+	assert_eq!(other.orphans_len(), 1);
+
+	assert_eq!(other.clear_orphans(), 1);
+	assert_eq!(other.orphans_len(), 0);
+
+	// Cleared orphans are processed again once received again.
+	process_block(&other, &block_a);
+	process_block(&other, &block_b);
+	process_block(&other, &block_c);
+	assert_eq!(other.head().unwrap(), Tip::from_header(&block_c.header));
+
+	clean_output_dir(chain_dir);
+	clean_output_dir(other_dir);
+}
+
+// The orphan pool evicts the blocks too far ahead of the head, then the
+// highest ones once full, and records the peer each orphan came from.
+#[test]
+fn orphan_pool_limits() {
+	let chain_dir = ".kepler.orphan_pool_limits";
+	let other_dir = ".kepler.orphan_pool_limits_other";
+	clean_output_dir(chain_dir);
+	clean_output_dir(other_dir);
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+	let kc = ExtKeychain::from_random_seed(false).unwrap();
+	let genesis = pow::mine_genesis_block().unwrap();
+	let chain = init_chain(chain_dir, genesis.clone());
+
+	let mut blocks = vec![];
+	let mut prev = chain.head_header().unwrap();
+	for n in 1..5 {
+		let b = prepare_block(&kc, &prev, &chain, n);
+		process_block(&chain, &b);
+		prev = b.header.clone();
+		blocks.push(b);
+	}
+
+	let other = init_chain(other_dir, genesis).with_orphan_limits(OrphanPoolLimits {
+		max_orphans: 1,
+		max_age_secs: 300,
+		max_height_ahead: 3,
+	});
+	let peer = Some("10.0.0.2:7414".to_string());
+	for b in &blocks[1..] {
+		let res = other.process_block_from(b.clone(), Options::SKIP_POW, peer.clone());
+		assert_eq!(res.map_err(|e| e.kind()), Err(ErrorKind::Orphan));
+	}
+	let stats = other.orphan_stats();
+	assert_eq!(stats.len, 1);
+	assert_eq!(stats.max_orphans, 1);
+	assert_eq!(stats.evicted_too_far, 1);
+	assert_eq!(stats.evicted_full, 1);
+	assert_eq!(stats.evicted_expired, 0);
+	assert_eq!(other.orphans_evicted_len(), 2);
+
+	let orphans = other.orphans();
+	assert_eq!(orphans[0].header.hash(), blocks[1].hash());
+	assert_eq!(orphans[0].peer, peer);
+
+	// The orphan left is processed once its parent arrives.
+	process_block(&other, &blocks[0]);
+	assert_eq!(other.head().unwrap(), Tip::from_header(&blocks[1].header));
+	assert_eq!(other.orphans_len(), 0);
+
+	clean_output_dir(chain_dir);
+	clean_output_dir(other_dir);
+}
+
+#[test]
+fn signed_checkpoints() {
+	let chain_dir = ".kepler.signed_checkpoints";
+	let other_dir = ".kepler.signed_checkpoints_other";
+	clean_output_dir(chain_dir);
+	clean_output_dir(other_dir);
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+	let kc = ExtKeychain::from_random_seed(false).unwrap();
+	let genesis = pow::mine_genesis_block().unwrap();
+	let chain = init_chain(chain_dir, genesis.clone());
+
+	let block_a = prepare_block(&kc, &chain.head_header().unwrap(), &chain, 1);
+	process_block(&chain, &block_a);
+	let block_b = prepare_block(&kc, &block_a.header, &chain, 2);
+	process_block(&chain, &block_b);
+	let block_a_fork = prepare_block(&kc, &genesis.header, &chain, 3);
+
+	let cp = chain.checkpoint(Some(1)).unwrap();
+	assert_eq!(cp, Checkpoint::from_header(&block_a.header));
+	assert_eq!(chain.checkpoint(None).unwrap().hash, block_b.hash());
+	assert!(chain.checkpoint(Some(3)).is_err());
+
+	let secp = util::static_secp_instance();
+	let key = SecretKey::from_slice(&secp.lock(), &[1; 32]).unwrap();
+	let other_key = SecretKey::from_slice(&secp.lock(), &[2; 32]).unwrap();
+	let signed = SignedCheckpoint::sign(cp, &genesis.hash(), &key).unwrap();
+	signed.verify(&genesis.hash()).unwrap();
+	let mut tampered = signed.clone();
+	tampered.checkpoint.height = 2;
+	assert!(tampered.verify(&genesis.hash()).is_err());
+
+	// A checkpoint signed for another chain doesn't verify on ours.
+	let mut other_genesis = genesis.clone();
+	other_genesis.header.timestamp = genesis.header.timestamp + Duration::seconds(1);
+	assert!(signed.verify(&other_genesis.hash()).is_err());
+	let foreign =
+		SignedCheckpoint::sign(signed.checkpoint.clone(), &other_genesis.hash(), &key).unwrap();
+	assert_eq!(
+		chain
+			.add_checkpoint(foreign, &[signed.operator])
+			.map_err(|e| e.kind()),
+		Err(ErrorKind::InvalidCheckpoint("bad signature".to_owned()))
+	);
+
+	// Only checkpoints of trusted operators are imported.
+	let untrusted =
+		SignedCheckpoint::sign(signed.checkpoint.clone(), &genesis.hash(), &other_key).unwrap();
+	let other = init_chain(other_dir, genesis);
+	assert_eq!(
+		other
+			.add_checkpoint(untrusted, &[signed.operator])
+			.map_err(|e| e.kind()),
+		Err(ErrorKind::InvalidCheckpoint(
+			"operator not trusted".to_owned()
+		))
+	);
+	other
+		.add_checkpoint(signed.clone(), &[signed.operator])
+		.unwrap();
+	assert_eq!(other.checkpoints(), vec![signed.clone()]);
+
+	// Blocks conflicting with the checkpoint are rejected.
+	let res = other.process_block(block_a_fork.clone(), chain::Options::SKIP_POW);
+	assert_eq!(
+		res.map_err(|e| e.kind()),
+		Err(ErrorKind::CheckpointMismatch(1))
+	);
+	process_block(&other, &block_a);
+	process_block(&other, &block_b);
+	assert_eq!(other.head().unwrap(), Tip::from_header(&block_b.header));
+
+	// A checkpoint conflicting with our own header chain is refused.
+	let fork_cp = Checkpoint::from_header(&block_a_fork.header);
+	let fork_signed = SignedCheckpoint::sign(fork_cp, &genesis.hash(), &key).unwrap();
+	assert_eq!(
+		chain
+			.add_checkpoint(fork_signed, &[signed.operator])
+			.map_err(|e| e.kind()),
+		Err(ErrorKind::CheckpointMismatch(1))
+	);
+
+	clean_output_dir(chain_dir);
+	clean_output_dir(other_dir);
+}
+
+#[test]
+fn hard_checkpoints_and_assume_valid() {
+	let chain_dir = ".kepler.hard_checkpoints";
+	clean_output_dir(chain_dir);
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+	let kc = ExtKeychain::from_random_seed(false).unwrap();
+	let genesis = pow::mine_genesis_block().unwrap();
+	let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+	let chain = chain::Chain::init(
+		chain_dir.to_string(),
+		Arc::new(NoopAdapter {}),
+		genesis.clone(),
+		pow::verify_size,
+		verifier_cache.clone(),
+		false,
+		HeadRacePolicy::default(),
+	)
+	.unwrap();
+
+	let block_a = prepare_block(&kc, &chain.head_header().unwrap(), &chain, 1);
+	let block_a_fork = prepare_block(&kc, &genesis.header, &chain, 3);
+	let checkpoints = vec![(1, block_a.hash())].into_iter().collect();
+	let chain = chain.with_checkpoints(checkpoints, Some(1));
+
+	// Headers conflicting with a hard checkpoint are rejected.
+	let res = chain.process_block_header(&block_a_fork.header, Options::SKIP_POW);
+	assert_eq!(
+		res.map_err(|e| e.kind()),
+		Err(ErrorKind::CheckpointMismatch(1))
+	);
+
+	// The rangeproofs up to the assume-valid height aren't verified, the
+	// ones above are.
+	process_block(&chain, &block_a);
+	let block_b = prepare_block(&kc, &block_a.header, &chain, 2);
+	process_block(&chain, &block_b);
+	assert_eq!(chain.head().unwrap(), Tip::from_header(&block_b.header));
+	let mut cache = verifier_cache.write();
+	assert_eq!(
+		cache.filter_rangeproof_unverified(block_a.outputs()).len(),
+		1
+	);
+	assert!(cache
+		.filter_rangeproof_unverified(block_b.outputs())
+		.is_empty());
+
+	clean_output_dir(chain_dir);
+}
+
+#[test]
+fn assume_valid_needs_hard_checkpoint() {
+	let chain_dir = ".kepler.assume_valid_no_checkpoint";
+	clean_output_dir(chain_dir);
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+	let kc = ExtKeychain::from_random_seed(false).unwrap();
+	let genesis = pow::mine_genesis_block().unwrap();
+	let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+	let chain = chain::Chain::init(
+		chain_dir.to_string(),
+		Arc::new(NoopAdapter {}),
+		genesis.clone(),
+		pow::verify_size,
+		verifier_cache.clone(),
+		false,
+		HeadRacePolicy::default(),
+	)
+	.unwrap();
+
+	// Without a hard checkpoint at the assume-valid height, every
+	// rangeproof is verified.
+	let block_a = prepare_block(&kc, &chain.head_header().unwrap(), &chain, 1);
+	let chain = chain.with_checkpoints(vec![].into_iter().collect(), Some(1));
+	process_block(&chain, &block_a);
+	let block_b = prepare_block(&kc, &block_a.header, &chain, 2);
+	process_block(&chain, &block_b);
+	assert_eq!(chain.head().unwrap(), Tip::from_header(&block_b.header));
+	let mut cache = verifier_cache.write();
+	assert!(cache
+		.filter_rangeproof_unverified(block_a.outputs())
+		.is_empty());
+
+	clean_output_dir(chain_dir);
+}
+
+//
+// a - b
+//  \
+//   - b'
+//
+// Blocks b and b' have the same total difficulty, so which one becomes
+// the head depends on the configured head race policy.
+#[test]
+fn head_race_lowest_hash() {
+	let chain_dir = ".kepler.head_race_lowest_hash";
+	clean_output_dir(chain_dir);
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+	let kc = ExtKeychain::from_random_seed(false).unwrap();
+	let genesis = pow::mine_genesis_block().unwrap();
+	let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+	let chain = chain::Chain::init(
+		chain_dir.to_string(),
+		Arc::new(NoopAdapter {}),
+		genesis,
+		pow::verify_size,
+		verifier_cache,
+		false,
+		HeadRacePolicy::LowestHash,
+	)
+	.unwrap();
+
+	let block_a = prepare_block(&kc, &chain.head_header().unwrap(), &chain, 1);
+	process_block(&chain, &block_a);
+
+	let block_b = prepare_block(&kc, &block_a.header, &chain, 2);
+	let block_b_fork = prepare_block(&kc, &block_a.header, &chain, 2);
+	assert_eq!(
+		block_b.header.total_difficulty(),
+		block_b_fork.header.total_difficulty()
+	);
+
+	process_block(&chain, &block_b);
+	process_block(&chain, &block_b_fork);
+
+	let lowest = if block_b.hash() < block_b_fork.hash() {
+		&block_b
+	} else {
+		&block_b_fork
+	};
+	assert_eq!(chain.head().unwrap(), Tip::from_header(&lowest.header));
+	assert_eq!(chain.tip_races(), 1);
+
+	clean_output_dir(chain_dir);
+}
+
 //
 // a - b
 //  \
@@ -390,6 +1191,16 @@ fn mine_reorg() {
 		let head = chain.head_header().unwrap();
 		assert_eq!(head.height, NUM_BLOCKS_MAIN - REORG_DEPTH + 1);
 		assert_eq!(head.hash(), reorg_head.hash());
+
+		// The reorg is recorded along with the blocks taken off the chain.
+		let reorgs = chain.reorgs(0).unwrap();
+		assert_eq!(reorgs.len(), 1);
+		assert_eq!(reorgs[0].depth, REORG_DEPTH);
+		assert_eq!(reorgs[0].old_tip.last_block_h, prev.hash());
+		assert_eq!(reorgs[0].new_tip.last_block_h, reorg_head.hash());
+		assert_eq!(reorgs[0].fork_point, fork_head.hash());
+		assert_eq!(reorgs[0].disconnected.len() as u64, REORG_DEPTH);
+		assert_eq!(reorgs[0].disconnected[0], prev.hash());
 	}
 
 	// Cleanup chain directory
@@ -610,6 +1421,142 @@ fn spend_rewind_spend() {
 	clean_output_dir(".kepler_spend_rewind_spend");
 }
 
+// Spent outputs can still be looked up by commitment, until a rewind
+// unspends them.
+#[test]
+fn spent_output_lookup() {
+	let chain_dir = ".kepler.spent_output_lookup";
+	clean_output_dir(chain_dir);
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+	let kc = ExtKeychain::from_random_seed(false).unwrap();
+	let pb = ProofBuilder::new(&kc);
+	let chain = init_chain(chain_dir, pow::mine_genesis_block().unwrap());
+
+	let b = prepare_block_key_idx(&kc, &chain.head_header().unwrap(), &chain, 2, 1);
+	let out_id = OutputIdentifier::from_output(&b.outputs()[0]);
+	process_block(&chain, &b);
+	let out_pos = chain.get_output_pos(&out_id.commit).unwrap();
+	assert!(chain.get_spent_output_pos(&out_id.commit).is_err());
+
+	let mut head = b.header.clone();
+	for n in 3..6 {
+		let b = prepare_block(&kc, &head, &chain, n);
+		head = b.header.clone();
+		process_block(&chain, &b);
+	}
+	let fork_point = head.clone();
+
+	let key_id_coinbase = ExtKeychainPath::new(1, 1, 0, 0, 0).to_identifier();
+	let key_id30 = ExtKeychainPath::new(1, 30, 0, 0, 0).to_identifier();
+	let tx = build::transaction(
+		KernelFeatures::Plain { fee: 20000 },
+		vec![
+			build::coinbase_input(consensus::reward(head.height, 0), key_id_coinbase),
+			build::output(consensus::reward(head.height, 0) - 20000, key_id30),
+		],
+		&kc,
+		&pb,
+	)
+	.unwrap();
+	let b = prepare_block_tx(&kc, &head, &chain, 6, vec![&tx]);
+	process_block(&chain, &b);
+
+	assert!(chain.is_unspent(&out_id).is_err());
+	let spent = chain.get_spent_output_pos(&out_id.commit).unwrap();
+	assert_eq!((spent.pos, spent.height), (out_pos, 1));
+
+	// A fork with more work not spending the output unspends it.
+	let mut head = fork_point;
+	for n in 6..8 {
+		let b = prepare_block_key_idx(&kc, &head, &chain, n, n as u32 + 10);
+		head = b.header.clone();
+		process_block(&chain, &b);
+	}
+	assert_eq!(chain.head().unwrap().last_block_h, head.hash());
+	assert_eq!(chain.is_unspent(&out_id).unwrap().pos, out_pos);
+	assert!(chain.get_spent_output_pos(&out_id.commit).is_err());
+
+	clean_output_dir(chain_dir);
+}
+
+// Archive nodes can report the original outputs spent by the inputs of a block.
+#[test]
+fn spent_outputs_archive() {
+	let chain_dir = ".kepler.spent_outputs_archive";
+	clean_output_dir(chain_dir);
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+	let kc = ExtKeychain::from_random_seed(false).unwrap();
+	let pb = ProofBuilder::new(&kc);
+	let genesis = pow::mine_genesis_block().unwrap();
+	let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+	let chain = chain::Chain::init(
+		chain_dir.to_string(),
+		Arc::new(NoopAdapter {}),
+		genesis,
+		pow::verify_size,
+		verifier_cache,
+		true,
+		HeadRacePolicy::default(),
+	)
+	.unwrap();
+
+	let b = prepare_block_key_idx(&kc, &chain.head_header().unwrap(), &chain, 2, 1);
+	let out_id = OutputIdentifier::from_output(&b.outputs()[0]);
+	process_block(&chain, &b);
+	let out_pos = chain.get_output_pos(&out_id.commit).unwrap();
+
+	let mut head = b.header.clone();
+	for n in 3..6 {
+		let b = prepare_block(&kc, &head, &chain, n);
+		head = b.header.clone();
+		process_block(&chain, &b);
+	}
+
+	let key_id_coinbase = ExtKeychainPath::new(1, 1, 0, 0, 0).to_identifier();
+	let key_id30 = ExtKeychainPath::new(1, 30, 0, 0, 0).to_identifier();
+	let tx = build::transaction(
+		KernelFeatures::Plain { fee: 20000 },
+		vec![
+			build::coinbase_input(consensus::reward(head.height, 0), key_id_coinbase),
+			build::output(consensus::reward(head.height, 0) - 20000, key_id30),
+		],
+		&kc,
+		&pb,
+	)
+	.unwrap();
+	let b = prepare_block_tx(&kc, &head, &chain, 6, vec![&tx]);
+	process_block(&chain, &b);
+
+	let spent = chain.get_spent_outputs(&b.hash()).unwrap();
+	assert_eq!(spent.len(), 1);
+	let (spent_id, spent_pos) = &spent[0];
+	assert_eq!(spent_id, &out_id);
+	assert!(spent_id.features.is_coinbase());
+	assert_eq!(spent_pos.pos, out_pos);
+	assert_eq!(spent_pos.height, 1);
+
+	// Nothing gets pruned nor compacted away beyond the horizon.
+	let first = chain.get_header_by_height(1).unwrap();
+	let mut head = b.header.clone();
+	for n in 7..100 {
+		let b = prepare_block(&kc, &head, &chain, n);
+		head = b.header.clone();
+		process_block(&chain, &b);
+	}
+	chain.compact().unwrap();
+	assert_eq!(chain.compaction_status().last_error, None);
+	assert!(chain.get_block(&first.hash()).is_ok());
+	let spent = chain.get_spent_outputs(&b.hash()).unwrap();
+	assert_eq!(spent[0].0, out_id);
+	assert_eq!(spent[0].1.pos, out_pos);
+	assert_eq!(
+		chain.get_spent_output_pos(&out_id.commit).unwrap().pos,
+		out_pos
+	);
+
+	clean_output_dir(chain_dir);
+}
+
 #[test]
 fn spend_in_fork_and_compact() {
 	clean_output_dir(".kepler6");
@@ -746,6 +1693,9 @@ fn spend_in_fork_and_compact() {
 		if let Err(e) = chain.compact() {
 			panic!("Error compacting chain: {:?}", e);
 		}
+		let status = chain.compaction_status();
+		assert!(!status.running);
+		assert_eq!(status.last_error, None);
 		if let Err(e) = chain.validate(false) {
 			panic!("Validation error after compacting chain: {:?}", e);
 		}
@@ -781,11 +1731,13 @@ fn output_header_mappings() {
 			)
 			.unwrap();
 			reward_outputs.push(reward.0.clone());
-			let mut b =
-				core::core::Block::new(&prev, vec![], next_header_info.clone().difficulty, reward)
-					.unwrap();
-			b.header.timestamp = prev.timestamp + Duration::seconds(60);
-			b.header.pow.secondary_scaling = next_header_info.secondary_scaling;
+			let mut b = BlockBuilder::new(&prev)
+				.reward(reward.0, reward.1)
+				.difficulty(next_header_info.difficulty)
+				.secondary_scaling(next_header_info.secondary_scaling)
+				.timestamp(prev.timestamp + Duration::seconds(60))
+				.build()
+				.unwrap();
 
 			chain.set_txhashset_roots(&mut b).unwrap();
 
@@ -891,32 +1843,16 @@ fn prepare_block_nosum<K>(
 where
 	K: Keychain,
 {
-	let proof_size = global::proofsize();
 	let key_id = ExtKeychainPath::new(1, key_idx, 0, 0, 0).to_identifier();
-
-	let fees = txs.iter().map(|tx| tx.fee()).sum();
-	let reward = libtx::reward::output(
-		kc,
-		&libtx::ProofBuilder::new(kc),
-		&key_id,
-		fees,
-		prev.height + 1,
-		false,
-	)
-	.unwrap();
-	let mut b = match core::core::Block::new(
-		prev,
-		txs.into_iter().cloned().collect(),
-		Difficulty::from_num(diff),
-		reward,
-	) {
-		Err(e) => panic!("{:?}", e),
-		Ok(b) => b,
-	};
-	b.header.timestamp = prev.timestamp + Duration::seconds(60);
-	b.header.pow.total_difficulty = prev.total_difficulty() + Difficulty::from_num(diff);
-	b.header.pow.proof = pow::Proof::random(proof_size);
-	b
+	BlockBuilder::new(prev)
+		.txs(txs.into_iter().cloned().collect())
+		.difficulty(Difficulty::from_num(diff))
+		.timestamp(prev.timestamp + Duration::seconds(60))
+		.proof(pow::Proof::random(global::proofsize()))
+		.coinbase(kc, &ProofBuilder::new(kc), &key_id)
+		.unwrap()
+		.build()
+		.unwrap_or_else(|e| panic!("{:?}", e))
 }
 
 #[test]
@@ -932,6 +1868,7 @@ fn actual_diff_iter_output() {
 		pow::verify_size,
 		verifier_cache,
 		false,
+		HeadRacePolicy::default(),
 	)
 	.unwrap();
 	let iter = chain.difficulty_iter().unwrap();
@@ -12,7 +12,10 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use self::chain::store::{DifficultyCache, DifficultyIter};
+use self::core::consensus::DIFFICULTY_ADJUST_WINDOW;
 use self::core::core::hash::Hashed;
+use kepler_chain as chain;
 use kepler_core as core;
 use kepler_util as util;
 
@@ -64,3 +67,34 @@ fn test_store_indices() {
 	// Cleanup chain directory
 	clean_output_dir(chain_dir);
 }
+
+#[test]
+fn test_difficulty_cache() {
+	util::init_test_logger();
+
+	let chain_dir = ".kepler_idx_2";
+	clean_output_dir(chain_dir);
+
+	let chain = mine_chain(chain_dir, 8);
+	let store = chain.store();
+	let mut batch = store.batch().unwrap();
+	let needed = DIFFICULTY_ADJUST_WINDOW as usize + 1;
+
+	// Walk up the chain so each window is extended from the cached parent window
+	// and check it matches the window read directly from the db.
+	let mut cache = DifficultyCache::new();
+	for height in 0..8 {
+		let header = chain.get_header_by_height(height).unwrap();
+		let cached = cache.window(&header, &mut batch).unwrap();
+		let expected: Vec<_> = DifficultyIter::from(header.hash(), store.clone())
+			.take(needed)
+			.collect();
+		assert_eq!(cached, expected);
+
+		// Second lookup is a direct cache hit.
+		assert_eq!(cache.window(&header, &mut batch).unwrap(), expected);
+	}
+
+	// Cleanup chain directory
+	clean_output_dir(chain_dir);
+}
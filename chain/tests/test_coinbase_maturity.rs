@@ -13,7 +13,7 @@
 // limitations under the License.
 
 use self::chain::types::NoopAdapter;
-use self::chain::ErrorKind;
+use self::chain::{ErrorKind, HeadRacePolicy};
 use self::core::core::verifier_cache::LruVerifierCache;
 use self::core::core::KernelFeatures;
 use self::core::global::{self, ChainTypes};
@@ -54,6 +54,7 @@ fn test_coinbase_maturity() {
 			pow::verify_size,
 			verifier_cache,
 			false,
+			HeadRacePolicy::default(),
 		)
 		.unwrap();
 
@@ -23,6 +23,7 @@ use std::sync::Arc;
 
 use crate::chain::store::ChainStore;
 use crate::chain::txhashset;
+use crate::chain::types::NoStatus;
 use crate::core::core::BlockHeader;
 use crate::util::file;
 use kepler_core::core::hash::Hashed;
@@ -47,7 +48,10 @@ fn test_unexpected_zip() {
 			head.hash().to_string()
 		));
 		let zip_file = File::open(&zip_path).unwrap();
-		assert!(txhashset::zip_write(PathBuf::from(db_root.clone()), zip_file, &head).is_ok());
+		assert!(
+			txhashset::zip_write(PathBuf::from(db_root.clone()), zip_file, &head, &NoStatus)
+				.is_ok()
+		);
 		// Remove temp txhashset dir
 		let _ = fs::remove_dir_all(
 			Path::new(&db_root).join(format!("txhashset_zip_{}", head.hash().to_string())),
@@ -89,7 +93,10 @@ fn test_unexpected_zip() {
 		);
 		let zip_file = File::open(zip_path).unwrap();
 		let _ = fs::remove_dir_all(Path::new(&db_root).join("txhashset"));
-		assert!(txhashset::zip_write(PathBuf::from(db_root.clone()), zip_file, &head).is_ok());
+		assert!(
+			txhashset::zip_write(PathBuf::from(db_root.clone()), zip_file, &head, &NoStatus)
+				.is_ok()
+		);
 
 		// Check that the new txhashset dir contains *only* the expected files
 		// No "badfiles" and no "size" file.
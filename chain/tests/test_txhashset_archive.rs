@@ -14,7 +14,34 @@
 
 mod chain_test_helper;
 
-use self::chain_test_helper::{clean_output_dir, mine_chain};
+use self::chain::types::{NoStatus, Options, TxHashsetWriteStatus};
+use self::chain::SignedCheckpoint;
+use self::chain_test_helper::{clean_output_dir, init_chain, mine_chain};
+use self::core::core::hash::Hashed;
+use self::util::secp::key::SecretKey;
+use kepler_chain as chain;
+use kepler_core as core;
+use kepler_util as util;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Counts the kernel signatures verified.
+#[derive(Default)]
+struct VerifiedStatus {
+	kernels: AtomicU64,
+}
+
+impl TxHashsetWriteStatus for VerifiedStatus {
+	fn on_setup(&self) {}
+	fn on_unzip(&self, _us: u64, _ts: u64) {}
+	fn on_validation_kernel_history(&self, _hs: u64, _ht: u64) {}
+	fn on_validation_mmrs(&self, _ms: u64, _mt: u64, _ns: u64, _nt: u64) {}
+	fn on_validation_kernels(&self, kernels: u64, _kt: u64) {
+		self.kernels.store(kernels, Ordering::Relaxed);
+	}
+	fn on_validation_rproofs(&self, _rs: u64, _rt: u64) {}
+	fn on_save(&self) {}
+	fn on_done(&self) {}
+}
 
 #[test]
 fn test() {
@@ -25,3 +52,128 @@ fn test() {
 	assert_eq!(10, header.height);
 	clean_output_dir(chain_dir);
 }
+
+#[test]
+fn reject_bad_txhashset() {
+	let src_dir = ".bad_txhashset_src";
+	let other_dir = ".bad_txhashset_other";
+	let dest_dir = ".bad_txhashset_dest";
+	clean_output_dir(src_dir);
+	clean_output_dir(other_dir);
+	clean_output_dir(dest_dir);
+
+	let src = mine_chain(src_dir, 35);
+	let other = mine_chain(other_dir, 35);
+	let genesis = src
+		.get_block(&src.get_header_by_height(0).unwrap().hash())
+		.unwrap();
+	let dest = init_chain(dest_dir, genesis);
+	let headers: Vec<_> = (1..=35)
+		.map(|height| src.get_header_by_height(height).unwrap())
+		.collect();
+	dest.sync_block_headers(&headers, Options::SKIP_POW)
+		.unwrap();
+
+	// The txhashset of another chain is bad data, its sender gets banned.
+	let header = src.txhashset_archive_header().unwrap();
+	let other_header = other.get_header_by_height(header.height).unwrap();
+	let (_, _, bad_zip) = other.txhashset_read(other_header.hash()).unwrap();
+	let peer = "10.0.0.1:3414".to_owned();
+	assert!(dest
+		.txhashset_write_from(
+			header.hash(),
+			bad_zip.try_clone().unwrap(),
+			&NoStatus,
+			Some(peer.clone())
+		)
+		.unwrap());
+	let bad = dest.bad_txhashsets();
+	assert_eq!(bad.len(), 1);
+	assert_eq!(bad[0].header, header.hash());
+	assert!(bad[0].archive.is_some());
+	assert_eq!(bad[0].peer, Some(peer.clone()));
+	assert!(dest.is_bad_txhashset_provider(&peer));
+	assert_eq!(dest.head().unwrap().height, 0);
+
+	// The same archive is turned down again, whoever sends it.
+	let relayer = "10.0.0.2:3414".to_owned();
+	assert!(dest
+		.txhashset_write_from(header.hash(), bad_zip, &NoStatus, Some(relayer.clone()))
+		.unwrap());
+	assert!(dest.is_bad_txhashset_provider(&relayer));
+	assert_eq!(dest.bad_txhashsets().len(), 2);
+
+	// While the good one is accepted.
+	dest.clean_txhashset_sandbox();
+	let (_, _, zip) = src.txhashset_read(header.hash()).unwrap();
+	assert!(!dest
+		.txhashset_write_from(
+			header.hash(),
+			zip,
+			&NoStatus,
+			Some("10.0.0.3:3414".to_owned())
+		)
+		.unwrap());
+	assert_eq!(dest.head().unwrap().last_block_h, header.hash());
+	assert!(!dest.is_bad_txhashset_provider("10.0.0.3:3414"));
+
+	clean_output_dir(src_dir);
+	clean_output_dir(other_dir);
+	clean_output_dir(dest_dir);
+}
+
+#[test]
+fn txhashset_below_trusted_checkpoint() {
+	let src_dir = ".trusted_txhashset_src";
+	let dest_dir = ".trusted_txhashset_dest";
+	let trusting_dir = ".trusted_txhashset_trusting";
+	clean_output_dir(src_dir);
+	clean_output_dir(dest_dir);
+	clean_output_dir(trusting_dir);
+
+	let src = mine_chain(src_dir, 35);
+	let genesis = src
+		.get_block(&src.get_header_by_height(0).unwrap().hash())
+		.unwrap();
+	let headers: Vec<_> = (1..=35)
+		.map(|height| src.get_header_by_height(height).unwrap())
+		.collect();
+	let header = src.txhashset_archive_header().unwrap();
+
+	// Without a checkpoint, every kernel signature is verified.
+	let dest = init_chain(dest_dir, genesis.clone());
+	dest.sync_block_headers(&headers, Options::SKIP_POW)
+		.unwrap();
+	let (_, _, zip) = src.txhashset_read(header.hash()).unwrap();
+	let status = VerifiedStatus::default();
+	assert!(!dest.txhashset_write(header.hash(), zip, &status).unwrap());
+	assert!(status.kernels.load(Ordering::Relaxed) > 0);
+
+	// Below the checkpoint of a trusted operator, none are.
+	let secp = util::static_secp_instance();
+	let key = SecretKey::from_slice(&secp.lock(), &[1; 32]).unwrap();
+	let signed = SignedCheckpoint::sign(
+		src.checkpoint(Some(header.height + 5)).unwrap(),
+		&genesis.hash(),
+		&key,
+	)
+	.unwrap();
+	let trusting = init_chain(trusting_dir, genesis);
+	trusting
+		.sync_block_headers(&headers, Options::SKIP_POW)
+		.unwrap();
+	trusting
+		.add_checkpoint(signed.clone(), &[signed.operator])
+		.unwrap();
+	let (_, _, zip) = src.txhashset_read(header.hash()).unwrap();
+	let status = VerifiedStatus::default();
+	assert!(!trusting
+		.txhashset_write(header.hash(), zip, &status)
+		.unwrap());
+	assert_eq!(status.kernels.load(Ordering::Relaxed), 0);
+	assert_eq!(trusting.head().unwrap().last_block_h, header.hash());
+
+	clean_output_dir(src_dir);
+	clean_output_dir(dest_dir);
+	clean_output_dir(trusting_dir);
+}
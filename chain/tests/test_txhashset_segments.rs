@@ -0,0 +1,226 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+mod chain_test_helper;
+
+use self::chain::txhashset::{SegmentType, Segmenter};
+use self::chain::types::{NoStatus, Options};
+use self::chain::Chain;
+use self::chain_test_helper::{clean_output_dir, init_chain, mine_chain};
+use self::core::core::hash::Hashed;
+use self::core::core::{Block, BlockBuilder, KernelFeatures, Transaction};
+use self::core::genesis;
+use self::core::global::{self, ChainTypes};
+use self::core::libtx::{build, reward, ProofBuilder};
+use self::core::{consensus, pow};
+use self::keychain::{ExtKeychain, ExtKeychainPath, Keychain};
+use chrono::Duration;
+use kepler_chain as chain;
+use kepler_core as core;
+use kepler_keychain as keychain;
+use std::collections::HashSet;
+
+#[test]
+fn sync_txhashset_in_segments() {
+	let src_dir = ".txhashset_segments_src";
+	let dest_dir = ".txhashset_segments_dest";
+	clean_output_dir(src_dir);
+	clean_output_dir(dest_dir);
+
+	let src = mine_chain(src_dir, 35);
+	let genesis = src
+		.get_block(&src.get_header_by_height(0).unwrap().hash())
+		.unwrap();
+	let dest = init_chain(dest_dir, genesis);
+	let headers: Vec<_> = (1..=35)
+		.map(|height| src.get_header_by_height(height).unwrap())
+		.collect();
+	dest.sync_block_headers(&headers, Options::SKIP_POW)
+		.unwrap();
+
+	let segmenter = src.segmenter().unwrap();
+	let header = segmenter.header().clone();
+	assert_eq!(header.height, 10);
+	dest.init_desegmenter(&header).unwrap();
+
+	// Only the first bitmap segment is known before it's received.
+	let desired = dest.next_desired_segments(16, &HashSet::new());
+	assert_eq!(desired.len(), 1);
+
+	// A segment of another height is rejected.
+	let (mut segment, output_root, bitmap_size) = segmenter.bitmap_segment(desired[0].1).unwrap();
+	segment.identifier.height += 1;
+	assert!(dest
+		.add_bitmap_segment(header.hash(), segment, output_root, bitmap_size)
+		.is_err());
+
+	// So is a bitmap accumulator size not matching the header.
+	let (segment, output_root, bitmap_size) = segmenter.bitmap_segment(desired[0].1).unwrap();
+	assert!(dest
+		.add_bitmap_segment(header.hash(), segment, output_root, bitmap_size + 1)
+		.is_err());
+
+	sync_segments(&dest, &segmenter);
+	assert!(dest.desegmenter_complete());
+
+	assert_eq!(dest.txhashset_write_segments(&NoStatus).unwrap(), false);
+	assert_eq!(dest.head().unwrap().last_block_h, header.hash());
+	assert_eq!(dest.desegmenter_header(), None);
+
+	clean_output_dir(src_dir);
+	clean_output_dir(dest_dir);
+}
+
+#[test]
+fn sync_compacted_txhashset_in_segments() {
+	let src_dir = ".txhashset_segments_compacted_src";
+	let dest_dir = ".txhashset_segments_compacted_dest";
+	clean_output_dir(src_dir);
+	clean_output_dir(dest_dir);
+
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+	let kc = ExtKeychain::from_random_seed(false).unwrap();
+	let pb = ProofBuilder::new(&kc);
+	let genesis = genesis_block(&kc);
+	let src = init_chain(src_dir, genesis.clone());
+	for _ in 1..=20 {
+		mine_block(&src, &kc, vec![]);
+	}
+
+	// Spend the coinbase outputs of blocks 8 to 17, leaves 8 to 17 of the
+	// output MMR: once compacted they leave the root of a pruned subtree
+	// spanning two segments and one within a segment.
+	let spent: Vec<_> = (8..=17)
+		.map(|height| {
+			let header = src.get_header_by_height(height).unwrap();
+			src.get_block(&header.hash()).unwrap().outputs()[0].clone()
+		})
+		.collect();
+	let fee = 20000;
+	let mut parts: Vec<_> = (8..=17)
+		.map(|height| {
+			let key_id = ExtKeychainPath::new(1, height as u32, 0, 0, 0).to_identifier();
+			build::coinbase_input(consensus::reward(height, 0), key_id)
+		})
+		.collect();
+	let total: u64 = (8..=17).map(|height| consensus::reward(height, 0)).sum();
+	let change_key_id = ExtKeychainPath::new(1, 1000, 0, 0, 0).to_identifier();
+	parts.push(build::output(total - fee, change_key_id));
+	let tx = build::transaction(KernelFeatures::Plain { fee }, parts, &kc, &pb).unwrap();
+	let change = tx.outputs()[0].clone();
+	mine_block(&src, &kc, vec![tx]);
+
+	// Past the horizon and the startup compaction threshold.
+	for _ in 22..=80 {
+		mine_block(&src, &kc, vec![]);
+	}
+	src.compact().unwrap();
+	assert!(src.compaction_status().last_finished.is_some());
+
+	let dest = init_chain(dest_dir, genesis);
+	let headers: Vec<_> = (1..=80)
+		.map(|height| src.get_header_by_height(height).unwrap())
+		.collect();
+	dest.sync_block_headers(&headers, Options::SKIP_POW)
+		.unwrap();
+
+	let segmenter = src.segmenter().unwrap();
+	let header = segmenter.header().clone();
+	assert_eq!(header.height, 60);
+	dest.init_desegmenter(&header).unwrap();
+	sync_segments(&dest, &segmenter);
+	assert!(dest.desegmenter_complete());
+
+	assert_eq!(dest.txhashset_write_segments(&NoStatus).unwrap(), false);
+	assert_eq!(dest.head().unwrap().last_block_h, header.hash());
+	for out in spent {
+		assert!(dest.is_unspent(&out.into()).is_err());
+	}
+	assert!(dest.is_unspent(&change.into()).is_ok());
+
+	clean_output_dir(src_dir);
+	clean_output_dir(dest_dir);
+}
+
+// Genesis with a reward output, the first leaf of the output MMR.
+fn genesis_block<K>(kc: &K) -> Block
+where
+	K: Keychain,
+{
+	let key_id = ExtKeychainPath::new(0, 1, 0, 0, 0).to_identifier();
+	let reward = reward::output(kc, &ProofBuilder::new(kc), &key_id, 0, 0, false).unwrap();
+	genesis::genesis_dev().with_reward(reward.0, reward.1)
+}
+
+// Mine the next block with the txs, its coinbase to the key of its height.
+fn mine_block<K>(chain: &Chain, kc: &K, txs: Vec<Transaction>)
+where
+	K: Keychain,
+{
+	let prev = chain.head_header().unwrap();
+	let next_header_info = consensus::next_difficulty(1, chain.difficulty_iter().unwrap());
+	let key_id = ExtKeychainPath::new(1, prev.height as u32 + 1, 0, 0, 0).to_identifier();
+	let mut b = BlockBuilder::new(&prev)
+		.txs(txs)
+		.difficulty(next_header_info.difficulty)
+		.secondary_scaling(next_header_info.secondary_scaling)
+		.timestamp(prev.timestamp + Duration::seconds(60))
+		.coinbase(kc, &ProofBuilder::new(kc), &key_id)
+		.unwrap()
+		.build()
+		.unwrap();
+	chain.set_txhashset_roots(&mut b).unwrap();
+
+	let edge_bits = global::min_edge_bits();
+	b.header.pow.proof.edge_bits = edge_bits;
+	pow::pow_size(
+		&mut b.header,
+		next_header_info.difficulty,
+		global::proofsize(),
+		edge_bits,
+	)
+	.unwrap();
+	b.header.pow.proof.edge_bits = edge_bits;
+	chain.process_block(b, Options::MINE).unwrap();
+}
+
+// Feed the dest chain all the segments it asks for, until it's complete.
+fn sync_segments(dest: &Chain, segmenter: &Segmenter) {
+	let header = segmenter.header().clone();
+	loop {
+		let desired = dest.next_desired_segments(16, &HashSet::new());
+		if desired.is_empty() {
+			break;
+		}
+		for (segment_type, id) in desired {
+			let hash = header.hash();
+			match segment_type {
+				SegmentType::Bitmap => {
+					let (segment, output_root, bitmap_size) = segmenter.bitmap_segment(id).unwrap();
+					dest.add_bitmap_segment(hash, segment, output_root, bitmap_size)
+				}
+				SegmentType::Output => {
+					dest.add_output_segment(hash, segmenter.output_segment(id).unwrap())
+				}
+				SegmentType::RangeProof => {
+					dest.add_rangeproof_segment(hash, segmenter.rangeproof_segment(id).unwrap())
+				}
+				SegmentType::Kernel => {
+					dest.add_kernel_segment(hash, segmenter.kernel_segment(id).unwrap())
+				}
+			}
+			.unwrap();
+		}
+	}
+}
@@ -71,6 +71,44 @@ fn comments() -> HashMap<String, String> {
 		.to_string(),
 	);
 
+	retval.insert(
+		"api_open_reads".to_string(),
+		"
+#serve the read-only Rest API requests without the api secret. Requests
+#changing the node state (pushing transactions, banning peers, compacting
+#the chain, the v2 Owner API...) always need it
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"api_open_routes".to_string(),
+		"
+#Rest API routes whose read-only requests are served without the api secret,
+#as registered by the node, e.g. [\"/v1/chain\", \"/v1/blocks/**\"]
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"api_protected_routes".to_string(),
+		"
+#Rest API routes needing the api secret even to read when api_open_reads is
+#set, e.g. [\"/v1/peers/**\"]
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"api_cors_origins".to_string(),
+		"
+#origins whose pages may query the rest API from a browser, for explorers
+#and wallets served from another origin, e.g. [\"https://explorer.example.com\"]
+#or [\"*\"] for any. The api secret is still needed where configured
+"
+		.to_string(),
+	);
+
 	retval.insert(
 		"db_root".to_string(),
 		"
@@ -107,7 +145,234 @@ fn comments() -> HashMap<String, String> {
 	retval.insert(
 		"archive_mode".to_string(),
 		"
-#run the node in \"full archive\" mode (default is fast-sync, pruned node)
+#run the node in \"full archive\" mode (default is fast-sync, pruned node).
+#An archive node syncs every block from genesis rather than the txhashset,
+#never prunes nor compacts them and serves them all to its peers
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"safe_mode".to_string(),
+		"
+#open the chain read-only and only serve API queries, without syncing nor
+#connecting to peers. Lets a data dir written by a newer version be queried
+#without migrating it
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"block_files_dir".to_string(),
+		"
+#directory of the flat block files (blk00000.dat, ...) every accepted block
+#gets appended to, alongside the db, i.e. \"/backup/kepler/blocks\". The
+#files can be replayed with kepler server import-blocks
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"checkpoint_files".to_string(),
+		"
+#checkpoint files exported with kepler server export-checkpoint by the
+#operators of a federation, i.e. [\"/etc/kepler/op1.checkpoint\"]. Only the
+#ones signed by a trusted_checkpoint_operators key are imported, at startup
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"trusted_checkpoint_operators".to_string(),
+		"
+#node identity keys (hex) of the operators whose checkpoints are trusted.
+#Headers conflicting with their checkpoints are rejected, and a txhashset
+#below one is accepted without verifying its rangeproofs and kernel
+#signatures. Only list operators you trust with the validity of the chain
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"hard_checkpoints".to_string(),
+		"
+#hard checkpoints, as \"height:hash\", i.e. [\"100000:0a1b...\"]. Headers
+#conflicting with them are rejected
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"assume_valid_height".to_string(),
+		"
+#blocks of the most work header chain up to this height are synced without
+#verifying their rangeproofs, or those of a txhashset below it. Must be the
+#height of one of the hard_checkpoints, which the header chain goes through
+#assume_valid_height = 0
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"public_node".to_string(),
+		"
+#run as a public, wallet serving node. Disables the owner API and peer
+#endpoints, rate limits API requests per client IP and bans misbehaving peers
+#for longer
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"api_worker_threads".to_string(),
+		"
+#most API requests served per second to a single client IP, and at once
+#across all clients. Unlimited unless set, public nodes default to 10 and 32
+#api_max_requests_per_ip = 10
+#api_max_in_flight_requests = 32
+
+#threads the API requests are handled on, the others waiting their turn, so
+#that slow requests (blocks with their proofs, txhashset queries) don't hold
+#up the server. Set to 0 to handle them on the async runtime
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"api_cache_ttl_secs".to_string(),
+		"
+#how long to cache block and header API responses, in seconds. Lowers the
+#load of explorers requesting the same blocks over and over. Responses
+#are dropped as soon as the chain head changes. Set to 0 to disable caching
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"api_graphql".to_string(),
+		"
+#serve GraphQL queries of the blocks, headers, outputs and kernels of the
+#chain at /v2/graphql, fetching only the selected fields in a single request
+
+#serve the tip, blocks, outputs, tx push and peers over gRPC as well, at the
+#address, for nodes built with the grpc feature (see api/proto/node.proto).
+#Requests need the api secret, as basic auth in the authorization metadata,
+#and are rate limited as the http ones
+#grpc_addr = \"127.0.0.1:7417\"
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"owner_api_scan_outputs".to_string(),
+		"
+#let wallets restore against this node with the scan_outputs method of the
+#owner api, rewinding the rangeproofs of the UTXO set with their view key
+#rewind hash. Scans are costly, they run one at a time every few seconds
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"executor_threads".to_string(),
+		"
+#number of worker threads of the runtime running the API, stratum and
+#background tasks of the node. Set to 0 for one per cpu
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"sync_watchdog_secs".to_string(),
+		"
+#how long the sync can stay in a stage (header sync, txhashset download or
+#validation, body sync) without making progress before the node resets that
+#stage and moves to other sync peers, in seconds. Set to 0 to disable
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"idle_reclaim_secs".to_string(),
+		"
+#how long the node has to be synced before it shrinks its caches, compacts
+#its peer store and hands its unused memory back to the OS, and how often it
+#does so again, in seconds. Done between blocks only. Set to 0 to disable
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"verification_threads".to_string(),
+		"
+#number of threads verifying the rangeproofs and kernel signatures of blocks,
+#transactions and the txhashset. Bounds the cpu the node uses while syncing.
+#Set to 0 for one per cpu
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"validation_nice".to_string(),
+		"
+#nice level of the threads verifying the txhashset downloaded during the
+#sync, from 0 (unchanged) to 19 (yield the cpu to any other process). Blocks,
+#txs and the chain compaction keep the normal priority, the node waiting on
+#them. Linux only
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"upstream_node_url".to_string(),
+		"
+#api url of a trusted node to follow instead of connecting to the p2p
+#network, i.e. \"http://10.0.0.2:7413\". Blocks are fetched from it and
+#fully validated locally, pushed transactions are forwarded to it
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"upstream_api_secret_path".to_string(),
+		"
+#path of the api secret of the upstream node, if it requires one
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"head_race_policy".to_string(),
+		"
+#how to resolve a race between blocks of equal total difficulty. Can be:
+#\"FirstSeen\" - keep the block seen first as the chain head (default)
+#\"LowestHash\" - prefer the block with the lowest hash
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"max_orphans".to_string(),
+		"
+#most blocks held in the orphan pool while waiting for their parent, the
+#highest ones are evicted first when it's full
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"max_orphan_age_secs".to_string(),
+		"
+#orphan blocks older than that many seconds are evicted
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"max_orphan_height_ahead".to_string(),
+		"
+#orphan blocks further ahead of the chain head than that many blocks are
+#evicted. Keep it above max_orphans for the body sync to fill the pool
 "
 		.to_string(),
 	);
@@ -176,6 +441,196 @@ fn comments() -> HashMap<String, String> {
 		.to_string(),
 	);
 
+	retval.insert(
+		"[server.alert_config]".to_string(),
+		"
+#########################################
+### ALERT CONFIGURATION               ###
+#########################################
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"alert_log".to_string(),
+		"
+#Whether to log the alerts raised and cleared, with an \"ALERT\" marker.
+#Active alerts are also listed by the api at /v1/alerts.
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"min_free_disk_mb".to_string(),
+		"
+#The url where a POST request will be sent when an alert is raised or cleared.
+#alert_webhook_url = \"http://127.0.0.1:8080/alert\"
+
+#A command run when an alert is raised or cleared, getting the alert state
+#(raised or cleared), kind and message as arguments.
+#alert_command = \"/usr/local/bin/notify_operator\"
+
+#Alert when the disk of the chain data has less space left, in megabytes.
+#The thresholds below disable their alert when set to 0.
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"sync_stall_secs".to_string(),
+		"
+#Alert when the chain head didn't move for that long while peers are ahead,
+#not counting the time spent syncing.
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"max_validation_failures".to_string(),
+		"
+#Alert when that many blocks received failed validation within an hour.
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"max_clock_skew_secs".to_string(),
+		"
+#Alert when the local clock is further off the network time estimated from
+#the peers, in seconds.
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"[server.tx_policy_config]".to_string(),
+		"
+#########################################
+### TX POLICY CONFIGURATION           ###
+#########################################
+
+#An external policy engine can be asked whether to accept the transactions
+#received from peers into the pool, once per transaction. The block templates
+#leave out the ones it rejected. It has no effect on the validity of blocks
+#mined by others.
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"policy_timeout_ms".to_string(),
+		"
+#The url where a POST request will be sent with each transaction and its hash,
+#expecting {\"accept\": true|false} back.
+#policy_url = \"http://127.0.0.1:8080/policy\"
+
+#Or a command run with the transaction hash as argument and the transaction
+#JSON on its standard input, accepting it by exiting with 0.
+#policy_command = \"/usr/local/bin/tx_policy\"
+
+#How long the policy engine has to decide, in milliseconds.
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"policy_accept_on_error".to_string(),
+		"
+#Whether to accept the transactions the policy engine failed to decide on, an
+#engine down or too slow then letting them all through. Off by default, they
+#are turned down until it decides on them.
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"policy_dry_run".to_string(),
+		"
+#Only log the transactions the policy engine rejects, to try out a policy.
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"[server.faucet_config]".to_string(),
+		"
+#########################################
+### FAUCET CONFIGURATION              ###
+#########################################
+
+#The node can hand out test coins of a funded wallet at POST /v1/faucet, on
+#floonet and usernet only. Anyone can request them, within a quota per IP.
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"enable_faucet".to_string(),
+		"
+#Whether to serve the faucet.
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"wallet_owner_url".to_string(),
+		"
+#Owner api url of the funded wallet the coins are sent from.
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"wallet_owner_secret_path".to_string(),
+		"
+#Location of the secret of the wallet owner api, if it has one.
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"faucet_amount".to_string(),
+		"
+#Amount sent per request, in nanokeplers.
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"max_requests_per_ip".to_string(),
+		"
+#Requests per client IP, or IPv6 /64, within the quota period, failed ones
+#included.
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"quota_period_secs".to_string(),
+		"
+#Quota period, in seconds.
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"captcha_verify_url".to_string(),
+		"
+#url to POST the captcha responses to for verification, as JSON with the
+#\"secret\", \"response\" and \"remoteip\" fields, answering {\"success\": true} if
+#valid. No captcha if not set.
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"captcha_secret".to_string(),
+		"
+#Secret sent along with the captcha responses.
+"
+		.to_string(),
+	);
+
 	retval.insert(
 		"[server.dandelion_config]".to_string(),
 		"
@@ -226,6 +681,15 @@ fn comments() -> HashMap<String, String> {
 		.to_string(),
 	);
 
+	retval.insert(
+		"relay_jitter_min_ms".to_string(),
+		"
+#bounds of the random delay (in ms) before relaying a fluffed tx to each peer,
+#trickling it out instead of sending it to all peers at once (0 max to disable)
+"
+		.to_string(),
+	);
+
 	retval.insert(
 		"[server.p2p_config]".to_string(),
 		"#test miner wallet URL (burns if this doesn't exist)
@@ -266,6 +730,38 @@ fn comments() -> HashMap<String, String> {
 		.to_string(),
 	);
 
+	retval.insert(
+		"adjust_network_time".to_string(),
+		"
+#Whether to adjust the local clock by the median offset of the peers clocks
+#(up to 5 minutes) when refusing blocks too far in the future
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"fast_block_announce".to_string(),
+		"
+#Whether to announce the blocks we mine or relay by their header to all our
+#peers at once, then upload the block to each of them in turn, best peers
+#first. Lowers the orphan rate of miners on slow links, at the cost of some
+#blocks being sent twice to peers asking for them meanwhile
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"capture_messages".to_string(),
+		"
+#debugging only: record all the messages received from peers to rotating
+#files in the p2p_capture directory of the chain data, to replay them with
+#'kepler server replay-capture'. Four files are kept, each of up to
+#capture_file_size_mb
+#capture_file_size_mb = 64
+"
+		.to_string(),
+	);
+
 	retval.insert(
 		"[server.p2p_config.capabilities]".to_string(),
 		"#If the seeding type is List, the list of peers to connect to can
@@ -279,10 +775,23 @@ fn comments() -> HashMap<String, String> {
 #peers_deny = [\"192.168.0.3:7414\", \"192.168.0.4:7414\"]
 #a list of preferred peers to connect to
 #peers_preferred = [\"192.168.0.1:7414\",\"192.168.0.2:7414\"]
+#peers and subnets never banned automatically (manual bans still apply),
+#also adjustable through the owner API
+#peers_ban_exempt = [\"192.168.0.5\", \"10.0.0.0/8\"]
 
 #how long a banned peer should stay banned
 #ban_window = 10800
 
+#distinct headers of forks with less work than our chain a peer can send
+#per hour, past it they are dropped and the peer banned
+#max_fork_headers_per_hour = 100
+
+#requests sent for the missing parent of an orphan block, to the peer that
+#sent the orphan first then to other peers, with a delay doubling after each
+#one, only the first request if 0
+#orphan_parent_requests = 5
+#orphan_parent_retry_secs = 2
+
 #maximum number of inbound peer connections
 #peer_max_inbound_count = 128
 
@@ -293,6 +802,10 @@ fn comments() -> HashMap<String, String> {
 #until we get to at least this number)
 #peer_min_preferred_outbound_count = 8
 
+#minimum number of outbound peers, below it we report insufficient peers and
+#dial replacements right away (capped to peer_min_preferred_outbound_count)
+#peer_min_outbound_count = 4
+
 #amount of incoming connections temporarily allowed to exceed peer_max_inbound_count
 #peer_listener_buffer_count = 8
 
@@ -348,6 +861,43 @@ fn comments() -> HashMap<String, String> {
 		.to_string(),
 	);
 
+	retval.insert(
+		"validation_threads".to_string(),
+		"
+#number of threads validating the transactions received from peers, their
+#signatures and range proofs are checked outside of the pool lock
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"max_intake_queue".to_string(),
+		"
+#maximum number of transactions received from peers waiting to be validated.
+#A single peer can take up at most a quarter of the queue, more are dropped
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"input_proofs_above".to_string(),
+		"
+#number of inputs above which the transactions relayed by peers must come with
+#a proof of the ability to spend each input, before their inputs are looked up.
+#0 never asks for proofs
+"
+		.to_string(),
+	);
+
+	retval.insert(
+		"max_input_proofs".to_string(),
+		"
+#maximum number of input proofs kept for the transactions received or to relay,
+#a single peer taking up at most an eighth of them
+"
+		.to_string(),
+	);
+
 	retval.insert(
 		"[server.stratum_mining_config]".to_string(),
 		"
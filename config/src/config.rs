@@ -170,7 +170,7 @@ impl GlobalConfig {
 			global::ChainTypes::Mainnet => {}
 			global::ChainTypes::Floonet => {
 				defaults.api_http_addr = "127.0.0.1:17413".to_owned();
-				defaults.p2p_config.port = 17414;
+				defaults.p2p_config.port = chain_type.default_p2p_port();
 				defaults
 					.stratum_mining_config
 					.as_mut()
@@ -184,7 +184,7 @@ impl GlobalConfig {
 			}
 			global::ChainTypes::UserTesting => {
 				defaults.api_http_addr = "127.0.0.1:27413".to_owned();
-				defaults.p2p_config.port = 27414;
+				defaults.p2p_config.port = chain_type.default_p2p_port();
 				defaults.p2p_config.seeding_type = p2p::Seeding::None;
 				defaults
 					.stratum_mining_config
@@ -0,0 +1,77 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Source of the current time for the time based consensus rules and timers,
+//! so tests and simulations can control the time they run at.
+
+use crate::consensus::BLOCK_TIME_SEC;
+use crate::global;
+use chrono::prelude::{DateTime, Utc};
+use chrono::Duration;
+use std::fmt;
+use util::RwLock;
+
+/// Provides the current time.
+pub trait Clock: fmt::Debug + Send + Sync {
+	/// Current time.
+	fn now(&self) -> DateTime<Utc>;
+}
+
+/// Clock reading the system time, used everywhere outside of tests.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+	fn now(&self) -> DateTime<Utc> {
+		Utc::now()
+	}
+}
+
+/// Clock only moving when told to.
+#[derive(Debug)]
+pub struct ManualClock {
+	now: RwLock<DateTime<Utc>>,
+}
+
+impl ManualClock {
+	/// New clock stopped at the provided time.
+	pub fn new(now: DateTime<Utc>) -> ManualClock {
+		ManualClock {
+			now: RwLock::new(now),
+		}
+	}
+
+	/// Set the clock to the provided time.
+	pub fn set(&self, now: DateTime<Utc>) {
+		*self.now.write() = now;
+	}
+
+	/// Move the clock forward (or backward) by the provided duration.
+	pub fn advance(&self, duration: Duration) {
+		let mut now = self.now.write();
+		*now = *now + duration;
+	}
+}
+
+impl Clock for ManualClock {
+	fn now(&self) -> DateTime<Utc> {
+		*self.now.read()
+	}
+}
+
+/// Latest timestamp a block header can have according to the provided clock:
+/// 12 block intervals past the network time (as in bitcoin).
+pub fn max_header_time(clock: &dyn Clock) -> DateTime<Utc> {
+	clock.now() + Duration::seconds(global::network_time_offset() + 12 * BLOCK_TIME_SEC as i64)
+}
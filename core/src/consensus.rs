@@ -194,6 +194,30 @@ pub fn valid_header_version(height: u64, version: HeaderVersion) -> bool {
 		&& version == header_version(height);
 }
 
+/// Height of the next scheduled hard fork after the provided height, along
+/// with the header version it activates. None past the end of the schedule,
+/// from where no header version is valid anymore and the node needs an
+/// upgrade to follow the chain.
+pub fn next_hard_fork(height: u64) -> Option<(u64, HeaderVersion)> {
+	let end = (3 * HARD_FORK_INTERVAL).saturating_sub(HARD_FORK_ADJUST_HEIGHT);
+	if height >= end {
+		return None;
+	}
+	// Header versions only ever increase with the height, the end of the
+	// schedule counting as the last hard fork.
+	let version = header_version(height);
+	let (mut lo, mut hi) = (height, end);
+	while hi - lo > 1 {
+		let mid = lo + (hi - lo) / 2;
+		if header_version(mid) > version {
+			hi = mid;
+		} else {
+			lo = mid;
+		}
+	}
+	Some((hi, HeaderVersion(version.0 + 1)))
+}
+
 /// Number of blocks used to calculate difficulty adjustments
 pub const DIFFICULTY_ADJUST_WINDOW: u64 = HOUR_HEIGHT;
 
@@ -15,11 +15,14 @@
 //! Core types
 
 pub mod block;
+pub mod block_chunk;
+pub mod block_filter;
 pub mod block_sums;
 pub mod committed;
 pub mod compact_block;
 pub mod hash;
 pub mod id;
+pub mod input_proof;
 pub mod merkle_proof;
 pub mod pmmr;
 pub mod transaction;
@@ -30,10 +33,13 @@ use crate::consensus::KEPLER_BASE;
 use util::secp::pedersen::Commitment;
 
 pub use self::block::*;
+pub use self::block_chunk::{BlockChunk, BlockManifest, ChunkItems, ChunkPart, ChunkedBody};
+pub use self::block_filter::BlockFilter;
 pub use self::block_sums::*;
 pub use self::committed::Committed;
 pub use self::compact_block::*;
 pub use self::id::ShortId;
+pub use self::input_proof::InputProof;
 pub use self::transaction::*;
 
 /// Common errors
@@ -24,14 +24,14 @@ use crate::core::{
 	Weighting,
 };
 use crate::global;
+use crate::libtx::{self, proof::ProofBuild};
 use crate::pow::{verify_size, Difficulty, Proof, ProofOfWork};
 use crate::ser::{
 	self, deserialize_default, serialize_default, PMMRable, Readable, Reader, Writeable, Writer,
 };
 use chrono::naive::{MAX_DATE, MIN_DATE};
 use chrono::prelude::{DateTime, NaiveDateTime, Utc};
-use chrono::Duration;
-use keychain::{self, BlindingFactor};
+use keychain::{self, BlindingFactor, Identifier, Keychain};
 use std::collections::HashSet;
 use std::convert::TryInto;
 use std::fmt;
@@ -77,6 +77,8 @@ pub enum Error {
 	/// Validation error relating to cut-through.
 	/// Specifically the tx is spending its own output, which is not valid.
 	CutThrough,
+	/// Chunk of a block body not matching the manifest of the block.
+	InvalidChunk,
 	/// Underlying serialization error.
 	Serialization(ser::Error),
 	/// Other unspecified error condition
@@ -417,17 +419,9 @@ pub struct UntrustedBlockHeader(BlockHeader);
 impl Readable for UntrustedBlockHeader {
 	fn read(reader: &mut dyn Reader) -> Result<UntrustedBlockHeader, ser::Error> {
 		let header = read_block_header(reader)?;
-		if header.timestamp
-			> Utc::now() + Duration::seconds(12 * (consensus::BLOCK_TIME_SEC as i64))
-		{
-			// refuse blocks more than 12 blocks intervals in future (as in bitcoin)
-			// TODO add warning in p2p code if local time is too different from peers
-			error!(
-				"block header {} validation error: block time is more than 12 blocks in future",
-				header.hash()
-			);
-			return Err(ser::Error::CorruptedData);
-		}
+
+		// Headers too far in the future are refused by the chain, against
+		// its own clock, rather than here.
 
 		// Check the block version before proceeding any further.
 		// We want to do this here because blocks can be pretty large
@@ -540,16 +534,14 @@ impl Block {
 		difficulty: Difficulty,
 		reward_output: (Output, TxKernel),
 	) -> Result<Block, Error> {
-		let mut block =
-			Block::from_reward(prev, txs, reward_output.0, reward_output.1, difficulty)?;
-
-		// Now set the pow on the header so block hashing works as expected.
-		{
-			let proof_size = global::proofsize();
-			block.header.pow.proof = Proof::random(proof_size);
-		}
-
-		Ok(block)
+		let (reward_out, reward_kern) = reward_output;
+		// Set a random pow on the header so block hashing works as expected.
+		BlockBuilder::new(prev)
+			.txs(txs)
+			.reward(reward_out, reward_kern)
+			.difficulty(difficulty)
+			.proof(Proof::random(global::proofsize()))
+			.build()
 	}
 
 	/// Hydrate a block from a compact block.
@@ -601,6 +593,11 @@ impl Block {
 		}
 	}
 
+	/// Build a block from its header and body. The body is not validated.
+	pub fn with_body(header: BlockHeader, body: TransactionBody) -> Block {
+		Block { header, body }
+	}
+
 	/// Builds a new block ready to mine from the header of the previous block,
 	/// a vector of transactions and the reward information. Checks
 	/// that all transactions are valid and calculates the Merkle tree.
@@ -611,45 +608,11 @@ impl Block {
 		reward_kern: TxKernel,
 		difficulty: Difficulty,
 	) -> Result<Block, Error> {
-		// A block is just a big transaction, aggregate and add the reward output
-		// and reward kernel. At this point the tx is technically invalid but the
-		// tx body is valid if we account for the reward (i.e. as a block).
-		let agg_tx = transaction::aggregate(txs)?
-			.with_output(reward_out)
-			.with_kernel(reward_kern);
-
-		// Now add the kernel offset of the previous block for a total
-		let total_kernel_offset = committed::sum_kernel_offsets(
-			vec![agg_tx.offset.clone(), prev.total_kernel_offset.clone()],
-			vec![],
-		)?;
-
-		// Determine the height and associated version for the new header.
-		let height = prev.height + 1;
-		let version = consensus::header_version(height);
-
-		let now = Utc::now().timestamp();
-		let timestamp = DateTime::<Utc>::from_utc(NaiveDateTime::from_timestamp(now, 0), Utc);
-
-		// Now build the block with all the above information.
-		// Note: We have not validated the block here.
-		// Caller must validate the block as necessary.
-		Block {
-			header: BlockHeader {
-				version,
-				height,
-				timestamp,
-				prev_hash: prev.hash(),
-				total_kernel_offset,
-				pow: ProofOfWork {
-					total_difficulty: difficulty + prev.pow.total_difficulty,
-					..Default::default()
-				},
-				..Default::default()
-			},
-			body: agg_tx.into(),
-		}
-		.cut_through()
+		BlockBuilder::new(prev)
+			.txs(txs)
+			.reward(reward_out, reward_kern)
+			.difficulty(difficulty)
+			.build()
 	}
 
 	/// Consumes this block and returns a new block with the coinbase output
@@ -746,13 +709,14 @@ impl Block {
 
 	/// Validates all the elements in a block that can be checked without
 	/// additional data. Includes commitment sums and kernels, Merkle
-	/// trees, reward, etc.
+	/// trees, reward, etc. The range proofs and kernel signatures are
+	/// verified in parallel, see TransactionBody::validate_parallel.
 	pub fn validate(
 		&self,
 		prev_kernel_offset: &BlindingFactor,
 		verifier: Arc<RwLock<dyn VerifierCache>>,
 	) -> Result<Commitment, Error> {
-		self.body.validate(Weighting::AsBlock, verifier)?;
+		self.body.validate_parallel(Weighting::AsBlock, verifier)?;
 
 		self.verify_kernel_lock_heights()?;
 		self.verify_coinbase()?;
@@ -818,6 +782,171 @@ impl Block {
 	}
 }
 
+/// Assembles a new block on top of the header of the previous block, from a
+/// set of transactions and the coinbase reward. The header template gets the
+/// height, version, previous hash and total difficulty, the kernel offset of
+/// the transactions is added to the previous total and cut-through applied.
+///
+/// The block is neither validated nor given its txhashset roots (see
+/// `Chain::set_txhashset_roots`), its proof of work is left to be found.
+pub struct BlockBuilder<'a> {
+	prev: &'a BlockHeader,
+	txs: Vec<Transaction>,
+	reward: Option<(Output, TxKernel)>,
+	difficulty: Difficulty,
+	secondary_scaling: u32,
+	timestamp: Option<DateTime<Utc>>,
+	nonce: u64,
+	proof: Option<Proof>,
+}
+
+impl<'a> BlockBuilder<'a> {
+	/// Start building the block following the provided header, with no
+	/// transactions and at the minimum difficulty.
+	pub fn new(prev: &'a BlockHeader) -> BlockBuilder<'a> {
+		BlockBuilder {
+			prev,
+			txs: vec![],
+			reward: None,
+			difficulty: Difficulty::min(),
+			secondary_scaling: 1,
+			timestamp: None,
+			nonce: 0,
+			proof: None,
+		}
+	}
+
+	/// Height of the block being built.
+	pub fn height(&self) -> u64 {
+		self.prev.height + 1
+	}
+
+	/// Sum of the fees of the transactions, owed to the coinbase.
+	pub fn fees(&self) -> u64 {
+		self.txs.iter().map(|tx| tx.fee()).sum()
+	}
+
+	/// Transactions to include in the block.
+	pub fn txs(mut self, txs: Vec<Transaction>) -> BlockBuilder<'a> {
+		self.txs = txs;
+		self
+	}
+
+	/// Coinbase output and kernel of the block.
+	pub fn reward(mut self, reward_out: Output, reward_kern: TxKernel) -> BlockBuilder<'a> {
+		self.reward = Some((reward_out, reward_kern));
+		self
+	}
+
+	/// Builds the coinbase paying the block reward and the fees of the
+	/// transactions to the provided key. Transactions must be set first.
+	pub fn coinbase<K, B>(
+		self,
+		keychain: &K,
+		builder: &B,
+		key_id: &Identifier,
+	) -> Result<BlockBuilder<'a>, libtx::Error>
+	where
+		K: Keychain,
+		B: ProofBuild,
+	{
+		let (reward_out, reward_kern) =
+			libtx::reward::output(keychain, builder, key_id, self.fees(), self.height(), false)?;
+		Ok(self.reward(reward_out, reward_kern))
+	}
+
+	/// Difficulty of the block, added to the previous total difficulty.
+	pub fn difficulty(mut self, difficulty: Difficulty) -> BlockBuilder<'a> {
+		self.difficulty = difficulty;
+		self
+	}
+
+	/// Secondary PoW scaling factor of the block.
+	pub fn secondary_scaling(mut self, secondary_scaling: u32) -> BlockBuilder<'a> {
+		self.secondary_scaling = secondary_scaling;
+		self
+	}
+
+	/// Timestamp of the block, defaults to now but always after the previous
+	/// block.
+	pub fn timestamp(mut self, timestamp: DateTime<Utc>) -> BlockBuilder<'a> {
+		self.timestamp = Some(timestamp);
+		self
+	}
+
+	/// Starting nonce of the proof of work.
+	pub fn nonce(mut self, nonce: u64) -> BlockBuilder<'a> {
+		self.nonce = nonce;
+		self
+	}
+
+	/// Proof of work of the block, when already known.
+	pub fn proof(mut self, proof: Proof) -> BlockBuilder<'a> {
+		self.proof = Some(proof);
+		self
+	}
+
+	/// Assemble the block. Fails if the transactions can't be aggregated,
+	/// cut-through would spend an output of the same transaction or no
+	/// reward was provided.
+	pub fn build(self) -> Result<Block, Error> {
+		let (reward_out, reward_kern) = self
+			.reward
+			.ok_or_else(|| Error::Other("block reward missing".to_owned()))?;
+
+		// A block is just a big transaction, aggregate and add the reward output
+		// and reward kernel. At this point the tx is technically invalid but the
+		// tx body is valid if we account for the reward (i.e. as a block).
+		let agg_tx = transaction::aggregate(self.txs)?
+			.with_output(reward_out)
+			.with_kernel(reward_kern);
+
+		// Now add the kernel offset of the previous block for a total
+		let total_kernel_offset = committed::sum_kernel_offsets(
+			vec![agg_tx.offset.clone(), self.prev.total_kernel_offset.clone()],
+			vec![],
+		)?;
+
+		// Determine the height and associated version for the new header.
+		let height = self.prev.height + 1;
+		let version = consensus::header_version(height);
+
+		let timestamp = match self.timestamp {
+			Some(timestamp) => timestamp,
+			None => {
+				let now = Utc::now()
+					.timestamp()
+					.max(self.prev.timestamp.timestamp() + 1);
+				DateTime::<Utc>::from_utc(NaiveDateTime::from_timestamp(now, 0), Utc)
+			}
+		};
+
+		// Now build the block with all the above information.
+		// Note: We have not validated the block here.
+		// Caller must validate the block as necessary.
+		Block {
+			header: BlockHeader {
+				version,
+				height,
+				timestamp,
+				prev_hash: self.prev.hash(),
+				total_kernel_offset,
+				pow: ProofOfWork {
+					total_difficulty: self.difficulty + self.prev.pow.total_difficulty,
+					secondary_scaling: self.secondary_scaling,
+					nonce: self.nonce,
+					proof: self
+						.proof
+						.unwrap_or_else(|| Proof::zero(global::proofsize())),
+				},
+				..Default::default()
+			},
+			body: agg_tx.into(),
+		}
+		.cut_through()
+	}
+}
+
 impl From<UntrustedBlock> for Block {
 	fn from(block: UntrustedBlock) -> Self {
 		block.0
@@ -0,0 +1,456 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Chunks of the body of a block, for the blocks near the weight limit to be
+//! downloaded in pieces. The manifest of a block lists the hash of each of
+//! its chunks, every chunk is checked against it as it arrives so a chunk
+//! lost or corrupted on the way is the only one fetched again.
+
+use crate::consensus;
+use crate::core::block::{Block, BlockHeader, Error};
+use crate::core::hash::{Hash, HashWriter, Hashed};
+use crate::core::transaction::{Input, Output, TransactionBody, TxKernel};
+use crate::global;
+use crate::ser::{self, Readable, Reader, Writeable, Writer};
+
+/// Most inputs in a chunk.
+pub const CHUNK_INPUTS: usize = 4096;
+
+/// Most outputs in a chunk, their range proofs making them the largest items.
+pub const CHUNK_OUTPUTS: usize = 256;
+
+/// Most kernels in a chunk.
+pub const CHUNK_KERNELS: usize = 1024;
+
+/// Most items in a chunk outside of the production chains, for the small test
+/// blocks to span several chunks.
+pub const TESTING_CHUNK_ITEMS: usize = 2;
+
+/// Part of a block body a chunk holds items of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChunkPart {
+	/// The inputs of the block.
+	Inputs = 0,
+	/// The outputs of the block, with their range proofs.
+	Outputs = 1,
+	/// The kernels of the block.
+	Kernels = 2,
+}
+
+impl ChunkPart {
+	/// All the parts, in the order their chunks are listed in a manifest.
+	pub const ALL: [ChunkPart; 3] = [ChunkPart::Inputs, ChunkPart::Outputs, ChunkPart::Kernels];
+
+	/// Most items of the part in a chunk.
+	pub fn chunk_size(self) -> usize {
+		if !global::is_production_mode() {
+			return TESTING_CHUNK_ITEMS;
+		}
+		match self {
+			ChunkPart::Inputs => CHUNK_INPUTS,
+			ChunkPart::Outputs => CHUNK_OUTPUTS,
+			ChunkPart::Kernels => CHUNK_KERNELS,
+		}
+	}
+
+	/// Most items of the part a block within the weight limit can hold.
+	pub fn max_items(self) -> usize {
+		let weight = match self {
+			ChunkPart::Inputs => consensus::BLOCK_INPUT_WEIGHT,
+			ChunkPart::Outputs => consensus::BLOCK_OUTPUT_WEIGHT,
+			ChunkPart::Kernels => consensus::BLOCK_KERNEL_WEIGHT,
+		};
+		global::max_block_weight() / weight
+	}
+
+	/// Number of chunks holding the given number of items of the part.
+	pub fn chunks(self, items: usize) -> usize {
+		(items + self.chunk_size() - 1) / self.chunk_size()
+	}
+}
+
+/// Most chunks a block within the weight limit can be split into.
+pub fn max_chunks() -> usize {
+	ChunkPart::ALL
+		.iter()
+		.map(|part| part.chunks(part.max_items()))
+		.sum()
+}
+
+impl Writeable for ChunkPart {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_u8(*self as u8)
+	}
+}
+
+impl Readable for ChunkPart {
+	fn read(reader: &mut dyn Reader) -> Result<ChunkPart, ser::Error> {
+		match reader.read_u8()? {
+			0 => Ok(ChunkPart::Inputs),
+			1 => Ok(ChunkPart::Outputs),
+			2 => Ok(ChunkPart::Kernels),
+			_ => Err(ser::Error::CorruptedData),
+		}
+	}
+}
+
+/// Items of one part of a block body.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChunkItems {
+	/// A range of the inputs.
+	Inputs(Vec<Input>),
+	/// A range of the outputs.
+	Outputs(Vec<Output>),
+	/// A range of the kernels.
+	Kernels(Vec<TxKernel>),
+}
+
+impl ChunkItems {
+	/// The part of the body the items are of.
+	pub fn part(&self) -> ChunkPart {
+		match self {
+			ChunkItems::Inputs(_) => ChunkPart::Inputs,
+			ChunkItems::Outputs(_) => ChunkPart::Outputs,
+			ChunkItems::Kernels(_) => ChunkPart::Kernels,
+		}
+	}
+
+	/// Number of items.
+	pub fn len(&self) -> usize {
+		match self {
+			ChunkItems::Inputs(inputs) => inputs.len(),
+			ChunkItems::Outputs(outputs) => outputs.len(),
+			ChunkItems::Kernels(kernels) => kernels.len(),
+		}
+	}
+
+	/// Whether there are no items.
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	/// Hash of the items listed in the manifest. The hash of an output leaves
+	/// its range proof out, the proof hash is added so it is covered too.
+	pub fn hash(&self) -> Hash {
+		let hashes: Vec<Hash> = match self {
+			ChunkItems::Inputs(inputs) => inputs.iter().map(|input| input.hash()).collect(),
+			ChunkItems::Outputs(outputs) => outputs
+				.iter()
+				.flat_map(|output| vec![output.hash(), output.proof.hash()])
+				.collect(),
+			ChunkItems::Kernels(kernels) => kernels.iter().map(|kernel| kernel.hash()).collect(),
+		};
+		let mut hasher = HashWriter::default();
+		for hash in hashes {
+			// Writing to a hasher can't fail.
+			let _ = hasher.write_fixed_bytes(hash.as_bytes());
+		}
+		hasher.into_hash()
+	}
+}
+
+impl Writeable for ChunkItems {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.part().write(writer)?;
+		writer.write_u32(self.len() as u32)?;
+		match self {
+			ChunkItems::Inputs(inputs) => inputs.write(writer),
+			ChunkItems::Outputs(outputs) => outputs.write(writer),
+			ChunkItems::Kernels(kernels) => kernels.write(writer),
+		}
+	}
+}
+
+impl Readable for ChunkItems {
+	fn read(reader: &mut dyn Reader) -> Result<ChunkItems, ser::Error> {
+		let part = ChunkPart::read(reader)?;
+		let count = reader.read_u32()? as u64;
+		if count > part.chunk_size() as u64 {
+			return Err(ser::Error::TooLargeReadErr);
+		}
+		let items = match part {
+			ChunkPart::Inputs => ChunkItems::Inputs(read_items(reader, count)?),
+			ChunkPart::Outputs => ChunkItems::Outputs(read_items(reader, count)?),
+			ChunkPart::Kernels => ChunkItems::Kernels(read_items(reader, count)?),
+		};
+		Ok(items)
+	}
+}
+
+fn read_items<T: Readable>(reader: &mut dyn Reader, count: u64) -> Result<Vec<T>, ser::Error> {
+	let mut items = Vec::with_capacity(count as usize);
+	for _ in 0..count {
+		items.push(T::read(reader)?);
+	}
+	Ok(items)
+}
+
+/// A chunk of the body of a block.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockChunk {
+	/// Hash of the block.
+	pub hash: Hash,
+	/// Index of the chunk among those of its part.
+	pub index: u32,
+	/// Items of the chunk.
+	pub items: ChunkItems,
+}
+
+impl BlockChunk {
+	/// The chunk of the block body at the index, None past the last chunk of
+	/// the part.
+	pub fn from_block(block: &Block, part: ChunkPart, index: u32) -> Option<BlockChunk> {
+		let size = part.chunk_size();
+		let start = index as usize * size;
+		let range = |len: usize| {
+			if start < len {
+				Some(start..len.min(start + size))
+			} else {
+				None
+			}
+		};
+		let items = match part {
+			ChunkPart::Inputs => {
+				ChunkItems::Inputs(block.inputs()[range(block.inputs().len())?].to_vec())
+			}
+			ChunkPart::Outputs => {
+				ChunkItems::Outputs(block.outputs()[range(block.outputs().len())?].to_vec())
+			}
+			ChunkPart::Kernels => {
+				ChunkItems::Kernels(block.kernels()[range(block.kernels().len())?].to_vec())
+			}
+		};
+		Some(BlockChunk {
+			hash: block.hash(),
+			index,
+			items,
+		})
+	}
+}
+
+impl Writeable for BlockChunk {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.hash.write(writer)?;
+		writer.write_u32(self.index)?;
+		self.items.write(writer)
+	}
+}
+
+impl Readable for BlockChunk {
+	fn read(reader: &mut dyn Reader) -> Result<BlockChunk, ser::Error> {
+		let hash = Hash::read(reader)?;
+		let index = reader.read_u32()?;
+		let items = ChunkItems::read(reader)?;
+		Ok(BlockChunk { hash, index, items })
+	}
+}
+
+/// Number of items in each part of a block body and the hash of each of its
+/// chunks, the inputs chunks first, then the outputs and kernels ones.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockManifest {
+	/// Hash of the block.
+	pub hash: Hash,
+	/// Number of inputs.
+	pub inputs: u32,
+	/// Number of outputs.
+	pub outputs: u32,
+	/// Number of kernels.
+	pub kernels: u32,
+	/// Hashes of the chunks.
+	pub chunk_hashes: Vec<Hash>,
+}
+
+impl BlockManifest {
+	/// The manifest of the chunks of the block.
+	pub fn from_block(block: &Block) -> BlockManifest {
+		let mut chunk_hashes = vec![];
+		for part in ChunkPart::ALL.iter() {
+			let mut index = 0;
+			while let Some(chunk) = BlockChunk::from_block(block, *part, index) {
+				chunk_hashes.push(chunk.items.hash());
+				index += 1;
+			}
+		}
+		BlockManifest {
+			hash: block.hash(),
+			inputs: block.inputs().len() as u32,
+			outputs: block.outputs().len() as u32,
+			kernels: block.kernels().len() as u32,
+			chunk_hashes,
+		}
+	}
+
+	/// Number of items of the part.
+	pub fn items(&self, part: ChunkPart) -> usize {
+		match part {
+			ChunkPart::Inputs => self.inputs as usize,
+			ChunkPart::Outputs => self.outputs as usize,
+			ChunkPart::Kernels => self.kernels as usize,
+		}
+	}
+
+	/// Number of chunks of the part.
+	pub fn chunks(&self, part: ChunkPart) -> u32 {
+		part.chunks(self.items(part)) as u32
+	}
+
+	/// Hash of the chunk of the part at the index.
+	pub fn chunk_hash(&self, part: ChunkPart, index: u32) -> Option<Hash> {
+		if index >= self.chunks(part) {
+			return None;
+		}
+		self.chunk_hashes
+			.get(self.offset(part) + index as usize)
+			.cloned()
+	}
+
+	/// Number of items in the chunk of the part at the index, all chunks but
+	/// the last one of a part being full.
+	pub fn chunk_len(&self, part: ChunkPart, index: u32) -> usize {
+		let start = index as usize * part.chunk_size();
+		self.items(part)
+			.saturating_sub(start)
+			.min(part.chunk_size())
+	}
+
+	// Position of the first chunk of the part in the list of hashes.
+	fn offset(&self, part: ChunkPart) -> usize {
+		ChunkPart::ALL
+			.iter()
+			.take_while(|p| **p != part)
+			.map(|p| self.chunks(*p) as usize)
+			.sum()
+	}
+}
+
+impl Writeable for BlockManifest {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.hash.write(writer)?;
+		writer.write_u32(self.inputs)?;
+		writer.write_u32(self.outputs)?;
+		writer.write_u32(self.kernels)?;
+		for hash in &self.chunk_hashes {
+			hash.write(writer)?;
+		}
+		Ok(())
+	}
+}
+
+impl Readable for BlockManifest {
+	fn read(reader: &mut dyn Reader) -> Result<BlockManifest, ser::Error> {
+		let hash = Hash::read(reader)?;
+		let mut manifest = BlockManifest {
+			hash,
+			inputs: reader.read_u32()?,
+			outputs: reader.read_u32()?,
+			kernels: reader.read_u32()?,
+			chunk_hashes: vec![],
+		};
+		// The number of hashes follows from the number of items, bounded by
+		// what a block within the weight limit holds.
+		let mut count = 0;
+		for part in ChunkPart::ALL.iter() {
+			if manifest.items(*part) > part.max_items() {
+				return Err(ser::Error::TooLargeReadErr);
+			}
+			count += manifest.chunks(*part) as usize;
+		}
+		for _ in 0..count {
+			manifest.chunk_hashes.push(Hash::read(reader)?);
+		}
+		Ok(manifest)
+	}
+}
+
+/// Body of a block being assembled from its chunks, as they are received.
+#[derive(Debug, PartialEq)]
+pub struct ChunkedBody {
+	manifest: BlockManifest,
+	chunks: Vec<Option<ChunkItems>>,
+}
+
+impl ChunkedBody {
+	/// Start the assembly of the body listed by the manifest, no chunk
+	/// received yet.
+	pub fn new(manifest: BlockManifest) -> ChunkedBody {
+		let chunks = vec![None; manifest.chunk_hashes.len()];
+		ChunkedBody { manifest, chunks }
+	}
+
+	/// The manifest of the body.
+	pub fn manifest(&self) -> &BlockManifest {
+		&self.manifest
+	}
+
+	/// Add a chunk of the body, checked against the manifest. Returns whether
+	/// the chunk was still missing.
+	pub fn add(&mut self, chunk: BlockChunk) -> Result<bool, Error> {
+		let part = chunk.items.part();
+		let expected = match self.manifest.chunk_hash(part, chunk.index) {
+			Some(hash) if chunk.hash == self.manifest.hash => hash,
+			_ => return Err(Error::InvalidChunk),
+		};
+		if chunk.items.len() != self.manifest.chunk_len(part, chunk.index)
+			|| chunk.items.hash() != expected
+		{
+			return Err(Error::InvalidChunk);
+		}
+		let slot = &mut self.chunks[self.manifest.offset(part) + chunk.index as usize];
+		if slot.is_some() {
+			return Ok(false);
+		}
+		*slot = Some(chunk.items);
+		Ok(true)
+	}
+
+	/// The chunks not received yet.
+	pub fn missing(&self) -> Vec<(ChunkPart, u32)> {
+		let mut missing = vec![];
+		for part in ChunkPart::ALL.iter() {
+			let offset = self.manifest.offset(*part);
+			for index in 0..self.manifest.chunks(*part) {
+				if self.chunks[offset + index as usize].is_none() {
+					missing.push((*part, index));
+				}
+			}
+		}
+		missing
+	}
+
+	/// Whether all the chunks were received.
+	pub fn is_complete(&self) -> bool {
+		self.chunks.iter().all(|chunk| chunk.is_some())
+	}
+
+	/// The block of the header, its body assembled from the chunks. The
+	/// block still has to be validated.
+	pub fn into_block(self, header: BlockHeader) -> Result<Block, Error> {
+		if header.hash() != self.manifest.hash || !self.is_complete() {
+			return Err(Error::InvalidChunk);
+		}
+		let mut inputs = Vec::with_capacity(self.manifest.inputs as usize);
+		let mut outputs = Vec::with_capacity(self.manifest.outputs as usize);
+		let mut kernels = Vec::with_capacity(self.manifest.kernels as usize);
+		for items in self.chunks.into_iter().flatten() {
+			match items {
+				ChunkItems::Inputs(items) => inputs.extend(items),
+				ChunkItems::Outputs(items) => outputs.extend(items),
+				ChunkItems::Kernels(items) => kernels.extend(items),
+			}
+		}
+		let body = TransactionBody::init(inputs, outputs, kernels, true)?;
+		Ok(Block::with_body(header, body))
+	}
+}
@@ -0,0 +1,243 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Compact filters of the commitments created and spent by a block, Golomb
+//! coded sets along the lines of BIP158. Wallets test the filter of every
+//! block for their commitments and only fetch the blocks that match.
+
+use crate::core::block::Block;
+use crate::core::hash::{Hash, Hashed};
+use crate::ser::{self, Readable, Reader, Writeable, Writer};
+use blake2::blake2b::blake2b;
+use std::convert::TryInto;
+
+/// Bits of the remainder of each Golomb-Rice coded value.
+pub const FILTER_P: u8 = 19;
+
+/// Inverse of the false positive rate of a filter.
+pub const FILTER_M: u64 = 784_931;
+
+// Most bytes the reader hands out in a single read.
+const MAX_READ_CHUNK: usize = 100_000;
+
+/// Compact filter of the output and input commitments of a block, keyed by
+/// the block hash. Testing a commitment never misses one in the filter but
+/// can match one that isn't, about once in `FILTER_M` tests.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockFilter {
+	/// Number of distinct commitments in the filter.
+	pub n: u32,
+	/// Golomb-Rice coded deltas between the sorted hashed commitments.
+	pub data: Vec<u8>,
+}
+
+impl BlockFilter {
+	/// Filter of the commitments of the outputs and inputs of the block.
+	pub fn from_block(block: &Block) -> BlockFilter {
+		let outputs = block.outputs().iter().map(|out| &out.commit.0[..]);
+		let inputs = block.inputs().iter().map(|input| &input.commit.0[..]);
+		BlockFilter::new(&block.hash(), outputs.chain(inputs))
+	}
+
+	/// Filter of the provided items, keyed by the hash of their block.
+	pub fn new<'a, I>(key: &Hash, items: I) -> BlockFilter
+	where
+		I: IntoIterator<Item = &'a [u8]>,
+	{
+		let mut items: Vec<_> = items.into_iter().collect();
+		items.sort();
+		items.dedup();
+
+		let range = items.len() as u64 * FILTER_M;
+		let mut values: Vec<u64> = items
+			.iter()
+			.map(|item| hash_to_range(key, item, range))
+			.collect();
+		values.sort_unstable();
+
+		let mut writer = BitWriter::default();
+		let mut last = 0;
+		for value in values {
+			writer.write_golomb(value - last);
+			last = value;
+		}
+		BlockFilter {
+			n: items.len() as u32,
+			data: writer.bytes,
+		}
+	}
+
+	/// Whether the item may be in the filter.
+	pub fn matches(&self, key: &Hash, item: &[u8]) -> bool {
+		self.matches_any(key, &[item])
+	}
+
+	/// Whether any of the items may be in the filter, decoding it only once.
+	pub fn matches_any(&self, key: &Hash, items: &[&[u8]]) -> bool {
+		let range = self.n as u64 * FILTER_M;
+		let mut targets: Vec<u64> = items
+			.iter()
+			.map(|item| hash_to_range(key, item, range))
+			.collect();
+		targets.sort_unstable();
+
+		let mut targets = targets.into_iter().peekable();
+		let mut reader = BitReader::new(&self.data);
+		let mut value = 0;
+		for _ in 0..self.n {
+			value += match reader.read_golomb() {
+				Some(delta) => delta,
+				None => return false,
+			};
+			while let Some(&target) = targets.peek() {
+				if target > value {
+					break;
+				}
+				if target == value {
+					return true;
+				}
+				targets.next();
+			}
+			if targets.peek().is_none() {
+				break;
+			}
+		}
+		false
+	}
+}
+
+impl Writeable for BlockFilter {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_u32(self.n)?;
+		writer.write_bytes(&self.data)
+	}
+}
+
+impl Readable for BlockFilter {
+	fn read(reader: &mut dyn Reader) -> Result<BlockFilter, ser::Error> {
+		let n = reader.read_u32()?;
+		let len = reader.read_u64()? as usize;
+
+		// The quotients sum to at most 1.5 bits per value, each value takes
+		// less than 3 bytes.
+		if len > n as usize * 3 + 1 {
+			return Err(ser::Error::TooLargeReadErr);
+		}
+		let mut data = Vec::with_capacity(len);
+		while data.len() < len {
+			let chunk = (len - data.len()).min(MAX_READ_CHUNK);
+			data.extend(reader.read_fixed_bytes(chunk)?);
+		}
+		Ok(BlockFilter { n, data })
+	}
+}
+
+// Hash the item uniformly into [0, range).
+fn hash_to_range(key: &Hash, item: &[u8], range: u64) -> u64 {
+	let hash = blake2b(8, &key.as_bytes()[..16], item);
+	let hash = u64::from_be_bytes(hash.as_bytes().try_into().unwrap());
+	((hash as u128 * range as u128) >> 64) as u64
+}
+
+#[derive(Default)]
+struct BitWriter {
+	bytes: Vec<u8>,
+	// Bits used in the last byte, 0 when full.
+	used: u8,
+}
+
+impl BitWriter {
+	fn write_bit(&mut self, bit: bool) {
+		if self.used == 0 {
+			self.bytes.push(0);
+		}
+		if bit {
+			*self.bytes.last_mut().unwrap() |= 0x80 >> self.used;
+		}
+		self.used = (self.used + 1) % 8;
+	}
+
+	// Quotient in unary, then the remainder on FILTER_P bits.
+	fn write_golomb(&mut self, value: u64) {
+		for _ in 0..(value >> FILTER_P) {
+			self.write_bit(true);
+		}
+		self.write_bit(false);
+		for i in (0..FILTER_P).rev() {
+			self.write_bit((value >> i) & 1 == 1);
+		}
+	}
+}
+
+struct BitReader<'a> {
+	bytes: &'a [u8],
+	pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+	fn new(bytes: &'a [u8]) -> BitReader<'a> {
+		BitReader { bytes, pos: 0 }
+	}
+
+	fn read_bit(&mut self) -> Option<bool> {
+		let byte = self.bytes.get(self.pos / 8)?;
+		let bit = byte & (0x80 >> (self.pos % 8)) != 0;
+		self.pos += 1;
+		Some(bit)
+	}
+
+	fn read_golomb(&mut self) -> Option<u64> {
+		let mut value = 0;
+		while self.read_bit()? {
+			value += 1;
+		}
+		for _ in 0..FILTER_P {
+			value = (value << 1) | self.read_bit()? as u64;
+		}
+		Some(value)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::ser::{deserialize_default, ser_vec};
+
+	#[test]
+	fn block_filter_matches() {
+		let key = Hash::from_vec(&[7; 32]);
+		let items: Vec<Vec<u8>> = (0..200u32).map(|i| i.to_be_bytes().to_vec()).collect();
+		let filter = BlockFilter::new(&key, items.iter().map(|i| &i[..]));
+		assert_eq!(filter.n, 200);
+
+		for item in &items {
+			assert!(filter.matches(&key, item));
+		}
+		assert!(!filter.matches(&key, b"not in there"));
+		assert!(filter.matches_any(&key, &[&b"not in there"[..], &items[42][..]]));
+		assert!(!filter.matches_any(&key, &[]));
+
+		// Duplicates count once.
+		let dups = BlockFilter::new(&key, vec![&b"a"[..], &b"a"[..], &b"b"[..]]);
+		assert_eq!(dups.n, 2);
+
+		let empty = BlockFilter::new(&key, vec![]);
+		assert_eq!(empty.n, 0);
+		assert!(!empty.matches(&key, &items[0]));
+
+		let vec = ser_vec(&filter, ser::ProtocolVersion::local()).unwrap();
+		let filter2: BlockFilter = deserialize_default(&mut &vec[..]).unwrap();
+		assert_eq!(filter, filter2);
+	}
+}
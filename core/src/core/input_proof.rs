@@ -0,0 +1,182 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Proofs of the ability to spend the inputs of a transaction, optionally
+//! asked by the nodes relaying transactions with many inputs. Building one
+//! takes the blinding factor and value of the input, so that floods of
+//! crafted transactions spending outputs of others can't be relayed.
+
+use crate::core::transaction::{Error, Transaction};
+use crate::libtx::secp_ser;
+use crate::ser::{self, Readable, Reader, Writeable, Writer};
+use blake2::blake2b::blake2b;
+use keychain::BlindingFactor;
+use rand::thread_rng;
+use util::secp::key::{PublicKey, SecretKey};
+use util::secp::pedersen::Commitment;
+use util::secp::Secp256k1;
+use util::static_secp_instance;
+
+/// Proof of knowledge of the blinding factor and value of an input
+/// commitment, bound to the kernel excess of the transaction spending it. A
+/// Schnorr proof over the two generators of the commitment: the nonce
+/// commits to two random scalars, each response is the scalar plus the
+/// challenge times the blinding factor or the value.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct InputProof {
+	/// Commitment of the input spent.
+	#[serde(
+		serialize_with = "secp_ser::as_hex",
+		deserialize_with = "secp_ser::commitment_from_hex"
+	)]
+	pub commit: Commitment,
+	/// Kernel excess of the transaction the proof is for.
+	#[serde(
+		serialize_with = "secp_ser::as_hex",
+		deserialize_with = "secp_ser::commitment_from_hex"
+	)]
+	pub excess: Commitment,
+	/// Commitment to the random scalars of the proof.
+	#[serde(with = "secp_ser::pubkey_serde")]
+	pub nonce: PublicKey,
+	/// Response of the proof for the blinding factor.
+	#[serde(
+		serialize_with = "secp_ser::as_hex",
+		deserialize_with = "secp_ser::blind_from_hex"
+	)]
+	pub s_blind: BlindingFactor,
+	/// Response of the proof for the value.
+	#[serde(
+		serialize_with = "secp_ser::as_hex",
+		deserialize_with = "secp_ser::blind_from_hex"
+	)]
+	pub s_value: BlindingFactor,
+}
+
+impl InputProof {
+	/// Proves the input `commit` of the given value and blinding factor is
+	/// spent by the transaction with the kernel `excess`.
+	pub fn create(
+		commit: Commitment,
+		value: u64,
+		blind: &BlindingFactor,
+		excess: Commitment,
+	) -> Result<InputProof, Error> {
+		let secp = static_secp_instance();
+		let secp = secp.lock();
+
+		let k_blind = SecretKey::new(&secp, &mut thread_rng());
+		let k_value = SecretKey::new(&secp, &mut thread_rng());
+		let nonce = point(&secp, &k_blind, &k_value)?;
+		let e = challenge(&secp, &nonce, &commit, &excess)?;
+
+		let mut s_blind = blind.secret_key(&secp)?;
+		s_blind.mul_assign(&secp, &e)?;
+		s_blind.add_assign(&secp, &k_blind)?;
+		let mut s_value = value_key(&secp, value)?;
+		s_value.mul_assign(&secp, &e)?;
+		s_value.add_assign(&secp, &k_value)?;
+
+		Ok(InputProof {
+			commit,
+			excess,
+			nonce,
+			s_blind: BlindingFactor::from_secret_key(s_blind),
+			s_value: BlindingFactor::from_secret_key(s_value),
+		})
+	}
+
+	/// Checks the proof, that whoever built it knew the opening of the
+	/// input commitment.
+	pub fn verify(&self) -> Result<(), Error> {
+		let secp = static_secp_instance();
+		let secp = secp.lock();
+
+		let e = challenge(&secp, &self.nonce, &self.commit, &self.excess)?;
+		let s_blind = self.s_blind.secret_key(&secp)?;
+		let s_value = self.s_value.secret_key(&secp)?;
+		let mut committed = self.commit.to_pubkey(&secp)?;
+		committed.mul_assign(&secp, &e)?;
+		let expected = PublicKey::from_combination(&secp, vec![&self.nonce, &committed])?;
+		if point(&secp, &s_blind, &s_value)? != expected {
+			return Err(Error::InvalidInputProof);
+		}
+		Ok(())
+	}
+
+	/// Whether the proof is for one of the inputs of the transaction, and
+	/// bound to one of its kernels.
+	pub fn is_for(&self, tx: &Transaction) -> bool {
+		tx.inputs().iter().any(|input| input.commit == self.commit)
+			&& tx
+				.kernels()
+				.iter()
+				.any(|kernel| kernel.excess == self.excess)
+	}
+}
+
+// The blinding factor scalar times G plus the value scalar times H.
+fn point(secp: &Secp256k1, blind: &SecretKey, value: &SecretKey) -> Result<PublicKey, Error> {
+	let blind_point = PublicKey::from_secret_key(secp, blind)?;
+	let mut value_point = secp.commit_value(1)?.to_pubkey(secp)?;
+	value_point.mul_assign(secp, value)?;
+	Ok(PublicKey::from_combination(
+		secp,
+		vec![&blind_point, &value_point],
+	)?)
+}
+
+fn challenge(
+	secp: &Secp256k1,
+	nonce: &PublicKey,
+	commit: &Commitment,
+	excess: &Commitment,
+) -> Result<SecretKey, Error> {
+	let mut msg = nonce.serialize_vec(secp, true).to_vec();
+	msg.extend_from_slice(&commit.0);
+	msg.extend_from_slice(&excess.0);
+	let hash = blake2b(32, &[], &msg);
+	Ok(SecretKey::from_slice(secp, hash.as_bytes())?)
+}
+
+// The value as a scalar, big-endian. Zero isn't a valid secret key, zero
+// value inputs can't be proven.
+fn value_key(secp: &Secp256k1, value: u64) -> Result<SecretKey, Error> {
+	let mut bytes = [0; 32];
+	bytes[24..].copy_from_slice(&value.to_be_bytes());
+	Ok(SecretKey::from_slice(secp, &bytes)?)
+}
+
+impl Writeable for InputProof {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.commit.write(writer)?;
+		self.excess.write(writer)?;
+		self.nonce.write(writer)?;
+		self.s_blind.write(writer)?;
+		self.s_value.write(writer)?;
+		Ok(())
+	}
+}
+
+impl Readable for InputProof {
+	fn read(reader: &mut dyn Reader) -> Result<InputProof, ser::Error> {
+		Ok(InputProof {
+			commit: Commitment::read(reader)?,
+			excess: Commitment::read(reader)?,
+			nonce: PublicKey::read(reader)?,
+			s_blind: BlindingFactor::read(reader)?,
+			s_value: BlindingFactor::read(reader)?,
+		})
+	}
+}
@@ -14,6 +14,7 @@
 
 //! Merkle Proofs
 
+use crate::core::block::BlockHeader;
 use crate::core::hash::Hash;
 use crate::core::pmmr;
 use crate::ser;
@@ -154,3 +155,21 @@ impl MerkleProof {
 		}
 	}
 }
+
+/// Verifies the Merkle proof of the header being included in the header MMR
+/// with the provided root, at the position of its height. The header MMR
+/// under the prev_root of a block holds all the headers before it, so only
+/// that root needs to be known, not the headers of the chain.
+/// Note the header MMR commits to the header hashes only, it is up to the
+/// caller to check the proof of work of the header binds its other fields.
+pub fn verify_header_inclusion(
+	root: Hash,
+	header: &BlockHeader,
+	proof: &MerkleProof,
+) -> Result<(), MerkleProofError> {
+	let pos = pmmr::insertion_to_pmmr_index(header.height + 1);
+	if pos > proof.mmr_size {
+		return Err(MerkleProofError::RootMismatch);
+	}
+	proof.verify(root, header, pos)
+}
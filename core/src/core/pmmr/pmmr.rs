@@ -301,30 +301,7 @@ where
 
 	/// Walks all unpruned nodes in the MMR and revalidate all parent hashes
 	pub fn validate(&self) -> Result<(), String> {
-		// iterate on all parent nodes
-		for n in 1..(self.last_pos + 1) {
-			let height = bintree_postorder_height(n);
-			if height > 0 {
-				if let Some(hash) = self.get_hash(n) {
-					let left_pos = n - (1 << height);
-					let right_pos = n - 1;
-					// using get_from_file here for the children (they may have been "removed")
-					if let Some(left_child_hs) = self.get_from_file(left_pos) {
-						if let Some(right_child_hs) = self.get_from_file(right_pos) {
-							// hash the two child nodes together with parent_pos and compare
-							if (left_child_hs, right_child_hs).hash_with_index(n - 1) != hash {
-								return Err(format!(
-									"Invalid MMR, hash of parent at {} does \
-									 not match children.",
-									n
-								));
-							}
-						}
-					}
-				}
-			}
-		}
-		Ok(())
+		self.readonly_pmmr().validate()
 	}
 
 	/// Is the MMR empty?
@@ -0,0 +1,468 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Segments of a PMMR, a range of its leaves along with a proof of their
+//! place in the MMR. Each segment can be checked against the MMR root on its
+//! own, so the state of the chain can be downloaded from many peers at once.
+//!
+//! In a pruned MMR, the subtrees whose leaves are all spent are only sent as
+//! the hash of their root. The segment is then checked against the bitmap of
+//! the unspent leaves, both the sender and the receiver derive the nodes a
+//! segment is made of from it.
+
+use croaring::Bitmap;
+
+use crate::core::hash::Hash;
+use crate::core::pmmr::{
+	bintree_leftmost, bintree_postorder_height, family, family_branch, insertion_to_pmmr_index,
+	is_left_sibling, n_leaves, peaks, Backend, ReadonlyPMMR,
+};
+use crate::ser::{self, PMMRIndexHashable, PMMRable, Readable, Reader, Writeable, Writer};
+
+/// Highest segment supported, in a segment of height h there are up to 2^h
+/// leaves.
+pub const MAX_SEGMENT_HEIGHT: u8 = 32;
+
+/// Most hashes in a segment proof, the path to a peak and the other peaks.
+const MAX_PROOF_HASHES: u64 = 128;
+
+/// Segment errors.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SegmentError {
+	/// The segment starts past the last leaf of the MMR.
+	OutOfRange,
+	/// The hash of a node isn't available to build the segment.
+	MissingHash(u64),
+	/// The data of a leaf isn't available to build the segment.
+	MissingLeaf(u64),
+	/// The segment doesn't have the hashes and leaves expected.
+	NodeMismatch,
+	/// The segment proof doesn't have the hashes expected.
+	ProofMismatch,
+	/// The segment doesn't match the MMR root.
+	RootMismatch,
+}
+
+/// The leaves idx * 2^height to (idx + 1) * 2^height - 1 of an MMR, the last
+/// segment having fewer leaves when the MMR doesn't end on a segment boundary.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SegmentIdentifier {
+	/// Height of the segment
+	pub height: u8,
+	/// Index of the segment among those of its height
+	pub idx: u64,
+}
+
+impl SegmentIdentifier {
+	/// Number of segments of the given height covering the leaves of an MMR
+	/// of the given size.
+	pub fn count(mmr_size: u64, height: u8) -> u64 {
+		let leaves = 1u64 << height;
+		(n_leaves(mmr_size) + leaves - 1) / leaves
+	}
+
+	/// All the segments of the given height covering the leaves of an MMR of
+	/// the given size.
+	pub fn all(mmr_size: u64, height: u8) -> impl Iterator<Item = SegmentIdentifier> {
+		(0..SegmentIdentifier::count(mmr_size, height))
+			.map(move |idx| SegmentIdentifier { height, idx })
+	}
+
+	/// Position of the first leaf of the segment.
+	pub fn first_pos(&self) -> u64 {
+		insertion_to_pmmr_index((self.idx << self.height) + 1)
+	}
+
+	/// Positions of the roots of the subtrees the segment spans in an MMR of
+	/// the given size. A single root unless this is the last segment and the
+	/// MMR doesn't end on a segment boundary, its roots being the last peaks
+	/// of the MMR then.
+	pub fn roots(&self, mmr_size: u64) -> Result<Vec<u64>, SegmentError> {
+		if self.height > MAX_SEGMENT_HEIGHT {
+			return Err(SegmentError::OutOfRange);
+		}
+		let leaves = 1u64 << self.height;
+		let first = self
+			.idx
+			.checked_mul(leaves)
+			.ok_or(SegmentError::OutOfRange)?;
+		let n_leaves = n_leaves(mmr_size);
+		if first >= n_leaves || peaks(mmr_size).is_empty() {
+			return Err(SegmentError::OutOfRange);
+		}
+		let first_pos = self.first_pos();
+		if first + leaves <= n_leaves {
+			Ok(vec![first_pos + 2 * leaves - 2])
+		} else {
+			Ok(peaks(mmr_size)
+				.into_iter()
+				.filter(|&p| p >= first_pos)
+				.collect())
+		}
+	}
+}
+
+impl Writeable for SegmentIdentifier {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_u8(self.height)?;
+		writer.write_u64(self.idx)
+	}
+}
+
+impl Readable for SegmentIdentifier {
+	fn read(reader: &mut dyn Reader) -> Result<SegmentIdentifier, ser::Error> {
+		let height = reader.read_u8()?;
+		if height > MAX_SEGMENT_HEIGHT {
+			return Err(ser::Error::CorruptedData);
+		}
+		let idx = reader.read_u64()?;
+		Ok(SegmentIdentifier { height, idx })
+	}
+}
+
+// A node of a segment, in position order.
+#[derive(Debug, PartialEq)]
+enum SegmentNode {
+	// A leaf, sent with its data.
+	Leaf(u64),
+	// The root of a subtree whose leaves are all spent, sent as its hash.
+	Pruned(u64),
+}
+
+// Whether all the leaves under the node are spent.
+fn fully_spent(unspent: &Bitmap, pos: u64) -> bool {
+	let first = bintree_leftmost(pos);
+	let before = if first > 1 {
+		unspent.rank(first as u32 - 1)
+	} else {
+		0
+	};
+	unspent.rank(pos as u32) == before
+}
+
+fn push_nodes(pos: u64, unspent: Option<&Bitmap>, nodes: &mut Vec<SegmentNode>) {
+	let height = bintree_postorder_height(pos);
+	if height == 0 {
+		nodes.push(SegmentNode::Leaf(pos));
+	} else if unspent.map(|b| fully_spent(b, pos)).unwrap_or(false) {
+		nodes.push(SegmentNode::Pruned(pos));
+	} else {
+		push_nodes(pos - (1 << height), unspent, nodes);
+		push_nodes(pos - 1, unspent, nodes);
+	}
+}
+
+// The nodes a segment is made of. Every subtree whose leaves are all spent is
+// replaced by its root, the highest such root when the segment itself is all
+// spent, which can be above the segment. A spent leaf is still sent when its
+// sibling isn't spent. Without a bitmap nothing is pruned.
+fn segment_nodes(
+	id: SegmentIdentifier,
+	mmr_size: u64,
+	unspent: Option<&Bitmap>,
+) -> Result<Vec<SegmentNode>, SegmentError> {
+	let roots = id.roots(mmr_size)?;
+	if let (Some(bitmap), [root]) = (unspent, roots.as_slice()) {
+		let mut top = *root;
+		while fully_spent(bitmap, top) {
+			let (parent, _) = family(top);
+			if parent > mmr_size || !fully_spent(bitmap, parent) {
+				break;
+			}
+			top = parent;
+		}
+		if top != *root {
+			return Ok(vec![SegmentNode::Pruned(top)]);
+		}
+	}
+	let mut nodes = vec![];
+	for root in roots {
+		push_nodes(root, unspent, &mut nodes);
+	}
+	Ok(nodes)
+}
+
+/// The hashes proving the place of a segment in an MMR: the siblings from
+/// the segment root up to its peak, the other peaks to the right bagged,
+/// then the peaks to the left from the closest.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SegmentProof {
+	/// Hashes of the proof
+	pub hashes: Vec<Hash>,
+}
+
+impl Writeable for SegmentProof {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_u64(self.hashes.len() as u64)?;
+		self.hashes.write(writer)
+	}
+}
+
+impl Readable for SegmentProof {
+	fn read(reader: &mut dyn Reader) -> Result<SegmentProof, ser::Error> {
+		let len = reader.read_u64()?;
+		if len > MAX_PROOF_HASHES {
+			return Err(ser::Error::TooLargeReadErr);
+		}
+		let mut hashes = Vec::with_capacity(len as usize);
+		for _ in 0..len {
+			hashes.push(Hash::read(reader)?);
+		}
+		Ok(SegmentProof { hashes })
+	}
+}
+
+/// A segment of an MMR: the data of its leaves, the hashes of its pruned
+/// subtrees and the proof of its place in the MMR.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Segment<T> {
+	/// Identifier of the segment
+	pub identifier: SegmentIdentifier,
+	/// Positions and hashes of the roots of the pruned subtrees
+	pub hashes: Vec<(u64, Hash)>,
+	/// Positions and data of the leaves
+	pub leaf_data: Vec<(u64, T)>,
+	/// Proof of the segment against the MMR root
+	pub proof: SegmentProof,
+}
+
+impl<T> Segment<T>
+where
+	T: PMMRIndexHashable,
+{
+	/// Build the segment from the MMR, the leaves not in the unspent bitmap
+	/// (if any) being pruned.
+	pub fn from_pmmr<U, B>(
+		identifier: SegmentIdentifier,
+		pmmr: &ReadonlyPMMR<'_, U, B>,
+		unspent: Option<&Bitmap>,
+	) -> Result<Segment<T>, SegmentError>
+	where
+		U: PMMRable<E = T>,
+		B: Backend<U>,
+	{
+		let mmr_size = pmmr.unpruned_size();
+		let hash = |pos| {
+			pmmr.get_from_file(pos)
+				.ok_or(SegmentError::MissingHash(pos))
+		};
+
+		let mut hashes = vec![];
+		let mut leaf_data = vec![];
+		for node in segment_nodes(identifier, mmr_size, unspent)? {
+			match node {
+				SegmentNode::Leaf(pos) => {
+					let data = pmmr
+						.get_data_from_file(pos)
+						.ok_or(SegmentError::MissingLeaf(pos))?;
+					leaf_data.push((pos, data));
+				}
+				SegmentNode::Pruned(pos) => hashes.push((pos, hash(pos)?)),
+			}
+		}
+		let mut proof = vec![];
+		let all_roots = identifier.roots(mmr_size)?;
+		let top = match hashes.as_slice() {
+			[(pos, _)] if leaf_data.is_empty() && *pos > all_roots[0] => *pos,
+			_ => all_roots[0],
+		};
+
+		// Up to the peak for a single subtree, the last segment spanning
+		// several peaks starts from the first of them.
+		let (peak, covered) = if all_roots.len() == 1 {
+			let mut peak = top;
+			for (parent, sibling) in family_branch(top, mmr_size) {
+				proof.push(hash(sibling)?);
+				peak = parent;
+			}
+			(peak, 1)
+		} else {
+			(all_roots[0], all_roots.len())
+		};
+		let peaks = peaks(mmr_size);
+		let idx = peaks
+			.iter()
+			.position(|&p| p == peak)
+			.ok_or(SegmentError::ProofMismatch)?;
+		let mut rhs = None;
+		for &p in peaks[idx + covered..].iter().rev() {
+			rhs = Some(match rhs {
+				None => hash(p)?,
+				Some(rhs) => (hash(p)?, rhs).hash_with_index(mmr_size),
+			});
+		}
+		if let Some(rhs) = rhs {
+			proof.push(rhs);
+		}
+		for &p in peaks[..idx].iter().rev() {
+			proof.push(hash(p)?);
+		}
+
+		Ok(Segment {
+			identifier,
+			hashes,
+			leaf_data,
+			proof: SegmentProof { hashes: proof },
+		})
+	}
+
+	/// The root of the MMR of the given size the segment proves to be part
+	/// of, given the unspent bitmap (if pruned). To be compared with the root
+	/// expected.
+	pub fn root(&self, mmr_size: u64, unspent: Option<&Bitmap>) -> Result<Hash, SegmentError> {
+		// Hashes of the segment roots, bottom up.
+		let mut stack: Vec<(u64, Hash)> = vec![];
+		let mut hashes = self.hashes.iter();
+		let mut leaves = self.leaf_data.iter();
+		for node in segment_nodes(self.identifier, mmr_size, unspent)? {
+			let (pos, hash) = match node {
+				SegmentNode::Leaf(pos) => match leaves.next() {
+					Some((p, data)) if *p == pos => (pos, data.hash_with_index(pos - 1)),
+					_ => return Err(SegmentError::NodeMismatch),
+				},
+				SegmentNode::Pruned(pos) => match hashes.next() {
+					Some((p, hash)) if *p == pos => (pos, *hash),
+					_ => return Err(SegmentError::NodeMismatch),
+				},
+			};
+			let (mut pos, mut hash) = (pos, hash);
+			while !is_left_sibling(pos) {
+				let (parent, sibling) = family(pos);
+				match stack.last() {
+					Some((p, _)) if *p == sibling => {
+						let (_, left) = stack.pop().expect("last");
+						hash = (left, hash).hash_with_index(parent - 1);
+						pos = parent;
+					}
+					_ => break,
+				}
+			}
+			stack.push((pos, hash));
+		}
+		if hashes.next().is_some() || leaves.next().is_some() {
+			return Err(SegmentError::NodeMismatch);
+		}
+
+		let mut proof = self.proof.hashes.iter();
+		let (mut hash, peak, covered) = if stack.len() == 1 {
+			let (mut pos, mut hash) = stack[0];
+			for (parent, _) in family_branch(pos, mmr_size) {
+				let sibling = proof.next().ok_or(SegmentError::ProofMismatch)?;
+				hash = if is_left_sibling(pos) {
+					(hash, *sibling).hash_with_index(parent - 1)
+				} else {
+					(*sibling, hash).hash_with_index(parent - 1)
+				};
+				pos = parent;
+			}
+			(hash, pos, 1)
+		} else {
+			let mut bagged = None;
+			for (_, h) in stack.iter().rev() {
+				bagged = Some(match bagged {
+					None => *h,
+					Some(rhs) => (*h, rhs).hash_with_index(mmr_size),
+				});
+			}
+			let hash = bagged.ok_or(SegmentError::NodeMismatch)?;
+			(hash, stack[0].0, stack.len())
+		};
+
+		let peaks = peaks(mmr_size);
+		let idx = peaks
+			.iter()
+			.position(|&p| p == peak)
+			.ok_or(SegmentError::NodeMismatch)?;
+		if covered > 1 && peaks.len() - idx != covered {
+			return Err(SegmentError::NodeMismatch);
+		}
+		if idx + covered < peaks.len() {
+			let rhs = proof.next().ok_or(SegmentError::ProofMismatch)?;
+			hash = (hash, *rhs).hash_with_index(mmr_size);
+		}
+		for _ in 0..idx {
+			let lhs = proof.next().ok_or(SegmentError::ProofMismatch)?;
+			hash = (*lhs, hash).hash_with_index(mmr_size);
+		}
+		if proof.next().is_some() {
+			return Err(SegmentError::ProofMismatch);
+		}
+		Ok(hash)
+	}
+
+	/// Check the segment against the root of the MMR of the given size.
+	pub fn validate(
+		&self,
+		mmr_size: u64,
+		unspent: Option<&Bitmap>,
+		root: Hash,
+	) -> Result<(), SegmentError> {
+		if self.root(mmr_size, unspent)? == root {
+			Ok(())
+		} else {
+			Err(SegmentError::RootMismatch)
+		}
+	}
+}
+
+impl<T: Writeable> Writeable for Segment<T> {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.identifier.write(writer)?;
+		writer.write_u64(self.hashes.len() as u64)?;
+		for (pos, hash) in &self.hashes {
+			writer.write_u64(*pos)?;
+			hash.write(writer)?;
+		}
+		writer.write_u64(self.leaf_data.len() as u64)?;
+		for (pos, data) in &self.leaf_data {
+			writer.write_u64(*pos)?;
+			data.write(writer)?;
+		}
+		self.proof.write(writer)
+	}
+}
+
+impl<T: Readable> Readable for Segment<T> {
+	fn read(reader: &mut dyn Reader) -> Result<Segment<T>, ser::Error> {
+		let identifier = SegmentIdentifier::read(reader)?;
+		// A segment can't have more nodes than leaves.
+		let max_nodes = 1u64 << identifier.height;
+
+		let len = reader.read_u64()?;
+		if len > max_nodes {
+			return Err(ser::Error::TooLargeReadErr);
+		}
+		let mut hashes = vec![];
+		for _ in 0..len {
+			hashes.push((reader.read_u64()?, Hash::read(reader)?));
+		}
+
+		let len = reader.read_u64()?;
+		if len > max_nodes {
+			return Err(ser::Error::TooLargeReadErr);
+		}
+		let mut leaf_data = vec![];
+		for _ in 0..len {
+			leaf_data.push((reader.read_u64()?, T::read(reader)?));
+		}
+
+		let proof = SegmentProof::read(reader)?;
+		Ok(Segment {
+			identifier,
+			hashes,
+			leaf_data,
+			proof,
+		})
+	}
+}
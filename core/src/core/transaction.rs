@@ -25,6 +25,8 @@ use crate::ser::{
 use crate::{consensus, global};
 use enum_primitive::FromPrimitive;
 use keychain::{self, BlindingFactor};
+use rayon::prelude::*;
+use std::cell::Cell;
 use std::cmp::Ordering;
 use std::cmp::{max, min};
 use std::convert::TryInto;
@@ -33,8 +35,105 @@ use std::{error, fmt};
 use util;
 use util::secp;
 use util::secp::pedersen::{Commitment, RangeProof};
-use util::static_secp_instance;
 use util::RwLock;
+use util::{static_secp_instance, with_thread_secp};
+
+/// Fewest rangeproofs or kernel signatures batch verified by a thread when
+/// verifying in parallel. Smaller batches don't make up for the overhead.
+pub const PARALLEL_VERIFY_MIN_BATCH: usize = 16;
+
+// Size of the batches the items are verified in, spread across the threads
+// of the pool. All in a single batch if too few for more than one.
+fn parallel_batch_size(len: usize) -> usize {
+	let threads = rayon::current_num_threads().max(1);
+	let size = (len + threads - 1) / threads;
+	size.max(PARALLEL_VERIFY_MIN_BATCH)
+}
+
+/// Sizes the pool of threads rangeproofs and kernel signatures are verified
+/// on in parallel, one per cpu if 0. Each of them runs `on_start` first.
+/// Only takes effect if called before any parallel verification, and once.
+pub fn init_verification_threads<F>(threads: usize, on_start: F) -> Result<(), String>
+where
+	F: Fn(usize) + Send + Sync + 'static,
+{
+	rayon::ThreadPoolBuilder::new()
+		.num_threads(threads)
+		.thread_name(|i| format!("verifier-{}", i))
+		.start_handler(on_start)
+		.build_global()
+		.map_err(|e| e.to_string())
+}
+
+lazy_static! {
+	// Threads verifying for the work done in the background, if started.
+	static ref BACKGROUND_POOL: RwLock<Option<Arc<rayon::ThreadPool>>> = RwLock::new(None);
+}
+
+thread_local! {
+	// Whether the work on this thread verifies on the background threads.
+	static IN_BACKGROUND: Cell<bool> = Cell::new(false);
+}
+
+/// Starts the pool of threads verifying the rangeproofs and kernel
+/// signatures of the work done in the background, apart from the global one
+/// the blocks and txs are verified on, one thread per cpu if 0. Each of them
+/// runs `on_start` first, to lower its priority for instance. Only the first
+/// call starts it.
+pub fn init_background_verification_threads<F>(threads: usize, on_start: F) -> Result<(), String>
+where
+	F: Fn(usize) + Send + Sync + 'static,
+{
+	let mut background = BACKGROUND_POOL.write();
+	if background.is_some() {
+		return Err("background verification threads already started".to_owned());
+	}
+	let pool = rayon::ThreadPoolBuilder::new()
+		.num_threads(threads)
+		.thread_name(|i| format!("bg-verifier-{}", i))
+		.start_handler(on_start)
+		.build()
+		.map_err(|e| e.to_string())?;
+	*background = Some(Arc::new(pool));
+	Ok(())
+}
+
+/// Runs `f`, the rangeproofs and kernel signatures it verifies in parallel
+/// being verified on the background threads if started, on the global ones
+/// otherwise. Only for work nothing else urgently waits on (holding the
+/// txhashset lock for instance), as the background threads may be slowed
+/// down by a lower priority.
+pub fn in_background<F, R>(f: F) -> R
+where
+	F: FnOnce() -> R,
+{
+	// Reset even if `f` panics, the thread may be reused.
+	struct Reset(bool);
+	impl Drop for Reset {
+		fn drop(&mut self) {
+			let prev = self.0;
+			IN_BACKGROUND.with(|b| b.set(prev));
+		}
+	}
+	let _reset = Reset(IN_BACKGROUND.with(|b| b.replace(true)));
+	f()
+}
+
+// Runs the parallel verification on the background threads if the calling
+// work asked for them, on the global ones otherwise.
+fn on_verification_threads<F, R>(f: F) -> R
+where
+	F: FnOnce() -> R + Send,
+	R: Send,
+{
+	if IN_BACKGROUND.with(|b| b.get()) {
+		let pool = BACKGROUND_POOL.read().clone();
+		if let Some(pool) = pool {
+			return pool.install(f);
+		}
+	}
+	f()
+}
 
 /// Various tx kernel variants.
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
@@ -250,6 +349,8 @@ pub enum Error {
 	IncorrectSignature,
 	/// Underlying serialization error.
 	Serialization(ser::Error),
+	/// Proof of the ability to spend an input failing to verify.
+	InvalidInputProof,
 }
 
 impl error::Error for Error {
@@ -439,21 +540,38 @@ impl TxKernel {
 
 	/// Batch signature verification.
 	pub fn batch_sig_verify(tx_kernels: &[TxKernel]) -> Result<(), Error> {
+		let secp = static_secp_instance();
+		let secp = secp.lock();
+		TxKernel::verify_sigs(&secp, tx_kernels)
+	}
+
+	/// Batch signature verification, the kernels split in batches verified
+	/// in parallel on the rayon thread pool.
+	pub fn par_batch_sig_verify(tx_kernels: &[TxKernel]) -> Result<(), Error> {
+		on_verification_threads(|| {
+			let size = parallel_batch_size(tx_kernels.len());
+			if size >= tx_kernels.len() {
+				return TxKernel::batch_sig_verify(tx_kernels);
+			}
+			tx_kernels.par_chunks(size).try_for_each(|kernels| {
+				with_thread_secp(|secp| TxKernel::verify_sigs(secp, kernels))
+			})
+		})
+	}
+
+	fn verify_sigs(secp: &secp::Secp256k1, tx_kernels: &[TxKernel]) -> Result<(), Error> {
 		let len = tx_kernels.len();
 		let mut sigs: Vec<secp::Signature> = Vec::with_capacity(len);
 		let mut pubkeys: Vec<secp::key::PublicKey> = Vec::with_capacity(len);
 		let mut msgs: Vec<secp::Message> = Vec::with_capacity(len);
 
-		let secp = static_secp_instance();
-		let secp = secp.lock();
-
 		for tx_kernel in tx_kernels {
 			sigs.push(tx_kernel.excess_sig);
-			pubkeys.push(tx_kernel.excess.to_pubkey(&secp)?);
+			pubkeys.push(tx_kernel.excess.to_pubkey(secp)?);
 			msgs.push(tx_kernel.msg_to_sign()?);
 		}
 
-		if !secp::aggsig::verify_batch(&secp, &sigs, &msgs, &pubkeys) {
+		if !secp::aggsig::verify_batch(secp, &sigs, &msgs, &pubkeys) {
 			return Err(Error::IncorrectSignature);
 		}
 
@@ -827,35 +945,56 @@ impl TransactionBody {
 		&self,
 		weighting: Weighting,
 		verifier: Arc<RwLock<dyn VerifierCache>>,
+	) -> Result<(), Error> {
+		self.validate_with(weighting, verifier, false)
+	}
+
+	/// Same as validate, with the range proofs and kernel signatures
+	/// verified in batches across the cores. Worth it for the many outputs
+	/// and kernels of a full block.
+	pub fn validate_parallel(
+		&self,
+		weighting: Weighting,
+		verifier: Arc<RwLock<dyn VerifierCache>>,
+	) -> Result<(), Error> {
+		self.validate_with(weighting, verifier, true)
+	}
+
+	fn validate_with(
+		&self,
+		weighting: Weighting,
+		verifier: Arc<RwLock<dyn VerifierCache>>,
+		parallel: bool,
 	) -> Result<(), Error> {
 		self.validate_read(weighting)?;
 
-		// Find all the outputs that have not had their rangeproofs verified.
-		let outputs = {
+		// Find all the outputs that have not had their rangeproofs verified,
+		// and all the kernels that have not yet been verified.
+		let (outputs, kernels) = {
 			let mut verifier = verifier.write();
-			verifier.filter_rangeproof_unverified(&self.outputs)
+			(
+				verifier.filter_rangeproof_unverified(&self.outputs),
+				verifier.filter_kernel_sig_unverified(&self.kernels),
+			)
 		};
-
-		// Now batch verify all those unverified rangeproofs
-		if !outputs.is_empty() {
-			let mut commits = vec![];
-			let mut proofs = vec![];
-			for x in &outputs {
-				commits.push(x.commit);
-				proofs.push(x.proof);
+		let (commits, proofs): (Vec<_>, Vec<_>) =
+			outputs.iter().map(|x| (x.commit, x.proof)).unzip();
+
+		// Now batch verify all those unverified rangeproofs and kernels.
+		if parallel {
+			let (proofs_res, sigs_res) = rayon::join(
+				|| Output::par_batch_verify_proofs(&commits, &proofs),
+				|| TxKernel::par_batch_sig_verify(&kernels),
+			);
+			proofs_res?;
+			sigs_res?;
+		} else {
+			if !outputs.is_empty() {
+				Output::batch_verify_proofs(&commits, &proofs)?;
 			}
-			Output::batch_verify_proofs(&commits, &proofs)?;
+			TxKernel::batch_sig_verify(&kernels)?;
 		}
 
-		// Find all the kernels that have not yet been verified.
-		let kernels = {
-			let mut verifier = verifier.write();
-			verifier.filter_kernel_sig_unverified(&self.kernels)
-		};
-
-		// Verify the unverified tx kernels.
-		TxKernel::batch_sig_verify(&kernels)?;
-
 		// Cache the successful verification results for the new outputs and kernels.
 		{
 			let mut verifier = verifier.write();
@@ -1484,6 +1623,32 @@ impl Output {
 			.verify_bullet_proof_multi(commits.to_vec(), proofs.to_vec(), None)?;
 		Ok(())
 	}
+
+	/// Batch validates the range proofs using the commitments, split in
+	/// batches verified in parallel on the rayon thread pool.
+	pub fn par_batch_verify_proofs(
+		commits: &[Commitment],
+		proofs: &[RangeProof],
+	) -> Result<(), Error> {
+		if commits.is_empty() {
+			return Ok(());
+		}
+		on_verification_threads(|| {
+			let size = parallel_batch_size(commits.len());
+			if size >= commits.len() {
+				return Output::batch_verify_proofs(commits, proofs);
+			}
+			commits
+				.par_chunks(size)
+				.zip(proofs.par_chunks(size))
+				.try_for_each(|(commits, proofs)| {
+					with_thread_secp(|secp| {
+						secp.verify_bullet_proof_multi(commits.to_vec(), proofs.to_vec(), None)
+					})?;
+					Ok(())
+				})
+		})
+	}
 }
 
 /// An output_identifier can be build from either an input _or_ an output and
@@ -1584,6 +1749,28 @@ mod test {
 	use keychain::{ExtKeychain, Keychain, SwitchCommitmentType};
 	use util::secp;
 
+	#[test]
+	fn test_background_verification_threads() {
+		// Verifies on the global threads until the background ones start.
+		assert_eq!(
+			in_background(|| on_verification_threads(rayon::current_num_threads)),
+			rayon::current_num_threads()
+		);
+		init_background_verification_threads(3, |_| {}).unwrap();
+		assert!(init_background_verification_threads(2, |_| {}).is_err());
+
+		assert_eq!(
+			in_background(|| on_verification_threads(rayon::current_num_threads)),
+			3
+		);
+		// Only within the background work.
+		assert!(!IN_BACKGROUND.with(|b| b.get()));
+		assert!(on_verification_threads(rayon::current_thread_index).is_none());
+		let res = std::panic::catch_unwind(|| in_background(|| panic!("failed")));
+		assert!(res.is_err());
+		assert!(!IN_BACKGROUND.with(|b| b.get()));
+	}
+
 	#[test]
 	fn test_kernel_ser_deser() {
 		let keychain = ExtKeychain::from_random_seed(false).unwrap();
@@ -34,6 +34,9 @@ pub trait VerifierCache: Sync + Send {
 	fn add_kernel_sig_verified(&mut self, kernels: Vec<TxKernel>);
 	/// Adds a vec of outputs to the cache (used in conjunction with the the filter above).
 	fn add_rangeproof_verified(&mut self, outputs: Vec<Output>);
+	/// Forgets all but the keep most recently used results of each kind,
+	/// releasing the memory the others took.
+	fn shrink(&mut self, keep: usize);
 }
 
 /// An implementation of verifier_cache using lru_cache.
@@ -100,4 +103,21 @@ impl VerifierCache for LruVerifierCache {
 				.insert(o.proof.hash(), ());
 		}
 	}
+
+	fn shrink(&mut self, keep: usize) {
+		shrink_lru(&mut self.kernel_sig_verification_cache, keep);
+		shrink_lru(&mut self.rangeproof_verification_cache, keep);
+		debug!("lru_verifier_cache: shrunk to at most {} entries", keep);
+	}
+}
+
+// Rebuilds the cache with its keep most recently used entries only. Clearing
+// it in place would keep the memory of its largest size allocated.
+fn shrink_lru(cache: &mut LruCache<Hash, ()>, keep: usize) {
+	let mut shrunk = LruCache::new(cache.capacity());
+	// Iterates from the least recently used.
+	for (hash, _) in cache.iter().skip(cache.len().saturating_sub(keep)) {
+		shrunk.insert(*hash, ());
+	}
+	*cache = shrunk;
 }
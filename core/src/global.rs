@@ -27,6 +27,7 @@ use crate::pow::{
 	self, new_cuckaroo_ctx, new_cuckarood_ctx, new_cuckaroom_ctx, new_cuckatoo_ctx, EdgeType,
 	PoWContext,
 };
+use std::sync::atomic::{AtomicI64, Ordering};
 use util::RwLock;
 
 /// An enum collecting sets of parameters used throughout the
@@ -102,6 +103,13 @@ pub const TESTING_TXHASHSET_ARCHIVE_INTERVAL: u64 = 10;
 /// Number of blocks to reuse a txhashset zip for.
 pub const TXHASHSET_ARCHIVE_INTERVAL: u64 = 12 * 60;
 
+/// Maximum adjustment of the local clock towards the network time, in
+/// seconds.
+pub const MAX_NETWORK_TIME_OFFSET: i64 = 5 * 60;
+
+/// Offset of the network time from the local clock, in seconds.
+static NETWORK_TIME_OFFSET: AtomicI64 = AtomicI64::new(0);
+
 /// Types of chain a server can run with, dictates the genesis block and
 /// and mining parameters used.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -126,6 +134,17 @@ impl ChainTypes {
 			ChainTypes::Mainnet => "main".to_owned(),
 		}
 	}
+
+	/// Default port of the p2p server, distinct for each chain type so the
+	/// nodes of a chain don't end up dialing the nodes of another.
+	pub fn default_p2p_port(&self) -> u16 {
+		match *self {
+			ChainTypes::AutomatedTesting => 37414,
+			ChainTypes::UserTesting => 27414,
+			ChainTypes::Floonet => 17414,
+			ChainTypes::Mainnet => 7414,
+		}
+	}
 }
 
 impl Default for ChainTypes {
@@ -322,6 +341,21 @@ pub fn is_floonet() -> bool {
 	ChainTypes::Floonet == *param_ref
 }
 
+/// Set the offset of the network time from the local clock, bounded by
+/// MAX_NETWORK_TIME_OFFSET.
+pub fn set_network_time_offset(offset: i64) {
+	let offset = offset
+		.max(-MAX_NETWORK_TIME_OFFSET)
+		.min(MAX_NETWORK_TIME_OFFSET);
+	NETWORK_TIME_OFFSET.store(offset, Ordering::Relaxed);
+}
+
+/// Offset of the network time from the local clock, in seconds. Zero unless
+/// the node is configured to adjust its clock to the network time.
+pub fn network_time_offset() -> i64 {
+	NETWORK_TIME_OFFSET.load(Ordering::Relaxed)
+}
+
 /// Converts an iterator of block difficulty data to more a more manageable
 /// vector and pads if needed (which will) only be needed for the first few
 /// blocks after genesis
@@ -36,6 +36,7 @@ extern crate failure_derive;
 #[macro_use]
 pub mod macros;
 
+pub mod clock;
 pub mod consensus;
 pub mod core;
 pub mod genesis;
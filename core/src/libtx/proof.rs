@@ -96,6 +96,32 @@ where
 	Ok(check.map(|(id, switch)| (amount, id, switch)))
 }
 
+/// Rewind a rangeproof with only the rewind hash of a view key, to find the
+/// outputs of a wallet without its keys. Returns the amount and message of
+/// the proof if it rewinds, it's up to the wallet to check the message.
+pub fn rewind_with_hash(
+	secp: &Secp256k1,
+	rewind_hash: &[u8],
+	commit: Commitment,
+	proof: RangeProof,
+) -> Result<Option<(u64, ProofMessage)>, Error> {
+	let nonce = view_rewind_nonce(secp, rewind_hash, &commit)?;
+	Ok(secp
+		.rewind_bullet_proof(commit, nonce, None, proof)
+		.ok()
+		.map(|info| (info.value, info.message)))
+}
+
+fn view_rewind_nonce(
+	secp: &Secp256k1,
+	rewind_hash: &[u8],
+	commit: &Commitment,
+) -> Result<SecretKey, Error> {
+	let res = blake2b(32, &commit.0, rewind_hash);
+	SecretKey::from_slice(secp, res.as_bytes())
+		.map_err(|e| ErrorKind::RangeProof(format!("Unable to create nonce: {:?}", e)).into())
+}
+
 /// Used for building proofs and checking if the output belongs to the wallet
 pub trait ProofBuild {
 	/// Create a BP nonce that will allow to rewind the derivation path and flags
@@ -358,9 +384,7 @@ where
 
 impl ProofBuild for ViewKey {
 	fn rewind_nonce(&self, secp: &Secp256k1, commit: &Commitment) -> Result<SecretKey, Error> {
-		let res = blake2b(32, &commit.0, &self.rewind_hash);
-		SecretKey::from_slice(secp, res.as_bytes())
-			.map_err(|e| ErrorKind::RangeProof(format!("Unable to create nonce: {:?}", e)).into())
+		view_rewind_nonce(secp, &self.rewind_hash, commit)
 	}
 
 	fn private_nonce(&self, _secp: &Secp256k1, _commit: &Commitment) -> Result<SecretKey, Error> {
@@ -562,6 +586,32 @@ mod tests {
 		assert_eq!(r_switch, switch);
 	}
 
+	#[test]
+	fn rewind_hash_only() {
+		let rng = &mut thread_rng();
+		let keychain = ExtKeychain::from_random_seed(false).unwrap();
+
+		let builder = ProofBuilder::new(&keychain);
+		let amount = rng.gen();
+		let id = ExtKeychain::derive_key_id(3, rng.gen::<u16>() as u32, 0, 0, 0);
+		let switch = SwitchCommitmentType::Regular;
+		let commit = keychain.commit(amount, &id, switch).unwrap();
+		let proof = create(&keychain, &builder, amount, &id, switch, commit, None).unwrap();
+
+		// Rewinds with the rewind hash of the wallet..
+		let rewind = rewind_with_hash(keychain.secp(), &builder.rewind_hash, commit, proof);
+		let (r_amount, r_message) = rewind.unwrap().unwrap();
+		assert_eq!(r_amount, amount);
+		let message = builder.proof_message(keychain.secp(), &id, switch).unwrap();
+		assert_eq!(r_message.as_bytes(), message.as_bytes());
+
+		// ..but not with another one
+		let other = ExtKeychain::from_random_seed(false).unwrap();
+		let other_builder = ProofBuilder::new(&other);
+		let rewind = rewind_with_hash(keychain.secp(), &other_builder.rewind_hash, commit, proof);
+		assert!(rewind.unwrap().is_none());
+	}
+
 	#[test]
 	fn view_key_hardened() {
 		let rng = &mut thread_rng();
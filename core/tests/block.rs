@@ -13,19 +13,21 @@
 // limitations under the License.
 
 mod common;
-use crate::common::{new_block, tx1i2o, tx2i1o, txspend1i1o};
+use crate::common::{new_block, tx1i1o, tx1i2o, tx2i1o, txspend1i1o};
 use crate::core::consensus::BLOCK_OUTPUT_WEIGHT;
 use crate::core::core::block::Error;
 use crate::core::core::hash::Hashed;
 use crate::core::core::id::ShortIdentifiable;
 use crate::core::core::transaction::{self, Transaction};
 use crate::core::core::verifier_cache::{LruVerifierCache, VerifierCache};
-use crate::core::core::Committed;
+use crate::core::core::{committed, Committed};
 use crate::core::core::{
-	Block, BlockHeader, CompactBlock, HeaderVersion, KernelFeatures, OutputFeatures,
+	Block, BlockBuilder, BlockChunk, BlockHeader, BlockManifest, ChunkItems, ChunkPart,
+	ChunkedBody, CompactBlock, HeaderVersion, KernelFeatures, OutputFeatures,
 };
 use crate::core::libtx::build::{self, input, output};
 use crate::core::libtx::ProofBuilder;
+use crate::core::pow::Difficulty;
 use crate::core::{global, ser};
 use chrono::Duration;
 use kepler_core as core;
@@ -562,6 +564,66 @@ fn wrong_amount_range_proof() {
 	}
 }
 
+#[test]
+fn parallel_proof_verification() {
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+	let keychain = ExtKeychain::from_random_seed(false).unwrap();
+	let builder = ProofBuilder::new(&keychain);
+	let key_id = |n| ExtKeychain::derive_key_id(1, n, 0, 0, 0);
+
+	// Enough outputs and kernels for several batches.
+	let mut txs: Vec<Transaction> = (0..40)
+		.map(|i| {
+			build::transaction(
+				KernelFeatures::Plain { fee: 1 },
+				vec![
+					input(10, key_id(3 * i + 1)),
+					output(4, key_id(3 * i + 2)),
+					output(5, key_id(3 * i + 3)),
+				],
+				&keychain,
+				&builder,
+			)
+			.unwrap()
+		})
+		.collect();
+	let prev = BlockHeader::default();
+	let reward_key = key_id(1000);
+	let b = new_block(
+		txs.iter().collect(),
+		&keychain,
+		&builder,
+		&prev,
+		&reward_key,
+	);
+	assert!(b.outputs().len() > 2 * transaction::PARALLEL_VERIFY_MIN_BATCH);
+	b.validate(&BlindingFactor::zero(), verifier_cache())
+		.unwrap();
+
+	// A single bad range proof fails the batch it's in.
+	let tx = txs.pop().unwrap();
+	let mut outs = tx.outputs().clone();
+	let proof = outs[0].proof;
+	outs[0].proof = outs[1].proof;
+	outs[1].proof = proof;
+	txs.push(Transaction::new(
+		tx.inputs().clone(),
+		outs,
+		tx.kernels().clone(),
+	));
+	let b = new_block(
+		txs.iter().collect(),
+		&keychain,
+		&builder,
+		&prev,
+		&reward_key,
+	);
+	match b.validate(&BlindingFactor::zero(), verifier_cache()) {
+		Err(Error::Transaction(transaction::Error::Secp(secp::Error::InvalidRangeProof))) => {}
+		_ => panic!("Bad range proof should be invalid"),
+	}
+}
+
 #[test]
 fn validate_header_proof() {
 	let keychain = ExtKeychain::from_random_seed(false).unwrap();
@@ -594,3 +656,112 @@ fn validate_header_proof() {
 	)
 	.is_err());
 }
+
+#[test]
+fn block_builder_follows_prev_header() {
+	let keychain = ExtKeychain::from_random_seed(false).unwrap();
+	let builder = ProofBuilder::new(&keychain);
+	let key_id = ExtKeychain::derive_key_id(1, 1, 0, 0, 0);
+	let mut prev = new_block(
+		vec![],
+		&keychain,
+		&builder,
+		&BlockHeader::default(),
+		&key_id,
+	)
+	.header;
+	// previous block slightly in the future
+	prev.timestamp = prev.timestamp + Duration::minutes(5);
+
+	// no coinbase, nothing to build
+	assert!(BlockBuilder::new(&prev).build().is_err());
+
+	let tx = tx1i2o();
+	let key_id = ExtKeychain::derive_key_id(1, 2, 0, 0, 0);
+	let b = BlockBuilder::new(&prev)
+		.txs(vec![tx.clone()])
+		.secondary_scaling(3)
+		.coinbase(&keychain, &builder, &key_id)
+		.unwrap()
+		.build()
+		.unwrap();
+
+	assert_eq!(b.header.height, prev.height + 1);
+	assert_eq!(b.header.prev_hash, prev.hash());
+	assert!(b.header.timestamp > prev.timestamp);
+	assert_eq!(b.header.pow.secondary_scaling, 3);
+	assert_eq!(
+		b.header.total_difficulty(),
+		prev.total_difficulty() + Difficulty::min()
+	);
+	assert_eq!(
+		b.header.total_kernel_offset,
+		committed::sum_kernel_offsets(
+			vec![tx.offset.clone(), prev.total_kernel_offset.clone()],
+			vec![]
+		)
+		.unwrap()
+	);
+	assert_eq!(b.total_fees(), tx.fee());
+	b.validate(&prev.total_kernel_offset, verifier_cache())
+		.unwrap();
+}
+
+#[test]
+fn block_chunks() {
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+	let keychain = ExtKeychain::from_random_seed(false).unwrap();
+	let builder = ProofBuilder::new(&keychain);
+	let prev = BlockHeader::default();
+	let key_id = ExtKeychain::derive_key_id(1, 1, 0, 0, 0);
+	let txs = vec![tx1i2o(), tx2i1o(), tx1i1o()];
+	let b = new_block(txs.iter().collect(), &keychain, &builder, &prev, &key_id);
+
+	// 4 inputs, 5 outputs and 4 kernels, 2 items to a chunk when testing.
+	let manifest = BlockManifest::from_block(&b);
+	assert_eq!(manifest.chunks(ChunkPart::Inputs), 2);
+	assert_eq!(manifest.chunks(ChunkPart::Outputs), 3);
+	assert_eq!(manifest.chunks(ChunkPart::Kernels), 2);
+	assert_eq!(manifest.chunk_hashes.len(), 7);
+	assert!(BlockChunk::from_block(&b, ChunkPart::Outputs, 3).is_none());
+
+	let mut vec = Vec::new();
+	ser::serialize_default(&mut vec, &manifest).expect("serialization failed");
+	let manifest2: BlockManifest = ser::deserialize_default(&mut &vec[..]).unwrap();
+	assert_eq!(manifest, manifest2);
+
+	let mut body = ChunkedBody::new(manifest);
+	assert_eq!(body.missing().len(), 7);
+
+	// A chunk with an output dropped, or another range proof, is rejected.
+	let mut chunk = BlockChunk::from_block(&b, ChunkPart::Outputs, 0).unwrap();
+	if let ChunkItems::Outputs(ref mut outputs) = chunk.items {
+		outputs.pop();
+	}
+	assert_eq!(body.add(chunk), Err(Error::InvalidChunk));
+	let mut chunk = BlockChunk::from_block(&b, ChunkPart::Outputs, 0).unwrap();
+	if let ChunkItems::Outputs(ref mut outputs) = chunk.items {
+		outputs[0].proof = b.outputs()[1].proof;
+	}
+	assert_eq!(body.add(chunk), Err(Error::InvalidChunk));
+
+	for part in ChunkPart::ALL.iter() {
+		let mut index = 0;
+		while let Some(chunk) = BlockChunk::from_block(&b, *part, index) {
+			let mut vec = Vec::new();
+			ser::serialize_default(&mut vec, &chunk).expect("serialization failed");
+			let chunk2: BlockChunk = ser::deserialize_default(&mut &vec[..]).unwrap();
+			assert_eq!(body.add(chunk2), Ok(true));
+			index += 1;
+		}
+	}
+	let chunk = BlockChunk::from_block(&b, ChunkPart::Kernels, 1).unwrap();
+	assert_eq!(body.add(chunk), Ok(false));
+	assert!(body.missing().is_empty());
+	assert!(body.is_complete());
+
+	let b2 = body.into_block(b.header.clone()).unwrap();
+	assert_eq!(b2.inputs(), b.inputs());
+	assert_eq!(b2.outputs(), b.outputs());
+	assert_eq!(b2.kernels(), b.kernels());
+}
@@ -15,13 +15,13 @@
 //! Common test functions
 
 use kepler_core::core::hash::DefaultHashable;
-use kepler_core::core::{Block, BlockHeader, KernelFeatures, Transaction};
+use kepler_core::core::{Block, BlockBuilder, BlockHeader, KernelFeatures, Transaction};
+use kepler_core::global;
 use kepler_core::libtx::{
 	build::{self, input, output},
 	proof::{ProofBuild, ProofBuilder},
-	reward,
 };
-use kepler_core::pow::Difficulty;
+use kepler_core::pow::Proof;
 use kepler_core::ser::{self, PMMRable, Readable, Reader, Writeable, Writer};
 use keychain::{Identifier, Keychain};
 
@@ -94,16 +94,13 @@ where
 	K: Keychain,
 	B: ProofBuild,
 {
-	let fees = txs.iter().map(|tx| tx.fee()).sum();
-	let height = previous_header.height + 1;
-	let reward_output = reward::output(keychain, builder, &key_id, fees, height, false).unwrap();
-	Block::new(
-		&previous_header,
-		txs.into_iter().cloned().collect(),
-		Difficulty::min(),
-		reward_output,
-	)
-	.unwrap()
+	BlockBuilder::new(previous_header)
+		.txs(txs.into_iter().cloned().collect())
+		.proof(Proof::random(global::proofsize()))
+		.coinbase(keychain, builder, key_id)
+		.unwrap()
+		.build()
+		.unwrap()
 }
 
 // utility producing a transaction that spends an output with the provided
@@ -699,6 +699,31 @@ fn hard_forks() {
 			YEAR_HEIGHT * 3 / 2 + 1,
 			HeaderVersion(3)
 		));
+
+		assert_eq!(
+			next_hard_fork(0),
+			Some((YEAR_HEIGHT / 2 - HARD_FORK_ADJUST_HEIGHT, HeaderVersion(2)))
+		);
+		assert_eq!(
+			next_hard_fork(YEAR_HEIGHT / 2 - HARD_FORK_ADJUST_HEIGHT - 1),
+			Some((YEAR_HEIGHT / 2 - HARD_FORK_ADJUST_HEIGHT, HeaderVersion(2)))
+		);
+		assert_eq!(
+			next_hard_fork(YEAR_HEIGHT / 2 - HARD_FORK_ADJUST_HEIGHT),
+			Some((YEAR_HEIGHT - HARD_FORK_ADJUST_HEIGHT, HeaderVersion(3)))
+		);
+		// end of the schedule, needs an upgrade
+		assert_eq!(
+			next_hard_fork(YEAR_HEIGHT - HARD_FORK_ADJUST_HEIGHT),
+			Some((
+				YEAR_HEIGHT * 3 / 2 - HARD_FORK_ADJUST_HEIGHT,
+				HeaderVersion(4)
+			))
+		);
+		assert_eq!(
+			next_hard_fork(YEAR_HEIGHT * 3 / 2 - HARD_FORK_ADJUST_HEIGHT),
+			None
+		);
 	}
 	// Tests for floonet chain type.
 	{
@@ -759,6 +784,22 @@ fn hard_forks() {
 			YEAR_HEIGHT * 3 / 2 + 1,
 			HeaderVersion(3)
 		));
+
+		assert_eq!(
+			next_hard_fork(0),
+			Some((FLOONET_FIRST_HARD_FORK, HeaderVersion(2)))
+		);
+		assert_eq!(
+			next_hard_fork(FLOONET_FIRST_HARD_FORK),
+			Some((FLOONET_SECOND_HARD_FORK, HeaderVersion(3)))
+		);
+		assert_eq!(
+			next_hard_fork(FLOONET_SECOND_HARD_FORK),
+			Some((
+				YEAR_HEIGHT * 3 / 2 - HARD_FORK_ADJUST_HEIGHT,
+				HeaderVersion(4)
+			))
+		);
 	}
 }
 
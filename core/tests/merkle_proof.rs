@@ -14,8 +14,9 @@
 
 mod common;
 
-use self::core::core::merkle_proof::MerkleProof;
-use self::core::core::pmmr::{VecBackend, PMMR};
+use self::core::core::merkle_proof::{verify_header_inclusion, MerkleProof, MerkleProofError};
+use self::core::core::pmmr::{self, VecBackend, PMMR};
+use self::core::core::BlockHeader;
 use self::core::ser::{self, PMMRIndexHashable};
 use crate::common::TestElem;
 use kepler_core as core;
@@ -187,3 +188,52 @@ fn pmmr_merkle_proof() {
 	assert_eq!(proof.path, vec![pos_9, pos_6]);
 	assert!(proof.verify(pmmr.root().unwrap(), &elems[6], 11).is_ok());
 }
+
+#[test]
+fn header_inclusion_proof() {
+	let mut ba = VecBackend::new();
+	let mut pmmr = PMMR::new(&mut ba);
+	let headers: Vec<_> = (0..11)
+		.map(|height| {
+			let mut header = BlockHeader::default();
+			header.height = height;
+			header.pow.proof.nonces[0] = height;
+			header
+		})
+		.collect();
+	for header in &headers {
+		pmmr.push(header).unwrap();
+	}
+	let root = pmmr.root().unwrap();
+
+	for header in &headers {
+		let pos = pmmr::insertion_to_pmmr_index(header.height + 1);
+		let proof = pmmr.merkle_proof(pos).unwrap();
+		assert_eq!(verify_header_inclusion(root, header, &proof), Ok(()));
+	}
+
+	// Another header at the same height.
+	let proof = pmmr.merkle_proof(pmmr::insertion_to_pmmr_index(5)).unwrap();
+	let mut header = headers[4].clone();
+	header.pow.proof.nonces[0] += 1;
+	assert_eq!(
+		verify_header_inclusion(root, &header, &proof),
+		Err(MerkleProofError::RootMismatch)
+	);
+
+	// The header at another height.
+	let mut header = headers[4].clone();
+	header.height = 5;
+	assert_eq!(
+		verify_header_inclusion(root, &header, &proof),
+		Err(MerkleProofError::RootMismatch)
+	);
+
+	// A header beyond the MMR.
+	let mut header = headers[10].clone();
+	header.height = 11;
+	assert_eq!(
+		verify_header_inclusion(root, &header, &proof),
+		Err(MerkleProofError::RootMismatch)
+	);
+}
@@ -0,0 +1,160 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+mod common;
+
+use self::core::core::pmmr::{
+	self, ReadonlyPMMR, Segment, SegmentError, SegmentIdentifier, VecBackend, PMMR,
+};
+use self::core::ser;
+use crate::common::TestElem;
+use croaring::Bitmap;
+use kepler_core as core;
+
+fn build_pmmr(n: u32) -> VecBackend<TestElem> {
+	let mut ba = VecBackend::new();
+	let mut pmmr = PMMR::new(&mut ba);
+	for x in 0..n {
+		pmmr.push(&TestElem([0, 0, 0, x])).unwrap();
+	}
+	ba
+}
+
+// Unspent leaf positions, all the leaves but the ones at the given indices.
+fn unspent(n: u32, spent: &[u32]) -> Bitmap {
+	(0..n)
+		.filter(|idx| !spent.contains(idx))
+		.map(|idx| pmmr::insertion_to_pmmr_index(idx as u64 + 1) as u32)
+		.collect()
+}
+
+#[test]
+fn segment_identifiers() {
+	let size = build_pmmr(23).size();
+	assert_eq!(size, 42);
+	assert_eq!(SegmentIdentifier::count(size, 2), 6);
+	assert_eq!(SegmentIdentifier::all(size, 4).count(), 2);
+
+	let id = SegmentIdentifier { height: 2, idx: 1 };
+	assert_eq!(id.first_pos(), 8);
+	assert_eq!(id.roots(size), Ok(vec![14]));
+
+	// The last segment spans the trailing peaks.
+	let id = SegmentIdentifier { height: 2, idx: 5 };
+	assert_eq!(id.roots(size), Ok(vec![41, 42]));
+	let id = SegmentIdentifier { height: 2, idx: 6 };
+	assert_eq!(id.roots(size), Err(SegmentError::OutOfRange));
+}
+
+#[test]
+fn unpruned_segments() {
+	let n = 23;
+	let ba = build_pmmr(n);
+	let pmmr = ReadonlyPMMR::at(&ba, ba.size());
+	let root = pmmr.root();
+
+	for height in 0..6 {
+		for id in SegmentIdentifier::all(ba.size(), height) {
+			let segment: Segment<TestElem> = Segment::from_pmmr(id, &pmmr, None).unwrap();
+			assert!(segment.hashes.is_empty());
+			assert_eq!(segment.root(ba.size(), None), Ok(root));
+
+			let mut vec = Vec::new();
+			ser::serialize_default(&mut vec, &segment).unwrap();
+			let segment_2: Segment<TestElem> = ser::deserialize_default(&mut &vec[..]).unwrap();
+			assert_eq!(segment, segment_2);
+		}
+	}
+}
+
+#[test]
+fn pruned_segments() {
+	let n = 23;
+	let ba = build_pmmr(n);
+	let pmmr = ReadonlyPMMR::at(&ba, ba.size());
+	let root = pmmr.root();
+	// The first 8 leaves are spent, as well as a lone leaf and a pair.
+	let spent = [0, 1, 2, 3, 4, 5, 6, 7, 9, 12, 13];
+	let bitmap = unspent(n, &spent);
+
+	for height in 0..6 {
+		for id in SegmentIdentifier::all(ba.size(), height) {
+			let segment: Segment<TestElem> = Segment::from_pmmr(id, &pmmr, Some(&bitmap)).unwrap();
+			assert_eq!(segment.root(ba.size(), Some(&bitmap)), Ok(root));
+			assert!(segment
+				.leaf_data
+				.iter()
+				.all(|(_, elem)| !spent[..8].contains(&elem.0[3])));
+		}
+	}
+
+	// The first two segments of height 2 are covered by their pruned parent.
+	for idx in 0..2 {
+		let id = SegmentIdentifier { height: 2, idx };
+		let segment: Segment<TestElem> = Segment::from_pmmr(id, &pmmr, Some(&bitmap)).unwrap();
+		assert_eq!(segment.hashes, vec![(15, pmmr.get_from_file(15).unwrap())]);
+		assert!(segment.leaf_data.is_empty());
+	}
+
+	// The lone spent leaf is sent with its unspent sibling, the pair is pruned.
+	let id = SegmentIdentifier { height: 2, idx: 2 };
+	let segment: Segment<TestElem> = Segment::from_pmmr(id, &pmmr, Some(&bitmap)).unwrap();
+	assert_eq!(segment.leaf_data.len(), 4);
+	let id = SegmentIdentifier { height: 2, idx: 3 };
+	let segment: Segment<TestElem> = Segment::from_pmmr(id, &pmmr, Some(&bitmap)).unwrap();
+	assert_eq!(segment.hashes.len(), 1);
+	assert_eq!(segment.leaf_data.len(), 2);
+}
+
+#[test]
+fn bad_segments() {
+	let n = 23;
+	let ba = build_pmmr(n);
+	let pmmr = ReadonlyPMMR::at(&ba, ba.size());
+	let root = pmmr.root();
+	let bitmap = unspent(n, &[12, 13]);
+	let id = SegmentIdentifier { height: 2, idx: 3 };
+	let segment: Segment<TestElem> = Segment::from_pmmr(id, &pmmr, Some(&bitmap)).unwrap();
+	assert_eq!(segment.validate(ba.size(), Some(&bitmap), root), Ok(()));
+
+	// Checked against another bitmap, the nodes expected differ.
+	assert_eq!(
+		segment.root(ba.size(), None),
+		Err(SegmentError::NodeMismatch)
+	);
+
+	// Altered leaf data doesn't match the root.
+	let mut bad = segment.clone();
+	bad.leaf_data[0].1 = TestElem([1, 0, 0, 0]);
+	assert_eq!(
+		bad.validate(ba.size(), Some(&bitmap), root),
+		Err(SegmentError::RootMismatch)
+	);
+
+	// Nor does a truncated proof.
+	let mut bad = segment.clone();
+	bad.proof.hashes.pop();
+	assert_eq!(
+		bad.root(ba.size(), Some(&bitmap)),
+		Err(SegmentError::ProofMismatch)
+	);
+
+	// An unspent leaf can't be hidden behind a hash.
+	let id = SegmentIdentifier { height: 1, idx: 7 };
+	let segment: Segment<TestElem> = Segment::from_pmmr(id, &pmmr, None).unwrap();
+	let mut bad = segment.clone();
+	bad.leaf_data.clear();
+	bad.hashes = vec![(28, pmmr.get_from_file(28).unwrap())];
+	assert_eq!(bad.root(ba.size(), None), Err(SegmentError::NodeMismatch));
+}
@@ -16,11 +16,12 @@
 
 pub mod common;
 
-use self::core::core::{Output, OutputFeatures};
+use self::core::core::transaction::Error;
+use self::core::core::{InputProof, Output, OutputFeatures};
 use self::core::libtx::proof;
 use self::core::ser;
 use kepler_core as core;
-use keychain::{ExtKeychain, Keychain};
+use keychain::{BlindingFactor, ExtKeychain, Keychain};
 
 #[test]
 fn test_output_ser_deser() {
@@ -45,3 +46,35 @@ fn test_output_ser_deser() {
 	assert_eq!(dout.commit, out.commit);
 	assert_eq!(dout.proof, out.proof);
 }
+
+#[test]
+fn test_input_proof() {
+	let keychain = ExtKeychain::from_random_seed(false).unwrap();
+	let switch = keychain::SwitchCommitmentType::Regular;
+	let key_id = ExtKeychain::derive_key_id(1, 1, 0, 0, 0);
+	let commit = keychain.commit(5, &key_id, switch).unwrap();
+	let blind = BlindingFactor::from_secret_key(keychain.derive_key(5, &key_id, switch).unwrap());
+	let excess_id = ExtKeychain::derive_key_id(1, 2, 0, 0, 0);
+	let excess = keychain.commit(0, &excess_id, switch).unwrap();
+
+	let proof = InputProof::create(commit, 5, &blind, excess).unwrap();
+	proof.verify().unwrap();
+
+	let mut vec = vec![];
+	ser::serialize_default(&mut vec, &proof).expect("serialized failed");
+	let dproof: InputProof = ser::deserialize_default(&mut &vec[..]).unwrap();
+	assert_eq!(dproof, proof);
+	dproof.verify().unwrap();
+
+	// Not knowing the value or the blinding factor, or moving the proof to
+	// another tx, fails it.
+	let proof = InputProof::create(commit, 6, &blind, excess).unwrap();
+	assert_eq!(proof.verify(), Err(Error::InvalidInputProof));
+	let other_blind =
+		BlindingFactor::from_secret_key(keychain.derive_key(5, &excess_id, switch).unwrap());
+	let proof = InputProof::create(commit, 5, &other_blind, excess).unwrap();
+	assert_eq!(proof.verify(), Err(Error::InvalidInputProof));
+	let mut proof = InputProof::create(commit, 5, &blind, excess).unwrap();
+	proof.excess = commit;
+	assert_eq!(proof.verify(), Err(Error::InvalidInputProof));
+}
@@ -63,3 +63,32 @@ fn test_verifier_cache_rangeproofs() {
 		assert_eq!(unverified, vec![]);
 	}
 }
+
+#[test]
+fn test_verifier_cache_shrink() {
+	let cache = verifier_cache();
+
+	let keychain = ExtKeychain::from_random_seed(false).unwrap();
+	let builder = proof::ProofBuilder::new(&keychain);
+	let switch = SwitchCommitmentType::Regular;
+	let outputs: Vec<Output> = (1..4)
+		.map(|i| {
+			let key_id = ExtKeychain::derive_key_id(1, i, 0, 0, 0);
+			let commit = keychain.commit(5, &key_id, switch).unwrap();
+			let proof =
+				proof::create(&keychain, &builder, 5, &key_id, switch, commit, None).unwrap();
+			Output {
+				features: OutputFeatures::Plain,
+				commit,
+				proof,
+			}
+		})
+		.collect();
+
+	// Only the most recently used results are kept.
+	let mut cache = cache.write();
+	cache.add_rangeproof_verified(outputs.clone());
+	cache.shrink(2);
+	let unverified = cache.filter_rangeproof_unverified(&outputs);
+	assert_eq!(unverified, vec![outputs[0]]);
+}
@@ -0,0 +1,319 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Capture of the messages received from our peers, to replay them later
+//! through the protocol handlers. Each message is recorded with the peer it
+//! came from, the time and the protocol version it was read with, followed
+//! by its raw header and body. The txhashset archives attached to some
+//! messages aren't recorded.
+
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+use chrono::prelude::Utc;
+
+use crate::conn::{Message, MessageHandler, Tracker};
+use crate::core::pow::Difficulty;
+use crate::core::ser::{self, ProtocolVersion, Readable, Reader, Writeable, Writer};
+use crate::msg::{read_header, MsgHeader, MsgHeaderWrapper};
+use crate::protocol::Protocol;
+use crate::types::{Capabilities, Direction, Error, NetAdapter, PeerAddr, PeerInfo, PeerLiveInfo};
+use crate::util::{Mutex, RwLock};
+
+/// Name of the capture file being written, the older ones get a number
+/// appended to it, the lowest being the most recent.
+pub const CAPTURE_FILE: &str = "p2p_capture.bin";
+
+/// Number of capture files kept, the current one included.
+const MAX_CAPTURE_FILES: usize = 4;
+
+/// A message received from a peer, as captured.
+pub struct CapturedMsg {
+	/// When the message was received (milliseconds since the epoch)
+	pub timestamp: i64,
+	/// Peer the message was received from
+	pub peer: PeerAddr,
+	/// Protocol version the message was read with
+	pub version: ProtocolVersion,
+	/// Header of the message
+	pub header: MsgHeader,
+	/// Raw body of the message
+	pub body: Vec<u8>,
+}
+
+impl Writeable for CapturedMsg {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_i64(self.timestamp)?;
+		self.peer.write(writer)?;
+		self.version.write(writer)?;
+		self.header.write(writer)?;
+		writer.write_fixed_bytes(&self.body)
+	}
+}
+
+// The peer, time and version of a captured message, before its header.
+struct CaptureEntry {
+	timestamp: i64,
+	peer: PeerAddr,
+	version: ProtocolVersion,
+}
+
+impl Readable for CaptureEntry {
+	fn read(reader: &mut dyn Reader) -> Result<CaptureEntry, ser::Error> {
+		Ok(CaptureEntry {
+			timestamp: reader.read_i64()?,
+			peer: PeerAddr::read(reader)?,
+			version: ProtocolVersion::read(reader)?,
+		})
+	}
+}
+
+struct CaptureFile {
+	file: File,
+	size: u64,
+}
+
+/// Records the messages received from our peers to a capture file, rotating
+/// to a new one once it reaches its maximum size.
+pub struct MessageCapture {
+	dir: PathBuf,
+	max_file_size: u64,
+	current: Mutex<CaptureFile>,
+}
+
+impl MessageCapture {
+	/// Capture to the directory, appending to its current capture file.
+	pub fn new(dir: PathBuf, max_file_size: u64) -> Result<MessageCapture, Error> {
+		fs::create_dir_all(&dir)?;
+		let file = OpenOptions::new()
+			.create(true)
+			.append(true)
+			.open(dir.join(CAPTURE_FILE))?;
+		let size = file.metadata()?.len();
+		Ok(MessageCapture {
+			dir,
+			max_file_size,
+			current: Mutex::new(CaptureFile { file, size }),
+		})
+	}
+
+	/// Read the body of a message received from the peer and record it.
+	/// Failing to record it is only logged, the message still gets handled.
+	pub fn capture(
+		&self,
+		peer: PeerAddr,
+		version: ProtocolVersion,
+		header: &MsgHeader,
+		stream: &mut dyn Read,
+	) -> Result<Vec<u8>, Error> {
+		let mut body = vec![0u8; header.msg_len as usize];
+		stream.read_exact(&mut body)?;
+		let msg = CapturedMsg {
+			timestamp: Utc::now().timestamp_millis(),
+			peer,
+			version,
+			header: header.clone(),
+			body,
+		};
+		if let Err(e) = self.record(&msg) {
+			warn!("p2p capture: failed to record a message: {:?}", e);
+		}
+		Ok(msg.body)
+	}
+
+	fn record(&self, msg: &CapturedMsg) -> Result<(), Error> {
+		let bytes = ser::ser_vec(msg, ProtocolVersion::local())?;
+		let mut current = self.current.lock();
+		if current.size > 0 && current.size + bytes.len() as u64 > self.max_file_size {
+			self.rotate(&mut current)?;
+		}
+		current.file.write_all(&bytes)?;
+		current.size += bytes.len() as u64;
+		Ok(())
+	}
+
+	fn rotate(&self, current: &mut CaptureFile) -> Result<(), Error> {
+		for n in (1..MAX_CAPTURE_FILES).rev() {
+			let from = self.path(n - 1);
+			if from.exists() {
+				fs::rename(from, self.path(n))?;
+			}
+		}
+		current.file = File::create(self.path(0))?;
+		current.size = 0;
+		Ok(())
+	}
+
+	fn path(&self, n: usize) -> PathBuf {
+		if n == 0 {
+			self.dir.join(CAPTURE_FILE)
+		} else {
+			self.dir.join(format!("{}.{}", CAPTURE_FILE, n))
+		}
+	}
+}
+
+/// Reads the messages of a capture file, in the order they were received.
+pub struct CaptureReader {
+	reader: BufReader<File>,
+}
+
+impl CaptureReader {
+	pub fn open(path: &Path) -> Result<CaptureReader, Error> {
+		Ok(CaptureReader {
+			reader: BufReader::new(File::open(path)?),
+		})
+	}
+
+	fn read_msg(&mut self) -> Result<CapturedMsg, Error> {
+		let entry: CaptureEntry = ser::deserialize_default(&mut self.reader)?;
+		let header = match read_header(&mut self.reader, entry.version)? {
+			MsgHeaderWrapper::Known(header) => header,
+			MsgHeaderWrapper::Unknown(_, _) => return Err(Error::BadMessage),
+		};
+		let mut body = vec![0u8; header.msg_len as usize];
+		self.reader.read_exact(&mut body)?;
+		Ok(CapturedMsg {
+			timestamp: entry.timestamp,
+			peer: entry.peer,
+			version: entry.version,
+			header,
+			body,
+		})
+	}
+}
+
+impl Iterator for CaptureReader {
+	type Item = Result<CapturedMsg, Error>;
+
+	fn next(&mut self) -> Option<Result<CapturedMsg, Error>> {
+		match self.reader.fill_buf() {
+			Ok(buf) if buf.is_empty() => None,
+			Ok(_) => Some(self.read_msg()),
+			Err(e) => Some(Err(Error::Connection(e))),
+		}
+	}
+}
+
+/// Feeds captured messages to the protocol handlers, each peer getting its
+/// own handler as when connected, against the provided adapter. Responses
+/// are dropped.
+pub struct Replayer {
+	adapter: Arc<dyn NetAdapter>,
+	handlers: HashMap<PeerAddr, Protocol>,
+	tracker: Arc<Tracker>,
+	stopped: Arc<AtomicBool>,
+}
+
+impl Replayer {
+	pub fn new(adapter: Arc<dyn NetAdapter>) -> Replayer {
+		Replayer {
+			adapter,
+			handlers: HashMap::new(),
+			tracker: Arc::new(Tracker::new()),
+			stopped: Arc::new(AtomicBool::new(false)),
+		}
+	}
+
+	/// Handle the message as if just received from its peer.
+	pub fn replay(&mut self, msg: &CapturedMsg) -> Result<(), Error> {
+		let adapter = &self.adapter;
+		let handler = self.handlers.entry(msg.peer).or_insert_with(|| {
+			let info = PeerInfo {
+				capabilities: Capabilities::FULL_NODE,
+				user_agent: "replay".to_owned(),
+				version: msg.version,
+				addr: msg.peer,
+				direction: Direction::Inbound,
+				live_info: Arc::new(RwLock::new(PeerLiveInfo::new(Difficulty::min()))),
+			};
+			Protocol::new(
+				adapter.clone(),
+				info,
+				Arc::new(AtomicBool::new(false)),
+				Arc::new(Mutex::new(VecDeque::new())),
+				Arc::new(Mutex::new(VecDeque::new())),
+				None,
+			)
+		});
+		let mut body = &msg.body[..];
+		let message = Message::from_header(msg.header.clone(), &mut body, msg.version);
+		handler
+			.consume(message, self.stopped.clone(), self.tracker.clone())
+			.map(|_| ())
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::msg::{Ping, Type};
+	use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+	fn ping_msg(height: u64) -> CapturedMsg {
+		let ping = Ping {
+			total_difficulty: Difficulty::min(),
+			height,
+			timestamp: None,
+			body_tail: None,
+		};
+		let body = ser::ser_vec(&ping, ProtocolVersion::local()).unwrap();
+		CapturedMsg {
+			timestamp: 0,
+			peer: PeerAddr(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 7414)),
+			version: ProtocolVersion::local(),
+			header: MsgHeader::new(Type::Ping, body.len() as u64),
+			body,
+		}
+	}
+
+	#[test]
+	fn test_capture_rotation() {
+		let dir = tempfile::tempdir().unwrap();
+		let msg = ping_msg(1);
+		let len = ser::ser_vec(&msg, ProtocolVersion::local()).unwrap().len() as u64;
+
+		// Two messages per file.
+		let capture = MessageCapture::new(dir.path().to_path_buf(), 2 * len).unwrap();
+		for height in 0..(2 * MAX_CAPTURE_FILES as u64 + 1) {
+			let msg = ping_msg(height);
+			let mut stream = &msg.body[..];
+			let body = capture
+				.capture(msg.peer, msg.version, &msg.header, &mut stream)
+				.unwrap();
+			assert_eq!(body, msg.body);
+		}
+
+		// The oldest messages were dropped with their file.
+		assert!(!capture.path(MAX_CAPTURE_FILES).exists());
+		let oldest = CaptureReader::open(&capture.path(MAX_CAPTURE_FILES - 1))
+			.unwrap()
+			.collect::<Result<Vec<_>, _>>()
+			.unwrap();
+		assert_eq!(oldest.len(), 2);
+		assert_eq!(oldest[0].body, ping_msg(2).body);
+		assert_eq!(oldest[0].header.msg_type, Type::Ping);
+
+		let current = CaptureReader::open(&capture.path(0))
+			.unwrap()
+			.collect::<Result<Vec<_>, _>>()
+			.unwrap();
+		assert_eq!(current.len(), 1);
+		assert_eq!(current[0].body, ping_msg(2 * MAX_CAPTURE_FILES as u64).body);
+	}
+}
@@ -20,13 +20,14 @@
 //! forces us to go through some additional gymnastic to loop over the async
 //! stream and make sure we get the right number of bytes out.
 
+use crate::capture::MessageCapture;
 use crate::core::ser;
 use crate::core::ser::ProtocolVersion;
 use crate::msg::{
 	read_body, read_discard, read_header, read_item, write_message, Msg, MsgHeader,
 	MsgHeaderWrapper,
 };
-use crate::types::Error;
+use crate::types::{Error, PeerAddr};
 use crate::util::{RateCounter, RwLock};
 use std::io::{self, Read, Write};
 use std::net::{Shutdown, TcpStream};
@@ -95,7 +96,7 @@ pub struct Message<'a> {
 }
 
 impl<'a> Message<'a> {
-	fn from_header(
+	pub(crate) fn from_header(
 		header: MsgHeader,
 		stream: &'a mut dyn Read,
 		version: ProtocolVersion,
@@ -234,12 +235,13 @@ impl Tracker {
 
 /// Start listening on the provided connection and wraps it. Does not hang
 /// the current thread, instead just returns a future and the Connection
-/// itself.
+/// itself. The messages received are recorded to the capture, if any.
 pub fn listen<H>(
 	stream: TcpStream,
 	version: ProtocolVersion,
 	tracker: Arc<Tracker>,
 	handler: H,
+	capture: Option<Arc<MessageCapture>>,
 ) -> io::Result<(ConnHandle, StopHandle)>
 where
 	H: MessageHandler,
@@ -260,6 +262,7 @@ where
 		send_rx,
 		stopped.clone(),
 		tracker,
+		capture,
 	)?;
 
 	Ok((
@@ -280,6 +283,7 @@ fn poll<H>(
 	send_rx: mpsc::Receiver<Msg>,
 	stopped: Arc<AtomicBool>,
 	tracker: Arc<Tracker>,
+	capture: Option<Arc<MessageCapture>>,
 ) -> io::Result<(JoinHandle<()>, JoinHandle<()>)>
 where
 	H: MessageHandler,
@@ -291,6 +295,7 @@ where
 
 	let reader_tracker = tracker.clone();
 	let writer_tracker = tracker;
+	let peer_addr = conn.peer_addr().ok().map(PeerAddr);
 
 	let reader_thread = thread::Builder::new()
 		.name("peer_read".to_string())
@@ -300,7 +305,20 @@ where
 				match try_header!(read_header(&mut reader, version), &reader) {
 					Some(MsgHeaderWrapper::Known(header)) => {
 						let _ = reader.set_read_timeout(Some(BODY_IO_TIMEOUT));
-						let msg = Message::from_header(header, &mut reader, version);
+						// A captured message body is read ahead to be recorded, what
+						// follows it (an attachment) is still read from the connection.
+						let captured = match (&capture, peer_addr) {
+							(Some(capture), Some(addr)) => {
+								try_break!(capture.capture(addr, version, &header, &mut reader))
+							}
+							_ => Some(vec![]),
+						};
+						let body = match captured {
+							Some(body) => body,
+							None => continue,
+						};
+						let mut stream = (&body[..]).chain(&mut reader);
+						let msg = Message::from_header(header, &mut stream, version);
 
 						trace!(
 							"Received message header, type {:?}, len {}.",
@@ -20,6 +20,7 @@ use crate::msg::{read_message, write_message, Hand, Msg, Shake, Type, USER_AGENT
 use crate::peer::Peer;
 use crate::types::{Capabilities, Direction, Error, P2PConfig, PeerAddr, PeerInfo, PeerLiveInfo};
 use crate::util::RwLock;
+use chrono::prelude::Utc;
 use rand::{thread_rng, Rng};
 use std::collections::VecDeque;
 use std::net::{SocketAddr, TcpStream};
@@ -96,6 +97,7 @@ impl Handshake {
 		&self,
 		capabilities: Capabilities,
 		total_difficulty: Difficulty,
+		body_tail: Option<u64>,
 		self_addr: PeerAddr,
 		conn: &mut TcpStream,
 	) -> Result<PeerInfo, Error> {
@@ -121,6 +123,8 @@ impl Handshake {
 			sender_addr: self_addr,
 			receiver_addr: peer_addr,
 			user_agent: USER_AGENT.to_string(),
+			timestamp: Some(Utc::now().timestamp()),
+			body_tail,
 		};
 
 		// write and read the handshake response
@@ -145,6 +149,8 @@ impl Handshake {
 			live_info: Arc::new(RwLock::new(PeerLiveInfo::new(shake.total_difficulty))),
 			direction: Direction::Outbound,
 		};
+		peer_info.update_clock(shake.timestamp);
+		peer_info.update_body_tail(shake.body_tail);
 
 		// If denied then we want to close the connection
 		// (without providing our peer with any details why).
@@ -168,6 +174,7 @@ impl Handshake {
 		&self,
 		capab: Capabilities,
 		total_difficulty: Difficulty,
+		body_tail: Option<u64>,
 		conn: &mut TcpStream,
 	) -> Result<PeerInfo, Error> {
 		// Set explicit timeouts on the tcp stream for hand/shake messages.
@@ -210,6 +217,8 @@ impl Handshake {
 			live_info: Arc::new(RwLock::new(PeerLiveInfo::new(hand.total_difficulty))),
 			direction: Direction::Inbound,
 		};
+		peer_info.update_clock(hand.timestamp);
+		peer_info.update_body_tail(hand.body_tail);
 
 		// At this point we know the published ip and port of the peer
 		// so check if we are configured to explicitly allow or deny it.
@@ -226,6 +235,8 @@ impl Handshake {
 			genesis: self.genesis,
 			total_difficulty: total_difficulty,
 			user_agent: USER_AGENT.to_string(),
+			timestamp: Some(Utc::now().timestamp()),
+			body_tail,
 		};
 
 		let msg = Msg::new(Type::Shake, shake, negotiated_version)?;
@@ -36,22 +36,27 @@ extern crate serde_derive;
 #[macro_use]
 extern crate log;
 
+mod capture;
 mod conn;
 pub mod handshake;
 pub mod msg;
 mod peer;
 mod peers;
 mod protocol;
+mod recon;
 mod serv;
 mod store;
+mod throughput;
 pub mod types;
 
+pub use crate::capture::{CaptureReader, CapturedMsg, MessageCapture, Replayer, CAPTURE_FILE};
 pub use crate::conn::SEND_CHANNEL_CAP;
 pub use crate::peer::Peer;
-pub use crate::peers::Peers;
+pub use crate::peers::{Peers, TxRelay};
 pub use crate::serv::{DummyAdapter, Server};
-pub use crate::store::{PeerData, State};
+pub use crate::store::{PeerData, PeerOrder, PeerQuery, State};
+pub use crate::throughput::{SyncThroughput, SyncTransfer, TransferStats};
 pub use crate::types::{
-	Capabilities, ChainAdapter, Direction, Error, P2PConfig, PeerAddr, PeerInfo, ReasonForBan,
-	Seeding, TxHashSetRead, MAX_BLOCK_HEADERS, MAX_LOCATORS, MAX_PEER_ADDRS,
+	Capabilities, ChainAdapter, Direction, Error, IpSubnet, P2PConfig, PeerAddr, PeerInfo,
+	ReasonForBan, Seeding, TxHashSetRead, MAX_BLOCK_HEADERS, MAX_LOCATORS, MAX_PEER_ADDRS,
 };
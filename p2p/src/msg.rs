@@ -14,20 +14,24 @@
 
 //! Message types that transit over the network and related serialization code.
 
+use crate::chain::txhashset::{BitmapChunk, SegmentType};
 use crate::conn::Tracker;
+use crate::core::core::block_chunk::{self, ChunkPart};
 use crate::core::core::hash::Hash;
-use crate::core::core::BlockHeader;
+use crate::core::core::pmmr::{Segment, SegmentIdentifier};
+use crate::core::core::{BlockFilter, BlockHeader, InputProof, UntrustedBlockHeader};
 use crate::core::pow::Difficulty;
 use crate::core::ser::{
 	self, ProtocolVersion, Readable, Reader, StreamingReader, Writeable, Writer,
 };
 use crate::core::{consensus, global};
+use crate::recon::{self, Sketch, MAX_RECON_SET_SIZE};
 use crate::types::{
 	Capabilities, Error, PeerAddr, ReasonForBan, MAX_BLOCK_HEADERS, MAX_LOCATORS, MAX_PEER_ADDRS,
 };
 use num::FromPrimitive;
 use std::fs::File;
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
 use std::sync::Arc;
 
 /// Kepler's user agent with current version
@@ -67,6 +71,24 @@ enum_from_primitive! {
 		TransactionKernel = 20,
 		KernelDataRequest = 21,
 		KernelDataResponse = 22,
+		GetBlockFilter = 23,
+		BlockFilter = 24,
+		InputProofs = 25,
+		GetBlockManifest = 26,
+		BlockManifest = 27,
+		GetBlockChunk = 28,
+		BlockChunk = 29,
+		GetOutputBitmapSegment = 30,
+		OutputBitmapSegment = 31,
+		GetOutputSegment = 32,
+		OutputSegment = 33,
+		GetRangeProofSegment = 34,
+		RangeProofSegment = 35,
+		GetKernelSegment = 36,
+		KernelSegment = 37,
+		TxSketch = 38,
+		TxReconcileResult = 39,
+		TxAnnouncements = 40,
 	}
 }
 
@@ -84,10 +106,10 @@ fn default_max_msg_size() -> u64 {
 fn max_msg_size(msg_type: Type) -> u64 {
 	match msg_type {
 		Type::Error => 0,
-		Type::Hand => 128,
-		Type::Shake => 88,
-		Type::Ping => 16,
-		Type::Pong => 16,
+		Type::Hand => 144,
+		Type::Shake => 104,
+		Type::Ping => 32,
+		Type::Pong => 32,
 		Type::GetPeerAddrs => 4,
 		Type::PeerAddrs => 4 + (1 + 16 + 2) * MAX_PEER_ADDRS as u64,
 		Type::GetHeaders => 1 + 32 * MAX_LOCATORS as u64,
@@ -106,17 +128,71 @@ fn max_msg_size(msg_type: Type) -> u64 {
 		Type::TransactionKernel => 32,
 		Type::KernelDataRequest => 0,
 		Type::KernelDataResponse => 8,
+		Type::GetBlockFilter => 32,
+		Type::BlockFilter => 44 + 3 * global::max_block_weight() as u64,
+		Type::InputProofs => 4 + 163 * max_input_proofs() as u64,
+		Type::GetBlockManifest => 32,
+		Type::BlockManifest => 44 + 32 * block_chunk::max_chunks() as u64,
+		Type::GetBlockChunk => 37,
+		Type::BlockChunk => 41 + 717 * block_chunk::CHUNK_OUTPUTS as u64,
+		Type::GetOutputBitmapSegment => 41,
+		Type::OutputBitmapSegment => 72 + max_segment_size(SegmentType::Bitmap, 128),
+		Type::GetOutputSegment => 41,
+		Type::OutputSegment => 32 + max_segment_size(SegmentType::Output, 34),
+		Type::GetRangeProofSegment => 41,
+		Type::RangeProofSegment => 32 + max_segment_size(SegmentType::RangeProof, 683),
+		Type::GetKernelSegment => 41,
+		Type::KernelSegment => 32 + max_segment_size(SegmentType::Kernel, 114),
+		Type::TxSketch => 12 + recon::SKETCH_CELL_SIZE * recon::MAX_SKETCH_CELLS as u64,
+		Type::TxReconcileResult => 17 + 8 * MAX_RECON_SET_SIZE as u64 + max_tx_announcements_size(),
+		Type::TxAnnouncements => max_tx_announcements_size(),
 	}
 }
 
+// Max size of a txhashset segment with leaves of the given size, each leaf
+// or pruned subtree taking its size plus its position, along with the
+// identifier, the lengths and the proof.
+fn max_segment_size(segment_type: SegmentType, leaf_size: u64) -> u64 {
+	let nodes = 1 << segment_type.height();
+	9 + 3 * 8 + 32 * 128 + nodes * (8 + leaf_size.max(32))
+}
+
+// Most input proofs in a message, as many as the inputs a block can hold.
+fn max_input_proofs() -> u32 {
+	(global::max_block_weight() / consensus::BLOCK_INPUT_WEIGHT) as u32
+}
+
+// Max size of a batch of tx announcements, the kernel hashes along with the
+// proofs of their inputs.
+fn max_tx_announcements_size() -> u64 {
+	8 + 32 * MAX_RECON_SET_SIZE as u64 + 163 * max_input_proofs() as u64
+}
+
 fn magic() -> [u8; 2] {
-	match *global::CHAIN_TYPE.read() {
+	chain_magic(&global::CHAIN_TYPE.read())
+}
+
+fn chain_magic(chain_type: &global::ChainTypes) -> [u8; 2] {
+	match *chain_type {
 		global::ChainTypes::Floonet => FLOONET_MAGIC,
 		global::ChainTypes::Mainnet => MAINNET_MAGIC,
 		_ => OTHER_MAGIC,
 	}
 }
 
+/// Chain type of the provided magic bytes, if any. The testing chain types
+/// share theirs, told as user testing.
+pub fn magic_chain_type(magic: [u8; 2]) -> Option<global::ChainTypes> {
+	[
+		global::ChainTypes::Floonet,
+		global::ChainTypes::Mainnet,
+		global::ChainTypes::UserTesting,
+	]
+	.iter()
+	.find(|c| chain_magic(c) == magic)
+	.cloned()
+}
+
 pub struct Msg {
 	header: MsgHeader,
 	body: Vec<u8>,
@@ -142,6 +218,16 @@ impl Msg {
 	pub fn add_attachment(&mut self, attachment: File) {
 		self.attachment = Some(attachment)
 	}
+
+	#[cfg(test)]
+	pub(crate) fn header(&self) -> &MsgHeader {
+		&self.header
+	}
+
+	#[cfg(test)]
+	pub(crate) fn body(&self) -> &[u8] {
+		&self.body
+	}
 }
 
 /// Read a header from the provided stream without blocking if the
@@ -156,6 +242,13 @@ pub fn read_header(
 ) -> Result<MsgHeaderWrapper, Error> {
 	let mut head = vec![0u8; MsgHeader::LEN];
 	stream.read_exact(&mut head)?;
+
+	// Checked ahead of the rest of the header, so a peer of another network
+	// can be told apart from a misbehaving one.
+	let (us, peer) = (magic(), [head[0], head[1]]);
+	if peer != us {
+		return Err(Error::MagicMismatch { us, peer });
+	}
 	let header = ser::deserialize::<MsgHeaderWrapper>(&mut &head[..], version)?;
 	Ok(header)
 }
@@ -335,6 +428,45 @@ impl Readable for MsgHeaderWrapper {
 	}
 }
 
+// Sender clock (unix timestamp, in seconds) trailing the handshake and ping
+// messages. Older peers neither send it nor read it, the extra bytes at the
+// end of a message are ignored.
+fn write_timestamp<W: Writer>(writer: &mut W, timestamp: Option<i64>) -> Result<(), ser::Error> {
+	match timestamp {
+		Some(timestamp) => writer.write_i64(timestamp),
+		None => Ok(()),
+	}
+}
+
+fn read_timestamp(reader: &mut dyn Reader) -> Result<Option<i64>, ser::Error> {
+	match reader.read_i64() {
+		Ok(timestamp) => Ok(Some(timestamp)),
+		Err(ser::Error::IOErr(_, io::ErrorKind::UnexpectedEof)) => Ok(None),
+		Err(e) => Err(e),
+	}
+}
+
+// Height of the oldest full block a pruned sender can serve, trailing its
+// clock. Only written after the clock, a body tail alone would be read as one.
+fn write_body_tail<W: Writer>(
+	writer: &mut W,
+	timestamp: Option<i64>,
+	body_tail: Option<u64>,
+) -> Result<(), ser::Error> {
+	match (timestamp, body_tail) {
+		(Some(_), Some(body_tail)) => writer.write_u64(body_tail),
+		_ => Ok(()),
+	}
+}
+
+fn read_body_tail(reader: &mut dyn Reader) -> Result<Option<u64>, ser::Error> {
+	match reader.read_u64() {
+		Ok(body_tail) => Ok(Some(body_tail)),
+		Err(ser::Error::IOErr(_, io::ErrorKind::UnexpectedEof)) => Ok(None),
+		Err(e) => Err(e),
+	}
+}
+
 /// First part of a handshake, sender advertises its version and
 /// characteristics.
 pub struct Hand {
@@ -355,6 +487,11 @@ pub struct Hand {
 	pub receiver_addr: PeerAddr,
 	/// name of version of the software
 	pub user_agent: String,
+	/// clock of the sender, absent from older peers
+	pub timestamp: Option<i64>,
+	/// height of the oldest full block the sender can serve, absent from
+	/// older peers
+	pub body_tail: Option<u64>,
 }
 
 impl Writeable for Hand {
@@ -370,7 +507,8 @@ impl Writeable for Hand {
 		self.receiver_addr.write(writer)?;
 		writer.write_bytes(&self.user_agent)?;
 		self.genesis.write(writer)?;
-		Ok(())
+		write_timestamp(writer, self.timestamp)?;
+		write_body_tail(writer, self.timestamp, self.body_tail)
 	}
 }
 
@@ -385,6 +523,8 @@ impl Readable for Hand {
 		let ua = reader.read_bytes_len_prefix()?;
 		let user_agent = String::from_utf8(ua).map_err(|_| ser::Error::CorruptedData)?;
 		let genesis = Hash::read(reader)?;
+		let timestamp = read_timestamp(reader)?;
+		let body_tail = read_body_tail(reader)?;
 		Ok(Hand {
 			version,
 			capabilities,
@@ -394,6 +534,8 @@ impl Readable for Hand {
 			sender_addr,
 			receiver_addr,
 			user_agent,
+			timestamp,
+			body_tail,
 		})
 	}
 }
@@ -412,6 +554,11 @@ pub struct Shake {
 	pub total_difficulty: Difficulty,
 	/// name of version of the software
 	pub user_agent: String,
+	/// clock of the sender, absent from older peers
+	pub timestamp: Option<i64>,
+	/// height of the oldest full block the sender can serve, absent from
+	/// older peers
+	pub body_tail: Option<u64>,
 }
 
 impl Writeable for Shake {
@@ -421,7 +568,8 @@ impl Writeable for Shake {
 		self.total_difficulty.write(writer)?;
 		writer.write_bytes(&self.user_agent)?;
 		self.genesis.write(writer)?;
-		Ok(())
+		write_timestamp(writer, self.timestamp)?;
+		write_body_tail(writer, self.timestamp, self.body_tail)
 	}
 }
 
@@ -434,12 +582,16 @@ impl Readable for Shake {
 		let ua = reader.read_bytes_len_prefix()?;
 		let user_agent = String::from_utf8(ua).map_err(|_| ser::Error::CorruptedData)?;
 		let genesis = Hash::read(reader)?;
+		let timestamp = read_timestamp(reader)?;
+		let body_tail = read_body_tail(reader)?;
 		Ok(Shake {
 			version,
 			capabilities,
 			genesis,
 			total_difficulty,
 			user_agent,
+			timestamp,
+			body_tail,
 		})
 	}
 }
@@ -570,19 +722,41 @@ impl Writeable for Headers {
 	}
 }
 
+/// Reads a whole Headers message at once, as the API serves it to the clients
+/// not on the network. Peers stream theirs instead.
+impl Readable for Headers {
+	fn read(reader: &mut dyn Reader) -> Result<Headers, ser::Error> {
+		let len = reader.read_u16()?;
+		if len as u32 > MAX_BLOCK_HEADERS {
+			return Err(ser::Error::TooLargeReadErr);
+		}
+		let mut headers = Vec::with_capacity(len as usize);
+		for _ in 0..len {
+			headers.push(UntrustedBlockHeader::read(reader)?.into());
+		}
+		Ok(Headers { headers })
+	}
+}
+
 pub struct Ping {
 	/// total difficulty accumulated by the sender, used to check whether sync
 	/// may be needed
 	pub total_difficulty: Difficulty,
 	/// total height
 	pub height: u64,
+	/// clock of the sender, absent from older peers
+	pub timestamp: Option<i64>,
+	/// height of the oldest full block the sender can serve, absent from
+	/// older peers
+	pub body_tail: Option<u64>,
 }
 
 impl Writeable for Ping {
 	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
 		self.total_difficulty.write(writer)?;
 		self.height.write(writer)?;
-		Ok(())
+		write_timestamp(writer, self.timestamp)?;
+		write_body_tail(writer, self.timestamp, self.body_tail)
 	}
 }
 
@@ -590,9 +764,13 @@ impl Readable for Ping {
 	fn read(reader: &mut dyn Reader) -> Result<Ping, ser::Error> {
 		let total_difficulty = Difficulty::read(reader)?;
 		let height = reader.read_u64()?;
+		let timestamp = read_timestamp(reader)?;
+		let body_tail = read_body_tail(reader)?;
 		Ok(Ping {
 			total_difficulty,
 			height,
+			timestamp,
+			body_tail,
 		})
 	}
 }
@@ -603,13 +781,19 @@ pub struct Pong {
 	pub total_difficulty: Difficulty,
 	/// height accumulated by sender
 	pub height: u64,
+	/// clock of the sender, absent from older peers
+	pub timestamp: Option<i64>,
+	/// height of the oldest full block the sender can serve, absent from
+	/// older peers
+	pub body_tail: Option<u64>,
 }
 
 impl Writeable for Pong {
 	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
 		self.total_difficulty.write(writer)?;
 		self.height.write(writer)?;
-		Ok(())
+		write_timestamp(writer, self.timestamp)?;
+		write_body_tail(writer, self.timestamp, self.body_tail)
 	}
 }
 
@@ -617,9 +801,13 @@ impl Readable for Pong {
 	fn read(reader: &mut dyn Reader) -> Result<Pong, ser::Error> {
 		let total_difficulty = Difficulty::read(reader)?;
 		let height = reader.read_u64()?;
+		let timestamp = read_timestamp(reader)?;
+		let body_tail = read_body_tail(reader)?;
 		Ok(Pong {
 			total_difficulty,
 			height,
+			timestamp,
+			body_tail,
 		})
 	}
 }
@@ -735,3 +923,297 @@ impl Readable for KernelDataResponse {
 		Ok(KernelDataResponse { bytes })
 	}
 }
+
+/// Compact filter of the commitments of a block, in response to a
+/// GetBlockFilter.
+pub struct BlockFilterResponse {
+	/// Hash of the block, also the key of the filter.
+	pub hash: Hash,
+	/// Filter of the output and input commitments of the block.
+	pub filter: BlockFilter,
+}
+
+impl Writeable for BlockFilterResponse {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.hash.write(writer)?;
+		self.filter.write(writer)
+	}
+}
+
+impl Readable for BlockFilterResponse {
+	fn read(reader: &mut dyn Reader) -> Result<BlockFilterResponse, ser::Error> {
+		let hash = Hash::read(reader)?;
+		let filter = BlockFilter::read(reader)?;
+		Ok(BlockFilterResponse { hash, filter })
+	}
+}
+
+/// Proofs of the ability to spend the inputs of a tx, sent ahead of the tx
+/// to the peers asking for them.
+pub struct InputProofs {
+	pub proofs: Vec<InputProof>,
+}
+
+impl Writeable for InputProofs {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_u32(self.proofs.len() as u32)?;
+		for proof in &self.proofs {
+			proof.write(writer)?;
+		}
+		Ok(())
+	}
+}
+
+impl Readable for InputProofs {
+	fn read(reader: &mut dyn Reader) -> Result<InputProofs, ser::Error> {
+		let count = reader.read_u32()?;
+		if count > max_input_proofs() {
+			return Err(ser::Error::TooLargeReadErr);
+		}
+		let mut proofs = Vec::with_capacity(count as usize);
+		for _ in 0..count {
+			proofs.push(InputProof::read(reader)?);
+		}
+		Ok(InputProofs { proofs })
+	}
+}
+
+/// Request for a chunk of the body of a block, after its manifest.
+pub struct BlockChunkRequest {
+	/// Hash of the block.
+	pub hash: Hash,
+	/// Part of the body the chunk is of.
+	pub part: ChunkPart,
+	/// Index of the chunk among those of its part.
+	pub index: u32,
+}
+
+impl Writeable for BlockChunkRequest {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.hash.write(writer)?;
+		self.part.write(writer)?;
+		writer.write_u32(self.index)
+	}
+}
+
+impl Readable for BlockChunkRequest {
+	fn read(reader: &mut dyn Reader) -> Result<BlockChunkRequest, ser::Error> {
+		let hash = Hash::read(reader)?;
+		let part = ChunkPart::read(reader)?;
+		let index = reader.read_u32()?;
+		Ok(BlockChunkRequest { hash, part, index })
+	}
+}
+
+/// Request for a segment of the txhashset at a block, the one served by the
+/// peer for fast sync.
+pub struct SegmentRequest {
+	/// Hash of the block the txhashset is at.
+	pub block_hash: Hash,
+	/// Identifier of the segment.
+	pub identifier: SegmentIdentifier,
+}
+
+impl Writeable for SegmentRequest {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.block_hash.write(writer)?;
+		self.identifier.write(writer)
+	}
+}
+
+impl Readable for SegmentRequest {
+	fn read(reader: &mut dyn Reader) -> Result<SegmentRequest, ser::Error> {
+		let block_hash = Hash::read(reader)?;
+		let identifier = SegmentIdentifier::read(reader)?;
+		Ok(SegmentRequest {
+			block_hash,
+			identifier,
+		})
+	}
+}
+
+/// A segment of the output, rangeproof or kernel MMR at a block.
+pub struct SegmentResponse<T> {
+	/// Hash of the block the txhashset is at.
+	pub block_hash: Hash,
+	/// The segment.
+	pub segment: Segment<T>,
+}
+
+impl<T: Writeable> Writeable for SegmentResponse<T> {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.block_hash.write(writer)?;
+		self.segment.write(writer)
+	}
+}
+
+impl<T: Readable> Readable for SegmentResponse<T> {
+	fn read(reader: &mut dyn Reader) -> Result<SegmentResponse<T>, ser::Error> {
+		let block_hash = Hash::read(reader)?;
+		let segment = Segment::read(reader)?;
+		Ok(SegmentResponse {
+			block_hash,
+			segment,
+		})
+	}
+}
+
+/// A segment of the bitmap accumulator of the unspent outputs at a block,
+/// along with what's needed to check it against the output root of the
+/// block header.
+pub struct OutputBitmapSegmentResponse {
+	/// Hash of the block the txhashset is at.
+	pub block_hash: Hash,
+	/// The segment.
+	pub segment: Segment<BitmapChunk>,
+	/// Root of the output MMR.
+	pub output_root: Hash,
+	/// Size of the bitmap accumulator MMR.
+	pub bitmap_mmr_size: u64,
+}
+
+impl Writeable for OutputBitmapSegmentResponse {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		self.block_hash.write(writer)?;
+		self.segment.write(writer)?;
+		self.output_root.write(writer)?;
+		writer.write_u64(self.bitmap_mmr_size)
+	}
+}
+
+impl Readable for OutputBitmapSegmentResponse {
+	fn read(reader: &mut dyn Reader) -> Result<OutputBitmapSegmentResponse, ser::Error> {
+		let block_hash = Hash::read(reader)?;
+		let segment = Segment::read(reader)?;
+		let output_root = Hash::read(reader)?;
+		let bitmap_mmr_size = reader.read_u64()?;
+		Ok(OutputBitmapSegmentResponse {
+			block_hash,
+			segment,
+			output_root,
+			bitmap_mmr_size,
+		})
+	}
+}
+
+/// Sketch of the tx announcements queued for a peer, starting their
+/// reconciliation with the ones it queued for us.
+pub struct TxSketch {
+	/// Salt of the short ids of the transactions.
+	pub salt: u64,
+	/// Sketch of the short ids.
+	pub sketch: Sketch,
+}
+
+impl Writeable for TxSketch {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_u64(self.salt)?;
+		self.sketch.write(writer)
+	}
+}
+
+impl Readable for TxSketch {
+	fn read(reader: &mut dyn Reader) -> Result<TxSketch, ser::Error> {
+		let salt = reader.read_u64()?;
+		let sketch = Sketch::read(reader)?;
+		Ok(TxSketch { salt, sketch })
+	}
+}
+
+/// Answer to a tx sketch: the short ids of the transactions the peer lacks,
+/// along with the announcements of the ones we lack.
+pub struct TxReconcileResult {
+	/// Whether the difference of the sketches could be decoded. If not, all
+	/// the queued transactions are announced both ways.
+	pub decoded: bool,
+	/// Number of announcements we had queued, to size the next sketch.
+	pub set_size: u32,
+	/// Short ids of the transactions to announce to us.
+	pub missing: Vec<u64>,
+	/// Announcements of the transactions the peer lacks.
+	pub announcements: TxAnnouncements,
+}
+
+impl Writeable for TxReconcileResult {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_u8(self.decoded as u8)?;
+		writer.write_u32(self.set_size)?;
+		writer.write_u32(self.missing.len() as u32)?;
+		for id in &self.missing {
+			writer.write_u64(*id)?;
+		}
+		self.announcements.write(writer)
+	}
+}
+
+impl Readable for TxReconcileResult {
+	fn read(reader: &mut dyn Reader) -> Result<TxReconcileResult, ser::Error> {
+		let decoded = reader.read_u8()? != 0;
+		let set_size = reader.read_u32()?;
+		let count = reader.read_u32()? as usize;
+		if count > MAX_RECON_SET_SIZE {
+			return Err(ser::Error::TooLargeReadErr);
+		}
+		let mut missing = Vec::with_capacity(count);
+		for _ in 0..count {
+			missing.push(reader.read_u64()?);
+		}
+		let announcements = TxAnnouncements::read(reader)?;
+		Ok(TxReconcileResult {
+			decoded,
+			set_size,
+			missing,
+			announcements,
+		})
+	}
+}
+
+/// Batch of tx announcements, the hashes of their kernels along with the
+/// proofs of their inputs for the peers asking for them.
+pub struct TxAnnouncements {
+	pub kernels: Vec<Hash>,
+	pub proofs: Vec<InputProof>,
+}
+
+impl TxAnnouncements {
+	/// Most input proofs in a batch.
+	pub fn max_proofs() -> u32 {
+		max_input_proofs()
+	}
+}
+
+impl Writeable for TxAnnouncements {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_u32(self.kernels.len() as u32)?;
+		for h in &self.kernels {
+			h.write(writer)?;
+		}
+		writer.write_u32(self.proofs.len() as u32)?;
+		for proof in &self.proofs {
+			proof.write(writer)?;
+		}
+		Ok(())
+	}
+}
+
+impl Readable for TxAnnouncements {
+	fn read(reader: &mut dyn Reader) -> Result<TxAnnouncements, ser::Error> {
+		let count = reader.read_u32()? as usize;
+		if count > MAX_RECON_SET_SIZE {
+			return Err(ser::Error::TooLargeReadErr);
+		}
+		let mut kernels = Vec::with_capacity(count);
+		for _ in 0..count {
+			kernels.push(Hash::read(reader)?);
+		}
+		let count = reader.read_u32()?;
+		if count > max_input_proofs() {
+			return Err(ser::Error::TooLargeReadErr);
+		}
+		let mut proofs = Vec::with_capacity(count as usize);
+		for _ in 0..count {
+			proofs.push(InputProof::read(reader)?);
+		}
+		Ok(TxAnnouncements { kernels, proofs })
+	}
+}
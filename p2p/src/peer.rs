@@ -12,7 +12,9 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::util::{Mutex, RwLock};
+use crate::util::secp::pedersen::RangeProof;
+use crate::util::{MinuteQuota, Mutex, RwLock};
+use std::collections::VecDeque;
 use std::fmt;
 use std::fs::File;
 use std::io::Read;
@@ -23,25 +25,35 @@ use std::sync::Arc;
 
 use lru_cache::LruCache;
 
+use crate::capture::MessageCapture;
 use crate::chain;
+use crate::chain::txhashset::{BitmapChunk, SegmentType};
 use crate::conn;
 use crate::core::core::hash::{Hash, Hashed};
+use crate::core::core::pmmr::{Segment, SegmentIdentifier};
 use crate::core::pow::Difficulty;
 use crate::core::ser::Writeable;
 use crate::core::{core, global};
 use crate::handshake::Handshake;
 use crate::msg::{
-	self, BanReason, GetPeerAddrs, KernelDataRequest, Locator, Msg, Ping, TxHashSetRequest, Type,
+	self, BanReason, BlockChunkRequest, GetPeerAddrs, InputProofs, KernelDataRequest, Locator, Msg,
+	Ping, SegmentRequest, TxHashSetRequest, Type,
 };
 use crate::protocol::Protocol;
+use crate::recon::TxReconciliation;
+use crate::throughput::SyncTransfer;
 use crate::types::{
 	Capabilities, ChainAdapter, Error, NetAdapter, P2PConfig, PeerAddr, PeerInfo, ReasonForBan,
-	TxHashSetRead,
+	TxHashSetRead, MAX_TX_REQUESTS_PER_MIN,
 };
 use chrono::prelude::{DateTime, Utc};
 
 const MAX_TRACK_SIZE: usize = 30;
 const MAX_PEER_MSG_PER_MIN: u64 = 500;
+// Peer address requests we remember the capabilities of, awaiting answers.
+const MAX_PEER_REQUESTS_IN_FLIGHT: usize = 8;
+// Block manifest requests we remember, to drop the manifests not asked for.
+const MAX_MANIFEST_REQUESTS_IN_FLIGHT: usize = 16;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 /// Remind: don't mix up this 'State' with that 'State' in p2p/src/store.rs,
@@ -65,6 +77,14 @@ pub struct Peer {
 	stop_handle: Mutex<conn::StopHandle>,
 	// Whether or not we requested a txhashset from this peer
 	state_sync_requested: Arc<AtomicBool>,
+	// Transactions requested from this peer, to enforce the per peer quota
+	tx_requests: Mutex<MinuteQuota>,
+	// Capabilities of the peer addresses requested and not received yet
+	peers_requested: Arc<Mutex<VecDeque<Capabilities>>>,
+	// Block manifests requested and not received yet
+	manifests_requested: Arc<Mutex<VecDeque<Hash>>>,
+	// Tx announcements queued for this peer, if both sides reconcile them
+	tx_recon: Option<Arc<Mutex<TxReconciliation>>>,
 }
 
 impl fmt::Debug for Peer {
@@ -75,17 +95,35 @@ impl fmt::Debug for Peer {
 
 impl Peer {
 	// Only accept and connect can be externally used to build a peer
-	fn new(info: PeerInfo, conn: TcpStream, adapter: Arc<dyn NetAdapter>) -> std::io::Result<Peer> {
+	fn new(
+		info: PeerInfo,
+		capab: Capabilities,
+		conn: TcpStream,
+		adapter: Arc<dyn NetAdapter>,
+		capture: Option<Arc<MessageCapture>>,
+	) -> std::io::Result<Peer> {
 		let state = Arc::new(RwLock::new(State::Connected));
 		let state_sync_requested = Arc::new(AtomicBool::new(false));
+		let peers_requested = Arc::new(Mutex::new(VecDeque::new()));
+		let manifests_requested = Arc::new(Mutex::new(VecDeque::new()));
+		let tx_recon = if capab.contains(Capabilities::TX_RECONCILIATION)
+			&& info.capabilities.contains(Capabilities::TX_RECONCILIATION)
+		{
+			Some(Arc::new(Mutex::new(TxReconciliation::new())))
+		} else {
+			None
+		};
 		let tracking_adapter = TrackingAdapter::new(adapter);
 		let handler = Protocol::new(
 			Arc::new(tracking_adapter.clone()),
 			info.clone(),
 			state_sync_requested.clone(),
+			peers_requested.clone(),
+			manifests_requested.clone(),
+			tx_recon.clone(),
 		);
 		let tracker = Arc::new(conn::Tracker::new());
-		let (sendh, stoph) = conn::listen(conn, info.version, tracker.clone(), handler)?;
+		let (sendh, stoph) = conn::listen(conn, info.version, tracker.clone(), handler, capture)?;
 		let send_handle = Mutex::new(sendh);
 		let stop_handle = Mutex::new(stoph);
 		Ok(Peer {
@@ -96,6 +134,10 @@ impl Peer {
 			send_handle,
 			stop_handle,
 			state_sync_requested,
+			tx_requests: Mutex::new(MinuteQuota::new(MAX_TX_REQUESTS_PER_MIN)),
+			peers_requested,
+			manifests_requested,
+			tx_recon,
 		})
 	}
 
@@ -105,11 +147,13 @@ impl Peer {
 		total_difficulty: Difficulty,
 		hs: &Handshake,
 		adapter: Arc<dyn NetAdapter>,
+		capture: Option<Arc<MessageCapture>>,
 	) -> Result<Peer, Error> {
 		debug!("accept: handshaking from {:?}", conn.peer_addr());
-		let info = hs.accept(capab, total_difficulty, &mut conn);
+		let body_tail = adapter.body_tail().ok();
+		let info = hs.accept(capab, total_difficulty, body_tail, &mut conn);
 		match info {
-			Ok(info) => Ok(Peer::new(info, conn, adapter)?),
+			Ok(info) => Ok(Peer::new(info, capab, conn, adapter, capture)?),
 			Err(e) => {
 				debug!(
 					"accept: handshaking from {:?} failed with error: {:?}",
@@ -131,11 +175,13 @@ impl Peer {
 		self_addr: PeerAddr,
 		hs: &Handshake,
 		adapter: Arc<dyn NetAdapter>,
+		capture: Option<Arc<MessageCapture>>,
 	) -> Result<Peer, Error> {
 		debug!("connect: handshaking with {:?}", conn.peer_addr());
-		let info = hs.initiate(capab, total_difficulty, self_addr, &mut conn);
+		let body_tail = adapter.body_tail().ok();
+		let info = hs.initiate(capab, total_difficulty, body_tail, self_addr, &mut conn);
 		match info {
-			Ok(info) => Ok(Peer::new(info, conn, adapter)?),
+			Ok(info) => Ok(Peer::new(info, capab, conn, adapter, capture)?),
 			Err(e) => {
 				debug!(
 					"connect: handshaking with {:?} failed with error: {:?}",
@@ -239,12 +285,19 @@ impl Peer {
 		self.send_handle.lock().send(msg)
 	}
 
-	/// Send a ping to the remote peer, providing our local difficulty and
-	/// height
-	pub fn send_ping(&self, total_difficulty: Difficulty, height: u64) -> Result<(), Error> {
+	/// Send a ping to the remote peer, providing our local difficulty, height
+	/// and body tail
+	pub fn send_ping(
+		&self,
+		total_difficulty: Difficulty,
+		height: u64,
+		body_tail: Option<u64>,
+	) -> Result<(), Error> {
 		let ping_msg = Ping {
 			total_difficulty,
 			height,
+			timestamp: Some(Utc::now().timestamp()),
+			body_tail,
 		};
 		self.send(ping_msg, msg::Type::Ping)
 	}
@@ -316,11 +369,31 @@ impl Peer {
 		}
 	}
 
-	/// Sends the provided transaction to the remote peer. The request may be
-	/// dropped if the remote peer is known to already have the transaction.
+	/// Sends the proofs of the ability to spend the inputs of a tx, if the
+	/// remote peer asks for them.
+	pub fn send_input_proofs(&self, proofs: &[core::InputProof]) -> Result<(), Error> {
+		if proofs.is_empty() || !self.info.capabilities.contains(Capabilities::INPUT_PROOFS) {
+			return Ok(());
+		}
+		debug!("Send {} input proofs to {}", proofs.len(), self.info.addr);
+		self.send(
+			&InputProofs {
+				proofs: proofs.to_vec(),
+			},
+			msg::Type::InputProofs,
+		)
+	}
+
+	/// Sends the provided transaction to the remote peer, preceded by the
+	/// proofs of its inputs. The request may be dropped if the remote peer
+	/// is known to already have the transaction.
 	/// We support broadcast of lightweight tx kernel hash
 	/// so track known txs by kernel hash.
-	pub fn send_transaction(&self, tx: &core::Transaction) -> Result<bool, Error> {
+	pub fn send_transaction(
+		&self,
+		tx: &core::Transaction,
+		proofs: &[core::InputProof],
+	) -> Result<bool, Error> {
 		let kernel = &tx.kernels()[0];
 
 		if self
@@ -328,11 +401,16 @@ impl Peer {
 			.capabilities
 			.contains(Capabilities::TX_KERNEL_HASH)
 		{
+			// The peer gets the proofs before asking for the tx.
+			if !self.tracking_adapter.has_recv(kernel.hash()) {
+				self.send_input_proofs(proofs)?;
+			}
 			return self.send_tx_kernel_hash(kernel.hash());
 		}
 
 		if !self.tracking_adapter.has_recv(kernel.hash()) {
 			debug!("Send full tx {} to {}", tx.hash(), self.info.addr);
+			self.send_input_proofs(proofs)?;
 			self.send(tx, msg::Type::Transaction)?;
 			Ok(true)
 		} else {
@@ -345,20 +423,87 @@ impl Peer {
 		}
 	}
 
+	/// Whether tx announcements to the remote peer are reconciled rather
+	/// than flooded.
+	pub fn reconciles_txs(&self) -> bool {
+		self.tx_recon.is_some()
+	}
+
+	/// Queues the announcement of the provided transaction for the next
+	/// reconciliation with the remote peer. The transaction is sent right
+	/// away if the peer doesn't reconcile announcements or too many are
+	/// queued already.
+	pub fn queue_transaction(
+		&self,
+		tx: &core::Transaction,
+		proofs: &[core::InputProof],
+	) -> Result<bool, Error> {
+		let kernel_hash = tx.kernels()[0].hash();
+		if self.tracking_adapter.has_recv(kernel_hash) {
+			return Ok(false);
+		}
+		if let Some(ref tx_recon) = self.tx_recon {
+			if tx_recon.lock().queue(kernel_hash, proofs) {
+				trace!("Queue tx {} for {}", kernel_hash, self.info.addr);
+				return Ok(true);
+			}
+		}
+		self.send_transaction(tx, proofs)
+	}
+
+	/// Starts the reconciliation of the tx announcements queued for the
+	/// remote peer with the ones it queued for us, sending it the sketch of
+	/// ours.
+	pub fn send_tx_sketch(&self) -> Result<(), Error> {
+		let sketch = match self.tx_recon {
+			Some(ref tx_recon) => {
+				let mut tx_recon = tx_recon.lock();
+				tx_recon.forget(|h| self.tracking_adapter.has_recv(*h));
+				tx_recon.initiate()
+			}
+			None => None,
+		};
+		if let Some(sketch) = sketch {
+			trace!(
+				"Send tx sketch of {} cells to {}",
+				sketch.sketch.len(),
+				self.info.addr
+			);
+			self.send(sketch, msg::Type::TxSketch)?;
+		}
+		Ok(())
+	}
+
 	/// Sends the provided stem transaction to the remote peer.
 	/// Note: tracking adapter is ignored for stem transactions (while under
 	/// embargo).
-	pub fn send_stem_transaction(&self, tx: &core::Transaction) -> Result<(), Error> {
+	pub fn send_stem_transaction(
+		&self,
+		tx: &core::Transaction,
+		proofs: &[core::InputProof],
+	) -> Result<(), Error> {
 		debug!("Send (stem) tx {} to {}", tx.hash(), self.info.addr);
+		self.send_input_proofs(proofs)?;
 		self.send(tx, msg::Type::StemTransaction)
 	}
 
 	/// Sends a request for block headers from the provided block locator
 	pub fn send_header_request(&self, locator: Vec<Hash>) -> Result<(), Error> {
+		self.info.sync_requested(SyncTransfer::Headers);
 		self.send(&Locator { hashes: locator }, msg::Type::GetHeaders)
 	}
 
+	/// Sends a request for a specific transaction by kernel hash.
+	/// Requests over the per peer quota are dropped, the peer would not
+	/// serve them anyway.
 	pub fn send_tx_request(&self, h: Hash) -> Result<(), Error> {
+		if !self.tx_requests.lock().take() {
+			debug!(
+				"Not requesting tx (kernel hash) {} from peer {} (over quota).",
+				h, self.info.addr
+			);
+			return Ok(());
+		}
 		debug!(
 			"Requesting tx (kernel hash) {} from peer {}.",
 			h, self.info.addr
@@ -371,9 +516,84 @@ impl Peer {
 	pub fn send_block_request(&self, h: Hash, opts: chain::Options) -> Result<(), Error> {
 		debug!("Requesting block {} from peer {}.", h, self.info.addr);
 		self.tracking_adapter.push_req(h, opts);
+		if opts.contains(chain::Options::SYNC) {
+			self.info.sync_requested(SyncTransfer::Blocks);
+		}
 		self.send(&h, msg::Type::GetBlock)
 	}
 
+	/// Sends a request for the manifest of the chunks of a block body, to
+	/// download the body in chunks.
+	pub fn send_block_manifest_request(&self, h: Hash) -> Result<(), Error> {
+		debug!("Requesting block manifest {} from {}", h, self.info.addr);
+		{
+			let mut requested = self.manifests_requested.lock();
+			if !requested.contains(&h) {
+				if requested.len() >= MAX_MANIFEST_REQUESTS_IN_FLIGHT {
+					requested.pop_front();
+				}
+				requested.push_back(h);
+			}
+		}
+		self.send(&h, msg::Type::GetBlockManifest)
+	}
+
+	/// Sends a request for a chunk of a block body, listed in its manifest.
+	pub fn send_block_chunk_request(
+		&self,
+		h: Hash,
+		part: core::ChunkPart,
+		index: u32,
+	) -> Result<(), Error> {
+		trace!(
+			"Requesting chunk {:?} {} of block {} from {}",
+			part,
+			index,
+			h,
+			self.info.addr
+		);
+		self.info.sync_requested(SyncTransfer::Blocks);
+		self.send(
+			&BlockChunkRequest {
+				hash: h,
+				part,
+				index,
+			},
+			msg::Type::GetBlockChunk,
+		)
+	}
+
+	/// Sends a request for a segment of one of the MMRs of the txhashset at
+	/// the block, to download it from several peers at once.
+	pub fn send_segment_request(
+		&self,
+		segment_type: SegmentType,
+		block_hash: Hash,
+		identifier: SegmentIdentifier,
+	) -> Result<(), Error> {
+		trace!(
+			"Requesting {:?} segment {} at {} from {}",
+			segment_type,
+			identifier.idx,
+			block_hash,
+			self.info.addr
+		);
+		let msg_type = match segment_type {
+			SegmentType::Bitmap => msg::Type::GetOutputBitmapSegment,
+			SegmentType::Output => msg::Type::GetOutputSegment,
+			SegmentType::RangeProof => msg::Type::GetRangeProofSegment,
+			SegmentType::Kernel => msg::Type::GetKernelSegment,
+		};
+		self.info.sync_requested(SyncTransfer::TxHashSet);
+		self.send(
+			&SegmentRequest {
+				block_hash,
+				identifier,
+			},
+			msg_type,
+		)
+	}
+
 	/// Sends a request for a specific compact block by hash
 	pub fn send_compact_block_request(&self, h: Hash) -> Result<(), Error> {
 		debug!("Requesting compact block {} from {}", h, self.info.addr);
@@ -382,6 +602,15 @@ impl Peer {
 
 	pub fn send_peer_request(&self, capab: Capabilities) -> Result<(), Error> {
 		trace!("Asking {} for more peers {:?}", self.info.addr, capab);
+		{
+			// The peer answers in order, the addresses it sends are the
+			// ones of peers with the capabilities of the oldest request.
+			let mut requested = self.peers_requested.lock();
+			if requested.len() >= MAX_PEER_REQUESTS_IN_FLIGHT {
+				requested.pop_front();
+			}
+			requested.push_back(capab);
+		}
 		self.send(
 			&GetPeerAddrs {
 				capabilities: capab,
@@ -396,6 +625,7 @@ impl Peer {
 			self.info.addr, height, hash
 		);
 		self.state_sync_requested.store(true, Ordering::Relaxed);
+		self.info.sync_requested(SyncTransfer::TxHashSet);
 		self.send(
 			&TxHashSetRequest { hash, height },
 			msg::Type::TxHashSetRequest,
@@ -473,6 +703,10 @@ impl ChainAdapter for TrackingAdapter {
 		self.adapter.total_height()
 	}
 
+	fn body_tail(&self) -> Result<u64, chain::Error> {
+		self.adapter.body_tail()
+	}
+
 	fn get_transaction(&self, kernel_hash: Hash) -> Option<core::Transaction> {
 		self.adapter.get_transaction(kernel_hash)
 	}
@@ -486,10 +720,19 @@ impl ChainAdapter for TrackingAdapter {
 		self.adapter.tx_kernel_received(kernel_hash, peer_info)
 	}
 
+	fn input_proofs_received(
+		&self,
+		proofs: Vec<core::InputProof>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		self.adapter.input_proofs_received(proofs, peer_info)
+	}
+
 	fn transaction_received(
 		&self,
 		tx: core::Transaction,
 		stem: bool,
+		peer_info: &PeerInfo,
 	) -> Result<bool, chain::Error> {
 		// Do not track the tx hash for stem txs.
 		// Otherwise we fail to handle the subsequent fluff or embargo expiration
@@ -498,7 +741,7 @@ impl ChainAdapter for TrackingAdapter {
 			let kernel = &tx.kernels()[0];
 			self.push_recv(kernel.hash());
 		}
-		self.adapter.transaction_received(tx, stem)
+		self.adapter.transaction_received(tx, stem, peer_info)
 	}
 
 	fn block_received(
@@ -552,6 +795,118 @@ impl ChainAdapter for TrackingAdapter {
 		self.adapter.get_block(h)
 	}
 
+	fn get_block_filter(&self, h: Hash) -> Option<core::BlockFilter> {
+		self.adapter.get_block_filter(h)
+	}
+
+	fn get_block_manifest(&self, h: Hash) -> Option<core::BlockManifest> {
+		self.adapter.get_block_manifest(h)
+	}
+
+	fn get_block_chunk(
+		&self,
+		h: Hash,
+		part: core::ChunkPart,
+		index: u32,
+	) -> Option<core::BlockChunk> {
+		self.adapter.get_block_chunk(h, part, index)
+	}
+
+	fn block_manifest_received(
+		&self,
+		manifest: core::BlockManifest,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		self.adapter.block_manifest_received(manifest, peer_info)
+	}
+
+	fn block_chunk_received(
+		&self,
+		chunk: core::BlockChunk,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		self.adapter.block_chunk_received(chunk, peer_info)
+	}
+
+	fn get_bitmap_segment(
+		&self,
+		h: Hash,
+		id: SegmentIdentifier,
+	) -> Option<(Segment<BitmapChunk>, Hash, u64)> {
+		self.adapter.get_bitmap_segment(h, id)
+	}
+
+	fn get_output_segment(
+		&self,
+		h: Hash,
+		id: SegmentIdentifier,
+	) -> Option<Segment<core::OutputIdentifier>> {
+		self.adapter.get_output_segment(h, id)
+	}
+
+	fn get_rangeproof_segment(
+		&self,
+		h: Hash,
+		id: SegmentIdentifier,
+	) -> Option<Segment<RangeProof>> {
+		self.adapter.get_rangeproof_segment(h, id)
+	}
+
+	fn get_kernel_segment(
+		&self,
+		h: Hash,
+		id: SegmentIdentifier,
+	) -> Option<Segment<core::TxKernel>> {
+		self.adapter.get_kernel_segment(h, id)
+	}
+
+	fn bitmap_segment_received(
+		&self,
+		block_hash: Hash,
+		segment: Segment<BitmapChunk>,
+		output_root: Hash,
+		bitmap_mmr_size: u64,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		self.adapter.bitmap_segment_received(
+			block_hash,
+			segment,
+			output_root,
+			bitmap_mmr_size,
+			peer_info,
+		)
+	}
+
+	fn output_segment_received(
+		&self,
+		block_hash: Hash,
+		segment: Segment<core::OutputIdentifier>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		self.adapter
+			.output_segment_received(block_hash, segment, peer_info)
+	}
+
+	fn rangeproof_segment_received(
+		&self,
+		block_hash: Hash,
+		segment: Segment<RangeProof>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		self.adapter
+			.rangeproof_segment_received(block_hash, segment, peer_info)
+	}
+
+	fn kernel_segment_received(
+		&self,
+		block_hash: Hash,
+		segment: Segment<core::TxKernel>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		self.adapter
+			.kernel_segment_received(block_hash, segment, peer_info)
+	}
+
 	fn kernel_data_read(&self) -> Result<File, chain::Error> {
 		self.adapter.kernel_data_read()
 	}
@@ -598,6 +953,14 @@ impl ChainAdapter for TrackingAdapter {
 	fn get_tmpfile_pathname(&self, tmpfile_name: String) -> PathBuf {
 		self.adapter.get_tmpfile_pathname(tmpfile_name)
 	}
+
+	fn peer_connected(&self, peer_info: &PeerInfo) {
+		self.adapter.peer_connected(peer_info)
+	}
+
+	fn peer_disconnected(&self, addr: PeerAddr) {
+		self.adapter.peer_disconnected(addr)
+	}
 }
 
 impl NetAdapter for TrackingAdapter {
@@ -605,8 +968,8 @@ impl NetAdapter for TrackingAdapter {
 		self.adapter.find_peer_addrs(capab)
 	}
 
-	fn peer_addrs_received(&self, addrs: Vec<PeerAddr>) {
-		self.adapter.peer_addrs_received(addrs)
+	fn peer_addrs_received(&self, addrs: Vec<PeerAddr>, capab: Capabilities) {
+		self.adapter.peer_addrs_received(addrs, capab)
 	}
 
 	fn peer_difficulty(&self, addr: PeerAddr, diff: Difficulty, height: u64) {
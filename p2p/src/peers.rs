@@ -12,36 +12,50 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::util::secp::pedersen::RangeProof;
 use crate::util::RwLock;
-use std::collections::HashMap;
+use std::cell::Cell;
+use std::cmp;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::Read;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::thread;
 
 use rand::seq::SliceRandom;
 use rand::thread_rng;
 
 use crate::chain;
+use crate::chain::txhashset::{BitmapChunk, SegmentType};
 use crate::core::core;
 use crate::core::core::hash::{Hash, Hashed};
+use crate::core::core::pmmr::{Segment, SegmentIdentifier};
 use crate::core::global;
 use crate::core::pow::Difficulty;
 use crate::peer::Peer;
-use crate::store::{PeerData, PeerStore, State};
+use crate::store::{PeerData, PeerQuery, PeerStore, State};
 use crate::types::{
-	Capabilities, ChainAdapter, Error, NetAdapter, P2PConfig, PeerAddr, PeerInfo, ReasonForBan,
-	TxHashSetRead, MAX_PEER_ADDRS,
+	Capabilities, ChainAdapter, Direction, Error, IpSubnet, NetAdapter, P2PConfig, PeerAddr,
+	PeerInfo, ReasonForBan, TxHashSetRead, MAX_PEER_ADDRS,
 };
 use chrono::prelude::*;
 use chrono::Duration;
 
 const LOCK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
 
+/// Minimum number of peer clocks needed to estimate the network time.
+const MIN_CLOCK_SAMPLES: usize = 5;
+
+/// Outbound peers a tx still gets flooded to when the other peers reconcile
+/// their announcements, for it to keep propagating fast.
+const RECON_FLOOD_PEERS: usize = 2;
+
 pub struct Peers {
 	pub adapter: Arc<dyn ChainAdapter>,
 	store: PeerStore,
 	peers: RwLock<HashMap<PeerAddr, Arc<Peer>>>,
+	ban_exempt: RwLock<Vec<IpSubnet>>,
 	config: P2PConfig,
 }
 
@@ -50,8 +64,9 @@ impl Peers {
 		Peers {
 			adapter,
 			store,
-			config,
 			peers: RwLock::new(HashMap::new()),
+			ban_exempt: RwLock::new(config.peers_ban_exempt.clone().unwrap_or_default()),
+			config,
 		}
 	}
 
@@ -70,10 +85,12 @@ impl Peers {
 			last_banned: 0,
 			ban_reason: ReasonForBan::None,
 			last_connected: Utc::now().timestamp(),
+			body_tail: peer.info.body_tail(),
 		};
 		debug!("Saving newly connected peer {}.", peer_data.addr);
 		self.save_peer(&peer_data)?;
-		peers.insert(peer_data.addr, peer);
+		peers.insert(peer_data.addr, peer.clone());
+		self.adapter.peer_connected(&peer.info);
 
 		Ok(())
 	}
@@ -81,6 +98,10 @@ impl Peers {
 	/// Add a peer as banned to block future connections, usually due to failed
 	/// handshake
 	pub fn add_banned(&self, addr: PeerAddr, ban_reason: ReasonForBan) -> Result<(), Error> {
+		if self.is_ban_exempt(addr) {
+			info!("Not banning exempt peer {} ({:?}).", addr, ban_reason);
+			return Ok(());
+		}
 		let peer_data = PeerData {
 			addr,
 			capabilities: Capabilities::UNKNOWN,
@@ -89,6 +110,7 @@ impl Peers {
 			last_banned: Utc::now().timestamp(),
 			ban_reason,
 			last_connected: Utc::now().timestamp(),
+			body_tail: None,
 		};
 		debug!("Banning peer {}.", addr);
 		self.save_peer(&peer_data)
@@ -162,6 +184,22 @@ impl Peers {
 		self.outgoing_connected_peers().len() as u32
 	}
 
+	/// Estimated offset of the network time from our clock, in seconds. The
+	/// median of the connected peers clock offsets, so a minority of peers
+	/// with wrong clocks can't move it.
+	pub fn network_time_offset(&self) -> Option<i64> {
+		let mut offsets = self
+			.connected_peers()
+			.iter()
+			.filter_map(|p| p.info.clock_offset())
+			.collect::<Vec<_>>();
+		if offsets.len() < MIN_CLOCK_SAMPLES {
+			return None;
+		}
+		offsets.sort_unstable();
+		Some(offsets[offsets.len() / 2])
+	}
+
 	/// Number of inbound peers currently connected to.
 	pub fn peer_inbound_count(&self) -> u32 {
 		self.incoming_connected_peers().len() as u32
@@ -247,8 +285,13 @@ impl Peers {
 		}
 		false
 	}
-	/// Ban a peer, disconnecting it if we're currently connected
+	/// Ban a peer, disconnecting it if we're currently connected.
+	/// Peers exempt from banning are only ever banned manually.
 	pub fn ban_peer(&self, peer_addr: PeerAddr, ban_reason: ReasonForBan) -> Result<(), Error> {
+		if ban_reason != ReasonForBan::ManualBan && self.is_ban_exempt(peer_addr) {
+			info!("Not banning exempt peer {} ({:?}).", peer_addr, ban_reason);
+			return Ok(());
+		}
 		self.update_state(peer_addr, State::Banned)?;
 
 		match self.get_connected_peer(peer_addr) {
@@ -262,13 +305,44 @@ impl Peers {
 					error!("ban_peer: failed to get peers lock");
 					Error::PeerException
 				})?;
-				peers.remove(&peer.info.addr);
+				if peers.remove(&peer.info.addr).is_some() {
+					self.adapter.peer_disconnected(peer.info.addr);
+				}
 				Ok(())
 			}
 			None => Err(Error::PeerNotFound),
 		}
 	}
 
+	/// Whether the peer is exempt from automatic banning.
+	pub fn is_ban_exempt(&self, peer_addr: PeerAddr) -> bool {
+		let ip = peer_addr.0.ip();
+		self.ban_exempt.read().iter().any(|x| x.contains(ip))
+	}
+
+	/// Peers and subnets exempt from automatic banning.
+	pub fn ban_exemptions(&self) -> Vec<IpSubnet> {
+		self.ban_exempt.read().clone()
+	}
+
+	/// Exempt a peer or subnet from automatic banning. Already banned peers
+	/// stay banned until unbanned.
+	pub fn add_ban_exemption(&self, subnet: IpSubnet) {
+		let mut exempt = self.ban_exempt.write();
+		if !exempt.contains(&subnet) {
+			exempt.push(subnet);
+		}
+	}
+
+	/// Lift the exemption of a peer or subnet from automatic banning,
+	/// returns whether it was exempt.
+	pub fn remove_ban_exemption(&self, subnet: &IpSubnet) -> bool {
+		let mut exempt = self.ban_exempt.write();
+		let len = exempt.len();
+		exempt.retain(|x| x != subnet);
+		exempt.len() < len
+	}
+
 	/// Unban a peer, checks if it exists and banned then unban
 	pub fn unban_peer(&self, peer_addr: PeerAddr) -> Result<(), Error> {
 		debug!("unban_peer: peer {}", peer_addr);
@@ -305,7 +379,9 @@ impl Peers {
 						}
 					};
 					p.stop();
-					peers.remove(&p.info.addr);
+					if peers.remove(&p.info.addr).is_some() {
+						self.adapter.peer_disconnected(p.info.addr);
+					}
 				}
 			}
 		}
@@ -339,11 +415,68 @@ impl Peers {
 		);
 	}
 
-	/// Broadcasts the provided transaction to all our connected peers.
+	/// Announce a new block by its header to all our connected peers at once,
+	/// then upload its compact block to them one after the other in the
+	/// background, preferred and outbound peers first then the ones with the
+	/// most work. A slow link doesn't hold up the announcement to the other
+	/// peers behind a full block.
+	pub fn announce_block(&self, b: &core::CompactBlock) {
+		let count = self.broadcast("header", |p| p.send_header(&b.header));
+		debug!(
+			"announce_block: header {} at {} to {} peers, uploading the block.",
+			b.hash(),
+			b.header.height,
+			count,
+		);
+
+		let mut peers = self.connected_peers();
+		let preferred = self
+			.config
+			.peers_preferred
+			.clone()
+			.map(|p| p.peers)
+			.unwrap_or_default();
+		peers.sort_by_key(|p| {
+			(
+				!preferred.contains(&p.info.addr),
+				!p.info.is_outbound(),
+				cmp::Reverse(p.info.total_difficulty()),
+			)
+		});
+		let b = b.clone();
+		let _ = thread::Builder::new()
+			.name("block_upload".to_string())
+			.spawn(move || {
+				let mut count = 0;
+				for p in peers {
+					match p.send_compact_block(&b) {
+						Ok(true) => count += 1,
+						Ok(false) => (),
+						Err(e) => debug!(
+							"Error uploading block {} to peer {:?}: {:?}",
+							b.hash(),
+							p.info.addr,
+							e
+						),
+					}
+				}
+				debug!(
+					"announce_block: block {} uploaded to {} peers, done.",
+					b.hash(),
+					count
+				);
+			});
+	}
+
+	/// Broadcasts the provided transaction, and the proofs of its inputs, to
+	/// all our connected peers.
 	/// A peer implementation may drop the broadcast request
 	/// if it knows the remote peer already has the transaction.
-	pub fn broadcast_transaction(&self, tx: &core::Transaction) {
-		let count = self.broadcast("transaction", |p| p.send_transaction(tx));
+	/// The tx is flooded to a few of the outbound peers reconciling their
+	/// announcements, queued for the next reconciliation with the others.
+	pub fn broadcast_transaction(&self, tx: &core::Transaction, proofs: &[core::InputProof]) {
+		let relay = TxRelay::new();
+		let count = self.broadcast("transaction", |p| relay.send(p, tx, proofs));
 		debug!(
 			"broadcast_transaction: {} to {} peers, done.",
 			tx.hash(),
@@ -351,11 +484,25 @@ impl Peers {
 		);
 	}
 
+	/// Reconcile the tx announcements queued for our outbound peers with
+	/// the ones they queued for us. Inbound peers initiate it their side.
+	pub fn reconcile_transactions(&self) {
+		let count = self.broadcast("tx sketch", |p| {
+			if p.info.direction != Direction::Outbound || !p.reconciles_txs() {
+				return Ok(false);
+			}
+			p.send_tx_sketch()?;
+			Ok(true)
+		});
+		trace!("reconcile_transactions: with {} peers", count);
+	}
+
 	/// Ping all our connected peers. Always automatically expects a pong back
 	/// or disconnects. This acts as a liveness test.
 	pub fn check_all(&self, total_difficulty: Difficulty, height: u64) {
+		let body_tail = self.body_tail().ok();
 		for p in self.connected_peers().iter() {
-			if let Err(e) = p.send_ping(total_difficulty, height) {
+			if let Err(e) = p.send_ping(total_difficulty, height, body_tail) {
 				debug!("Error pinging peer {:?}: {:?}", &p.info.addr, e);
 				let mut peers = match self.peers.try_write_for(LOCK_TIMEOUT) {
 					Some(peers) => peers,
@@ -365,7 +512,9 @@ impl Peers {
 					}
 				};
 				p.stop();
-				peers.remove(&p.info.addr);
+				if peers.remove(&p.info.addr).is_some() {
+					self.adapter.peer_disconnected(p.info.addr);
+				}
 			}
 		}
 	}
@@ -381,6 +530,21 @@ impl Peers {
 		}
 	}
 
+	/// Known peers matching the query, along with the total number of
+	/// matching peers
+	pub fn query_peers(&self, query: &PeerQuery) -> Result<(usize, Vec<PeerData>), Error> {
+		let connected = if query.connected {
+			let peers = self.connected_peers();
+			Some(peers.iter().map(|p| p.info.addr).collect::<HashSet<_>>())
+		} else {
+			None
+		};
+		let res = self.store.query_peers(query, |p| {
+			connected.as_ref().map_or(true, |c| c.contains(&p.addr))
+		})?;
+		Ok(res)
+	}
+
 	/// Find peers in store (not necessarily connected) and return their data
 	pub fn find_peers(&self, state: State, cap: Capabilities, count: usize) -> Vec<PeerData> {
 		match self.store.find_peers(state, cap, count) {
@@ -416,8 +580,9 @@ impl Peers {
 
 	/// Iterate over the peer list and prune all peers we have
 	/// lost connection to or have been deemed problematic.
-	/// Also avoid connected peer count getting too high.
-	pub fn clean_peers(&self, max_inbound_count: usize, max_outbound_count: usize) {
+	/// Also avoid connected peer count getting too high, an archive node
+	/// keeping one of its archive peers though.
+	pub fn clean_peers(&self, max_inbound_count: usize, max_outbound_count: usize, archive: bool) {
 		let mut rm = vec![];
 
 		// build a list of peers to be cleaned up
@@ -443,7 +608,9 @@ impl Peers {
 							peer.info.addr, counts.0, counts.1,
 						);
 					}
-					let _ = self.update_state(peer.info.addr, State::Banned);
+					if !self.is_ban_exempt(peer.info.addr) {
+						let _ = self.update_state(peer.info.addr, State::Banned);
+					}
 					rm.push(peer.info.addr.clone());
 				} else {
 					let (stuck, diff) = peer.is_stuck();
@@ -461,6 +628,17 @@ impl Peers {
 			}
 		}
 
+		// an archive node keeps an archive peer connected, for the blocks
+		// beyond the horizon, whatever the number of connections
+		let keep = if archive {
+			self.connected_peers()
+				.iter()
+				.find(|p| p.info.capabilities.contains(Capabilities::ARCHIVE))
+				.map(|p| p.info.addr)
+		} else {
+			None
+		};
+
 		// check here to make sure we don't have too many outgoing connections
 		let excess_outgoing_count =
 			(self.peer_outbound_count() as usize).saturating_sub(max_outbound_count);
@@ -468,8 +646,9 @@ impl Peers {
 			let mut addrs = self
 				.outgoing_connected_peers()
 				.iter()
-				.take(excess_outgoing_count)
 				.map(|x| x.info.addr)
+				.filter(|addr| Some(*addr) != keep)
+				.take(excess_outgoing_count)
 				.collect::<Vec<_>>();
 			rm.append(&mut addrs);
 		}
@@ -481,8 +660,9 @@ impl Peers {
 			let mut addrs = self
 				.incoming_connected_peers()
 				.iter()
-				.take(excess_incoming_count)
 				.map(|x| x.info.addr)
+				.filter(|addr| Some(*addr) != keep)
+				.take(excess_incoming_count)
 				.collect::<Vec<_>>();
 			rm.append(&mut addrs);
 		}
@@ -497,8 +677,10 @@ impl Peers {
 				}
 			};
 			for addr in rm {
-				let _ = peers.get(&addr).map(|peer| peer.stop());
-				peers.remove(&addr);
+				if let Some(peer) = peers.remove(&addr) {
+					peer.stop();
+					self.adapter.peer_disconnected(addr);
+				}
 			}
 		}
 	}
@@ -513,11 +695,24 @@ impl Peers {
 		}
 	}
 
+	/// Whether one of our connected peers is an archive node, serving all
+	/// the blocks back to genesis.
+	pub fn archive_peer_connected(&self) -> bool {
+		self.connected_peers()
+			.iter()
+			.any(|p| p.info.capabilities.contains(Capabilities::ARCHIVE))
+	}
+
 	/// We have enough outbound connected peers
 	pub fn enough_outbound_peers(&self) -> bool {
 		self.peer_outbound_count() >= self.config.peer_min_preferred_outbound_count()
 	}
 
+	/// We have less outbound connected peers than the configured minimum
+	pub fn insufficient_outbound_peers(&self) -> bool {
+		self.peer_outbound_count() < self.config.peer_min_outbound_count()
+	}
+
 	/// Removes those peers that seem to have expired
 	pub fn remove_expired(&self) {
 		let now = Utc::now();
@@ -542,6 +737,58 @@ impl Peers {
 			should_remove
 		});
 	}
+
+	/// Compacts the peer storage: removes the expired peers, then all but
+	/// the max_defunct most recently connected defunct ones. Banned peers
+	/// are kept, their bans have to outlive the compaction.
+	pub fn compact_store(&self, max_defunct: usize) {
+		self.remove_expired();
+
+		let mut defunct = match self.store.all_peers() {
+			Ok(peers) => peers
+				.into_iter()
+				.filter(|p| p.flags == State::Defunct)
+				.collect::<Vec<_>>(),
+			Err(e) => {
+				error!("compact_store: failed to get peers: {:?}", e);
+				return;
+			}
+		};
+		if defunct.len() <= max_defunct {
+			return;
+		}
+		defunct.sort_by_key(|p| cmp::Reverse(p.last_connected));
+		let removed: HashSet<PeerAddr> = defunct[max_defunct..].iter().map(|p| p.addr).collect();
+		debug!("compact_store: removing {} defunct peers", removed.len());
+		if let Err(e) = self.store.delete_peers(|p| removed.contains(&p.addr)) {
+			error!("compact_store: failed to remove peers: {:?}", e);
+		}
+	}
+
+	// Ban the peer that sent a txhashset segment not matching the header it
+	// was requested for, no honest peer sends one.
+	fn segment_received(
+		&self,
+		segment_type: SegmentType,
+		block_hash: Hash,
+		ok: bool,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		if ok {
+			return Ok(true);
+		}
+		debug!(
+			"Received a bad {:?} segment at {} from {}, the peer will be banned",
+			segment_type, block_hash, peer_info.addr
+		);
+		self.ban_peer(peer_info.addr, ReasonForBan::BadTxHashSet)
+			.map_err(|e| {
+				let err: chain::Error =
+					chain::ErrorKind::Other(format!("ban peer error :{:?}", e)).into();
+				err
+			})?;
+		Ok(false)
+	}
 }
 
 impl ChainAdapter for Peers {
@@ -553,6 +800,10 @@ impl ChainAdapter for Peers {
 		self.adapter.total_height()
 	}
 
+	fn body_tail(&self) -> Result<u64, chain::Error> {
+		self.adapter.body_tail()
+	}
+
 	fn get_transaction(&self, kernel_hash: Hash) -> Option<core::Transaction> {
 		self.adapter.get_transaction(kernel_hash)
 	}
@@ -565,12 +816,36 @@ impl ChainAdapter for Peers {
 		self.adapter.tx_kernel_received(kernel_hash, peer_info)
 	}
 
+	fn input_proofs_received(
+		&self,
+		proofs: Vec<core::InputProof>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		if !self.adapter.input_proofs_received(proofs, peer_info)? {
+			// Proofs of unspent outputs that don't verify are forged.
+			debug!(
+				"Received bad input proofs from {}, the peer will be banned",
+				peer_info.addr
+			);
+			self.ban_peer(peer_info.addr, ReasonForBan::BadInputProofs)
+				.map_err(|e| {
+					let err: chain::Error =
+						chain::ErrorKind::Other(format!("ban peer error :{:?}", e)).into();
+					err
+				})?;
+			Ok(false)
+		} else {
+			Ok(true)
+		}
+	}
+
 	fn transaction_received(
 		&self,
 		tx: core::Transaction,
 		stem: bool,
+		peer_info: &PeerInfo,
 	) -> Result<bool, chain::Error> {
-		self.adapter.transaction_received(tx, stem)
+		self.adapter.transaction_received(tx, stem, peer_info)
 	}
 
 	fn block_received(
@@ -672,6 +947,159 @@ impl ChainAdapter for Peers {
 		self.adapter.get_block(h)
 	}
 
+	fn get_block_filter(&self, h: Hash) -> Option<core::BlockFilter> {
+		self.adapter.get_block_filter(h)
+	}
+
+	fn get_block_manifest(&self, h: Hash) -> Option<core::BlockManifest> {
+		self.adapter.get_block_manifest(h)
+	}
+
+	fn get_block_chunk(
+		&self,
+		h: Hash,
+		part: core::ChunkPart,
+		index: u32,
+	) -> Option<core::BlockChunk> {
+		self.adapter.get_block_chunk(h, part, index)
+	}
+
+	fn block_manifest_received(
+		&self,
+		manifest: core::BlockManifest,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		let hash = manifest.hash;
+		if !self.adapter.block_manifest_received(manifest, peer_info)? {
+			// the manifest contradicts the header of the block, no honest
+			// peer sends one
+			debug!(
+				"Received a bad block manifest {} from {}, the peer will be banned",
+				hash, peer_info.addr
+			);
+			self.ban_peer(peer_info.addr, ReasonForBan::BadBlock)
+				.map_err(|e| {
+					let err: chain::Error =
+						chain::ErrorKind::Other(format!("ban peer error :{:?}", e)).into();
+					err
+				})?;
+			Ok(false)
+		} else {
+			Ok(true)
+		}
+	}
+
+	fn block_chunk_received(
+		&self,
+		chunk: core::BlockChunk,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		let hash = chunk.hash;
+		if !self.adapter.block_chunk_received(chunk, peer_info)? {
+			// the block assembled from the chunks of the peer is bad, as if
+			// it had sent it whole
+			debug!(
+				"Received the chunks of a bad block {} from {}, the peer will be banned",
+				hash, peer_info.addr
+			);
+			self.ban_peer(peer_info.addr, ReasonForBan::BadBlock)
+				.map_err(|e| {
+					let err: chain::Error =
+						chain::ErrorKind::Other(format!("ban peer error :{:?}", e)).into();
+					err
+				})?;
+			Ok(false)
+		} else {
+			Ok(true)
+		}
+	}
+
+	fn get_bitmap_segment(
+		&self,
+		h: Hash,
+		id: SegmentIdentifier,
+	) -> Option<(Segment<BitmapChunk>, Hash, u64)> {
+		self.adapter.get_bitmap_segment(h, id)
+	}
+
+	fn get_output_segment(
+		&self,
+		h: Hash,
+		id: SegmentIdentifier,
+	) -> Option<Segment<core::OutputIdentifier>> {
+		self.adapter.get_output_segment(h, id)
+	}
+
+	fn get_rangeproof_segment(
+		&self,
+		h: Hash,
+		id: SegmentIdentifier,
+	) -> Option<Segment<RangeProof>> {
+		self.adapter.get_rangeproof_segment(h, id)
+	}
+
+	fn get_kernel_segment(
+		&self,
+		h: Hash,
+		id: SegmentIdentifier,
+	) -> Option<Segment<core::TxKernel>> {
+		self.adapter.get_kernel_segment(h, id)
+	}
+
+	fn bitmap_segment_received(
+		&self,
+		block_hash: Hash,
+		segment: Segment<BitmapChunk>,
+		output_root: Hash,
+		bitmap_mmr_size: u64,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		let ok = self.adapter.bitmap_segment_received(
+			block_hash,
+			segment,
+			output_root,
+			bitmap_mmr_size,
+			peer_info,
+		)?;
+		self.segment_received(SegmentType::Bitmap, block_hash, ok, peer_info)
+	}
+
+	fn output_segment_received(
+		&self,
+		block_hash: Hash,
+		segment: Segment<core::OutputIdentifier>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		let ok = self
+			.adapter
+			.output_segment_received(block_hash, segment, peer_info)?;
+		self.segment_received(SegmentType::Output, block_hash, ok, peer_info)
+	}
+
+	fn rangeproof_segment_received(
+		&self,
+		block_hash: Hash,
+		segment: Segment<RangeProof>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		let ok = self
+			.adapter
+			.rangeproof_segment_received(block_hash, segment, peer_info)?;
+		self.segment_received(SegmentType::RangeProof, block_hash, ok, peer_info)
+	}
+
+	fn kernel_segment_received(
+		&self,
+		block_hash: Hash,
+		segment: Segment<core::TxKernel>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		let ok = self
+			.adapter
+			.kernel_segment_received(block_hash, segment, peer_info)?;
+		self.segment_received(SegmentType::Kernel, block_hash, ok, peer_info)
+	}
+
 	fn kernel_data_read(&self) -> Result<File, chain::Error> {
 		self.adapter.kernel_data_read()
 	}
@@ -732,6 +1160,14 @@ impl ChainAdapter for Peers {
 	fn get_tmpfile_pathname(&self, tmpfile_name: String) -> PathBuf {
 		self.adapter.get_tmpfile_pathname(tmpfile_name)
 	}
+
+	fn peer_connected(&self, peer_info: &PeerInfo) {
+		self.adapter.peer_connected(peer_info)
+	}
+
+	fn peer_disconnected(&self, addr: PeerAddr) {
+		self.adapter.peer_disconnected(addr)
+	}
 }
 
 impl NetAdapter for Peers {
@@ -743,23 +1179,36 @@ impl NetAdapter for Peers {
 		map_vec!(peers, |p| p.addr)
 	}
 
-	/// A list of peers has been received from one of our peers.
-	fn peer_addrs_received(&self, peer_addrs: Vec<PeerAddr>) {
-		trace!("Received {} peer addrs, saving.", peer_addrs.len());
+	/// A list of peers has been received from one of our peers, recorded
+	/// with the capabilities we asked for, so we can look for them among the
+	/// peers we know, until we connect and learn their actual ones.
+	fn peer_addrs_received(&self, peer_addrs: Vec<PeerAddr>, capab: Capabilities) {
+		trace!(
+			"Received {} peer addrs ({:?}), saving.",
+			peer_addrs.len(),
+			capab
+		);
 		for pa in peer_addrs {
-			if let Ok(e) = self.exists_peer(pa) {
-				if e {
-					continue;
+			let peer = match self.get_peer(pa) {
+				// Only a peer we never connected to can learn capabilities
+				// this way.
+				Ok(mut peer) => {
+					if capab.is_empty() || !peer.capabilities.is_empty() {
+						continue;
+					}
+					peer.capabilities = capab;
+					peer
 				}
-			}
-			let peer = PeerData {
-				addr: pa,
-				capabilities: Capabilities::UNKNOWN,
-				user_agent: "".to_string(),
-				flags: State::Healthy,
-				last_banned: 0,
-				ban_reason: ReasonForBan::None,
-				last_connected: Utc::now().timestamp(),
+				Err(_) => PeerData {
+					addr: pa,
+					capabilities: capab,
+					user_agent: "".to_string(),
+					flags: State::Healthy,
+					last_banned: 0,
+					ban_reason: ReasonForBan::None,
+					last_connected: Utc::now().timestamp(),
+					body_tail: None,
+				},
 			};
 			if let Err(e) = self.save_peer(&peer) {
 				error!("Could not save received peer address: {:?}", e);
@@ -781,3 +1230,34 @@ impl NetAdapter for Peers {
 		}
 	}
 }
+
+/// Relays a transaction to peers one after the other, flooding it to a few
+/// of the outbound peers reconciling their announcements and queueing it for
+/// the next reconciliation with the others.
+#[derive(Default)]
+pub struct TxRelay {
+	flooded: Cell<usize>,
+}
+
+impl TxRelay {
+	pub fn new() -> TxRelay {
+		TxRelay::default()
+	}
+
+	/// Sends or queues the tx for the peer, true if it was relayed.
+	pub fn send(
+		&self,
+		peer: &Peer,
+		tx: &core::Transaction,
+		proofs: &[core::InputProof],
+	) -> Result<bool, Error> {
+		if !peer.reconciles_txs() {
+			return peer.send_transaction(tx, proofs);
+		}
+		if peer.info.direction == Direction::Outbound && self.flooded.get() < RECON_FLOOD_PEERS {
+			self.flooded.set(self.flooded.get() + 1);
+			return peer.send_transaction(tx, proofs);
+		}
+		peer.queue_transaction(tx, proofs)
+	}
+}
@@ -17,13 +17,20 @@ use crate::conn::{Message, MessageHandler, Tracker};
 use crate::core::core::{self, hash::Hash, hash::Hashed, CompactBlock};
 
 use crate::msg::{
-	BanReason, GetPeerAddrs, Headers, KernelDataResponse, Locator, Msg, PeerAddrs, Ping, Pong,
-	TxHashSetArchive, TxHashSetRequest, Type,
+	BanReason, BlockChunkRequest, BlockFilterResponse, GetPeerAddrs, Headers, InputProofs,
+	KernelDataResponse, Locator, Msg, OutputBitmapSegmentResponse, PeerAddrs, Ping, Pong,
+	SegmentRequest, SegmentResponse, TxAnnouncements, TxHashSetArchive, TxHashSetRequest,
+	TxReconcileResult, TxSketch, Type,
 };
-use crate::types::{Error, NetAdapter, PeerInfo};
+use crate::recon::{TxReconciliation, MAX_TX_SKETCHES_PER_MIN};
+use crate::throughput::SyncTransfer;
+use crate::types::{Capabilities, Error, NetAdapter, PeerInfo, MAX_TX_REQUESTS_PER_MIN};
+use crate::util::secp::pedersen::RangeProof;
+use crate::util::{MinuteQuota, Mutex};
 use chrono::prelude::Utc;
 use rand::{thread_rng, Rng};
 use std::cmp;
+use std::collections::VecDeque;
 use std::fs::{self, File, OpenOptions};
 use std::io::{BufWriter, Seek, SeekFrom};
 use std::sync::atomic::{AtomicBool, Ordering};
@@ -35,6 +42,16 @@ pub struct Protocol {
 	adapter: Arc<dyn NetAdapter>,
 	peer_info: PeerInfo,
 	state_sync_requested: Arc<AtomicBool>,
+	// transaction requests served to this peer, to enforce the per peer quota
+	tx_requests: Mutex<MinuteQuota>,
+	// capabilities of the peer addresses requested from this peer, in order
+	peers_requested: Arc<Mutex<VecDeque<Capabilities>>>,
+	// block manifests requested from this peer, the others are dropped
+	manifests_requested: Arc<Mutex<VecDeque<Hash>>>,
+	// tx sketches answered to this peer, to enforce the per peer quota
+	tx_sketches: Mutex<MinuteQuota>,
+	// tx announcements queued for this peer, if both sides reconcile them
+	tx_recon: Option<Arc<Mutex<TxReconciliation>>>,
 }
 
 impl Protocol {
@@ -42,13 +59,40 @@ impl Protocol {
 		adapter: Arc<dyn NetAdapter>,
 		peer_info: PeerInfo,
 		state_sync_requested: Arc<AtomicBool>,
+		peers_requested: Arc<Mutex<VecDeque<Capabilities>>>,
+		manifests_requested: Arc<Mutex<VecDeque<Hash>>>,
+		tx_recon: Option<Arc<Mutex<TxReconciliation>>>,
 	) -> Protocol {
 		Protocol {
 			adapter,
 			peer_info,
 			state_sync_requested,
+			tx_requests: Mutex::new(MinuteQuota::new(MAX_TX_REQUESTS_PER_MIN)),
+			peers_requested,
+			manifests_requested,
+			tx_sketches: Mutex::new(MinuteQuota::new(MAX_TX_SKETCHES_PER_MIN)),
+			tx_recon,
 		}
 	}
+
+	// Whether the peer wants the proofs of the inputs of the txs announced.
+	fn wants_input_proofs(&self) -> bool {
+		self.peer_info
+			.capabilities
+			.contains(Capabilities::INPUT_PROOFS)
+	}
+
+	// Proofs first, for the peer to have them before asking for the txs.
+	fn tx_announcements_received(&self, announcements: TxAnnouncements) -> Result<(), Error> {
+		if !announcements.proofs.is_empty() {
+			self.adapter
+				.input_proofs_received(announcements.proofs, &self.peer_info)?;
+		}
+		for h in announcements.kernels {
+			self.adapter.tx_kernel_received(h, &self.peer_info)?;
+		}
+		Ok(())
+	}
 }
 
 impl MessageHandler for Protocol {
@@ -75,12 +119,16 @@ impl MessageHandler for Protocol {
 			Type::Ping => {
 				let ping: Ping = msg.body()?;
 				adapter.peer_difficulty(self.peer_info.addr, ping.total_difficulty, ping.height);
+				self.peer_info.update_clock(ping.timestamp);
+				self.peer_info.update_body_tail(ping.body_tail);
 
 				Ok(Some(Msg::new(
 					Type::Pong,
 					Pong {
 						total_difficulty: adapter.total_difficulty()?,
 						height: adapter.total_height()?,
+						timestamp: Some(Utc::now().timestamp()),
+						body_tail: adapter.body_tail().ok(),
 					},
 					self.peer_info.version,
 				)?))
@@ -89,6 +137,8 @@ impl MessageHandler for Protocol {
 			Type::Pong => {
 				let pong: Pong = msg.body()?;
 				adapter.peer_difficulty(self.peer_info.addr, pong.total_difficulty, pong.height);
+				self.peer_info.update_clock(pong.timestamp);
+				self.peer_info.update_body_tail(pong.body_tail);
 				Ok(None)
 			}
 
@@ -114,6 +164,13 @@ impl MessageHandler for Protocol {
 					"handle_payload: GetTransaction: {}, msg_len: {}",
 					h, msg.header.msg_len,
 				);
+				if !self.tx_requests.lock().take() {
+					debug!(
+						"handle_payload: GetTransaction: {} from {} over quota, dropping.",
+						h, self.peer_info.addr,
+					);
+					return Ok(None);
+				}
 				let tx = adapter.get_transaction(h);
 				if let Some(tx) = tx {
 					Ok(Some(Msg::new(
@@ -126,13 +183,86 @@ impl MessageHandler for Protocol {
 				}
 			}
 
+			Type::InputProofs => {
+				let input_proofs: InputProofs = msg.body()?;
+				debug!(
+					"handle_payload: received {} input proofs, msg_len: {}",
+					input_proofs.proofs.len(),
+					msg.header.msg_len
+				);
+				adapter.input_proofs_received(input_proofs.proofs, &self.peer_info)?;
+				Ok(None)
+			}
+
+			Type::TxSketch => {
+				let sketch: TxSketch = msg.body()?;
+				debug!(
+					"handle_payload: received tx sketch of {} cells, msg_len: {}",
+					sketch.sketch.len(),
+					msg.header.msg_len
+				);
+				if !self.tx_sketches.lock().take() {
+					debug!(
+						"handle_payload: TxSketch from {} over quota, dropping.",
+						self.peer_info.addr,
+					);
+					return Ok(None);
+				}
+				match self.tx_recon {
+					Some(ref tx_recon) => {
+						let result = tx_recon.lock().respond(&sketch, self.wants_input_proofs());
+						Ok(Some(Msg::new(
+							Type::TxReconcileResult,
+							result,
+							self.peer_info.version,
+						)?))
+					}
+					None => Ok(None),
+				}
+			}
+
+			Type::TxReconcileResult => {
+				let result: TxReconcileResult = msg.body()?;
+				debug!(
+					"handle_payload: received tx reconciliation result, decoded: {}, missing: {}",
+					result.decoded,
+					result.missing.len()
+				);
+				let tx_recon = match self.tx_recon {
+					Some(ref tx_recon) => tx_recon,
+					None => return Ok(None),
+				};
+				let missing = tx_recon.lock().complete(&result, self.wants_input_proofs());
+				self.tx_announcements_received(result.announcements)?;
+				if missing.kernels.is_empty() {
+					Ok(None)
+				} else {
+					Ok(Some(Msg::new(
+						Type::TxAnnouncements,
+						missing,
+						self.peer_info.version,
+					)?))
+				}
+			}
+
+			Type::TxAnnouncements => {
+				let announcements: TxAnnouncements = msg.body()?;
+				debug!(
+					"handle_payload: received {} tx announcements, msg_len: {}",
+					announcements.kernels.len(),
+					msg.header.msg_len
+				);
+				self.tx_announcements_received(announcements)?;
+				Ok(None)
+			}
+
 			Type::Transaction => {
 				debug!(
 					"handle_payload: received tx: msg_len: {}",
 					msg.header.msg_len
 				);
 				let tx: core::Transaction = msg.body()?;
-				adapter.transaction_received(tx, false)?;
+				adapter.transaction_received(tx, false, &self.peer_info)?;
 				Ok(None)
 			}
 
@@ -142,7 +272,7 @@ impl MessageHandler for Protocol {
 					msg.header.msg_len
 				);
 				let tx: core::Transaction = msg.body()?;
-				adapter.transaction_received(tx, true)?;
+				adapter.transaction_received(tx, true, &self.peer_info)?;
 				Ok(None)
 			}
 
@@ -167,6 +297,8 @@ impl MessageHandler for Protocol {
 					msg.header.msg_len
 				);
 				let b: core::UntrustedBlock = msg.body()?;
+				self.peer_info
+					.sync_received(SyncTransfer::Blocks, msg.header.msg_len, true);
 
 				// We default to NONE opts here as we do not know know yet why this block was
 				// received.
@@ -190,6 +322,251 @@ impl MessageHandler for Protocol {
 				}
 			}
 
+			Type::GetBlockFilter => {
+				let h: Hash = msg.body()?;
+				trace!("handle_payload: GetBlockFilter: {}", h);
+				match adapter.get_block_filter(h) {
+					Some(filter) => Ok(Some(Msg::new(
+						Type::BlockFilter,
+						BlockFilterResponse { hash: h, filter },
+						self.peer_info.version,
+					)?)),
+					None => Ok(None),
+				}
+			}
+
+			Type::GetBlockManifest => {
+				let h: Hash = msg.body()?;
+				trace!("handle_payload: GetBlockManifest: {}", h);
+				match adapter.get_block_manifest(h) {
+					Some(manifest) => Ok(Some(Msg::new(
+						Type::BlockManifest,
+						manifest,
+						self.peer_info.version,
+					)?)),
+					None => Ok(None),
+				}
+			}
+
+			Type::BlockManifest => {
+				let manifest: core::BlockManifest = msg.body()?;
+				debug!(
+					"handle_payload: received block manifest {} from {}, {} chunks",
+					manifest.hash,
+					self.peer_info.addr,
+					manifest.chunk_hashes.len()
+				);
+				// A manifest starts a download, only the ones asked for.
+				{
+					let mut requested = self.manifests_requested.lock();
+					match requested.iter().position(|h| *h == manifest.hash) {
+						Some(pos) => {
+							requested.remove(pos);
+						}
+						None => {
+							debug!(
+								"handle_payload: unrequested block manifest {} from {}, dropping",
+								manifest.hash, self.peer_info.addr
+							);
+							return Ok(None);
+						}
+					}
+				}
+				adapter.block_manifest_received(manifest, &self.peer_info)?;
+				Ok(None)
+			}
+
+			Type::GetBlockChunk => {
+				let req: BlockChunkRequest = msg.body()?;
+				trace!(
+					"handle_payload: GetBlockChunk: {} {:?} {}",
+					req.hash,
+					req.part,
+					req.index
+				);
+				match adapter.get_block_chunk(req.hash, req.part, req.index) {
+					Some(chunk) => Ok(Some(Msg::new(
+						Type::BlockChunk,
+						chunk,
+						self.peer_info.version,
+					)?)),
+					None => Ok(None),
+				}
+			}
+
+			Type::BlockChunk => {
+				let chunk: core::BlockChunk = msg.body()?;
+				trace!(
+					"handle_payload: received chunk {:?} {} of block {}, msg_len: {}",
+					chunk.items.part(),
+					chunk.index,
+					chunk.hash,
+					msg.header.msg_len
+				);
+				self.peer_info
+					.sync_received(SyncTransfer::Blocks, msg.header.msg_len, true);
+				adapter.block_chunk_received(chunk, &self.peer_info)?;
+				Ok(None)
+			}
+
+			Type::GetOutputBitmapSegment => {
+				let req: SegmentRequest = msg.body()?;
+				trace!(
+					"handle_payload: GetOutputBitmapSegment: {} {:?}",
+					req.block_hash,
+					req.identifier
+				);
+				match adapter.get_bitmap_segment(req.block_hash, req.identifier) {
+					Some((segment, output_root, bitmap_mmr_size)) => Ok(Some(Msg::new(
+						Type::OutputBitmapSegment,
+						OutputBitmapSegmentResponse {
+							block_hash: req.block_hash,
+							segment,
+							output_root,
+							bitmap_mmr_size,
+						},
+						self.peer_info.version,
+					)?)),
+					None => Ok(None),
+				}
+			}
+
+			Type::GetOutputSegment => {
+				let req: SegmentRequest = msg.body()?;
+				trace!(
+					"handle_payload: GetOutputSegment: {} {:?}",
+					req.block_hash,
+					req.identifier
+				);
+				match adapter.get_output_segment(req.block_hash, req.identifier) {
+					Some(segment) => Ok(Some(Msg::new(
+						Type::OutputSegment,
+						SegmentResponse {
+							block_hash: req.block_hash,
+							segment,
+						},
+						self.peer_info.version,
+					)?)),
+					None => Ok(None),
+				}
+			}
+
+			Type::GetRangeProofSegment => {
+				let req: SegmentRequest = msg.body()?;
+				trace!(
+					"handle_payload: GetRangeProofSegment: {} {:?}",
+					req.block_hash,
+					req.identifier
+				);
+				match adapter.get_rangeproof_segment(req.block_hash, req.identifier) {
+					Some(segment) => Ok(Some(Msg::new(
+						Type::RangeProofSegment,
+						SegmentResponse {
+							block_hash: req.block_hash,
+							segment,
+						},
+						self.peer_info.version,
+					)?)),
+					None => Ok(None),
+				}
+			}
+
+			Type::GetKernelSegment => {
+				let req: SegmentRequest = msg.body()?;
+				trace!(
+					"handle_payload: GetKernelSegment: {} {:?}",
+					req.block_hash,
+					req.identifier
+				);
+				match adapter.get_kernel_segment(req.block_hash, req.identifier) {
+					Some(segment) => Ok(Some(Msg::new(
+						Type::KernelSegment,
+						SegmentResponse {
+							block_hash: req.block_hash,
+							segment,
+						},
+						self.peer_info.version,
+					)?)),
+					None => Ok(None),
+				}
+			}
+
+			Type::OutputBitmapSegment => {
+				let resp: OutputBitmapSegmentResponse = msg.body()?;
+				trace!(
+					"handle_payload: received bitmap segment {:?} at {}, msg_len: {}",
+					resp.segment.identifier,
+					resp.block_hash,
+					msg.header.msg_len
+				);
+				self.peer_info
+					.sync_received(SyncTransfer::TxHashSet, msg.header.msg_len, true);
+				adapter.bitmap_segment_received(
+					resp.block_hash,
+					resp.segment,
+					resp.output_root,
+					resp.bitmap_mmr_size,
+					&self.peer_info,
+				)?;
+				Ok(None)
+			}
+
+			Type::OutputSegment => {
+				let resp: SegmentResponse<core::OutputIdentifier> = msg.body()?;
+				trace!(
+					"handle_payload: received output segment {:?} at {}, msg_len: {}",
+					resp.segment.identifier,
+					resp.block_hash,
+					msg.header.msg_len
+				);
+				self.peer_info
+					.sync_received(SyncTransfer::TxHashSet, msg.header.msg_len, true);
+				adapter.output_segment_received(resp.block_hash, resp.segment, &self.peer_info)?;
+				Ok(None)
+			}
+
+			Type::RangeProofSegment => {
+				let resp: SegmentResponse<RangeProof> = msg.body()?;
+				trace!(
+					"handle_payload: received rangeproof segment {:?} at {}, msg_len: {}",
+					resp.segment.identifier,
+					resp.block_hash,
+					msg.header.msg_len
+				);
+				self.peer_info
+					.sync_received(SyncTransfer::TxHashSet, msg.header.msg_len, true);
+				adapter.rangeproof_segment_received(
+					resp.block_hash,
+					resp.segment,
+					&self.peer_info,
+				)?;
+				Ok(None)
+			}
+
+			Type::KernelSegment => {
+				let resp: SegmentResponse<core::TxKernel> = msg.body()?;
+				trace!(
+					"handle_payload: received kernel segment {:?} at {}, msg_len: {}",
+					resp.segment.identifier,
+					resp.block_hash,
+					msg.header.msg_len
+				);
+				self.peer_info
+					.sync_received(SyncTransfer::TxHashSet, msg.header.msg_len, true);
+				adapter.kernel_segment_received(resp.block_hash, resp.segment, &self.peer_info)?;
+				Ok(None)
+			}
+
+			Type::BlockFilter => {
+				// Served to light clients, we never ask for them.
+				let resp: BlockFilterResponse = msg.body()?;
+				debug!(
+					"handle_payload: unrequested block filter {} from {}, dropping.",
+					resp.hash, self.peer_info.addr
+				);
+				Ok(None)
+			}
+
 			Type::CompactBlock => {
 				debug!(
 					"handle_payload: received compact block: msg_len: {}",
@@ -246,6 +623,8 @@ impl MessageHandler for Protocol {
 				if total_bytes_read != msg.header.msg_len {
 					return Err(Error::MsgLen);
 				}
+				self.peer_info
+					.sync_received(SyncTransfer::Headers, total_bytes_read, true);
 
 				Ok(None)
 			}
@@ -262,7 +641,13 @@ impl MessageHandler for Protocol {
 
 			Type::PeerAddrs => {
 				let peer_addrs: PeerAddrs = msg.body()?;
-				adapter.peer_addrs_received(peer_addrs.peers);
+				// Addresses we didn't ask for have unknown capabilities.
+				let capab = self
+					.peers_requested
+					.lock()
+					.pop_front()
+					.unwrap_or(Capabilities::UNKNOWN);
+				adapter.peer_addrs_received(peer_addrs.peers, capab);
 				Ok(None)
 			}
 
@@ -386,6 +771,11 @@ impl MessageHandler for Protocol {
 						let size = msg.copy_attachment(request_size, &mut tmp_zip)?;
 						downloaded_size += size;
 						request_size = cmp::min(48_000, total_size - downloaded_size);
+						self.peer_info.sync_received(
+							SyncTransfer::TxHashSet,
+							size as u64,
+							request_size == 0,
+						);
 						self.adapter.txhashset_download_update(
 							download_start_time,
 							downloaded_size as u64,
@@ -455,3 +845,134 @@ impl MessageHandler for Protocol {
 		}
 	}
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::core::pow::Difficulty;
+	use crate::core::ser::{self, ProtocolVersion, Readable};
+	use crate::recon::{estimate_difference, Sketch};
+	use crate::serv::DummyAdapter;
+	use crate::types::{Direction, PeerAddr, PeerLiveInfo};
+	use crate::util::RwLock;
+
+	// Protocol of a peer reconciling its tx announcements, along with them.
+	fn recon_protocol(port: u16) -> (Protocol, Arc<Mutex<TxReconciliation>>) {
+		let info = PeerInfo {
+			capabilities: Capabilities::FULL_NODE,
+			user_agent: "test".to_owned(),
+			version: ProtocolVersion::local(),
+			addr: PeerAddr(format!("127.0.0.1:{}", port).parse().unwrap()),
+			direction: Direction::Outbound,
+			live_info: Arc::new(RwLock::new(PeerLiveInfo::new(Difficulty::min()))),
+		};
+		let tx_recon = Arc::new(Mutex::new(TxReconciliation::new()));
+		let protocol = Protocol::new(
+			Arc::new(DummyAdapter {}),
+			info,
+			Arc::new(AtomicBool::new(false)),
+			Arc::new(Mutex::new(VecDeque::new())),
+			Arc::new(Mutex::new(VecDeque::new())),
+			Some(tx_recon.clone()),
+		);
+		(protocol, tx_recon)
+	}
+
+	// Has the protocol handle the message, as received from the wire.
+	fn deliver(protocol: &Protocol, msg: &Msg) -> Option<Msg> {
+		let mut body = msg.body();
+		let message =
+			Message::from_header(msg.header().clone(), &mut body, ProtocolVersion::local());
+		protocol
+			.consume(
+				message,
+				Arc::new(AtomicBool::new(false)),
+				Arc::new(Tracker::new()),
+			)
+			.unwrap()
+	}
+
+	fn read<T: Readable>(msg: &Msg) -> T {
+		ser::deserialize(&mut msg.body(), ProtocolVersion::local()).unwrap()
+	}
+
+	fn hashes(range: std::ops::Range<u64>) -> Vec<Hash> {
+		range.map(|i| i.hash()).collect()
+	}
+
+	fn sorted(mut hashes: Vec<Hash>) -> Vec<Hash> {
+		hashes.sort();
+		hashes
+	}
+
+	#[test]
+	fn reconcile_tx_announcements() {
+		let (initiator, initiator_recon) = recon_protocol(3414);
+		let (responder, responder_recon) = recon_protocol(3415);
+		let queue = |recon: &Arc<Mutex<TxReconciliation>>, hashes: &[Hash]| {
+			for h in hashes {
+				assert!(recon.lock().queue(*h, &[]));
+			}
+		};
+
+		// The difference decodes, each side only announces what the other
+		// lacks.
+		queue(&initiator_recon, &hashes(0..50));
+		queue(&responder_recon, &hashes(10..65));
+		let sketch = initiator_recon.lock().initiate().unwrap();
+		let sketch = Msg::new(Type::TxSketch, sketch, ProtocolVersion::local()).unwrap();
+		let result = deliver(&responder, &sketch).unwrap();
+		assert_eq!(result.header().msg_type, Type::TxReconcileResult);
+		let decoded: TxReconcileResult = read(&result);
+		assert!(decoded.decoded);
+		assert_eq!(
+			sorted(decoded.announcements.kernels),
+			sorted(hashes(50..65))
+		);
+		let missing = deliver(&initiator, &result).unwrap();
+		assert_eq!(missing.header().msg_type, Type::TxAnnouncements);
+		let missing: TxAnnouncements = read(&missing);
+		assert_eq!(sorted(missing.kernels), sorted(hashes(0..10)));
+
+		// A sketch too small for the difference, both sides flood their
+		// whole sets.
+		queue(&initiator_recon, &hashes(100..200));
+		queue(&responder_recon, &hashes(200..300));
+		let salt = initiator_recon.lock().initiate().unwrap().salt;
+		let small = TxSketch {
+			salt,
+			sketch: Sketch::with_capacity(0),
+		};
+		let small = Msg::new(Type::TxSketch, small, ProtocolVersion::local()).unwrap();
+		let result = deliver(&responder, &small).unwrap();
+		let failed: TxReconcileResult = read(&result);
+		assert!(!failed.decoded);
+		assert_eq!(
+			sorted(failed.announcements.kernels),
+			sorted(hashes(200..300))
+		);
+		let flooded: TxAnnouncements = read(&deliver(&initiator, &result).unwrap());
+		assert_eq!(sorted(flooded.kernels), sorted(hashes(100..200)));
+
+		// The next sketch is sized up for a larger difference than measured
+		// on the first reconciliation, 20 of the 50 of the smaller set.
+		queue(&initiator_recon, &hashes(100..200));
+		let larger = initiator_recon.lock().initiate().unwrap();
+		let measured = Sketch::with_capacity(estimate_difference(100, 100, 0.4));
+		assert!(larger.sketch.len() > measured.len());
+	}
+
+	#[test]
+	fn tx_sketches_quota() {
+		let (responder, _) = recon_protocol(3416);
+		let sketch = TxSketch {
+			salt: 0,
+			sketch: Sketch::with_capacity(0),
+		};
+		let sketch = Msg::new(Type::TxSketch, sketch, ProtocolVersion::local()).unwrap();
+		for _ in 0..MAX_TX_SKETCHES_PER_MIN {
+			assert!(deliver(&responder, &sketch).is_some());
+		}
+		assert!(deliver(&responder, &sketch).is_none());
+	}
+}
@@ -0,0 +1,524 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Set reconciliation of the transaction announcements, after Erlay. Rather
+//! than announcing every transaction to every peer, the announcements to a
+//! peer are queued and periodically reconciled with the ones it queued for
+//! us. A transaction queued on both sides is known to both and never
+//! announced, the others are found by decoding the difference of a sketch
+//! of each set. A sketch too small for the difference can't be decoded,
+//! both sides then fall back to flooding their whole sets.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use rand::{thread_rng, Rng};
+
+use crate::core::core::hash::{Hash, Hashed};
+use crate::core::core::InputProof;
+use crate::core::ser::{self, Readable, Reader, Writeable, Writer};
+use crate::msg::{TxAnnouncements, TxReconcileResult, TxSketch};
+
+/// Most announcements queued for a peer, the transactions beyond are
+/// announced right away.
+pub const MAX_RECON_SET_SIZE: usize = 4096;
+
+/// Number of cells each short id is added to, one per sub-table.
+const SKETCH_HASHES: usize = 3;
+
+/// Most cells of a sketch, enough to decode the difference of two full sets.
+pub const MAX_SKETCH_CELLS: usize = SKETCH_HASHES * (MAX_RECON_SET_SIZE + 4);
+
+/// Size of a sketch cell once serialized.
+pub const SKETCH_CELL_SIZE: u64 = 20;
+
+/// A reconciliation we initiated, unanswered after that long, is given up
+/// and its announcements queued again.
+const RECON_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Most sketches answered per peer and minute, each one costing us a decode.
+pub const MAX_TX_SKETCHES_PER_MIN: usize = 30;
+
+/// Share of the smaller set expected to be missing from the other one,
+/// until measured on a reconciliation with the peer.
+const DEFAULT_DIFF_COEF: f64 = 0.25;
+
+/// Margin added to the estimated set difference, so the sketches of small
+/// sets decode too.
+const DIFF_MARGIN: usize = 8;
+
+// Seed of the checksums of the short ids.
+const CHECK_SEED: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// Estimated size of the difference of two sets of n and m elements, as in
+/// Erlay: the sizes difference plus q times the smaller size, q being the
+/// share of the smaller set found missing from the other on the previous
+/// reconciliation, plus a margin.
+pub fn estimate_difference(n: usize, m: usize, q: f64) -> usize {
+	let (min, max) = if n < m { (n, m) } else { (m, n) };
+	max - min + (q * min as f64).ceil() as usize + DIFF_MARGIN
+}
+
+/// Short id of a transaction kernel under the salt of a reconciliation.
+pub fn short_id(salt: u64, kernel_hash: &Hash) -> u64 {
+	(salt, *kernel_hash).hash().to_u64()
+}
+
+// Mixes the bits of a short id (splitmix64 finalizer).
+fn mix(mut x: u64) -> u64 {
+	x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+	x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+	x ^ (x >> 31)
+}
+
+fn checksum(id: u64) -> u64 {
+	mix(id ^ CHECK_SEED)
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Cell {
+	count: i32,
+	id_sum: u64,
+	check_sum: u64,
+}
+
+impl Cell {
+	fn toggle(&mut self, id: u64, sign: i32) {
+		self.count = self.count.wrapping_add(sign);
+		self.id_sum ^= id;
+		self.check_sum ^= checksum(id);
+	}
+
+	fn is_empty(&self) -> bool {
+		self.count == 0 && self.id_sum == 0 && self.check_sum == 0
+	}
+
+	// Holds a single id, the cell of a set minus the cell of the other.
+	fn is_pure(&self) -> bool {
+		(self.count == 1 || self.count == -1) && self.check_sum == checksum(self.id_sum)
+	}
+}
+
+/// Invertible Bloom lookup table of the short ids of a set. The sketch of a
+/// set minus the sketch of another, with as many cells, decodes to the ids
+/// of either set missing from the other, unless there are too many of them
+/// for the cells.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sketch {
+	cells: Vec<Cell>,
+}
+
+impl Sketch {
+	/// Empty sketch able to decode differences of about `capacity` ids.
+	pub fn with_capacity(capacity: usize) -> Sketch {
+		let per_hash = capacity.min(2 * MAX_RECON_SET_SIZE) / 2 + 4;
+		Sketch::with_cells(SKETCH_HASHES * per_hash)
+	}
+
+	fn with_cells(len: usize) -> Sketch {
+		Sketch {
+			cells: vec![Cell::default(); len],
+		}
+	}
+
+	/// Number of cells of the sketch.
+	pub fn len(&self) -> usize {
+		self.cells.len()
+	}
+
+	/// Add a short id to the sketch.
+	pub fn insert(&mut self, id: u64) {
+		self.toggle(id, 1);
+	}
+
+	fn toggle(&mut self, id: u64, sign: i32) {
+		for idx in self.indexes(id) {
+			self.cells[idx].toggle(id, sign);
+		}
+	}
+
+	// The cell of the id in each sub-table.
+	fn indexes(&self, id: u64) -> Vec<usize> {
+		let per_hash = (self.cells.len() / SKETCH_HASHES) as u64;
+		(0..SKETCH_HASHES)
+			.map(|i| i * per_hash as usize + (mix(id.wrapping_add(i as u64)) % per_hash) as usize)
+			.collect()
+	}
+
+	/// Subtract the sketch of another set, with the same number of cells.
+	pub fn subtract(&mut self, other: &Sketch) {
+		for (cell, other) in self.cells.iter_mut().zip(other.cells.iter()) {
+			cell.count = cell.count.wrapping_sub(other.count);
+			cell.id_sum ^= other.id_sum;
+			cell.check_sum ^= other.check_sum;
+		}
+	}
+
+	/// Decode a sketch subtracted from another into the ids only in the
+	/// first set and the ids only in the second. None if the difference is
+	/// too large for the sketch.
+	pub fn decode(mut self) -> Option<(Vec<u64>, Vec<u64>)> {
+		let mut ours = vec![];
+		let mut theirs = vec![];
+		let mut candidates: Vec<usize> = (0..self.cells.len()).collect();
+		while let Some(idx) = candidates.pop() {
+			let cell = self.cells[idx];
+			if !cell.is_pure() {
+				continue;
+			}
+			// A crafted sketch could keep us peeling forever.
+			if ours.len() + theirs.len() >= self.cells.len() {
+				return None;
+			}
+			// Peeling the id off its cells may leave some of them pure.
+			if cell.count == 1 {
+				ours.push(cell.id_sum);
+			} else {
+				theirs.push(cell.id_sum);
+			}
+			let indexes = self.indexes(cell.id_sum);
+			for i in &indexes {
+				self.cells[*i].toggle(cell.id_sum, -cell.count);
+			}
+			candidates.extend(indexes);
+		}
+		if self.cells.iter().all(Cell::is_empty) {
+			Some((ours, theirs))
+		} else {
+			None
+		}
+	}
+}
+
+impl Writeable for Sketch {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), ser::Error> {
+		writer.write_u32(self.cells.len() as u32)?;
+		for cell in &self.cells {
+			writer.write_i32(cell.count)?;
+			writer.write_u64(cell.id_sum)?;
+			writer.write_u64(cell.check_sum)?;
+		}
+		Ok(())
+	}
+}
+
+impl Readable for Sketch {
+	fn read(reader: &mut dyn Reader) -> Result<Sketch, ser::Error> {
+		let len = reader.read_u32()? as usize;
+		if len > MAX_SKETCH_CELLS {
+			return Err(ser::Error::TooLargeReadErr);
+		}
+		if len == 0 || len % SKETCH_HASHES != 0 {
+			return Err(ser::Error::CorruptedData);
+		}
+		let mut cells = Vec::with_capacity(len);
+		for _ in 0..len {
+			cells.push(Cell {
+				count: reader.read_i32()?,
+				id_sum: reader.read_u64()?,
+				check_sum: reader.read_u64()?,
+			});
+		}
+		Ok(Sketch { cells })
+	}
+}
+
+// Announcement of a transaction, by the hash of its kernel, along with the
+// proofs of its inputs.
+type Announcement = (Hash, Vec<InputProof>);
+
+// Reconciliation we initiated, waiting for the answer of the peer.
+struct InFlight {
+	salt: u64,
+	sent_at: Instant,
+	announcements: HashMap<u64, Announcement>,
+}
+
+/// Announcements of transactions queued for a peer, until reconciled with
+/// the ones the peer queued for us.
+pub struct TxReconciliation {
+	queued: HashMap<Hash, Vec<InputProof>>,
+	in_flight: Option<InFlight>,
+	// Announcements the peer had queued at the last reconciliation, and the
+	// share of the smaller set missing from the other, to size the next
+	// sketch.
+	peer_set_size: usize,
+	diff_coef: f64,
+}
+
+impl TxReconciliation {
+	pub fn new() -> TxReconciliation {
+		TxReconciliation {
+			queued: HashMap::new(),
+			in_flight: None,
+			peer_set_size: 0,
+			diff_coef: DEFAULT_DIFF_COEF,
+		}
+	}
+
+	/// Queue the announcement of a transaction, false if too many are
+	/// queued already.
+	pub fn queue(&mut self, kernel_hash: Hash, proofs: &[InputProof]) -> bool {
+		if self.queued.len() >= MAX_RECON_SET_SIZE && !self.queued.contains_key(&kernel_hash) {
+			return false;
+		}
+		self.queued.insert(kernel_hash, proofs.to_vec());
+		true
+	}
+
+	/// Drop the queued announcements of the transactions the peer is known
+	/// to have.
+	pub fn forget<F>(&mut self, known: F)
+	where
+		F: Fn(&Hash) -> bool,
+	{
+		self.queued.retain(|h, _| !known(h));
+	}
+
+	/// Start a reconciliation with the sketch of the queued announcements,
+	/// set aside until the peer answers. The sketch is sized for the set
+	/// difference estimated from the previous reconciliation. None while
+	/// waiting for the answer to the previous one.
+	pub fn initiate(&mut self) -> Option<TxSketch> {
+		if let Some(in_flight) = self.in_flight.take() {
+			if in_flight.sent_at.elapsed() < RECON_TIMEOUT {
+				self.in_flight = Some(in_flight);
+				return None;
+			}
+			for (_, (h, proofs)) in in_flight.announcements {
+				self.queued.entry(h).or_insert(proofs);
+			}
+		}
+
+		let salt = thread_rng().gen();
+		let mut sketch = Sketch::with_capacity(estimate_difference(
+			self.queued.len(),
+			self.peer_set_size,
+			self.diff_coef,
+		));
+		let announcements = self
+			.queued
+			.drain()
+			.map(|(h, proofs)| {
+				let id = short_id(salt, &h);
+				sketch.insert(id);
+				(id, (h, proofs))
+			})
+			.collect();
+		self.in_flight = Some(InFlight {
+			salt,
+			sent_at: Instant::now(),
+			announcements,
+		});
+		Some(TxSketch { salt, sketch })
+	}
+
+	/// Answer the sketch of the peer: the transactions it lacks get
+	/// announced, the ones we lack asked for. Everything gets announced if
+	/// the difference can't be decoded. Proofs are only sent along if the
+	/// peer wants them.
+	pub fn respond(&mut self, peer_sketch: &TxSketch, with_proofs: bool) -> TxReconcileResult {
+		let mut announcements: HashMap<u64, Announcement> = self
+			.queued
+			.drain()
+			.map(|(h, proofs)| (short_id(peer_sketch.salt, &h), (h, proofs)))
+			.collect();
+		let set_size = announcements.len() as u32;
+
+		let mut sketch = Sketch::with_cells(peer_sketch.sketch.len());
+		for id in announcements.keys() {
+			sketch.insert(*id);
+		}
+		sketch.subtract(&peer_sketch.sketch);
+		match sketch.decode() {
+			Some((ours, theirs)) if theirs.len() <= MAX_RECON_SET_SIZE => {
+				let ours = ours
+					.iter()
+					.filter_map(|id| announcements.remove(id))
+					.collect();
+				TxReconcileResult {
+					decoded: true,
+					set_size,
+					missing: theirs,
+					announcements: tx_announcements(ours, with_proofs),
+				}
+			}
+			_ => TxReconcileResult {
+				decoded: false,
+				set_size,
+				missing: vec![],
+				announcements: tx_announcements(
+					announcements.into_iter().map(|(_, a)| a).collect(),
+					with_proofs,
+				),
+			},
+		}
+	}
+
+	/// Complete the reconciliation we initiated with the answer of the peer,
+	/// returning the announcements it asked for, all of them if it couldn't
+	/// decode the difference.
+	pub fn complete(&mut self, result: &TxReconcileResult, with_proofs: bool) -> TxAnnouncements {
+		let in_flight = match self.in_flight.take() {
+			Some(in_flight) => in_flight,
+			None => return tx_announcements(vec![], with_proofs),
+		};
+		self.peer_set_size = result.set_size as usize;
+		self.update_diff_coef(in_flight.announcements.len(), result);
+		let mut announcements = in_flight.announcements;
+		let missing = if result.decoded {
+			result
+				.missing
+				.iter()
+				.filter_map(|id| announcements.remove(id))
+				.collect()
+		} else {
+			announcements.into_iter().map(|(_, a)| a).collect()
+		};
+		trace!(
+			"tx reconciliation {}: {} announcements asked for",
+			in_flight.salt,
+			missing.len()
+		);
+		tx_announcements(missing, with_proofs)
+	}
+
+	// Measure the share of the smaller set missing from the other on the
+	// reconciliation of our set of n announcements, or double it if the
+	// sketch was too small for the difference.
+	fn update_diff_coef(&mut self, n: usize, result: &TxReconcileResult) {
+		if !result.decoded {
+			self.diff_coef = (self.diff_coef * 2.0).max(DEFAULT_DIFF_COEF).min(1.0);
+			return;
+		}
+		let m = result.set_size as usize;
+		let min = n.min(m);
+		if min > 0 {
+			let diff = result.missing.len() + result.announcements.kernels.len();
+			let excess = diff.saturating_sub(n.max(m) - min);
+			self.diff_coef = (excess as f64 / min as f64).min(1.0);
+		}
+	}
+}
+
+impl Default for TxReconciliation {
+	fn default() -> TxReconciliation {
+		TxReconciliation::new()
+	}
+}
+
+// Batch the announcements, the proofs of the inputs first if the peer wants
+// them. The proofs of a message are bounded, the announcements that would
+// overflow them are dropped, the peer will get these transactions another
+// way.
+fn tx_announcements(announcements: Vec<Announcement>, with_proofs: bool) -> TxAnnouncements {
+	let max_proofs = TxAnnouncements::max_proofs() as usize;
+	let mut kernels = Vec::with_capacity(announcements.len());
+	let mut proofs = vec![];
+	for (h, tx_proofs) in announcements {
+		if with_proofs {
+			if proofs.len() + tx_proofs.len() > max_proofs {
+				continue;
+			}
+			proofs.extend(tx_proofs);
+		}
+		kernels.push(h);
+	}
+	TxAnnouncements { kernels, proofs }
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn hashes(range: std::ops::Range<u64>) -> Vec<Hash> {
+		range.map(|i| i.hash()).collect()
+	}
+
+	#[test]
+	fn sketch_decodes_difference() {
+		let ours: Vec<u64> = (0..200).map(mix).collect();
+		let theirs: Vec<u64> = (150..300).map(mix).collect();
+
+		let mut sketch = Sketch::with_capacity(400);
+		let mut other = Sketch::with_capacity(400);
+		ours.iter().for_each(|id| sketch.insert(*id));
+		theirs.iter().for_each(|id| other.insert(*id));
+		sketch.subtract(&other);
+
+		let (mut only_ours, mut only_theirs) = sketch.clone().decode().unwrap();
+		only_ours.sort();
+		only_theirs.sort();
+		let mut expected_ours = ours[..150].to_vec();
+		let mut expected_theirs = theirs[50..].to_vec();
+		expected_ours.sort();
+		expected_theirs.sort();
+		assert_eq!(only_ours, expected_ours);
+		assert_eq!(only_theirs, expected_theirs);
+
+		// Too small a sketch for the difference.
+		let mut small = Sketch::with_capacity(10);
+		let mut small_other = Sketch::with_capacity(10);
+		ours.iter().for_each(|id| small.insert(*id));
+		theirs.iter().for_each(|id| small_other.insert(*id));
+		small.subtract(&small_other);
+		assert!(small.decode().is_none());
+
+		let ser = ser::ser_vec(&sketch, ser::ProtocolVersion::local()).unwrap();
+		assert_eq!(ser.len() as u64, 4 + SKETCH_CELL_SIZE * sketch.len() as u64);
+		let de: Sketch = ser::deserialize_default(&mut &ser[..]).unwrap();
+		assert_eq!(de, sketch);
+	}
+
+	#[test]
+	fn reconcile_announcements() {
+		let mut initiator = TxReconciliation::new();
+		let mut responder = TxReconciliation::new();
+		let shared = hashes(0..50);
+		let initiator_only = hashes(50..60);
+		let responder_only = hashes(60..65);
+		for h in shared.iter().chain(initiator_only.iter()) {
+			assert!(initiator.queue(*h, &[]));
+		}
+		for h in shared.iter().chain(responder_only.iter()) {
+			assert!(responder.queue(*h, &[]));
+		}
+
+		let sketch = initiator.initiate().unwrap();
+		// Still waiting for the answer.
+		assert!(initiator.initiate().is_none());
+
+		let result = responder.respond(&sketch, false);
+		assert!(result.decoded);
+		assert_eq!(result.set_size, 55);
+		let mut announced = result.announcements.kernels.clone();
+		announced.sort();
+		let mut expected = responder_only.clone();
+		expected.sort();
+		assert_eq!(announced, expected);
+
+		let mut asked = initiator.complete(&result, false).kernels;
+		asked.sort();
+		let mut expected = initiator_only.clone();
+		expected.sort();
+		assert_eq!(asked, expected);
+
+		// Everything was reconciled, the next sketch starts afresh.
+		let sketch = initiator.initiate().unwrap();
+		let result = responder.respond(&sketch, false);
+		assert!(result.decoded);
+		assert!(result.missing.is_empty());
+		assert!(result.announcements.kernels.is_empty());
+	}
+}
@@ -15,17 +15,21 @@
 use std::fs::File;
 use std::io::{self, Read};
 use std::net::{IpAddr, Shutdown, SocketAddr, SocketAddrV4, TcpListener, TcpStream};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 
+use crate::capture::MessageCapture;
 use crate::chain;
+use crate::chain::txhashset::BitmapChunk;
 use crate::core::core;
 use crate::core::core::hash::Hash;
+use crate::core::core::pmmr::{Segment, SegmentIdentifier};
 use crate::core::global;
 use crate::core::pow::Difficulty;
 use crate::handshake::Handshake;
+use crate::msg;
 use crate::peer::Peer;
 use crate::peers::Peers;
 use crate::store::PeerStore;
@@ -33,6 +37,7 @@ use crate::types::{
 	Capabilities, ChainAdapter, Error, NetAdapter, P2PConfig, PeerAddr, PeerInfo, ReasonForBan,
 	TxHashSetRead,
 };
+use crate::util::secp::pedersen::RangeProof;
 use crate::util::StopState;
 use chrono::prelude::{DateTime, Utc};
 
@@ -44,6 +49,7 @@ pub struct Server {
 	handshake: Arc<Handshake>,
 	pub peers: Arc<Peers>,
 	stop_state: Arc<StopState>,
+	capture: Option<Arc<MessageCapture>>,
 }
 
 // TODO TLS
@@ -57,12 +63,23 @@ impl Server {
 		genesis: Hash,
 		stop_state: Arc<StopState>,
 	) -> Result<Server, Error> {
+		let capture = if config.capture_messages {
+			let dir = Path::new(db_root).join("p2p_capture");
+			warn!("Capturing the messages received from peers to {:?}", dir);
+			Some(Arc::new(MessageCapture::new(
+				dir,
+				config.capture_file_size(),
+			)?))
+		} else {
+			None
+		};
 		Ok(Server {
 			config: config.clone(),
 			capabilities: capab,
 			handshake: Arc::new(Handshake::new(genesis, config.clone())),
 			peers: Arc::new(Peers::new(PeerStore::new(db_root)?, adapter, config)),
 			stop_state,
+			capture,
 		})
 	}
 
@@ -116,6 +133,12 @@ impl Server {
 					}
 					match self.handle_new_peer(stream) {
 						Err(Error::ConnectionClose) => debug!("shutting down, ignoring a new peer"),
+						// Not banned, other networks may run on the same host.
+						Err(Error::MagicMismatch { peer, .. }) => debug!(
+							"Peer {} is on another network ({:?}), refusing connection",
+							peer_addr,
+							msg::magic_chain_type(peer)
+						),
 						Err(e) => {
 							debug!("Error accepting peer {}: {:?}", peer_addr.to_string(), e);
 							let _ = self.peers.add_banned(peer_addr, ReasonForBan::BadHandshake);
@@ -183,6 +206,7 @@ impl Server {
 					PeerAddr(addr),
 					&self.handshake,
 					self.peers.clone(),
+					self.capture.clone(),
 				)?;
 				let peer = Arc::new(peer);
 				self.peers.add_connected(peer.clone())?;
@@ -214,6 +238,7 @@ impl Server {
 			total_diff,
 			&self.handshake,
 			self.peers.clone(),
+			self.capture.clone(),
 		)?;
 		self.peers.add_connected(Arc::new(peer))?;
 		Ok(())
@@ -265,6 +290,11 @@ impl Server {
 		false
 	}
 
+	/// The capabilities we advertise to our peers.
+	pub fn capabilities(&self) -> Capabilities {
+		self.capabilities
+	}
+
 	pub fn stop(&self) {
 		self.stop_state.stop();
 		self.peers.stop();
@@ -289,6 +319,9 @@ impl ChainAdapter for DummyAdapter {
 	fn total_height(&self) -> Result<u64, chain::Error> {
 		Ok(0)
 	}
+	fn body_tail(&self) -> Result<u64, chain::Error> {
+		Ok(0)
+	}
 	fn get_transaction(&self, _h: Hash) -> Option<core::Transaction> {
 		None
 	}
@@ -296,10 +329,18 @@ impl ChainAdapter for DummyAdapter {
 	fn tx_kernel_received(&self, _h: Hash, _peer_info: &PeerInfo) -> Result<bool, chain::Error> {
 		Ok(true)
 	}
+	fn input_proofs_received(
+		&self,
+		_: Vec<core::InputProof>,
+		_peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		Ok(true)
+	}
 	fn transaction_received(
 		&self,
 		_: core::Transaction,
 		_stem: bool,
+		_peer_info: &PeerInfo,
 	) -> Result<bool, chain::Error> {
 		Ok(true)
 	}
@@ -338,6 +379,83 @@ impl ChainAdapter for DummyAdapter {
 	fn get_block(&self, _: Hash) -> Option<core::Block> {
 		None
 	}
+	fn get_block_filter(&self, _: Hash) -> Option<core::BlockFilter> {
+		None
+	}
+	fn get_block_manifest(&self, _: Hash) -> Option<core::BlockManifest> {
+		None
+	}
+	fn get_block_chunk(&self, _: Hash, _: core::ChunkPart, _: u32) -> Option<core::BlockChunk> {
+		None
+	}
+	fn block_manifest_received(
+		&self,
+		_: core::BlockManifest,
+		_: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		Ok(true)
+	}
+	fn block_chunk_received(
+		&self,
+		_: core::BlockChunk,
+		_: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		Ok(true)
+	}
+	fn get_bitmap_segment(
+		&self,
+		_: Hash,
+		_: SegmentIdentifier,
+	) -> Option<(Segment<BitmapChunk>, Hash, u64)> {
+		None
+	}
+	fn get_output_segment(
+		&self,
+		_: Hash,
+		_: SegmentIdentifier,
+	) -> Option<Segment<core::OutputIdentifier>> {
+		None
+	}
+	fn get_rangeproof_segment(&self, _: Hash, _: SegmentIdentifier) -> Option<Segment<RangeProof>> {
+		None
+	}
+	fn get_kernel_segment(&self, _: Hash, _: SegmentIdentifier) -> Option<Segment<core::TxKernel>> {
+		None
+	}
+	fn bitmap_segment_received(
+		&self,
+		_: Hash,
+		_: Segment<BitmapChunk>,
+		_: Hash,
+		_: u64,
+		_: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		Ok(true)
+	}
+	fn output_segment_received(
+		&self,
+		_: Hash,
+		_: Segment<core::OutputIdentifier>,
+		_: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		Ok(true)
+	}
+	fn rangeproof_segment_received(
+		&self,
+		_: Hash,
+		_: Segment<RangeProof>,
+		_: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		Ok(true)
+	}
+	fn kernel_segment_received(
+		&self,
+		_: Hash,
+		_: Segment<core::TxKernel>,
+		_: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		Ok(true)
+	}
 	fn kernel_data_read(&self) -> Result<File, chain::Error> {
 		unimplemented!()
 	}
@@ -381,13 +499,17 @@ impl ChainAdapter for DummyAdapter {
 	fn get_tmpfile_pathname(&self, _tmpfile_name: String) -> PathBuf {
 		unimplemented!()
 	}
+
+	fn peer_connected(&self, _: &PeerInfo) {}
+
+	fn peer_disconnected(&self, _: PeerAddr) {}
 }
 
 impl NetAdapter for DummyAdapter {
 	fn find_peer_addrs(&self, _: Capabilities) -> Vec<PeerAddr> {
 		vec![]
 	}
-	fn peer_addrs_received(&self, _: Vec<PeerAddr>) {}
+	fn peer_addrs_received(&self, _: Vec<PeerAddr>, _: Capabilities) {}
 	fn peer_difficulty(&self, _: PeerAddr, _: Difficulty, _: u64) {}
 	fn is_banned(&self, _: PeerAddr) -> bool {
 		false
@@ -38,6 +38,57 @@ enum_from_primitive! {
 	}
 }
 
+/// Order of the peers in a listing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PeerOrder {
+	/// Most recently connected first
+	LastSeen,
+	/// Most recently banned first
+	LastBanned,
+}
+
+/// Filters, order and page of a listing of the known peers.
+#[derive(Debug, Clone)]
+pub struct PeerQuery {
+	/// Only the peers in that state, in any state if None.
+	pub state: Option<State>,
+	/// Only the peers we're currently connected to.
+	pub connected: bool,
+	/// Only the peers advertising all these capabilities.
+	pub capabilities: Capabilities,
+	/// Order of the peers, storage order if None.
+	pub order: Option<PeerOrder>,
+	/// Number of matching peers skipped.
+	pub offset: usize,
+	/// Maximum number of peers listed.
+	pub limit: usize,
+}
+
+impl PeerQuery {
+	fn matches(&self, p: &PeerData) -> bool {
+		self.state.map_or(true, |s| p.flags == s) && p.capabilities.contains(self.capabilities)
+	}
+
+	// Sort the matching peers and keep the requested page, along with the
+	// total number of matching peers.
+	fn page(&self, mut peers: Vec<PeerData>) -> (usize, Vec<PeerData>) {
+		match self.order {
+			Some(PeerOrder::LastSeen) => {
+				peers.sort_by(|a, b| b.last_connected.cmp(&a.last_connected))
+			}
+			Some(PeerOrder::LastBanned) => peers.sort_by(|a, b| b.last_banned.cmp(&a.last_banned)),
+			None => {}
+		}
+		let total = peers.len();
+		let page = peers
+			.into_iter()
+			.skip(self.offset)
+			.take(self.limit)
+			.collect();
+		(total, page)
+	}
+}
+
 /// Data stored for any given peer we've encountered.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PeerData {
@@ -56,6 +107,10 @@ pub struct PeerData {
 	pub ban_reason: ReasonForBan,
 	/// Time when we last connected to this peer.
 	pub last_connected: i64,
+	/// Height of the oldest full block the peer advertised it can serve when
+	/// we last connected to it.
+	#[serde(default)]
+	pub body_tail: Option<u64>,
 }
 
 impl Writeable for PeerData {
@@ -70,6 +125,9 @@ impl Writeable for PeerData {
 			[write_i32, self.ban_reason as i32],
 			[write_i64, self.last_connected]
 		);
+		if let Some(body_tail) = self.body_tail {
+			writer.write_u64(body_tail)?;
+		}
 		Ok(())
 	}
 }
@@ -82,12 +140,13 @@ impl Readable for PeerData {
 		let (fl, lb, br) = ser_multiread!(reader, read_u8, read_i64, read_i32);
 
 		let lc = reader.read_i64();
-		// this only works because each PeerData is read in its own vector and this
-		// is the last data element
+		// this only works because each PeerData is read in its own vector and
+		// these are the last data elements
 		let last_connected = match lc {
 			Err(_) => Utc::now().timestamp(),
 			Ok(lc) => lc,
 		};
+		let body_tail = reader.read_u64().ok();
 
 		let user_agent = String::from_utf8(ua).map_err(|_| ser::Error::CorruptedData)?;
 		let capabilities = Capabilities::from_bits_truncate(capab);
@@ -102,6 +161,7 @@ impl Readable for PeerData {
 				last_banned: lb,
 				ban_reason,
 				last_connected,
+				body_tail,
 			}),
 			None => Err(ser::Error::CorruptedData),
 		}
@@ -173,6 +233,27 @@ impl PeerStore {
 			.collect::<Vec<_>>())
 	}
 
+	/// Known peers matching the query and passing the provided filter, along
+	/// with the total number of matching peers.
+	/// Used for /v1/peers api endpoint
+	pub fn query_peers<F>(
+		&self,
+		query: &PeerQuery,
+		filter: F,
+	) -> Result<(usize, Vec<PeerData>), Error>
+	where
+		F: Fn(&PeerData) -> bool,
+	{
+		let key = to_key(PEER_PREFIX, &mut "".to_string().into_bytes());
+		let peers = self
+			.db
+			.iter::<PeerData>(&key)?
+			.map(|(_, v)| v)
+			.filter(|p| query.matches(p) && filter(p))
+			.collect::<Vec<_>>();
+		Ok(query.page(peers))
+	}
+
 	/// Convenience method to load a peer data, update its status and save it
 	/// back. If new state is Banned its last banned time will be updated too.
 	pub fn update_state(&self, peer_addr: PeerAddr, new_state: State) -> Result<(), Error> {
@@ -223,3 +304,73 @@ impl PeerStore {
 fn peer_key(peer_addr: PeerAddr) -> Vec<u8> {
 	to_key(PEER_PREFIX, &mut peer_addr.as_key().into_bytes())
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn peer_data(port: u16, flags: State, last_connected: i64) -> PeerData {
+		PeerData {
+			addr: PeerAddr(format!("10.0.0.1:{}", port).parse().unwrap()),
+			capabilities: if port % 2 == 0 {
+				Capabilities::FULL_NODE
+			} else {
+				Capabilities::PEER_LIST
+			},
+			user_agent: "test".to_owned(),
+			flags,
+			last_banned: 0,
+			ban_reason: ReasonForBan::None,
+			last_connected,
+			body_tail: None,
+		}
+	}
+
+	#[test]
+	fn test_peer_query() {
+		let peers: Vec<_> = (0..10)
+			.map(|i| {
+				let flags = if i < 3 { State::Banned } else { State::Healthy };
+				peer_data(i, flags, (i as i64 * 7) % 10)
+			})
+			.collect();
+		let mut query = PeerQuery {
+			state: None,
+			connected: false,
+			capabilities: Capabilities::UNKNOWN,
+			order: None,
+			offset: 0,
+			limit: 100,
+		};
+		let filtered = |query: &PeerQuery| {
+			query.page(peers.iter().filter(|p| query.matches(p)).cloned().collect())
+		};
+
+		let (total, page) = filtered(&query);
+		assert_eq!((total, page.len()), (10, 10));
+
+		query.state = Some(State::Banned);
+		let (total, _) = filtered(&query);
+		assert_eq!(total, 3);
+
+		query.state = Some(State::Healthy);
+		query.capabilities = Capabilities::TX_KERNEL_HASH;
+		let (total, page) = filtered(&query);
+		assert_eq!(total, 3);
+		assert!(page.iter().all(|p| p.addr.0.port() % 2 == 0));
+
+		query.state = None;
+		query.capabilities = Capabilities::UNKNOWN;
+		query.order = Some(PeerOrder::LastSeen);
+		query.offset = 2;
+		query.limit = 3;
+		let (total, page) = filtered(&query);
+		assert_eq!(total, 10);
+		let seen: Vec<_> = page.iter().map(|p| p.last_connected).collect();
+		assert_eq!(seen, vec![7, 6, 5]);
+
+		query.offset = 9;
+		let (total, page) = filtered(&query);
+		assert_eq!((total, page.len()), (10, 1));
+	}
+}
@@ -0,0 +1,202 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Throughput and latency of the sync transfers served by a peer, for the
+//! sync to pick the fastest peers to download from.
+
+use std::time::{Duration, Instant};
+
+/// Weight of the latest batch in the rolling measures.
+const SMOOTHING: f64 = 0.3;
+
+/// Time after which a batch still waiting for responses counts against the
+/// peer, as if it only received what it did so far.
+const STALL_SECS: u64 = 5;
+
+/// Time after which a batch still waiting for responses is given up on, the
+/// requests left unanswered.
+const STALE_SECS: u64 = 30;
+
+/// Sync transfers, measured separately as their responses differ in size.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncTransfer {
+	Headers,
+	Blocks,
+	TxHashSet,
+}
+
+/// Rolling measure of one sync transfer served by a peer. The requests sent
+/// before the earlier ones are all answered form a batch, its throughput is
+/// the bytes received over the time since the first request was sent, its
+/// latency the time the first response took to start coming.
+#[derive(Clone, Debug, Default)]
+pub struct TransferStats {
+	batch_start: Option<Instant>,
+	pending: u32,
+	batch_bytes: u64,
+	batch_latency: Option<Duration>,
+	bytes_per_sec: Option<f64>,
+	latency: Option<Duration>,
+}
+
+impl TransferStats {
+	/// Record a request sent to the peer.
+	pub fn requested(&mut self, now: Instant) {
+		if let Some(start) = self.batch_start {
+			if now.duration_since(start) > Duration::from_secs(STALE_SECS) {
+				self.close_batch(now);
+			}
+		}
+		if self.batch_start.is_none() {
+			self.batch_start = Some(now);
+			self.batch_bytes = 0;
+			self.batch_latency = None;
+		}
+		self.pending += 1;
+	}
+
+	/// Record bytes of a response received from the peer, `done` once the
+	/// whole response is. Responses to no request are left out.
+	pub fn received(&mut self, bytes: u64, done: bool, now: Instant) {
+		let start = match self.batch_start {
+			Some(start) => start,
+			None => return,
+		};
+		self.batch_bytes += bytes;
+		if self.batch_latency.is_none() {
+			self.batch_latency = Some(now.duration_since(start));
+		}
+		if done {
+			self.pending = self.pending.saturating_sub(1);
+			if self.pending == 0 {
+				self.close_batch(now);
+			}
+		}
+	}
+
+	/// Bytes per second the peer serves the transfer at, None until
+	/// measured. A batch waiting on responses for a while lowers it right
+	/// away, a peer that stops answering doesn't keep its past score.
+	pub fn bytes_per_sec(&self, now: Instant) -> Option<f64> {
+		let ongoing = self.batch_start.and_then(|start| {
+			let elapsed = now.duration_since(start);
+			if elapsed > Duration::from_secs(STALL_SECS) {
+				Some(self.batch_bytes as f64 / elapsed.as_secs_f64())
+			} else {
+				None
+			}
+		});
+		match (self.bytes_per_sec, ongoing) {
+			(Some(rate), Some(ongoing)) => Some(rate.min(ongoing)),
+			(rate, ongoing) => rate.or(ongoing),
+		}
+	}
+
+	/// Time the peer takes to start answering a request, None until measured.
+	pub fn latency(&self) -> Option<Duration> {
+		self.latency
+	}
+
+	fn close_batch(&mut self, now: Instant) {
+		let start = match self.batch_start.take() {
+			Some(start) => start,
+			None => return,
+		};
+		let elapsed = now.duration_since(start).max(Duration::from_millis(1));
+		let rate = self.batch_bytes as f64 / elapsed.as_secs_f64();
+		self.bytes_per_sec = Some(match self.bytes_per_sec {
+			Some(prev) => prev + SMOOTHING * (rate - prev),
+			None => rate,
+		});
+		// Unanswered batches tell nothing of the latency.
+		if let Some(latency) = self.batch_latency.take() {
+			self.latency = Some(match self.latency {
+				Some(prev) => prev.mul_f64(1.0 - SMOOTHING) + latency.mul_f64(SMOOTHING),
+				None => latency,
+			});
+		}
+		self.pending = 0;
+	}
+}
+
+/// Measures of all the sync transfers served by a peer.
+#[derive(Clone, Debug, Default)]
+pub struct SyncThroughput {
+	headers: TransferStats,
+	blocks: TransferStats,
+	txhashset: TransferStats,
+}
+
+impl SyncThroughput {
+	pub fn stats(&self, transfer: SyncTransfer) -> &TransferStats {
+		match transfer {
+			SyncTransfer::Headers => &self.headers,
+			SyncTransfer::Blocks => &self.blocks,
+			SyncTransfer::TxHashSet => &self.txhashset,
+		}
+	}
+
+	pub fn stats_mut(&mut self, transfer: SyncTransfer) -> &mut TransferStats {
+		match transfer {
+			SyncTransfer::Headers => &mut self.headers,
+			SyncTransfer::Blocks => &mut self.blocks,
+			SyncTransfer::TxHashSet => &mut self.txhashset,
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn transfer_stats() {
+		let start = Instant::now();
+		let secs = |s: u64| start + Duration::from_secs(s);
+		let rate = |stats: &TransferStats, now| stats.bytes_per_sec(now).map(|r| r.round());
+		let mut stats = TransferStats::default();
+		assert_eq!(stats.bytes_per_sec(start), None);
+
+		// Unrequested responses aren't measured.
+		stats.received(1_000, true, start);
+		assert_eq!(stats.bytes_per_sec(start), None);
+
+		// Two requests, answered 1s and 2s later.
+		stats.requested(start);
+		stats.requested(start);
+		stats.received(100_000, true, secs(1));
+		assert_eq!(stats.bytes_per_sec(secs(1)), None);
+		stats.received(100_000, true, secs(2));
+		assert_eq!(rate(&stats, secs(2)), Some(100_000.0));
+		assert_eq!(stats.latency(), Some(Duration::from_secs(1)));
+
+		// A faster batch raises the rolling throughput.
+		stats.requested(secs(10));
+		stats.received(400_000, true, secs(11));
+		assert_eq!(rate(&stats, secs(11)), Some(190_000.0));
+
+		// Stalling lowers it, until the peer answers.
+		stats.requested(secs(20));
+		assert_eq!(rate(&stats, secs(22)), Some(190_000.0));
+		assert_eq!(rate(&stats, secs(30)), Some(0.0));
+		stats.received(10_000, false, secs(30));
+		assert_eq!(rate(&stats, secs(30)), Some(1_000.0));
+
+		// Given up on once stale.
+		stats.requested(secs(60));
+		assert_eq!(stats.pending, 1);
+		assert_eq!(stats.batch_start, Some(secs(60)));
+		assert_eq!(rate(&stats, secs(60)), Some(133_075.0));
+	}
+}
@@ -20,20 +20,25 @@ use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV
 use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use chrono::prelude::*;
 use serde::de::{SeqAccess, Visitor};
-use serde::{Deserialize, Deserializer};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use kepler_store;
 
 use crate::chain;
+use crate::chain::txhashset::BitmapChunk;
 use crate::core::core;
 use crate::core::core::hash::Hash;
+use crate::core::core::pmmr::{Segment, SegmentIdentifier};
 use crate::core::global;
 use crate::core::pow::Difficulty;
 use crate::core::ser::{self, ProtocolVersion, Readable, Reader, Writeable, Writer};
 use crate::msg::PeerAddrs;
+use crate::throughput::{SyncThroughput, SyncTransfer};
+use crate::util::secp::pedersen::RangeProof;
 use crate::util::RwLock;
 
 /// Maximum number of block headers a peer should ever send
@@ -49,9 +54,24 @@ pub const MAX_PEER_ADDRS: u32 = 256;
 /// Maximum number of block header hashes to send as part of a locator
 pub const MAX_LOCATORS: u32 = 20;
 
+/// Maximum number of transactions (by kernel hash) we request from, or serve
+/// to, an individual peer per minute
+pub const MAX_TX_REQUESTS_PER_MIN: usize = 100;
+
 /// How long a banned peer should be banned for
 const BAN_WINDOW: i64 = 10800;
 
+/// Distinct low-work fork headers a peer can send us per hour before being
+/// banned
+const MAX_FORK_HEADERS_PER_HOUR: u32 = 100;
+
+/// Requests for the missing parent of an orphan block before giving up on it
+const ORPHAN_PARENT_REQUESTS: u32 = 5;
+
+/// Delay before asking again for the missing parent of an orphan block,
+/// doubling after each request
+const ORPHAN_PARENT_RETRY_SECS: u64 = 2;
+
 /// The max inbound peer count
 const PEER_MAX_INBOUND_COUNT: u32 = 128;
 
@@ -61,10 +81,16 @@ const PEER_MAX_OUTBOUND_COUNT: u32 = 8;
 /// The min preferred outbound peer count
 const PEER_MIN_PREFERRED_OUTBOUND_COUNT: u32 = 8;
 
+/// The min outbound peer count, below which we have insufficient peers
+const PEER_MIN_OUTBOUND_COUNT: u32 = 4;
+
 /// The peer listener buffer count. Allows temporarily accepting more connections
 /// than allowed by PEER_MAX_INBOUND_COUNT to encourage network bootstrapping.
 const PEER_LISTENER_BUFFER_COUNT: u32 = 8;
 
+/// Size of a file of captured messages before rotating to the next one.
+const CAPTURE_FILE_SIZE_MB: u64 = 64;
+
 #[derive(Debug)]
 pub enum Error {
 	Serialization(ser::Error),
@@ -83,6 +109,11 @@ pub enum Error {
 		us: Hash,
 		peer: Hash,
 	},
+	/// Magic bytes of a message from a peer on another network
+	MagicMismatch {
+		us: [u8; 2],
+		peer: [u8; 2],
+	},
 	Send(String),
 	PeerNotFound,
 	PeerNotBanned,
@@ -235,9 +266,9 @@ impl std::fmt::Display for PeerAddr {
 
 impl PeerAddr {
 	/// Convenient way of constructing a new peer_addr from an ip_addr
-	/// defaults to port 7414 on mainnet and 17414 on floonet.
+	/// defaults to the p2p port of our chain type (7414 on mainnet).
 	pub fn from_ip(addr: IpAddr) -> PeerAddr {
-		let port = if global::is_floonet() { 17414 } else { 7414 };
+		let port = global::CHAIN_TYPE.read().default_p2p_port();
 		PeerAddr(SocketAddr::new(addr, port))
 	}
 
@@ -252,6 +283,93 @@ impl PeerAddr {
 	}
 }
 
+/// An IP address or a subnet in CIDR notation, e.g. "10.0.0.0/8". The port
+/// of a peer address is ignored, as peers are told apart by IP.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct IpSubnet {
+	addr: IpAddr,
+	prefix: u8,
+}
+
+impl IpSubnet {
+	/// Subnet of the provided prefix length, the address bits past the
+	/// prefix are ignored.
+	pub fn new(addr: IpAddr, prefix: u8) -> Result<IpSubnet, String> {
+		let addr = match addr {
+			IpAddr::V4(ip) if prefix <= 32 => {
+				let mask = u32::max_value()
+					.checked_shl(32 - prefix as u32)
+					.unwrap_or(0);
+				IpAddr::V4(Ipv4Addr::from(u32::from(ip) & mask))
+			}
+			IpAddr::V6(ip) if prefix <= 128 => {
+				let mask = u128::max_value()
+					.checked_shl(128 - prefix as u32)
+					.unwrap_or(0);
+				IpAddr::V6(Ipv6Addr::from(u128::from(ip) & mask))
+			}
+			_ => return Err(format!("invalid prefix length {} for {}", prefix, addr)),
+		};
+		Ok(IpSubnet { addr, prefix })
+	}
+
+	/// Whether the IP address is part of the subnet.
+	pub fn contains(&self, ip: IpAddr) -> bool {
+		let same_family = ip.is_ipv4() == self.addr.is_ipv4();
+		same_family && IpSubnet::new(ip, self.prefix).ok() == Some(*self)
+	}
+}
+
+impl From<IpAddr> for IpSubnet {
+	fn from(addr: IpAddr) -> IpSubnet {
+		let prefix = if addr.is_ipv4() { 32 } else { 128 };
+		IpSubnet { addr, prefix }
+	}
+}
+
+impl FromStr for IpSubnet {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<IpSubnet, String> {
+		let mut parts = s.splitn(2, '/');
+		let addr = parts.next().unwrap_or("");
+		match parts.next() {
+			Some(prefix) => {
+				let addr = IpAddr::from_str(addr).map_err(|e| format!("{}: {}", s, e))?;
+				let prefix = u8::from_str(prefix).map_err(|e| format!("{}: {}", s, e))?;
+				IpSubnet::new(addr, prefix)
+			}
+			None => IpAddr::from_str(addr)
+				.or_else(|_| SocketAddr::from_str(addr).map(|x| x.ip()))
+				.map(IpSubnet::from)
+				.map_err(|e| format!("{}: {}", s, e)),
+		}
+	}
+}
+
+impl fmt::Display for IpSubnet {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		if *self == IpSubnet::from(self.addr) {
+			write!(f, "{}", self.addr)
+		} else {
+			write!(f, "{}/{}", self.addr, self.prefix)
+		}
+	}
+}
+
+impl Serialize for IpSubnet {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.collect_str(self)
+	}
+}
+
+impl<'de> Deserialize<'de> for IpSubnet {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<IpSubnet, D::Error> {
+		let s = String::deserialize(deserializer)?;
+		IpSubnet::from_str(&s).map_err(serde::de::Error::custom)
+	}
+}
+
 /// Configuration for the peer-to-peer server.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct P2PConfig {
@@ -276,17 +394,56 @@ pub struct P2PConfig {
 	/// The list of preferred peers that we will try to connect to
 	pub peers_preferred: Option<PeerAddrs>,
 
+	/// Peers or subnets never banned automatically, e.g. our own nodes
+	/// legitimately sending old blocks during maintenance.
+	pub peers_ban_exempt: Option<Vec<IpSubnet>>,
+
 	pub ban_window: Option<i64>,
 
+	/// Distinct headers of forks with less work than our header chain a
+	/// peer can send us per hour. Past it they aren't processed anymore
+	/// and the peer gets banned.
+	pub max_fork_headers_per_hour: Option<u32>,
+
+	/// Requests sent for the missing parent of an orphan block, first to
+	/// the peer that sent the orphan, then to other peers. Only the first
+	/// one if 0.
+	pub orphan_parent_requests: Option<u32>,
+
+	/// Delay before asking again for the missing parent of an orphan
+	/// block, doubling after each request.
+	pub orphan_parent_retry_secs: Option<u64>,
+
 	pub peer_max_inbound_count: Option<u32>,
 
 	pub peer_max_outbound_count: Option<u32>,
 
 	pub peer_min_preferred_outbound_count: Option<u32>,
 
+	pub peer_min_outbound_count: Option<u32>,
+
 	pub peer_listener_buffer_count: Option<u32>,
 
 	pub dandelion_peer: Option<PeerAddr>,
+
+	/// Adjust our clock by the median offset of the peers clocks (bounded)
+	/// when checking whether block timestamps are too far in the future.
+	#[serde(default)]
+	pub adjust_network_time: bool,
+
+	/// Announce the blocks we mine or relay by their header to all our peers
+	/// at once, uploading the block itself to them afterwards.
+	#[serde(default)]
+	pub fast_block_announce: bool,
+
+	/// Record the messages received from our peers to rotating files, to
+	/// replay them later. Debugging only, the files grow fast.
+	#[serde(default)]
+	pub capture_messages: bool,
+
+	/// Size of a file of captured messages before rotating to the next one,
+	/// in MB.
+	pub capture_file_size_mb: Option<u64>,
 }
 
 /// Default address for peer-to-peer connections.
@@ -302,12 +459,21 @@ impl Default for P2PConfig {
 			peers_allow: None,
 			peers_deny: None,
 			peers_preferred: None,
+			peers_ban_exempt: None,
 			ban_window: None,
+			max_fork_headers_per_hour: None,
+			orphan_parent_requests: None,
+			orphan_parent_retry_secs: None,
 			peer_max_inbound_count: None,
 			peer_max_outbound_count: None,
 			peer_min_preferred_outbound_count: None,
+			peer_min_outbound_count: None,
 			peer_listener_buffer_count: None,
 			dandelion_peer: None,
+			adjust_network_time: false,
+			fast_block_announce: false,
+			capture_messages: false,
+			capture_file_size_mb: None,
 		}
 	}
 }
@@ -323,6 +489,30 @@ impl P2PConfig {
 		}
 	}
 
+	/// return maximum rate of low-work fork headers per peer
+	pub fn max_fork_headers_per_hour(&self) -> u32 {
+		match self.max_fork_headers_per_hour {
+			Some(n) => n,
+			None => MAX_FORK_HEADERS_PER_HOUR,
+		}
+	}
+
+	/// return number of requests sent for the missing parent of an orphan
+	pub fn orphan_parent_requests(&self) -> u32 {
+		match self.orphan_parent_requests {
+			Some(n) => n,
+			None => ORPHAN_PARENT_REQUESTS,
+		}
+	}
+
+	/// return initial delay between requests for the parent of an orphan
+	pub fn orphan_parent_retry_secs(&self) -> u64 {
+		match self.orphan_parent_retry_secs {
+			Some(n) => n,
+			None => ORPHAN_PARENT_RETRY_SECS,
+		}
+	}
+
 	/// return maximum inbound peer connections count
 	pub fn peer_max_inbound_count(&self) -> u32 {
 		match self.peer_max_inbound_count {
@@ -347,6 +537,15 @@ impl P2PConfig {
 		}
 	}
 
+	/// return minimum outbound peer count, never above the preferred one
+	pub fn peer_min_outbound_count(&self) -> u32 {
+		let min = match self.peer_min_outbound_count {
+			Some(n) => n,
+			None => PEER_MIN_OUTBOUND_COUNT,
+		};
+		min.min(self.peer_min_preferred_outbound_count())
+	}
+
 	/// return peer buffer count for listener
 	pub fn peer_listener_buffer_count(&self) -> u32 {
 		match self.peer_listener_buffer_count {
@@ -354,6 +553,11 @@ impl P2PConfig {
 			None => PEER_LISTENER_BUFFER_COUNT,
 		}
 	}
+
+	/// return the size of a capture file before rotating, in bytes
+	pub fn capture_file_size(&self) -> u64 {
+		self.capture_file_size_mb.unwrap_or(CAPTURE_FILE_SIZE_MB) * 1024 * 1024
+	}
 }
 
 /// Type of seeding the server will use to find other peers on the network.
@@ -391,10 +595,28 @@ bitflags! {
 		const PEER_LIST = 0b0000_0100;
 		/// Can broadcast and request txs by kernel hash.
 		const TX_KERNEL_HASH = 0b0000_1000;
+		/// Pruned node, only serves the full blocks from the body tail it
+		/// advertises in the handshake and pings up to its head.
+		const BLOCK_RANGE = 0b0001_0000;
+		/// Asks for proofs of the ability to spend the inputs of the txs
+		/// relayed to it, sent ahead of the txs.
+		const INPUT_PROOFS = 0b0010_0000;
+		/// Serves the manifests and chunks of the block bodies, for large
+		/// blocks to be downloaded in pieces.
+		const BLOCK_CHUNKS = 0b0100_0000;
+		/// Serves the txhashset at its archive header in segments, for it to
+		/// be downloaded from several peers at once.
+		const TXHASHSET_SEGMENTS = 0b1000_0000;
+		/// Reconciles the tx announcements with sketches of the sets queued
+		/// both ways, rather than flooding them.
+		const TX_RECONCILIATION = 0b1_0000_0000;
+		/// Archive node, keeps and serves all the full blocks back to
+		/// genesis, beyond the horizon.
+		const ARCHIVE = 0b10_0000_0000;
 
 		/// All nodes right now are "full nodes".
-		/// Some nodes internally may maintain longer block histories (archival_mode)
-		/// but we do not advertise this to other nodes.
+		/// Some nodes internally may maintain longer block histories
+		/// (archive_mode), advertised with ARCHIVE.
 		/// All nodes by default will accept lightweight "kernel first" tx broadcast.
 		const FULL_NODE = Capabilities::HEADER_HIST.bits
 			| Capabilities::TXHASHSET_HIST.bits
@@ -424,6 +646,10 @@ enum_from_primitive! {
 		ManualBan = 5,
 		FraudHeight = 6,
 		BadHandshake = 7,
+		DuplicateBlocks = 8,
+		BadBlockState = 9,
+		ForkSpam = 10,
+		BadInputProofs = 11,
 	}
 }
 
@@ -434,6 +660,14 @@ pub struct PeerLiveInfo {
 	pub last_seen: DateTime<Utc>,
 	pub stuck_detector: DateTime<Utc>,
 	pub first_seen: DateTime<Utc>,
+	/// Seconds the peer clock is ahead of ours, as of the last message it
+	/// sent us its clock in.
+	pub clock_offset: Option<i64>,
+	/// Height of the oldest full block the peer can serve, as of the last
+	/// message it advertised it in.
+	pub body_tail: Option<u64>,
+	/// How fast the peer served the sync requests sent to it.
+	pub sync_throughput: SyncThroughput,
 }
 
 /// General information about a connected peer that's useful to other modules.
@@ -455,6 +689,9 @@ impl PeerLiveInfo {
 			first_seen: Utc::now(),
 			last_seen: Utc::now(),
 			stuck_detector: Utc::now(),
+			clock_offset: None,
+			body_tail: None,
+			sync_throughput: SyncThroughput::default(),
 		}
 	}
 }
@@ -488,6 +725,86 @@ impl PeerInfo {
 		self.live_info.read().first_seen
 	}
 
+	/// Seconds the peer clock is ahead of ours, if it told us its clock.
+	pub fn clock_offset(&self) -> Option<i64> {
+		self.live_info.read().clock_offset
+	}
+
+	/// Record the peer clock, sent along with one of its messages.
+	/// Takes a write lock on the live_info.
+	pub fn update_clock(&self, timestamp: Option<i64>) {
+		if let Some(timestamp) = timestamp {
+			self.live_info.write().clock_offset = Some(timestamp - Utc::now().timestamp());
+		}
+	}
+
+	/// Height of the oldest full block the peer can serve, if it advertised it.
+	pub fn body_tail(&self) -> Option<u64> {
+		self.live_info.read().body_tail
+	}
+
+	/// Record the body tail the peer advertised along with one of its messages.
+	/// Takes a write lock on the live_info.
+	pub fn update_body_tail(&self, body_tail: Option<u64>) {
+		if let Some(body_tail) = body_tail {
+			self.live_info.write().body_tail = Some(body_tail);
+		}
+	}
+
+	/// Whether the peer can serve the full block at the provided height.
+	/// Only pruned peers advertising their block range are known not to,
+	/// others are assumed to have all blocks.
+	pub fn has_block_body(&self, height: u64) -> bool {
+		if self.capabilities.contains(Capabilities::ARCHIVE)
+			|| !self.capabilities.contains(Capabilities::BLOCK_RANGE)
+		{
+			return true;
+		}
+		self.body_tail()
+			.map_or(true, |body_tail| height >= body_tail)
+	}
+
+	/// Record a sync request sent to the peer.
+	/// Takes a write lock on the live_info.
+	pub fn sync_requested(&self, transfer: SyncTransfer) {
+		self.live_info
+			.write()
+			.sync_throughput
+			.stats_mut(transfer)
+			.requested(Instant::now());
+	}
+
+	/// Record bytes of a sync response received from the peer, `done` once
+	/// the whole response is.
+	/// Takes a write lock on the live_info.
+	pub fn sync_received(&self, transfer: SyncTransfer, bytes: u64, done: bool) {
+		self.live_info
+			.write()
+			.sync_throughput
+			.stats_mut(transfer)
+			.received(bytes, done, Instant::now());
+	}
+
+	/// Bytes per second the peer serves the sync transfer at, None until
+	/// measured.
+	pub fn sync_bytes_per_sec(&self, transfer: SyncTransfer) -> Option<f64> {
+		self.live_info
+			.read()
+			.sync_throughput
+			.stats(transfer)
+			.bytes_per_sec(Instant::now())
+	}
+
+	/// Time the peer takes to start answering a sync request, None until
+	/// measured.
+	pub fn sync_latency(&self, transfer: SyncTransfer) -> Option<Duration> {
+		self.live_info
+			.read()
+			.sync_throughput
+			.stats(transfer)
+			.latency()
+	}
+
 	/// Update the total_difficulty, height and last_seen of the peer.
 	/// Takes a write lock on the live_info.
 	pub fn update(&self, height: u64, total_difficulty: Difficulty) {
@@ -512,6 +829,8 @@ pub struct PeerInfoDisplay {
 	pub direction: Direction,
 	pub total_difficulty: Difficulty,
 	pub height: u64,
+	#[serde(default)]
+	pub body_tail: Option<u64>,
 }
 
 impl From<PeerInfo> for PeerInfoDisplay {
@@ -524,6 +843,7 @@ impl From<PeerInfo> for PeerInfoDisplay {
 			direction: info.direction,
 			total_difficulty: info.total_difficulty(),
 			height: info.height(),
+			body_tail: info.body_tail(),
 		}
 	}
 }
@@ -549,9 +869,16 @@ pub trait ChainAdapter: Sync + Send {
 	/// Current total height
 	fn total_height(&self) -> Result<u64, chain::Error>;
 
+	/// Height of the oldest full block we can serve, older ones were pruned
+	fn body_tail(&self) -> Result<u64, chain::Error>;
+
 	/// A valid transaction has been received from one of our peers
-	fn transaction_received(&self, tx: core::Transaction, stem: bool)
-		-> Result<bool, chain::Error>;
+	fn transaction_received(
+		&self,
+		tx: core::Transaction,
+		stem: bool,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error>;
 
 	fn get_transaction(&self, kernel_hash: Hash) -> Option<core::Transaction>;
 
@@ -561,6 +888,14 @@ pub trait ChainAdapter: Sync + Send {
 		peer_info: &PeerInfo,
 	) -> Result<bool, chain::Error>;
 
+	/// Proofs of the ability to spend the inputs of a tx have been received
+	/// from one of our peers, ahead of the tx.
+	fn input_proofs_received(
+		&self,
+		proofs: Vec<core::InputProof>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error>;
+
 	/// A block has been received from one of our peers. Returns true if the
 	/// block could be handled properly and is not deemed defective by the
 	/// chain. Returning false means the block will never be valid and
@@ -601,6 +936,103 @@ pub trait ChainAdapter: Sync + Send {
 	/// Gets a full block by its hash.
 	fn get_block(&self, h: Hash) -> Option<core::Block>;
 
+	/// Gets the compact filter of the commitments of a block by its hash.
+	fn get_block_filter(&self, h: Hash) -> Option<core::BlockFilter>;
+
+	/// Gets the manifest of the chunks of a block body by the block hash.
+	fn get_block_manifest(&self, h: Hash) -> Option<core::BlockManifest>;
+
+	/// Gets a chunk of a block body by the block hash, the part of the body
+	/// and the index of the chunk in it.
+	fn get_block_chunk(
+		&self,
+		h: Hash,
+		part: core::ChunkPart,
+		index: u32,
+	) -> Option<core::BlockChunk>;
+
+	/// The manifest of a block body we asked a peer for has been received,
+	/// the chunks still missing are to be requested from it. Returning false
+	/// means the manifest contradicts the block header.
+	fn block_manifest_received(
+		&self,
+		manifest: core::BlockManifest,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error>;
+
+	/// A chunk of a block body has been received, dropped if it doesn't match
+	/// the manifest of the block. Returning false means the block assembled
+	/// from the chunks is defective and may result in the peer being banned.
+	fn block_chunk_received(
+		&self,
+		chunk: core::BlockChunk,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error>;
+
+	/// Gets a segment of the bitmap accumulator of the unspent outputs at the
+	/// block, with the root of the output MMR and the size of the accumulator
+	/// MMR. Only the segments of the txhashset at our archive header are
+	/// served.
+	fn get_bitmap_segment(
+		&self,
+		h: Hash,
+		id: SegmentIdentifier,
+	) -> Option<(Segment<BitmapChunk>, Hash, u64)>;
+
+	/// Gets a segment of the output MMR at the block.
+	fn get_output_segment(
+		&self,
+		h: Hash,
+		id: SegmentIdentifier,
+	) -> Option<Segment<core::OutputIdentifier>>;
+
+	/// Gets a segment of the rangeproof MMR at the block.
+	fn get_rangeproof_segment(&self, h: Hash, id: SegmentIdentifier)
+		-> Option<Segment<RangeProof>>;
+
+	/// Gets a segment of the kernel MMR at the block.
+	fn get_kernel_segment(&self, h: Hash, id: SegmentIdentifier)
+		-> Option<Segment<core::TxKernel>>;
+
+	/// A segment of the bitmap accumulator we asked a peer for has been
+	/// received. Returning false means it doesn't match the block header and
+	/// may result in the peer being banned.
+	fn bitmap_segment_received(
+		&self,
+		block_hash: Hash,
+		segment: Segment<BitmapChunk>,
+		output_root: Hash,
+		bitmap_mmr_size: u64,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error>;
+
+	/// A segment of the output MMR has been received, false if it doesn't
+	/// match the block header.
+	fn output_segment_received(
+		&self,
+		block_hash: Hash,
+		segment: Segment<core::OutputIdentifier>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error>;
+
+	/// A segment of the rangeproof MMR has been received, false if it doesn't
+	/// match the block header.
+	fn rangeproof_segment_received(
+		&self,
+		block_hash: Hash,
+		segment: Segment<RangeProof>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error>;
+
+	/// A segment of the kernel MMR has been received, false if it doesn't
+	/// match the block header.
+	fn kernel_segment_received(
+		&self,
+		block_hash: Hash,
+		segment: Segment<core::TxKernel>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error>;
+
 	fn kernel_data_read(&self) -> Result<File, chain::Error>;
 
 	fn kernel_data_write(&self, reader: &mut dyn Read) -> Result<bool, chain::Error>;
@@ -644,6 +1076,12 @@ pub trait ChainAdapter: Sync + Send {
 	/// Get a tmp file path in above specific tmp dir (create tmp dir if not exist)
 	/// Delete file if tmp file already exists
 	fn get_tmpfile_pathname(&self, tmpfile_name: String) -> PathBuf;
+
+	/// A peer completed its handshake and got added to our connected peers
+	fn peer_connected(&self, peer_info: &PeerInfo);
+
+	/// A peer got removed from our connected peers
+	fn peer_disconnected(&self, addr: PeerAddr);
 }
 
 /// Additional methods required by the protocol that don't need to be
@@ -653,8 +1091,9 @@ pub trait NetAdapter: ChainAdapter {
 	/// addresses.
 	fn find_peer_addrs(&self, capab: Capabilities) -> Vec<PeerAddr>;
 
-	/// A list of peers has been received from one of our peers.
-	fn peer_addrs_received(&self, _: Vec<PeerAddr>);
+	/// A list of peers has been received from one of our peers, in answer
+	/// to a request for peers with the provided capabilities.
+	fn peer_addrs_received(&self, _: Vec<PeerAddr>, _: Capabilities);
 
 	/// Heard total_difficulty from a connected peer (via ping/pong).
 	fn peer_difficulty(&self, _: PeerAddr, _: Difficulty, _: u64);
@@ -17,7 +17,7 @@ use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 
 use kepler_p2p as p2p;
 
-use crate::p2p::types::PeerAddr;
+use crate::p2p::types::{IpSubnet, PeerAddr};
 
 // Test the behavior of a hashmap of peers keyed by peer_addr.
 #[test]
@@ -53,3 +53,33 @@ fn test_peer_addr_hashing() {
 	assert_eq!(peer_addr1.0.port(), 8080);
 	assert_eq!(peer_addr2.0.port(), 8081);
 }
+
+#[test]
+fn test_ip_subnet() {
+	let ip = |s: &str| -> IpAddr { s.parse().unwrap() };
+
+	let subnet: IpSubnet = "10.1.0.0/16".parse().unwrap();
+	assert!(subnet.contains(ip("10.1.2.3")));
+	assert!(!subnet.contains(ip("10.2.2.3")));
+	assert!(!subnet.contains(ip("::ffff:10.1.2.3")));
+	assert_eq!(subnet.to_string(), "10.1.0.0/16");
+
+	// Bits past the prefix are ignored.
+	assert_eq!("10.1.2.3/16".parse::<IpSubnet>().unwrap(), subnet);
+
+	// Single addresses, with or without port.
+	let single: IpSubnet = "192.168.0.1:7414".parse().unwrap();
+	assert_eq!(single, "192.168.0.1".parse().unwrap());
+	assert!(single.contains(ip("192.168.0.1")));
+	assert!(!single.contains(ip("192.168.0.2")));
+	assert_eq!(single.to_string(), "192.168.0.1");
+
+	let all: IpSubnet = "0.0.0.0/0".parse().unwrap();
+	assert!(all.contains(ip("1.2.3.4")));
+	let v6: IpSubnet = "2001:db8::/32".parse().unwrap();
+	assert!(v6.contains(ip("2001:db8::1")));
+	assert!(!v6.contains(ip("2001:db9::1")));
+
+	assert!("10.0.0.0/33".parse::<IpSubnet>().is_err());
+	assert!("10.0.0/8".parse::<IpSubnet>().is_err());
+}
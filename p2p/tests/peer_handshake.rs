@@ -25,7 +25,7 @@ use std::{thread, time};
 use crate::core::core::hash::Hash;
 use crate::core::pow::Difficulty;
 use crate::p2p::types::PeerAddr;
-use crate::p2p::Peer;
+use crate::p2p::{Peer, PeerData, ReasonForBan, State};
 
 fn open_port() -> u16 {
 	// use port 0 to allow the OS to assign an open port
@@ -77,6 +77,7 @@ fn peer_handshake() {
 		my_addr,
 		&p2p::handshake::Handshake::new(Hash::from_vec(&vec![]), p2p_config.clone()),
 		net_adapter,
+		None,
 	)
 	.unwrap();
 
@@ -84,10 +85,91 @@ fn peer_handshake() {
 
 	thread::sleep(time::Duration::from_secs(1));
 
-	peer.send_ping(Difficulty::min(), 0).unwrap();
+	peer.send_ping(Difficulty::min(), 0, Some(0)).unwrap();
 	thread::sleep(time::Duration::from_secs(1));
 
 	let server_peer = server.peers.get_connected_peer(my_addr).unwrap();
 	assert_eq!(server_peer.info.total_difficulty(), Difficulty::min());
+	assert!(server_peer.info.clock_offset().unwrap().abs() <= 2);
+	assert!(peer.info.clock_offset().unwrap().abs() <= 2);
+	assert_eq!(server_peer.info.body_tail(), Some(0));
 	assert!(server.peers.peer_count() > 0);
 }
+
+// The addresses received in answer to a request for archive peers are
+// recorded as such, for an archive node to find and dial them.
+#[test]
+fn archive_peer_addrs() {
+	util::init_test_logger();
+
+	let genesis = Hash::from_vec(&vec![]);
+	let p2p_config = p2p::P2PConfig {
+		host: "127.0.0.1".parse().unwrap(),
+		port: open_port(),
+		peers_allow: None,
+		peers_deny: None,
+		..p2p::P2PConfig::default()
+	};
+	let new_server = |db_root: &str| {
+		Arc::new(
+			p2p::Server::new(
+				db_root,
+				p2p::Capabilities::UNKNOWN,
+				p2p_config.clone(),
+				Arc::new(p2p::DummyAdapter {}),
+				genesis,
+				Arc::new(StopState::new()),
+			)
+			.unwrap(),
+		)
+	};
+	let server = new_server(".kepler_archive_addrs");
+	let client = new_server(".kepler_archive_addrs_client");
+
+	let archive_addr = PeerAddr("10.0.0.9:3414".parse().unwrap());
+	server
+		.peers
+		.save_peer(&PeerData {
+			addr: archive_addr,
+			capabilities: p2p::Capabilities::FULL_NODE | p2p::Capabilities::ARCHIVE,
+			user_agent: "".to_string(),
+			flags: State::Healthy,
+			last_banned: 0,
+			ban_reason: ReasonForBan::None,
+			last_connected: 0,
+			body_tail: None,
+		})
+		.unwrap();
+
+	let p2p_inner = server.clone();
+	let _ = thread::spawn(move || p2p_inner.listen());
+	thread::sleep(time::Duration::from_secs(1));
+
+	let addr = SocketAddr::new(p2p_config.host, p2p_config.port);
+	let socket = TcpStream::connect_timeout(&addr, time::Duration::from_secs(10)).unwrap();
+	let peer = Peer::connect(
+		socket,
+		p2p::Capabilities::UNKNOWN,
+		Difficulty::min(),
+		PeerAddr("127.0.0.1:5001".parse().unwrap()),
+		&p2p::handshake::Handshake::new(genesis, p2p_config.clone()),
+		client.peers.clone(),
+		None,
+	)
+	.unwrap();
+
+	peer.send_peer_request(p2p::Capabilities::PEER_LIST | p2p::Capabilities::ARCHIVE)
+		.unwrap();
+	thread::sleep(time::Duration::from_secs(1));
+
+	let archive_peers = client
+		.peers
+		.find_peers(State::Healthy, p2p::Capabilities::ARCHIVE, 8);
+	assert_eq!(
+		archive_peers.iter().map(|p| p.addr).collect::<Vec<_>>(),
+		vec![archive_addr]
+	);
+
+	let _ = std::fs::remove_dir_all(".kepler_archive_addrs");
+	let _ = std::fs::remove_dir_all(".kepler_archive_addrs_client");
+}
@@ -12,8 +12,11 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use kepler_core as core;
 use kepler_p2p as p2p;
 
+use crate::core::global::{self, ChainTypes};
+use crate::core::ser::{self, ProtocolVersion};
 use num::FromPrimitive;
 
 // Test that Healthy == 0.
@@ -48,7 +51,15 @@ fn test_capabilities() {
 	);
 	assert_eq!(
 		p2p::types::Capabilities::from_bits_truncate(0b10000000 as u32),
-		p2p::types::Capabilities::UNKNOWN
+		p2p::types::Capabilities::TXHASHSET_SEGMENTS
+	);
+	assert_eq!(
+		p2p::types::Capabilities::from_bits_truncate(0b1_00000000 as u32),
+		p2p::types::Capabilities::TX_RECONCILIATION
+	);
+	assert_eq!(
+		p2p::types::Capabilities::from_bits_truncate(0b10_00000000 as u32),
+		p2p::types::Capabilities::ARCHIVE
 	);
 
 	assert_eq!(
@@ -62,10 +73,26 @@ fn test_capabilities() {
 	assert_eq!(
 		p2p::types::Capabilities::from_bits_truncate(0b11111111 as u32),
 		p2p::types::Capabilities::FULL_NODE
+			| p2p::types::Capabilities::BLOCK_RANGE
+			| p2p::types::Capabilities::INPUT_PROOFS
+			| p2p::types::Capabilities::BLOCK_CHUNKS
+			| p2p::types::Capabilities::TXHASHSET_SEGMENTS
+	);
+	assert_eq!(
+		p2p::types::Capabilities::from_bits_truncate(0b1_00001111 as u32),
+		p2p::types::Capabilities::FULL_NODE | p2p::types::Capabilities::TX_RECONCILIATION
+	);
+	assert_eq!(
+		p2p::types::Capabilities::from_bits_truncate(0b10001111 as u32),
+		p2p::types::Capabilities::FULL_NODE | p2p::types::Capabilities::TXHASHSET_SEGMENTS
+	);
+	assert_eq!(
+		p2p::types::Capabilities::from_bits_truncate(0b01001111 as u32),
+		p2p::types::Capabilities::FULL_NODE | p2p::types::Capabilities::BLOCK_CHUNKS
 	);
 	assert_eq!(
 		p2p::types::Capabilities::from_bits_truncate(0b00101111 as u32),
-		p2p::types::Capabilities::FULL_NODE
+		p2p::types::Capabilities::FULL_NODE | p2p::types::Capabilities::INPUT_PROOFS
 	);
 
 	assert!(
@@ -77,4 +104,47 @@ fn test_capabilities() {
 		p2p::types::Capabilities::from_bits_truncate(0b00101111 as u32)
 			.contains(p2p::types::Capabilities::TX_KERNEL_HASH)
 	);
+
+	assert!(
+		p2p::types::Capabilities::from_bits_truncate(0b00011111 as u32)
+			.contains(p2p::types::Capabilities::BLOCK_RANGE)
+	);
+}
+
+#[test]
+fn test_magic_mismatch() {
+	global::set_mining_mode(ChainTypes::AutomatedTesting);
+	let version = ProtocolVersion(1);
+	let header = p2p::msg::MsgHeader::new(p2p::msg::Type::Ping, 0);
+	let mut head = ser::ser_vec(&header, version).unwrap();
+	match p2p::msg::read_header(&mut &head[..], version) {
+		Ok(p2p::msg::MsgHeaderWrapper::Known(h)) => assert_eq!(h.msg_type, p2p::msg::Type::Ping),
+		_ => panic!("header of our own network not read"),
+	}
+
+	// Same header from a floonet peer.
+	head[0] = 83;
+	head[1] = 59;
+	match p2p::msg::read_header(&mut &head[..], version) {
+		Err(p2p::Error::MagicMismatch { peer, .. }) => {
+			assert_eq!(p2p::msg::magic_chain_type(peer), Some(ChainTypes::Floonet))
+		}
+		_ => panic!("header of another network read"),
+	}
+}
+
+#[test]
+fn test_headers_read() {
+	let version = ProtocolVersion(1);
+	let empty = p2p::msg::Headers { headers: vec![] };
+	let bytes = ser::ser_vec(&empty, version).unwrap();
+	let headers: p2p::msg::Headers = ser::deserialize(&mut &bytes[..], version).unwrap();
+	assert!(headers.headers.is_empty());
+
+	// More headers than a Headers message carries.
+	let too_many = (p2p::MAX_BLOCK_HEADERS as u16 + 1).to_be_bytes();
+	match ser::deserialize::<p2p::msg::Headers>(&mut &too_many[..], version) {
+		Err(ser::Error::TooLargeReadErr) => {}
+		_ => panic!("oversized headers message read"),
+	}
 }
@@ -0,0 +1,161 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Proofs of the ability to spend the inputs of the txs relayed to us. Nodes
+//! can ask for them before looking up the inputs of the txs spending many,
+//! so that flooding them with txs crafted to spend as many outputs of others
+//! as possible costs more than it costs them.
+
+use self::core::core::{InputProof, Transaction};
+use self::util::secp::pedersen::Commitment;
+use self::util::RwLock;
+use crate::types::{BlockChain, PoolError};
+use kepler_core as core;
+use kepler_util as util;
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+/// A single peer can take up at most an eighth of the cached proofs, as can
+/// the txs pushed through our API.
+const MAX_PROOFS_SHARE_PER_PEER: usize = 8;
+
+/// Where input proofs come from, each source having its own share of the
+/// cached proofs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ProofSource {
+	/// Relayed by the peer at this address.
+	Peer(SocketAddr),
+	/// Pushed along with a tx through our API.
+	Api,
+}
+
+/// Input proofs received, the latest ones kept up to the capacity.
+pub struct InputProofs {
+	capacity: usize,
+	blockchain: Arc<dyn BlockChain>,
+	proofs: RwLock<CachedProofs>,
+}
+
+#[derive(Default)]
+struct CachedProofs {
+	by_peer: HashMap<ProofSource, PeerProofs>,
+	// Sources in the order they last sent proofs, least recent first.
+	peers: VecDeque<ProofSource>,
+	len: usize,
+}
+
+#[derive(Default)]
+struct PeerProofs {
+	by_commit: HashMap<Commitment, InputProof>,
+	// Commitments in the order their proofs were added, oldest first.
+	order: VecDeque<Commitment>,
+}
+
+impl PeerProofs {
+	fn pop_oldest(&mut self) {
+		if let Some(commit) = self.order.pop_front() {
+			self.by_commit.remove(&commit);
+		}
+	}
+}
+
+impl InputProofs {
+	pub fn new(capacity: usize, blockchain: Arc<dyn BlockChain>) -> InputProofs {
+		InputProofs {
+			capacity,
+			blockchain,
+			proofs: RwLock::new(CachedProofs::default()),
+		}
+	}
+
+	/// Verify the proofs received from the source and keep the valid ones,
+	/// replacing any earlier proof of the source for the same input. The
+	/// proofs of outputs not in the UTXO set are dropped without being
+	/// verified. Returns the number of invalid proofs.
+	pub fn add(&self, peer: ProofSource, proofs: Vec<InputProof>) -> usize {
+		let (valid, invalid): (Vec<_>, Vec<_>) = proofs
+			.into_iter()
+			.filter(|p| self.blockchain.is_unspent(&p.commit))
+			.partition(|p| p.verify().is_ok());
+		if valid.is_empty() {
+			return invalid.len();
+		}
+
+		let share = (self.capacity / MAX_PROOFS_SHARE_PER_PEER).max(1);
+		let mut cached = self.proofs.write();
+		let cached = &mut *cached;
+		cached.peers.retain(|p| *p != peer);
+		cached.peers.push_back(peer);
+		let peer_proofs = cached.by_peer.entry(peer).or_default();
+		for proof in valid {
+			let commit = proof.commit;
+			if peer_proofs.by_commit.insert(commit, proof).is_none() {
+				peer_proofs.order.push_back(commit);
+				cached.len += 1;
+			}
+			// Past its share, the peer makes room with its own proofs.
+			if peer_proofs.order.len() > share {
+				peer_proofs.pop_oldest();
+				cached.len -= 1;
+			}
+		}
+
+		// Then the peers that sent theirs the longest ago make room.
+		while cached.len > self.capacity {
+			let oldest = match cached.peers.front() {
+				Some(oldest) => *oldest,
+				None => break,
+			};
+			if let Some(peer_proofs) = cached.by_peer.get_mut(&oldest) {
+				peer_proofs.pop_oldest();
+				cached.len -= 1;
+				if !peer_proofs.order.is_empty() {
+					continue;
+				}
+			}
+			cached.by_peer.remove(&oldest);
+			cached.peers.pop_front();
+		}
+		invalid.len()
+	}
+
+	/// The proofs for the inputs of the tx, bound to one of its kernels.
+	pub fn for_tx(&self, tx: &Transaction) -> Vec<InputProof> {
+		let cached = self.proofs.read();
+		tx.inputs()
+			.iter()
+			.filter_map(|input| {
+				cached
+					.by_peer
+					.values()
+					.filter_map(|p| p.by_commit.get(&input.commit))
+					.find(|proof| proof.is_for(tx))
+			})
+			.cloned()
+			.collect()
+	}
+	/// Check every input of the tx is proven, if it has more than `above`
+	/// inputs. Never asks for proofs when `above` is 0.
+	pub fn check(&self, tx: &Transaction, above: usize) -> Result<(), PoolError> {
+		let inputs = tx.inputs().len();
+		if above == 0 || inputs <= above {
+			return Ok(());
+		}
+		if self.for_tx(tx).len() < inputs {
+			return Err(PoolError::MissingInputProofs);
+		}
+		Ok(())
+	}
+}
@@ -30,12 +30,15 @@ extern crate serde_derive;
 #[macro_use]
 extern crate log;
 
+pub mod input_proofs;
 mod pool;
 pub mod transaction_pool;
 pub mod types;
 
+pub use crate::input_proofs::{InputProofs, ProofSource};
 pub use crate::pool::Pool;
-pub use crate::transaction_pool::TransactionPool;
+pub use crate::transaction_pool::{validate_tx, TransactionPool};
 pub use crate::types::{
-	BlockChain, DandelionConfig, PoolAdapter, PoolConfig, PoolEntry, PoolError, TxSource,
+	BlockChain, DandelionConfig, FluffMode, PoolAdapter, PoolConfig, PoolEntry, PoolError,
+	PoolSnapshot, PoolSnapshotDiff, StemOptions, TxRemoval, TxSource,
 };
@@ -22,12 +22,14 @@ use self::core::core::verifier_cache::VerifierCache;
 use self::core::core::{
 	Block, BlockHeader, BlockSums, Committed, Transaction, TxKernel, Weighting,
 };
+use self::util::secp::pedersen::Commitment;
 use self::util::RwLock;
-use crate::types::{BlockChain, PoolEntry, PoolError};
+use crate::types::{BlockChain, PoolEntry, PoolError, PoolSnapshot};
 use kepler_core as core;
 use kepler_util as util;
 use std::cmp::Reverse;
 use std::collections::{HashMap, HashSet};
+use std::mem;
 use std::sync::Arc;
 
 pub struct Pool {
@@ -58,6 +60,17 @@ impl Pool {
 		self.entries.iter().any(|x| x.tx.hash() == hash)
 	}
 
+	/// Are all the given kernels already in the pool, whether or not they came
+	/// aggregated the same way?
+	pub fn contains_kernels(&self, kernels: &[TxKernel]) -> bool {
+		let pool_kernels = self
+			.entries
+			.iter()
+			.flat_map(|x| x.tx.kernels())
+			.collect::<HashSet<_>>();
+		!kernels.is_empty() && kernels.iter().all(|k| pool_kernels.contains(k))
+	}
+
 	pub fn get_tx(&self, hash: Hash) -> Option<Transaction> {
 		self.entries
 			.iter()
@@ -115,6 +128,14 @@ impl Pool {
 		)
 	}
 
+	/// Snapshot of the pool entries along with the current chain head.
+	pub fn snapshot(&self) -> Result<PoolSnapshot, PoolError> {
+		Ok(PoolSnapshot {
+			header: self.blockchain.chain_head()?,
+			entries: self.entries.clone(),
+		})
+	}
+
 	/// Take pool transactions, filtering and ordering them in a way that's
 	/// appropriate to put in a mined block. Aggregates chains of dependent
 	/// transactions, orders by fee over weight and ensures the total weight
@@ -122,21 +143,33 @@ impl Pool {
 	pub fn prepare_mineable_transactions(
 		&self,
 		max_weight: usize,
+	) -> Result<Vec<Transaction>, PoolError> {
+		let snapshot = self.snapshot()?;
+		self.mineable_transactions(&snapshot, max_weight)
+	}
+
+	/// Same as prepare_mineable_transactions, from the transactions of a
+	/// snapshot of the pool. The same snapshot always gives the same
+	/// transactions in the same order, as long as the chain state is the
+	/// one the snapshot was taken at.
+	pub fn mineable_transactions(
+		&self,
+		snapshot: &PoolSnapshot,
+		max_weight: usize,
 	) -> Result<Vec<Transaction>, PoolError> {
 		let weighting = Weighting::AsLimitedTransaction(max_weight);
 
-		// Sort the txs in the pool via the "bucket" logic to -
+		// Sort the txs in the snapshot via the "bucket" logic to -
 		//   * maintain dependency ordering
 		//   * maximize cut-through
 		//   * maximize overall fees
-		let txs = self.bucket_transactions(weighting);
+		let txs = self.bucket_entries(&snapshot.entries, weighting);
 
-		// Iteratively apply the txs to the current chain state,
+		// Iteratively apply the txs to the chain state,
 		// rejecting any that do not result in a valid state.
 		// Verify these txs produce an aggregated tx below max_weight.
 		// Return a vec of all the valid txs.
-		let header = self.blockchain.chain_head()?;
-		let valid_txs = self.validate_raw_txs(&txs, None, &header, weighting)?;
+		let valid_txs = self.validate_raw_txs(&txs, None, &snapshot.header, weighting)?;
 		Ok(valid_txs)
 	}
 
@@ -168,12 +201,34 @@ impl Pool {
 		entry: PoolEntry,
 		extra_txs: Vec<Transaction>,
 		header: &BlockHeader,
+	) -> Result<(), PoolError> {
+		self.check_add(&entry.tx, &[], extra_txs, header)?;
+
+		// If we get here successfully then we can safely add the entry to the pool.
+		self.log_pool_add(&entry, header);
+		self.entries.push(entry);
+
+		Ok(())
+	}
+
+	/// Check the tx could be added to the pool, in place of the pool txs it
+	/// subsumes (if any), without actually adding it.
+	pub fn check_add(
+		&self,
+		tx: &Transaction,
+		subsumed: &[Transaction],
+		extra_txs: Vec<Transaction>,
+		header: &BlockHeader,
 	) -> Result<(), PoolError> {
 		// Combine all the txs from the pool with any extra txs provided.
-		let mut txs = self.all_transactions();
+		let mut txs: Vec<_> = self
+			.all_transactions()
+			.into_iter()
+			.filter(|x| !subsumed.contains(x))
+			.collect();
 
 		// Quick check to see if we have seen this tx before.
-		if txs.contains(&entry.tx) {
+		if txs.contains(tx) {
 			return Err(PoolError::DuplicateTx);
 		}
 
@@ -181,23 +236,44 @@ impl Pool {
 
 		let agg_tx = if txs.is_empty() {
 			// If we have nothing to aggregate then simply return the tx itself.
-			entry.tx.clone()
+			tx.clone()
 		} else {
 			// Create a single aggregated tx from the existing pool txs and the
-			// new entry
-			txs.push(entry.tx.clone());
+			// new tx
+			txs.push(tx.clone());
 			transaction::aggregate(txs)?
 		};
 
 		// Validate aggregated tx (existing pool + new tx), ignoring tx weight limits.
 		// Validate against known chain state at the provided header.
 		self.validate_raw_tx(&agg_tx, header, Weighting::NoLimit)?;
+		Ok(())
+	}
 
-		// If we get here successfully then we can safely add the entry to the pool.
+	/// Add a tx aggregating some of the pool txs in place of them, returning
+	/// the entries it replaced. The pool is left untouched if the resulting
+	/// pool is not valid.
+	pub fn replace_subsumed(
+		&mut self,
+		entry: PoolEntry,
+		subsumed: &[Transaction],
+		header: &BlockHeader,
+	) -> Result<Vec<PoolEntry>, PoolError> {
+		self.check_add(&entry.tx, subsumed, vec![], header)?;
+
+		let (removed, kept) = mem::take(&mut self.entries)
+			.into_iter()
+			.partition::<Vec<_>, _>(|x| subsumed.contains(&x.tx));
+		self.entries = kept;
+		debug!(
+			"replace_subsumed [{}]: evicted {} txs subsumed by {}",
+			self.name,
+			removed.len(),
+			entry.tx.hash(),
+		);
 		self.log_pool_add(&entry, header);
 		self.entries.push(entry);
-
-		Ok(())
+		Ok(removed)
 	}
 
 	fn log_pool_add(&self, entry: &PoolEntry, header: &BlockHeader) {
@@ -287,20 +363,26 @@ impl Pool {
 		&mut self,
 		extra_tx: Option<Transaction>,
 		header: &BlockHeader,
-	) -> Result<(), PoolError> {
-		let existing_entries = self.entries.clone();
-		self.entries.clear();
+	) -> Result<Vec<PoolEntry>, PoolError> {
+		let existing_entries = mem::take(&mut self.entries);
 
 		let mut extra_txs = vec![];
 		if let Some(extra_tx) = extra_tx {
 			extra_txs.push(extra_tx);
 		}
 
+		let mut removed = vec![];
 		for x in existing_entries {
-			let _ = self.add_to_pool(x, extra_txs.clone(), header);
+			match self.check_add(&x.tx, &[], extra_txs.clone(), header) {
+				Ok(()) => {
+					self.log_pool_add(&x, header);
+					self.entries.push(x);
+				}
+				Err(_) => removed.push(x),
+			}
 		}
 
-		Ok(())
+		Ok(removed)
 	}
 
 	/// Buckets consist of a vec of txs and track the aggregate fee_to_weight.
@@ -311,11 +393,15 @@ impl Pool {
 	/// Sorting the buckets by fee_to_weight will therefore preserve dependency ordering,
 	/// maximizing both cut-through and overall fees.
 	pub fn bucket_transactions(&self, weighting: Weighting) -> Vec<Transaction> {
+		self.bucket_entries(&self.entries, weighting)
+	}
+
+	fn bucket_entries(&self, entries: &[PoolEntry], weighting: Weighting) -> Vec<Transaction> {
 		let mut tx_buckets: Vec<Bucket> = Vec::new();
 		let mut output_commits = HashMap::new();
 		let mut rejected = HashSet::new();
 
-		for entry in &self.entries {
+		for entry in entries {
 			// check the commits index to find parents and their position
 			// if single parent then we are good, we can bucket it with its parent
 			// if multiple parents then we need to combine buckets, but for now simply reject it (rare case)
@@ -425,16 +511,25 @@ impl Pool {
 	}
 
 	/// Quick reconciliation step - we can evict any txs in the pool where
-	/// inputs or kernels intersect with the block.
-	pub fn reconcile_block(&mut self, block: &Block) {
+	/// inputs or kernels intersect with the block, returning them.
+	pub fn reconcile_block(&mut self, block: &Block) -> Vec<PoolEntry> {
 		// Filter txs in the pool based on the latest block.
 		// Reject any txs where we see a matching tx kernel in the block.
 		// Also reject any txs where we see a conflicting tx,
 		// where an input is spent in a different tx.
-		self.entries.retain(|x| {
-			!x.tx.kernels().iter().any(|y| block.kernels().contains(y))
-				&& !x.tx.inputs().iter().any(|y| block.inputs().contains(y))
-		});
+		let kernels: HashSet<Hash> = block.kernels().iter().map(|k| k.hash()).collect();
+		let inputs: HashSet<Commitment> = block.inputs().iter().map(|i| i.commitment()).collect();
+		let (removed, kept) = mem::take(&mut self.entries)
+			.into_iter()
+			.partition::<Vec<_>, _>(|x| {
+				x.tx.kernels().iter().any(|k| kernels.contains(&k.hash()))
+					|| x.tx
+						.inputs()
+						.iter()
+						.any(|i| inputs.contains(&i.commitment()))
+			});
+		self.entries = kept;
+		removed
 	}
 
 	/// Size of the pool.
@@ -17,17 +17,22 @@
 //! resulting tx pool can be added to the current chain state to produce a
 //! valid chain state.
 
+use self::core::clock::Clock;
 use self::core::core::hash::{Hash, Hashed};
 use self::core::core::id::ShortId;
 use self::core::core::verifier_cache::VerifierCache;
 use self::core::core::{transaction, Block, BlockHeader, Transaction, Weighting};
 use self::util::RwLock;
+use crate::input_proofs::InputProofs;
 use crate::pool::Pool;
-use crate::types::{BlockChain, PoolAdapter, PoolConfig, PoolEntry, PoolError, TxSource};
+use crate::types::{
+	BlockChain, FluffMode, PoolAdapter, PoolConfig, PoolEntry, PoolError, PoolSnapshot,
+	StemOptions, TxRemoval, TxSource,
+};
 use chrono::prelude::*;
 use kepler_core as core;
 use kepler_util as util;
-use std::collections::VecDeque;
+use std::collections::{HashSet, VecDeque};
 use std::sync::Arc;
 
 /// Transaction pool implementation.
@@ -45,6 +50,10 @@ pub struct TransactionPool {
 	pub verifier_cache: Arc<RwLock<dyn VerifierCache>>,
 	/// The pool adapter
 	pub adapter: Arc<dyn PoolAdapter>,
+	/// Clock the arrival time of the txs is taken from.
+	pub clock: Arc<dyn Clock>,
+	/// Proofs of the ability to spend the inputs of the txs relayed to us.
+	pub input_proofs: Arc<InputProofs>,
 }
 
 impl TransactionPool {
@@ -54,7 +63,9 @@ impl TransactionPool {
 		chain: Arc<dyn BlockChain>,
 		verifier_cache: Arc<RwLock<dyn VerifierCache>>,
 		adapter: Arc<dyn PoolAdapter>,
+		clock: Arc<dyn Clock>,
 	) -> TransactionPool {
+		let input_proofs = Arc::new(InputProofs::new(config.max_input_proofs, chain.clone()));
 		TransactionPool {
 			config,
 			txpool: Pool::new(chain.clone(), verifier_cache.clone(), "txpool".to_string()),
@@ -67,6 +78,8 @@ impl TransactionPool {
 			blockchain: chain,
 			verifier_cache,
 			adapter,
+			clock,
+			input_proofs,
 		}
 	}
 
@@ -99,19 +112,21 @@ impl TransactionPool {
 		header: &BlockHeader,
 	) -> Result<(), PoolError> {
 		// First deaggregate the tx based on current txpool txs.
-		if entry.tx.kernels().len() > 1 {
-			let txs = self.txpool.find_matching_transactions(entry.tx.kernels());
-			if !txs.is_empty() {
-				let tx = transaction::deaggregate(entry.tx, txs)?;
-
-				// Validate this deaggregated tx "as tx", subject to regular tx weight limits.
-				tx.validate(Weighting::AsTransaction, self.verifier_cache.clone())?;
-
-				entry.tx = tx;
-				entry.src = TxSource::Deaggregate;
+		let (deaggregated, subsumed) = self.deaggregate_for_txpool(&entry.tx);
+		if let Some(tx) = deaggregated {
+			entry.tx = tx;
+			entry.src = TxSource::Deaggregate;
+		}
+		if subsumed.is_empty() {
+			self.txpool.add_to_pool(entry.clone(), vec![], header)?;
+		} else {
+			let removed = self
+				.txpool
+				.replace_subsumed(entry.clone(), &subsumed, header)?;
+			for x in removed {
+				self.adapter.tx_removed(&x, TxRemoval::Subsumed);
 			}
 		}
-		self.txpool.add_to_pool(entry.clone(), vec![], header)?;
 
 		// We now need to reconcile the stempool based on the new state of the txpool.
 		// Some stempool txs may no longer be valid and we need to evict them.
@@ -122,6 +137,38 @@ impl TransactionPool {
 		Ok(())
 	}
 
+	// Deaggregate a multi-kernel tx based on current txpool txs. Returns the
+	// deaggregated tx if the pool txs could be removed from it, otherwise the
+	// pool txs it subsumes (if any).
+	fn deaggregate_for_txpool(&self, tx: &Transaction) -> (Option<Transaction>, Vec<Transaction>) {
+		if tx.kernels().len() > 1 {
+			let txs = self.txpool.find_matching_transactions(tx.kernels());
+			if !txs.is_empty() {
+				match self.deaggregate(tx.clone(), txs.clone()) {
+					Ok(tx) => return (Some(tx), vec![]),
+					// Cut-through between the pool txs and the rest of the
+					// aggregate leaves nothing valid to deaggregate, accept the
+					// aggregate in place of the pool txs it subsumes instead.
+					Err(_) => return (None, txs),
+				}
+			}
+		}
+		(None, vec![])
+	}
+
+	// Remove the pool txs from a multi-kernel tx.
+	fn deaggregate(
+		&self,
+		tx: Transaction,
+		txs: Vec<Transaction>,
+	) -> Result<Transaction, PoolError> {
+		let tx = transaction::deaggregate(tx, txs)?;
+
+		// Validate this deaggregated tx "as tx", subject to regular tx weight limits.
+		tx.validate(Weighting::AsTransaction, self.verifier_cache.clone())?;
+		Ok(tx)
+	}
+
 	/// Add the given tx to the pool, directing it to either the stempool or
 	/// txpool based on stem flag provided.
 	pub fn add_to_pool(
@@ -131,36 +178,42 @@ impl TransactionPool {
 		stem: bool,
 		header: &BlockHeader,
 	) -> Result<(), PoolError> {
-		// Quick check to deal with common case of seeing the *same* tx
-		// broadcast from multiple peers simultaneously.
-		if !stem && self.txpool.contains_tx(tx.hash()) {
-			return Err(PoolError::DuplicateTx);
-		}
-
-		// Do we have the capacity to accept this transaction?
-		let acceptability = self.is_acceptable(&tx, stem);
-		let mut evict = false;
-		if !stem && acceptability.as_ref().err() == Some(&PoolError::OverCapacity) {
-			evict = true;
-		} else if acceptability.is_err() {
-			return acceptability;
-		}
-
-		// Make sure the transaction is valid before anything else.
-		// Validate tx accounting for max tx weight.
-		tx.validate(Weighting::AsTransaction, self.verifier_cache.clone())
-			.map_err(PoolError::InvalidTx)?;
+		self.add_entry_to_pool(src, tx, stem, None, header)
+	}
 
-		// Check the tx lock_time is valid based on current chain state.
-		self.blockchain.verify_tx_lock_height(&tx)?;
+	/// Add the given tx to the pool, applying per tx Dandelion overrides
+	/// to decide whether to stem or fluff it and for how long it may be stemmed.
+	pub fn add_to_pool_with_opts(
+		&mut self,
+		src: TxSource,
+		tx: Transaction,
+		stem_opts: StemOptions,
+		header: &BlockHeader,
+	) -> Result<(), PoolError> {
+		let stem = stem_opts.fluff != FluffMode::Fluff;
+		self.add_entry_to_pool(src, tx, stem, Some(stem_opts), header)
+	}
 
-		// Check coinbase maturity before we go any further.
-		self.blockchain.verify_coinbase_maturity(&tx)?;
+	fn add_entry_to_pool(
+		&mut self,
+		src: TxSource,
+		tx: Transaction,
+		stem: bool,
+		stem_opts: Option<StemOptions>,
+		header: &BlockHeader,
+	) -> Result<(), PoolError> {
+		if src == TxSource::Broadcast {
+			self.input_proofs
+				.check(&tx, self.config.input_proofs_above)?;
+		}
+		let evict = self.check_entry(&tx, stem)?;
 
 		let entry = PoolEntry {
 			src,
-			tx_at: Utc::now(),
+			tx_at: self.clock.now(),
+			input_proofs: self.input_proofs.for_tx(&tx),
 			tx,
+			stem_opts,
 		};
 
 		// If not stem then we are fluff.
@@ -185,6 +238,59 @@ impl TransactionPool {
 		Ok(())
 	}
 
+	// Run the checks on a tx entering the pool that do not depend on the
+	// pool txs, returning whether a tx needs evicting to make space for it.
+	fn check_entry(&self, tx: &Transaction, stem: bool) -> Result<bool, PoolError> {
+		// Quick check to deal with common case of seeing the *same* tx
+		// broadcast from multiple peers simultaneously.
+		if !stem && self.txpool.contains_tx(tx.hash()) {
+			return Err(PoolError::DuplicateTx);
+		}
+
+		// Same when all its kernels are already in the txpool, aggregated
+		// differently (e.g. a single tx out of an aggregate we accepted).
+		if self.txpool.contains_kernels(tx.kernels()) {
+			return Err(PoolError::DuplicateTx);
+		}
+
+		// Do we have the capacity to accept this transaction?
+		let evict = match self.is_acceptable(tx, stem) {
+			Ok(()) => false,
+			Err(PoolError::OverCapacity) if !stem => true,
+			Err(e) => return Err(e),
+		};
+
+		validate_tx(tx, &*self.blockchain, self.verifier_cache.clone())?;
+
+		Ok(evict)
+	}
+
+	/// Check whether the pool would accept the given tx, going through the
+	/// same checks as adding it but leaving the pool untouched and without
+	/// relaying anything.
+	pub fn check_tx(
+		&self,
+		tx: &Transaction,
+		stem: bool,
+		header: &BlockHeader,
+	) -> Result<(), PoolError> {
+		self.check_entry(tx, stem)?;
+
+		// A stem tx falls back to fluff if the stempool does not accept it.
+		if stem
+			&& self
+				.stempool
+				.check_add(tx, &[], self.txpool.all_transactions(), header)
+				.is_ok()
+		{
+			return Ok(());
+		}
+
+		let (deaggregated, subsumed) = self.deaggregate_for_txpool(tx);
+		let tx = deaggregated.as_ref().unwrap_or(tx);
+		self.txpool.check_add(tx, &subsumed, vec![], header)
+	}
+
 	// Remove the last transaction from the flattened bucket transactions.
 	// No other tx depends on it, it has low fee_to_weight and is unlikely to participate in any cut-through.
 	pub fn evict_from_txpool(&mut self) {
@@ -194,9 +300,14 @@ impl TransactionPool {
 		// Get last transaction and remove it
 		if let Some(evictable_transaction) = bucket_transactions.last() {
 			// Remove transaction
-			self.txpool
-				.entries
-				.retain(|x| x.tx != *evictable_transaction);
+			let adapter = &self.adapter;
+			self.txpool.entries.retain(|x| {
+				if x.tx == *evictable_transaction {
+					adapter.tx_removed(x, TxRemoval::Evicted);
+					return false;
+				}
+				true
+			});
 		};
 	}
 
@@ -232,8 +343,25 @@ impl TransactionPool {
 	/// provided block.
 	pub fn reconcile_block(&mut self, block: &Block) -> Result<(), PoolError> {
 		// First reconcile the txpool.
-		self.txpool.reconcile_block(block);
-		self.txpool.reconcile(None, &block.header)?;
+		let mut removed = self.txpool.reconcile_block(block);
+		removed.extend(self.txpool.reconcile(None, &block.header)?);
+
+		// Let the adapter know about the txs that left the txpool, either
+		// confirmed by the block or no longer valid.
+		let kernels: HashSet<Hash> = block.kernels().iter().map(|k| k.hash()).collect();
+		for entry in removed {
+			let removal = if entry
+				.tx
+				.kernels()
+				.iter()
+				.all(|k| kernels.contains(&k.hash()))
+			{
+				TxRemoval::Confirmed
+			} else {
+				TxRemoval::Evicted
+			};
+			self.adapter.tx_removed(&entry, removal);
+		}
 
 		// Now reconcile our stempool, accounting for the updated txpool txs.
 		self.stempool.reconcile_block(block);
@@ -300,4 +428,43 @@ impl TransactionPool {
 		self.txpool
 			.prepare_mineable_transactions(self.config.mineable_max_weight)
 	}
+
+	/// Snapshot of the txpool, to build a block from while the pool keeps
+	/// changing.
+	pub fn snapshot(&self) -> Result<PoolSnapshot, PoolError> {
+		self.txpool.snapshot()
+	}
+
+	/// Returns a vector of transactions from a snapshot of the txpool so we
+	/// can build a block from them.
+	pub fn mineable_transactions(
+		&self,
+		snapshot: &PoolSnapshot,
+	) -> Result<Vec<Transaction>, PoolError> {
+		self.txpool
+			.mineable_transactions(snapshot, self.config.mineable_max_weight)
+	}
+}
+
+/// Run the checks on a tx entering the pool that depend neither on the pool
+/// txs nor on its state: the tx validity (signatures and range proofs, their
+/// results cached), lock height and coinbase maturity. Not needing the pool
+/// lock, they can run ahead of adding the tx, which then finds them cached.
+pub fn validate_tx(
+	tx: &Transaction,
+	blockchain: &dyn BlockChain,
+	verifier_cache: Arc<RwLock<dyn VerifierCache>>,
+) -> Result<(), PoolError> {
+	// Make sure the transaction is valid before anything else.
+	// Validate tx accounting for max tx weight.
+	tx.validate(Weighting::AsTransaction, verifier_cache)
+		.map_err(PoolError::InvalidTx)?;
+
+	// Check the tx lock_time is valid based on current chain state.
+	blockchain.verify_tx_lock_height(tx)?;
+
+	// Check coinbase maturity before we go any further.
+	blockchain.verify_coinbase_maturity(tx)?;
+
+	Ok(())
 }
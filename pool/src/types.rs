@@ -19,13 +19,15 @@ use chrono::prelude::{DateTime, Utc};
 
 use self::core::core::block;
 use self::core::core::committed;
-use self::core::core::hash::Hash;
+use self::core::core::hash::{Hash, Hashed};
 use self::core::core::transaction::{self, Transaction};
-use self::core::core::{BlockHeader, BlockSums};
+use self::core::core::{BlockHeader, BlockSums, InputProof};
 use self::core::{consensus, global};
+use self::util::secp::pedersen::Commitment;
 use failure::Fail;
 use kepler_core as core;
 use kepler_keychain as keychain;
+use kepler_util as util;
 
 /// Dandelion "epoch" length.
 const DANDELION_EPOCH_SECS: u16 = 600;
@@ -45,6 +47,10 @@ const DANDELION_STEM_PROBABILITY: u8 = 90;
 /// If set to false we will stem/fluff our txs as per current epoch.
 const DANDELION_ALWAYS_STEM_OUR_TXS: bool = true;
 
+/// Bounds of the random delay before relaying a fluffed tx to each peer.
+const DANDELION_RELAY_JITTER_MIN_MS: u32 = 0;
+const DANDELION_RELAY_JITTER_MAX_MS: u32 = 2_000;
+
 /// Configuration for "Dandelion".
 /// Note: shared between p2p and pool.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -65,6 +71,15 @@ pub struct DandelionConfig {
 	/// Default to always stem our txs as described in Dandelion++ paper.
 	#[serde(default = "default_dandelion_always_stem_our_txs")]
 	pub always_stem_our_txs: bool,
+	/// Minimum delay (in ms) before relaying a fluffed tx to a peer.
+	#[serde(default = "default_dandelion_relay_jitter_min_ms")]
+	pub relay_jitter_min_ms: u32,
+	/// Maximum delay (in ms) before relaying a fluffed tx to a peer. Each
+	/// peer gets the tx after its own random delay within these bounds, so
+	/// the first peers relaying it don't give away its origin. Zero relays
+	/// it to all peers at once.
+	#[serde(default = "default_dandelion_relay_jitter_max_ms")]
+	pub relay_jitter_max_ms: u32,
 }
 
 impl Default for DandelionConfig {
@@ -75,6 +90,8 @@ impl Default for DandelionConfig {
 			aggregation_secs: default_dandelion_aggregation_secs(),
 			stem_probability: default_dandelion_stem_probability(),
 			always_stem_our_txs: default_dandelion_always_stem_our_txs(),
+			relay_jitter_min_ms: default_dandelion_relay_jitter_min_ms(),
+			relay_jitter_max_ms: default_dandelion_relay_jitter_max_ms(),
 		}
 	}
 }
@@ -99,6 +116,14 @@ fn default_dandelion_always_stem_our_txs() -> bool {
 	DANDELION_ALWAYS_STEM_OUR_TXS
 }
 
+fn default_dandelion_relay_jitter_min_ms() -> u32 {
+	DANDELION_RELAY_JITTER_MIN_MS
+}
+
+fn default_dandelion_relay_jitter_max_ms() -> u32 {
+	DANDELION_RELAY_JITTER_MAX_MS
+}
+
 /// Transaction pool configuration
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct PoolConfig {
@@ -121,6 +146,26 @@ pub struct PoolConfig {
 	/// blocks.
 	#[serde(default = "default_mineable_max_weight")]
 	pub mineable_max_weight: usize,
+
+	/// Number of threads validating the transactions received from our
+	/// peers before they enter the pool.
+	#[serde(default = "default_validation_threads")]
+	pub validation_threads: usize,
+
+	/// Maximum number of transactions received from our peers waiting to be
+	/// validated, more are dropped.
+	#[serde(default = "default_max_intake_queue")]
+	pub max_intake_queue: usize,
+
+	/// Number of inputs above which the txs relayed to us need a proof of
+	/// the ability to spend each of their inputs, 0 to never ask for them.
+	#[serde(default = "default_input_proofs_above")]
+	pub input_proofs_above: usize,
+
+	/// Maximum number of input proofs kept, waiting for their txs or to be
+	/// relayed along with them.
+	#[serde(default = "default_max_input_proofs")]
+	pub max_input_proofs: usize,
 }
 
 impl Default for PoolConfig {
@@ -130,6 +175,10 @@ impl Default for PoolConfig {
 			max_pool_size: default_max_pool_size(),
 			max_stempool_size: default_max_stempool_size(),
 			mineable_max_weight: default_mineable_max_weight(),
+			validation_threads: default_validation_threads(),
+			max_intake_queue: default_max_intake_queue(),
+			input_proofs_above: default_input_proofs_above(),
+			max_input_proofs: default_max_input_proofs(),
 		}
 	}
 }
@@ -146,6 +195,18 @@ fn default_max_stempool_size() -> usize {
 fn default_mineable_max_weight() -> usize {
 	global::max_block_weight()
 }
+fn default_validation_threads() -> usize {
+	4
+}
+fn default_max_intake_queue() -> usize {
+	1_000
+}
+fn default_input_proofs_above() -> usize {
+	0
+}
+fn default_max_input_proofs() -> usize {
+	10_000
+}
 
 /// Represents a single entry in the pool.
 /// A single (possibly aggregated) transaction.
@@ -157,6 +218,110 @@ pub struct PoolEntry {
 	pub tx_at: DateTime<Utc>,
 	/// The transaction itself.
 	pub tx: Transaction,
+	/// Dandelion overrides requested when this tx was pushed, if any.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub stem_opts: Option<StemOptions>,
+	/// Proofs of the ability to spend the inputs of the tx, relayed along
+	/// with it.
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub input_proofs: Vec<InputProof>,
+}
+
+impl PoolEntry {
+	/// Whether this tx was explicitly requested to be stemmed, even during a
+	/// fluff epoch.
+	pub fn force_stem(&self) -> bool {
+		self.stem_opts.map_or(false, |x| x.fluff == FluffMode::Stem)
+	}
+
+	/// Secs this tx may remain in the stempool before being fluffed.
+	/// A per tx maximum stem time can only shorten the embargo timer.
+	pub fn embargo_secs(&self, embargo_secs: u16) -> u16 {
+		match self.stem_opts.and_then(|x| x.max_stem_secs) {
+			Some(secs) => secs.min(embargo_secs),
+			None => embargo_secs,
+		}
+	}
+}
+
+/// Immutable view of the pool entries, in insertion order, along with the
+/// chain head they were taken at. Blocks built from a snapshot aren't affected
+/// by txs added to or evicted from the pool in the meantime.
+#[derive(Clone, Debug)]
+pub struct PoolSnapshot {
+	/// Chain head when the snapshot was taken.
+	pub header: BlockHeader,
+	/// Entries in the pool, in insertion order.
+	pub entries: Vec<PoolEntry>,
+}
+
+impl PoolSnapshot {
+	/// Hashes of the snapshot transactions, in insertion order.
+	pub fn tx_hashes(&self) -> Vec<Hash> {
+		self.entries.iter().map(|x| x.tx.hash()).collect()
+	}
+
+	/// Transactions added to and removed from the pool since the provided
+	/// earlier snapshot.
+	pub fn diff(&self, earlier: &PoolSnapshot) -> PoolSnapshotDiff {
+		let hashes = self.tx_hashes();
+		let earlier_hashes = earlier.tx_hashes();
+		PoolSnapshotDiff {
+			added: hashes
+				.iter()
+				.filter(|h| !earlier_hashes.contains(h))
+				.cloned()
+				.collect(),
+			removed: earlier_hashes
+				.iter()
+				.filter(|h| !hashes.contains(h))
+				.cloned()
+				.collect(),
+		}
+	}
+}
+
+/// Transactions added to and removed from the pool between two snapshots,
+/// in insertion order.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PoolSnapshotDiff {
+	pub added: Vec<Hash>,
+	pub removed: Vec<Hash>,
+}
+
+impl PoolSnapshotDiff {
+	/// Whether the pool didn't change between the two snapshots.
+	pub fn is_empty(&self) -> bool {
+		self.added.is_empty() && self.removed.is_empty()
+	}
+}
+
+/// How a pushed transaction should be relayed.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum FluffMode {
+	/// Broadcast immediately, skipping the stem phase.
+	Fluff,
+	/// Stem, even if the node is currently in a fluff epoch.
+	Stem,
+	/// Follow the node Dandelion config.
+	Auto,
+}
+
+impl Default for FluffMode {
+	fn default() -> FluffMode {
+		FluffMode::Auto
+	}
+}
+
+/// Per transaction overrides of the Dandelion config, so a wallet can opt
+/// out of stem delays for time sensitive payments.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct StemOptions {
+	/// How this tx should be relayed.
+	pub fluff: FluffMode,
+	/// Maximum time in secs this tx may spend in the stem phase before it is
+	/// fluffed, in place of the configured embargo timer.
+	pub max_stem_secs: Option<u16>,
 }
 
 /// Used to make decisions based on transaction acceptance priority from
@@ -184,6 +349,18 @@ impl TxSource {
 	}
 }
 
+/// Why a transaction left the transaction pool.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TxRemoval {
+	/// All its kernels made it into a block.
+	Confirmed,
+	/// Made invalid by a block (double spend, partial aggregation) or evicted
+	/// to make room in a full pool.
+	Evicted,
+	/// Replaced by a tx aggregating it.
+	Subsumed,
+}
+
 /// Possible errors when interacting with the transaction pool.
 #[derive(Debug, Fail, PartialEq)]
 pub enum PoolError {
@@ -221,6 +398,10 @@ pub enum PoolError {
 	/// Attempt to add a duplicate tx to the pool.
 	#[fail(display = "Duplicate tx")]
 	DuplicateTx,
+	/// Tx with more inputs than allowed without proofs of the ability to
+	/// spend them, relayed without them.
+	#[fail(display = "Missing input proofs")]
+	MissingInputProofs,
 	/// Other kinds of error (not yet pulled out into meaningful errors).
 	#[fail(display = "General pool error {}", _0)]
 	Other(String),
@@ -266,6 +447,9 @@ pub trait BlockChain: Sync + Send {
 
 	fn get_block_header(&self, hash: &Hash) -> Result<BlockHeader, PoolError>;
 	fn get_block_sums(&self, hash: &Hash) -> Result<BlockSums, PoolError>;
+
+	/// Whether the output with the commitment is in the UTXO set.
+	fn is_unspent(&self, commit: &Commitment) -> bool;
 }
 
 /// Bridge between the transaction pool and the rest of the system. Handles
@@ -277,6 +461,9 @@ pub trait PoolAdapter: Send + Sync {
 
 	/// The stem transaction pool has accepted this transactions as valid.
 	fn stem_tx_accepted(&self, entry: &PoolEntry) -> Result<(), PoolError>;
+
+	/// This transaction left the transaction pool.
+	fn tx_removed(&self, entry: &PoolEntry, removal: TxRemoval);
 }
 
 /// Dummy adapter used as a placeholder for real implementations
@@ -288,4 +475,5 @@ impl PoolAdapter for NoopAdapter {
 	fn stem_tx_accepted(&self, _entry: &PoolEntry) -> Result<(), PoolError> {
 		Ok(())
 	}
+	fn tx_removed(&self, _entry: &PoolEntry, _removal: TxRemoval) {}
 }
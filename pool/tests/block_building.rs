@@ -112,7 +112,12 @@ fn test_transaction_pool_block_building() {
 			assert_eq!(write_pool.total_size(), 5);
 		}
 
-		let txs = pool.read().prepare_mineable_transactions().unwrap();
+		// Building from the same snapshot always gives the same txs.
+		let snapshot = pool.read().snapshot().unwrap();
+		assert_eq!(snapshot.entries.len(), 5);
+		let txs = pool.read().mineable_transactions(&snapshot).unwrap();
+		assert_eq!(txs, pool.read().mineable_transactions(&snapshot).unwrap());
+		assert_eq!(txs, pool.read().prepare_mineable_transactions().unwrap());
 
 		let block = add_block(header, txs, &mut chain);
 
@@ -135,6 +140,11 @@ fn test_transaction_pool_block_building() {
 
 			assert_eq!(write_pool.total_size(), 0);
 		}
+
+		// All the snapshot txs were removed from the pool by the block.
+		let diff = pool.read().snapshot().unwrap().diff(&snapshot);
+		assert!(diff.added.is_empty());
+		assert_eq!(diff.removed, snapshot.tx_hashes());
 	}
 	// Cleanup db directory
 	clean_output_dir(db_root.clone());
@@ -20,11 +20,13 @@ use self::core::core::{Block, BlockHeader};
 use self::core::libtx;
 use self::core::pow::Difficulty;
 use self::keychain::{ExtKeychain, Keychain};
+use self::pool::TxRemoval;
 use self::util::RwLock;
 use crate::common::ChainAdapter;
 use crate::common::*;
 use kepler_core as core;
 use kepler_keychain as keychain;
+use kepler_pool as pool;
 use kepler_util as util;
 use std::sync::Arc;
 
@@ -132,7 +134,7 @@ fn test_transaction_pool_block_reconciliation() {
 
 		let txs_to_add = vec![
 			block_transaction,
-			conflict_transaction,
+			conflict_transaction.clone(),
 			valid_transaction.clone(),
 			block_child,
 			pool_child.clone(),
@@ -200,8 +202,10 @@ fn test_transaction_pool_block_reconciliation() {
 		}
 
 		// And reconcile the pool with this latest block.
+		let recorder = Arc::new(RemovalRecorder::default());
 		{
 			let mut write_pool = pool.write();
+			write_pool.adapter = recorder.clone();
 			write_pool.reconcile_block(&block).unwrap();
 
 			assert_eq!(write_pool.total_size(), 4);
@@ -210,6 +214,12 @@ fn test_transaction_pool_block_reconciliation() {
 			assert_eq!(write_pool.txpool.entries[2].tx, conflict_valid_child);
 			assert_eq!(write_pool.txpool.entries[3].tx, valid_child_valid);
 		}
+
+		// The adapter heard about every tx that left the pool.
+		let removed = recorder.removed.read();
+		assert_eq!(removed.len(), txs_to_add.len() - 4);
+		assert!(removed.contains(&(conflict_transaction, TxRemoval::Evicted)));
+		assert!(!removed.iter().any(|(tx, _)| *tx == valid_transaction));
 	}
 	// Cleanup db directory
 	clean_output_dir(db_root.clone());
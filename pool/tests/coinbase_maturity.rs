@@ -19,6 +19,7 @@ use self::core::core::verifier_cache::LruVerifierCache;
 use self::core::core::{BlockHeader, BlockSums, Transaction};
 use self::keychain::{ExtKeychain, Keychain};
 use self::pool::types::{BlockChain, PoolError};
+use self::util::secp::pedersen::Commitment;
 use self::util::RwLock;
 use crate::common::*;
 use kepler_core as core;
@@ -53,6 +54,10 @@ impl BlockChain for CoinbaseMaturityErrorChainAdapter {
 		unimplemented!();
 	}
 
+	fn is_unspent(&self, _commit: &Commitment) -> bool {
+		unimplemented!();
+	}
+
 	// Returns an ImmatureCoinbase for every tx we pass in.
 	fn verify_coinbase_maturity(&self, _tx: &Transaction) -> Result<(), PoolError> {
 		Err(PoolError::ImmatureCoinbase)
@@ -16,6 +16,7 @@
 
 use self::chain::store::ChainStore;
 use self::chain::types::Tip;
+use self::core::clock::SystemClock;
 use self::core::core::hash::{Hash, Hashed};
 use self::core::core::verifier_cache::VerifierCache;
 use self::core::core::{Block, BlockHeader, BlockSums, Committed, KernelFeatures, Transaction};
@@ -136,6 +137,10 @@ impl BlockChain for ChainAdapter {
 		Ok(())
 	}
 
+	fn is_unspent(&self, commit: &Commitment) -> bool {
+		self.utxo.read().contains(commit)
+	}
+
 	// Mocking this check out for these tests.
 	// We will test the Merkle proof verification logic elsewhere.
 	fn verify_coinbase_maturity(&self, _tx: &Transaction) -> Result<(), PoolError> {
@@ -148,6 +153,22 @@ impl BlockChain for ChainAdapter {
 	}
 }
 
+/// Pool adapter recording the transactions leaving the pool.
+#[derive(Default)]
+pub struct RemovalRecorder {
+	pub removed: RwLock<Vec<(Transaction, TxRemoval)>>,
+}
+
+impl PoolAdapter for RemovalRecorder {
+	fn tx_accepted(&self, _entry: &PoolEntry) {}
+	fn stem_tx_accepted(&self, _entry: &PoolEntry) -> Result<(), PoolError> {
+		Ok(())
+	}
+	fn tx_removed(&self, entry: &PoolEntry, removal: TxRemoval) {
+		self.removed.write().push((entry.tx.clone(), removal));
+	}
+}
+
 pub fn test_setup(
 	chain: Arc<dyn BlockChain>,
 	verifier_cache: Arc<RwLock<dyn VerifierCache>>,
@@ -158,10 +179,15 @@ pub fn test_setup(
 			max_pool_size: 50,
 			max_stempool_size: 50,
 			mineable_max_weight: 10_000,
+			validation_threads: 1,
+			max_intake_queue: 50,
+			input_proofs_above: 0,
+			max_input_proofs: 50,
 		},
 		chain.clone(),
 		verifier_cache.clone(),
 		Arc::new(NoopAdapter {}),
+		Arc::new(SystemClock),
 	)
 }
 
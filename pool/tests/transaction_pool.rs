@@ -14,12 +14,15 @@
 
 pub mod common;
 
+use self::core::core::hash::Hashed;
 use self::core::core::verifier_cache::LruVerifierCache;
-use self::core::core::{transaction, Block, BlockHeader, Weighting};
+use self::core::core::{transaction, Block, BlockHeader, InputProof, Transaction, Weighting};
 use self::core::libtx;
 use self::core::pow::Difficulty;
-use self::keychain::{ExtKeychain, Keychain};
-use self::pool::TxSource;
+use self::keychain::{BlindingFactor, ExtKeychain, Keychain, SwitchCommitmentType};
+use self::pool::{
+	FluffMode, InputProofs, PoolError, ProofSource, StemOptions, TxRemoval, TxSource,
+};
 use self::util::RwLock;
 use crate::common::*;
 use kepler_core as core;
@@ -243,6 +246,48 @@ fn test_the_transaction_pool() {
 		assert_eq!(entry.src, TxSource::Deaggregate);
 	}
 
+	// Now check an aggregated tx that cannot be deaggregated, due to cut-through
+	// with the tx already in the pool, replaces it instead of being rejected.
+	{
+		let mut write_pool = pool.write();
+
+		let tx5 = test_transaction(&keychain, vec![900], vec![899]);
+		let tx6 = test_transaction(&keychain, vec![899], vec![898]);
+		write_pool
+			.add_to_pool(test_source(), tx5.clone(), false, &header)
+			.unwrap();
+		assert_eq!(write_pool.total_size(), 7);
+
+		// tx5 output is cut-through with tx6 input
+		let agg_tx = transaction::aggregate(vec![tx5.clone(), tx6.clone()]).unwrap();
+		assert!(agg_tx.outputs().iter().all(|x| !tx5.outputs().contains(x)));
+		let recorder = Arc::new(RemovalRecorder::default());
+		write_pool.adapter = recorder.clone();
+		write_pool
+			.add_to_pool(test_source(), agg_tx.clone(), false, &header)
+			.unwrap();
+		assert_eq!(write_pool.total_size(), 7);
+		assert!(!write_pool.txpool.contains_tx(tx5.hash()));
+		let entry = write_pool.txpool.entries.last().unwrap();
+		assert_eq!(entry.tx, agg_tx);
+		assert_eq!(
+			*recorder.removed.read(),
+			vec![(tx5.clone(), TxRemoval::Subsumed)]
+		);
+
+		// Both components are now duplicates, whether stemmed or fluffed.
+		assert_eq!(
+			write_pool.add_to_pool(test_source(), tx5, true, &header),
+			Err(PoolError::DuplicateTx)
+		);
+		assert_eq!(
+			write_pool.add_to_pool(test_source(), tx6, false, &header),
+			Err(PoolError::DuplicateTx)
+		);
+		assert_eq!(write_pool.total_size(), 7);
+		assert!(write_pool.stempool.is_empty());
+	}
+
 	// Check we cannot "double spend" an output spent in a previous block.
 	// We use the initial coinbase output here for convenience.
 	{
@@ -476,3 +521,313 @@ fn test_the_transaction_pool() {
 	// Cleanup db directory
 	clean_output_dir(db_root.clone());
 }
+
+/// Test per tx Dandelion overrides when pushing txs to the pool.
+#[test]
+fn test_stem_opts() {
+	let keychain: ExtKeychain = Keychain::from_random_seed(false).unwrap();
+
+	let db_root = ".kepler_transaction_pool_stem_opts".to_string();
+	clean_output_dir(db_root.clone());
+
+	let chain = Arc::new(ChainAdapter::init(db_root.clone()).unwrap());
+
+	let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+
+	let pool = RwLock::new(test_setup(chain.clone(), verifier_cache.clone()));
+
+	let header = {
+		let height = 1;
+		let key_id = ExtKeychain::derive_key_id(1, height as u32, 0, 0, 0);
+		let reward = libtx::reward::output(
+			&keychain,
+			&libtx::ProofBuilder::new(&keychain),
+			&key_id,
+			0,
+			height,
+			false,
+		)
+		.unwrap();
+		let block = Block::new(&BlockHeader::default(), vec![], Difficulty::min(), reward).unwrap();
+
+		chain.update_db_for_block(&block);
+
+		block.header
+	};
+
+	let initial_tx = test_transaction_spending_coinbase(&keychain, &header, vec![500, 600, 700]);
+
+	{
+		let mut write_pool = pool.write();
+		write_pool
+			.add_to_pool(test_source(), initial_tx, false, &header)
+			.unwrap();
+		assert_eq!(write_pool.total_size(), 1);
+	}
+
+	// Fluff skips the stempool entirely.
+	{
+		let mut write_pool = pool.write();
+		let tx = test_transaction(&keychain, vec![500], vec![499]);
+		let opts = StemOptions {
+			fluff: FluffMode::Fluff,
+			max_stem_secs: None,
+		};
+		write_pool
+			.add_to_pool_with_opts(TxSource::PushApi, tx, opts, &header)
+			.unwrap();
+		assert_eq!(write_pool.total_size(), 2);
+		assert!(write_pool.stempool.is_empty());
+	}
+
+	// Stem and auto both go to the stempool, keeping the requested options.
+	{
+		let mut write_pool = pool.write();
+		let tx = test_transaction(&keychain, vec![600], vec![599]);
+		let opts = StemOptions {
+			fluff: FluffMode::Stem,
+			max_stem_secs: Some(5),
+		};
+		write_pool
+			.add_to_pool_with_opts(TxSource::PushApi, tx, opts, &header)
+			.unwrap();
+		let tx = test_transaction(&keychain, vec![700], vec![699]);
+		write_pool
+			.add_to_pool_with_opts(TxSource::PushApi, tx, StemOptions::default(), &header)
+			.unwrap();
+		assert_eq!(write_pool.total_size(), 2);
+		assert_eq!(write_pool.stempool.size(), 2);
+
+		let entry = &write_pool.stempool.entries[0];
+		assert!(entry.force_stem());
+		assert_eq!(entry.embargo_secs(180), 5);
+		let entry = &write_pool.stempool.entries[1];
+		assert!(!entry.force_stem());
+		assert_eq!(entry.embargo_secs(180), 180);
+	}
+
+	// Cleanup db directory
+	clean_output_dir(db_root.clone());
+}
+
+/// Test checking txs against the pool leaves the pool untouched.
+#[test]
+fn test_check_tx() {
+	let keychain: ExtKeychain = Keychain::from_random_seed(false).unwrap();
+
+	let db_root = ".kepler_transaction_pool_check_tx".to_string();
+	clean_output_dir(db_root.clone());
+
+	let chain = Arc::new(ChainAdapter::init(db_root.clone()).unwrap());
+
+	let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+
+	let pool = RwLock::new(test_setup(chain.clone(), verifier_cache.clone()));
+
+	let header = {
+		let height = 1;
+		let key_id = ExtKeychain::derive_key_id(1, height as u32, 0, 0, 0);
+		let reward = libtx::reward::output(
+			&keychain,
+			&libtx::ProofBuilder::new(&keychain),
+			&key_id,
+			0,
+			height,
+			false,
+		)
+		.unwrap();
+		let block = Block::new(&BlockHeader::default(), vec![], Difficulty::min(), reward).unwrap();
+
+		chain.update_db_for_block(&block);
+
+		block.header
+	};
+
+	let initial_tx = test_transaction_spending_coinbase(&keychain, &header, vec![500, 600, 700]);
+
+	// Nothing in the pool yet, the initial tx would be accepted either way.
+	{
+		let read_pool = pool.read();
+		read_pool.check_tx(&initial_tx, false, &header).unwrap();
+		read_pool.check_tx(&initial_tx, true, &header).unwrap();
+		assert_eq!(read_pool.total_size(), 0);
+		assert!(read_pool.stempool.is_empty());
+	}
+
+	// Spending outputs of the initial tx depends on it being in the pool.
+	let tx1 = test_transaction(&keychain, vec![500], vec![499]);
+	assert!(pool.read().check_tx(&tx1, false, &header).is_err());
+
+	{
+		let mut write_pool = pool.write();
+		write_pool
+			.add_to_pool(test_source(), initial_tx.clone(), false, &header)
+			.unwrap();
+		write_pool.check_tx(&tx1, false, &header).unwrap();
+		write_pool.check_tx(&tx1, true, &header).unwrap();
+		assert_eq!(write_pool.total_size(), 1);
+		assert!(write_pool.stempool.is_empty());
+	}
+
+	// Txs already in the pool, double spends and spends of unknown outputs
+	// are all rejected.
+	{
+		let read_pool = pool.read();
+		assert_eq!(
+			read_pool.check_tx(&initial_tx, false, &header),
+			Err(PoolError::DuplicateTx)
+		);
+		let double_spend_tx = test_transaction_spending_coinbase(&keychain, &header, vec![501]);
+		assert!(read_pool
+			.check_tx(&double_spend_tx, false, &header)
+			.is_err());
+		let bad_tx = test_transaction(&keychain, vec![10_001], vec![10_000]);
+		assert!(read_pool.check_tx(&bad_tx, false, &header).is_err());
+	}
+
+	// Checks agree with what adding the tx does.
+	{
+		let mut write_pool = pool.write();
+		write_pool
+			.add_to_pool(test_source(), tx1.clone(), false, &header)
+			.unwrap();
+		assert_eq!(
+			write_pool.check_tx(&tx1, false, &header),
+			Err(PoolError::DuplicateTx)
+		);
+		let tx2 = test_transaction(&keychain, vec![499], vec![498]);
+		write_pool.check_tx(&tx2, false, &header).unwrap();
+		assert_eq!(write_pool.total_size(), 2);
+	}
+
+	// Cleanup db directory
+	clean_output_dir(db_root.clone());
+}
+
+// Proofs of the inputs of a tx built by test_transaction.
+fn input_proofs<K: Keychain>(keychain: &K, tx: &Transaction, values: &[u64]) -> Vec<InputProof> {
+	let excess = tx.kernels()[0].excess;
+	values
+		.iter()
+		.zip(tx.inputs())
+		.map(|(value, input)| {
+			let key_id = ExtKeychain::derive_key_id(1, *value as u32, 0, 0, 0);
+			let key = keychain
+				.derive_key(*value, &key_id, SwitchCommitmentType::Regular)
+				.unwrap();
+			let blind = BlindingFactor::from_secret_key(key);
+			InputProof::create(input.commit, *value, &blind, excess).unwrap()
+		})
+		.collect()
+}
+
+/// Test txs relayed with many inputs need proofs of the ability to spend them.
+#[test]
+fn test_input_proofs() {
+	let keychain: ExtKeychain = Keychain::from_random_seed(false).unwrap();
+
+	let db_root = ".kepler_transaction_pool_input_proofs".to_string();
+	clean_output_dir(db_root.clone());
+
+	let chain = Arc::new(ChainAdapter::init(db_root.clone()).unwrap());
+
+	let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+
+	let mut pool = test_setup(chain.clone(), verifier_cache.clone());
+	pool.config.input_proofs_above = 1;
+
+	let header = {
+		let height = 1;
+		let key_id = ExtKeychain::derive_key_id(1, height as u32, 0, 0, 0);
+		let reward = libtx::reward::output(
+			&keychain,
+			&libtx::ProofBuilder::new(&keychain),
+			&key_id,
+			0,
+			height,
+			false,
+		)
+		.unwrap();
+		let block = Block::new(&BlockHeader::default(), vec![], Difficulty::min(), reward).unwrap();
+
+		chain.update_db_for_block(&block);
+
+		block.header
+	};
+
+	// Outputs of the chain to spend, only those can be proven.
+	let initial_tx = test_transaction_spending_coinbase(&keychain, &header, vec![500, 600, 700]);
+	let header = {
+		let key_id = ExtKeychain::derive_key_id(1, 2, 0, 0, 0);
+		let fees = initial_tx.fee();
+		let reward = libtx::reward::output(
+			&keychain,
+			&libtx::ProofBuilder::new(&keychain),
+			&key_id,
+			fees,
+			header.height,
+			false,
+		)
+		.unwrap();
+		let block = Block::new(&header, vec![initial_tx], Difficulty::min(), reward).unwrap();
+
+		chain.update_db_for_block(&block);
+
+		block.header
+	};
+	let peer = ProofSource::Peer("10.0.0.1:3414".parse().unwrap());
+
+	// Two inputs need proofs, unless the tx was pushed to us.
+	let tx = test_transaction(&keychain, vec![500, 600], vec![1_099]);
+	assert_eq!(
+		pool.add_to_pool(test_source(), tx.clone(), false, &header),
+		Err(PoolError::MissingInputProofs)
+	);
+
+	// Proofs of another tx don't count, invalid ones are rejected.
+	let other_tx = test_transaction(&keychain, vec![500, 600], vec![1_098]);
+	assert_eq!(
+		pool.input_proofs
+			.add(peer, input_proofs(&keychain, &other_tx, &[500, 600])),
+		0
+	);
+	assert!(pool.input_proofs.for_tx(&tx).is_empty());
+	let mut invalid = input_proofs(&keychain, &tx, &[500, 600]);
+	invalid[0].s_value = invalid[1].s_value.clone();
+	assert_eq!(pool.input_proofs.add(peer, invalid), 1);
+	assert_eq!(
+		pool.add_to_pool(test_source(), tx.clone(), false, &header),
+		Err(PoolError::MissingInputProofs)
+	);
+
+	// Proofs of outputs not in the UTXO set aren't even verified.
+	let unknown_tx = test_transaction(&keychain, vec![800, 900], vec![1_699]);
+	let mut unknown = input_proofs(&keychain, &unknown_tx, &[800, 900]);
+	unknown[0].s_value = unknown[1].s_value.clone();
+	assert_eq!(pool.input_proofs.add(peer, unknown), 0);
+	assert!(pool.input_proofs.for_tx(&unknown_tx).is_empty());
+
+	// A peer only takes up its share of the proofs kept.
+	let few_proofs = InputProofs::new(8, chain.clone());
+	assert_eq!(
+		few_proofs.add(peer, input_proofs(&keychain, &tx, &[500, 600])),
+		0
+	);
+	assert_eq!(few_proofs.for_tx(&tx).len(), 1);
+
+	assert_eq!(
+		pool.input_proofs
+			.add(peer, input_proofs(&keychain, &tx, &[500, 600])),
+		0
+	);
+	assert_eq!(pool.input_proofs.for_tx(&tx).len(), 2);
+	pool.add_to_pool(test_source(), tx, false, &header).unwrap();
+
+	let tx = test_transaction(&keychain, vec![700, 1_099], vec![1_798]);
+	pool.add_to_pool(TxSource::PushApi, tx, false, &header)
+		.unwrap();
+	assert_eq!(pool.total_size(), 2);
+
+	// Cleanup db directory
+	clean_output_dir(db_root.clone());
+}
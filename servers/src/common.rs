@@ -15,6 +15,12 @@
 //! Modules common to all Kepler server types
 
 pub mod adapters;
+pub mod alerts;
+pub mod block_chunks;
+pub mod executor;
 pub mod hooks;
+pub mod policy;
+pub mod priority;
 pub mod stats;
+pub mod tx_intake;
 pub mod types;
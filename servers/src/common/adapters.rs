@@ -15,27 +15,45 @@
 //! Adapters connecting new block, new transaction, and accepted transaction
 //! events to consumers of those events.
 
-use crate::util::RwLock;
+use crate::util::{Mutex, RwLock};
+use std::collections::VecDeque;
 use std::fs::File;
 use std::io::Read;
 use std::path::PathBuf;
-use std::sync::{Arc, Weak};
+use std::sync::{mpsc, Arc, Weak};
 use std::thread;
-use std::time::Instant;
-
-use crate::chain::{self, BlockStatus, ChainAdapter, Options, SyncState, SyncStatus};
+use std::time::{self, Instant};
+
+use crate::api;
+use crate::chain::block_files::BlockFileWriter;
+use crate::chain::txhashset::{BitmapChunk, Segmenter};
+use crate::chain::{
+	self, BlockStatus, ChainAdapter, ChainEvent, Options, SyncState, SyncStatus, Tip,
+};
+use crate::common::alerts::Alerter;
+use crate::common::block_chunks::ChunkedDownloads;
 use crate::common::hooks::{ChainEvents, NetEvents};
-use crate::common::types::{ChainValidationMode, DandelionEpoch, ServerConfig};
+use crate::common::policy::TxPolicy;
+use crate::common::tx_intake::TxIntake;
+use crate::common::types::{
+	BadBlockDenylist, ChainValidationMode, DandelionEpoch, DuplicateBlockTracker,
+	ForkHeaderTracker, OrphanParentRequests, ServerConfig, DUPLICATE_BLOCKS_BAN_THRESHOLD,
+	DUPLICATE_BLOCKS_WINDOW_SECS, FORK_HEADERS_WINDOW_SECS,
+};
+use crate::core::clock::Clock;
 use crate::core::core::hash::{Hash, Hashed};
+use crate::core::core::pmmr::{self, Segment, SegmentIdentifier};
 use crate::core::core::transaction::Transaction;
 use crate::core::core::verifier_cache::VerifierCache;
-use crate::core::core::{BlockHeader, BlockSums, CompactBlock};
+use crate::core::core::{BlockHeader, BlockSums, CompactBlock, InputProof};
 use crate::core::pow::Difficulty;
 use crate::core::{core, global};
+use crate::kepler::upstream::UpstreamNode;
 use crate::p2p;
-use crate::p2p::types::PeerInfo;
+use crate::p2p::types::{PeerAddr, PeerInfo, ReasonForBan};
 use crate::pool;
-use crate::util::OneTime;
+use crate::util::secp::pedersen::{Commitment, RangeProof};
+use crate::util::{OneTime, Watch};
 use chrono::prelude::*;
 use chrono::Duration;
 use rand::prelude::*;
@@ -51,6 +69,13 @@ pub struct NetToChainAdapter {
 	peers: OneTime<Weak<p2p::Peers>>,
 	config: ServerConfig,
 	hooks: Vec<Box<dyn NetEvents + Send + Sync>>,
+	duplicates: DuplicateBlockTracker,
+	fork_headers: ForkHeaderTracker,
+	orphan_parents: OrphanParentRequests,
+	bad_blocks: BadBlockDenylist,
+	alerter: Arc<Alerter>,
+	tx_intake: Arc<TxIntake>,
+	chunked_blocks: ChunkedDownloads,
 }
 
 impl p2p::ChainAdapter for NetToChainAdapter {
@@ -62,6 +87,15 @@ impl p2p::ChainAdapter for NetToChainAdapter {
 		Ok(self.chain().head()?.height)
 	}
 
+	fn body_tail(&self) -> Result<u64, chain::Error> {
+		// Archive nodes have all blocks, and a chain without tail hasn't
+		// pruned any yet.
+		if self.chain().archive_mode() {
+			return Ok(0);
+		}
+		Ok(self.chain().tail().map(|tail| tail.height).unwrap_or(0))
+	}
+
 	fn get_transaction(&self, kernel_hash: Hash) -> Option<core::Transaction> {
 		self.tx_pool.read().retrieve_tx_by_kernel_hash(kernel_hash)
 	}
@@ -84,34 +118,53 @@ impl p2p::ChainAdapter for NetToChainAdapter {
 		Ok(true)
 	}
 
+	fn input_proofs_received(
+		&self,
+		proofs: Vec<core::InputProof>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		// nothing much we can do with them while syncing
+		if self.sync_state.is_syncing() {
+			return Ok(true);
+		}
+
+		// Verified without the pool lock, the txs they're for come next.
+		let input_proofs = self.tx_pool.read().input_proofs.clone();
+		let invalid = input_proofs.add(pool::ProofSource::Peer(peer_info.addr.0), proofs);
+		if invalid > 0 {
+			debug!(
+				"Received {} invalid input proofs from {}",
+				invalid, peer_info.addr
+			);
+			return Ok(false);
+		}
+		Ok(true)
+	}
+
 	fn transaction_received(
 		&self,
 		tx: core::Transaction,
 		stem: bool,
+		peer_info: &PeerInfo,
 	) -> Result<bool, chain::Error> {
 		// nothing much we can do with a new transaction while syncing
 		if self.sync_state.is_syncing() {
 			return Ok(true);
 		}
 
-		let source = pool::TxSource::Broadcast;
-
-		let header = self.chain().head_header()?;
-
 		for hook in &self.hooks {
 			hook.on_transaction_received(&tx);
 		}
 
+		// Validated and added to the pool by the tx validation workers.
 		let tx_hash = tx.hash();
-
-		let mut tx_pool = self.tx_pool.write();
-		match tx_pool.add_to_pool(source, tx, stem, &header) {
-			Ok(_) => Ok(true),
-			Err(e) => {
-				debug!("Transaction {} rejected: {:?}", tx_hash, e);
-				Ok(false)
-			}
+		if !self.tx_intake.submit(peer_info.addr, tx, stem) {
+			debug!(
+				"Transaction {} from {} dropped, intake queue full",
+				tx_hash, peer_info.addr
+			);
 		}
+		Ok(true)
 	}
 
 	fn block_received(
@@ -120,12 +173,14 @@ impl p2p::ChainAdapter for NetToChainAdapter {
 		peer_info: &PeerInfo,
 		opts: chain::Options,
 	) -> Result<bool, chain::Error> {
-		if self.chain().block_exists(b.hash())? {
+		let bhash = b.hash();
+		if self.duplicates.is_repeat(peer_info.addr, &bhash) || self.chain().block_exists(bhash)? {
+			self.duplicate_block(bhash, peer_info);
 			return Ok(true);
 		}
 		debug!(
 			"Received block {} at {} from {} [in/out/kern: {}/{}/{}] going to process.",
-			b.hash(),
+			bhash,
 			b.header.height,
 			peer_info.addr,
 			b.inputs().len(),
@@ -141,10 +196,11 @@ impl p2p::ChainAdapter for NetToChainAdapter {
 		peer_info: &PeerInfo,
 	) -> Result<bool, chain::Error> {
 		// No need to process this compact block if we have previously accepted the _full block_.
-		if self.chain().block_exists(cb.hash())? {
+		let bhash = cb.hash();
+		if self.duplicates.is_repeat(peer_info.addr, &bhash) || self.chain().block_exists(bhash)? {
+			self.duplicate_block(bhash, peer_info);
 			return Ok(true);
 		}
-		let bhash = cb.hash();
 		debug!(
 			"Received compact_block {} at {} from {} [out/kern/kern_ids: {}/{}/{}] going to process.",
 			bhash,
@@ -248,6 +304,16 @@ impl p2p::ChainAdapter for NetToChainAdapter {
 		if self.chain().block_exists(bh.hash())? {
 			return Ok(true);
 		}
+		if self.is_fork_spammer(peer_info) {
+			return Ok(true);
+		}
+		let header_head = self.chain().header_head()?;
+		if bh.total_difficulty() <= header_head.total_difficulty
+			&& self.chain().get_block_header(&bh.hash()).is_err()
+			&& self.fork_headers_received(vec![bh.hash()], peer_info)
+		{
+			return Ok(true);
+		}
 		if !self.sync_state.is_syncing() {
 			for hook in &self.hooks {
 				hook.on_header_received(&bh, &peer_info.addr);
@@ -265,6 +331,12 @@ impl p2p::ChainAdapter for NetToChainAdapter {
 				e.kind()
 			);
 			if e.is_bad_data() {
+				warn!(
+					"Block header {} from {} is invalid: {}, banning.",
+					bh.hash(),
+					peer_info.addr,
+					e.kind()
+				);
 				return Ok(false);
 			} else {
 				// we got an error when trying to process the block header
@@ -295,6 +367,25 @@ impl p2p::ChainAdapter for NetToChainAdapter {
 		if bhs.len() == 0 {
 			return Ok(false);
 		}
+		if self.is_fork_spammer(peer_info) {
+			return Ok(true);
+		}
+
+		// Unsolicited headers from a peer not even claiming more work than
+		// our header chain, of a fork with no more work either.
+		let header_head = self.chain().header_head()?;
+		if peer_info.total_difficulty() <= header_head.total_difficulty
+			&& bhs[bhs.len() - 1].total_difficulty() <= header_head.total_difficulty
+		{
+			let unknown: Vec<Hash> = bhs
+				.iter()
+				.map(|bh| bh.hash())
+				.filter(|h| self.chain().get_block_header(h).is_err())
+				.collect();
+			if !unknown.is_empty() && self.fork_headers_received(unknown, peer_info) {
+				return Ok(true);
+			}
+		}
 
 		// try to add headers to our header chain
 		match self.chain().sync_block_headers(bhs, chain::Options::SYNC) {
@@ -302,6 +393,11 @@ impl p2p::ChainAdapter for NetToChainAdapter {
 			Err(e) => {
 				debug!("Block headers refused by chain: {:?}", e);
 				if e.is_bad_data() {
+					warn!(
+						"Block headers from {} are invalid: {}, banning.",
+						peer_info.addr,
+						e.kind()
+					);
 					return Ok(false);
 				} else {
 					Err(e)
@@ -354,6 +450,211 @@ impl p2p::ChainAdapter for NetToChainAdapter {
 		}
 	}
 
+	fn get_block_filter(&self, h: Hash) -> Option<core::BlockFilter> {
+		self.chain().get_block_filter(&h).ok()
+	}
+
+	fn get_block_manifest(&self, h: Hash) -> Option<core::BlockManifest> {
+		let b = self
+			.chunked_blocks
+			.served_block(h, || self.chain().get_block(&h).ok())?;
+		Some(core::BlockManifest::from_block(&b))
+	}
+
+	fn get_block_chunk(
+		&self,
+		h: Hash,
+		part: core::ChunkPart,
+		index: u32,
+	) -> Option<core::BlockChunk> {
+		let b = self
+			.chunked_blocks
+			.served_block(h, || self.chain().get_block(&h).ok())?;
+		core::BlockChunk::from_block(&b, part, index)
+	}
+
+	fn block_manifest_received(
+		&self,
+		manifest: core::BlockManifest,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		let hash = manifest.hash;
+		if self.chain().block_exists(hash)? {
+			return Ok(true);
+		}
+		// Only asked for the blocks of the headers we have.
+		let header = match self.chain().get_block_header(&hash) {
+			Ok(header) => header,
+			Err(_) => {
+				debug!(
+					"Received unrequested block manifest {} from {}",
+					hash, peer_info.addr
+				);
+				return Ok(true);
+			}
+		};
+		// The header commits to the number of outputs and kernels, the
+		// inputs are only known once the block is assembled.
+		let prev = self.chain().get_previous_header(&header)?;
+		let leaves = |size: u64, prev_size: u64| {
+			pmmr::n_leaves(size).saturating_sub(pmmr::n_leaves(prev_size))
+		};
+		if manifest.outputs as u64 != leaves(header.output_mmr_size, prev.output_mmr_size)
+			|| manifest.kernels as u64 != leaves(header.kernel_mmr_size, prev.kernel_mmr_size)
+		{
+			return Ok(false);
+		}
+
+		let missing = self.chunked_blocks.start(manifest);
+		debug!(
+			"Requesting {} chunks of block {} at {} from {}",
+			missing.len(),
+			hash,
+			header.height,
+			peer_info.addr
+		);
+		if let Some(peer) = self.peers().get_connected_peer(peer_info.addr) {
+			for (part, index) in missing {
+				if let Err(e) = peer.send_block_chunk_request(hash, part, index) {
+					debug!("Skipped chunk request to {}: {:?}", peer_info.addr, e);
+					break;
+				}
+			}
+		}
+		Ok(true)
+	}
+
+	fn block_chunk_received(
+		&self,
+		chunk: core::BlockChunk,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		let hash = chunk.hash;
+		let body = match self.chunked_blocks.add(chunk) {
+			Ok(Some(body)) => body,
+			Ok(None) => return Ok(true),
+			Err(e) => {
+				// Asked for again along with the other missing chunks, once
+				// the body sync times out.
+				debug!(
+					"Dropped chunk of block {} from {}: {:?}",
+					hash, peer_info.addr, e
+				);
+				return Ok(true);
+			}
+		};
+		let header = self.chain().get_block_header(&hash)?;
+		match body.into_block(header) {
+			Ok(b) => self.block_received(b, peer_info, chain::Options::SYNC),
+			Err(e) => {
+				debug!(
+					"Failed to assemble block {} from the chunks of {}: {:?}",
+					hash, peer_info.addr, e
+				);
+				Ok(false)
+			}
+		}
+	}
+
+	fn get_bitmap_segment(
+		&self,
+		h: Hash,
+		id: SegmentIdentifier,
+	) -> Option<(Segment<BitmapChunk>, Hash, u64)> {
+		self.segmenter(h)?
+			.bitmap_segment(id)
+			.map_err(|e| debug!("Couldn't produce bitmap segment {:?} at {}: {}", id, h, e))
+			.ok()
+	}
+
+	fn get_output_segment(
+		&self,
+		h: Hash,
+		id: SegmentIdentifier,
+	) -> Option<Segment<core::OutputIdentifier>> {
+		self.segmenter(h)?
+			.output_segment(id)
+			.map_err(|e| debug!("Couldn't produce output segment {:?} at {}: {}", id, h, e))
+			.ok()
+	}
+
+	fn get_rangeproof_segment(
+		&self,
+		h: Hash,
+		id: SegmentIdentifier,
+	) -> Option<Segment<RangeProof>> {
+		self.segmenter(h)?
+			.rangeproof_segment(id)
+			.map_err(|e| {
+				debug!(
+					"Couldn't produce rangeproof segment {:?} at {}: {}",
+					id, h, e
+				)
+			})
+			.ok()
+	}
+
+	fn get_kernel_segment(
+		&self,
+		h: Hash,
+		id: SegmentIdentifier,
+	) -> Option<Segment<core::TxKernel>> {
+		self.segmenter(h)?
+			.kernel_segment(id)
+			.map_err(|e| debug!("Couldn't produce kernel segment {:?} at {}: {}", id, h, e))
+			.ok()
+	}
+
+	fn bitmap_segment_received(
+		&self,
+		block_hash: Hash,
+		segment: Segment<BitmapChunk>,
+		output_root: Hash,
+		bitmap_mmr_size: u64,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		let res =
+			self.chain()
+				.add_bitmap_segment(block_hash, segment, output_root, bitmap_mmr_size);
+		segment_added(res, peer_info)
+	}
+
+	fn output_segment_received(
+		&self,
+		block_hash: Hash,
+		segment: Segment<core::OutputIdentifier>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		segment_added(
+			self.chain().add_output_segment(block_hash, segment),
+			peer_info,
+		)
+	}
+
+	fn rangeproof_segment_received(
+		&self,
+		block_hash: Hash,
+		segment: Segment<RangeProof>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		segment_added(
+			self.chain().add_rangeproof_segment(block_hash, segment),
+			peer_info,
+		)
+	}
+
+	fn kernel_segment_received(
+		&self,
+		block_hash: Hash,
+		segment: Segment<core::TxKernel>,
+		peer_info: &PeerInfo,
+	) -> Result<bool, chain::Error> {
+		segment_added(
+			self.chain().add_kernel_segment(block_hash, segment),
+			peer_info,
+		)
+	}
+
 	fn kernel_data_read(&self) -> Result<File, chain::Error> {
 		self.chain().kernel_data_read()
 	}
@@ -425,7 +726,7 @@ impl p2p::ChainAdapter for NetToChainAdapter {
 		&self,
 		h: Hash,
 		txhashset_data: File,
-		_peer_info: &PeerInfo,
+		peer_info: &PeerInfo,
 	) -> Result<bool, chain::Error> {
 		// check status again after download, in case 2 txhashsets made it somehow
 		if let SyncStatus::TxHashsetDownload { .. } = self.sync_state.status() {
@@ -433,14 +734,21 @@ impl p2p::ChainAdapter for NetToChainAdapter {
 			return Ok(false);
 		}
 
-		match self
-			.chain()
-			.txhashset_write(h, txhashset_data, self.sync_state.as_ref())
-		{
+		match self.chain().txhashset_write_from(
+			h,
+			txhashset_data,
+			self.sync_state.as_ref(),
+			Some(peer_info.addr.to_string()),
+		) {
 			Ok(is_bad_data) => {
 				if is_bad_data {
+					// The provider gets banned upstream and the sync error below
+					// restarts state sync with a different peer.
 					self.chain().clean_txhashset_sandbox();
-					error!("Failed to save txhashset archive: bad data");
+					error!(
+						"Failed to save txhashset archive for {}: bad data from {}",
+						h, peer_info.addr
+					);
 					self.sync_state.set_sync_error(
 						chain::ErrorKind::TxHashSetErr("bad txhashset data".to_string()).into(),
 					);
@@ -465,6 +773,18 @@ impl p2p::ChainAdapter for NetToChainAdapter {
 	fn get_tmpfile_pathname(&self, tmpfile_name: String) -> PathBuf {
 		self.chain().get_tmpfile_pathname(tmpfile_name)
 	}
+
+	fn peer_connected(&self, peer_info: &PeerInfo) {
+		for hook in &self.hooks {
+			hook.on_peer_connected(peer_info);
+		}
+	}
+
+	fn peer_disconnected(&self, addr: PeerAddr) {
+		for hook in &self.hooks {
+			hook.on_peer_disconnected(&addr);
+		}
+	}
 }
 
 impl NetToChainAdapter {
@@ -476,7 +796,14 @@ impl NetToChainAdapter {
 		verifier_cache: Arc<RwLock<dyn VerifierCache>>,
 		config: ServerConfig,
 		hooks: Vec<Box<dyn NetEvents + Send + Sync>>,
+		alerter: Arc<Alerter>,
+		tx_policy: Option<Arc<TxPolicy>>,
 	) -> NetToChainAdapter {
+		let orphan_parents = OrphanParentRequests::new(
+			config.p2p_config.orphan_parent_requests(),
+			config.p2p_config.orphan_parent_retry_secs(),
+		);
+		let tx_intake = Arc::new(TxIntake::new(tx_pool.clone(), tx_policy));
 		NetToChainAdapter {
 			sync_state,
 			chain: Arc::downgrade(&chain),
@@ -485,9 +812,22 @@ impl NetToChainAdapter {
 			peers: OneTime::new(),
 			config,
 			hooks,
+			duplicates: DuplicateBlockTracker::new(),
+			fork_headers: ForkHeaderTracker::new(),
+			orphan_parents,
+			bad_blocks: BadBlockDenylist::new(),
+			alerter,
+			tx_intake,
+			chunked_blocks: ChunkedDownloads::new(),
 		}
 	}
 
+	/// Intake of the txs received from our peers, to be validated by the
+	/// workers started along with the server.
+	pub fn tx_intake(&self) -> Arc<TxIntake> {
+		self.tx_intake.clone()
+	}
+
 	/// Initialize a NetToChainAdaptor with reference to a Peers object.
 	/// Should only be called once.
 	pub fn init(&self, peers: Arc<p2p::Peers>) {
@@ -507,6 +847,26 @@ impl NetToChainAdapter {
 			.expect("Failed to upgrade weak ref to our chain.")
 	}
 
+	// The segments of the txhashset we serve, if they're at the block asked
+	// for. Peers only ask for the ones at our archive header.
+	fn segmenter(&self, h: Hash) -> Option<Arc<Segmenter>> {
+		match self.chain().segmenter() {
+			Ok(segmenter) if segmenter.header().hash() == h => Some(segmenter),
+			Ok(segmenter) => {
+				debug!(
+					"Segments asked at {}, we serve the ones at {}",
+					h,
+					segmenter.header().hash()
+				);
+				None
+			}
+			Err(e) => {
+				debug!("Couldn't serve the txhashset segments at {}: {}", h, e);
+				None
+			}
+		}
+	}
+
 	// Find the first locator hash that refers to a known header on our main chain.
 	fn find_common_header(&self, locator: &[Hash]) -> Option<BlockHeader> {
 		let header_pmmr = self.chain().header_pmmr();
@@ -546,32 +906,94 @@ impl NetToChainAdapter {
 		}
 
 		let bhash = b.hash();
-		let previous = self.chain().get_previous_header(&b.header);
+		let prev_hash = b.header.prev_hash;
+
+		// A block that failed to apply before, the same header with the same
+		// body, isn't processed again. Its sender was banned already, not the
+		// peers relaying the valid block under the same header.
+		let block_id = BadBlockDenylist::block_id(&b);
+		if block_id.map_or(false, |id| self.bad_blocks.contains(&id)) {
+			debug!(
+				"process_block: block {} from {} failed to apply before, dropped",
+				bhash, peer_info.addr
+			);
+			return Ok(true);
+		}
+
+		// Got the block, whatever comes of it, no need to request it anymore.
+		self.orphan_parents.remove(&bhash);
 
-		match self.chain().process_block(b, opts) {
+		match self
+			.chain()
+			.process_block_from(b, opts, Some(peer_info.addr.to_string()))
+		{
 			Ok(_) => {
 				self.validate_chain(bhash);
 				self.check_compact();
 				Ok(true)
 			}
+			Err(ref e) if e.is_invalid_block_state() => {
+				// Such a block has a valid PoW yet doesn't apply to the chain,
+				// it can only have been crafted on purpose.
+				warn!(
+					"process_block: block {} from {} is invalid: {}, banning.",
+					bhash,
+					peer_info.addr,
+					e.kind()
+				);
+				if let Some(id) = block_id {
+					self.bad_blocks.insert(id);
+				}
+				self.alerter.validation_failed(Utc::now().timestamp());
+				if let Err(e) = self
+					.peers()
+					.ban_peer(peer_info.addr, ReasonForBan::BadBlockState)
+				{
+					debug!("process_block: failed to ban {}: {:?}", peer_info.addr, e);
+				}
+				// Already banned, no need for the caller to ban the peer again.
+				Ok(true)
+			}
 			Err(ref e) if e.is_bad_data() => {
+				// The caller bans the peer, the reason only shows here.
+				warn!(
+					"process_block: block {} from {} is invalid: {}, banning.",
+					bhash,
+					peer_info.addr,
+					e.kind()
+				);
+				self.alerter.validation_failed(Utc::now().timestamp());
 				self.validate_chain(bhash);
 				Ok(false)
 			}
 			Err(e) => {
 				match e.kind() {
 					chain::ErrorKind::Orphan => {
-						if let Ok(previous) = previous {
-							// make sure we did not miss the parent block
-							if !self.chain().is_orphan(&previous.hash())
-								&& !self.sync_state.is_syncing()
-							{
-								debug!("process_block: received an orphan block, checking the parent: {:}", previous.hash());
-								self.request_block(&previous, peer_info, chain::Options::NONE)
-							}
+						// make sure we did not miss the parent block, asking
+						// the peer first and others later if it doesn't come
+						if !self.chain().is_orphan(&prev_hash)
+							&& !self.sync_state.is_syncing()
+							&& self.orphan_parents.add(prev_hash, peer_info.addr)
+						{
+							debug!(
+								"process_block: received an orphan block, checking the parent: {:}",
+								prev_hash
+							);
+							self.send_block_request_to_peer(prev_hash, peer_info, |peer, h| {
+								peer.send_block_request(h, chain::Options::NONE)
+							});
+							self.orphan_parents.requested(
+								&prev_hash,
+								peer_info.addr,
+								Utc::now().timestamp(),
+							);
 						}
 						Ok(true)
 					}
+					chain::ErrorKind::Known(_) => {
+						self.duplicate_block(bhash, peer_info);
+						Ok(true)
+					}
 					_ => {
 						debug!(
 							"process_block: block {} refused by chain: {}",
@@ -585,6 +1007,111 @@ impl NetToChainAdapter {
 		}
 	}
 
+	// Count a block we already know about sent again by the peer, banning it
+	// if it keeps flooding us with them.
+	fn duplicate_block(&self, bhash: Hash, peer_info: &PeerInfo) {
+		let count = self.duplicates.record(peer_info.addr, bhash);
+		if count >= DUPLICATE_BLOCKS_BAN_THRESHOLD {
+			warn!(
+				"Received {} duplicate blocks from {} in under {}s, banning.",
+				count, peer_info.addr, DUPLICATE_BLOCKS_WINDOW_SECS
+			);
+			if let Err(e) = self
+				.peers()
+				.ban_peer(peer_info.addr, ReasonForBan::DuplicateBlocks)
+			{
+				debug!("duplicate_block: failed to ban {}: {:?}", peer_info.addr, e);
+			}
+		}
+	}
+
+	// Whether the peer sent us too many low-work fork headers in the current
+	// window, its headers are then dropped without being processed.
+	fn is_fork_spammer(&self, peer_info: &PeerInfo) -> bool {
+		let max = self.config.p2p_config.max_fork_headers_per_hour() as usize;
+		self.fork_headers.count(peer_info.addr) > max
+	}
+
+	// Count low-work fork headers sent by the peer, each one costing a header
+	// MMR rewind to process. Bans the peer once past the allowed rate, returns
+	// whether it did so and the headers should be dropped.
+	fn fork_headers_received(&self, hashes: Vec<Hash>, peer_info: &PeerInfo) -> bool {
+		let count = self.fork_headers.record(peer_info.addr, hashes);
+		let max = self.config.p2p_config.max_fork_headers_per_hour() as usize;
+		if count <= max {
+			return false;
+		}
+		warn!(
+			"Received {} low-work fork headers from {} in under {}s, banning.",
+			count, peer_info.addr, FORK_HEADERS_WINDOW_SECS
+		);
+		if let Err(e) = self
+			.peers()
+			.ban_peer(peer_info.addr, ReasonForBan::ForkSpam)
+		{
+			debug!(
+				"fork_headers_received: failed to ban {}: {:?}",
+				peer_info.addr, e
+			);
+		}
+		true
+	}
+
+	/// Ask again for the missing parents of our orphan blocks that are due,
+	/// from the peers that sent us the orphans first, then from other peers
+	/// with more work than us.
+	pub fn request_orphan_parents(&self) {
+		if self.sync_state.is_syncing() {
+			return;
+		}
+		let now = Utc::now().timestamp();
+		for (parent, req) in self.orphan_parents.due(now) {
+			// The parent arrived, or is itself an orphan whose own parent
+			// gets requested.
+			if self.chain().block_exists(parent).unwrap_or(false) || self.chain().is_orphan(&parent)
+			{
+				self.orphan_parents.remove(&parent);
+				continue;
+			}
+
+			let peers = self.peers();
+			let peer = req
+				.senders
+				.iter()
+				.filter(|addr| !req.asked.contains(addr))
+				.filter_map(|addr| peers.get_connected_peer(*addr))
+				.next()
+				.or_else(|| {
+					let mut others = peers.more_work_peers().unwrap_or_default();
+					others.retain(|p| !req.asked.contains(&p.info.addr));
+					others.choose(&mut thread_rng()).cloned()
+				})
+				.or_else(|| {
+					// Everyone was asked already, try the senders again.
+					req.senders
+						.iter()
+						.filter_map(|addr| peers.get_connected_peer(*addr))
+						.next()
+				});
+
+			match peer {
+				Some(peer) => {
+					debug!(
+						"request_orphan_parents: asking {} for {}, request {}",
+						peer.info.addr,
+						parent,
+						req.attempts + 1
+					);
+					if let Err(e) = peer.send_block_request(parent, chain::Options::NONE) {
+						debug!("request_orphan_parents: failed: {:?}", e);
+					}
+					self.orphan_parents.requested(&parent, peer.info.addr, now);
+				}
+				None => trace!("request_orphan_parents: no peer to ask for {}", parent),
+			}
+		}
+	}
+
 	fn validate_chain(&self, bhash: Hash) {
 		// If we are running in "validate the full chain every block" then
 		// panic here if validation fails for any reason.
@@ -623,6 +1150,7 @@ impl NetToChainAdapter {
 		// uses a different thread to avoid blocking the caller thread (likely a peer)
 		let mut rng = thread_rng();
 		if 0 == rng.gen_range(0, global::COMPACTION_CHECK) {
+			// Not reniced, the compaction holds the txhashset lock.
 			let chain = self.chain().clone();
 			let _ = thread::Builder::new()
 				.name("compactor".to_string())
@@ -699,17 +1227,49 @@ impl NetToChainAdapter {
 	}
 }
 
+// A txhashset segment received from a peer, false if it doesn't match the
+// header it was asked for.
+fn segment_added(
+	res: Result<(), chain::Error>,
+	peer_info: &PeerInfo,
+) -> Result<bool, chain::Error> {
+	match res {
+		Ok(()) => Ok(true),
+		Err(e) if e.is_bad_data() => {
+			debug!("Received a bad segment from {}: {}", peer_info.addr, e);
+			Ok(false)
+		}
+		Err(e) => Err(e),
+	}
+}
+
 /// Implementation of the ChainAdapter for the network. Gets notified when the
 ///  accepted a new block, asking the pool to update its state and
 /// the network to broadcast the block
 pub struct ChainToPoolAndNetAdapter {
 	tx_pool: Arc<RwLock<pool::TransactionPool>>,
 	peers: OneTime<Weak<p2p::Peers>>,
+	events: Arc<Watch<ChainEvent>>,
 	hooks: Vec<Box<dyn ChainEvents + Send + Sync>>,
+	block_files: Option<Mutex<BlockFileWriter>>,
+	fast_block_announce: bool,
 }
 
 impl ChainAdapter for ChainToPoolAndNetAdapter {
 	fn block_accepted(&self, b: &core::Block, status: BlockStatus, opts: Options) {
+		// Archive all the blocks, forks included, in the order we accepted
+		// them so their parents always come first.
+		if let Some(ref block_files) = self.block_files {
+			if let Err(e) = block_files.lock().append(b) {
+				error!(
+					"failed to append block {} at {} to the block files: {}",
+					b.hash(),
+					b.header.height,
+					e
+				);
+			}
+		}
+
 		// not broadcasting blocks received through sync
 		if !opts.contains(chain::Options::SYNC) {
 			for hook in &self.hooks {
@@ -718,7 +1278,12 @@ impl ChainAdapter for ChainToPoolAndNetAdapter {
 			// If we mined the block then we want to broadcast the compact block.
 			// If we received the block from another node then broadcast "header first"
 			// to minimize network traffic.
-			if opts.contains(Options::MINE) {
+			// Unless configured to announce the header to everyone first and
+			// push the block right after, to get it out as fast as we can.
+			if self.fast_block_announce {
+				let cb: CompactBlock = b.clone().into();
+				self.peers().announce_block(&cb);
+			} else if opts.contains(Options::MINE) {
 				// propagate compact block out if we mined the block
 				let cb: CompactBlock = b.clone().into();
 				self.peers().broadcast_compact_block(&cb);
@@ -742,13 +1307,28 @@ impl ChainAdapter for ChainToPoolAndNetAdapter {
 			let _ = tx_pool.reconcile_block(b);
 
 			// First "age out" any old txs in the reorg_cache.
-			let cutoff = Utc::now() - Duration::minutes(30);
+			let cutoff = tx_pool.clock.now() - Duration::minutes(30);
 			tx_pool.truncate_reorg_cache(cutoff);
 		}
 
 		if is_reorg {
 			let _ = self.tx_pool.write().reconcile_reorg_cache(&b.header);
 		}
+
+		// Let the components waiting on it know the chain head moved.
+		if status == BlockStatus::Next || is_reorg {
+			self.events
+				.publish(ChainEvent::TipUpdated(Tip::from_header(&b.header)));
+		}
+	}
+
+	fn head_race(&self, b: &core::Block, won: bool) {
+		info!(
+			"head race: block {} at {} with same total difficulty as our head, {}",
+			b.hash(),
+			b.header.height,
+			if won { "won" } else { "lost" },
+		);
 	}
 }
 
@@ -756,12 +1336,18 @@ impl ChainToPoolAndNetAdapter {
 	/// Construct a ChainToPoolAndNetAdapter instance.
 	pub fn new(
 		tx_pool: Arc<RwLock<pool::TransactionPool>>,
+		events: Arc<Watch<ChainEvent>>,
 		hooks: Vec<Box<dyn ChainEvents + Send + Sync>>,
+		block_files: Option<BlockFileWriter>,
+		fast_block_announce: bool,
 	) -> ChainToPoolAndNetAdapter {
 		ChainToPoolAndNetAdapter {
 			tx_pool,
 			peers: OneTime::new(),
+			events,
 			hooks: hooks,
+			block_files: block_files.map(Mutex::new),
+			fast_block_announce,
 		}
 	}
 
@@ -783,7 +1369,12 @@ impl ChainToPoolAndNetAdapter {
 /// transactions that have been accepted.
 pub struct PoolToNetAdapter {
 	peers: OneTime<Weak<p2p::Peers>>,
+	chain: OneTime<Weak<chain::Chain>>,
 	dandelion_epoch: Arc<RwLock<DandelionEpoch>>,
+	upstream: Option<Arc<UpstreamNode>>,
+	pool_events: Arc<api::PoolEvents>,
+	trickler: TxTrickler,
+	kernels_seen: OneTime<Arc<KernelsSeen>>,
 }
 
 /// Adapter between the Dandelion monitor and the current Dandelion "epoch".
@@ -814,10 +1405,35 @@ impl DandelionAdapter for PoolToNetAdapter {
 
 impl pool::PoolAdapter for PoolToNetAdapter {
 	fn tx_accepted(&self, entry: &pool::PoolEntry) {
-		self.peers().broadcast_transaction(&entry.tx);
+		self.pool_events.tx_accepted(entry);
+		// Kept to tell how long the kernels take to get confirmed.
+		self.kernels_seen
+			.borrow()
+			.record(entry.tx.kernels(), entry.tx_at);
+		if let Some(upstream) = self.upstream.clone() {
+			// Forward to the upstream node without holding up the pool.
+			let tx = entry.tx.clone();
+			let input_proofs = entry.input_proofs.clone();
+			let _ = thread::Builder::new()
+				.name("upstream_push_tx".to_string())
+				.spawn(move || {
+					if let Err(e) = upstream.push_transaction(&tx, &input_proofs) {
+						error!("Failed to push tx {} upstream: {:?}", tx.hash(), e);
+					}
+				});
+			return;
+		}
+		self.trickler
+			.relay(self.peers(), entry.tx.clone(), entry.input_proofs.clone());
 	}
 
 	fn stem_tx_accepted(&self, entry: &pool::PoolEntry) -> Result<(), pool::PoolError> {
+		// No peers to stem to, the upstream node takes care of the Dandelion relay.
+		if self.upstream.is_some() {
+			info!("Relaying via upstream node. Fluffing.");
+			return Err(pool::PoolError::DandelionError);
+		}
+
 		// Take write lock on the current epoch.
 		// We need to be able to update the current relay peer if not currently connected.
 		let mut epoch = self.dandelion_epoch.write();
@@ -826,9 +1442,13 @@ impl pool::PoolAdapter for PoolToNetAdapter {
 		// Fallback to immediately fluffing the tx if we cannot stem for any reason.
 		// If "fluff" epoch then nothing to do right now (fluff via Dandelion monitor).
 		// If node is configured to always stem our (pushed via api) txs then do so.
-		if epoch.is_stem() || (entry.src.is_pushed() && epoch.always_stem_our_txs()) {
+		// Likewise if stemming was explicitly requested when the tx was pushed.
+		if epoch.is_stem()
+			|| (entry.src.is_pushed() && epoch.always_stem_our_txs())
+			|| entry.force_stem()
+		{
 			if let Some(peer) = epoch.relay_peer(&self.peers()) {
-				match peer.send_stem_transaction(&entry.tx) {
+				match peer.send_stem_transaction(&entry.tx, &entry.input_proofs) {
 					Ok(_) => {
 						info!("Stemming this epoch, relaying to next peer.");
 						Ok(())
@@ -847,20 +1467,40 @@ impl pool::PoolAdapter for PoolToNetAdapter {
 			Ok(())
 		}
 	}
+
+	fn tx_removed(&self, entry: &pool::PoolEntry, removal: pool::TxRemoval) {
+		self.pool_events.tx_removed(entry, removal);
+	}
 }
 
 impl PoolToNetAdapter {
-	/// Create a new pool to net adapter
-	pub fn new(config: pool::DandelionConfig) -> PoolToNetAdapter {
+	/// Create a new pool to net adapter, relaying transactions to the
+	/// upstream node instead of our peers if one is provided. Transactions
+	/// entering and leaving the pool are pushed to the `pool_events`
+	/// subscribers.
+	pub fn new(
+		config: pool::DandelionConfig,
+		upstream: Option<Arc<UpstreamNode>>,
+		pool_events: Arc<api::PoolEvents>,
+		clock: Arc<dyn Clock>,
+	) -> PoolToNetAdapter {
 		PoolToNetAdapter {
 			peers: OneTime::new(),
-			dandelion_epoch: Arc::new(RwLock::new(DandelionEpoch::new(config))),
+			chain: OneTime::new(),
+			dandelion_epoch: Arc::new(RwLock::new(DandelionEpoch::new(config.clone(), clock))),
+			upstream,
+			pool_events,
+			trickler: TxTrickler::new(config),
+			kernels_seen: OneTime::new(),
 		}
 	}
 
-	/// Setup the p2p server on the adapter
-	pub fn init(&self, peers: Arc<p2p::Peers>) {
+	/// Setup the p2p server and the chain on the adapter
+	pub fn init(&self, peers: Arc<p2p::Peers>, chain: Arc<chain::Chain>) {
 		self.peers.init(Arc::downgrade(&peers));
+		self.chain.init(Arc::downgrade(&chain));
+		self.kernels_seen
+			.init(Arc::new(KernelsSeen::new(Arc::downgrade(&chain))));
 	}
 
 	fn peers(&self) -> Arc<p2p::Peers> {
@@ -869,6 +1509,187 @@ impl PoolToNetAdapter {
 			.upgrade()
 			.expect("Failed to upgrade weak ref to our peers.")
 	}
+
+	fn chain(&self) -> Arc<chain::Chain> {
+		self.chain
+			.borrow()
+			.upgrade()
+			.expect("Failed to upgrade the weak ref to our chain.")
+	}
+}
+
+// Records the kernels of the transactions entering the pool as seen, on
+// its own thread, so the pool lock isn't held for a db transaction per
+// transaction. The kernels queued meanwhile are written in a single one.
+struct KernelsSeen {
+	queue: Mutex<mpsc::Sender<(Commitment, DateTime<Utc>)>>,
+}
+
+impl KernelsSeen {
+	fn new(chain: Weak<chain::Chain>) -> KernelsSeen {
+		let (queue, rx) = mpsc::channel();
+		let res = thread::Builder::new()
+			.name("kernels_seen".to_string())
+			.spawn(move || kernels_seen_loop(chain, rx));
+		if let Err(e) = res {
+			error!("kernels_seen: failed to start thread: {}", e);
+		}
+		KernelsSeen {
+			queue: Mutex::new(queue),
+		}
+	}
+
+	fn record(&self, kernels: &[core::TxKernel], seen: DateTime<Utc>) {
+		let queue = self.queue.lock();
+		for kernel in kernels {
+			if queue.send((kernel.excess, seen)).is_err() {
+				error!("kernels_seen: recording thread is gone");
+				return;
+			}
+		}
+	}
+}
+
+fn kernels_seen_loop(chain: Weak<chain::Chain>, rx: mpsc::Receiver<(Commitment, DateTime<Utc>)>) {
+	while let Ok(first) = rx.recv() {
+		let mut seen = vec![first];
+		seen.extend(rx.try_iter());
+		let chain = match chain.upgrade() {
+			Some(chain) => chain,
+			None => break,
+		};
+		if let Err(e) = chain.record_kernels_seen(&seen) {
+			warn!("Failed to record {} kernels as seen: {:?}", seen.len(), e);
+		}
+	}
+}
+
+// Relays fluffed transactions to each of our peers after its own random
+// delay instead of to all of them at once, so the first peers relaying them
+// don't give away where they originated. A single thread sends them all as
+// they come due.
+struct TxTrickler {
+	config: pool::DandelionConfig,
+	queue: Mutex<mpsc::Sender<Trickle>>,
+}
+
+impl TxTrickler {
+	fn new(config: pool::DandelionConfig) -> TxTrickler {
+		let (queue, rx) = mpsc::channel();
+		let res = thread::Builder::new()
+			.name("tx_trickle".to_string())
+			.spawn(move || trickle_loop(rx));
+		if let Err(e) = res {
+			error!("trickle_transaction: failed to start thread: {}", e);
+		}
+		TxTrickler {
+			config,
+			queue: Mutex::new(queue),
+		}
+	}
+
+	fn relay(&self, peers: Arc<p2p::Peers>, tx: Transaction, input_proofs: Vec<InputProof>) {
+		let min = self.config.relay_jitter_min_ms;
+		let max = self.config.relay_jitter_max_ms.max(min);
+		if max == 0 {
+			peers.broadcast_transaction(&tx, &input_proofs);
+			return;
+		}
+
+		let mut rng = thread_rng();
+		let mut schedule: Vec<_> = peers
+			.connected_peers()
+			.into_iter()
+			.map(|p| (rng.gen_range(min, max + 1), p))
+			.collect();
+		schedule.sort_by_key(|(delay, _)| *delay);
+
+		let trickle = Trickle {
+			start: Instant::now(),
+			schedule: schedule.into(),
+			tx,
+			input_proofs,
+			relay: p2p::TxRelay::new(),
+			count: 0,
+		};
+		if self.queue.lock().send(trickle).is_err() {
+			error!("trickle_transaction: relay thread is gone");
+		}
+	}
+}
+
+// A transaction being trickled, with the peers it's still due to by their
+// delay (in ms) from the start.
+struct Trickle {
+	start: Instant,
+	schedule: VecDeque<(u32, Arc<p2p::Peer>)>,
+	tx: Transaction,
+	input_proofs: Vec<InputProof>,
+	relay: p2p::TxRelay,
+	count: usize,
+}
+
+impl Trickle {
+	fn next_due(&self) -> Option<Instant> {
+		self.schedule
+			.front()
+			.map(|(delay, _)| self.start + time::Duration::from_millis(*delay as u64))
+	}
+
+	// Sends the tx to the peers it's due to by now, true once it went to all.
+	fn send_due(&mut self, now: Instant) -> bool {
+		while let Some(due) = self.next_due() {
+			if due > now {
+				return false;
+			}
+			let (_, peer) = self.schedule.pop_front().unwrap();
+			if !peer.is_connected() {
+				continue;
+			}
+			match self.relay.send(&peer, &self.tx, &self.input_proofs) {
+				Ok(true) => self.count += 1,
+				Ok(false) => (),
+				Err(e) => debug!(
+					"trickle_transaction: failed to send {} to {}: {:?}",
+					self.tx.hash(),
+					peer.info.addr,
+					e
+				),
+			}
+		}
+		debug!(
+			"trickle_transaction: {} to {} peers, done.",
+			self.tx.hash(),
+			self.count
+		);
+		true
+	}
+}
+
+fn trickle_loop(rx: mpsc::Receiver<Trickle>) {
+	let mut trickles: Vec<Trickle> = vec![];
+	loop {
+		let now = Instant::now();
+		let mut i = 0;
+		while i < trickles.len() {
+			if trickles[i].send_due(now) {
+				trickles.swap_remove(i);
+			} else {
+				i += 1;
+			}
+		}
+
+		let res = match trickles.iter().filter_map(|t| t.next_due()).min() {
+			Some(due) => rx.recv_timeout(due.saturating_duration_since(Instant::now())),
+			None => rx.recv().map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+		};
+		match res {
+			Ok(trickle) => trickles.push(trickle),
+			Err(mpsc::RecvTimeoutError::Timeout) => (),
+			// The adapter is gone, so is the server.
+			Err(mpsc::RecvTimeoutError::Disconnected) => break,
+		}
+	}
 }
 
 /// Implements the view of the  required by the TransactionPool to
@@ -925,6 +1746,15 @@ impl pool::BlockChain for PoolToChainAdapter {
 			.map_err(|_| pool::PoolError::Other(format!("failed to validate tx")))
 	}
 
+	fn is_unspent(&self, commit: &Commitment) -> bool {
+		let chain = self.chain();
+		chain
+			.get_output_pos(commit)
+			.and_then(|pos| chain.get_unspent_output_at(pos))
+			.map(|out| out.commitment() == *commit)
+			.unwrap_or(false)
+	}
+
 	fn verify_coinbase_maturity(&self, tx: &Transaction) -> Result<(), pool::PoolError> {
 		self.chain()
 			.verify_coinbase_maturity(tx)
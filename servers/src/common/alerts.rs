@@ -0,0 +1,312 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Alerts on conditions needing the attention of the node operator. An alert
+//! stays active until its condition clears, the configured notifiers hear
+//! about it once when raised and once when cleared.
+
+use std::collections::VecDeque;
+use std::process::Command;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use chrono::prelude::Utc;
+use futures::TryFutureExt;
+use hyper::client::HttpConnector;
+use hyper::header::HeaderValue;
+use hyper::{Body, Client, Method, Request};
+use hyper_rustls::HttpsConnector;
+use serde_json::json;
+use tokio::runtime::{Builder, Runtime};
+
+use crate::api::{ActiveAlerts, Alert};
+use crate::common::hooks::parse_url;
+use crate::common::types::AlertConfig;
+use crate::util::RwLock;
+
+/// Block validation failures are counted over the last hour.
+const VALIDATION_FAILURE_WINDOW_SECS: i64 = 3600;
+
+/// Timeout of the alert webhook requests.
+const WEBHOOK_TIMEOUT_SECS: u64 = 10;
+
+/// Conditions the node raises an alert for.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AlertKind {
+	/// The disk of the chain data is nearly full.
+	DiskSpace,
+	/// The chain head stopped moving while our peers are ahead, once synced.
+	SyncStalled,
+	/// Not connected to any peer.
+	NoPeers,
+	/// Blocks keep failing validation.
+	ValidationFailures,
+	/// The local clock is off the network time.
+	ClockSkew,
+}
+
+impl AlertKind {
+	/// Name of the kind, as listed by the api.
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			AlertKind::DiskSpace => "disk_space",
+			AlertKind::SyncStalled => "sync_stalled",
+			AlertKind::NoPeers => "no_peers",
+			AlertKind::ValidationFailures => "validation_failures",
+			AlertKind::ClockSkew => "clock_skew",
+		}
+	}
+}
+
+/// Trait to be implemented by the alert notifiers.
+pub trait AlertNotifier {
+	/// The alert was raised, or cleared if `raised` is false.
+	fn notify(&self, alert: &Alert, raised: bool);
+}
+
+/// Keeps the active alerts and notifies of their changes.
+pub struct Alerter {
+	active: Arc<ActiveAlerts>,
+	notifiers: Vec<Box<dyn AlertNotifier + Send + Sync>>,
+	// Times of the latest block validation failures, oldest first.
+	validation_failures: RwLock<VecDeque<i64>>,
+}
+
+impl Alerter {
+	/// Alerter notifying as configured.
+	pub fn new(config: &AlertConfig, active: Arc<ActiveAlerts>) -> Alerter {
+		let mut notifiers: Vec<Box<dyn AlertNotifier + Send + Sync>> = Vec::new();
+		if config.alert_log {
+			notifiers.push(Box::new(AlertLogger));
+		}
+		if let Some(url) = parse_url(&config.alert_webhook_url) {
+			notifiers.push(Box::new(AlertWebHook::new(url)));
+		}
+		if let Some(command) = config.alert_command.clone() {
+			notifiers.push(Box::new(AlertCommand(command)));
+		}
+		Alerter::with_notifiers(active, notifiers)
+	}
+
+	/// Alerter notifying the provided notifiers.
+	pub fn with_notifiers(
+		active: Arc<ActiveAlerts>,
+		notifiers: Vec<Box<dyn AlertNotifier + Send + Sync>>,
+	) -> Alerter {
+		Alerter {
+			active,
+			notifiers,
+			validation_failures: RwLock::new(VecDeque::new()),
+		}
+	}
+
+	/// Raise the alert, the notifiers only hear about it if it wasn't active
+	/// already.
+	pub fn raise(&self, kind: AlertKind, message: String) {
+		let alert = Alert {
+			kind: kind.as_str().to_owned(),
+			message,
+			raised_at: Utc::now().to_rfc3339(),
+		};
+		if self.active.raise(alert.clone()) {
+			self.notify(&alert, true);
+		}
+	}
+
+	/// Clear the alert, if active.
+	pub fn clear(&self, kind: AlertKind) {
+		if let Some(alert) = self.active.clear(kind.as_str()) {
+			self.notify(&alert, false);
+		}
+	}
+
+	/// Raise the alert with the message if there's one, clear it otherwise.
+	pub fn update(&self, kind: AlertKind, message: Option<String>) {
+		match message {
+			Some(message) => self.raise(kind, message),
+			None => self.clear(kind),
+		}
+	}
+
+	/// Record a block that failed validation.
+	pub fn validation_failed(&self, now: i64) {
+		let mut failures = self.validation_failures.write();
+		failures.push_back(now);
+		prune_failures(&mut failures, now);
+	}
+
+	/// Number of blocks that failed validation over the last hour.
+	pub fn validation_failures(&self, now: i64) -> usize {
+		let mut failures = self.validation_failures.write();
+		prune_failures(&mut failures, now);
+		failures.len()
+	}
+
+	fn notify(&self, alert: &Alert, raised: bool) {
+		for notifier in &self.notifiers {
+			notifier.notify(alert, raised);
+		}
+	}
+}
+
+fn prune_failures(failures: &mut VecDeque<i64>, now: i64) {
+	while let Some(&at) = failures.front() {
+		if at > now - VALIDATION_FAILURE_WINDOW_SECS {
+			break;
+		}
+		failures.pop_front();
+	}
+}
+
+fn state(raised: bool) -> &'static str {
+	if raised {
+		"raised"
+	} else {
+		"cleared"
+	}
+}
+
+/// Logs the alerts with an "ALERT" marker, easy to grep for.
+struct AlertLogger;
+
+impl AlertNotifier for AlertLogger {
+	fn notify(&self, alert: &Alert, raised: bool) {
+		if raised {
+			warn!("ALERT raised: {}: {}", alert.kind, alert.message);
+		} else {
+			info!("ALERT cleared: {}: {}", alert.kind, alert.message);
+		}
+	}
+}
+
+/// POSTs the alerts as JSON to a url.
+struct AlertWebHook {
+	url: hyper::Uri,
+	client: Client<HttpsConnector<HttpConnector>>,
+	runtime: Runtime,
+}
+
+impl AlertWebHook {
+	fn new(url: hyper::Uri) -> AlertWebHook {
+		let https = HttpsConnector::new();
+		let client = Client::builder()
+			.keep_alive_timeout(Duration::from_secs(WEBHOOK_TIMEOUT_SECS))
+			.build::<_, hyper::Body>(https);
+		AlertWebHook {
+			url,
+			client,
+			runtime: Builder::new()
+				.threaded_scheduler()
+				.enable_all()
+				.core_threads(1)
+				.build()
+				.unwrap(),
+		}
+	}
+}
+
+impl AlertNotifier for AlertWebHook {
+	fn notify(&self, alert: &Alert, raised: bool) {
+		let payload = json!({
+			"state": state(raised),
+			"kind": alert.kind,
+			"message": alert.message,
+			"raised_at": alert.raised_at,
+		});
+		let mut req = Request::new(Body::from(payload.to_string()));
+		*req.method_mut() = Method::POST;
+		*req.uri_mut() = self.url.clone();
+		req.headers_mut().insert(
+			hyper::header::CONTENT_TYPE,
+			HeaderValue::from_static("application/json"),
+		);
+
+		let url = self.url.clone();
+		let future = self.client.request(req).map_err(move |_res| {
+			warn!("Error sending alert to {}", url);
+		});
+		self.runtime.spawn(future);
+	}
+}
+
+/// Runs a command with the alert state, kind and message as arguments.
+struct AlertCommand(String);
+
+impl AlertNotifier for AlertCommand {
+	fn notify(&self, alert: &Alert, raised: bool) {
+		let mut command = Command::new(&self.0);
+		command.args(&[state(raised), &alert.kind, &alert.message]);
+		// Don't hold up the alerts on a slow command.
+		let _ = thread::Builder::new()
+			.name("alert_command".to_string())
+			.spawn(move || match command.status() {
+				Ok(status) if !status.success() => {
+					warn!("alert command {:?} failed: {}", command, status)
+				}
+				Ok(_) => {}
+				Err(e) => warn!("alert command {:?} failed to run: {}", command, e),
+			});
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	struct Recorder(Arc<RwLock<Vec<(String, bool)>>>);
+
+	impl AlertNotifier for Recorder {
+		fn notify(&self, alert: &Alert, raised: bool) {
+			self.0.write().push((alert.kind.clone(), raised));
+		}
+	}
+
+	#[test]
+	fn test_alerter() {
+		let notified = Arc::new(RwLock::new(vec![]));
+		let recorder: Box<dyn AlertNotifier + Send + Sync> = Box::new(Recorder(notified.clone()));
+		let active = Arc::new(ActiveAlerts::new());
+		let alerter = Alerter::with_notifiers(active.clone(), vec![recorder]);
+
+		alerter.raise(AlertKind::NoPeers, "no peers".to_owned());
+		alerter.update(AlertKind::NoPeers, Some("still no peers".to_owned()));
+		alerter.update(AlertKind::ClockSkew, None);
+		assert_eq!(active.list().len(), 1);
+		assert_eq!(active.list()[0].message, "still no peers");
+
+		alerter.update(AlertKind::NoPeers, None);
+		alerter.clear(AlertKind::NoPeers);
+		assert!(active.list().is_empty());
+
+		// Notified once when raised and once when cleared.
+		assert_eq!(
+			*notified.read(),
+			vec![
+				("no_peers".to_owned(), true),
+				("no_peers".to_owned(), false)
+			]
+		);
+
+		let now = Utc::now().timestamp();
+		alerter.validation_failed(now - VALIDATION_FAILURE_WINDOW_SECS);
+		alerter.validation_failed(now - 10);
+		alerter.validation_failed(now);
+		assert_eq!(alerter.validation_failures(now), 2);
+		assert_eq!(
+			alerter.validation_failures(now + VALIDATION_FAILURE_WINDOW_SECS),
+			0
+		);
+	}
+}
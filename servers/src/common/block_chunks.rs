@@ -0,0 +1,209 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Large block bodies downloaded in chunks during the body sync. The chunks
+//! received are kept until the block is complete, so a timed out request
+//! only has the chunks still missing asked for again.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::core::core::block;
+use crate::core::core::hash::{Hash, Hashed};
+use crate::core::core::{Block, BlockChunk, BlockManifest, ChunkPart, ChunkedBody};
+use crate::util::{Mutex, RwLock};
+
+/// Most block bodies downloaded in chunks at once.
+pub const MAX_CHUNKED_BLOCKS: usize = 16;
+
+/// Time after which the chunks of a block that didn't complete are dropped.
+pub const CHUNKED_BLOCK_EXPIRY_SECS: u64 = 300;
+
+struct Download {
+	body: ChunkedBody,
+	started: Instant,
+}
+
+/// The block bodies being downloaded in chunks, by block hash.
+pub struct ChunkedDownloads {
+	downloads: Mutex<HashMap<Hash, Download>>,
+	// Block last served in chunks, its chunks being asked for one by one.
+	served: RwLock<Option<Arc<Block>>>,
+}
+
+impl ChunkedDownloads {
+	/// Create a new tracker, with no download in progress.
+	pub fn new() -> ChunkedDownloads {
+		ChunkedDownloads {
+			downloads: Mutex::new(HashMap::new()),
+			served: RwLock::new(None),
+		}
+	}
+
+	/// Start the download of the body listed by the manifest, or resume it
+	/// if it was already started with the same manifest. Returns the chunks
+	/// still missing.
+	pub fn start(&self, manifest: BlockManifest) -> Vec<(ChunkPart, u32)> {
+		let mut downloads = self.downloads.lock();
+		let expiry = Duration::from_secs(CHUNKED_BLOCK_EXPIRY_SECS);
+		downloads.retain(|_, d| d.started.elapsed() < expiry);
+
+		if let Some(download) = downloads.get(&manifest.hash) {
+			if *download.body.manifest() == manifest {
+				return download.body.missing();
+			}
+		}
+		if !downloads.contains_key(&manifest.hash) && downloads.len() >= MAX_CHUNKED_BLOCKS {
+			let oldest = downloads
+				.iter()
+				.min_by_key(|(_, d)| d.started)
+				.map(|(h, _)| *h);
+			if let Some(oldest) = oldest {
+				downloads.remove(&oldest);
+			}
+		}
+		let body = ChunkedBody::new(manifest);
+		let missing = body.missing();
+		downloads.insert(
+			body.manifest().hash,
+			Download {
+				body,
+				started: Instant::now(),
+			},
+		);
+		missing
+	}
+
+	/// Add a chunk received, checked against the manifest of its block.
+	/// Returns the body once all its chunks were received, None while some
+	/// are missing or if the block isn't being downloaded.
+	pub fn add(&self, chunk: BlockChunk) -> Result<Option<ChunkedBody>, block::Error> {
+		let mut downloads = self.downloads.lock();
+		let hash = chunk.hash;
+		let complete = match downloads.get_mut(&hash) {
+			Some(download) => {
+				download.body.add(chunk)?;
+				download.body.is_complete()
+			}
+			None => false,
+		};
+		if complete {
+			Ok(downloads.remove(&hash).map(|d| d.body))
+		} else {
+			Ok(None)
+		}
+	}
+
+	/// Number of block bodies being downloaded.
+	pub fn count(&self) -> usize {
+		self.downloads.lock().len()
+	}
+
+	/// The block to serve the chunks of, loaded unless it was the last one
+	/// served. Shared rather than copied for each chunk asked for.
+	pub fn served_block<F>(&self, hash: Hash, load: F) -> Option<Arc<Block>>
+	where
+		F: FnOnce() -> Option<Block>,
+	{
+		if let Some(b) = self.served.read().as_ref() {
+			if b.hash() == hash {
+				return Some(b.clone());
+			}
+		}
+		let b = Arc::new(load()?);
+		*self.served.write() = Some(b.clone());
+		Some(b)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::core::core::{ChunkItems, Input, OutputFeatures};
+	use crate::core::global;
+	use crate::util::secp::pedersen::Commitment;
+
+	fn manifest(n: u8, inputs: &[Input]) -> BlockManifest {
+		let chunk_hashes = inputs
+			.chunks(ChunkPart::Inputs.chunk_size())
+			.map(|c| ChunkItems::Inputs(c.to_vec()).hash())
+			.collect();
+		BlockManifest {
+			hash: Hash::from_vec(&[n; 32]),
+			inputs: inputs.len() as u32,
+			outputs: 0,
+			kernels: 0,
+			chunk_hashes,
+		}
+	}
+
+	fn chunk(manifest: &BlockManifest, index: u32, inputs: &[Input]) -> BlockChunk {
+		BlockChunk {
+			hash: manifest.hash,
+			index,
+			items: ChunkItems::Inputs(inputs.to_vec()),
+		}
+	}
+
+	#[test]
+	fn chunked_downloads() {
+		global::set_mining_mode(global::ChainTypes::AutomatedTesting);
+		let inputs: Vec<Input> = (1..=3)
+			.map(|n| Input::new(OutputFeatures::Plain, Commitment::from_vec(vec![n; 33])))
+			.collect();
+		let downloads = ChunkedDownloads::new();
+		let m = manifest(1, &inputs);
+		assert_eq!(
+			downloads.start(m.clone()),
+			vec![(ChunkPart::Inputs, 0), (ChunkPart::Inputs, 1)]
+		);
+
+		// Chunks of unknown blocks are ignored, bad chunks rejected.
+		let other = manifest(2, &inputs);
+		assert_eq!(downloads.add(chunk(&other, 0, &inputs[..2])), Ok(None));
+		assert_eq!(
+			downloads.add(chunk(&m, 0, &inputs[1..])),
+			Err(block::Error::InvalidChunk)
+		);
+
+		// Resuming only asks for the chunks still missing.
+		assert_eq!(downloads.add(chunk(&m, 0, &inputs[..2])), Ok(None));
+		assert_eq!(downloads.start(m.clone()), vec![(ChunkPart::Inputs, 1)]);
+
+		// Complete once the last chunk arrives.
+		let body = downloads.add(chunk(&m, 1, &inputs[2..])).unwrap().unwrap();
+		assert!(body.is_complete());
+		assert_eq!(downloads.count(), 0);
+
+		// Older downloads are dropped to make room.
+		for n in 0..=MAX_CHUNKED_BLOCKS {
+			downloads.start(manifest(10 + n as u8, &inputs));
+		}
+		assert_eq!(downloads.count(), MAX_CHUNKED_BLOCKS);
+	}
+
+	#[test]
+	fn served_block() {
+		let downloads = ChunkedDownloads::new();
+		let b = Block::default();
+		assert!(downloads.served_block(b.hash(), || None).is_none());
+		let served = downloads
+			.served_block(b.hash(), || Some(b.clone()))
+			.unwrap();
+		// Not loaded again, the same block is shared.
+		let again = downloads.served_block(b.hash(), || None).unwrap();
+		assert!(Arc::ptr_eq(&served, &again));
+	}
+}
@@ -0,0 +1,286 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Runtime shared by the server subsystems (API, stratum, sync, dandelion...)
+//! instead of each of them spinning up its own threads and runtimes. Tasks are
+//! spawned under a name and tracked, so the stats can tell what's running.
+
+use crate::common::stats::TaskStats;
+use crate::util::{Mutex, RwLock, StopState};
+use futures::channel::oneshot;
+use futures::executor::block_on;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::runtime::{Builder, Handle};
+use tokio::task::{self, JoinError, JoinHandle};
+use tokio::time;
+
+type TaskRegistry = Arc<RwLock<HashMap<String, TaskStats>>>;
+
+/// How often a periodic task checks whether it should stop, between runs.
+const STOP_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Handle to a named task spawned on the executor.
+pub struct TaskHandle {
+	name: String,
+	handle: JoinHandle<()>,
+}
+
+impl TaskHandle {
+	/// Name the task was spawned under.
+	pub fn name(&self) -> &str {
+		&self.name
+	}
+
+	/// Block until the task completes. Not to be called from a task running
+	/// on the executor.
+	pub fn join(self) -> Result<(), JoinError> {
+		block_on(self.handle)
+	}
+}
+
+// Keeps the stats of a task up to date, dropped along with the task when it
+// completes, panics or gets cancelled.
+struct TaskGuard {
+	name: String,
+	started: Instant,
+	tasks: TaskRegistry,
+}
+
+impl TaskGuard {
+	fn new(name: &str, tasks: &TaskRegistry) -> TaskGuard {
+		{
+			let mut tasks = tasks.write();
+			let stats = tasks
+				.entry(name.to_owned())
+				.or_insert_with(|| TaskStats::new(name));
+			stats.spawned += 1;
+			stats.running += 1;
+		}
+		TaskGuard {
+			name: name.to_owned(),
+			started: Instant::now(),
+			tasks: tasks.clone(),
+		}
+	}
+}
+
+impl Drop for TaskGuard {
+	fn drop(&mut self) {
+		if let Some(stats) = self.tasks.write().get_mut(&self.name) {
+			stats.running = stats.running.saturating_sub(1);
+			stats.run_time_ms += self.started.elapsed().as_millis() as u64;
+		}
+	}
+}
+
+/// Runs the async and blocking tasks of all the server subsystems on a
+/// single runtime.
+pub struct Executor {
+	handle: Handle,
+	shutdown: Mutex<Option<oneshot::Sender<()>>>,
+	tasks: TaskRegistry,
+}
+
+impl Executor {
+	/// New executor running async tasks on the provided number of worker
+	/// threads, one per cpu if 0. Blocking tasks get a thread each, taken
+	/// from a pool shrinking when idle.
+	pub fn new(threads: usize) -> std::io::Result<Executor> {
+		let (handle_tx, handle_rx) = mpsc::channel();
+		let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+		// The runtime lives on its own thread until the executor is dropped.
+		// Dropping it then waits for all its blocking tasks, and some never
+		// complete (the stratum server), so that thread is left to the
+		// process exit instead of hanging the shutdown.
+		thread::Builder::new()
+			.name("executor".to_string())
+			.spawn(move || {
+				let mut builder = Builder::new();
+				builder
+					.threaded_scheduler()
+					.enable_all()
+					.thread_name("executor_worker");
+				if threads > 0 {
+					builder.core_threads(threads);
+				}
+				match builder.build() {
+					Ok(mut runtime) => {
+						let _ = handle_tx.send(Ok(runtime.handle().clone()));
+						let _ = runtime.block_on(shutdown_rx);
+					}
+					Err(e) => {
+						let _ = handle_tx.send(Err(e));
+					}
+				}
+			})?;
+		let handle = handle_rx.recv().map_err(|_| {
+			std::io::Error::new(std::io::ErrorKind::Other, "executor thread failed")
+		})??;
+		Ok(Executor {
+			handle,
+			shutdown: Mutex::new(Some(shutdown_tx)),
+			tasks: Arc::new(RwLock::new(HashMap::new())),
+		})
+	}
+
+	/// Handle to the underlying runtime, for the libraries expecting one.
+	pub fn handle(&self) -> Handle {
+		self.handle.clone()
+	}
+
+	/// Spawn an async task under the provided name.
+	pub fn spawn<F>(&self, name: &str, task: F) -> TaskHandle
+	where
+		F: Future<Output = ()> + Send + 'static,
+	{
+		let guard = TaskGuard::new(name, &self.tasks);
+		let handle = self.handle.spawn(async move {
+			let _guard = guard;
+			task.await
+		});
+		TaskHandle {
+			name: name.to_owned(),
+			handle,
+		}
+	}
+
+	/// Spawn a task blocking its thread (long running loops, chain or pool
+	/// processing) under the provided name.
+	pub fn spawn_blocking<F>(&self, name: &str, task: F) -> TaskHandle
+	where
+		F: FnOnce() + Send + 'static,
+	{
+		let guard = TaskGuard::new(name, &self.tasks);
+		let handle = self.handle.enter(|| {
+			task::spawn_blocking(move || {
+				let _guard = guard;
+				task()
+			})
+		});
+		TaskHandle {
+			name: name.to_owned(),
+			handle,
+		}
+	}
+
+	/// Run the task right away then every period under the provided name,
+	/// until stopped.
+	/// Each run takes a blocking thread from the pool, only a timer checking
+	/// whether to stop is kept on the runtime in between.
+	pub fn spawn_periodic<F>(
+		&self,
+		name: &str,
+		period: Duration,
+		stop_state: Arc<StopState>,
+		task: F,
+	) -> TaskHandle
+	where
+		F: FnMut() + Send + 'static,
+	{
+		let task = Arc::new(Mutex::new(task));
+		self.spawn(name, async move {
+			let mut ticks = time::interval(period.min(STOP_CHECK_INTERVAL));
+			let mut last_run: Option<Instant> = None;
+			loop {
+				ticks.tick().await;
+				if stop_state.is_stopped() {
+					break;
+				}
+				if last_run.map_or(false, |t| t.elapsed() < period) {
+					continue;
+				}
+				last_run = Some(Instant::now());
+				let task = task.clone();
+				if let Err(e) = task::spawn_blocking(move || (&mut *task.lock())()).await {
+					error!("periodic task failed: {}", e);
+					break;
+				}
+			}
+		})
+	}
+
+	/// Stats of the tasks spawned so far, by name.
+	pub fn task_stats(&self) -> Vec<TaskStats> {
+		let mut stats = self.tasks.read().values().cloned().collect::<Vec<_>>();
+		stats.sort_by(|a, b| a.name.cmp(&b.name));
+		stats
+	}
+}
+
+impl Drop for Executor {
+	fn drop(&mut self) {
+		if let Some(shutdown) = self.shutdown.get_mut().take() {
+			let _ = shutdown.send(());
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_executor_task_stats() {
+		let executor = Executor::new(1).unwrap();
+
+		let (tx, rx) = mpsc::channel();
+		let blocking = executor.spawn_blocking("blocking", move || {
+			rx.recv().unwrap();
+		});
+		executor.spawn("async", async {}).join().unwrap();
+
+		let stats = executor.task_stats();
+		assert_eq!(stats.len(), 2);
+		assert_eq!(stats[0].name, "async");
+		assert_eq!((stats[0].spawned, stats[0].running), (1, 0));
+		assert_eq!(stats[1].name, "blocking");
+		assert_eq!((stats[1].spawned, stats[1].running), (1, 1));
+
+		tx.send(()).unwrap();
+		assert_eq!(blocking.name(), "blocking");
+		blocking.join().unwrap();
+		assert_eq!(executor.task_stats()[1].running, 0);
+	}
+
+	#[test]
+	fn test_executor_periodic_task() {
+		let executor = Executor::new(1).unwrap();
+		let stop_state = Arc::new(StopState::new());
+
+		let (tx, rx) = mpsc::channel();
+		let mut runs = 0;
+		let periodic = executor.spawn_periodic(
+			"periodic",
+			Duration::from_millis(10),
+			stop_state.clone(),
+			move || {
+				runs += 1;
+				let _ = tx.send(runs);
+			},
+		);
+		for i in 1..=3 {
+			assert_eq!(rx.recv_timeout(Duration::from_secs(5)), Ok(i));
+		}
+
+		stop_state.stop();
+		periodic.join().unwrap();
+		let stats = executor.task_stats();
+		assert_eq!(stats[0].name, "periodic");
+		assert_eq!((stats[0].spawned, stats[0].running), (1, 0));
+	}
+}
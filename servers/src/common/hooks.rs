@@ -19,11 +19,12 @@ extern crate hyper;
 extern crate hyper_rustls;
 extern crate tokio;
 
+use crate::api::{BlockEvents, NodeEvents};
 use crate::chain::BlockStatus;
 use crate::common::types::{ServerConfig, WebHooksConfig};
 use crate::core::core;
 use crate::core::core::hash::Hashed;
-use crate::p2p::types::PeerAddr;
+use crate::p2p::types::{PeerAddr, PeerInfo};
 use futures::TryFutureExt;
 use hyper::client::HttpConnector;
 use hyper::header::HeaderValue;
@@ -32,13 +33,18 @@ use hyper::{Body, Method, Request};
 use hyper_rustls::HttpsConnector;
 use serde::Serialize;
 use serde_json::{json, to_string};
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::runtime::{Builder, Runtime};
 
 /// Returns the list of event hooks that will be initialized for network events
-pub fn init_net_hooks(config: &ServerConfig) -> Vec<Box<dyn NetEvents + Send + Sync>> {
+pub fn init_net_hooks(
+	config: &ServerConfig,
+	node_events: Arc<NodeEvents>,
+) -> Vec<Box<dyn NetEvents + Send + Sync>> {
 	let mut list: Vec<Box<dyn NetEvents + Send + Sync>> = Vec::new();
 	list.push(Box::new(EventLogger));
+	list.push(Box::new(PeerSubscriptions(node_events)));
 	if config.webhook_config.block_received_url.is_some()
 		|| config.webhook_config.tx_received_url.is_some()
 		|| config.webhook_config.header_received_url.is_some()
@@ -49,9 +55,13 @@ pub fn init_net_hooks(config: &ServerConfig) -> Vec<Box<dyn NetEvents + Send + S
 }
 
 /// Returns the list of event hooks that will be initialized for chain events
-pub fn init_chain_hooks(config: &ServerConfig) -> Vec<Box<dyn ChainEvents + Send + Sync>> {
+pub fn init_chain_hooks(
+	config: &ServerConfig,
+	block_events: Arc<BlockEvents>,
+) -> Vec<Box<dyn ChainEvents + Send + Sync>> {
 	let mut list: Vec<Box<dyn ChainEvents + Send + Sync>> = Vec::new();
 	list.push(Box::new(EventLogger));
+	list.push(Box::new(BlockSubscriptions(block_events)));
 	if config.webhook_config.block_accepted_url.is_some() {
 		list.push(Box::new(WebHook::from_config(&config.webhook_config)));
 	}
@@ -69,6 +79,12 @@ pub trait NetEvents {
 
 	/// Triggers when a new block header arrives
 	fn on_header_received(&self, header: &core::BlockHeader, addr: &PeerAddr) {}
+
+	/// Triggers when a peer gets connected
+	fn on_peer_connected(&self, peer_info: &PeerInfo) {}
+
+	/// Triggers when a peer gets disconnected
+	fn on_peer_disconnected(&self, addr: &PeerAddr) {}
 }
 
 #[allow(unused_variables)]
@@ -146,7 +162,29 @@ impl ChainEvents for EventLogger {
 	}
 }
 
-fn parse_url(value: &Option<String>) -> Option<hyper::Uri> {
+/// Pushes the accepted blocks to the API block subscribers
+struct BlockSubscriptions(Arc<BlockEvents>);
+
+impl ChainEvents for BlockSubscriptions {
+	fn on_block_accepted(&self, block: &core::Block, status: &BlockStatus) {
+		self.0.block_accepted(&block.header, status);
+	}
+}
+
+/// Pushes the peers connecting and disconnecting to the API event subscribers
+struct PeerSubscriptions(Arc<NodeEvents>);
+
+impl NetEvents for PeerSubscriptions {
+	fn on_peer_connected(&self, peer_info: &PeerInfo) {
+		self.0.peer_connected(peer_info);
+	}
+
+	fn on_peer_disconnected(&self, addr: &PeerAddr) {
+		self.0.peer_disconnected(*addr);
+	}
+}
+
+pub(crate) fn parse_url(value: &Option<String>) -> Option<hyper::Uri> {
 	match value {
 		Some(url) => {
 			let uri: hyper::Uri = match url.parse() {
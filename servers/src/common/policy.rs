@@ -0,0 +1,324 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Local policy on the transactions the node relays and mines, enforced by an
+//! external policy engine. The engine is asked about the txs received from
+//! our peers once, before they enter the pool. Its decisions are remembered
+//! for the block templates to leave out the pool txs it rejected, without
+//! asking it again. It has no say on the validity of blocks, the txs mined by
+//! others are accepted regardless.
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::json;
+use tokio::runtime::{Builder, Runtime};
+
+use crate::api;
+use crate::common::types::{Error, TxPolicyConfig};
+use crate::core::core::hash::{Hash, Hashed};
+use crate::core::core::Transaction;
+use crate::pool::PoolSnapshot;
+use crate::util::Mutex;
+
+/// Decisions remembered, for the block templates to find the decisions on
+/// their txs. Forgotten all at once past that.
+const MAX_POLICY_DECISIONS: usize = 10_000;
+
+/// How often a policy command is polled for its exit status.
+const COMMAND_POLL_MS: u64 = 10;
+
+/// Asks the policy engine whether a tx is accepted.
+pub trait PolicyCheck {
+	fn check(&self, tx: &Transaction, timeout: Duration) -> Result<bool, String>;
+}
+
+/// Consults the policy engine on the txs, remembering its decisions.
+pub struct TxPolicy {
+	check: Box<dyn PolicyCheck + Send + Sync>,
+	timeout: Duration,
+	accept_on_error: bool,
+	dry_run: bool,
+	decisions: Mutex<HashMap<Hash, bool>>,
+}
+
+impl TxPolicy {
+	/// The policy of the configuration, if a policy url or command is set.
+	pub fn from_config(config: &TxPolicyConfig) -> Result<Option<TxPolicy>, Error> {
+		let check: Box<dyn PolicyCheck + Send + Sync> = if let Some(ref url) = config.policy_url {
+			Box::new(PolicyWebHook::new(url)?)
+		} else if let Some(ref command) = config.policy_command {
+			Box::new(PolicyCommand(command.clone()))
+		} else {
+			return Ok(None);
+		};
+		Ok(Some(TxPolicy::with_check(check, config)))
+	}
+
+	/// The policy of the configuration, enforced by the provided check.
+	pub fn with_check(
+		check: Box<dyn PolicyCheck + Send + Sync>,
+		config: &TxPolicyConfig,
+	) -> TxPolicy {
+		TxPolicy {
+			check,
+			timeout: Duration::from_millis(config.policy_timeout_ms),
+			accept_on_error: config.policy_accept_on_error,
+			dry_run: config.policy_dry_run,
+			decisions: Mutex::new(HashMap::new()),
+		}
+	}
+
+	/// Whether the tx is accepted into the pool, asking the policy engine
+	/// unless it decided already. In dry run, rejections are only logged and
+	/// all txs are accepted.
+	pub fn accept(&self, tx: &Transaction) -> bool {
+		let hash = tx.hash();
+		let decision = self.decisions.lock().get(&hash).cloned();
+		let accepted = match decision {
+			Some(accepted) => accepted,
+			None => {
+				let accepted = match self.check.check(tx, self.timeout) {
+					Ok(accepted) => accepted,
+					Err(e) => {
+						warn!("tx policy: no decision on tx {}: {}", hash, e);
+						// Asked again next time.
+						return self.accept_on_error || self.dry_run;
+					}
+				};
+				let mut decisions = self.decisions.lock();
+				if decisions.len() >= MAX_POLICY_DECISIONS {
+					decisions.clear();
+				}
+				decisions.insert(hash, accepted);
+				if !accepted {
+					info!(
+						"tx policy: tx {} rejected{}",
+						hash,
+						if self.dry_run { ", dry run" } else { "" }
+					);
+				}
+				accepted
+			}
+		};
+		accepted || self.dry_run
+	}
+
+	/// The snapshot without the txs the policy engine rejected, going by its
+	/// past decisions only, so building a block template never waits on it.
+	/// The txs spending their outputs get left out of the template along
+	/// with them.
+	pub fn filter_snapshot(&self, mut snapshot: PoolSnapshot) -> PoolSnapshot {
+		if self.dry_run {
+			return snapshot;
+		}
+		let decisions = self.decisions.lock();
+		snapshot
+			.entries
+			.retain(|entry| decisions.get(&entry.tx.hash()) != Some(&false));
+		snapshot
+	}
+}
+
+/// POSTs the tx as JSON to the policy engine, along with its hash, expecting
+/// `{"accept": true|false}` back. All the requests go through the same
+/// runtime.
+struct PolicyWebHook {
+	url: String,
+	runtime: Runtime,
+}
+
+impl PolicyWebHook {
+	fn new(url: &str) -> Result<PolicyWebHook, Error> {
+		let uri: hyper::Uri = url
+			.parse()
+			.map_err(|_| Error::Configuration(format!("invalid policy url {}", url)))?;
+		match uri.scheme_str() {
+			Some("http") | Some("https") => (),
+			_ => {
+				return Err(Error::Configuration(format!(
+					"invalid policy url {}, expected an http or https one",
+					url
+				)))
+			}
+		}
+		let runtime = Builder::new()
+			.threaded_scheduler()
+			.enable_all()
+			.core_threads(1)
+			.thread_name("tx_policy")
+			.build()?;
+		Ok(PolicyWebHook {
+			url: uri.to_string(),
+			runtime,
+		})
+	}
+}
+
+#[derive(Deserialize)]
+struct PolicyResponse {
+	accept: bool,
+}
+
+impl PolicyCheck for PolicyWebHook {
+	fn check(&self, tx: &Transaction, timeout: Duration) -> Result<bool, String> {
+		let payload = json!({
+			"hash": tx.hash().to_hex(),
+			"data": tx,
+		});
+		let url = self.url.clone();
+		let req = self.runtime.spawn(async move {
+			tokio::time::timeout(
+				timeout,
+				api::client::post_async::<_, PolicyResponse>(&url, &payload, None),
+			)
+			.await
+		});
+		match futures::executor::block_on(req) {
+			Ok(Ok(Ok(res))) => Ok(res.accept),
+			Ok(Ok(Err(e))) => Err(e.to_string()),
+			Ok(Err(_)) => Err("timed out".to_owned()),
+			Err(e) => Err(e.to_string()),
+		}
+	}
+}
+
+/// Runs a command with the tx hash as argument and the tx as JSON on its
+/// standard input, the tx being accepted if it exits with 0.
+struct PolicyCommand(String);
+
+impl PolicyCheck for PolicyCommand {
+	fn check(&self, tx: &Transaction, timeout: Duration) -> Result<bool, String> {
+		let data = serde_json::to_vec(tx).map_err(|e| e.to_string())?;
+		let mut child = Command::new(&self.0)
+			.arg(tx.hash().to_hex())
+			.stdin(Stdio::piped())
+			.stdout(Stdio::null())
+			.spawn()
+			.map_err(|e| e.to_string())?;
+		if let Some(mut stdin) = child.stdin.take() {
+			// Written aside, so a command not reading it can still time out.
+			thread::spawn(move || {
+				let _ = stdin.write_all(&data);
+			});
+		}
+
+		let deadline = Instant::now() + timeout;
+		loop {
+			match child.try_wait().map_err(|e| e.to_string())? {
+				Some(status) => return Ok(status.success()),
+				None if Instant::now() >= deadline => {
+					let _ = child.kill();
+					let _ = child.wait();
+					return Err("timed out".to_owned());
+				}
+				None => thread::sleep(Duration::from_millis(COMMAND_POLL_MS)),
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::core::core::BlockHeader;
+	use crate::pool::{PoolEntry, TxSource};
+	use chrono::Utc;
+	use std::sync::Arc;
+
+	// Always gives the same decision, None for no decision, counting the
+	// checks.
+	struct FixedPolicy(Option<bool>, Arc<Mutex<usize>>);
+
+	impl PolicyCheck for FixedPolicy {
+		fn check(&self, _tx: &Transaction, _timeout: Duration) -> Result<bool, String> {
+			*self.1.lock() += 1;
+			self.0.ok_or_else(|| "unreachable".to_owned())
+		}
+	}
+
+	fn policy(decision: Option<bool>, config: &TxPolicyConfig) -> (TxPolicy, Arc<Mutex<usize>>) {
+		let checks = Arc::new(Mutex::new(0));
+		let check = Box::new(FixedPolicy(decision, checks.clone()));
+		(TxPolicy::with_check(check, config), checks)
+	}
+
+	fn snapshot(tx: &Transaction) -> PoolSnapshot {
+		let entry = PoolEntry {
+			src: TxSource::Broadcast,
+			tx_at: Utc::now(),
+			tx: tx.clone(),
+			stem_opts: None,
+			input_proofs: vec![],
+		};
+		PoolSnapshot {
+			header: BlockHeader::default(),
+			entries: vec![entry],
+		}
+	}
+
+	#[test]
+	fn test_tx_policy() {
+		let tx = Transaction::empty();
+		let mut config = TxPolicyConfig::default();
+
+		// Decisions are remembered, the block templates only go by them and
+		// keep the txs not decided on.
+		let (tx_policy, checks) = policy(Some(false), &config);
+		assert_eq!(tx_policy.filter_snapshot(snapshot(&tx)).entries.len(), 1);
+		assert!(!tx_policy.accept(&tx));
+		assert!(!tx_policy.accept(&tx));
+		assert!(tx_policy.filter_snapshot(snapshot(&tx)).entries.is_empty());
+		assert_eq!(*checks.lock(), 1);
+
+		// Without a decision, the tx is turned down unless configured
+		// otherwise and the engine is asked again next time.
+		let (tx_policy, checks) = policy(None, &config);
+		assert!(!tx_policy.accept(&tx));
+		assert!(!tx_policy.accept(&tx));
+		assert_eq!(*checks.lock(), 2);
+		assert_eq!(tx_policy.filter_snapshot(snapshot(&tx)).entries.len(), 1);
+		config.policy_accept_on_error = true;
+		let (tx_policy, _) = policy(None, &config);
+		assert!(tx_policy.accept(&tx));
+
+		// Dry runs only log the rejections.
+		config.policy_dry_run = true;
+		let (tx_policy, _) = policy(Some(false), &config);
+		assert!(tx_policy.accept(&tx));
+		assert_eq!(tx_policy.filter_snapshot(snapshot(&tx)).entries.len(), 1);
+	}
+
+	#[test]
+	fn test_policy_config() {
+		let mut config = TxPolicyConfig::default();
+		assert!(!config.policy_accept_on_error);
+		assert!(TxPolicy::from_config(&config).unwrap().is_none());
+
+		// Bad urls are configuration errors.
+		for url in &["not a url", "ftp://127.0.0.1/policy"] {
+			config.policy_url = Some(url.to_string());
+			match TxPolicy::from_config(&config) {
+				Err(Error::Configuration(_)) => (),
+				Err(e) => panic!("unexpected error: {:?}", e),
+				Ok(_) => panic!("url {} accepted", url),
+			}
+		}
+		config.policy_url = Some("http://127.0.0.1:8080/policy".to_owned());
+		assert!(TxPolicy::from_config(&config).unwrap().is_some());
+	}
+}
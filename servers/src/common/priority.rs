@@ -0,0 +1,95 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! OS scheduling priority of the threads validating the chain in the
+//! background, so a node sharing its host with other services can leave
+//! them the cpu when they need it. The work holding the chain locks, block
+//! and tx validation or the compaction, keeps the normal priority, as the
+//! rest of the node waits on it.
+
+use crate::core::core::transaction;
+
+/// Sizes the pools of threads verifying rangeproofs and kernel signatures,
+/// the global one of blocks and txs and, with a nice level, the one of the
+/// txhashset downloaded during the sync.
+pub fn init_verification_threads(threads: usize, nice: i32) {
+	match transaction::init_verification_threads(threads, |_| {}) {
+		Ok(()) if threads == 0 => info!("verifying on a thread per cpu"),
+		Ok(()) => info!("verifying on {} threads", threads),
+		// Only the first server of the process gets to size them.
+		Err(e) => debug!("verification threads already started: {}", e),
+	}
+	// Without a nice level the background work verifies on the global ones.
+	if nice == 0 {
+		return;
+	}
+	let res =
+		transaction::init_background_verification_threads(threads, move |_| set_thread_nice(nice));
+	match res {
+		Ok(()) => info!("verifying in the background with nice {}", nice),
+		Err(e) => debug!("background verification threads not started: {}", e),
+	}
+}
+
+/// Sets the nice level of the calling thread, leaving it as is if 0.
+/// Linux only, elsewhere the priority is the process one.
+#[cfg(target_os = "linux")]
+pub fn set_thread_nice(nice: i32) {
+	if nice == 0 {
+		return;
+	}
+	// On linux the priority of a thread id is the one of that thread only.
+	let res = unsafe {
+		let tid = libc::syscall(libc::SYS_gettid) as libc::id_t;
+		libc::setpriority(libc::PRIO_PROCESS, tid, nice)
+	};
+	if res != 0 {
+		warn!(
+			"failed to set the nice level of thread {:?} to {}: {}",
+			std::thread::current().name(),
+			nice,
+			std::io::Error::last_os_error()
+		);
+	}
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set_thread_nice(_nice: i32) {}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[cfg(target_os = "linux")]
+	#[test]
+	fn test_set_thread_nice() {
+		let tid = || unsafe { libc::syscall(libc::SYS_gettid) as libc::id_t };
+		let nice = |tid| unsafe { libc::getpriority(libc::PRIO_PROCESS, tid) };
+		let main_tid = tid();
+		let main_nice = nice(main_tid);
+		std::thread::spawn(move || {
+			let before = nice(tid());
+			set_thread_nice(0);
+			assert_eq!(nice(tid()), before);
+			// Lowering the priority needs no privileges.
+			let lower = (before + 1).min(19);
+			set_thread_nice(lower);
+			assert_eq!(nice(tid()), lower);
+		})
+		.join()
+		.unwrap();
+		// Only the thread is reniced.
+		assert_eq!(nice(main_tid), main_nice);
+	}
+}
@@ -66,6 +66,10 @@ pub struct ServerStats {
 	pub tx_stats: Option<TxStats>,
 	/// Disk usage in GB
 	pub disk_usage_gb: String,
+	/// Number of blocks received with the same total difficulty as our head
+	pub tip_races: u64,
+	/// Stats of the tasks run by the server executor
+	pub task_stats: Vec<TaskStats>,
 }
 
 /// Chain Statistics
@@ -193,6 +197,19 @@ pub struct PeerStats {
 	pub received_bytes_per_sec: u64,
 }
 
+/// Stats of the tasks spawned on the server executor under a given name
+#[derive(Clone, Serialize, Debug)]
+pub struct TaskStats {
+	/// Name the tasks were spawned under
+	pub name: String,
+	/// Number of tasks spawned
+	pub spawned: u64,
+	/// Number of those still running
+	pub running: u64,
+	/// Total time the completed tasks ran for, in milliseconds
+	pub run_time_ms: u64,
+}
+
 impl PartialEq for PeerStats {
 	fn eq(&self, other: &PeerStats) -> bool {
 		*self.addr == other.addr
@@ -219,6 +236,18 @@ impl StratumStats {
 	}
 }
 
+impl TaskStats {
+	/// No task spawned yet under the provided name
+	pub fn new(name: &str) -> TaskStats {
+		TaskStats {
+			name: name.to_owned(),
+			spawned: 0,
+			running: 0,
+			run_time_ms: 0,
+		}
+	}
+}
+
 impl PeerStats {
 	/// Convert from a peer directly
 	pub fn from_peer(peer: &p2p::Peer) -> PeerStats {
@@ -0,0 +1,267 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Intake of the transactions received from our peers. They're queued per
+//! peer and validated on a few worker threads, taking turns between peers,
+//! before entering the pool. The pool lock is only taken to add a tx once its
+//! signatures and range proofs were checked, so a flood of txs neither
+//! serializes behind it nor holds up block processing.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::common::executor::{Executor, TaskHandle};
+use crate::common::policy::TxPolicy;
+use crate::core::core::hash::Hashed;
+use crate::core::core::verifier_cache::VerifierCache;
+use crate::core::core::Transaction;
+use crate::p2p::PeerAddr;
+use crate::pool::{self, BlockChain, InputProofs, TxSource};
+use crate::util::{Condvar, Mutex, RwLock, StopState};
+
+/// A single peer can take up at most a quarter of the intake queue.
+const MAX_QUEUE_SHARE_PER_PEER: usize = 4;
+
+/// How long the workers wait for a tx before checking whether we're stopping.
+const WORKER_WAIT_SECS: u64 = 1;
+
+struct QueuedTx {
+	tx: Transaction,
+	stem: bool,
+}
+
+// The txs waiting to be validated, by the peer they were received from.
+#[derive(Default)]
+struct IntakeQueues {
+	by_peer: HashMap<PeerAddr, VecDeque<QueuedTx>>,
+	// Peers with txs waiting, in the order they get their next one validated.
+	turns: VecDeque<PeerAddr>,
+	len: usize,
+}
+
+impl IntakeQueues {
+	fn push(&mut self, addr: PeerAddr, tx: QueuedTx, capacity: usize) -> bool {
+		if self.len >= capacity {
+			return false;
+		}
+		let queue = self.by_peer.entry(addr).or_default();
+		if queue.len() >= (capacity / MAX_QUEUE_SHARE_PER_PEER).max(1) {
+			return false;
+		}
+		if queue.is_empty() {
+			self.turns.push_back(addr);
+		}
+		queue.push_back(tx);
+		self.len += 1;
+		true
+	}
+
+	fn pop(&mut self) -> Option<QueuedTx> {
+		let addr = self.turns.pop_front()?;
+		let queue = self.by_peer.get_mut(&addr)?;
+		let tx = queue.pop_front()?;
+		if queue.is_empty() {
+			self.by_peer.remove(&addr);
+		} else {
+			self.turns.push_back(addr);
+		}
+		self.len -= 1;
+		Some(tx)
+	}
+}
+
+/// Bounded queue of the txs received from our peers, validated and added to
+/// the pool by the workers of `start_tx_validation`.
+pub struct TxIntake {
+	queues: Mutex<IntakeQueues>,
+	ready: Condvar,
+	capacity: usize,
+	tx_pool: Arc<RwLock<pool::TransactionPool>>,
+	blockchain: Arc<dyn BlockChain>,
+	verifier_cache: Arc<RwLock<dyn VerifierCache>>,
+	input_proofs: Arc<InputProofs>,
+	input_proofs_above: usize,
+	tx_policy: Option<Arc<TxPolicy>>,
+}
+
+impl TxIntake {
+	/// Intake of the txs to add to the pool, queueing up to its configured
+	/// `max_intake_queue`. Valid txs are only added if the tx policy, when
+	/// provided, accepts them.
+	pub fn new(
+		tx_pool: Arc<RwLock<pool::TransactionPool>>,
+		tx_policy: Option<Arc<TxPolicy>>,
+	) -> TxIntake {
+		let (capacity, blockchain, verifier_cache, input_proofs, input_proofs_above) = {
+			let pool = tx_pool.read();
+			(
+				pool.config.max_intake_queue,
+				pool.blockchain.clone(),
+				pool.verifier_cache.clone(),
+				pool.input_proofs.clone(),
+				pool.config.input_proofs_above,
+			)
+		};
+		TxIntake {
+			queues: Mutex::new(IntakeQueues::default()),
+			ready: Condvar::new(),
+			capacity,
+			tx_pool,
+			blockchain,
+			verifier_cache,
+			input_proofs,
+			input_proofs_above,
+			tx_policy,
+		}
+	}
+
+	/// Queue a tx received from the peer for validation. Returns false if the
+	/// queue, or the share of it the peer can take up, is full.
+	pub fn submit(&self, addr: PeerAddr, tx: Transaction, stem: bool) -> bool {
+		let queued = self
+			.queues
+			.lock()
+			.push(addr, QueuedTx { tx, stem }, self.capacity);
+		if queued {
+			self.ready.notify_one();
+		}
+		queued
+	}
+
+	/// Validate the queued txs on the current thread, until none is left.
+	pub fn validate_queued(&self) {
+		loop {
+			let queued = self.queues.lock().pop();
+			match queued {
+				Some(queued) => self.validate(queued),
+				None => break,
+			}
+		}
+	}
+
+	// Next tx to validate, waiting for one up to the timeout.
+	fn next(&self, timeout: Duration) -> Option<QueuedTx> {
+		let mut queues = self.queues.lock();
+		if queues.len == 0 {
+			self.ready.wait_for(&mut queues, timeout);
+		}
+		queues.pop()
+	}
+
+	fn validate(&self, queued: QueuedTx) {
+		let QueuedTx { tx, stem } = queued;
+		let tx_hash = tx.hash();
+
+		// Txs spending many inputs are only looked up once proven, as the
+		// pool would reject them anyway.
+		if let Err(e) = self.input_proofs.check(&tx, self.input_proofs_above) {
+			debug!("Transaction {} rejected: {:?}", tx_hash, e);
+			return;
+		}
+
+		// The costly checks run without the pool lock, adding the tx to the
+		// pool then finds their results in the verifier cache.
+		if let Err(e) = pool::validate_tx(&tx, &*self.blockchain, self.verifier_cache.clone()) {
+			debug!("Transaction {} rejected: {:?}", tx_hash, e);
+			return;
+		}
+		if let Some(ref tx_policy) = self.tx_policy {
+			if !tx_policy.accept(&tx) {
+				return;
+			}
+		}
+		let header = match self.blockchain.chain_head() {
+			Ok(header) => header,
+			Err(e) => {
+				debug!("Transaction {} not added, no chain head: {:?}", tx_hash, e);
+				return;
+			}
+		};
+		let mut tx_pool = self.tx_pool.write();
+		if let Err(e) = tx_pool.add_to_pool(TxSource::Broadcast, tx, stem, &header) {
+			debug!("Transaction {} rejected: {:?}", tx_hash, e);
+		}
+	}
+}
+
+/// Start the configured number of workers validating the txs of the intake
+/// and adding them to the pool, until we stop.
+pub fn start_tx_validation(
+	intake: Arc<TxIntake>,
+	threads: usize,
+	stop_state: Arc<StopState>,
+	executor: &Executor,
+) -> Vec<TaskHandle> {
+	(0..threads.max(1))
+		.map(|_| {
+			let intake = intake.clone();
+			let stop_state = stop_state.clone();
+			executor.spawn_blocking("tx_validation", move || {
+				while !stop_state.is_stopped() {
+					if let Some(queued) = intake.next(Duration::from_secs(WORKER_WAIT_SECS)) {
+						intake.validate(queued);
+					}
+				}
+			})
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+	fn peer(n: u8) -> PeerAddr {
+		PeerAddr(SocketAddr::new(
+			IpAddr::V4(Ipv4Addr::new(10, 0, 0, n)),
+			7414,
+		))
+	}
+
+	fn queued(stem: bool) -> QueuedTx {
+		QueuedTx {
+			tx: Transaction::empty(),
+			stem,
+		}
+	}
+
+	#[test]
+	fn test_intake_queues() {
+		let mut queues = IntakeQueues::default();
+
+		// A peer can only take up a quarter of the queue.
+		for _ in 0..2 {
+			assert!(queues.push(peer(1), queued(false), 8));
+		}
+		assert!(!queues.push(peer(1), queued(false), 8));
+		assert!(queues.push(peer(2), queued(true), 8));
+		assert_eq!(queues.len, 3);
+
+		// The peers take turns.
+		assert!(!queues.pop().unwrap().stem);
+		assert!(queues.pop().unwrap().stem);
+		assert!(!queues.pop().unwrap().stem);
+		assert!(queues.pop().is_none());
+		assert!(queues.by_peer.is_empty());
+
+		// Nothing more fits once full.
+		for n in 0..4 {
+			assert!(queues.push(peer(n), queued(false), 4));
+		}
+		assert!(!queues.push(peer(5), queued(false), 4));
+		assert_eq!(queues.len, 4);
+	}
+}
@@ -13,6 +13,7 @@
 // limitations under the License.
 
 //! Server types
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::convert::From;
 use std::sync::Arc;
 
@@ -21,13 +22,18 @@ use rand::prelude::*;
 
 use crate::api;
 use crate::chain;
+use crate::core::clock::Clock;
+use crate::core::core::hash::{Hash, Hashed};
 use crate::core::global::ChainTypes;
+use crate::core::ser::{self, ProtocolVersion};
 use crate::core::{core, libtx, pow};
 use crate::keychain;
 use crate::p2p;
+use crate::p2p::types::PeerAddr;
 use crate::pool;
 use crate::pool::types::DandelionConfig;
 use crate::store;
+use crate::util::RwLock;
 
 /// Error type wrapping underlying module errors.
 #[derive(Debug)]
@@ -135,6 +141,16 @@ impl Default for ChainValidationMode {
 	}
 }
 
+/// Ban window in secs for public nodes, unless configured otherwise.
+pub const PUBLIC_NODE_BAN_WINDOW: i64 = 24 * 3600;
+
+/// API requests per second served to a single client IP by public nodes,
+/// unless configured otherwise.
+pub const PUBLIC_NODE_MAX_REQUESTS_PER_IP: u32 = 10;
+
+/// API requests served at once by public nodes, unless configured otherwise.
+pub const PUBLIC_NODE_MAX_IN_FLIGHT_REQUESTS: usize = 32;
+
 /// Full server configuration, aggregating configurations required for the
 /// different components.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -151,11 +167,106 @@ pub struct ServerConfig {
 	/// Location of secret for basic auth on v2 Foreign API server.
 	pub foreign_api_secret_path: Option<String>,
 
+	/// Serve the read-only Rest API requests without the api secret.
+	/// Requests changing the node state always need it.
+	#[serde(default)]
+	pub api_open_reads: bool,
+
+	/// Rest API routes whose read-only requests are served without the
+	/// api secret, e.g. "/v1/chain".
+	#[serde(default)]
+	pub api_open_routes: Vec<String>,
+
+	/// Rest API routes needing the api secret even to read, when reads
+	/// are open.
+	#[serde(default)]
+	pub api_protected_routes: Vec<String>,
+
+	/// Origins whose pages may query the rest API from a browser, e.g.
+	/// "https://explorer.example.com", "*" for any. No CORS if empty.
+	#[serde(default)]
+	pub api_cors_origins: Vec<String>,
+
 	/// TLS certificate file
 	pub tls_certificate_file: Option<String>,
 	/// TLS certificate private key file
 	pub tls_certificate_key: Option<String>,
 
+	/// Run as a public (wallet serving) node. Serves the foreign API only,
+	/// rate limited and without peer details, and bans misbehaving peers
+	/// for longer.
+	#[serde(default)]
+	pub public_node: bool,
+
+	/// Most API requests served per second to a single client IP.
+	/// Unlimited if not set or 0.
+	#[serde(default)]
+	pub api_max_requests_per_ip: Option<u32>,
+
+	/// Most API requests served at once, across all clients.
+	/// Unlimited if not set or 0.
+	#[serde(default)]
+	pub api_max_in_flight_requests: Option<usize>,
+
+	/// Threads the API requests are handled on, so slow ones don't hold up
+	/// the others. Handled on the async runtime if 0.
+	#[serde(default = "default_api_worker_threads")]
+	pub api_worker_threads: usize,
+
+	/// How long to cache the block and header API responses, in seconds.
+	/// No caching if 0.
+	#[serde(default)]
+	pub api_cache_ttl_secs: u64,
+
+	/// Serve GraphQL queries of the chain at /v2/graphql.
+	#[serde(default)]
+	pub api_graphql: bool,
+
+	/// Also serve the node services over gRPC at this address, when built
+	/// with the grpc feature, behind the api secret and rate limits.
+	#[serde(default)]
+	pub grpc_addr: Option<String>,
+
+	/// Scan the UTXO set for the outputs of a wallet from the owner api,
+	/// to restore it against the node.
+	#[serde(default)]
+	pub owner_api_scan_outputs: bool,
+
+	/// Number of worker threads of the runtime shared by the server
+	/// subsystems. One per cpu if 0.
+	#[serde(default)]
+	pub executor_threads: usize,
+
+	/// How long the sync can stay in a stage without progress before its
+	/// state is reset and its peers rotated, in seconds. Never if 0.
+	#[serde(default = "default_sync_watchdog_secs")]
+	pub sync_watchdog_secs: u64,
+
+	/// How long the node has to be synced before its idle caches are shrunk
+	/// and its unused memory released, and how often it's done again, in
+	/// seconds. Never if 0.
+	#[serde(default = "default_idle_reclaim_secs")]
+	pub idle_reclaim_secs: u64,
+
+	/// Number of threads verifying rangeproofs and kernel signatures, of
+	/// the blocks, txs and txhashset. One per cpu if 0.
+	#[serde(default)]
+	pub verification_threads: usize,
+
+	/// Nice level of the threads verifying a txhashset downloaded during
+	/// the sync, from 0 (no change) to 19 (lowest priority). Linux only.
+	#[serde(default)]
+	pub validation_nice: i32,
+
+	/// Api url of a trusted node to follow instead of syncing over p2p
+	/// (upstream relay mode). Blocks are still fully validated locally.
+	#[serde(default)]
+	pub upstream_node_url: Option<String>,
+
+	/// Location of the api secret of the upstream node.
+	#[serde(default)]
+	pub upstream_api_secret_path: Option<String>,
+
 	/// Setup the server for tests, testnet or mainnet
 	#[serde(default)]
 	pub chain_type: ChainTypes,
@@ -167,6 +278,58 @@ pub struct ServerConfig {
 	/// Whether this node is a full archival node or a fast-sync, pruned node
 	pub archive_mode: Option<bool>,
 
+	/// Open the chain read-only and only serve API queries, without syncing
+	/// nor connecting to peers. For a data dir written by a newer version,
+	/// which this one refuses to migrate.
+	#[serde(default)]
+	pub safe_mode: bool,
+
+	/// Directory of the flat block files every block accepted by the chain
+	/// gets appended to, alongside the db. No block files if None.
+	#[serde(default)]
+	pub block_files_dir: Option<String>,
+
+	/// Checkpoint files exported by other nodes, imported at startup if
+	/// signed by one of the trusted_checkpoint_operators.
+	#[serde(default)]
+	pub checkpoint_files: Vec<String>,
+
+	/// Identity keys (hex) of the node operators whose checkpoints are
+	/// trusted. A txhashset below one of their checkpoints is accepted
+	/// without verifying its rangeproofs and kernel signatures.
+	#[serde(default)]
+	pub trusted_checkpoint_operators: Vec<String>,
+
+	/// Hard checkpoints, as "height:hash". Headers conflicting with one of
+	/// them are rejected, whoever sends them.
+	#[serde(default)]
+	pub hard_checkpoints: Vec<String>,
+
+	/// Blocks on the most work header chain up to this height are assumed
+	/// valid and have their rangeproofs left unverified during the sync.
+	/// Must be the height of one of the hard_checkpoints.
+	#[serde(default)]
+	pub assume_valid_height: Option<u64>,
+
+	/// How to resolve a race between a new block and our chain head
+	/// when both have the same total difficulty
+	#[serde(default)]
+	pub head_race_policy: chain::HeadRacePolicy,
+
+	/// Most blocks held in the orphan pool waiting for their parent, the
+	/// highest evicted first.
+	#[serde(default = "default_max_orphans")]
+	pub max_orphans: usize,
+
+	/// Orphans older than that are evicted, in seconds.
+	#[serde(default = "default_max_orphan_age_secs")]
+	pub max_orphan_age_secs: u64,
+
+	/// Orphans further ahead of the chain head than that many blocks are
+	/// evicted.
+	#[serde(default = "default_max_orphan_height_ahead")]
+	pub max_orphan_height_ahead: u64,
+
 	/// Whether to skip the sync timeout on startup
 	/// (To assist testing on solo chains)
 	pub skip_sync_wait: Option<bool>,
@@ -199,6 +362,18 @@ pub struct ServerConfig {
 	/// Configuration for the webhooks that trigger on certain events
 	#[serde(default)]
 	pub webhook_config: WebHooksConfig,
+
+	/// Configuration of the alerts raised for the node operator
+	#[serde(default)]
+	pub alert_config: AlertConfig,
+
+	/// Configuration of the external policy on the txs relayed and mined
+	#[serde(default)]
+	pub tx_policy_config: TxPolicyConfig,
+
+	/// Configuration of the test network faucet
+	#[serde(default)]
+	pub faucet_config: api::FaucetConfig,
 }
 
 impl Default for ServerConfig {
@@ -208,13 +383,42 @@ impl Default for ServerConfig {
 			api_http_addr: "127.0.0.1:7413".to_string(),
 			api_secret_path: Some(".api_secret".to_string()),
 			foreign_api_secret_path: Some(".foreign_api_secret".to_string()),
+			api_open_reads: false,
+			api_open_routes: vec![],
+			api_protected_routes: vec![],
+			api_cors_origins: vec![],
 			tls_certificate_file: None,
 			tls_certificate_key: None,
+			public_node: false,
+			api_max_requests_per_ip: None,
+			api_max_in_flight_requests: None,
+			api_worker_threads: default_api_worker_threads(),
+			api_cache_ttl_secs: 0,
+			api_graphql: false,
+			grpc_addr: None,
+			owner_api_scan_outputs: false,
+			executor_threads: 0,
+			sync_watchdog_secs: default_sync_watchdog_secs(),
+			idle_reclaim_secs: default_idle_reclaim_secs(),
+			verification_threads: 0,
+			validation_nice: 0,
+			upstream_node_url: None,
+			upstream_api_secret_path: None,
 			p2p_config: p2p::P2PConfig::default(),
 			dandelion_config: pool::DandelionConfig::default(),
 			stratum_mining_config: Some(StratumServerConfig::default()),
 			chain_type: ChainTypes::default(),
 			archive_mode: Some(false),
+			safe_mode: false,
+			block_files_dir: None,
+			checkpoint_files: vec![],
+			trusted_checkpoint_operators: vec![],
+			hard_checkpoints: vec![],
+			assume_valid_height: None,
+			head_race_policy: chain::HeadRacePolicy::default(),
+			max_orphans: default_max_orphans(),
+			max_orphan_age_secs: default_max_orphan_age_secs(),
+			max_orphan_height_ahead: default_max_orphan_height_ahead(),
 			chain_validation_mode: ChainValidationMode::default(),
 			pool_config: pool::PoolConfig::default(),
 			skip_sync_wait: Some(false),
@@ -222,6 +426,28 @@ impl Default for ServerConfig {
 			run_test_miner: Some(false),
 			test_miner_wallet_url: None,
 			webhook_config: WebHooksConfig::default(),
+			alert_config: AlertConfig::default(),
+			tx_policy_config: TxPolicyConfig::default(),
+			faucet_config: api::FaucetConfig::default(),
+		}
+	}
+}
+
+impl ServerConfig {
+	/// Applies the public node profile on top of the configuration.
+	/// Settings explicitly provided in the config file take precedence.
+	pub fn apply_public_node_profile(&mut self) {
+		if !self.public_node {
+			return;
+		}
+		if self.p2p_config.ban_window.is_none() {
+			self.p2p_config.ban_window = Some(PUBLIC_NODE_BAN_WINDOW);
+		}
+		if self.api_max_requests_per_ip.is_none() {
+			self.api_max_requests_per_ip = Some(PUBLIC_NODE_MAX_REQUESTS_PER_IP);
+		}
+		if self.api_max_in_flight_requests.is_none() {
+			self.api_max_in_flight_requests = Some(PUBLIC_NODE_MAX_IN_FLIGHT_REQUESTS);
 		}
 	}
 }
@@ -283,6 +509,30 @@ pub struct WebHooksConfig {
 	pub timeout: u16,
 }
 
+fn default_api_worker_threads() -> usize {
+	8
+}
+
+fn default_sync_watchdog_secs() -> u64 {
+	1800
+}
+
+fn default_idle_reclaim_secs() -> u64 {
+	3600
+}
+
+fn default_max_orphans() -> usize {
+	chain::OrphanPoolLimits::default().max_orphans
+}
+
+fn default_max_orphan_age_secs() -> u64 {
+	chain::OrphanPoolLimits::default().max_age_secs
+}
+
+fn default_max_orphan_height_ahead() -> u64 {
+	chain::OrphanPoolLimits::default().max_height_ahead
+}
+
 fn default_timeout() -> u16 {
 	10
 }
@@ -304,6 +554,105 @@ impl Default for WebHooksConfig {
 	}
 }
 
+/// Configuration of the external policy engine consulted on the txs received
+/// from our peers, its decisions also applying to the block templates.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TxPolicyConfig {
+	/// url to POST each tx to, answering whether it's accepted
+	pub policy_url: Option<String>,
+	/// Command run for each tx, with the tx hash as argument and the tx on its
+	/// standard input, accepting it by exiting with 0
+	pub policy_command: Option<String>,
+	/// Time the policy engine has to decide, in milliseconds
+	#[serde(default = "default_policy_timeout_ms")]
+	pub policy_timeout_ms: u64,
+	/// Accept the txs the policy engine failed to decide on in time, rather
+	/// than turning them down
+	#[serde(default)]
+	pub policy_accept_on_error: bool,
+	/// Only log the txs the policy engine rejects, accepting them anyway
+	#[serde(default)]
+	pub policy_dry_run: bool,
+}
+
+fn default_policy_timeout_ms() -> u64 {
+	1000
+}
+
+impl Default for TxPolicyConfig {
+	fn default() -> TxPolicyConfig {
+		TxPolicyConfig {
+			policy_url: None,
+			policy_command: None,
+			policy_timeout_ms: default_policy_timeout_ms(),
+			policy_accept_on_error: false,
+			policy_dry_run: false,
+		}
+	}
+}
+
+/// Alerts configuration. Each condition can be disabled by setting its
+/// threshold to 0.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AlertConfig {
+	/// Log the alerts raised and cleared, with an "ALERT" marker
+	#[serde(default = "default_alert_log")]
+	pub alert_log: bool,
+	/// url to POST the alerts to when raised and cleared
+	pub alert_webhook_url: Option<String>,
+	/// Command run when an alert is raised or cleared, with the alert state
+	/// ("raised" or "cleared"), kind and message as arguments
+	pub alert_command: Option<String>,
+	/// Alert when the disk of the db has less space left, in megabytes
+	#[serde(default = "default_min_free_disk_mb")]
+	pub min_free_disk_mb: u64,
+	/// Alert when the chain head didn't move for that long while our peers
+	/// are ahead and we're not syncing, in seconds
+	#[serde(default = "default_sync_stall_secs")]
+	pub sync_stall_secs: u64,
+	/// Alert when that many blocks failed validation within the last hour
+	#[serde(default = "default_max_validation_failures")]
+	pub max_validation_failures: usize,
+	/// Alert when the local clock is further off the network time
+	/// estimated from our peers, in seconds
+	#[serde(default = "default_max_clock_skew_secs")]
+	pub max_clock_skew_secs: i64,
+}
+
+fn default_alert_log() -> bool {
+	true
+}
+
+fn default_min_free_disk_mb() -> u64 {
+	1024
+}
+
+fn default_sync_stall_secs() -> u64 {
+	3600
+}
+
+fn default_max_validation_failures() -> usize {
+	5
+}
+
+fn default_max_clock_skew_secs() -> i64 {
+	60
+}
+
+impl Default for AlertConfig {
+	fn default() -> AlertConfig {
+		AlertConfig {
+			alert_log: default_alert_log(),
+			alert_webhook_url: None,
+			alert_command: None,
+			min_free_disk_mb: default_min_free_disk_mb(),
+			sync_stall_secs: default_sync_stall_secs(),
+			max_validation_failures: default_max_validation_failures(),
+			max_clock_skew_secs: default_max_clock_skew_secs(),
+		}
+	}
+}
+
 /// A node is either "stem" of "fluff" for the duration of a single epoch.
 /// A node also maintains an outbound relay peer for the epoch.
 #[derive(Debug)]
@@ -315,16 +664,19 @@ pub struct DandelionEpoch {
 	is_stem: bool,
 	// Our current Dandelion relay peer (effective for this epoch).
 	relay_peer: Option<Arc<p2p::Peer>>,
+	// Clock the epoch duration is measured with.
+	clock: Arc<dyn Clock>,
 }
 
 impl DandelionEpoch {
 	/// Create a new Dandelion epoch, defaulting to "stem" and no outbound relay peer.
-	pub fn new(config: DandelionConfig) -> DandelionEpoch {
+	pub fn new(config: DandelionConfig, clock: Arc<dyn Clock>) -> DandelionEpoch {
 		DandelionEpoch {
 			config,
 			start_time: None,
 			is_stem: true,
 			relay_peer: None,
+			clock,
 		}
 	}
 
@@ -335,7 +687,7 @@ impl DandelionEpoch {
 			None => true,
 			Some(start_time) => {
 				let epoch_secs = self.config.epoch_secs;
-				Utc::now().timestamp().saturating_sub(start_time) > epoch_secs as i64
+				self.clock.now().timestamp().saturating_sub(start_time) > epoch_secs as i64
 			}
 		}
 	}
@@ -344,7 +696,7 @@ impl DandelionEpoch {
 	/// Select stem/fluff based on configured stem_probability.
 	/// Choose a new outbound stem relay peer.
 	pub fn next_epoch(&mut self, peers: &Arc<p2p::Peers>) {
-		self.start_time = Some(Utc::now().timestamp());
+		self.start_time = Some(self.clock.now().timestamp());
 		self.relay_peer = peers.outgoing_connected_peers().first().cloned();
 
 		// If stem_probability == 90 then we stem 90% of the time.
@@ -396,3 +748,393 @@ impl DandelionEpoch {
 		self.relay_peer.clone()
 	}
 }
+
+/// Window (in secs) over which duplicate blocks from a peer are aggregated.
+pub const DUPLICATE_BLOCKS_WINDOW_SECS: i64 = 60;
+
+/// A peer sending us this many duplicate blocks within a single window is
+/// banned, honest peers relay a given block to us once or twice at most.
+pub const DUPLICATE_BLOCKS_BAN_THRESHOLD: u32 = 100;
+
+// Duplicate blocks received from a single peer in its current window.
+struct DuplicateBlocks {
+	window_start: i64,
+	count: u32,
+	last: Hash,
+}
+
+/// Tracks blocks we already know about being sent to us again by our peers.
+/// Duplicates are counted per peer and logged once per window instead of being
+/// processed and logged individually.
+pub struct DuplicateBlockTracker {
+	peers: RwLock<HashMap<PeerAddr, DuplicateBlocks>>,
+}
+
+impl DuplicateBlockTracker {
+	/// Create a new tracker, with no duplicates recorded.
+	pub fn new() -> DuplicateBlockTracker {
+		DuplicateBlockTracker {
+			peers: RwLock::new(HashMap::new()),
+		}
+	}
+
+	/// Is this block the last duplicate we recorded for this peer in its
+	/// current window? If so it can be dropped without looking it up again.
+	pub fn is_repeat(&self, addr: PeerAddr, hash: &Hash) -> bool {
+		match self.peers.read().get(&addr) {
+			Some(dups) => dups.last == *hash && !Self::is_expired(dups),
+			None => false,
+		}
+	}
+
+	/// Record a duplicate block received from the peer, returning the number
+	/// of duplicates received from it in the current window.
+	pub fn record(&self, addr: PeerAddr, hash: Hash) -> u32 {
+		let mut peers = self.peers.write();
+
+		// Close the windows that ended, logging a summary for each.
+		peers.retain(|addr, dups| {
+			let expired = Self::is_expired(dups);
+			if expired && dups.count > 1 {
+				info!(
+					"Received {} duplicate blocks from {} in the last {}s",
+					dups.count, addr, DUPLICATE_BLOCKS_WINDOW_SECS
+				);
+			}
+			!expired
+		});
+
+		let dups = peers.entry(addr).or_insert_with(|| DuplicateBlocks {
+			window_start: Utc::now().timestamp(),
+			count: 0,
+			last: hash,
+		});
+		dups.count += 1;
+		dups.last = hash;
+		if dups.count == 1 {
+			debug!("Received duplicate block {} from {}", hash, addr);
+		}
+		dups.count
+	}
+
+	fn is_expired(dups: &DuplicateBlocks) -> bool {
+		Utc::now().timestamp().saturating_sub(dups.window_start) >= DUPLICATE_BLOCKS_WINDOW_SECS
+	}
+}
+
+/// Window (in secs) over which the fork headers sent by a peer are counted.
+pub const FORK_HEADERS_WINDOW_SECS: i64 = 3600;
+
+// Distinct fork headers received from a single peer in its current window.
+struct ForkHeaders {
+	window_start: i64,
+	hashes: HashSet<Hash>,
+}
+
+/// Tracks the headers of low-work forks (unknown to us and bringing no more
+/// work than our header chain) sent by our peers. Processing each one rewinds
+/// the header MMR, so a peer sending many of them is likely spamming us.
+pub struct ForkHeaderTracker {
+	peers: RwLock<HashMap<PeerAddr, ForkHeaders>>,
+}
+
+impl ForkHeaderTracker {
+	/// Create a new tracker, with no fork headers recorded.
+	pub fn new() -> ForkHeaderTracker {
+		ForkHeaderTracker {
+			peers: RwLock::new(HashMap::new()),
+		}
+	}
+
+	/// Number of distinct fork headers received from the peer in its current
+	/// window.
+	pub fn count(&self, addr: PeerAddr) -> usize {
+		match self.peers.read().get(&addr) {
+			Some(forks) if !Self::is_expired(forks) => forks.hashes.len(),
+			_ => 0,
+		}
+	}
+
+	/// Record fork headers received from the peer, returning the number of
+	/// distinct ones received from it in the current window.
+	pub fn record<I>(&self, addr: PeerAddr, hashes: I) -> usize
+	where
+		I: IntoIterator<Item = Hash>,
+	{
+		let mut peers = self.peers.write();
+		peers.retain(|_, forks| !Self::is_expired(forks));
+
+		let forks = peers.entry(addr).or_insert_with(|| ForkHeaders {
+			window_start: Utc::now().timestamp(),
+			hashes: HashSet::new(),
+		});
+		forks.hashes.extend(hashes);
+		forks.hashes.len()
+	}
+
+	fn is_expired(forks: &ForkHeaders) -> bool {
+		Utc::now().timestamp().saturating_sub(forks.window_start) >= FORK_HEADERS_WINDOW_SECS
+	}
+}
+
+/// Maximum number of invalid blocks remembered by the denylist.
+pub const MAX_DENYLISTED_BLOCKS: usize = 1_000;
+
+/// Remembers the blocks that failed to apply to the chain state (bad kernel
+/// sums, roots or MMR sizes), so they aren't processed again. They are told
+/// apart by their header and body together, as a peer can pair a valid header
+/// with a bad body and the block hash only covers the header.
+pub struct BadBlockDenylist {
+	ids: RwLock<VecDeque<Hash>>,
+}
+
+impl BadBlockDenylist {
+	/// Create a new, empty, denylist.
+	pub fn new() -> BadBlockDenylist {
+		BadBlockDenylist {
+			ids: RwLock::new(VecDeque::new()),
+		}
+	}
+
+	/// Id of the full block in the denylist, the hash of its header and body.
+	pub fn block_id(b: &core::Block) -> Option<Hash> {
+		ser::ser_vec(b, ProtocolVersion::local())
+			.ok()
+			.map(|bytes| bytes.hash())
+	}
+
+	/// Add a block id to the denylist, forgetting the oldest one when full.
+	pub fn insert(&self, id: Hash) {
+		let mut ids = self.ids.write();
+		if ids.contains(&id) {
+			return;
+		}
+		if ids.len() >= MAX_DENYLISTED_BLOCKS {
+			ids.pop_front();
+		}
+		ids.push_back(id);
+	}
+
+	/// Whether the block id is in the denylist.
+	pub fn contains(&self, id: &Hash) -> bool {
+		self.ids.read().contains(id)
+	}
+}
+
+/// Missing parent of orphan blocks, asked for until it arrives.
+#[derive(Clone, Debug)]
+pub struct ParentRequest {
+	/// Peers that sent us orphans of this parent, asked first.
+	pub senders: Vec<PeerAddr>,
+	/// Peers already asked for the parent.
+	pub asked: HashSet<PeerAddr>,
+	/// Number of requests sent so far.
+	pub attempts: u32,
+	next_attempt: i64,
+}
+
+/// Tracks the missing parents of the blocks in the orphan pool, so they are
+/// actively requested again (with a backoff) instead of waiting for them to be
+/// relayed to us, which can leave us stuck a block behind.
+pub struct OrphanParentRequests {
+	max_attempts: u32,
+	retry_secs: i64,
+	requests: RwLock<HashMap<Hash, ParentRequest>>,
+}
+
+impl OrphanParentRequests {
+	/// Create a new tracker, giving up on a parent after `max_attempts`
+	/// requests. The delay between requests starts at `retry_secs` and doubles
+	/// after each one.
+	pub fn new(max_attempts: u32, retry_secs: u64) -> OrphanParentRequests {
+		OrphanParentRequests {
+			max_attempts,
+			retry_secs: retry_secs as i64,
+			requests: RwLock::new(HashMap::new()),
+		}
+	}
+
+	/// Record the missing parent of an orphan sent by the peer. Returns
+	/// whether the parent wasn't already being requested, in which case it
+	/// should be asked for right away.
+	pub fn add(&self, parent: Hash, sender: PeerAddr) -> bool {
+		let mut requests = self.requests.write();
+		if let Some(req) = requests.get_mut(&parent) {
+			if !req.senders.contains(&sender) {
+				req.senders.push(sender);
+			}
+			return false;
+		}
+		// Retries disabled, or already chasing as many parents as the orphan
+		// pool holds blocks.
+		if self.max_attempts > 0 && requests.len() < chain::MAX_ORPHAN_SIZE {
+			requests.insert(
+				parent,
+				ParentRequest {
+					senders: vec![sender],
+					asked: HashSet::new(),
+					attempts: 0,
+					next_attempt: 0,
+				},
+			);
+		}
+		true
+	}
+
+	/// Record a request for the parent sent to the peer, the next one being
+	/// due after the backoff.
+	pub fn requested(&self, parent: &Hash, addr: PeerAddr, now: i64) {
+		if let Some(req) = self.requests.write().get_mut(parent) {
+			req.asked.insert(addr);
+			req.attempts += 1;
+			let backoff = self.retry_secs << (req.attempts - 1).min(10);
+			req.next_attempt = now.saturating_add(backoff);
+		}
+	}
+
+	/// The parent arrived (or we gave up on it), stop requesting it.
+	pub fn remove(&self, parent: &Hash) {
+		self.requests.write().remove(parent);
+	}
+
+	/// The parents due for another request. Those already requested
+	/// `max_attempts` times are given up on.
+	pub fn due(&self, now: i64) -> Vec<(Hash, ParentRequest)> {
+		let mut requests = self.requests.write();
+		let max_attempts = self.max_attempts;
+		requests.retain(|parent, req| {
+			let expired = req.attempts >= max_attempts && req.next_attempt <= now;
+			if expired {
+				debug!(
+					"Giving up on orphan parent {} after {} requests",
+					parent, req.attempts
+				);
+			}
+			!expired
+		});
+		requests
+			.iter()
+			.filter(|(_, req)| req.next_attempt <= now)
+			.map(|(parent, req)| (*parent, req.clone()))
+			.collect()
+	}
+
+	/// Number of missing parents being requested.
+	pub fn count(&self) -> usize {
+		self.requests.read().len()
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::core::core::hash::ZERO_HASH;
+	use std::net::{IpAddr, Ipv4Addr};
+
+	#[test]
+	fn test_duplicate_block_tracker() {
+		let tracker = DuplicateBlockTracker::new();
+		let peer1 = PeerAddr::from_ip(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)));
+		let peer2 = PeerAddr::from_ip(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)));
+		let hash = Hash::from_vec(&[1; 32]);
+
+		assert!(!tracker.is_repeat(peer1, &hash));
+		assert_eq!(tracker.record(peer1, hash), 1);
+		assert!(tracker.is_repeat(peer1, &hash));
+		assert!(!tracker.is_repeat(peer1, &ZERO_HASH));
+		assert!(!tracker.is_repeat(peer2, &hash));
+
+		// Counts are per peer.
+		assert_eq!(tracker.record(peer1, ZERO_HASH), 2);
+		assert_eq!(tracker.record(peer2, hash), 1);
+		assert!(!tracker.is_repeat(peer1, &hash));
+	}
+
+	#[test]
+	fn test_fork_header_tracker() {
+		let tracker = ForkHeaderTracker::new();
+		let peer1 = PeerAddr::from_ip(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)));
+		let peer2 = PeerAddr::from_ip(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)));
+		let hash = Hash::from_vec(&[1; 32]);
+
+		assert_eq!(tracker.count(peer1), 0);
+		assert_eq!(tracker.record(peer1, vec![hash]), 1);
+
+		// Only distinct headers count, per peer.
+		assert_eq!(tracker.record(peer1, vec![hash, ZERO_HASH]), 2);
+		assert_eq!(tracker.record(peer2, vec![hash]), 1);
+		assert_eq!(tracker.count(peer1), 2);
+		assert_eq!(tracker.count(peer2), 1);
+	}
+
+	#[test]
+	fn test_orphan_parent_requests() {
+		let requests = OrphanParentRequests::new(2, 10);
+		let peer1 = PeerAddr::from_ip(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)));
+		let peer2 = PeerAddr::from_ip(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)));
+		let parent = Hash::from_vec(&[1; 32]);
+
+		// Only the first orphan of a parent triggers a request.
+		assert!(requests.add(parent, peer1));
+		assert!(!requests.add(parent, peer2));
+		assert!(!requests.add(parent, peer1));
+		requests.requested(&parent, peer1, 100);
+
+		// Backing off, 10s then 20s.
+		assert!(requests.due(109).is_empty());
+		let due = requests.due(110);
+		assert_eq!(due.len(), 1);
+		assert_eq!(due[0].0, parent);
+		assert_eq!(due[0].1.senders, vec![peer1, peer2]);
+		assert!(due[0].1.asked.contains(&peer1));
+		requests.requested(&parent, peer2, 110);
+		assert!(requests.due(129).is_empty());
+		assert_eq!(requests.count(), 1);
+
+		// Given up on once out of attempts.
+		assert!(requests.due(130).is_empty());
+		assert_eq!(requests.count(), 0);
+
+		assert!(requests.add(parent, peer1));
+		requests.remove(&parent);
+		assert_eq!(requests.count(), 0);
+
+		// Nothing tracked with retries disabled.
+		let requests = OrphanParentRequests::new(0, 10);
+		assert!(requests.add(parent, peer1));
+		assert!(requests.add(parent, peer1));
+		assert_eq!(requests.count(), 0);
+	}
+
+	#[test]
+	fn test_bad_block_denylist() {
+		let denylist = BadBlockDenylist::new();
+		let hash = Hash::from_vec(&[1; 32]);
+		assert!(!denylist.contains(&hash));
+		denylist.insert(hash);
+		denylist.insert(hash);
+		assert!(denylist.contains(&hash));
+		assert!(!denylist.contains(&ZERO_HASH));
+
+		// The oldest hashes are forgotten once full.
+		for i in 0..MAX_DENYLISTED_BLOCKS {
+			denylist.insert(Hash::from_vec(&(i as u64).to_be_bytes()));
+		}
+		assert!(!denylist.contains(&hash));
+		assert!(denylist.contains(&Hash::from_vec(&0u64.to_be_bytes())));
+	}
+
+	#[test]
+	fn test_bad_block_id() {
+		// The same header with another body is another block.
+		let block = core::Block::default();
+		let mut bad_block = block.clone();
+		bad_block.kernels_mut().push(core::TxKernel::empty());
+		assert_eq!(block.hash(), bad_block.hash());
+
+		let denylist = BadBlockDenylist::new();
+		denylist.insert(BadBlockDenylist::block_id(&bad_block).unwrap());
+		assert!(denylist.contains(&BadBlockDenylist::block_id(&bad_block).unwrap()));
+		assert!(!denylist.contains(&BadBlockDenylist::block_id(&block).unwrap()));
+	}
+}
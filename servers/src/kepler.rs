@@ -14,7 +14,18 @@
 
 //! Kepler P2P / API server
 
+pub mod alert_monitor;
+pub mod archive;
+pub mod bench;
+pub mod checkpoint;
 pub mod dandelion_monitor;
+pub mod event_monitor;
+pub mod orphan_monitor;
+pub mod reclaimer;
+pub mod replay;
 pub mod seed;
+pub mod selftest;
 pub mod server;
+pub mod stats_recorder;
 pub mod sync;
+pub mod upstream;
@@ -0,0 +1,210 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Watches the node for the conditions needing its operator, raising the
+//! alerts when they show up and clearing them once they're gone.
+
+use chrono::prelude::Utc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::chain::{self, SyncState};
+use crate::common::alerts::{AlertKind, Alerter};
+use crate::common::executor::{Executor, TaskHandle};
+use crate::common::types::{AlertConfig, ServerConfig};
+use crate::p2p;
+use crate::util::{StopState, Watch};
+
+/// The conditions are checked every 30 seconds.
+const CHECK_INTERVAL_SECS: u64 = 30;
+
+/// Being without peers is only alerted on after 5 minutes, giving a node
+/// starting up the time to connect.
+const NO_PEERS_GRACE_SECS: i64 = 300;
+
+// State of the checks carried from one round to the next.
+struct AlertChecks {
+	config: AlertConfig,
+	db_root: String,
+	expect_peers: bool,
+	// Height of the chain head and since when it's there, or since when we
+	// stopped syncing if later.
+	head_height: u64,
+	head_since: i64,
+	// Since when we're without peers, if we are.
+	no_peers_since: Option<i64>,
+}
+
+impl AlertChecks {
+	fn run(&mut self, alerter: &Alerter, sync_state: &SyncState, peers: &p2p::Peers) {
+		let now = Utc::now().timestamp();
+		alerter.update(AlertKind::DiskSpace, self.disk_space());
+		alerter.update(
+			AlertKind::SyncStalled,
+			self.sync_stalled(sync_state, peers, now),
+		);
+		alerter.update(AlertKind::NoPeers, self.no_peers(peers, now));
+		alerter.update(
+			AlertKind::ValidationFailures,
+			self.validation_failures(alerter, now),
+		);
+		alerter.update(AlertKind::ClockSkew, self.clock_skew(peers));
+	}
+
+	fn disk_space(&self) -> Option<String> {
+		if self.config.min_free_disk_mb == 0 {
+			return None;
+		}
+		let available = match fs2::available_space(&self.db_root) {
+			Ok(bytes) => bytes / (1024 * 1024),
+			Err(e) => {
+				debug!("alert_monitor: can't get the space left on the disk: {}", e);
+				return None;
+			}
+		};
+		if available < self.config.min_free_disk_mb {
+			Some(format!(
+				"{} MB left on the disk of {}",
+				available, self.db_root
+			))
+		} else {
+			None
+		}
+	}
+
+	fn head_updated(&mut self, height: u64, now: i64) {
+		if height != self.head_height {
+			self.head_height = height;
+			self.head_since = now;
+		}
+	}
+
+	// Header and block sync keep the head behind our peers for as long as
+	// they run, a sync that stopped making progress being the sync
+	// watchdog's business. So the head only counts as stalled from the time
+	// we're done syncing.
+	fn sync_stalled(
+		&mut self,
+		sync_state: &SyncState,
+		peers: &p2p::Peers,
+		now: i64,
+	) -> Option<String> {
+		if sync_state.is_syncing() {
+			self.head_since = now;
+			return None;
+		}
+		if self.config.sync_stall_secs == 0 {
+			return None;
+		}
+		let height = self.head_height;
+		let peer_height = peers
+			.connected_peers()
+			.iter()
+			.map(|p| p.info.height())
+			.max()
+			.unwrap_or(0);
+		let stalled = now - self.head_since;
+		if peer_height > height && stalled >= self.config.sync_stall_secs as i64 {
+			Some(format!(
+				"chain head stuck at height {} for {}s, peers are at {}",
+				height, stalled, peer_height
+			))
+		} else {
+			None
+		}
+	}
+
+	fn no_peers(&mut self, peers: &p2p::Peers, now: i64) -> Option<String> {
+		if !self.expect_peers || peers.peer_count() > 0 {
+			self.no_peers_since = None;
+			return None;
+		}
+		let since = *self.no_peers_since.get_or_insert(now);
+		if now - since >= NO_PEERS_GRACE_SECS {
+			Some(format!("no connected peer for {}s", now - since))
+		} else {
+			None
+		}
+	}
+
+	fn validation_failures(&self, alerter: &Alerter, now: i64) -> Option<String> {
+		let max = self.config.max_validation_failures;
+		let failures = alerter.validation_failures(now);
+		if max > 0 && failures >= max {
+			Some(format!(
+				"{} blocks failed validation over the last hour",
+				failures
+			))
+		} else {
+			None
+		}
+	}
+
+	fn clock_skew(&self, peers: &p2p::Peers) -> Option<String> {
+		let max = self.config.max_clock_skew_secs;
+		if max == 0 {
+			return None;
+		}
+		let offset = peers.network_time_offset()?;
+		if offset.abs() > max {
+			Some(format!(
+				"local clock is {}s off the network time estimated from our peers",
+				-offset
+			))
+		} else {
+			None
+		}
+	}
+}
+
+/// Checks the node every 30 seconds, raising and clearing the alerts. The
+/// chain head is only looked up when the chain events signal a change.
+/// Being without peers is only alerted on if we `expect_peers`.
+pub fn monitor_alerts(
+	config: &ServerConfig,
+	expect_peers: bool,
+	alerter: Arc<Alerter>,
+	chain: Arc<chain::Chain>,
+	sync_state: Arc<SyncState>,
+	peers: Arc<p2p::Peers>,
+	stop_state: Arc<StopState>,
+	executor: &Executor,
+) -> TaskHandle {
+	let mut checks = AlertChecks {
+		config: config.alert_config.clone(),
+		db_root: config.db_root.clone(),
+		expect_peers,
+		head_height: chain.head().map(|h| h.height).unwrap_or(0),
+		head_since: Utc::now().timestamp(),
+		no_peers_since: None,
+	};
+	executor.spawn_blocking("alert_monitor", move || {
+		let mut events = Watch::subscribe(&sync_state.events());
+		let mut last_check = Instant::now();
+		while !stop_state.is_stopped() {
+			// The events only hold the latest value, a tip update may be
+			// followed by a sync status change before we get to see it.
+			if events.recv_timeout(Duration::from_secs(1)).is_some() {
+				if let Ok(head) = chain.head() {
+					checks.head_updated(head.height, Utc::now().timestamp());
+				}
+			}
+			if last_check.elapsed() < Duration::from_secs(CHECK_INTERVAL_SECS) {
+				continue;
+			}
+			last_check = Instant::now();
+			checks.run(&alerter, &sync_state, &peers);
+		}
+	})
+}
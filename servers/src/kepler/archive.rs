@@ -0,0 +1,168 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Export of the chain blocks to flat block files, and import of block files
+//! through the block processing pipeline, to back up a node and seed new ones
+//! without copying the whole db.
+
+use std::sync::Arc;
+
+use crate::chain::block_files::{BlockFileReader, BlockFileWriter};
+use crate::chain::types::NoopAdapter;
+use crate::chain::{self, Chain, ErrorKind, Tip};
+use crate::common::types::{Error, ServerConfig};
+use crate::core::core::hash::Hashed;
+use crate::core::core::verifier_cache::LruVerifierCache;
+use crate::core::pow;
+use crate::kepler::server::{genesis_block, Server};
+use crate::util::RwLock;
+
+/// Number of blocks between two progress reports of an export or import.
+pub const BLOCK_FILES_REPORT_BLOCKS: u64 = 10_000;
+
+/// Outcome of a block files import.
+#[derive(Debug, Clone)]
+pub struct BlockImport {
+	/// Blocks processed by the chain
+	pub imported: u64,
+	/// Blocks skipped, the chain already had them
+	pub known: u64,
+	/// Chain head after the import
+	pub head: Tip,
+}
+
+fn open_chain(config: &ServerConfig) -> Result<Chain, Error> {
+	let chain = Chain::init(
+		config.db_root.clone(),
+		Arc::new(NoopAdapter {}),
+		genesis_block(&config.chain_type),
+		pow::verify_size,
+		Arc::new(RwLock::new(LruVerifierCache::new())),
+		config.archive_mode.unwrap_or(false),
+		config.head_race_policy,
+	)?;
+	Ok(chain)
+}
+
+/// Append the blocks of the main chain between the provided heights (up to
+/// the head if None) to the block files of the directory, calling `report`
+/// with the height reached every `BLOCK_FILES_REPORT_BLOCKS` blocks. Returns
+/// the number of blocks exported. Blocks below the horizon are only kept
+/// by archive nodes.
+pub fn export_blocks<F>(
+	config: &ServerConfig,
+	dir: &str,
+	from_height: u64,
+	to_height: Option<u64>,
+	mut report: F,
+) -> Result<u64, Error>
+where
+	F: FnMut(u64),
+{
+	// Make sure the node isn't running while we read its db.
+	let _lock_file = Server::one_kepler_at_a_time(config)?;
+
+	let chain = open_chain(config)?;
+	let head = chain.head()?;
+	let to_height = to_height.unwrap_or(head.height).min(head.height);
+	info!(
+		"export_blocks: exporting blocks {} to {} to {}",
+		from_height, to_height, dir
+	);
+
+	let mut writer = BlockFileWriter::open(dir)?;
+	let mut exported = 0;
+	for block in chain.blocks_by_height(from_height.max(1), to_height)? {
+		let block = block.map_err(|e| {
+			Error::General(format!(
+				"missing block, exporting blocks below the horizon needs an archive node: {}",
+				e
+			))
+		})?;
+		writer.append(&block)?;
+		exported += 1;
+		if exported % BLOCK_FILES_REPORT_BLOCKS == 0 {
+			report(block.header.height);
+		}
+	}
+	writer.sync()?;
+	info!("export_blocks: exported {} blocks", exported);
+	Ok(exported)
+}
+
+/// Process all the blocks of the block files of the directory, in the order
+/// they were appended, calling `report` with the number of blocks read and
+/// the chain height every `BLOCK_FILES_REPORT_BLOCKS` blocks. The blocks are
+/// fully validated, the ones the chain already has are skipped.
+pub fn import_blocks<F>(
+	config: &ServerConfig,
+	dir: &str,
+	mut report: F,
+) -> Result<BlockImport, Error>
+where
+	F: FnMut(u64, u64),
+{
+	let _lock_file = Server::one_kepler_at_a_time(config)?;
+
+	let chain = open_chain(config)?;
+	info!(
+		"import_blocks: importing blocks from {} at height {}",
+		dir,
+		chain.head()?.height
+	);
+
+	let mut imported = 0;
+	let mut known = 0;
+	for block in BlockFileReader::open(dir)? {
+		let block = block?;
+		let (hash, height) = (block.hash(), block.header.height);
+		if chain.block_exists(hash)? {
+			known += 1;
+		} else {
+			match chain.process_block(block, chain::Options::FROM_LOCAL) {
+				Ok(_) => imported += 1,
+				Err(e) => match e.kind() {
+					// Known already, or too old for a pruned chain.
+					ErrorKind::Known(_) | ErrorKind::Unfit(_) => known += 1,
+					ErrorKind::Orphan => {
+						return Err(Error::General(format!(
+							"block {} at {} is an orphan, the block files are missing its parent",
+							hash, height
+						)));
+					}
+					kind => {
+						return Err(Error::General(format!(
+							"block {} at {} rejected: {}",
+							hash, height, kind
+						)));
+					}
+				},
+			}
+		}
+		if (imported + known) % BLOCK_FILES_REPORT_BLOCKS == 0 {
+			report(imported + known, chain.head()?.height);
+		}
+	}
+
+	let head = chain.head()?;
+	info!(
+		"import_blocks: imported {} blocks, skipped {} known ones, head at {}",
+		imported, known, head.height
+	);
+	Ok(BlockImport {
+		imported,
+		known,
+		head,
+	})
+}
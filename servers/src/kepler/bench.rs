@@ -0,0 +1,109 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Full chain reprocessing benchmark. Replays all the blocks stored by the
+//! node through the block processing pipeline, against a fresh txhashset,
+//! timing each stage of the pipeline.
+
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::chain::pipe::PipeTimings;
+use crate::chain::types::NoopAdapter;
+use crate::chain::{self, Chain};
+use crate::common::types::{Error, ServerConfig};
+use crate::core::core::verifier_cache::LruVerifierCache;
+use crate::core::pow;
+use crate::kepler::server::{genesis_block, Server};
+use crate::util::RwLock;
+
+/// Number of blocks the stage timings are reported for at once.
+pub const BENCH_REPORT_BLOCKS: u64 = 1_000;
+
+/// Replay all the blocks of the node chain through a fresh chain, calling
+/// `report` with the height reached and the stage timings of the last
+/// `BENCH_REPORT_BLOCKS` blocks. Returns the timings over the whole chain.
+/// All blocks since genesis are needed, so the node has to be an archive node.
+pub fn bench_reindex<F>(config: &ServerConfig, mut report: F) -> Result<PipeTimings, Error>
+where
+	F: FnMut(u64, &PipeTimings),
+{
+	// Make sure the node isn't running while we read its db.
+	let _lock_file = Server::one_kepler_at_a_time(config)?;
+
+	let genesis = genesis_block(&config.chain_type);
+	let source = Chain::init(
+		config.db_root.clone(),
+		Arc::new(NoopAdapter {}),
+		genesis.clone(),
+		pow::verify_size,
+		Arc::new(RwLock::new(LruVerifierCache::new())),
+		config.archive_mode.unwrap_or(false),
+		config.head_race_policy,
+	)?;
+
+	let bench_root = format!("{}_bench_reindex", config.db_root.trim_end_matches('/'));
+	if Path::new(&bench_root).exists() {
+		fs::remove_dir_all(&bench_root)?;
+	}
+	let fresh = Chain::init(
+		bench_root.clone(),
+		Arc::new(NoopAdapter {}),
+		genesis,
+		pow::verify_size,
+		Arc::new(RwLock::new(LruVerifierCache::new())),
+		false,
+		config.head_race_policy,
+	)?;
+
+	let head = source.head()?;
+	info!(
+		"bench_reindex: replaying {} blocks in {}",
+		head.height, bench_root
+	);
+
+	let mut total = PipeTimings::default();
+	let mut window = PipeTimings::default();
+	for block in source.blocks_by_height(1, head.height)? {
+		let block = block.map_err(|e| {
+			Error::General(format!(
+				"missing block, replaying the chain needs an archive node: {}",
+				e
+			))
+		})?;
+		fresh.process_block_timed(&block, chain::Options::FROM_LOCAL, &mut window)?;
+		if window.blocks == BENCH_REPORT_BLOCKS || block.header.height == head.height {
+			report(block.header.height, &window);
+			total.merge(&window);
+			window = PipeTimings::default();
+		}
+	}
+
+	let replayed = fresh.head()?;
+	if replayed.last_block_h != head.last_block_h {
+		return Err(Error::General(format!(
+			"replayed chain head {} at {} differs from {} at {}",
+			replayed.last_block_h, replayed.height, head.last_block_h, head.height
+		)));
+	}
+	info!(
+		"bench_reindex: replayed {} blocks up to {}",
+		total.blocks, replayed.last_block_h
+	);
+
+	drop(fresh);
+	fs::remove_dir_all(&bench_root)?;
+	Ok(total)
+}
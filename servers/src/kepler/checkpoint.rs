@@ -0,0 +1,203 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Checkpoint files, signed with the identity key of the node exporting
+//! them. The nodes of a federation import the ones of the operators they
+//! trust at startup, speeding up their initial sync.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::path::Path;
+use std::sync::Arc;
+
+use rand::thread_rng;
+
+use crate::chain::types::NoopAdapter;
+use crate::chain::{Chain, SignedCheckpoint};
+use crate::common::types::{Error, ServerConfig};
+use crate::core::core::hash::{Hash, Hashed};
+use crate::core::core::verifier_cache::LruVerifierCache;
+use crate::core::pow;
+use crate::core::ser;
+use crate::kepler::server::{genesis_block, Server};
+use crate::util::secp::key::{PublicKey, SecretKey};
+use crate::util::{from_hex, static_secp_instance, to_hex, RwLock};
+
+/// File of the node identity key, in the db root.
+pub const NODE_IDENTITY_FILE: &str = "node_identity.key";
+
+/// The identity key of the node, created on first use. Only ever used to
+/// sign the checkpoints exported.
+pub fn node_identity(config: &ServerConfig) -> Result<SecretKey, Error> {
+	let secp = static_secp_instance();
+	let secp = secp.lock();
+	let path = Path::new(&config.db_root).join(NODE_IDENTITY_FILE);
+	if path.exists() {
+		let hex = fs::read_to_string(&path)?;
+		let key = from_hex(hex.trim().to_owned())
+			.ok()
+			.and_then(|bytes| SecretKey::from_slice(&secp, &bytes).ok())
+			.ok_or_else(|| {
+				Error::Configuration(format!("invalid node identity key in {:?}", path))
+			})?;
+		return Ok(key);
+	}
+
+	let key = SecretKey::new(&secp, &mut thread_rng());
+	fs::create_dir_all(&config.db_root)?;
+	fs::write(&path, to_hex(key.0.to_vec()))?;
+	#[cfg(unix)]
+	{
+		use std::os::unix::fs::PermissionsExt;
+		fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+	}
+	info!("checkpoint: node identity key created in {:?}", path);
+	Ok(key)
+}
+
+/// Write the checkpoint of the block at the provided height (the head if
+/// None), signed with the node identity key, to the file. Needs the server
+/// stopped.
+pub fn export_checkpoint(
+	config: &ServerConfig,
+	path: &str,
+	height: Option<u64>,
+) -> Result<SignedCheckpoint, Error> {
+	// Make sure the node isn't running while we read its db.
+	let _lock_file = Server::one_kepler_at_a_time(config)?;
+
+	let chain = Chain::init(
+		config.db_root.clone(),
+		Arc::new(NoopAdapter {}),
+		genesis_block(&config.chain_type),
+		pow::verify_size,
+		Arc::new(RwLock::new(LruVerifierCache::new())),
+		config.archive_mode.unwrap_or(false),
+		config.head_race_policy,
+	)?;
+	let checkpoint = chain.checkpoint(height)?;
+	let key = node_identity(config)?;
+	let genesis = chain.get_header_by_height(0)?;
+	let signed = SignedCheckpoint::sign(checkpoint, &genesis.hash(), &key)?;
+
+	let mut file = File::create(path)?;
+	ser::serialize_default(&mut file, &signed)
+		.map_err(|e| Error::General(format!("writing {}: {}", path, e)))?;
+	info!(
+		"export_checkpoint: {} at {} signed and written to {}",
+		signed.checkpoint.hash, signed.checkpoint.height, path
+	);
+	Ok(signed)
+}
+
+/// Read a checkpoint file.
+pub fn read_checkpoint(path: &str) -> Result<SignedCheckpoint, Error> {
+	let mut file = File::open(path)?;
+	ser::deserialize_default(&mut file)
+		.map_err(|e| Error::General(format!("reading {}: {}", path, e)))
+}
+
+/// The keys of the trusted_checkpoint_operators of the config.
+pub fn trusted_operators(config: &ServerConfig) -> Result<Vec<PublicKey>, Error> {
+	let secp = static_secp_instance();
+	let secp = secp.lock();
+	config
+		.trusted_checkpoint_operators
+		.iter()
+		.map(|hex| {
+			from_hex(hex.clone())
+				.ok()
+				.and_then(|bytes| PublicKey::from_slice(&secp, &bytes).ok())
+				.ok_or_else(|| {
+					Error::Configuration(format!("invalid trusted checkpoint operator {}", hex))
+				})
+		})
+		.collect()
+}
+
+/// The hard_checkpoints of the config, by height.
+pub fn hard_checkpoints(config: &ServerConfig) -> Result<BTreeMap<u64, Hash>, Error> {
+	config
+		.hard_checkpoints
+		.iter()
+		.map(|entry| {
+			let mut parts = entry.splitn(2, ':');
+			let height = parts.next().and_then(|h| h.trim().parse().ok());
+			let hash = parts.next().and_then(|h| Hash::from_hex(h.trim()).ok());
+			match (height, hash) {
+				(Some(height), Some(hash)) => Ok((height, hash)),
+				_ => Err(Error::Configuration(format!(
+					"invalid hard checkpoint {}, expected height:hash",
+					entry
+				))),
+			}
+		})
+		.collect()
+}
+
+/// The assume_valid_height of the config, which has to be the height of one
+/// of the hard checkpoints.
+pub fn assume_valid_height(
+	config: &ServerConfig,
+	hard_checkpoints: &BTreeMap<u64, Hash>,
+) -> Result<Option<u64>, Error> {
+	match config.assume_valid_height {
+		Some(height) if !hard_checkpoints.contains_key(&height) => {
+			Err(Error::Configuration(format!(
+				"assume_valid_height {} isn't the height of a hard checkpoint",
+				height
+			)))
+		}
+		height => Ok(height),
+	}
+}
+
+/// Import the configured checkpoint files into the chain. Files that can't
+/// be read or aren't signed by a trusted operator are skipped, the chain
+/// logs why. Returns the number of checkpoints imported.
+pub fn import_checkpoints(chain: &Chain, config: &ServerConfig) -> Result<usize, Error> {
+	if config.checkpoint_files.is_empty() {
+		return Ok(0);
+	}
+	let trusted = trusted_operators(config)?;
+	if trusted.is_empty() {
+		warn!(
+			"import_checkpoints: {} checkpoint files but no trusted operator, none imported",
+			config.checkpoint_files.len()
+		);
+		return Ok(0);
+	}
+
+	let mut imported = 0;
+	for path in &config.checkpoint_files {
+		let signed = match read_checkpoint(path) {
+			Ok(signed) => signed,
+			Err(e) => {
+				warn!("import_checkpoints: skipping {}: {:?}", path, e);
+				continue;
+			}
+		};
+		match chain.add_checkpoint(signed, &trusted) {
+			Ok(()) => imported += 1,
+			Err(e) => warn!("import_checkpoints: skipping {}: {}", path, e),
+		}
+	}
+	info!(
+		"import_checkpoints: {} of {} checkpoint files imported, from {} trusted operators",
+		imported,
+		config.checkpoint_files.len(),
+		trusted.len()
+	);
+	Ok(imported)
+}
@@ -12,18 +12,19 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use chrono::prelude::Utc;
 use rand::{thread_rng, Rng};
 use std::sync::Arc;
-use std::thread;
 use std::time::{Duration, Instant};
 
+use crate::chain::ChainEvent;
 use crate::common::adapters::DandelionAdapter;
+use crate::common::executor::{Executor, TaskHandle};
+use crate::core::clock::Clock;
 use crate::core::core::hash::Hashed;
 use crate::core::core::transaction;
 use crate::core::core::verifier_cache::VerifierCache;
 use crate::pool::{DandelionConfig, Pool, PoolEntry, PoolError, TransactionPool, TxSource};
-use crate::util::{RwLock, StopState};
+use crate::util::{RwLock, StopState, Watch};
 
 /// A process to monitor transactions in the stempool.
 /// With Dandelion, transaction can be broadcasted in stem or fluff phase.
@@ -38,60 +39,67 @@ pub fn monitor_transactions(
 	tx_pool: Arc<RwLock<TransactionPool>>,
 	adapter: Arc<dyn DandelionAdapter>,
 	verifier_cache: Arc<RwLock<dyn VerifierCache>>,
+	events: Arc<Watch<ChainEvent>>,
+	clock: Arc<dyn Clock>,
 	stop_state: Arc<StopState>,
-) -> std::io::Result<thread::JoinHandle<()>> {
+	executor: &Executor,
+) -> TaskHandle {
 	debug!("Started Dandelion transaction monitor.");
 
-	thread::Builder::new()
-		.name("dandelion".to_string())
-		.spawn(move || {
-			let run_interval = Duration::from_secs(10);
-			let mut last_run = Instant::now()
-				.checked_sub(Duration::from_secs(20))
-				.unwrap_or_else(|| Instant::now());
-			loop {
-				// Halt Dandelion monitor if we have been notified that we are stopping.
-				if stop_state.is_stopped() {
-					break;
-				}
+	executor.spawn_blocking("dandelion", move || {
+		let run_interval = Duration::from_secs(10);
+		let mut last_run = Instant::now()
+			.checked_sub(Duration::from_secs(20))
+			.unwrap_or_else(|| Instant::now());
+		let mut events = Watch::subscribe(&events);
+		loop {
+			// Halt Dandelion monitor if we have been notified that we are stopping.
+			if stop_state.is_stopped() {
+				break;
+			}
 
-				if last_run.elapsed() > run_interval {
-					if !adapter.is_stem() {
-						let _ = process_fluff_phase(
-							&dandelion_config,
-							&tx_pool,
-							&adapter,
-							&verifier_cache,
-						)
-						.map_err(|e| {
-							error!("dand_mon: Problem processing fluff phase. {:?}", e);
-						});
-					}
-
-					// Now find all expired entries based on embargo timer.
-					let _ = process_expired_entries(&dandelion_config, &tx_pool).map_err(|e| {
-						error!("dand_mon: Problem processing expired entries. {:?}", e);
+			if last_run.elapsed() >= run_interval {
+				if !adapter.is_stem() {
+					let _ = process_fluff_phase(
+						&dandelion_config,
+						&tx_pool,
+						&adapter,
+						&verifier_cache,
+						clock.as_ref(),
+					)
+					.map_err(|e| {
+						error!("dand_mon: Problem processing fluff phase. {:?}", e);
 					});
-
-					// Handle the tx above *before* we transition to next epoch.
-					// This gives us an opportunity to do the final "fluff" before we start
-					// stemming on the subsequent epoch.
-					if adapter.is_expired() {
-						adapter.next_epoch();
-					}
-					last_run = Instant::now();
 				}
 
-				// Monitor loops every 10s, but check stop flag every second.
-				thread::sleep(Duration::from_secs(1));
+				// Now find all expired entries based on embargo timer.
+				let res = process_expired_entries(&dandelion_config, &tx_pool, clock.as_ref());
+				let _ = res.map_err(|e| {
+					error!("dand_mon: Problem processing expired entries. {:?}", e);
+				});
+
+				// Handle the tx above *before* we transition to next epoch.
+				// This gives us an opportunity to do the final "fluff" before we start
+				// stemming on the subsequent epoch.
+				if adapter.is_expired() {
+					adapter.next_epoch();
+				}
+				last_run = Instant::now();
 			}
-		})
+
+			// Monitor loops every 10s, shutting down wakes us up early.
+			let next_run = run_interval
+				.checked_sub(last_run.elapsed())
+				.unwrap_or_default();
+			events.recv_timeout(next_run);
+		}
+	})
 }
 
 // Query the pool for transactions older than the cutoff.
 // Used for both periodic fluffing and handling expired embargo timer.
-fn select_txs_cutoff(pool: &Pool, cutoff_secs: u16) -> Vec<PoolEntry> {
-	let cutoff = Utc::now().timestamp() - cutoff_secs as i64;
+fn select_txs_cutoff(pool: &Pool, cutoff_secs: u16, clock: &dyn Clock) -> Vec<PoolEntry> {
+	let cutoff = clock.now().timestamp() - cutoff_secs as i64;
 	pool.entries
 		.iter()
 		.filter(|x| x.tx_at.timestamp() < cutoff)
@@ -99,11 +107,23 @@ fn select_txs_cutoff(pool: &Pool, cutoff_secs: u16) -> Vec<PoolEntry> {
 		.collect()
 }
 
+// Query the stempool for transactions held longer than their embargo timer.
+// Txs pushed with a maximum stem time may expire ahead of the embargo timer.
+fn select_txs_expired(pool: &Pool, embargo_secs: u16, clock: &dyn Clock) -> Vec<PoolEntry> {
+	let now = clock.now().timestamp();
+	pool.entries
+		.iter()
+		.filter(|x| x.tx_at.timestamp() < now - x.embargo_secs(embargo_secs) as i64)
+		.cloned()
+		.collect()
+}
+
 fn process_fluff_phase(
 	dandelion_config: &DandelionConfig,
 	tx_pool: &Arc<RwLock<TransactionPool>>,
 	adapter: &Arc<dyn DandelionAdapter>,
 	verifier_cache: &Arc<RwLock<dyn VerifierCache>>,
+	clock: &dyn Clock,
 ) -> Result<(), PoolError> {
 	// Take a write lock on the txpool for the duration of this processing.
 	let mut tx_pool = tx_pool.write();
@@ -114,7 +134,7 @@ fn process_fluff_phase(
 	}
 
 	let cutoff_secs = dandelion_config.aggregation_secs;
-	let cutoff_entries = select_txs_cutoff(&tx_pool.stempool, cutoff_secs);
+	let cutoff_entries = select_txs_cutoff(&tx_pool.stempool, cutoff_secs, clock);
 
 	// If epoch is expired, fluff *all* outstanding entries in stempool.
 	// If *any* entry older than aggregation_secs (30s) then fluff *all* entries.
@@ -154,12 +174,13 @@ fn process_fluff_phase(
 fn process_expired_entries(
 	dandelion_config: &DandelionConfig,
 	tx_pool: &Arc<RwLock<TransactionPool>>,
+	clock: &dyn Clock,
 ) -> Result<(), PoolError> {
 	// Take a write lock on the txpool for the duration of this processing.
 	let mut tx_pool = tx_pool.write();
 
 	let embargo_secs = dandelion_config.embargo_secs + thread_rng().gen_range(0, 31);
-	let expired_entries = select_txs_cutoff(&tx_pool.stempool, embargo_secs);
+	let expired_entries = select_txs_expired(&tx_pool.stempool, embargo_secs, clock);
 
 	if expired_entries.is_empty() {
 		return Ok(());
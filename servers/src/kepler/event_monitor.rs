@@ -0,0 +1,47 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Publishes the sync status changes to the API event subscribers, the
+//! blocks, pool and peer events being pushed by their hooks.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::api::NodeEvents;
+use crate::chain::SyncState;
+use crate::common::executor::{Executor, TaskHandle};
+use crate::util::{StopState, Watch};
+
+/// Wakes up on the chain events, publishing the sync status whenever it
+/// differs from the one last published. The chain events only hold the
+/// latest value, so the status is compared instead of relying on them.
+pub fn monitor_sync_status(
+	sync_state: Arc<SyncState>,
+	node_events: Arc<NodeEvents>,
+	stop_state: Arc<StopState>,
+	executor: &Executor,
+) -> TaskHandle {
+	executor.spawn_blocking("event_monitor", move || {
+		let mut events = Watch::subscribe(&sync_state.events());
+		let mut published = None;
+		while !stop_state.is_stopped() {
+			let status = sync_state.status();
+			if published != Some(status) {
+				node_events.sync_status_changed(status);
+				published = Some(status);
+			}
+			events.recv_timeout(Duration::from_secs(1));
+		}
+	})
+}
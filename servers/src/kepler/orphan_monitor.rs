@@ -0,0 +1,37 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Chases the missing parents of the blocks in the orphan pool, so a parent
+//! lost in relay doesn't leave us stuck behind the network.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::common::adapters::NetToChainAdapter;
+use crate::common::executor::{Executor, TaskHandle};
+use crate::util::StopState;
+
+/// Every second, asks again for the orphan parents whose backoff ran out.
+pub fn monitor_orphans(
+	adapter: Arc<NetToChainAdapter>,
+	stop_state: Arc<StopState>,
+	executor: &Executor,
+) -> TaskHandle {
+	executor.spawn_periodic(
+		"orphan_monitor",
+		Duration::from_secs(1),
+		stop_state,
+		move || adapter.request_orphan_parents(),
+	)
+}
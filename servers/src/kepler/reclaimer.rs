@@ -0,0 +1,103 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Hands the memory a long running node doesn't need anymore back to the OS.
+//! Caches filled while syncing, the pages of the MMR files read through their
+//! memory maps and the freed heap all stay with the process otherwise, its
+//! resident memory only ever growing.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::chain::{self, SyncState};
+use crate::common::executor::{Executor, TaskHandle};
+use crate::core::core::hash::Hash;
+use crate::core::core::verifier_cache::VerifierCache;
+use crate::p2p;
+use crate::util::{RwLock, StopState};
+
+/// Time without a new block for the node to be considered idle, so the
+/// reclamation doesn't compete with block processing.
+const IDLE_SECS: u64 = 20;
+
+/// Verification results kept in each verifier cache, enough for the txs of
+/// a full block.
+const VERIFIER_CACHE_KEEP: usize = 5_000;
+
+/// Defunct peers kept in the peer store.
+const MAX_DEFUNCT_PEERS: usize = 1_000;
+
+/// Once the node has been synced for `interval`, and again every `interval`
+/// after that, waits for it to be idle then shrinks the verifier cache,
+/// compacts the peer store, reopens the MMR files and releases the freed
+/// memory.
+pub fn reclaim_when_idle(
+	interval: Duration,
+	chain: Arc<chain::Chain>,
+	verifier_cache: Arc<RwLock<dyn VerifierCache>>,
+	peers: Arc<p2p::Peers>,
+	sync_state: Arc<SyncState>,
+	stop_state: Arc<StopState>,
+	executor: &Executor,
+) -> TaskHandle {
+	let mut synced_since: Option<Instant> = None;
+	let mut head: Option<Hash> = None;
+	let mut last_block = Instant::now();
+	let mut last_reclaim: Option<Instant> = None;
+	executor.spawn_periodic("reclaimer", Duration::from_secs(1), stop_state, move || {
+		if sync_state.is_syncing() {
+			synced_since = None;
+			return;
+		}
+		let synced = *synced_since.get_or_insert_with(Instant::now);
+		let current_head = chain.head().ok().map(|h| h.last_block_h);
+		if current_head != head {
+			head = current_head;
+			last_block = Instant::now();
+		}
+
+		let since = last_reclaim.unwrap_or(synced);
+		if since.elapsed() < interval || last_block.elapsed() < Duration::from_secs(IDLE_SECS) {
+			return;
+		}
+		reclaim(&chain, &verifier_cache, &peers);
+		last_reclaim = Some(Instant::now());
+	})
+}
+
+fn reclaim(chain: &chain::Chain, verifier_cache: &RwLock<dyn VerifierCache>, peers: &p2p::Peers) {
+	let start = Instant::now();
+	verifier_cache.write().shrink(VERIFIER_CACHE_KEEP);
+	peers.compact_store(MAX_DEFUNCT_PEERS);
+	if let Err(e) = chain.reopen_mmr_files() {
+		warn!("reclaimer: failed to reopen the MMR files: {}", e);
+	}
+	release_memory();
+	info!(
+		"reclaimer: released idle resources in {}ms",
+		start.elapsed().as_millis()
+	);
+}
+
+// The allocator keeps the freed memory for later allocations unless told
+// otherwise. Only glibc's can be told.
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+fn release_memory() {
+	unsafe {
+		libc::malloc_trim(0);
+	}
+}
+
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+fn release_memory() {}
@@ -0,0 +1,141 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Replay of the p2p messages captured by a node. Feeds them through the
+//! protocol handlers and the chain adapter of a fresh node, as if received
+//! from their peers, to reproduce the bugs hit while handling them.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::api::ActiveAlerts;
+use crate::chain::types::NoopAdapter;
+use crate::chain::{Chain, SyncState, SyncStatus, Tip};
+use crate::common::adapters::{NetToChainAdapter, PoolToChainAdapter};
+use crate::common::alerts::Alerter;
+use crate::common::policy::TxPolicy;
+use crate::common::types::{Error, ServerConfig};
+use crate::core::clock::SystemClock;
+use crate::core::core::hash::Hashed;
+use crate::core::core::verifier_cache::LruVerifierCache;
+use crate::core::pow;
+use crate::kepler::server::genesis_block;
+use crate::p2p;
+use crate::pool;
+use crate::util::{RwLock, StopState};
+
+/// Outcome of a replay.
+pub struct ReplayStats {
+	/// Messages replayed
+	pub messages: u64,
+	/// Messages whose handling failed
+	pub failures: u64,
+	/// Head of the chain the messages were replayed against
+	pub head: Tip,
+}
+
+/// Replay the messages of the capture files, in order, against a fresh chain
+/// next to the node one. `on_failure` is called with the index of each
+/// message whose handling failed. The txs received are validated right away
+/// rather than by background workers, so that replays are deterministic.
+pub fn replay_capture<F>(
+	config: &ServerConfig,
+	files: &[PathBuf],
+	mut on_failure: F,
+) -> Result<ReplayStats, Error>
+where
+	F: FnMut(u64, &p2p::CapturedMsg, &p2p::Error),
+{
+	let replay_root = format!("{}_replay", config.db_root.trim_end_matches('/'));
+	if Path::new(&replay_root).exists() {
+		fs::remove_dir_all(&replay_root)?;
+	}
+
+	let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
+	let pool_adapter = Arc::new(PoolToChainAdapter::new());
+	let tx_pool = Arc::new(RwLock::new(pool::TransactionPool::new(
+		config.pool_config.clone(),
+		pool_adapter.clone(),
+		verifier_cache.clone(),
+		Arc::new(pool::types::NoopAdapter {}),
+		Arc::new(SystemClock),
+	)));
+
+	let genesis = genesis_block(&config.chain_type);
+	let chain = Arc::new(Chain::init(
+		replay_root.clone(),
+		Arc::new(NoopAdapter {}),
+		genesis.clone(),
+		pow::verify_size,
+		verifier_cache.clone(),
+		false,
+		config.head_race_policy,
+	)?);
+	pool_adapter.set_chain(chain.clone());
+
+	// Handled as by a node in sync, txs are dropped while syncing.
+	let sync_state = Arc::new(SyncState::new());
+	sync_state.update(SyncStatus::NoSync);
+	let alerter = Arc::new(Alerter::with_notifiers(
+		Arc::new(ActiveAlerts::new()),
+		vec![],
+	));
+	let net_adapter = Arc::new(NetToChainAdapter::new(
+		sync_state,
+		chain.clone(),
+		tx_pool,
+		verifier_cache,
+		config.clone(),
+		vec![],
+		alerter,
+		TxPolicy::from_config(&config.tx_policy_config)?.map(Arc::new),
+	));
+
+	// Never started, only provides the peers the handlers run against.
+	let mut p2p_config = config.p2p_config.clone();
+	p2p_config.capture_messages = false;
+	let p2p_server = p2p::Server::new(
+		&replay_root,
+		p2p_config.capabilities,
+		p2p_config,
+		net_adapter.clone(),
+		genesis.hash(),
+		Arc::new(StopState::new()),
+	)?;
+	net_adapter.init(p2p_server.peers.clone());
+
+	let tx_intake = net_adapter.tx_intake();
+	let mut replayer = p2p::Replayer::new(p2p_server.peers.clone());
+	let mut messages = 0;
+	let mut failures = 0;
+	for file in files {
+		info!("replay_capture: replaying {:?}", file);
+		for msg in p2p::CaptureReader::open(file)? {
+			let msg = msg?;
+			if let Err(e) = replayer.replay(&msg) {
+				failures += 1;
+				on_failure(messages, &msg, &e);
+			}
+			tx_intake.validate_queued();
+			messages += 1;
+		}
+	}
+
+	Ok(ReplayStats {
+		messages,
+		failures,
+		head: chain.head()?,
+	})
+}
@@ -26,6 +26,7 @@ use std::net::ToSocketAddrs;
 use std::sync::{mpsc, Arc};
 use std::{cmp, str, thread, time};
 
+use crate::common::executor::{Executor, TaskHandle};
 use crate::core::global;
 use crate::p2p;
 use crate::p2p::types::PeerAddr;
@@ -43,98 +44,146 @@ const MAINNET_DNS_SEEDS: &'static [&'static str] = &[
 ];
 const FLOONET_DNS_SEEDS: &'static [&'static str] = &["testseed1.kepler.network"];
 
+// Seconds between two reconciliations of the tx announcements with each of
+// our outbound peers.
+const TX_RECONCILIATION_INTERVAL: i64 = 8;
+
 pub fn connect_and_monitor(
 	p2p_server: Arc<p2p::Server>,
 	capabilities: p2p::Capabilities,
 	seed_list: Box<dyn Fn() -> Vec<PeerAddr> + Send>,
 	preferred_peers: Option<Vec<PeerAddr>>,
 	stop_state: Arc<StopState>,
-) -> std::io::Result<thread::JoinHandle<()>> {
-	thread::Builder::new()
-		.name("seed".to_string())
-		.spawn(move || {
-			let peers = p2p_server.peers.clone();
+	executor: &Executor,
+) -> TaskHandle {
+	let peers = p2p_server.peers.clone();
+
+	// open a channel with a listener that connects every peer address sent below
+	// max peer count
+	let (tx, rx) = mpsc::channel();
+
+	let mut seeded = false;
+	let mut prev = MIN_DATE.and_hms(0, 0, 0);
+	let mut prev_expire_check = MIN_DATE.and_hms(0, 0, 0);
+	let mut prev_ping = Utc::now();
+	let mut prev_recon = Utc::now();
+	let archive = p2p_server
+		.capabilities()
+		.contains(p2p::Capabilities::ARCHIVE);
+	let mut clock_drift_warned = false;
+	let mut insufficient_peers = false;
+	let mut start_attempt = 0;
+	let mut connecting_history: HashMap<PeerAddr, DateTime<Utc>> = HashMap::new();
+
+	let period = time::Duration::from_secs(1);
+	executor.spawn_periodic("seed", period, stop_state.clone(), move || {
+		// Pause egress peer connection request, once we checked the seeds.
+		// Only for tests.
+		if seeded && stop_state.is_paused() {
+			return;
+		}
+
+		// Check for and remove expired peers from the storage
+		if Utc::now() - prev_expire_check > Duration::hours(1) {
+			peers.remove_expired();
 
-			// open a channel with a listener that connects every peer address sent below
-			// max peer count
-			let (tx, rx) = mpsc::channel();
+			prev_expire_check = Utc::now();
+		}
 
-			// check seeds first
+		// check seeds first, then dial replacements right away when we drop
+		// below the minimum outbound peer count, going back to the seeds in
+		// case our remaining peers can't give us any
+		let insufficient = peers.insufficient_outbound_peers();
+		if !seeded || (insufficient && !insufficient_peers) {
+			if seeded {
+				warn!(
+					"Only {} outbound peers left, below the minimum of {}, dialing replacements",
+					peers.peer_outbound_count(),
+					p2p_server.config.peer_min_outbound_count(),
+				);
+			}
 			connect_to_seeds_and_preferred_peers(
 				peers.clone(),
 				tx.clone(),
-				seed_list,
+				&seed_list,
 				preferred_peers.clone(),
 			);
+			prev = MIN_DATE.and_hms(0, 0, 0);
+			start_attempt = 0;
+			seeded = true;
+		}
+		insufficient_peers = insufficient;
 
-			let mut prev = MIN_DATE.and_hms(0, 0, 0);
-			let mut prev_expire_check = MIN_DATE.and_hms(0, 0, 0);
-			let mut prev_ping = Utc::now();
-			let mut start_attempt = 0;
-			let mut connecting_history: HashMap<PeerAddr, DateTime<Utc>> = HashMap::new();
-
-			loop {
-				if stop_state.is_stopped() {
-					break;
-				}
-
-				// Pause egress peer connection request. Only for tests.
-				if stop_state.is_paused() {
-					thread::sleep(time::Duration::from_secs(1));
-					continue;
-				}
-
-				// Check for and remove expired peers from the storage
-				if Utc::now() - prev_expire_check > Duration::hours(1) {
-					peers.remove_expired();
-
-					prev_expire_check = Utc::now();
-				}
+		// make several attempts to get peers as quick as possible
+		// with exponential backoff
+		if Utc::now() - prev > Duration::seconds(cmp::min(20, 1 << start_attempt)) {
+			// try to connect to any address sent to the channel
+			listen_for_addrs(
+				peers.clone(),
+				p2p_server.clone(),
+				capabilities,
+				&rx,
+				&mut connecting_history,
+			);
 
-				// make several attempts to get peers as quick as possible
-				// with exponential backoff
-				if Utc::now() - prev > Duration::seconds(cmp::min(20, 1 << start_attempt)) {
-					// try to connect to any address sent to the channel
-					listen_for_addrs(
-						peers.clone(),
-						p2p_server.clone(),
-						capabilities,
-						&rx,
-						&mut connecting_history,
-					);
+			// monitor additional peers if we need to add more
+			monitor_peers(
+				peers.clone(),
+				p2p_server.config.clone(),
+				archive,
+				tx.clone(),
+				preferred_peers.clone(),
+			);
 
-					// monitor additional peers if we need to add more
-					monitor_peers(
-						peers.clone(),
-						p2p_server.config.clone(),
-						tx.clone(),
-						preferred_peers.clone(),
-					);
+			prev = Utc::now();
+			start_attempt = cmp::min(6, start_attempt + 1);
+		}
 
-					prev = Utc::now();
-					start_attempt = cmp::min(6, start_attempt + 1);
-				}
+		// Ping connected peers on every 10s to monitor peers.
+		if Utc::now() - prev_ping > Duration::seconds(10) {
+			let total_diff = peers.total_difficulty();
+			let total_height = peers.total_height();
+			if total_diff.is_ok() && total_height.is_ok() {
+				peers.check_all(total_diff.unwrap(), total_height.unwrap());
+				prev_ping = Utc::now();
+			} else {
+				error!("failed to get peers difficulty and/or height");
+			}
+			check_network_time(&peers, &p2p_server.config, &mut clock_drift_warned);
+		}
 
-				// Ping connected peers on every 10s to monitor peers.
-				if Utc::now() - prev_ping > Duration::seconds(10) {
-					let total_diff = peers.total_difficulty();
-					let total_height = peers.total_height();
-					if total_diff.is_ok() && total_height.is_ok() {
-						peers.check_all(total_diff.unwrap(), total_height.unwrap());
-						prev_ping = Utc::now();
-					} else {
-						error!("failed to get peers difficulty and/or height");
-					}
-				}
+		// Reconcile the tx announcements queued for our outbound peers.
+		if Utc::now() - prev_recon > Duration::seconds(TX_RECONCILIATION_INTERVAL) {
+			peers.reconcile_transactions();
+			prev_recon = Utc::now();
+		}
+	})
+}
 
-				thread::sleep(time::Duration::from_secs(1));
-			}
-		})
+// Estimate the network time from the peers clocks, warning when ours drifted
+// too far from it and adjusting to it if configured to.
+fn check_network_time(peers: &p2p::Peers, config: &p2p::P2PConfig, warned: &mut bool) {
+	let offset = match peers.network_time_offset() {
+		Some(offset) => offset,
+		None => return,
+	};
+	let drifted = offset.abs() > global::MAX_NETWORK_TIME_OFFSET;
+	if drifted && !*warned {
+		warn!(
+			"Local clock is {}s off the network time estimated from our peers, check the system clock",
+			-offset
+		);
+	}
+	*warned = drifted;
+	if config.adjust_network_time {
+		global::set_network_time_offset(offset);
+	}
 }
 
 fn monitor_peers(
 	peers: Arc<p2p::Peers>,
 	config: p2p::P2PConfig,
+	archive: bool,
 	tx: mpsc::Sender<PeerAddr>,
 	preferred_peers_list: Option<Vec<PeerAddr>>,
 ) {
@@ -184,8 +233,23 @@ fn monitor_peers(
 	peers.clean_peers(
 		config.peer_max_inbound_count() as usize,
 		config.peer_max_outbound_count() as usize,
+		archive,
 	);
 
+	// an archive node needs an archive peer for the blocks beyond the
+	// horizon, and connects to one even with enough outbound peers
+	if seeks_archive_peer(&peers, archive) {
+		let archive_capab = p2p::Capabilities::PEER_LIST | p2p::Capabilities::ARCHIVE;
+		for p in peers.connected_peers() {
+			let _ = p.send_peer_request(archive_capab);
+		}
+		for p in peers.find_peers(p2p::State::Healthy, p2p::Capabilities::ARCHIVE, 8) {
+			if let Ok(false) = peers.is_known(p.addr) {
+				tx.send(p.addr).unwrap();
+			}
+		}
+	}
+
 	if peers.enough_outbound_peers() {
 		return;
 	}
@@ -251,7 +315,7 @@ fn monitor_peers(
 fn connect_to_seeds_and_preferred_peers(
 	peers: Arc<p2p::Peers>,
 	tx: mpsc::Sender<PeerAddr>,
-	seed_list: Box<dyn Fn() -> Vec<PeerAddr>>,
+	seed_list: &dyn Fn() -> Vec<PeerAddr>,
 	peers_preferred_list: Option<Vec<PeerAddr>>,
 ) {
 	// check if we have some peers in db
@@ -297,8 +361,10 @@ fn listen_for_addrs(
 	// here to prevent it backing up.
 	let addrs: Vec<PeerAddr> = rx.try_iter().collect();
 
-	// If we have a healthy number of outbound peers then we are done here.
-	if peers.enough_outbound_peers() {
+	// If we have a healthy number of outbound peers then we are done here,
+	// unless still looking for an archive peer.
+	let archive = p2p.capabilities().contains(p2p::Capabilities::ARCHIVE);
+	if peers.enough_outbound_peers() && !seeks_archive_peer(&peers, archive) {
 		return;
 	}
 
@@ -357,6 +423,11 @@ fn listen_for_addrs(
 	}
 }
 
+// Whether we're an archive node without any archive peer connected.
+fn seeks_archive_peer(peers: &p2p::Peers, archive: bool) -> bool {
+	archive && !peers.archive_peer_connected()
+}
+
 pub fn default_dns_seeds() -> Box<dyn Fn() -> Vec<PeerAddr> + Send> {
 	Box::new(|| {
 		let net_seeds = if global::is_floonet() {
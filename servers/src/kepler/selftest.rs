@@ -0,0 +1,241 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Node self-test. Checks what commonly breaks a node in ways that only show
+//! as "it doesn't sync": a broken PoW verifier or serialization, a slow or
+//! failing disk, corrupted txhashset files, a low open files limit or a
+//! clock off by too much.
+
+use chrono::prelude::Utc;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::chain::types::NoopAdapter;
+use crate::chain::Chain;
+use crate::common::types::{Error, ServerConfig};
+use crate::core::clock::{self, SystemClock};
+use crate::core::core::hash::Hashed;
+use crate::core::core::verifier_cache::LruVerifierCache;
+use crate::core::core::{Block, BlockHeader};
+use crate::core::pow;
+use crate::core::ser::{self, ProtocolVersion};
+use crate::kepler::server::{genesis_block, Server};
+use crate::store::Store;
+use crate::util::RwLock;
+
+/// Number of entries written to measure the LMDB write throughput.
+const LMDB_WRITES: u32 = 10_000;
+
+/// Size of the entries written to measure the LMDB write throughput.
+const LMDB_ENTRY_SIZE: usize = 1_024;
+
+/// Below that many writes per second, syncing will crawl.
+const MIN_LMDB_WRITES_PER_SEC: f64 = 1_000.0;
+
+/// Peer connections and MMR files need more than the usual defaults.
+const MIN_OPEN_FILES: u64 = 1_024;
+
+/// Outcome of a single self-test check.
+#[derive(Debug, Clone)]
+pub struct SelfTestCheck {
+	/// Name of the check
+	pub name: &'static str,
+	/// Whether the check passed
+	pub passed: bool,
+	/// What was measured or why the check failed
+	pub detail: String,
+	/// Time taken by the check
+	pub duration: Duration,
+}
+
+impl SelfTestCheck {
+	fn run<F>(name: &'static str, check: F) -> SelfTestCheck
+	where
+		F: FnOnce() -> Result<String, String>,
+	{
+		let start = Instant::now();
+		let res = check();
+		let duration = start.elapsed();
+		let passed = res.is_ok();
+		let detail = res.unwrap_or_else(|e| e);
+		if passed {
+			info!("selftest: {} passed: {}", name, detail);
+		} else {
+			warn!("selftest: {} failed: {}", name, detail);
+		}
+		SelfTestCheck {
+			name,
+			passed,
+			detail,
+			duration,
+		}
+	}
+}
+
+/// Run all the self-test checks against the node configuration. The node
+/// must not be running, its chain gets opened and validated.
+pub fn selftest(config: &ServerConfig) -> Result<Vec<SelfTestCheck>, Error> {
+	let _lock_file = Server::one_kepler_at_a_time(config)?;
+
+	let genesis = genesis_block(&config.chain_type);
+	let mut checks = vec![];
+	checks.push(SelfTestCheck::run("pow", || check_pow(&genesis.header)));
+	checks.push(SelfTestCheck::run("ser", || check_ser(&genesis)));
+	checks.push(SelfTestCheck::run("lmdb", || check_lmdb(&config.db_root)));
+
+	let mut head = None;
+	checks.push(SelfTestCheck::run("txhashset", || {
+		let chain = Chain::init(
+			config.db_root.clone(),
+			Arc::new(NoopAdapter {}),
+			genesis.clone(),
+			pow::verify_size,
+			Arc::new(RwLock::new(LruVerifierCache::new())),
+			config.archive_mode.unwrap_or(false),
+			config.head_race_policy,
+		)
+		.map_err(|e| format!("failed to open the chain: {}", e))?;
+		let header = chain
+			.head_header()
+			.map_err(|e| format!("failed to read the chain head: {}", e))?;
+		chain
+			.validate(true)
+			.map_err(|e| format!("invalid at height {}: {}", header.height, e))?;
+		let detail = format!("valid up to height {}", header.height);
+		head = Some(header);
+		Ok(detail)
+	}));
+
+	checks.push(SelfTestCheck::run("open_files", check_open_files));
+	checks.push(SelfTestCheck::run("clock", || {
+		check_clock(&genesis.header, head.as_ref())
+	}));
+	Ok(checks)
+}
+
+// The genesis PoW must verify and stop verifying as soon as the header
+// changes.
+fn check_pow(genesis: &BlockHeader) -> Result<String, String> {
+	pow::verify_size(genesis).map_err(|e| format!("genesis proof of work rejected: {}", e))?;
+	let mut tampered = genesis.clone();
+	tampered.pow.nonce = tampered.pow.nonce.wrapping_add(1);
+	if pow::verify_size(&tampered).is_ok() {
+		return Err("invalid proof of work accepted".to_owned());
+	}
+	Ok(format!(
+		"genesis proof of work verified ({} edge bits)",
+		genesis.pow.edge_bits()
+	))
+}
+
+fn check_ser(genesis: &Block) -> Result<String, String> {
+	let version = ProtocolVersion::local();
+	let bytes = ser::ser_vec(genesis, version).map_err(|e| format!("serialization: {}", e))?;
+	let block: Block = ser::deserialize(&mut &bytes[..], version)
+		.map_err(|e| format!("deserialization: {}", e))?;
+	if block.hash() != genesis.hash() {
+		return Err("deserialized block hash differs".to_owned());
+	}
+	let round_trip = ser::ser_vec(&block, version).map_err(|e| format!("serialization: {}", e))?;
+	if round_trip != bytes {
+		return Err("reserialized block differs".to_owned());
+	}
+	Ok(format!("{} bytes block round-tripped", bytes.len()))
+}
+
+fn check_lmdb(db_root: &str) -> Result<String, String> {
+	let root = format!("{}_selftest", db_root.trim_end_matches('/'));
+	if Path::new(&root).exists() {
+		fs::remove_dir_all(&root).map_err(|e| format!("failed to clean {}: {}", root, e))?;
+	}
+	let res = lmdb_writes(&root);
+	let _ = fs::remove_dir_all(&root);
+	let elapsed = res?;
+
+	let per_sec = LMDB_WRITES as f64 / elapsed.as_secs_f64().max(f64::EPSILON);
+	let detail = format!(
+		"{:.0} writes/s, {:.1} MB/s",
+		per_sec,
+		per_sec * LMDB_ENTRY_SIZE as f64 / 1_000_000.0
+	);
+	if per_sec < MIN_LMDB_WRITES_PER_SEC {
+		Err(format!(
+			"{}, below {:.0} writes/s",
+			detail, MIN_LMDB_WRITES_PER_SEC
+		))
+	} else {
+		Ok(detail)
+	}
+}
+
+fn lmdb_writes(root: &str) -> Result<Duration, String> {
+	let store = Store::new(root, None, None, None).map_err(|e| format!("open: {}", e))?;
+	let value = vec![0xa5; LMDB_ENTRY_SIZE];
+	let start = Instant::now();
+	let batch = store.batch().map_err(|e| format!("batch: {}", e))?;
+	for i in 0..LMDB_WRITES {
+		batch
+			.put(&i.to_be_bytes(), &value)
+			.map_err(|e| format!("write: {}", e))?;
+	}
+	batch.commit().map_err(|e| format!("commit: {}", e))?;
+	Ok(start.elapsed())
+}
+
+// Only Linux exposes the limits of the process without going through libc.
+fn check_open_files() -> Result<String, String> {
+	let limits = match fs::read_to_string("/proc/self/limits") {
+		Ok(limits) => limits,
+		Err(_) => return Ok("not checked on this platform".to_owned()),
+	};
+	let soft_limit = limits
+		.lines()
+		.find(|l| l.starts_with("Max open files"))
+		.and_then(|l| l.split_whitespace().nth(3))
+		.ok_or_else(|| "open files limit not found".to_owned())?;
+	if soft_limit == "unlimited" {
+		return Ok("unlimited".to_owned());
+	}
+	let soft_limit: u64 = soft_limit
+		.parse()
+		.map_err(|_| format!("unexpected open files limit {}", soft_limit))?;
+	if soft_limit < MIN_OPEN_FILES {
+		Err(format!(
+			"limit of {} open files, below {} (see ulimit -n)",
+			soft_limit, MIN_OPEN_FILES
+		))
+	} else {
+		Ok(format!("limit of {} open files", soft_limit))
+	}
+}
+
+// A clock running late rejects the headers of the network as too far in the
+// future, one running early can't be told from the network being stalled.
+fn check_clock(genesis: &BlockHeader, head: Option<&BlockHeader>) -> Result<String, String> {
+	let now = Utc::now();
+	if now < genesis.timestamp {
+		return Err(format!("{} is before the genesis block", now));
+	}
+	if let Some(head) = head {
+		if head.timestamp > clock::max_header_time(&SystemClock) {
+			return Err(format!(
+				"{} is too far behind the chain head time {}",
+				now, head.timestamp
+			));
+		}
+	}
+	Ok(format!("{}", now))
+}
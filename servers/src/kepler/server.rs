@@ -22,7 +22,7 @@ use std::io::prelude::*;
 use std::path::Path;
 use std::sync::{mpsc, Arc};
 use std::{
-	thread::{self, JoinHandle},
+	thread,
 	time::{self, Duration},
 };
 
@@ -31,20 +31,32 @@ use walkdir::WalkDir;
 
 use crate::api;
 use crate::api::TLSConfig;
+use crate::chain::block_files::BlockFileWriter;
 use crate::chain::{self, SyncState, SyncStatus};
 use crate::common::adapters::{
 	ChainToPoolAndNetAdapter, NetToChainAdapter, PoolToChainAdapter, PoolToNetAdapter,
 };
+use crate::common::alerts::Alerter;
+use crate::common::executor::{Executor, TaskHandle};
 use crate::common::hooks::{init_chain_hooks, init_net_hooks};
+use crate::common::policy::TxPolicy;
+use crate::common::priority;
 use crate::common::stats::{
 	ChainStats, DiffBlock, DiffStats, PeerStats, ServerStateInfo, ServerStats, TxStats,
 };
+use crate::common::tx_intake;
 use crate::common::types::{Error, ServerConfig, StratumServerConfig};
+use crate::core::clock::{Clock, SystemClock};
 use crate::core::core::hash::Hashed;
 use crate::core::core::verifier_cache::{LruVerifierCache, VerifierCache};
+use crate::core::core::Block;
 use crate::core::ser::ProtocolVersion;
 use crate::core::{consensus, genesis, global, pow};
-use crate::kepler::{dandelion_monitor, seed, sync};
+use crate::kepler::upstream::{self, UpstreamNode};
+use crate::kepler::{
+	alert_monitor, checkpoint, dandelion_monitor, event_monitor, orphan_monitor, reclaimer, seed,
+	stats_recorder, sync,
+};
 use crate::mining::stratumserver;
 use crate::mining::test_miner::Miner;
 use crate::p2p;
@@ -69,15 +81,27 @@ pub struct Server {
 	verifier_cache: Arc<RwLock<dyn VerifierCache>>,
 	/// Whether we're currently syncing
 	pub sync_state: Arc<SyncState>,
+	/// External policy on the txs relayed and mined, if configured
+	tx_policy: Option<Arc<TxPolicy>>,
 	/// To be passed around to collect stats and info
 	state_info: ServerStateInfo,
 	/// Stop flag
 	pub stop_state: Arc<StopState>,
 	/// Maintain a lock_file so we do not run multiple Kepler nodes from same dir.
 	lock_file: Arc<File>,
-	connect_thread: Option<JoinHandle<()>>,
-	sync_thread: JoinHandle<()>,
-	dandelion_thread: JoinHandle<()>,
+	/// Runtime shared by the server subsystems
+	pub executor: Arc<Executor>,
+	connect_thread: Option<TaskHandle>,
+	sync_thread: Option<TaskHandle>,
+	orphan_thread: Option<TaskHandle>,
+	dandelion_thread: TaskHandle,
+	stats_thread: TaskHandle,
+	event_thread: TaskHandle,
+	alert_thread: TaskHandle,
+	reclaimer_thread: Option<TaskHandle>,
+	tx_validation_threads: Vec<TaskHandle>,
+	api_server: api::ApiServer,
+	api_thread: api::ApiServerHandle,
 }
 
 impl Server {
@@ -123,7 +147,7 @@ impl Server {
 	// Exclusive (advisory) lock_file to ensure we do not run multiple
 	// instance of kepler server from the same dir.
 	// This uses fs2 and should be safe cross-platform unless somebody abuses the file itself.
-	fn one_kepler_at_a_time(config: &ServerConfig) -> Result<Arc<File>, Error> {
+	pub(crate) fn one_kepler_at_a_time(config: &ServerConfig) -> Result<Arc<File>, Error> {
 		let path = Path::new(&config.db_root);
 		fs::create_dir_all(path.clone())?;
 		let path = path.join("kepler.lock");
@@ -147,9 +171,24 @@ impl Server {
 
 	/// Instantiates a new server associated with the provided future reactor.
 	pub fn new(config: ServerConfig) -> Result<Server, Error> {
+		Server::new_with_clock(config, Arc::new(SystemClock))
+	}
+
+	/// Instantiates a new server checking block timestamps and running its
+	/// Dandelion timers against the provided clock, so simulations can
+	/// control the time the node runs at.
+	pub fn new_with_clock(
+		mut config: ServerConfig,
+		clock: Arc<dyn Clock>,
+	) -> Result<Server, Error> {
 		// Obtain our lock_file or fail immediately with an error.
 		let lock_file = Server::one_kepler_at_a_time(&config)?;
 
+		if config.public_node {
+			warn!("Running as a public node, owner API disabled.");
+			config.apply_public_node_profile();
+		}
+
 		// Defaults to None (optional) in config file.
 		// This translates to false here.
 		let archive_mode = match config.archive_mode {
@@ -159,58 +198,134 @@ impl Server {
 
 		let stop_state = Arc::new(StopState::new());
 
+		let executor = Arc::new(Executor::new(config.executor_threads)?);
+
+		priority::init_verification_threads(config.verification_threads, config.validation_nice);
+
 		// Shared cache for verification results.
 		// We cache rangeproof verification and kernel signature verification.
 		let verifier_cache = Arc::new(RwLock::new(LruVerifierCache::new()));
 
+		let upstream = config.upstream_node_url.as_ref().map(|url| {
+			let secret = get_first_line(config.upstream_api_secret_path.clone());
+			Arc::new(UpstreamNode::new(url, secret))
+		});
+
 		let pool_adapter = Arc::new(PoolToChainAdapter::new());
-		let pool_net_adapter = Arc::new(PoolToNetAdapter::new(config.dandelion_config.clone()));
+		let node_events = Arc::new(api::NodeEvents::new());
+		let pool_events = Arc::new(api::PoolEvents::new().with_node_events(node_events.clone()));
+		let pool_net_adapter = Arc::new(PoolToNetAdapter::new(
+			config.dandelion_config.clone(),
+			upstream.clone(),
+			pool_events.clone(),
+			clock.clone(),
+		));
 		let tx_pool = Arc::new(RwLock::new(pool::TransactionPool::new(
 			config.pool_config.clone(),
 			pool_adapter.clone(),
 			verifier_cache.clone(),
 			pool_net_adapter.clone(),
+			clock.clone(),
 		)));
 
 		let sync_state = Arc::new(SyncState::new());
 
+		let block_files = match config.block_files_dir {
+			Some(ref dir) => Some(BlockFileWriter::open(dir)?),
+			None => None,
+		};
+		let block_events = Arc::new(api::BlockEvents::new().with_node_events(node_events.clone()));
 		let chain_adapter = Arc::new(ChainToPoolAndNetAdapter::new(
 			tx_pool.clone(),
-			init_chain_hooks(&config),
+			sync_state.events(),
+			init_chain_hooks(&config, block_events.clone()),
+			block_files,
+			config.p2p_config.fast_block_announce,
 		));
 
-		let genesis = match config.chain_type {
-			global::ChainTypes::AutomatedTesting => pow::mine_genesis_block().unwrap(),
-			global::ChainTypes::UserTesting => pow::mine_genesis_block().unwrap(),
-			global::ChainTypes::Floonet => genesis::genesis_floo(),
-			global::ChainTypes::Mainnet => genesis::genesis_main(),
-		};
+		let genesis = genesis_block(&config.chain_type);
 
 		info!("Starting server, genesis block: {}", genesis.hash());
 
-		let shared_chain = Arc::new(chain::Chain::init(
+		let init_chain = if config.safe_mode {
+			warn!("Safe mode, the chain is read-only and the node won't sync");
+			chain::Chain::init_read_only
+		} else {
+			chain::Chain::init
+		};
+		let chain = init_chain(
 			config.db_root.clone(),
 			chain_adapter.clone(),
 			genesis.clone(),
 			pow::verify_size,
 			verifier_cache.clone(),
 			archive_mode,
-		)?);
+			config.head_race_policy,
+		)
+		.map_err(|e| {
+			if let chain::ErrorKind::DataVersionTooNew(_, _) = e.kind() {
+				error!(
+					"{}, upgrade or run in safe mode (--safe_mode) to serve API queries read-only",
+					e.kind()
+				);
+			}
+			e
+		})?;
+		let orphan_limits = chain::OrphanPoolLimits {
+			max_orphans: config.max_orphans,
+			max_age_secs: config.max_orphan_age_secs,
+			max_height_ahead: config.max_orphan_height_ahead,
+		};
+		let hard_checkpoints = checkpoint::hard_checkpoints(&config)?;
+		let assume_valid_height = checkpoint::assume_valid_height(&config, &hard_checkpoints)?;
+		let shared_chain = Arc::new(
+			chain
+				.with_clock(clock.clone())
+				.with_checkpoints(hard_checkpoints, assume_valid_height)
+				.with_orphan_limits(orphan_limits),
+		);
+		checkpoint::import_checkpoints(&shared_chain, &config)?;
 
 		pool_adapter.set_chain(shared_chain.clone());
 
+		let active_alerts = Arc::new(api::ActiveAlerts::new());
+		let alerter = Arc::new(Alerter::new(&config.alert_config, active_alerts.clone()));
+		let tx_policy = TxPolicy::from_config(&config.tx_policy_config)?.map(Arc::new);
+
 		let net_adapter = Arc::new(NetToChainAdapter::new(
 			sync_state.clone(),
 			shared_chain.clone(),
 			tx_pool.clone(),
 			verifier_cache.clone(),
 			config.clone(),
-			init_net_hooks(&config),
+			init_net_hooks(&config, node_events.clone()),
+			alerter.clone(),
+			tx_policy.clone(),
 		));
 
+		// Pruned nodes only serve the recent blocks, telling peers which ones.
+		let mut capabilities = config.p2p_config.capabilities;
+		// Archive nodes serve all the blocks, pruned ones those from their
+		// body tail.
+		if shared_chain.archive_mode() {
+			capabilities |= p2p::Capabilities::ARCHIVE;
+		} else {
+			capabilities |= p2p::Capabilities::BLOCK_RANGE;
+		}
+		// Peers send the proofs of the inputs of their txs only if we ask.
+		if config.pool_config.input_proofs_above > 0 {
+			capabilities |= p2p::Capabilities::INPUT_PROOFS;
+		}
+		// Every node serves the blocks it has in chunks too.
+		capabilities |= p2p::Capabilities::BLOCK_CHUNKS;
+		// And the txhashset at its archive header in segments.
+		capabilities |= p2p::Capabilities::TXHASHSET_SEGMENTS;
+		// And reconciles its tx announcements with the peers doing so.
+		capabilities |= p2p::Capabilities::TX_RECONCILIATION;
+
 		let p2p_server = Arc::new(p2p::Server::new(
 			&config.db_root,
-			config.p2p_config.capabilities,
+			capabilities,
 			config.p2p_config.clone(),
 			net_adapter.clone(),
 			genesis.hash(),
@@ -219,12 +334,17 @@ impl Server {
 
 		// Initialize various adapters with our dynamic set of connected peers.
 		chain_adapter.init(p2p_server.peers.clone());
-		pool_net_adapter.init(p2p_server.peers.clone());
+		pool_net_adapter.init(p2p_server.peers.clone(), shared_chain.clone());
 		net_adapter.init(p2p_server.peers.clone());
 
 		let mut connect_thread = None;
 
-		if config.p2p_config.seeding_type != p2p::Seeding::Programmatic {
+		// In upstream relay mode and in safe mode we neither connect to nor
+		// accept peers.
+		if upstream.is_none()
+			&& !config.safe_mode
+			&& config.p2p_config.seeding_type != p2p::Seeding::Programmatic
+		{
 			let seeder = match config.p2p_config.seeding_type {
 				p2p::Seeding::None => {
 					warn!("No seed configured, will stay solo until connected to");
@@ -250,7 +370,8 @@ impl Server {
 				seeder,
 				preferred_peers,
 				stop_state.clone(),
-			)?);
+				&executor,
+			));
 		}
 
 		// Defaults to None (optional) in config file.
@@ -258,25 +379,72 @@ impl Server {
 		let skip_sync_wait = config.skip_sync_wait.unwrap_or(false);
 		sync_state.update(SyncStatus::AwaitingPeers(!skip_sync_wait));
 
-		let sync_thread = sync::run_sync(
-			sync_state.clone(),
-			p2p_server.peers.clone(),
-			shared_chain.clone(),
-			stop_state.clone(),
-		)?;
+		let mut orphan_thread = None;
 
-		let p2p_inner = p2p_server.clone();
-		let _ = thread::Builder::new()
-			.name("p2p-server".to_string())
-			.spawn(move || {
-				if let Err(e) = p2p_inner.listen() {
-					error!("P2P server failed with erorr: {:?}", e);
-				}
-			})?;
+		// Peers are only expected when syncing over p2p.
+		let expect_peers = upstream.is_none() && !config.safe_mode;
+
+		let sync_thread = match upstream {
+			_ if config.safe_mode => {
+				sync_state.update(SyncStatus::NoSync);
+				None
+			}
+			Some(upstream) => {
+				warn!("Upstream relay mode, following {}", upstream.url());
+				Some(upstream::follow_upstream(
+					upstream,
+					sync_state.clone(),
+					shared_chain.clone(),
+					stop_state.clone(),
+					&executor,
+				))
+			}
+			None => {
+				let sync_thread = sync::run_sync(
+					sync_state.clone(),
+					p2p_server.peers.clone(),
+					shared_chain.clone(),
+					stop_state.clone(),
+					config.sync_watchdog_secs,
+					&executor,
+				);
+				orphan_thread = Some(orphan_monitor::monitor_orphans(
+					net_adapter.clone(),
+					stop_state.clone(),
+					&executor,
+				));
+
+				// Blocks on accept until the process exits, on a thread of its
+				// own rather than tying up one of the executor.
+				let p2p_inner = p2p_server.clone();
+				let _ = thread::Builder::new()
+					.name("p2p-server".to_string())
+					.spawn(move || {
+						if let Err(e) = p2p_inner.listen() {
+							error!("P2P server failed with erorr: {:?}", e);
+						}
+					})?;
+				Some(sync_thread)
+			}
+		};
 
 		info!("Starting rest apis at: {}", &config.api_http_addr);
 		let api_secret = get_first_line(config.api_secret_path.clone());
 		let foreign_api_secret = get_first_line(config.foreign_api_secret_path.clone());
+
+		let faucet = if config.faucet_config.enable_faucet {
+			let wallet_secret =
+				get_first_line(config.faucet_config.wallet_owner_secret_path.clone());
+			match api::Faucet::new(config.faucet_config.clone(), wallet_secret) {
+				Ok(faucet) => Some(Arc::new(faucet)),
+				Err(e) => {
+					warn!("Faucet not started: {}", e);
+					None
+				}
+			}
+		} else {
+			None
+		};
 		let tls_conf = match config.tls_certificate_file.clone() {
 			None => None,
 			Some(file) => {
@@ -291,8 +459,12 @@ impl Server {
 			}
 		};
 
-		// TODO fix API shutdown and join this thread
-		api::node_apis(
+		let request_limits = api::RequestLimits {
+			max_per_ip_per_sec: config.api_max_requests_per_ip.filter(|max| *max > 0),
+			max_in_flight: config.api_max_in_flight_requests.filter(|max| *max > 0),
+			worker_threads: Some(config.api_worker_threads).filter(|n| *n > 0),
+		};
+		let (api_server, api_thread) = api::node_apis(
 			&config.api_http_addr,
 			shared_chain.clone(),
 			tx_pool.clone(),
@@ -300,17 +472,114 @@ impl Server {
 			sync_state.clone(),
 			api_secret.clone(),
 			foreign_api_secret.clone(),
+			api::RouteAuthConfig {
+				open_reads: config.api_open_reads,
+				open_routes: config.api_open_routes.clone(),
+				protected_routes: config.api_protected_routes.clone(),
+			},
+			request_limits.clone(),
+			config.api_cors_origins.clone(),
 			tls_conf.clone(),
+			config.public_node,
+			Some(config.api_cache_ttl_secs)
+				.filter(|ttl| *ttl > 0)
+				.map(Duration::from_secs),
+			Some(executor.handle()),
+			block_events,
+			pool_events,
+			node_events.clone(),
+			active_alerts,
+			config.api_graphql,
+			config.owner_api_scan_outputs,
+			faucet,
 		)?;
 
+		#[cfg(feature = "grpc")]
+		{
+			if let Some(ref addr) = config.grpc_addr {
+				let grpc_addr = addr
+					.parse()
+					.map_err(|_| Error::ArgumentError(format!("invalid grpc_addr {}", addr)))?;
+				api::start_grpc(
+					grpc_addr,
+					Arc::downgrade(&shared_chain),
+					Arc::downgrade(&tx_pool),
+					Arc::downgrade(&p2p_server.peers),
+					Arc::downgrade(&sync_state),
+					api_secret.clone(),
+					&request_limits,
+					config.public_node,
+					api_server.stopping(),
+					&executor.handle(),
+				);
+			}
+		}
+		#[cfg(not(feature = "grpc"))]
+		{
+			if config.grpc_addr.is_some() {
+				warn!("grpc_addr is set but the node was built without the grpc feature");
+			}
+		}
+
 		info!("Starting dandelion monitor: {}", &config.api_http_addr);
 		let dandelion_thread = dandelion_monitor::monitor_transactions(
 			config.dandelion_config.clone(),
 			tx_pool.clone(),
 			pool_net_adapter.clone(),
 			verifier_cache.clone(),
+			sync_state.events(),
+			clock,
 			stop_state.clone(),
-		)?;
+			&executor,
+		);
+
+		let stats_thread = stats_recorder::record_stats(
+			shared_chain.clone(),
+			tx_pool.clone(),
+			p2p_server.peers.clone(),
+			sync_state.clone(),
+			stop_state.clone(),
+			&executor,
+		);
+
+		let event_thread = event_monitor::monitor_sync_status(
+			sync_state.clone(),
+			node_events,
+			stop_state.clone(),
+			&executor,
+		);
+
+		let alert_thread = alert_monitor::monitor_alerts(
+			&config,
+			expect_peers,
+			alerter,
+			shared_chain.clone(),
+			sync_state.clone(),
+			p2p_server.peers.clone(),
+			stop_state.clone(),
+			&executor,
+		);
+
+		let reclaimer_thread = if config.idle_reclaim_secs > 0 {
+			Some(reclaimer::reclaim_when_idle(
+				Duration::from_secs(config.idle_reclaim_secs),
+				shared_chain.clone(),
+				verifier_cache.clone(),
+				p2p_server.peers.clone(),
+				sync_state.clone(),
+				stop_state.clone(),
+				&executor,
+			))
+		} else {
+			None
+		};
+
+		let tx_validation_threads = tx_intake::start_tx_validation(
+			net_adapter.tx_intake(),
+			config.pool_config.validation_threads,
+			stop_state.clone(),
+			&executor,
+		);
 
 		warn!("Kepler server started.");
 		Ok(Server {
@@ -320,14 +589,24 @@ impl Server {
 			tx_pool,
 			verifier_cache,
 			sync_state,
+			tx_policy,
 			state_info: ServerStateInfo {
 				..Default::default()
 			},
 			stop_state,
 			lock_file,
+			executor,
 			connect_thread,
 			sync_thread,
+			orphan_thread,
 			dandelion_thread,
+			stats_thread,
+			event_thread,
+			alert_thread,
+			reclaimer_thread,
+			tx_validation_threads,
+			api_server,
+			api_thread,
 		})
 	}
 
@@ -350,7 +629,7 @@ impl Server {
 		self.p2p.peers.peer_count()
 	}
 
-	/// Start a minimal "stratum" mining service on a separate thread
+	/// Start a minimal "stratum" mining service on the executor
 	pub fn start_stratum_server(&self, config: StratumServerConfig) {
 		let edge_bits = global::min_edge_bits();
 		let proof_size = global::proofsize();
@@ -362,15 +641,15 @@ impl Server {
 			self.tx_pool.clone(),
 			self.verifier_cache.clone(),
 			self.state_info.stratum_stats.clone(),
+			self.executor.clone(),
+			self.tx_policy.clone(),
 		);
-		let _ = thread::Builder::new()
-			.name("stratum_server".to_string())
-			.spawn(move || {
-				stratum_server.run_loop(edge_bits as u32, proof_size, sync_state);
-			});
+		self.executor.spawn_blocking("stratum_server", move || {
+			stratum_server.run_loop(edge_bits as u32, proof_size, sync_state);
+		});
 	}
 
-	/// Start mining for blocks internally on a separate thread. Relies on
+	/// Start mining for blocks internally on the executor. Relies on
 	/// internal miner, and should only be used for automated testing. Burns
 	/// reward if wallet_listener_url is 'None'
 	pub fn start_test_miner(
@@ -403,9 +682,8 @@ impl Server {
 			sync_state,
 		);
 		miner.set_debug_output_id(format!("Port {}", self.config.p2p_config.port));
-		let _ = thread::Builder::new()
-			.name("test_miner".to_string())
-			.spawn(move || miner.run_loop(wallet_listener_url));
+		self.executor
+			.spawn_blocking("test_miner", move || miner.run_loop(wallet_listener_url));
 	}
 
 	/// The chain head
@@ -535,14 +813,21 @@ impl Server {
 			peer_stats: peer_stats,
 			diff_stats: diff_stats,
 			tx_stats: tx_stats,
+			tip_races: self.chain.tip_races() as u64,
+			task_stats: self.executor.task_stats(),
 		})
 	}
 
 	/// Stop the server.
-	pub fn stop(self) {
+	pub fn stop(mut self) {
+		// Stop accepting API requests first, in-flight requests are drained
+		// while we shut down everything else.
+		self.api_server.stop();
+
 		{
-			self.sync_state.update(SyncStatus::Shutdown);
+			// Stop first so threads woken up by the shutdown status see it.
 			self.stop_state.stop();
+			self.sync_state.update(SyncStatus::Shutdown);
 
 			if let Some(connect_thread) = self.connect_thread {
 				match connect_thread.join() {
@@ -553,19 +838,63 @@ impl Server {
 				info!("No active connect_and_monitor thread")
 			}
 
-			match self.sync_thread.join() {
-				Err(e) => error!("failed to join to sync thread: {:?}", e),
-				Ok(_) => info!("sync thread stopped"),
+			if let Some(sync_thread) = self.sync_thread {
+				match sync_thread.join() {
+					Err(e) => error!("failed to join to sync thread: {:?}", e),
+					Ok(_) => info!("sync thread stopped"),
+				}
+			}
+
+			if let Some(orphan_thread) = self.orphan_thread {
+				match orphan_thread.join() {
+					Err(e) => error!("failed to join to orphan_monitor thread: {:?}", e),
+					Ok(_) => info!("orphan_monitor thread stopped"),
+				}
 			}
 
 			match self.dandelion_thread.join() {
 				Err(e) => error!("failed to join to dandelion_monitor thread: {:?}", e),
 				Ok(_) => info!("dandelion_monitor thread stopped"),
 			}
+
+			match self.stats_thread.join() {
+				Err(e) => error!("failed to join to stats_recorder thread: {:?}", e),
+				Ok(_) => info!("stats_recorder thread stopped"),
+			}
+
+			match self.event_thread.join() {
+				Err(e) => error!("failed to join to event_monitor thread: {:?}", e),
+				Ok(_) => info!("event_monitor thread stopped"),
+			}
+
+			match self.alert_thread.join() {
+				Err(e) => error!("failed to join to alert_monitor thread: {:?}", e),
+				Ok(_) => info!("alert_monitor thread stopped"),
+			}
+
+			if let Some(reclaimer_thread) = self.reclaimer_thread {
+				match reclaimer_thread.join() {
+					Err(e) => error!("failed to join to reclaimer thread: {:?}", e),
+					Ok(_) => info!("reclaimer thread stopped"),
+				}
+			}
+
+			for thread in self.tx_validation_threads {
+				if let Err(e) = thread.join() {
+					error!("failed to join to tx_validation thread: {:?}", e);
+				}
+			}
+			info!("tx_validation threads stopped");
 		}
 		// this call is blocking and makes sure all peers stop, however
 		// we can't be sure that we stopped a listener blocked on accept, so we don't join the p2p thread
 		self.p2p.stop();
+
+		match self.api_thread.join() {
+			Err(e) => error!("failed to join to API server thread: {:?}", e),
+			Ok(_) => info!("API server thread stopped"),
+		}
+
 		let _ = self.lock_file.unlock();
 		warn!("Shutdown complete");
 	}
@@ -589,3 +918,13 @@ impl Server {
 		info!("stop_test_miner - stop",);
 	}
 }
+
+/// Genesis block of the provided chain type.
+pub(crate) fn genesis_block(chain_type: &global::ChainTypes) -> Block {
+	match chain_type {
+		global::ChainTypes::AutomatedTesting => pow::mine_genesis_block().unwrap(),
+		global::ChainTypes::UserTesting => pow::mine_genesis_block().unwrap(),
+		global::ChainTypes::Floonet => genesis::genesis_floo(),
+		global::ChainTypes::Mainnet => genesis::genesis_main(),
+	}
+}
@@ -0,0 +1,147 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Records hourly snapshots of the node statistics in the chain db, so their
+//! trends can be charted without an external monitoring stack.
+
+use chrono::prelude::Utc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::chain::{self, StatsSnapshot, SyncState};
+use crate::common::executor::{Executor, TaskHandle};
+use crate::p2p;
+use crate::pool::TransactionPool;
+use crate::util::{RwLock, StopState, Watch};
+
+/// Bandwidth is sampled every minute, peers only track the last minute.
+const SAMPLE_INTERVAL_SECS: u64 = 60;
+
+/// A snapshot is recorded every hour.
+const SNAPSHOT_INTERVAL_SECS: i64 = 3600;
+
+/// Snapshots are kept for 90 days.
+const SNAPSHOT_RETENTION_SECS: i64 = 90 * 24 * 3600;
+
+// Statistics accumulated over the hour being recorded.
+struct HourStats {
+	start: i64,
+	// Height and timestamp of the chain head at the start of the hour and of
+	// the latest one, followed through the chain events.
+	start_head: Option<(u64, i64)>,
+	head: Option<(u64, i64)>,
+	sent_bytes: u64,
+	received_bytes: u64,
+}
+
+impl HourStats {
+	fn new(now: i64, chain: &chain::Chain) -> HourStats {
+		let head = chain
+			.head_header()
+			.ok()
+			.map(|h| (h.height, h.timestamp.timestamp()));
+		HourStats {
+			start: now - now % SNAPSHOT_INTERVAL_SECS,
+			start_head: head,
+			head,
+			sent_bytes: 0,
+			received_bytes: 0,
+		}
+	}
+
+	fn head_updated(&mut self, chain: &chain::Chain) {
+		if let Ok(head) = chain.head_header() {
+			self.head = Some((head.height, head.timestamp.timestamp()));
+		}
+	}
+
+	fn sample(&mut self, peers: &p2p::Peers) {
+		for peer in peers.connected_peers() {
+			self.sent_bytes += peer.last_min_sent_bytes().unwrap_or(0);
+			self.received_bytes += peer.last_min_received_bytes().unwrap_or(0);
+		}
+	}
+
+	fn snapshot(&self, tx_pool: &RwLock<TransactionPool>, peers: &p2p::Peers) -> StatsSnapshot {
+		let (height, blocks, block_interval) = match (self.start_head, self.head) {
+			(Some((start_height, start_time)), Some((height, time))) => {
+				let blocks = height.saturating_sub(start_height);
+				let block_interval = if blocks > 0 {
+					(time - start_time).max(0) as u64 / blocks
+				} else {
+					0
+				};
+				(height, blocks, block_interval)
+			}
+			(_, head) => (head.map_or(0, |h| h.0), 0, 0),
+		};
+		let max_peer_height = peers
+			.connected_peers()
+			.iter()
+			.map(|p| p.info.height())
+			.max()
+			.unwrap_or(0);
+		StatsSnapshot {
+			timestamp: self.start,
+			peer_count: peers.peer_count(),
+			mempool_size: tx_pool.read().total_size() as u32,
+			sync_lag: max_peer_height.saturating_sub(height),
+			sent_bytes: self.sent_bytes,
+			received_bytes: self.received_bytes,
+			height,
+			blocks,
+			block_interval,
+		}
+	}
+}
+
+/// Follows the chain head through the chain events, samples the bandwidth
+/// every minute and saves a snapshot of the node statistics at the end of
+/// every hour.
+pub fn record_stats(
+	chain: Arc<chain::Chain>,
+	tx_pool: Arc<RwLock<TransactionPool>>,
+	peers: Arc<p2p::Peers>,
+	sync_state: Arc<SyncState>,
+	stop_state: Arc<StopState>,
+	executor: &Executor,
+) -> TaskHandle {
+	let mut hour = HourStats::new(Utc::now().timestamp(), &chain);
+	executor.spawn_blocking("stats_recorder", move || {
+		let mut events = Watch::subscribe(&sync_state.events());
+		let mut last_sample = Instant::now();
+		while !stop_state.is_stopped() {
+			// The events only hold the latest value, a tip update may be
+			// followed by a sync status change before we get to see it.
+			if events.recv_timeout(Duration::from_secs(1)).is_some() {
+				hour.head_updated(&chain);
+			}
+			if last_sample.elapsed() < Duration::from_secs(SAMPLE_INTERVAL_SECS) {
+				continue;
+			}
+			last_sample = Instant::now();
+			hour.sample(&peers);
+
+			let now = Utc::now().timestamp();
+			if now < hour.start + SNAPSHOT_INTERVAL_SECS {
+				continue;
+			}
+			let snapshot = hour.snapshot(&tx_pool, &peers);
+			if let Err(e) = chain.save_stats_snapshot(&snapshot, now - SNAPSHOT_RETENTION_SECS) {
+				warn!("stats_recorder: failed to save stats snapshot: {}", e);
+			}
+			hour = HourStats::new(now, &chain);
+		}
+	})
+}
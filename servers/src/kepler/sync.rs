@@ -16,7 +16,9 @@
 
 mod body_sync;
 mod header_sync;
+mod scoring;
 mod state_sync;
 mod syncer;
+mod watchdog;
 
 pub use self::syncer::run_sync;
@@ -19,7 +19,10 @@ use std::sync::Arc;
 
 use crate::chain::{self, SyncState, SyncStatus};
 use crate::core::core::hash::Hash;
-use crate::p2p;
+use crate::core::core::{pmmr, BlockHeader, ChunkPart};
+use crate::core::global;
+use crate::kepler::sync::scoring::SyncPeers;
+use crate::p2p::{self, Capabilities, SyncTransfer};
 
 pub struct BodySync {
 	chain: Arc<chain::Chain>,
@@ -30,6 +33,9 @@ pub struct BodySync {
 
 	receive_timeout: DateTime<Utc>,
 	prev_blocks_received: u64,
+
+	// Peer the lowest missing block was last requested from.
+	next_block_peer: Option<Arc<p2p::Peer>>,
 }
 
 impl BodySync {
@@ -45,9 +51,18 @@ impl BodySync {
 			blocks_requested: 0,
 			receive_timeout: Utc::now(),
 			prev_blocks_received: 0,
+			next_block_peer: None,
 		}
 	}
 
+	/// Forget the pending block requests so new ones go out right away,
+	/// returning the peer the lowest missing block was requested from.
+	pub fn reset(&mut self) -> Option<Arc<p2p::Peer>> {
+		self.blocks_requested = 0;
+		self.receive_timeout = Utc::now();
+		self.next_block_peer.take()
+	}
+
 	/// Check whether a body sync is needed and run it if so.
 	/// Return true if txhashset download is needed (when requested block is under the horizon).
 	pub fn check_run(
@@ -99,14 +114,17 @@ impl BodySync {
 
 		hashes.reverse();
 
-		let peers = self.peers.more_work_peers()?;
+		// Requests go to the peers in proportion to the throughput they served
+		// the earlier ones at, the too slow ones left out.
+		let mut peers = SyncPeers::new(self.peers.more_work_peers()?, SyncTransfer::Blocks);
 
 		// if we have 5 peers to sync from then ask for 50 blocks total (peer_count *
 		// 10) max will be 80 if all 8 peers are advertising more work
 		// also if the chain is already saturated with orphans, throttle
+		let orphans = self.chain.orphan_stats();
 		let block_count = cmp::min(
 			cmp::min(100, peers.len() * 10),
-			chain::MAX_ORPHAN_SIZE.saturating_sub(self.chain.orphans_len()) + 1,
+			orphans.max_orphans.saturating_sub(orphans.len) + 1,
 		);
 
 		let hashes_to_get = hashes
@@ -134,22 +152,66 @@ impl BodySync {
 			// reinitialize download tracking state
 			self.blocks_requested = 0;
 			self.receive_timeout = Utc::now() + Duration::seconds(6);
+			self.next_block_peer = None;
+
+			// Blocks beyond the horizon are asked from the archive peers
+			// first, the others likely pruned them.
+			let horizon_height = header_head
+				.height
+				.saturating_sub(global::cut_through_horizon() as u64);
 
-			let mut peers_iter = peers.iter().cycle();
 			for hash in hashes_to_get.clone() {
-				if let Some(peer) = peers_iter.next() {
-					if let Err(e) = peer.send_block_request(*hash, chain::Options::SYNC) {
+				// skip the pruned peers that don't have the block anymore
+				let header = self.chain.get_block_header(hash)?;
+				let height = header.height;
+				let chunked = self.spans_chunks(&header)?;
+				let serves_chunks =
+					|peer: &p2p::Peer| peer.info.capabilities.contains(Capabilities::BLOCK_CHUNKS);
+				let preferred = |peer: &p2p::Peer| {
+					peer.info.has_block_body(height)
+						&& (height >= horizon_height
+							|| peer.info.capabilities.contains(Capabilities::ARCHIVE))
+				};
+				let peer = if chunked {
+					peers.next(|peer| preferred(peer) && serves_chunks(peer))
+				} else {
+					None
+				};
+				let peer = peer.or_else(|| peers.next(|peer| preferred(peer)));
+				let peer = peer.or_else(|| peers.next(|peer| peer.info.has_block_body(height)));
+				if let Some(peer) = peer {
+					let res = if chunked && serves_chunks(&peer) {
+						peer.send_block_manifest_request(*hash)
+					} else {
+						peer.send_block_request(*hash, chain::Options::SYNC)
+					};
+					if let Err(e) = res {
 						debug!("Skipped request to {}: {:?}", peer.info.addr, e);
 						peer.stop();
 					} else {
+						if self.next_block_peer.is_none() {
+							self.next_block_peer = Some(peer.clone());
+						}
 						self.blocks_requested += 1;
 					}
+				} else {
+					debug!("block_sync: no peer has block {} at {}", hash, height);
 				}
 			}
 		}
 		return Ok(false);
 	}
 
+	// Whether the block has more outputs than fit in a chunk, to download it
+	// in chunks from the peers serving them. A timeout then only has the
+	// chunks still missing asked for again.
+	fn spans_chunks(&self, header: &BlockHeader) -> Result<bool, chain::Error> {
+		let prev = self.chain.get_previous_header(header)?;
+		let outputs = pmmr::n_leaves(header.output_mmr_size)
+			.saturating_sub(pmmr::n_leaves(prev.output_mmr_size));
+		Ok(outputs > ChunkPart::Outputs.chunk_size() as u64)
+	}
+
 	// Should we run block body sync and ask for more full blocks?
 	fn body_sync_due(&mut self) -> Result<bool, chain::Error> {
 		let blocks_received = self.blocks_received()?;
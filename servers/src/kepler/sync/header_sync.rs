@@ -19,7 +19,8 @@ use std::sync::Arc;
 use crate::chain::{self, SyncState, SyncStatus};
 use crate::common::types::Error;
 use crate::core::core::hash::{Hash, Hashed};
-use crate::p2p::{self, types::ReasonForBan, Peer};
+use crate::kepler::sync::scoring::SyncPeers;
+use crate::p2p::{self, types::ReasonForBan, Peer, SyncTransfer};
 
 pub struct HeaderSync {
 	sync_state: Arc<SyncState>,
@@ -101,6 +102,15 @@ impl HeaderSync {
 		Ok(false)
 	}
 
+	/// Drop the current round of header requests so the next one goes out
+	/// right away, returning the peer it was syncing from.
+	pub fn reset(&mut self) -> Option<Arc<Peer>> {
+		self.prev_header_sync = (Utc::now(), 0, 0);
+		self.stalling_ts = None;
+		self.history_locator.retain(|&x| x.0 == 0);
+		self.syncing_peer.take()
+	}
+
 	fn header_sync_due(&mut self, header_head: &chain::Tip) -> bool {
 		let now = Utc::now();
 		let (timeout, latest_height, prev_height) = self.prev_header_sync;
@@ -180,7 +190,10 @@ impl HeaderSync {
 		if let Ok(header_head) = self.chain.header_head() {
 			let difficulty = header_head.total_difficulty;
 
-			if let Some(peer) = self.peers.most_work_peer() {
+			// Of the peers with the most work, the fastest to serve headers so
+			// far, checked again on every request.
+			let peers = SyncPeers::new(self.peers.most_work_peers(), SyncTransfer::Headers);
+			if let Some(peer) = peers.fastest() {
 				if peer.info.total_difficulty() > difficulty {
 					return self.request_headers(peer);
 				}
@@ -0,0 +1,203 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Choice of the peers to sync from, by the throughput they served the
+//! earlier requests at.
+
+use std::sync::Arc;
+
+use crate::p2p::{Peer, SyncTransfer};
+
+/// Peers serving less than this share of the fastest peer throughput are
+/// left out while faster ones are connected.
+const MIN_SHARE: f64 = 0.1;
+
+/// Score of a peer with no measure yet, when no peer has one.
+const DEFAULT_SCORE: f64 = 1.0;
+
+/// Throughput the peer serves the transfer at, or failing a measure of it,
+/// the throughput of its other transfers.
+fn measured(peer: &Peer, transfer: SyncTransfer) -> Option<f64> {
+	let fallbacks = [
+		SyncTransfer::Blocks,
+		SyncTransfer::Headers,
+		SyncTransfer::TxHashSet,
+	];
+	peer.info.sync_bytes_per_sec(transfer).or_else(|| {
+		max(fallbacks
+			.iter()
+			.filter(|t| **t != transfer)
+			.filter_map(|t| peer.info.sync_bytes_per_sec(*t)))
+	})
+}
+
+fn max<I: Iterator<Item = f64>>(rates: I) -> Option<f64> {
+	rates.fold(None, |max, rate| {
+		Some(max.map_or(rate, |m: f64| m.max(rate)))
+	})
+}
+
+/// Scores of the measured peers, the unmeasured ones given the best score so
+/// that they get tried, and 0 for the peers too slow to sync from.
+fn scores(measures: &[Option<f64>]) -> Vec<f64> {
+	let best = match max(measures.iter().filter_map(|m| *m)) {
+		Some(best) if best > 0.0 => best,
+		_ => return vec![DEFAULT_SCORE; measures.len()],
+	};
+	measures
+		.iter()
+		.map(|m| match m {
+			Some(rate) if *rate < best * MIN_SHARE => 0.0,
+			Some(rate) => *rate,
+			None => best,
+		})
+		.collect()
+}
+
+/// Peers to sync a transfer from, with their scores.
+pub struct SyncPeers {
+	peers: Vec<Arc<Peer>>,
+	picker: Picker,
+}
+
+impl SyncPeers {
+	pub fn new(peers: Vec<Arc<Peer>>, transfer: SyncTransfer) -> SyncPeers {
+		let measures = peers
+			.iter()
+			.map(|p| measured(p, transfer))
+			.collect::<Vec<_>>();
+		SyncPeers {
+			peers,
+			picker: Picker::new(scores(&measures)),
+		}
+	}
+
+	/// Number of peers fast enough to sync from.
+	pub fn len(&self) -> usize {
+		self.picker.len()
+	}
+
+	/// The fastest peer, the first of them on ties. Unmeasured peers score
+	/// as the fastest, so they get a chance to show they are.
+	pub fn fastest(&self) -> Option<Arc<Peer>> {
+		self.picker.best().map(|i| self.peers[i].clone())
+	}
+
+	/// The next peer to send a request to, among those `eligible`, each
+	/// picked in proportion to its score.
+	pub fn next<F>(&mut self, eligible: F) -> Option<Arc<Peer>>
+	where
+		F: Fn(&Peer) -> bool,
+	{
+		let peers = &self.peers;
+		self.picker
+			.next(|i| eligible(&peers[i]))
+			.map(|i| peers[i].clone())
+	}
+}
+
+/// Smooth weighted round robin over the scores, spreading the picks of each
+/// index evenly instead of in bursts.
+struct Picker {
+	scores: Vec<f64>,
+	current: Vec<f64>,
+}
+
+impl Picker {
+	fn new(scores: Vec<f64>) -> Picker {
+		let current = vec![0.0; scores.len()];
+		Picker { scores, current }
+	}
+
+	fn len(&self) -> usize {
+		self.scores.iter().filter(|s| **s > 0.0).count()
+	}
+
+	fn best(&self) -> Option<usize> {
+		let mut best: Option<usize> = None;
+		for (i, score) in self.scores.iter().enumerate() {
+			if *score > 0.0 && best.map_or(true, |b| *score > self.scores[b]) {
+				best = Some(i);
+			}
+		}
+		best
+	}
+
+	fn next<F>(&mut self, eligible: F) -> Option<usize>
+	where
+		F: Fn(usize) -> bool,
+	{
+		let indices = (0..self.scores.len())
+			.filter(|i| self.scores[*i] > 0.0 && eligible(*i))
+			.collect::<Vec<_>>();
+		let total: f64 = indices.iter().map(|i| self.scores[*i]).sum();
+		let mut picked: Option<usize> = None;
+		for i in indices {
+			self.current[i] += self.scores[i];
+			if picked.map_or(true, |p| self.current[i] > self.current[p]) {
+				picked = Some(i);
+			}
+		}
+		if let Some(p) = picked {
+			self.current[p] -= total;
+		}
+		picked
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn slow_peers_left_out() {
+		assert_eq!(scores(&[None, None]), vec![1.0, 1.0]);
+		assert_eq!(scores(&[Some(0.0), None]), vec![1.0, 1.0]);
+		assert_eq!(
+			scores(&[Some(50_000.0), Some(1_000_000.0), None, Some(100_000.0)]),
+			vec![0.0, 1_000_000.0, 1_000_000.0, 100_000.0]
+		);
+	}
+
+	#[test]
+	fn fastest_peer() {
+		assert_eq!(Picker::new(vec![]).best(), None);
+		assert_eq!(Picker::new(vec![0.0]).best(), None);
+		assert_eq!(Picker::new(vec![1.0, 3.0, 3.0, 2.0]).best(), Some(1));
+	}
+
+	#[test]
+	fn weighted_picks() {
+		let mut picker = Picker::new(vec![3.0, 1.0, 0.0]);
+		assert_eq!(picker.len(), 2);
+		let picks = (0..8).map(|_| picker.next(|_| true)).collect::<Vec<_>>();
+		assert_eq!(
+			picks,
+			vec![
+				Some(0),
+				Some(0),
+				Some(1),
+				Some(0),
+				Some(0),
+				Some(0),
+				Some(1),
+				Some(0)
+			]
+		);
+
+		// Only the eligible peers are picked, none if no peer is.
+		assert_eq!(picker.next(|i| i != 0), Some(1));
+		assert_eq!(picker.next(|i| i == 2), None);
+	}
+}
@@ -14,19 +14,35 @@
 
 use chrono::prelude::{DateTime, Utc};
 use chrono::Duration;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
+use crate::chain::txhashset::{self, SegmentId};
 use crate::chain::{self, SyncState, SyncStatus};
 use crate::core::core::hash::Hashed;
+use crate::core::core::BlockHeader;
 use crate::core::global;
-use crate::p2p::{self, Peer};
+use crate::kepler::sync::scoring::SyncPeers;
+use crate::p2p::{self, Capabilities, Peer, PeerAddr, SyncTransfer};
+
+/// Segments requested from a single peer at once.
+const MAX_SEGMENTS_PER_PEER: usize = 8;
+
+/// Segments requested from all the peers at once.
+const MAX_SEGMENTS_IN_FLIGHT: usize = 64;
+
+/// Seconds before a segment not received is requested again, from another
+/// peer if there's one.
+const SEGMENT_REQUEST_TIMEOUT_SECS: i64 = 30;
 
 /// Fast sync has 3 "states":
 /// * syncing headers
 /// * once all headers are sync'd, requesting the txhashset state
 /// * once we have the state, get blocks after that
 ///
-/// The StateSync struct implements and monitors the middle step.
+/// The StateSync struct implements and monitors the middle step. The state
+/// is downloaded in segments from all the peers serving them, or as a zip
+/// from a single peer when none does or the segments failed us before.
 pub struct StateSync {
 	sync_state: Arc<SyncState>,
 	peers: Arc<p2p::Peers>,
@@ -34,6 +50,13 @@ pub struct StateSync {
 
 	prev_state_sync: Option<DateTime<Utc>>,
 	state_sync_peer: Option<Arc<Peer>>,
+
+	// Whether the state being downloaded comes in segments.
+	segmented: bool,
+	// Set once a download in segments failed, the next ones use the zip.
+	segments_failed: bool,
+	// Segments requested, with the peer and time of the request.
+	segments_in_flight: HashMap<SegmentId, (Arc<Peer>, DateTime<Utc>)>,
 }
 
 impl StateSync {
@@ -48,6 +71,9 @@ impl StateSync {
 			chain,
 			prev_state_sync: None,
 			state_sync_peer: None,
+			segmented: false,
+			segments_failed: false,
+			segments_in_flight: HashMap::new(),
 		}
 	}
 
@@ -74,6 +100,19 @@ impl StateSync {
 				error!("state_sync: error = {:?}. restart fast sync", sync_error);
 				sync_need_restart = true;
 			}
+			if sync_need_restart {
+				if self.segmented {
+					warn!("state_sync: download in segments failed, falling back to the zip");
+					self.segments_failed = true;
+				}
+				let bad_txhashsets = self.chain.bad_txhashsets();
+				if !bad_txhashsets.is_empty() {
+					warn!(
+						"state_sync: rejected txhashsets so far: {:?}",
+						bad_txhashsets
+					);
+				}
+			}
 			drop(clone);
 		}
 
@@ -115,8 +154,10 @@ impl StateSync {
 		if sync_need_restart || header_head.height == highest_height {
 			let (go, download_timeout) = self.state_sync_due();
 
+			// A download in segments goes on as long as segments come in, the
+			// sync watchdog restarting it when they stop.
 			if let SyncStatus::TxHashsetDownload { .. } = self.sync_state.status() {
-				if download_timeout {
+				if download_timeout && !self.segmented {
 					error!("state_sync: TxHashsetDownload status timeout in 10 minutes!");
 					self.sync_state.set_sync_error(
 						chain::ErrorKind::SyncError(format!("{:?}", p2p::Error::Timeout)).into(),
@@ -128,7 +169,7 @@ impl StateSync {
 				self.state_sync_peer = None;
 				match self.request_state(&header_head) {
 					Ok(peer) => {
-						self.state_sync_peer = Some(peer);
+						self.state_sync_peer = peer;
 					}
 					Err(e) => self
 						.sync_state
@@ -157,55 +198,231 @@ impl StateSync {
 				});
 			}
 		}
+
+		if self.segmented {
+			if let SyncStatus::TxHashsetDownload { .. } = self.sync_state.status() {
+				self.sync_segments();
+			}
+		}
 		true
 	}
 
-	fn request_state(&self, header_head: &chain::Tip) -> Result<Arc<Peer>, p2p::Error> {
+	/// Give up on the current txhashset request, returning the peer it was
+	/// sent to. A download in segments given up on is done as a zip next.
+	pub fn reset(&mut self) -> Option<Arc<Peer>> {
+		if self.segmented {
+			self.segments_failed = true;
+		}
+		let peer = self.state_sync_peer.take();
+		self.state_sync_reset();
+		peer
+	}
+
+	// Requests the state at the archive header below the state sync threshold,
+	// in segments if possible. Returns the peer the zip was requested from.
+	fn request_state(&mut self, header_head: &chain::Tip) -> Result<Option<Arc<Peer>>, p2p::Error> {
+		let txhashset_head = self.txhashset_head(header_head)?;
+		let bhash = txhashset_head.hash();
+		debug!(
+			"state_sync: before txhashset request, header head: {} / {}, txhashset_head: {} / {}",
+			header_head.height, header_head.last_block_h, txhashset_head.height, bhash
+		);
+
+		if !self.segments_failed
+			&& txhashset::segments_supported(&txhashset_head)
+			&& !self.segment_peers().is_empty()
+		{
+			self.chain.init_desegmenter(&txhashset_head).map_err(|e| {
+				error!("state_sync: failed to init the txhashset segments: {:?}", e);
+				p2p::Error::Internal
+			})?;
+			info!(
+				"state_sync: downloading txhashset at {} / {} in segments",
+				txhashset_head.height, bhash
+			);
+			self.segmented = true;
+			return Ok(None);
+		}
+		self.chain.reset_desegmenter();
+
+		// Only peers advertising txhashset history can provide one, not the ones
+		// that sent a bad txhashset before, even if exempted from bans. Of those,
+		// the fastest to serve sync requests so far.
+		let peers = self
+			.peers
+			.most_work_peers()
+			.into_iter()
+			.filter(|p| {
+				let addr = p.info.addr.to_string();
+				p.info.capabilities.contains(Capabilities::TXHASHSET_HIST)
+					&& !self.chain.is_bad_txhashset_provider(&addr)
+			})
+			.collect();
+		let peer = SyncPeers::new(peers, SyncTransfer::TxHashSet).fastest();
+
+		if let Some(peer) = peer {
+			if let Err(e) = peer.send_txhashset_request(txhashset_head.height, bhash) {
+				error!("state_sync: send_txhashset_request err! {:?}", e);
+				return Err(e);
+			}
+			return Ok(Some(peer));
+		}
+		Err(p2p::Error::PeerException)
+	}
+
+	// The archive header the state is requested at, the last one below the
+	// state sync threshold.
+	fn txhashset_head(&self, header_head: &chain::Tip) -> Result<BlockHeader, p2p::Error> {
 		let threshold = global::state_sync_threshold() as u64;
 		let archive_interval = global::txhashset_archive_interval();
 		let mut txhashset_height = header_head.height.saturating_sub(threshold);
 		txhashset_height = txhashset_height.saturating_sub(txhashset_height % archive_interval);
 
-		if let Some(peer) = self.peers.most_work_peer() {
-			// ask for txhashset at state_sync_threshold
-			let mut txhashset_head = self
+		let mut txhashset_head = self
+			.chain
+			.get_block_header(&header_head.prev_block_h)
+			.map_err(|e| {
+				error!(
+					"chain error during getting a block header {}: {:?}",
+					&header_head.prev_block_h, e
+				);
+				p2p::Error::Internal
+			})?;
+		while txhashset_head.height > txhashset_height {
+			txhashset_head = self
 				.chain
-				.get_block_header(&header_head.prev_block_h)
+				.get_previous_header(&txhashset_head)
 				.map_err(|e| {
 					error!(
-						"chain error during getting a block header {}: {:?}",
-						&header_head.prev_block_h, e
+						"chain error during getting a previous block header {}: {:?}",
+						txhashset_head.hash(),
+						e
 					);
 					p2p::Error::Internal
 				})?;
-			while txhashset_head.height > txhashset_height {
-				txhashset_head = self
-					.chain
-					.get_previous_header(&txhashset_head)
-					.map_err(|e| {
-						error!(
-							"chain error during getting a previous block header {}: {:?}",
-							txhashset_head.hash(),
-							e
-						);
-						p2p::Error::Internal
-					})?;
+		}
+		Ok(txhashset_head)
+	}
+
+	// The most work peers serving txhashset segments.
+	fn segment_peers(&self) -> Vec<Arc<Peer>> {
+		self.peers
+			.most_work_peers()
+			.into_iter()
+			.filter(|p| {
+				p.info
+					.capabilities
+					.contains(Capabilities::TXHASHSET_SEGMENTS)
+			})
+			.collect()
+	}
+
+	// Keeps the segments of the state flowing in, spread over the peers
+	// serving them, and validates the state once they're all in.
+	fn sync_segments(&mut self) {
+		if self.chain.desegmenter_complete() {
+			self.segments_in_flight.clear();
+			match self
+				.chain
+				.txhashset_write_segments(self.sync_state.as_ref())
+			{
+				Ok(false) => info!("state_sync: txhashset rebuilt from segments"),
+				Ok(true) => {
+					error!("state_sync: invalid txhashset rebuilt from segments");
+					self.sync_state.set_sync_error(
+						chain::ErrorKind::TxHashSetErr("bad txhashset segments".to_string()).into(),
+					);
+				}
+				Err(e) => {
+					error!("state_sync: failed to save txhashset segments: {}", e);
+					self.sync_state.set_sync_error(e);
+				}
 			}
-			let bhash = txhashset_head.hash();
-			debug!(
-				"state_sync: before txhashset request, header head: {} / {}, txhashset_head: {} / {}",
-				header_head.height,
-				header_head.last_block_h,
-				txhashset_head.height,
-				bhash
-			);
-			if let Err(e) = peer.send_txhashset_request(txhashset_head.height, bhash) {
-				error!("state_sync: send_txhashset_request err! {:?}", e);
-				return Err(e);
+			return;
+		}
+
+		let header = match self.chain.desegmenter_header() {
+			Some(header) => header,
+			None => return,
+		};
+
+		// Forget the segments received and the ones timed out, the latter to be
+		// requested from another peer.
+		let missing: HashSet<SegmentId> = self
+			.chain
+			.next_desired_segments(usize::MAX, &HashSet::new())
+			.into_iter()
+			.collect();
+		let now = Utc::now();
+		let timeout = Duration::seconds(SEGMENT_REQUEST_TIMEOUT_SECS);
+		let mut timed_out: HashMap<SegmentId, PeerAddr> = HashMap::new();
+		self.segments_in_flight.retain(|id, (peer, time)| {
+			if !missing.contains(id) {
+				return false;
+			}
+			if now - *time > timeout || !peer.is_connected() {
+				timed_out.insert(*id, peer.info.addr);
+				return false;
+			}
+			true
+		});
+
+		let mut peers = SyncPeers::new(self.segment_peers(), SyncTransfer::TxHashSet);
+		let mut per_peer: HashMap<PeerAddr, usize> = HashMap::new();
+		for (peer, _) in self.segments_in_flight.values() {
+			*per_peer.entry(peer.info.addr).or_insert(0) += 1;
+		}
+		let max = MAX_SEGMENTS_IN_FLIGHT
+			.min(peers.len() * MAX_SEGMENTS_PER_PEER)
+			.saturating_sub(self.segments_in_flight.len());
+		let in_flight = self.segments_in_flight.keys().cloned().collect();
+
+		for (segment_type, id) in self.chain.next_desired_segments(max, &in_flight) {
+			let available = |peer: &Peer| {
+				per_peer.get(&peer.info.addr).cloned().unwrap_or(0) < MAX_SEGMENTS_PER_PEER
+			};
+			let prev = timed_out.get(&(segment_type, id));
+			let peer = peers
+				.next(|peer| available(peer) && Some(&peer.info.addr) != prev)
+				.or_else(|| peers.next(available));
+			let peer = match peer {
+				Some(peer) => peer,
+				None => break,
+			};
+			if let Err(e) = peer.send_segment_request(segment_type, header.hash(), id) {
+				debug!(
+					"state_sync: skipped segment request to {}: {:?}",
+					peer.info.addr, e
+				);
+				continue;
+			}
+			*per_peer.entry(peer.info.addr).or_insert(0) += 1;
+			self.segments_in_flight
+				.insert((segment_type, id), (peer, now));
+		}
+
+		// Report the segments received as the download progress.
+		if let Some((received, total)) = self.chain.desegmenter_progress() {
+			if let SyncStatus::TxHashsetDownload {
+				start_time,
+				update_time,
+				downloaded_size,
+				..
+			} = self.sync_state.status()
+			{
+				if received != downloaded_size {
+					self.sync_state
+						.update_txhashset_download(SyncStatus::TxHashsetDownload {
+							start_time,
+							prev_update_time: update_time,
+							update_time: now,
+							prev_downloaded_size: downloaded_size,
+							downloaded_size: received,
+							total_size: total.unwrap_or(0),
+						});
+				}
 			}
-			return Ok(peer.clone());
 		}
-		Err(p2p::Error::PeerException)
 	}
 
 	// For now this is a one-time thing (it can be slow) at initial startup.
@@ -230,5 +447,10 @@ impl StateSync {
 	fn state_sync_reset(&mut self) {
 		self.prev_state_sync = None;
 		self.state_sync_peer = None;
+		if self.segmented {
+			self.segmented = false;
+			self.segments_in_flight.clear();
+			self.chain.reset_desegmenter();
+		}
 	}
 }
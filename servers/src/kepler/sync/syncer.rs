@@ -17,26 +17,28 @@ use std::thread;
 use std::time;
 
 use crate::chain::{self, SyncState, SyncStatus};
+use crate::common::executor::{Executor, TaskHandle};
 use crate::core::global;
 use crate::core::pow::Difficulty;
 use crate::kepler::sync::body_sync::BodySync;
 use crate::kepler::sync::header_sync::HeaderSync;
 use crate::kepler::sync::state_sync::StateSync;
-use crate::p2p;
-use crate::util::StopState;
+use crate::kepler::sync::watchdog::{SyncProgress, SyncWatchdog};
+use crate::p2p::{self, Peer};
+use crate::util::{StopState, Watch};
 
 pub fn run_sync(
 	sync_state: Arc<SyncState>,
 	peers: Arc<p2p::Peers>,
 	chain: Arc<chain::Chain>,
 	stop_state: Arc<StopState>,
-) -> std::io::Result<std::thread::JoinHandle<()>> {
-	thread::Builder::new()
-		.name("sync".to_string())
-		.spawn(move || {
-			let runner = SyncRunner::new(sync_state, peers, chain, stop_state);
-			runner.sync_loop();
-		})
+	watchdog_secs: u64,
+	executor: &Executor,
+) -> TaskHandle {
+	executor.spawn_blocking("sync", move || {
+		let runner = SyncRunner::new(sync_state, peers, chain, stop_state, watchdog_secs);
+		runner.sync_loop();
+	})
 }
 
 pub struct SyncRunner {
@@ -44,6 +46,7 @@ pub struct SyncRunner {
 	peers: Arc<p2p::Peers>,
 	chain: Arc<chain::Chain>,
 	stop_state: Arc<StopState>,
+	watchdog_secs: u64,
 }
 
 impl SyncRunner {
@@ -52,12 +55,14 @@ impl SyncRunner {
 		peers: Arc<p2p::Peers>,
 		chain: Arc<chain::Chain>,
 		stop_state: Arc<StopState>,
+		watchdog_secs: u64,
 	) -> SyncRunner {
 		SyncRunner {
 			sync_state,
 			peers,
 			chain,
 			stop_state,
+			watchdog_secs,
 		}
 	}
 
@@ -140,6 +145,12 @@ impl SyncRunner {
 		// whether some sync is needed
 		let mut highest_height = 0;
 
+		// New blocks and sync status changes (including shutdown) wake us up
+		// when there is nothing to sync.
+		let mut events = Watch::subscribe(&self.sync_state.events());
+
+		let mut watchdog = SyncWatchdog::new(self.watchdog_secs);
+
 		// Main syncing loop
 		loop {
 			if self.stop_state.is_stopped() {
@@ -169,13 +180,10 @@ impl SyncRunner {
 					unwrap_or_restart_loop!(self.chain.compact());
 				}
 
-				// sleep for 10 secs but check stop signal every second
-				for _ in 1..10 {
-					thread::sleep(time::Duration::from_secs(1));
-					if self.stop_state.is_stopped() {
-						break;
-					}
-				}
+				watchdog.check(None, time::Instant::now());
+
+				// wait for up to 10 secs before checking our peers again
+				events.recv_timeout(time::Duration::from_secs(10));
 				continue;
 			}
 
@@ -195,6 +203,36 @@ impl SyncRunner {
 				maybe_header_head.ok_or("failed to obtain lock for try_header_head")
 			);
 
+			let status = self.sync_state.status();
+			let progress = SyncProgress::new(&status, head.height, header_head.height);
+			if let Some(stuck) = watchdog.check(progress, time::Instant::now()) {
+				warn!(
+					"sync: stuck in {} for over {}s, status {:?}, head {} at {}, header head {} at {}, highest height {}, {} peers, {} orphans. Resetting.",
+					stuck.stage,
+					self.watchdog_secs,
+					status,
+					head.last_block_h,
+					head.height,
+					header_head.last_block_h,
+					header_head.height,
+					highest_height,
+					self.peers.peer_count(),
+					self.chain.orphans_len(),
+				);
+				let peer = if stuck.is_state_sync() {
+					// Restarts the state sync, with a new txhashset request.
+					self.sync_state.set_sync_error(
+						chain::ErrorKind::SyncError(format!("stuck in {}", stuck.stage)).into(),
+					);
+					state_sync.reset()
+				} else if stuck.stage == "header_sync" {
+					header_sync.reset()
+				} else {
+					body_sync.reset()
+				};
+				self.rotate_sync_peer(peer);
+			}
+
 			// run each sync stage, each of them deciding whether they're needed
 			// except for state sync that only runs if body sync return true (means txhashset is needed)
 			unwrap_or_restart_loop!(header_sync.check_run(&header_head, highest_height));
@@ -203,6 +241,9 @@ impl SyncRunner {
 			match self.sync_state.status() {
 				SyncStatus::TxHashsetDownload { .. }
 				| SyncStatus::TxHashsetSetup
+				| SyncStatus::TxHashsetUnzip { .. }
+				| SyncStatus::TxHashsetKernelHistoryValidation { .. }
+				| SyncStatus::TxHashsetMmrsValidation { .. }
 				| SyncStatus::TxHashsetRangeProofsValidation { .. }
 				| SyncStatus::TxHashsetKernelsValidation { .. }
 				| SyncStatus::TxHashsetSave
@@ -233,6 +274,19 @@ impl SyncRunner {
 		}
 	}
 
+	// Disconnect the peer the stuck stage was syncing from, the next sync
+	// requests go to other peers.
+	fn rotate_sync_peer(&self, peer: Option<Arc<Peer>>) {
+		if let Some(peer) = peer {
+			info!(
+				"sync: disconnecting {} at {}, rotating sync peers",
+				peer.info.addr,
+				peer.info.height()
+			);
+			peer.stop();
+		}
+	}
+
 	/// Whether we're currently syncing the chain or we're fully caught up and
 	/// just receiving blocks through gossip.
 	fn needs_syncing(&self) -> Result<(bool, u64), chain::Error> {
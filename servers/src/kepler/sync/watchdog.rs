@@ -0,0 +1,176 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::{Duration, Instant};
+
+use crate::chain::SyncStatus;
+
+/// Sync stage along with how far it got, the sync is stuck when neither
+/// changes for too long.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyncProgress {
+	pub stage: &'static str,
+	pub value: u64,
+}
+
+impl SyncProgress {
+	/// Progress of the sync in the provided status, None if not syncing.
+	pub fn new(status: &SyncStatus, head_height: u64, header_height: u64) -> Option<SyncProgress> {
+		let (stage, value) = match *status {
+			SyncStatus::HeaderSync { .. } => ("header_sync", header_height),
+			SyncStatus::TxHashsetDownload {
+				downloaded_size, ..
+			} => ("txhashset_download", downloaded_size),
+			SyncStatus::TxHashsetSetup => ("txhashset_setup", 0),
+			SyncStatus::TxHashsetUnzip { unzipped_size, .. } => ("txhashset_unzip", unzipped_size),
+			SyncStatus::TxHashsetKernelHistoryValidation { headers, .. } => {
+				("txhashset_kernel_history_validation", headers)
+			}
+			SyncStatus::TxHashsetMmrsValidation { mmrs, .. } => ("txhashset_mmrs_validation", mmrs),
+			SyncStatus::TxHashsetKernelsValidation { kernels, .. } => {
+				("txhashset_kernels_validation", kernels)
+			}
+			SyncStatus::TxHashsetRangeProofsValidation { rproofs, .. } => {
+				("txhashset_rangeproofs_validation", rproofs)
+			}
+			SyncStatus::TxHashsetSave => ("txhashset_save", 0),
+			SyncStatus::TxHashsetDone => ("txhashset_done", 0),
+			SyncStatus::BodySync { .. } => ("body_sync", head_height),
+			SyncStatus::Initial
+			| SyncStatus::NoSync
+			| SyncStatus::AwaitingPeers(_)
+			| SyncStatus::Shutdown => return None,
+		};
+		Some(SyncProgress { stage, value })
+	}
+
+	/// Whether the stuck stage is one of the txhashset download and
+	/// validation stages.
+	pub fn is_state_sync(&self) -> bool {
+		self.stage.starts_with("txhashset")
+	}
+}
+
+/// Detects the sync state machine making no progress in a stage for longer
+/// than the configured timeout.
+pub struct SyncWatchdog {
+	timeout: Option<Duration>,
+	progress: Option<SyncProgress>,
+	since: Instant,
+}
+
+impl SyncWatchdog {
+	/// New watchdog firing after the provided number of seconds without
+	/// progress, never if 0.
+	pub fn new(timeout_secs: u64) -> SyncWatchdog {
+		SyncWatchdog {
+			timeout: Some(timeout_secs)
+				.filter(|s| *s > 0)
+				.map(Duration::from_secs),
+			progress: None,
+			since: Instant::now(),
+		}
+	}
+
+	/// Record the current progress of the sync, returning it if it hasn't
+	/// changed for longer than the timeout. Fires again only after another
+	/// timeout without progress.
+	pub fn check(&mut self, progress: Option<SyncProgress>, now: Instant) -> Option<SyncProgress> {
+		let timeout = self.timeout?;
+		if progress != self.progress {
+			self.progress = progress;
+			self.since = now;
+			return None;
+		}
+		if self.progress.is_some() && now.duration_since(self.since) > timeout {
+			self.since = now;
+			return self.progress.clone();
+		}
+		None
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_sync_watchdog() {
+		let body_sync = |height| SyncStatus::BodySync {
+			current_height: height,
+			highest_height: 100,
+		};
+		let start = Instant::now();
+		let secs = |s| start + Duration::from_secs(s);
+		let mut watchdog = SyncWatchdog::new(60);
+
+		// Progressing, never fires.
+		assert_eq!(
+			watchdog.check(SyncProgress::new(&body_sync(1), 1, 100), secs(0)),
+			None
+		);
+		assert_eq!(
+			watchdog.check(SyncProgress::new(&body_sync(2), 2, 100), secs(50)),
+			None
+		);
+		assert_eq!(
+			watchdog.check(SyncProgress::new(&body_sync(3), 3, 100), secs(100)),
+			None
+		);
+
+		// Stuck at the same height.
+		assert_eq!(
+			watchdog.check(SyncProgress::new(&body_sync(3), 3, 100), secs(150)),
+			None
+		);
+		let stuck = watchdog.check(SyncProgress::new(&body_sync(3), 3, 100), secs(161));
+		assert_eq!(
+			stuck,
+			Some(SyncProgress {
+				stage: "body_sync",
+				value: 3
+			})
+		);
+		assert!(!stuck.unwrap().is_state_sync());
+		// Only fires again after another timeout.
+		assert_eq!(
+			watchdog.check(SyncProgress::new(&body_sync(3), 3, 100), secs(200)),
+			None
+		);
+		assert!(watchdog
+			.check(SyncProgress::new(&body_sync(3), 3, 100), secs(222))
+			.is_some());
+
+		// Not syncing, nothing to watch.
+		assert_eq!(
+			watchdog.check(SyncProgress::new(&SyncStatus::NoSync, 3, 100), secs(300)),
+			None
+		);
+		assert_eq!(
+			watchdog.check(SyncProgress::new(&SyncStatus::NoSync, 3, 100), secs(400)),
+			None
+		);
+
+		// Disabled.
+		let mut watchdog = SyncWatchdog::new(0);
+		assert_eq!(
+			watchdog.check(SyncProgress::new(&body_sync(3), 3, 100), secs(0)),
+			None
+		);
+		assert_eq!(
+			watchdog.check(SyncProgress::new(&body_sync(3), 3, 100), secs(1000)),
+			None
+		);
+	}
+}
@@ -0,0 +1,200 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Upstream relay mode. Instead of syncing over p2p, the node follows a
+//! trusted node through its api, fetching its blocks and forwarding our
+//! transactions to it. Everything received is still fully validated locally.
+
+use std::cmp;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::api;
+use crate::chain::{self, SyncState, SyncStatus};
+use crate::common::executor::{Executor, TaskHandle};
+use crate::common::types::Error;
+use crate::core::core::hash::{Hash, Hashed};
+use crate::core::core::{Block, InputProof, Transaction};
+use crate::core::ser::{self, ProtocolVersion};
+use crate::util::{self, StopState};
+
+/// How often the upstream node is polled for a new tip.
+const UPSTREAM_POLL_SECS: u64 = 2;
+
+/// Hex encoded transaction, as expected by the push_tx endpoint.
+#[derive(Serialize)]
+struct TxWrapper {
+	tx_hex: String,
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	input_proofs: Vec<InputProof>,
+}
+
+/// A trusted node we follow through its api.
+pub struct UpstreamNode {
+	url: String,
+	api_secret: Option<String>,
+}
+
+impl UpstreamNode {
+	/// Upstream node reachable at the provided api url (i.e.
+	/// http://127.0.0.1:7413), authenticating with its api secret if any.
+	pub fn new(url: &str, api_secret: Option<String>) -> UpstreamNode {
+		UpstreamNode {
+			url: url.trim_end_matches('/').to_owned(),
+			api_secret,
+		}
+	}
+
+	/// Api url of the upstream node.
+	pub fn url(&self) -> &str {
+		&self.url
+	}
+
+	fn tip(&self) -> Result<api::Tip, Error> {
+		let url = format!("{}/v1/chain", self.url);
+		Ok(api::client::get(&url, self.api_secret.clone())?)
+	}
+
+	fn header_hash(&self, height: u64) -> Result<Hash, Error> {
+		let url = format!("{}/v1/headers/{}", self.url, height);
+		let header: api::BlockHeaderPrintable = api::client::get(&url, self.api_secret.clone())?;
+		let hash = util::from_hex(header.hash)
+			.map_err(|e| Error::General(format!("invalid upstream header hash: {}", e)))?;
+		Ok(Hash::from_vec(&hash))
+	}
+
+	fn block(&self, height: u64) -> Result<Block, Error> {
+		let url = format!("{}/v1/blocks/{}?raw", self.url, height);
+		let raw: api::RawBlock = api::client::get(&url, self.api_secret.clone())?;
+		raw.to_block()
+			.map_err(|e| Error::General(format!("invalid upstream block: {}", e)))
+	}
+
+	/// Push a transaction, and the proofs of its inputs, to the upstream
+	/// node pool, letting it handle the Dandelion relay of the transaction.
+	pub fn push_transaction(
+		&self,
+		tx: &Transaction,
+		input_proofs: &[InputProof],
+	) -> Result<(), Error> {
+		// The push_tx endpoint expects protocol version 1, like wallets do.
+		let tx_bin = ser::ser_vec(tx, ProtocolVersion(1))
+			.map_err(|e| Error::General(format!("failed to serialize tx: {}", e)))?;
+		let wrapper = TxWrapper {
+			tx_hex: util::to_hex(tx_bin),
+			input_proofs: input_proofs.to_vec(),
+		};
+		let url = format!("{}/v1/pool/push_tx", self.url);
+		api::client::post_no_ret(&url, self.api_secret.clone(), &wrapper)?;
+		Ok(())
+	}
+}
+
+/// Follow the upstream node, polling it for new blocks and processing them
+/// through our own chain, in place of the p2p sync.
+pub fn follow_upstream(
+	upstream: Arc<UpstreamNode>,
+	sync_state: Arc<SyncState>,
+	chain: Arc<chain::Chain>,
+	stop_state: Arc<StopState>,
+	executor: &Executor,
+) -> TaskHandle {
+	executor.spawn_blocking("upstream", move || {
+		info!("Following upstream node at {}", upstream.url());
+		while !stop_state.is_stopped() {
+			if let Err(e) = follow(&upstream, &sync_state, &chain, &stop_state) {
+				warn!("Failed to follow upstream node {}: {:?}", upstream.url(), e);
+			}
+			thread::sleep(Duration::from_secs(UPSTREAM_POLL_SECS));
+		}
+	})
+}
+
+fn follow(
+	upstream: &UpstreamNode,
+	sync_state: &SyncState,
+	chain: &chain::Chain,
+	stop_state: &StopState,
+) -> Result<(), Error> {
+	let tip = upstream.tip()?;
+	let head = chain.head()?;
+	if tip.total_difficulty <= head.total_difficulty.to_num() {
+		sync_state.update(SyncStatus::NoSync);
+		return Ok(());
+	}
+
+	// Walk back from our head until we agree with upstream on the block at
+	// that height, in case it reorged since we last polled.
+	let mut height = cmp::min(head.height, tip.height);
+	while height > 0 && upstream.header_hash(height)? != chain.get_header_by_height(height)?.hash()
+	{
+		height -= 1;
+	}
+	if height < head.height {
+		info!(
+			"Upstream node forked from our chain at {}, our head at {}",
+			height, head.height
+		);
+	}
+
+	while height < tip.height {
+		if stop_state.is_stopped() {
+			return Ok(());
+		}
+		height += 1;
+		let opts = if tip.height - height > 1 {
+			sync_state.update(SyncStatus::BodySync {
+				current_height: height,
+				highest_height: tip.height,
+			});
+			chain::Options::SYNC
+		} else {
+			chain::Options::NONE
+		};
+		let block = upstream.block(height)?;
+		let hash = block.hash();
+		let e = match chain.process_block(block, opts) {
+			Ok(_) => continue,
+			Err(e) => e,
+		};
+		match e.kind() {
+			// Already processed, received over p2p or on a fork we knew about,
+			// the next blocks build on it.
+			chain::ErrorKind::Known(_) | chain::ErrorKind::OldBlock => {
+				debug!("Upstream block {} at {} already known", hash, height);
+			}
+			// The upstream chain likely moved on since we polled its tip, the
+			// blocks are requested again from the fork point on the next poll.
+			chain::ErrorKind::Unfit(_) => {
+				debug!(
+					"Upstream block {} at {} is unfit, requesting again: {}",
+					hash, height, e
+				);
+				return Ok(());
+			}
+			_ => {
+				if e.is_bad_data() {
+					error!(
+						"Upstream node sent invalid block {} at {}: {}",
+						hash, height, e
+					);
+				}
+				return Err(e.into());
+			}
+		}
+	}
+	sync_state.update(SyncStatus::NoSync);
+	Ok(())
+}
@@ -39,6 +39,15 @@ pub mod common;
 mod kepler;
 mod mining;
 
-pub use crate::common::stats::{DiffBlock, PeerStats, ServerStats, StratumStats, WorkerStats};
+pub use crate::common::stats::{
+	DiffBlock, PeerStats, ServerStats, StratumStats, TaskStats, WorkerStats,
+};
 pub use crate::common::types::{ServerConfig, StratumServerConfig};
+pub use crate::kepler::archive::{
+	export_blocks, import_blocks, BlockImport, BLOCK_FILES_REPORT_BLOCKS,
+};
+pub use crate::kepler::bench::{bench_reindex, BENCH_REPORT_BLOCKS};
+pub use crate::kepler::checkpoint::{export_checkpoint, node_identity, read_checkpoint};
+pub use crate::kepler::replay::{replay_capture, ReplayStats};
+pub use crate::kepler::selftest::{selftest, SelfTestCheck};
 pub use crate::kepler::server::Server;
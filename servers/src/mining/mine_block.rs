@@ -16,7 +16,6 @@
 //! them into a block and returns it.
 
 use crate::util::RwLock;
-use chrono::prelude::{DateTime, NaiveDateTime, Utc};
 use rand::{thread_rng, Rng};
 use serde_json::{json, Value};
 use std::sync::Arc;
@@ -25,7 +24,9 @@ use std::time::Duration;
 
 use crate::api;
 use crate::chain;
+use crate::common::policy::TxPolicy;
 use crate::common::types::Error;
+use crate::core::core::hash::Hashed;
 use crate::core::core::verifier_cache::VerifierCache;
 use crate::core::core::{Output, TxKernel};
 use crate::core::libtx::secp_ser;
@@ -68,6 +69,7 @@ pub struct CbData {
 
 // Ensure a block suitable for mining is built and returned
 // If a wallet listener URL is not provided the reward will be "burnt"
+// Only the pool txs accepted by the tx policy, if any, are included
 // Warning: This call does not return until/unless a new block can be built
 pub fn get_block(
 	chain: &Arc<chain::Chain>,
@@ -75,6 +77,7 @@ pub fn get_block(
 	verifier_cache: Arc<RwLock<dyn VerifierCache>>,
 	key_id: Option<Identifier>,
 	wallet_listener_url: Option<String>,
+	tx_policy: Option<&TxPolicy>,
 ) -> (core::Block, BlockFees) {
 	let wallet_retry_interval = 5;
 	// get the latest chain state and build a block on top of it
@@ -84,6 +87,7 @@ pub fn get_block(
 		verifier_cache.clone(),
 		key_id.clone(),
 		wallet_listener_url.clone(),
+		tx_policy,
 	);
 	while let Err(e) = result {
 		let mut new_key_id = key_id.to_owned();
@@ -124,6 +128,7 @@ pub fn get_block(
 			verifier_cache.clone(),
 			new_key_id,
 			wallet_listener_url.clone(),
+			tx_policy,
 		);
 	}
 	return result.unwrap();
@@ -137,25 +142,36 @@ fn build_block(
 	verifier_cache: Arc<RwLock<dyn VerifierCache>>,
 	key_id: Option<Identifier>,
 	wallet_listener_url: Option<String>,
+	tx_policy: Option<&TxPolicy>,
 ) -> Result<(core::Block, BlockFees), Error> {
 	let head = chain.head_header()?;
 
-	// prepare the block header timestamp
-	let mut now_sec = Utc::now().timestamp();
-	let head_sec = head.timestamp.timestamp();
-	if now_sec <= head_sec {
-		now_sec = head_sec + 1;
+	// Snapshot the pool, so the block template doesn't change while we build
+	// it, and make sure its txs were validated against the head we build on.
+	let snapshot = tx_pool.read().snapshot()?;
+	if snapshot.header.hash() != head.hash() {
+		return Err(Error::General(format!(
+			"chain head moved from {} to {} while building block",
+			head.hash(),
+			snapshot.header.hash()
+		)));
 	}
 
+	// Leave out the txs not accepted by the policy, outside the pool lock.
+	let snapshot = match tx_policy {
+		Some(tx_policy) => tx_policy.filter_snapshot(snapshot),
+		None => snapshot,
+	};
+
 	// Determine the difficulty our block should be at.
 	// Note: do not keep the difficulty_iter in scope (it has an active batch).
 	let difficulty = consensus::next_difficulty(head.height + 1, chain.difficulty_iter()?);
 
-	// Extract current "mineable" transactions from the pool.
+	// Extract "mineable" transactions from the pool snapshot.
 	// If this fails for *any* reason then fallback to an empty vec of txs.
 	// This will allow us to mine an "empty" block if the txpool is in an
 	// invalid (and unexpected) state.
-	let txs = match tx_pool.read().prepare_mineable_transactions() {
+	let txs = match tx_pool.read().mineable_transactions(&snapshot) {
 		Ok(txs) => txs,
 		Err(e) => {
 			error!(
@@ -177,15 +193,17 @@ fn build_block(
 	};
 
 	let (output, kernel, block_fees) = get_coinbase(wallet_listener_url, block_fees)?;
-	let mut b = core::Block::from_reward(&head, txs, output, kernel, difficulty.difficulty)?;
+	let mut b = core::BlockBuilder::new(&head)
+		.txs(txs)
+		.reward(output, kernel)
+		.difficulty(difficulty.difficulty)
+		.secondary_scaling(difficulty.secondary_scaling)
+		.nonce(thread_rng().gen())
+		.build()?;
 
 	// making sure we're not spending time mining a useless block
 	b.validate(&head.total_kernel_offset, verifier_cache)?;
 
-	b.header.pow.nonce = thread_rng().gen();
-	b.header.pow.secondary_scaling = difficulty.secondary_scaling;
-	b.header.timestamp = DateTime::<Utc>::from_utc(NaiveDateTime::from_timestamp(now_sec, 0), Utc);
-
 	debug!(
 		"Built new block with {} inputs and {} outputs, block difficulty: {}, cumulative difficulty {}",
 		b.inputs().len(),
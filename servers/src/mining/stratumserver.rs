@@ -18,7 +18,6 @@ use futures::channel::mpsc;
 use futures::pin_mut;
 use futures::{SinkExt, StreamExt, TryStreamExt};
 use tokio::net::TcpListener;
-use tokio::runtime::Runtime;
 use tokio_util::codec::{Framed, LinesCodec};
 
 use crate::util::RwLock;
@@ -33,6 +32,8 @@ use std::time::{Duration, SystemTime};
 use std::{cmp, thread};
 
 use crate::chain::{self, SyncState};
+use crate::common::executor::Executor;
+use crate::common::policy::TxPolicy;
 use crate::common::stats::{StratumStats, WorkerStats};
 use crate::common::types::StratumServerConfig;
 use crate::core::core::hash::Hashed;
@@ -107,10 +108,10 @@ impl RpcError {
 			message: "Solution submitted too late".to_string(),
 		}
 	}
-	pub fn cannot_validate() -> Self {
+	pub fn cannot_validate(reason: &chain::ErrorKind) -> Self {
 		RpcError {
 			code: -32502,
-			message: "Failed to validate solution".to_string(),
+			message: format!("Failed to validate solution: {}", reason),
 		}
 	}
 	pub fn too_low_difficulty() -> Self {
@@ -396,7 +397,9 @@ impl Handler {
 				);
 			self.workers
 				.update_stats(worker_id, |worker_stats| worker_stats.num_rejected += 1);
-			return Err(RpcError::cannot_validate());
+			return Err(RpcError::cannot_validate(&chain::ErrorKind::LowEdgebits(
+				b.header.pow.edge_bits(),
+			)));
 		}
 
 		// Get share difficulty
@@ -432,7 +435,7 @@ impl Handler {
 					);
 				self.workers
 					.update_stats(worker_id, |worker_stats| worker_stats.num_rejected += 1);
-				return Err(RpcError::cannot_validate());
+				return Err(RpcError::cannot_validate(&e.kind()));
 			}
 			share_is_block = true;
 			self.workers
@@ -464,7 +467,9 @@ impl Handler {
 					);
 				self.workers
 					.update_stats(worker_id, |worker_stats| worker_stats.num_rejected += 1);
-				return Err(RpcError::cannot_validate());
+				return Err(RpcError::cannot_validate(&chain::ErrorKind::InvalidPow(
+					b.header.pow.edge_bits(),
+				)));
 			}
 		}
 		// Log this as a valid share
@@ -526,6 +531,7 @@ impl Handler {
 		config: &StratumServerConfig,
 		tx_pool: &Arc<RwLock<pool::TransactionPool>>,
 		verifier_cache: Arc<RwLock<dyn VerifierCache>>,
+		tx_policy: Option<&TxPolicy>,
 	) {
 		debug!("Run main loop");
 		let mut deadline: i64 = 0;
@@ -560,6 +566,7 @@ impl Handler {
 						verifier_cache.clone(),
 						state.current_key_id.clone(),
 						wallet_listener_url,
+						tx_policy,
 					);
 
 					state.current_difficulty =
@@ -595,71 +602,71 @@ impl Handler {
 }
 
 // ----------------------------------------
-// Worker Factory Thread Function
-fn accept_connections(listen_addr: SocketAddr, handler: Arc<Handler>) {
+// Worker Factory Task
+async fn accept_connections(
+	listen_addr: SocketAddr,
+	handler: Arc<Handler>,
+	executor: Arc<Executor>,
+) {
 	info!("Start tokio stratum server");
-	let task = async move {
-		let mut listener = TcpListener::bind(&listen_addr).await.expect(&format!(
-			"Stratum: Failed to bind to listen address {}",
-			listen_addr
-		));
-		let server = listener
-			.incoming()
-			.filter_map(|s| async { s.map_err(|e| error!("accept error = {:?}", e)).ok() })
-			.for_each(move |socket| {
-				let handler = handler.clone();
-				async move {
-					// Spawn a task to process the connection
-					let (tx, mut rx) = mpsc::unbounded();
-
-					let worker_id = handler.workers.add_worker(tx);
-					info!("Worker {} connected", worker_id);
-
-					let framed = Framed::new(socket, LinesCodec::new());
-					let (mut writer, mut reader) = framed.split();
-
-					let h = handler.clone();
-					let read = async move {
-						while let Some(line) = reader
-							.try_next()
+	let mut listener = TcpListener::bind(&listen_addr).await.expect(&format!(
+		"Stratum: Failed to bind to listen address {}",
+		listen_addr
+	));
+	let server = listener
+		.incoming()
+		.filter_map(|s| async { s.map_err(|e| error!("accept error = {:?}", e)).ok() })
+		.for_each(move |socket| {
+			let handler = handler.clone();
+			let executor = executor.clone();
+			async move {
+				// Spawn a task to process the connection
+				let (tx, mut rx) = mpsc::unbounded();
+
+				let worker_id = handler.workers.add_worker(tx);
+				info!("Worker {} connected", worker_id);
+
+				let framed = Framed::new(socket, LinesCodec::new());
+				let (mut writer, mut reader) = framed.split();
+
+				let h = handler.clone();
+				let read = async move {
+					while let Some(line) = reader
+						.try_next()
+						.await
+						.map_err(|e| error!("error reading line: {}", e))?
+					{
+						let request = serde_json::from_str(&line)
+							.map_err(|e| error!("error serializing line: {}", e))?;
+						let resp = h.handle_rpc_requests(request, worker_id);
+						h.workers.send_to(worker_id, resp);
+					}
+
+					Result::<_, ()>::Ok(())
+				};
+
+				let write = async move {
+					while let Some(line) = rx.next().await {
+						let line = line + "\n";
+						writer
+							.send(line)
 							.await
-							.map_err(|e| error!("error reading line: {}", e))?
-						{
-							let request = serde_json::from_str(&line)
-								.map_err(|e| error!("error serializing line: {}", e))?;
-							let resp = h.handle_rpc_requests(request, worker_id);
-							h.workers.send_to(worker_id, resp);
-						}
-
-						Result::<_, ()>::Ok(())
-					};
-
-					let write = async move {
-						while let Some(line) = rx.next().await {
-							let line = line + "\n";
-							writer
-								.send(line)
-								.await
-								.map_err(|e| error!("error writing line: {}", e))?;
-						}
-
-						Result::<_, ()>::Ok(())
-					};
-
-					let task = async move {
-						pin_mut!(read, write);
-						futures::future::select(read, write).await;
-						handler.workers.remove_worker(worker_id);
-						info!("Worker {} disconnected", worker_id);
-					};
-					tokio::spawn(task);
-				}
-			});
-		server.await
-	};
+							.map_err(|e| error!("error writing line: {}", e))?;
+					}
 
-	let mut rt = Runtime::new().unwrap();
-	rt.block_on(task);
+					Result::<_, ()>::Ok(())
+				};
+
+				let task = async move {
+					pin_mut!(read, write);
+					futures::future::select(read, write).await;
+					handler.workers.remove_worker(worker_id);
+					info!("Worker {} disconnected", worker_id);
+				};
+				executor.spawn("stratum_worker", task);
+			}
+		});
+	server.await
 }
 
 // ----------------------------------------
@@ -806,6 +813,8 @@ pub struct StratumServer {
 	verifier_cache: Arc<RwLock<dyn VerifierCache>>,
 	sync_state: Arc<SyncState>,
 	stratum_stats: Arc<RwLock<StratumStats>>,
+	executor: Arc<Executor>,
+	tx_policy: Option<Arc<TxPolicy>>,
 }
 
 impl StratumServer {
@@ -816,6 +825,8 @@ impl StratumServer {
 		tx_pool: Arc<RwLock<pool::TransactionPool>>,
 		verifier_cache: Arc<RwLock<dyn VerifierCache>>,
 		stratum_stats: Arc<RwLock<StratumStats>>,
+		executor: Arc<Executor>,
+		tx_policy: Option<Arc<TxPolicy>>,
 	) -> StratumServer {
 		StratumServer {
 			id: String::from("0"),
@@ -825,11 +836,13 @@ impl StratumServer {
 			verifier_cache,
 			sync_state: Arc::new(SyncState::new()),
 			stratum_stats: stratum_stats,
+			executor,
+			tx_policy,
 		}
 	}
 
-	/// "main()" - Starts the stratum-server.  Creates a thread to Listens for
-	/// a connection, then enters a loop, building a new block on top of the
+	/// "main()" - Starts the stratum-server.  Spawns a task listening for
+	/// connections, then enters a loop, building a new block on top of the
 	/// existing chain anytime required and sending that to the connected
 	/// stratum miner, proxy, or pool, and accepts full solutions to
 	/// be submitted.
@@ -852,9 +865,10 @@ impl StratumServer {
 		let handler = Arc::new(Handler::from_stratum(&self));
 		let h = handler.clone();
 
-		let _listener_th = thread::spawn(move || {
-			accept_connections(listen_addr, h);
-		});
+		self.executor.spawn(
+			"stratum_listener",
+			accept_connections(listen_addr, h, self.executor.clone()),
+		);
 
 		// We have started
 		{
@@ -873,7 +887,12 @@ impl StratumServer {
 			thread::sleep(Duration::from_millis(50));
 		}
 
-		handler.run(&self.config, &self.tx_pool, self.verifier_cache.clone());
+		handler.run(
+			&self.config,
+			&self.tx_pool,
+			self.verifier_cache.clone(),
+			self.tx_policy.as_deref(),
+		);
 	} // fn run_loop()
 } // StratumServer
 
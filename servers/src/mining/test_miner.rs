@@ -161,6 +161,7 @@ impl Miner {
 				self.verifier_cache.clone(),
 				key_id.clone(),
 				wallet_listener_url.clone(),
+				None,
 			);
 
 			let sol = self.inner_mining_loop(
@@ -13,15 +13,24 @@
 // limitations under the License.
 
 /// Kepler client commands processing
+use std::fmt;
+use std::fs::File;
 use std::net::SocketAddr;
 
 use clap::ArgMatches;
 
 use crate::api;
+use crate::chain;
 use crate::config::GlobalConfig;
+use crate::core::core::hash::Hashed;
+use crate::core::core::BlockHeader;
+use crate::core::pow;
+use crate::core::ser::{self, ProtocolVersion};
 use crate::p2p;
 use crate::servers::ServerConfig;
 use crate::util::file::get_first_line;
+use serde::Serialize;
+use serde_json::json;
 use term;
 
 pub fn client_command(client_args: &ArgMatches<'_>, global_config: GlobalConfig) -> i32 {
@@ -29,37 +38,130 @@ pub fn client_command(client_args: &ArgMatches<'_>, global_config: GlobalConfig)
 	let server_config = global_config.members.unwrap().server;
 	let api_secret = get_first_line(server_config.api_secret_path.clone());
 
+	// --json is global, given before or after the subcommand
+	let json = client_args.is_present("json")
+		|| client_args
+			.subcommand()
+			.1
+			.map_or(false, |args| args.is_present("json"));
+
 	match client_args.subcommand() {
 		("status", Some(_)) => {
-			show_status(&server_config, api_secret);
+			let status = get_status_from_node(&server_config, api_secret);
+			if json {
+				return print_json(status);
+			}
+			show_status(status);
 		}
 		("listconnectedpeers", Some(_)) => {
-			list_connected_peers(&server_config, api_secret);
+			let peers = get_connected_peers(&server_config, api_secret);
+			if json {
+				return print_json(peers);
+			}
+			list_connected_peers(peers);
 		}
 		("ban", Some(peer_args)) => {
 			let peer = peer_args.value_of("peer").unwrap();
-
-			if let Ok(addr) = peer.parse() {
-				ban_peer(&server_config, &addr, api_secret);
-			} else {
-				panic!("Invalid peer address format");
+			let res = parse_peer(peer).and_then(|addr| {
+				ban_peer(&server_config, &addr, api_secret)?;
+				Ok(addr)
+			});
+			if json {
+				return print_json(res.map(|addr| json!({ "peer": addr, "banned": true })));
+			}
+			match res {
+				Ok(addr) => println!("Successfully banned peer {}", addr),
+				Err(Error::Argument(_)) => panic!("Invalid peer address format"),
+				Err(_) => println!("Failed to ban peer {}", peer),
 			}
 		}
 		("unban", Some(peer_args)) => {
 			let peer = peer_args.value_of("peer").unwrap();
-
-			if let Ok(addr) = peer.parse() {
-				unban_peer(&server_config, &addr, api_secret);
-			} else {
-				panic!("Invalid peer address format");
+			let res = parse_peer(peer).and_then(|addr| {
+				unban_peer(&server_config, &addr, api_secret)?;
+				Ok(addr)
+			});
+			if json {
+				return print_json(res.map(|addr| json!({ "peer": addr, "banned": false })));
 			}
+			match res {
+				Ok(addr) => println!("Successfully unbanned peer {}", addr),
+				Err(Error::Argument(_)) => panic!("Invalid peer address format"),
+				Err(_) => println!("Failed to unban peer {}", peer),
+			}
+		}
+		("verify-headers", Some(verify_args)) => {
+			let file = verify_args.value_of("file").unwrap();
+			let res = verify_headers(file);
+			if json {
+				return print_json(res.map(|(count, last)| {
+					json!({
+						"headers": count,
+						"last_hash": last.hash().to_hex(),
+						"last_height": last.height,
+					})
+				}));
+			}
+			return match res {
+				Ok((count, last)) => {
+					println!(
+						"Verified {} headers, up to {} at height {}",
+						count,
+						last.hash(),
+						last.height
+					);
+					0
+				}
+				Err(e) => {
+					println!("{}", e);
+					1
+				}
+			};
 		}
 		_ => panic!("Unknown client command, use 'kepler help client' for details"),
 	}
 	0
 }
 
-pub fn show_status(config: &ServerConfig, api_secret: Option<String>) {
+/// Prints the outcome of a command as JSON, the error as an object with its
+/// kind and message. Returns the exit code, 1 on error.
+fn print_json<T: Serialize>(res: Result<T, Error>) -> i32 {
+	let (value, code) = match res {
+		Ok(value) => (json!(value), 0),
+		Err(e) => (
+			json!({ "error": { "kind": e.kind(), "message": e.to_string() } }),
+			1,
+		),
+	};
+	println!("{}", serde_json::to_string_pretty(&value).unwrap());
+	code
+}
+
+fn parse_peer(peer: &str) -> Result<SocketAddr, Error> {
+	peer.parse()
+		.map_err(|_| Error::Argument(format!("Invalid peer address format: {}", peer)))
+}
+
+/// Verify a file of block headers against the consensus rules, without a
+/// running node. Returns the number of headers and the last one.
+fn verify_headers(path: &str) -> Result<(usize, BlockHeader), Error> {
+	let headers = File::open(path)
+		.map_err(|e| e.to_string())
+		.and_then(|mut file| {
+			ser::deserialize::<Vec<BlockHeader>>(&mut file, ProtocolVersion::local())
+				.map_err(|e| e.to_string())
+		})
+		.map_err(|e| Error::File(format!("Failed to read headers from {}: {}", path, e)))?;
+	let last = headers
+		.last()
+		.cloned()
+		.ok_or_else(|| Error::File(format!("No headers in {}", path)))?;
+	chain::Chain::verify_block_headers_chain(&headers, pow::verify_size)
+		.map_err(|e| Error::InvalidHeaders(format!("Invalid header chain: {}", e.kind())))?;
+	Ok((headers.len(), last))
+}
+
+fn show_status(status: Result<api::Status, Error>) {
 	println!();
 	let title = format!("Kepler Server Status");
 	if term::stdout().is_none() {
@@ -72,15 +174,24 @@ pub fn show_status(config: &ServerConfig, api_secret: Option<String>) {
 	writeln!(t, "{}", title).unwrap();
 	writeln!(t, "--------------------------").unwrap();
 	t.reset().unwrap();
-	match get_status_from_node(config, api_secret) {
+	match status {
 		Ok(status) => {
 			writeln!(e, "Protocol version: {:?}", status.protocol_version).unwrap();
 			writeln!(e, "User agent: {}", status.user_agent).unwrap();
 			writeln!(e, "Connections: {}", status.connections).unwrap();
+			if status.insufficient_peers {
+				writeln!(e, "WARNING: Not enough outbound peers, sync may be slow").unwrap();
+			}
 			writeln!(e, "Chain height: {}", status.tip.height).unwrap();
 			writeln!(e, "Last block hash: {}", status.tip.last_block_pushed).unwrap();
 			writeln!(e, "Previous block hash: {}", status.tip.prev_block_to_last).unwrap();
 			writeln!(e, "Total difficulty: {}", status.tip.total_difficulty).unwrap();
+			writeln!(
+				e,
+				"Orphan blocks: {} ({} evicted)",
+				status.orphans.blocks, status.orphans.evicted
+			)
+			.unwrap();
 		}
 		Err(_) => writeln!(
 			e,
@@ -92,47 +203,38 @@ pub fn show_status(config: &ServerConfig, api_secret: Option<String>) {
 	println!()
 }
 
-pub fn ban_peer(config: &ServerConfig, peer_addr: &SocketAddr, api_secret: Option<String>) {
+fn ban_peer(
+	config: &ServerConfig,
+	peer_addr: &SocketAddr,
+	api_secret: Option<String>,
+) -> Result<(), Error> {
 	let params = "";
-	let mut e = term::stdout().unwrap();
 	let url = format!(
 		"http://{}/v1/peers/{}/ban",
 		config.api_http_addr,
 		peer_addr.to_string()
 	);
-	match api::client::post_no_ret(url.as_str(), api_secret, &params).map_err(|e| Error::API(e)) {
-		Ok(_) => writeln!(e, "Successfully banned peer {}", peer_addr.to_string()).unwrap(),
-		Err(_) => writeln!(e, "Failed to ban peer {}", peer_addr).unwrap(),
-	};
-	e.reset().unwrap();
+	api::client::post_no_ret(url.as_str(), api_secret, &params).map_err(|e| Error::API(e))
 }
 
-pub fn unban_peer(config: &ServerConfig, peer_addr: &SocketAddr, api_secret: Option<String>) {
+fn unban_peer(
+	config: &ServerConfig,
+	peer_addr: &SocketAddr,
+	api_secret: Option<String>,
+) -> Result<(), Error> {
 	let params = "";
-	let mut e = term::stdout().unwrap();
 	let url = format!(
 		"http://{}/v1/peers/{}/unban",
 		config.api_http_addr,
 		peer_addr.to_string()
 	);
-	let res: Result<(), api::Error>;
-	res = api::client::post_no_ret(url.as_str(), api_secret, &params);
-
-	match res.map_err(|e| Error::API(e)) {
-		Ok(_) => writeln!(e, "Successfully unbanned peer {}", peer_addr).unwrap(),
-		Err(_) => writeln!(e, "Failed to unban peer {}", peer_addr).unwrap(),
-	};
-	e.reset().unwrap();
+	api::client::post_no_ret(url.as_str(), api_secret, &params).map_err(|e| Error::API(e))
 }
 
-pub fn list_connected_peers(config: &ServerConfig, api_secret: Option<String>) {
+fn list_connected_peers(peers_info: Result<Vec<p2p::types::PeerInfoDisplay>, Error>) {
 	let mut e = term::stdout().unwrap();
-	let url = format!("http://{}/v1/peers/connected", config.api_http_addr);
-	// let peers_info: Result<Vec<p2p::PeerInfoDisplay>, api::Error>;
-
-	let peers_info = api::client::get::<Vec<p2p::types::PeerInfoDisplay>>(url.as_str(), api_secret);
 
-	match peers_info.map_err(|e| Error::API(e)) {
+	match peers_info {
 		Ok(connected_peers) => {
 			let mut index = 0;
 			for connected_peer in connected_peers {
@@ -154,6 +256,15 @@ pub fn list_connected_peers(config: &ServerConfig, api_secret: Option<String>) {
 	e.reset().unwrap();
 }
 
+fn get_connected_peers(
+	config: &ServerConfig,
+	api_secret: Option<String>,
+) -> Result<Vec<p2p::types::PeerInfoDisplay>, Error> {
+	let url = format!("http://{}/v1/peers/connected", config.api_http_addr);
+	api::client::get::<Vec<p2p::types::PeerInfoDisplay>>(url.as_str(), api_secret)
+		.map_err(|e| Error::API(e))
+}
+
 fn get_status_from_node(
 	config: &ServerConfig,
 	api_secret: Option<String>,
@@ -167,4 +278,33 @@ fn get_status_from_node(
 enum Error {
 	/// Error originating from HTTP API calls.
 	API(api::Error),
+	/// Invalid command argument.
+	Argument(String),
+	/// Missing or unreadable file.
+	File(String),
+	/// Headers breaking the consensus rules.
+	InvalidHeaders(String),
+}
+
+impl Error {
+	/// Stable name of the kind of error, for scripts to match on.
+	fn kind(&self) -> &'static str {
+		match self {
+			Error::API(_) => "api",
+			Error::Argument(_) => "argument",
+			Error::File(_) => "file",
+			Error::InvalidHeaders(_) => "invalid_headers",
+		}
+	}
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Error::API(e) => write!(f, "{}", e),
+			Error::Argument(msg) | Error::File(msg) | Error::InvalidHeaders(msg) => {
+				write!(f, "{}", msg)
+			}
+		}
+	}
 }
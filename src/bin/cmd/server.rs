@@ -13,6 +13,7 @@
 // limitations under the License.
 
 /// Kepler server commands processing
+use std::path::PathBuf;
 use std::process::exit;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
@@ -22,6 +23,8 @@ use std::time::Duration;
 use clap::ArgMatches;
 use ctrlc;
 
+use crate::chain::checkpoint;
+use crate::chain::pipe::PipeTimings;
 use crate::config::GlobalConfig;
 use crate::core::global;
 use crate::p2p::Seeding;
@@ -80,6 +83,212 @@ fn start_server_tui(config: servers::ServerConfig, logs_rx: Option<mpsc::Receive
 	}
 }
 
+fn print_timings(timings: &PipeTimings) {
+	let total = timings.total();
+	for (stage, duration) in timings.stages() {
+		println!(
+			"  {:<10} {:>12.3}s {:>6.2}%",
+			stage,
+			duration.as_secs_f64(),
+			100.0 * duration.as_secs_f64() / total.as_secs_f64().max(f64::EPSILON),
+		);
+	}
+}
+
+/// Replays the whole chain through a fresh one, printing the time spent in
+/// each stage of the block processing pipeline.
+fn bench_reindex(server_config: &servers::ServerConfig) -> i32 {
+	println!(
+		"Replaying the chain in {}, timings every {} blocks (ms):",
+		server_config.db_root,
+		servers::BENCH_REPORT_BLOCKS
+	);
+	let res = servers::bench_reindex(server_config, |height, timings| {
+		let stages: Vec<String> = timings
+			.stages()
+			.iter()
+			.map(|(stage, d)| format!("{} {:.1}", stage, d.as_secs_f64() * 1000.0))
+			.collect();
+		println!(
+			"{:>10}: {:>10.1} total, {:.3} per block | {}",
+			height,
+			timings.total().as_secs_f64() * 1000.0,
+			timings.total().as_secs_f64() * 1000.0 / timings.blocks.max(1) as f64,
+			stages.join(", "),
+		);
+	});
+	match res {
+		Ok(timings) => {
+			println!(
+				"Replayed {} blocks in {:.3}s:",
+				timings.blocks,
+				timings.total().as_secs_f64()
+			);
+			print_timings(&timings);
+			0
+		}
+		Err(e) => {
+			println!("Chain replay failed: {:?}", e);
+			1
+		}
+	}
+}
+
+/// Appends the chain blocks to flat block files, printing the progress.
+fn export_blocks(server_config: &servers::ServerConfig, args: &ArgMatches<'_>) -> i32 {
+	let dir = args.value_of("dir").unwrap();
+	let parse_height = |name| match args.value_of(name).map(|h| h.parse::<u64>()) {
+		Some(Ok(h)) => Ok(Some(h)),
+		Some(Err(_)) => Err(format!("Invalid {} height", name)),
+		None => Ok(None),
+	};
+	let (from, to) = match (parse_height("from"), parse_height("to")) {
+		(Ok(from), Ok(to)) => (from.unwrap_or(1), to),
+		(Err(e), _) | (_, Err(e)) => {
+			println!("{}", e);
+			return 1;
+		}
+	};
+	println!(
+		"Exporting the blocks of {} to {}",
+		server_config.db_root, dir
+	);
+	let res = servers::export_blocks(server_config, dir, from, to, |height| {
+		println!("{:>10}", height);
+	});
+	match res {
+		Ok(exported) => {
+			println!("Exported {} blocks", exported);
+			0
+		}
+		Err(e) => {
+			println!("Block export failed: {:?}", e);
+			1
+		}
+	}
+}
+
+/// Replays the blocks of flat block files through the chain, printing the
+/// progress.
+fn import_blocks(server_config: &servers::ServerConfig, args: &ArgMatches<'_>) -> i32 {
+	let dir = args.value_of("dir").unwrap();
+	println!(
+		"Importing the blocks of {} into {}",
+		dir, server_config.db_root
+	);
+	let res = servers::import_blocks(server_config, dir, |read, height| {
+		println!("{:>10} blocks read, chain at {}", read, height);
+	});
+	match res {
+		Ok(import) => {
+			println!(
+				"Imported {} blocks, skipped {} known ones, chain at {} ({})",
+				import.imported, import.known, import.head.height, import.head.last_block_h
+			);
+			0
+		}
+		Err(e) => {
+			println!("Block import failed: {:?}", e);
+			1
+		}
+	}
+}
+
+/// Exports a checkpoint of the chain signed with the node identity key,
+/// printing the key to list in the trusted operators of the importing nodes.
+fn export_checkpoint(server_config: &servers::ServerConfig, args: &ArgMatches<'_>) -> i32 {
+	let file = args.value_of("file").unwrap();
+	let height = match args.value_of("height").map(|h| h.parse::<u64>()) {
+		Some(Ok(h)) => Some(h),
+		Some(Err(_)) => {
+			println!("Invalid height");
+			return 1;
+		}
+		None => None,
+	};
+	match servers::export_checkpoint(server_config, file, height) {
+		Ok(signed) => {
+			println!(
+				"Checkpoint {} at {} written to {}",
+				signed.checkpoint.hash, signed.checkpoint.height, file
+			);
+			println!(
+				"Signed by operator {}",
+				checkpoint::operator_id(&signed.operator)
+			);
+			0
+		}
+		Err(e) => {
+			println!("Checkpoint export failed: {:?}", e);
+			1
+		}
+	}
+}
+
+/// Replays captured p2p messages against a fresh chain, printing the ones
+/// failing. Exits with an error if any did, for regression tests.
+fn replay_capture(server_config: &servers::ServerConfig, args: &ArgMatches<'_>) -> i32 {
+	let files: Vec<PathBuf> = args
+		.values_of("files")
+		.unwrap()
+		.map(PathBuf::from)
+		.collect();
+	let res = servers::replay_capture(server_config, &files, |index, msg, e| {
+		println!(
+			"Message {} ({:?} from {}) failed: {:?}",
+			index, msg.header.msg_type, msg.peer, e
+		);
+	});
+	match res {
+		Ok(stats) => {
+			println!(
+				"Replayed {} messages, {} failed, chain at {} ({})",
+				stats.messages, stats.failures, stats.head.height, stats.head.last_block_h
+			);
+			if stats.failures > 0 {
+				1
+			} else {
+				0
+			}
+		}
+		Err(e) => {
+			println!("Replay failed: {:?}", e);
+			1
+		}
+	}
+}
+
+/// Runs the node self-test, printing a pass/fail line per check.
+fn selftest(server_config: &servers::ServerConfig) -> i32 {
+	println!(
+		"Running the self-test of the node in {}:",
+		server_config.db_root
+	);
+	let checks = match servers::selftest(server_config) {
+		Ok(checks) => checks,
+		Err(e) => {
+			println!("Self-test failed to run: {:?}", e);
+			return 1;
+		}
+	};
+	for check in &checks {
+		println!(
+			"  {:<10} {:<4} {:>9.3}s  {}",
+			check.name,
+			if check.passed { "PASS" } else { "FAIL" },
+			check.duration.as_secs_f64(),
+			check.detail,
+		);
+	}
+	let failed = checks.iter().filter(|c| !c.passed).count();
+	println!("{} checks passed, {} failed", checks.len() - failed, failed);
+	if failed > 0 {
+		1
+	} else {
+		0
+	}
+}
+
 /// Handles the server part of the command line, mostly running, starting and
 /// stopping the Kepler blockchain server. Processes all the command line
 /// arguments to build a proper configuration and runs Kepler with that
@@ -120,6 +329,10 @@ pub fn server_command(
 				.wallet_listener_url = wallet_url.to_string();
 		}
 
+		if a.is_present("safe_mode") {
+			server_config.safe_mode = true;
+		}
+
 		if let Some(seeds) = a.values_of("seed") {
 			let peers = seeds
 				.filter_map(|s| s.parse().ok())
@@ -135,6 +348,24 @@ pub fn server_command(
 			("run", _) => {
 				start_server(server_config, logs_rx);
 			}
+			("bench-reindex", _) => {
+				return bench_reindex(&server_config);
+			}
+			("selftest", _) => {
+				return selftest(&server_config);
+			}
+			("export-blocks", Some(args)) => {
+				return export_blocks(&server_config, args);
+			}
+			("import-blocks", Some(args)) => {
+				return import_blocks(&server_config, args);
+			}
+			("export-checkpoint", Some(args)) => {
+				return export_checkpoint(&server_config, args);
+			}
+			("replay-capture", Some(args)) => {
+				return replay_capture(&server_config, args);
+			}
 			("", _) => {
 				println!("Subcommand required, use 'kepler help server' for details");
 			}
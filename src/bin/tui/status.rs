@@ -84,6 +84,53 @@ impl TUIStatusView {
 			SyncStatus::TxHashsetSetup => {
 				"Sync step 3/7: Preparing chain state for validation".to_string()
 			}
+			SyncStatus::TxHashsetUnzip {
+				unzipped_size,
+				total_size,
+			} => {
+				let percent = if total_size > 0 {
+					unzipped_size * 100 / total_size
+				} else {
+					0
+				};
+				format!(
+					"Sync step 3/7: Extracting {}(MB) chain state: {}%",
+					total_size / 1_000_000,
+					percent
+				)
+			}
+			SyncStatus::TxHashsetKernelHistoryValidation {
+				headers,
+				headers_total,
+			} => {
+				let percent = if headers_total > 0 {
+					headers * 100 / headers_total
+				} else {
+					0
+				};
+				format!(
+					"Sync step 3/7: Validating chain state - kernel history: {}%",
+					percent
+				)
+			}
+			SyncStatus::TxHashsetMmrsValidation {
+				mmrs,
+				mmrs_total,
+				nodes,
+				nodes_total,
+			} => {
+				let percent = if nodes_total > 0 {
+					nodes * 100 / nodes_total
+				} else {
+					0
+				};
+				format!(
+					"Sync step 4/7: Validating chain state - MMR {}/{}: {}%",
+					(mmrs + 1).min(mmrs_total),
+					mmrs_total,
+					percent
+				)
+			}
 			SyncStatus::TxHashsetRangeProofsValidation {
 				rproofs,
 				rproofs_total,
@@ -36,6 +36,7 @@ use crate::tui::constants::ROOT_STACK;
 use crate::tui::types::{TUIStatusListener, UIMessage};
 use crate::tui::{logs, menu, mining, peers, status, version};
 use kepler_util::logger::LogEntry;
+use kepler_util::Watch;
 
 pub struct UI {
 	cursive: Cursive,
@@ -184,8 +185,11 @@ impl Controller {
 
 	/// Run the controller
 	pub fn run(&mut self, server: Server) {
-		let stat_update_interval = 1;
+		// Stats are refreshed as soon as the chain head or sync status changes,
+		// the interval only matters for the rest (peers, mining).
+		let stat_update_interval = 5;
 		let mut next_stat_update = Utc::now().timestamp() + stat_update_interval;
+		let mut events = Watch::subscribe(&server.sync_state.events());
 		while self.ui.step() {
 			while let Some(message) = self.rx.try_iter().next() {
 				match message {
@@ -198,7 +202,7 @@ impl Controller {
 				}
 			}
 
-			if Utc::now().timestamp() > next_stat_update {
+			if events.try_recv().is_some() || Utc::now().timestamp() > next_stat_update {
 				next_stat_update = Utc::now().timestamp() + stat_update_interval;
 				if let Ok(stats) = server.get_server_stats() {
 					self.ui.ui_tx.send(UIMessage::UpdateStatus(stats)).unwrap();
@@ -269,6 +269,18 @@ impl Store {
 	/// Produces an iterator of (key, value) pairs, where values are `Readable` types
 	/// moving forward from the provided key.
 	pub fn iter<T: ser::Readable>(&self, from: &[u8]) -> Result<SerIterator<T>, Error> {
+		self.iter_range(from, from)
+	}
+
+	/// Produces an iterator of (key, value) pairs for all keys sharing the
+	/// provided prefix, starting at the first key greater than or equal to
+	/// `start`. The scan is a single cursor walk, so is much faster than
+	/// repeated lookups when reading many consecutive keys.
+	pub fn iter_range<T: ser::Readable>(
+		&self,
+		prefix: &[u8],
+		start: &[u8],
+	) -> Result<SerIterator<T>, Error> {
 		let db = self.db.read();
 		let tx = Arc::new(lmdb::ReadTransaction::new(self.env.clone())?);
 		let cursor = Arc::new(tx.cursor(db.as_ref().unwrap().clone()).unwrap());
@@ -276,7 +288,8 @@ impl Store {
 			tx,
 			cursor,
 			seek: false,
-			prefix: from.to_vec(),
+			prefix: prefix.to_vec(),
+			start: start.to_vec(),
 			version: self.version,
 			_marker: marker::PhantomData,
 		})
@@ -346,6 +359,16 @@ impl<'a> Batch<'a> {
 		self.store.iter(from)
 	}
 
+	/// Produces an iterator of `Readable` types for all keys sharing the
+	/// provided prefix, starting at the provided key.
+	pub fn iter_range<T: ser::Readable>(
+		&self,
+		prefix: &[u8],
+		start: &[u8],
+	) -> Result<SerIterator<T>, Error> {
+		self.store.iter_range(prefix, start)
+	}
+
 	/// Gets a `Readable` value from the db, provided its key, taking the
 	/// content of the current batch into account.
 	pub fn get_ser<T: ser::Readable>(&self, key: &[u8]) -> Result<Option<T>, Error> {
@@ -387,6 +410,7 @@ where
 	cursor: Arc<lmdb::Cursor<'static, 'static>>,
 	seek: bool,
 	prefix: Vec<u8>,
+	start: Vec<u8>,
 	version: ProtocolVersion,
 	_marker: marker::PhantomData<T>,
 }
@@ -405,7 +429,7 @@ where
 			self.seek = true;
 			Arc::get_mut(&mut self.cursor)
 				.unwrap()
-				.seek_range_k(&access, &self.prefix[..])
+				.seek_range_k(&access, &self.start[..])
 		};
 		match kv {
 			Ok((k, v)) => self.deser_if_prefix_match(k, v),
@@ -337,6 +337,13 @@ impl<T: PMMRable> PMMRBackend<T> {
 		self.leaf_set.flush()
 	}
 
+	/// Close and reopen the hash and data files, releasing the memory their
+	/// memory maps took so far. Only to be called with everything synced.
+	pub fn reopen_files(&mut self) -> io::Result<()> {
+		self.hash_file.reopen()?;
+		self.data_file.reopen()
+	}
+
 	/// Discard the current, non synced state of the backend.
 	pub fn discard(&mut self) {
 		self.hash_file.discard();
@@ -344,6 +351,44 @@ impl<T: PMMRable> PMMRBackend<T> {
 		self.leaf_set.discard();
 	}
 
+	/// Append a leaf of a PMMR rebuilt from its segments, with the hash of the
+	/// leaf followed by the hashes of the parents it completes. Spent leaves
+	/// keep their data but aren't added to the leaf_set.
+	pub fn append_leaf(
+		&mut self,
+		pos: u64,
+		data: &T::E,
+		hashes: &[Hash],
+		unspent: bool,
+	) -> io::Result<()> {
+		self.data_file.append(data)?;
+		for h in hashes {
+			self.hash_file.append(h)?;
+		}
+		if self.prunable && unspent {
+			self.leaf_set.add(pos);
+		}
+		Ok(())
+	}
+
+	/// Append the root of a pruned subtree of a PMMR rebuilt from its
+	/// segments, with the hashes of the parents it completes. Nothing below
+	/// the root is stored, as if it had been compacted. The prune_list is
+	/// only saved by `sync_prune_list`.
+	pub fn append_pruned_root(&mut self, pos: u64, hashes: &[Hash]) -> io::Result<()> {
+		assert!(self.prunable, "Pruned root on non-prunable MMR");
+		for h in hashes {
+			self.hash_file.append(h)?;
+		}
+		self.prune_list.add(pos);
+		Ok(())
+	}
+
+	/// Save the prune_list to disk, rebuilding its caches.
+	pub fn sync_prune_list(&mut self) -> io::Result<()> {
+		self.prune_list.flush()
+	}
+
 	/// Takes the leaf_set at a given cutoff_pos and generates an updated
 	/// prune_list. Saves the updated prune_list to disk, compacts the hash
 	/// and data files based on the prune_list and saves both to disk.
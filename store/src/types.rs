@@ -152,6 +152,12 @@ where
 		self.file.release();
 	}
 
+	/// Close and reopen the underlying file handles, see
+	/// `AppendOnlyFile::reopen`.
+	pub fn reopen(&mut self) -> io::Result<()> {
+		self.file.reopen()
+	}
+
 	/// Write the file out to disk, pruning removed elements.
 	pub fn save_prune(&mut self, prune_pos: &[u64]) -> io::Result<()> {
 		// Need to convert from 1-index to 0-index (don't ask).
@@ -588,6 +594,20 @@ where
 		}
 	}
 
+	/// Close and reopen the underlying file handles and memory maps, handing
+	/// the pages of the file read so far back to the OS. Refused while some
+	/// changes are pending a flush, those would be lost.
+	pub fn reopen(&mut self) -> io::Result<()> {
+		if !self.buffer.is_empty() || self.buffer_start_pos_bak > 0 {
+			return Err(io::Error::new(
+				io::ErrorKind::Other,
+				format!("unflushed changes to {:?}", self.path),
+			));
+		}
+		self.release();
+		self.init()
+	}
+
 	/// Current size of the file in bytes.
 	pub fn size(&self) -> io::Result<u64> {
 		fs::metadata(&self.path).map(|md| md.len())
@@ -101,3 +101,29 @@ fn lmdb_allocate() -> Result<(), store::Error> {
 
 	Ok(())
 }
+
+#[test]
+fn lmdb_iter_range() -> Result<(), store::Error> {
+	let test_dir = "test_output/lmdb_iter_range";
+	setup(test_dir);
+	let store = store::Store::new(test_dir, Some("test1"), None, None)?;
+
+	// Keys sort by their big endian bytes, so by value.
+	let batch = store.batch()?;
+	for i in 0..10u64 {
+		batch.put_ser(&store::to_key(b'R', &mut i.to_be_bytes().to_vec()), &i)?;
+		batch.put_ser(&store::to_key(b'S', &mut i.to_be_bytes().to_vec()), &i)?;
+	}
+	batch.commit()?;
+
+	let start = store::to_key(b'R', &mut 5u64.to_be_bytes().to_vec());
+	let vals: Vec<u64> = store.iter_range(&[b'R'], &start)?.map(|(_, v)| v).collect();
+	assert_eq!(vals, vec![5, 6, 7, 8, 9]);
+
+	// Starting past the last key sharing the prefix yields nothing.
+	let start = store::to_key(b'R', &mut 10u64.to_be_bytes().to_vec());
+	assert_eq!(store.iter_range::<u64>(&[b'R'], &start)?.count(), 0);
+
+	clean_output_dir(test_dir);
+	Ok(())
+}
@@ -371,6 +371,42 @@ fn pmmr_reload() {
 	teardown(data_dir);
 }
 
+#[test]
+fn pmmr_reopen_files() {
+	let (data_dir, elems) = setup("reopen_files");
+	{
+		let mut backend =
+			store::pmmr::PMMRBackend::new(data_dir.to_string(), true, ProtocolVersion(1), None)
+				.unwrap();
+
+		// unsynced changes can't be reopened without losing them
+		let mmr_size = load(0, &elems[0..4], &mut backend);
+		assert!(backend.reopen_files().is_err());
+		backend.sync().unwrap();
+
+		let root = {
+			let pmmr: PMMR<'_, TestElem, _> = PMMR::at(&mut backend, mmr_size);
+			pmmr.root().unwrap()
+		};
+		backend.reopen_files().unwrap();
+		assert_eq!(backend.unpruned_size(), mmr_size);
+		{
+			let pmmr: PMMR<'_, TestElem, _> = PMMR::at(&mut backend, mmr_size);
+			assert_eq!(pmmr.root().unwrap(), root);
+			assert_eq!(pmmr.get_data(1), Some(elems[0]));
+		}
+
+		// and appending still works once reopened
+		let mmr_size = load(mmr_size, &elems[4..6], &mut backend);
+		backend.sync().unwrap();
+		{
+			let pmmr: PMMR<'_, TestElem, _> = PMMR::at(&mut backend, mmr_size);
+			assert_eq!(pmmr.get_data(8), Some(elems[4]));
+		}
+	}
+	teardown(data_dir);
+}
+
 #[test]
 fn pmmr_rewind() {
 	let (data_dir, elems) = setup("rewind");
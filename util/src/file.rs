@@ -43,6 +43,18 @@ pub fn copy_dir_to(src: &Path, dst: &Path) -> io::Result<u64> {
 	Ok(counter)
 }
 
+/// Total size of the files under a directory, those that can't be read
+/// left out.
+pub fn dir_size(path: &Path) -> u64 {
+	WalkDir::new(path)
+		.into_iter()
+		.filter_map(|x| x.ok())
+		.filter(|x| x.file_type().is_file())
+		.filter_map(|x| x.metadata().ok())
+		.map(|x| x.len())
+		.sum()
+}
+
 /// List directory
 pub fn list_files(path: &Path) -> Vec<PathBuf> {
 	WalkDir::new(path)
@@ -28,7 +28,7 @@ extern crate lazy_static;
 #[macro_use]
 extern crate serde_derive;
 // Re-export so only has to be included once
-pub use parking_lot::Mutex;
+pub use parking_lot::{Condvar, Mutex};
 pub use parking_lot::{RwLock, RwLockReadGuard};
 
 // Re-export so only has to be included once
@@ -40,7 +40,7 @@ pub use crate::logger::{init_logger, init_test_logger};
 
 // Static secp instance
 pub mod secp_static;
-pub use crate::secp_static::static_secp_instance;
+pub use crate::secp_static::{static_secp_instance, with_thread_secp};
 
 pub mod types;
 pub use crate::types::ZeroingString;
@@ -61,7 +61,10 @@ pub mod file;
 pub mod zip;
 
 mod rate_counter;
-pub use crate::rate_counter::RateCounter;
+pub use crate::rate_counter::{MinuteQuota, RateCounter};
+
+pub mod watch;
+pub use crate::watch::{Subscriber, Watch};
 
 /// Encapsulation of a RwLock<Option<T>> for one-time initialization.
 /// This implementation will purposefully fail hard if not used
@@ -12,9 +12,10 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::VecDeque;
 use std::convert::TryInto;
 /// Utility to track the rate of data transfers
-use std::time::SystemTime;
+use std::time::{Duration, Instant, SystemTime};
 
 struct Entry {
 	bytes: u64,
@@ -101,6 +102,46 @@ impl RateCounter {
 	}
 }
 
+/// Caps the number of events over the last minute. Unlike the RateCounter,
+/// the events older than a minute are forgotten before counting, so once
+/// over the cap, events are accepted again as soon as the older ones expire.
+pub struct MinuteQuota {
+	max: usize,
+	events: VecDeque<Instant>,
+}
+
+impl MinuteQuota {
+	/// Quota of max events per minute.
+	pub fn new(max: usize) -> MinuteQuota {
+		MinuteQuota {
+			max,
+			events: VecDeque::new(),
+		}
+	}
+
+	/// Counts an event now, unless the quota is reached.
+	pub fn take(&mut self) -> bool {
+		self.take_at(Instant::now())
+	}
+
+	/// Counts an event at the provided time, unless the quota over the
+	/// minute before is reached.
+	pub fn take_at(&mut self, now: Instant) -> bool {
+		while self
+			.events
+			.front()
+			.map_or(false, |t| now.duration_since(*t) >= Duration::from_secs(60))
+		{
+			self.events.pop_front();
+		}
+		if self.events.len() >= self.max {
+			return false;
+		}
+		self.events.push_back(now);
+		true
+	}
+}
+
 // turns out getting the millisecs since epoch in Rust isn't as easy as it
 // could be
 fn millis_since_epoch() -> u64 {
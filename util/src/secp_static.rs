@@ -34,6 +34,24 @@ pub fn static_secp_instance() -> Arc<Mutex<secp::Secp256k1>> {
 	SECP256K1.clone()
 }
 
+thread_local! {
+	// Instance of the threads verifying in parallel, randomized once.
+	static THREAD_SECP: secp::Secp256k1 = {
+		let mut secp = secp::Secp256k1::with_caps(secp::ContextFlag::Commit);
+		secp.randomize(&mut thread_rng());
+		secp
+	};
+}
+
+/// Calls the closure with an instance local to the current thread, so that
+/// verifications run in parallel don't all wait on the static instance.
+pub fn with_thread_secp<F, T>(f: F) -> T
+where
+	F: FnOnce(&secp::Secp256k1) -> T,
+{
+	THREAD_SECP.with(|secp| f(secp))
+}
+
 /// Convenient way to generate a commitment to zero.
 pub fn commit_to_zero_value() -> secp::pedersen::Commitment {
 	secp::pedersen::Commitment::from_vec(vec![0])
@@ -0,0 +1,94 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Single value broadcast channel. Threads subscribe to a value and sleep
+//! until a new one is published, instead of waking up regularly to poll for
+//! changes.
+
+use parking_lot::{Condvar, Mutex};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Holds the latest published value, along with the number of values
+/// published so far so subscribers can tell whether they missed any.
+pub struct Watch<T> {
+	state: Mutex<(u64, T)>,
+	changed: Condvar,
+}
+
+impl<T: Clone> Watch<T> {
+	/// New watch, holding the provided initial value.
+	pub fn new(value: T) -> Watch<T> {
+		Watch {
+			state: Mutex::new((0, value)),
+			changed: Condvar::new(),
+		}
+	}
+
+	/// Publish a new value, waking up all the waiting subscribers.
+	pub fn publish(&self, value: T) {
+		let mut state = self.state.lock();
+		state.0 += 1;
+		state.1 = value;
+		self.changed.notify_all();
+	}
+
+	/// Latest published value.
+	pub fn latest(&self) -> T {
+		self.state.lock().1.clone()
+	}
+
+	/// Subscribe to the values published from now on.
+	pub fn subscribe(watch: &Arc<Watch<T>>) -> Subscriber<T> {
+		let seen = watch.state.lock().0;
+		Subscriber {
+			watch: watch.clone(),
+			seen,
+		}
+	}
+}
+
+/// Receiving end of a watch. Only ever sees the latest value, values
+/// published in quick succession are skipped.
+pub struct Subscriber<T> {
+	watch: Arc<Watch<T>>,
+	seen: u64,
+}
+
+impl<T: Clone> Subscriber<T> {
+	/// Latest value if it was published since we last received one.
+	pub fn try_recv(&mut self) -> Option<T> {
+		let state = self.watch.state.lock();
+		self.receive(&state)
+	}
+
+	/// Wait for a new value to be published, at most for the provided
+	/// duration. Returns immediately if one was published since we last
+	/// received one.
+	pub fn recv_timeout(&mut self, timeout: Duration) -> Option<T> {
+		let mut state = self.watch.state.lock();
+		if state.0 == self.seen {
+			self.watch.changed.wait_for(&mut state, timeout);
+		}
+		self.receive(&state)
+	}
+
+	fn receive(&mut self, state: &(u64, T)) -> Option<T> {
+		if state.0 == self.seen {
+			return None;
+		}
+		self.seen = state.0;
+		Some(state.1.clone())
+	}
+}
@@ -14,13 +14,20 @@
 
 /// Wrappers around the `zip-rs` library to compress and decompress zip archives.
 use std::fs::{self, File};
-use std::io::{self, BufReader, BufWriter, Write};
+use std::io::{self, BufReader, BufWriter, Read, Write};
 use std::path::{Path, PathBuf};
+use std::sync::mpsc;
 use std::thread;
 
 use self::zip_rs::write::FileOptions;
 use zip as zip_rs;
 
+/// Size of the chunks files are extracted by.
+const EXTRACT_CHUNK_SIZE: usize = 1_048_576;
+
+/// Bytes extracted between two progress updates, at most.
+const EXTRACT_PROGRESS_INTERVAL: u64 = 16 * EXTRACT_CHUNK_SIZE as u64;
+
 /// Create a zip archive from source dir and list of relative file paths.
 /// Permissions are set to 644 by default.
 pub fn create_zip(dst_file: &File, src_dir: &Path, files: Vec<PathBuf>) -> io::Result<()> {
@@ -51,18 +58,61 @@ pub fn create_zip(dst_file: &File, src_dir: &Path, files: Vec<PathBuf>) -> io::R
 
 /// Extract a set of files from the provided zip archive.
 pub fn extract_files(from_archive: File, dest: &Path, files: Vec<PathBuf>) -> io::Result<()> {
+	extract_files_with_progress(from_archive, dest, files, |_, _| {})
+}
+
+/// Extract a set of files from the provided zip archive, calling `progress`
+/// with the number of bytes extracted so far and the total to extract as the
+/// extraction goes.
+pub fn extract_files_with_progress<F>(
+	from_archive: File,
+	dest: &Path,
+	files: Vec<PathBuf>,
+	mut progress: F,
+) -> io::Result<()>
+where
+	F: FnMut(u64, u64),
+{
 	let dest: PathBuf = PathBuf::from(dest);
 	let files: Vec<_> = files.to_vec();
-	let res = thread::spawn(move || {
+	let (progress_tx, progress_rx) = mpsc::channel();
+	let handle = thread::spawn(move || {
 		let mut archive = zip_rs::ZipArchive::new(from_archive).expect("archive file exists");
+		let total_size: u64 = files
+			.iter()
+			.filter_map(|x| {
+				let file = archive.by_name(x.to_str().expect("valid path")).ok()?;
+				Some(file.size())
+			})
+			.sum();
+		let mut extracted_size = 0;
+		let mut reported_size = 0;
+		let mut buf = vec![0; EXTRACT_CHUNK_SIZE];
 		for x in files {
-			if let Ok(file) = archive.by_name(x.to_str().expect("valid path")) {
+			if let Ok(mut file) = archive.by_name(x.to_str().expect("valid path")) {
 				let path = dest.join(file.sanitized_name());
 				let parent_dir = path.parent().expect("valid parent dir");
 				fs::create_dir_all(&parent_dir).expect("create parent dir");
 				let outfile = fs::File::create(&path).expect("file created");
-				io::copy(&mut BufReader::new(file), &mut BufWriter::new(outfile))
-					.expect("write to file");
+				let mut writer = BufWriter::new(outfile);
+				loop {
+					let n = file.read(&mut buf).expect("read from archive");
+					if n == 0 {
+						break;
+					}
+					writer.write_all(&buf[..n]).expect("write to file");
+					extracted_size += n as u64;
+					if extracted_size - reported_size >= EXTRACT_PROGRESS_INTERVAL {
+						reported_size = extracted_size;
+						// Only fails once the receiving side went away.
+						let _ = progress_tx.send((extracted_size, total_size));
+					}
+				}
+				writer.flush().expect("write to file");
+				if extracted_size > reported_size {
+					reported_size = extracted_size;
+					let _ = progress_tx.send((extracted_size, total_size));
+				}
 
 				info!("extract_files: {:?} -> {:?}", x, path);
 
@@ -75,8 +125,13 @@ pub fn extract_files(from_archive: File, dest: &Path, files: Vec<PathBuf>) -> io
 				}
 			}
 		}
-	})
-	.join();
+	});
+
+	// The channel closes when the extraction ends, successfully or not.
+	for (extracted_size, total_size) in progress_rx {
+		progress(extracted_size, total_size);
+	}
+	let res = handle.join();
 
 	// If join() above is Ok then we successfully extracted the files.
 	// If the result is Err then we failed to extract the files.
@@ -0,0 +1,38 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use kepler_util as util;
+
+use self::util::MinuteQuota;
+use std::time::{Duration, Instant};
+
+#[test]
+fn minute_quota_expires() {
+	let mut quota = MinuteQuota::new(2);
+	let now = Instant::now();
+
+	assert!(quota.take_at(now));
+	assert!(quota.take_at(now + Duration::from_secs(30)));
+	assert!(!quota.take_at(now + Duration::from_secs(59)));
+
+	// Once over the quota, the first event expiring frees up a slot.
+	assert!(quota.take_at(now + Duration::from_secs(60)));
+	assert!(!quota.take_at(now + Duration::from_secs(61)));
+
+	// And past the window of all of them, the whole quota is back.
+	let later = now + Duration::from_secs(200);
+	assert!(quota.take_at(later));
+	assert!(quota.take_at(later));
+	assert!(!quota.take_at(later));
+}
@@ -0,0 +1,54 @@
+// Copyright 2020 The Kepler Developers
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use kepler_util as util;
+
+use self::util::Watch;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[test]
+fn watch_latest_value() {
+	let watch = Arc::new(Watch::new(0));
+	let mut sub = Watch::subscribe(&watch);
+
+	// Nothing published since we subscribed.
+	assert_eq!(sub.try_recv(), None);
+	assert_eq!(sub.recv_timeout(Duration::from_millis(10)), None);
+
+	// Only the latest of the values published in a row is received.
+	watch.publish(1);
+	watch.publish(2);
+	assert_eq!(sub.try_recv(), Some(2));
+	assert_eq!(sub.try_recv(), None);
+	assert_eq!(watch.latest(), 2);
+}
+
+#[test]
+fn watch_wakes_up_subscriber() {
+	let watch = Arc::new(Watch::new(0));
+	let mut sub = Watch::subscribe(&watch);
+
+	let publisher = watch.clone();
+	let handle = thread::spawn(move || {
+		thread::sleep(Duration::from_millis(50));
+		publisher.publish(1);
+	});
+
+	let start = Instant::now();
+	assert_eq!(sub.recv_timeout(Duration::from_secs(30)), Some(1));
+	assert!(start.elapsed() < Duration::from_secs(30));
+	handle.join().unwrap();
+}
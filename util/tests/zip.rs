@@ -74,7 +74,16 @@ fn zip_unzip() {
 		// Note: we do not extract "wat.txt" here, even if present in the zip.
 		let files = vec![PathBuf::from("foo.txt"), PathBuf::from("sub/lorem.txt")];
 
-		zip::extract_files(zip_file, &dest_dir, files).unwrap();
+		let mut progress = vec![];
+		zip::extract_files_with_progress(zip_file, &dest_dir, files, |size, total| {
+			progress.push((size, total))
+		})
+		.unwrap();
+
+		// The whole content of the extracted files was reported.
+		let (size, total) = *progress.last().unwrap();
+		assert!(size > 0);
+		assert_eq!(size, total);
 
 		assert!(dest_dir.join("foo.txt").is_file());
 